@@ -0,0 +1,143 @@
+//! Benchmarks for the code that runs every block: DSP graphs, voices and
+//! the scheduler. Run with `cargo bench`.
+
+use aurio::audio::{
+    ADSRConfig, Instrument, OscConfig, PlaybackState, SampleBank, TrackConfig, Wave,
+};
+use aurio::dsp::{ProcessContext, parse_file};
+use aurio::events::NoteExpression;
+use aurio::scripting::{PatternContext, VariableStore};
+use aurio::timing::{Note, Sequence, StaticPattern, schedule_sequence_events};
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use ringbuf::HeapRb;
+use ringbuf::traits::{Consumer, Split};
+use std::hint::black_box;
+use std::time::Duration;
+
+const BLOCK: usize = 512;
+const SAMPLE_RATE: f32 = 48_000.0;
+
+/// A patch of `oscillators` oscillators summed through a gain into the output.
+fn patch(oscillators: usize) -> String {
+    let waves = ["Sine", "Saw", "Square"];
+    let mut patch = String::from("[0] Out\n[1] Gain 0.1\n");
+    for i in 0..oscillators {
+        let wave = waves[i % waves.len()];
+        patch += &format!("[{}] Osc {} {}\n", i + 2, wave, 110.0 + i as f32);
+    }
+    patch += "1->0,\n";
+    for i in 0..oscillators {
+        patch += &format!("{}->1,\n", i + 2);
+    }
+    patch
+}
+
+fn graph_process(c: &mut Criterion) {
+    let mut group = c.benchmark_group("graph_process");
+    let ctx = ProcessContext {
+        sample_rate: SAMPLE_RATE,
+    };
+    for oscillators in [2, 14, 62] {
+        let mut graph = parse_file(&patch(oscillators)).unwrap();
+        let mut output = vec![0.0; BLOCK];
+        group.throughput(Throughput::Elements(BLOCK as u64));
+        group.bench_function(BenchmarkId::from_parameter(oscillators + 2), |b| {
+            b.iter(|| graph.process(black_box(&mut output), &ctx))
+        });
+    }
+    group.finish();
+}
+
+fn synth() -> TrackConfig {
+    let osc = |wave, semitone| OscConfig {
+        wave,
+        gain: 0.3,
+        semitone,
+        pan: 0.0,
+    };
+    TrackConfig::new(
+        0,
+        Instrument::MultiOsc {
+            oscillators: vec![
+                osc(Wave::Saw, 0),
+                osc(Wave::Square, -12),
+                osc(Wave::Sine, 7),
+            ],
+            spread: 0.5,
+        },
+        ADSRConfig::new(0.01, 0.1, 0.8, 0.2),
+    )
+}
+
+fn voices(c: &mut Criterion) {
+    let mut group = c.benchmark_group("voices");
+    let config = synth();
+    let samples = SampleBank::new();
+    for polyphony in [1, 8, 32, 128] {
+        let mut state = PlaybackState::new();
+        for pitch in 0..polyphony {
+            state.note_on(pitch as u8, 100, NoteExpression::default(), &config);
+        }
+        let (mut left, mut right) = (vec![0.0; BLOCK], vec![0.0; BLOCK]);
+        group.throughput(Throughput::Elements(BLOCK as u64));
+        group.bench_function(BenchmarkId::new("block", polyphony), |b| {
+            b.iter(|| state.render_block(&config, &samples, SAMPLE_RATE, &mut left, &mut right))
+        });
+        group.bench_function(BenchmarkId::new("frame", polyphony), |b| {
+            b.iter(|| {
+                for _ in 0..BLOCK {
+                    black_box(state.render_frame(&config, &samples, SAMPLE_RATE));
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+fn scheduler(c: &mut Criterion) {
+    let mut group = c.benchmark_group("scheduler");
+    let variables = VariableStore::new();
+    for notes in [16, 256, 2048] {
+        let sequence = Sequence::Static(StaticPattern {
+            duration_bars: 4,
+            time_signature: (4, 4),
+            notes: (0..notes)
+                .map(|i| {
+                    Note::new(
+                        36 + (i % 48) as u8,
+                        100,
+                        i as f32 * 16.0 / notes as f32,
+                        0.1,
+                    )
+                })
+                .collect(),
+            automation: Vec::new(),
+        });
+        let context = PatternContext {
+            track_id: 0,
+            node_id: "bench",
+            start_sample: 0,
+            bpm: 120.0,
+            sample_rate: SAMPLE_RATE,
+            time_signature: (4, 4),
+            loop_count: 0,
+            repeat: None,
+            seed: 0,
+            pattern_seed: 0,
+            variables: &variables,
+        };
+        let (mut producer, mut consumer) = HeapRb::new(notes * 2).split();
+        group.throughput(Throughput::Elements(notes as u64));
+        group.bench_function(BenchmarkId::from_parameter(notes), |b| {
+            b.iter(|| {
+                schedule_sequence_events(&sequence, &context, &mut producer, None, Duration::ZERO)
+                    .unwrap();
+                consumer.clear();
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, graph_process, voices, scheduler);
+criterion_main!(benches);
@@ -3,7 +3,7 @@ use midir::MidiInput;
 use ringbuf::HeapRb;
 use ringbuf::traits::{Consumer, Producer, Split};
 use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, AtomicU8, AtomicU32, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicU64, Ordering};
 
 #[derive(Clone, Copy, PartialEq)]
 enum LooperState {
@@ -42,7 +42,7 @@ fn main() {
     let ctrl_bars_midi = ctrl_bars.clone();
     let ctrl_bars_audio = ctrl_bars.clone();
 
-    let first_tap_sample = Arc::new(AtomicU32::new(0));
+    let first_tap_sample = Arc::new(AtomicU64::new(0));
     let tap_count = Arc::new(AtomicU8::new(0));
 
     let first_tap_sample_midi = first_tap_sample.clone();
@@ -50,7 +50,7 @@ fn main() {
     let first_tap_sample_audio = first_tap_sample.clone();
     let tap_count_audio = tap_count.clone();
 
-    let global_sample_count = Arc::new(AtomicU32::new(0));
+    let global_sample_count = Arc::new(AtomicU64::new(0));
     let global_sample_count_midi = global_sample_count.clone();
     let global_sample_count_audio = global_sample_count.clone();
 
@@ -156,9 +156,8 @@ fn main() {
 
                         LooperState::WaitingSecond => {
                             if taps == 2 {
-                                let first = first_tap_sample_audio.load(Ordering::Relaxed) as usize;
-                                beat_samples = (current_count as usize)
-                                    .saturating_sub(first)
+                                let first = first_tap_sample_audio.load(Ordering::Relaxed);
+                                beat_samples = (current_count.saturating_sub(first) as usize)
                                     .max(sample_rate / 8);
                                 phase_in_beat = 0;
                                 beats_elapsed = 0;
@@ -199,7 +198,8 @@ fn main() {
 
                         LooperState::Recording => {
                             let click = if phase_in_beat < sample_rate / 50 {
-                                let freq = if phase_in_beat == 0 && beats_elapsed % 4 == 0 {
+                                let freq = if phase_in_beat == 0 && beats_elapsed.is_multiple_of(4)
+                                {
                                     1000.0
                                 } else {
                                     800.0
@@ -1,6 +1,6 @@
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 use std::sync::Arc;
-use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::atomic::{AtomicU64, Ordering};
 
 fn main() {
     let host = cpal::default_host();
@@ -22,17 +22,17 @@ fn run<T: cpal::SizedSample + cpal::FromSample<f32>>(
     let sample_rate = config.sample_rate as f32;
     let channels = config.channels as usize;
 
-    let sample_count = Arc::new(AtomicU32::new(0));
+    let sample_count = Arc::new(AtomicU64::new(0));
     let sample_count_clone = sample_count.clone();
 
     // Frequency schedule: (freq_hz, duration_ms)
     let schedule: Vec<(f32, u32)> = vec![(440.0, 150), (660.0, 150), (880.0, 100)];
 
     // Convert to sample boundaries
-    let boundaries: Vec<(f32, u32)> = schedule
+    let boundaries: Vec<(f32, u64)> = schedule
         .iter()
-        .scan(0u32, |acc, &(freq, ms)| {
-            *acc += (sample_rate * ms as f32 / 1000.0) as u32;
+        .scan(0u64, |acc, &(freq, ms)| {
+            *acc += (sample_rate * ms as f32 / 1000.0) as u64;
             Some((freq, *acc))
         })
         .collect();
@@ -79,4 +79,3 @@ fn run<T: cpal::SizedSample + cpal::FromSample<f32>>(
         std::thread::sleep(std::time::Duration::from_millis(10));
     }
 }
-
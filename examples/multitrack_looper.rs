@@ -3,7 +3,7 @@ use midir::{MidiInput, MidiOutput};
 use ringbuf::HeapRb;
 use ringbuf::traits::{Consumer, Producer, Split};
 use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, AtomicU8, AtomicU32, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicU32, AtomicU64, Ordering};
 
 const NUM_TRACKS: usize = 8;
 
@@ -77,7 +77,7 @@ fn main() {
     let ctrl_bars_midi = ctrl_bars.clone();
     let ctrl_bars_audio = ctrl_bars.clone();
 
-    let first_tap_sample = Arc::new(AtomicU32::new(0));
+    let first_tap_sample = Arc::new(AtomicU64::new(0));
     let tap_count = Arc::new(AtomicU8::new(0));
     let tempo_set = Arc::new(AtomicBool::new(false));
     let beat_samples_shared = Arc::new(AtomicU32::new(0));
@@ -90,7 +90,7 @@ fn main() {
     let tempo_set_audio = tempo_set.clone();
     let beat_samples_audio = beat_samples_shared.clone();
 
-    let global_sample_count = Arc::new(AtomicU32::new(0));
+    let global_sample_count = Arc::new(AtomicU64::new(0));
     let global_sample_count_midi = global_sample_count.clone();
     let global_sample_count_audio = global_sample_count.clone();
 
@@ -98,17 +98,15 @@ fn main() {
     let track_triggers: Vec<Arc<AtomicBool>> = (0..NUM_TRACKS)
         .map(|_| Arc::new(AtomicBool::new(false)))
         .collect();
-    let track_triggers_midi: Vec<Arc<AtomicBool>> =
-        track_triggers.iter().map(|t| t.clone()).collect();
-    let track_triggers_audio: Vec<Arc<AtomicBool>> =
-        track_triggers.iter().map(|t| t.clone()).collect();
+    let track_triggers_midi: Vec<Arc<AtomicBool>> = track_triggers.to_vec();
+    let track_triggers_audio: Vec<Arc<AtomicBool>> = track_triggers.to_vec();
 
     // LED state feedback from audio thread
     let track_states: Vec<Arc<AtomicU8>> = (0..NUM_TRACKS)
         .map(|_| Arc::new(AtomicU8::new(TrackState::Empty as u8)))
         .collect();
-    let track_states_audio: Vec<Arc<AtomicU8>> = track_states.iter().map(|t| t.clone()).collect();
-    let track_states_led: Vec<Arc<AtomicU8>> = track_states.iter().map(|t| t.clone()).collect();
+    let track_states_audio: Vec<Arc<AtomicU8>> = track_states.to_vec();
+    let track_states_led: Vec<Arc<AtomicU8>> = track_states.to_vec();
 
     // MIDI input
     let midi_in = MidiInput::new("aurio").expect("failed to create MIDI input");
@@ -163,7 +161,7 @@ fn main() {
                             println!("First tap - waiting for second tap to set tempo...");
                         } else if taps == 1 {
                             let first = first_tap_sample_midi.load(Ordering::Relaxed);
-                            let beat_samples = (current - first).max(1000) as usize;
+                            let beat_samples = current.saturating_sub(first).max(1000) as usize;
                             beat_samples_midi.store(beat_samples as u32, Ordering::Relaxed);
                             tempo_set_midi.store(true, Ordering::Relaxed);
                             tap_count_midi.store(2, Ordering::Relaxed);
@@ -0,0 +1,88 @@
+#ifndef AURIO_H
+#define AURIO_H
+
+/* Generated by cbindgen from src/ffi.rs. Don't edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+#define AURIO_OK 0
+
+#define AURIO_ERROR -1
+
+// A graph and what it needs to render into a host's buffers. Opaque to C.
+typedef struct AurioGraph AurioGraph;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// An empty graph. Free it with [`aurio_graph_free`].
+AurioGraph *aurio_graph_new(void);
+
+// # Safety
+//
+// `graph` is null or came from [`aurio_graph_new`] and wasn't freed yet.
+void aurio_graph_free(AurioGraph *graph);
+
+// Adds node `id`, or replaces it keeping its wires. `node` is its type and
+// arguments as written in a patch, e.g. `Osc Saw 110`.
+//
+// # Safety
+//
+// `graph` came from [`aurio_graph_new`]; `node` is a NUL-terminated string.
+int aurio_graph_add_node(AurioGraph *graph, uint32_t id, const char *node);
+
+// Wires the output of node `from` into node `to`. Fails if either is
+// missing or the wire would close a loop.
+//
+// # Safety
+//
+// `graph` came from [`aurio_graph_new`].
+int aurio_graph_add_wire(AurioGraph *graph, uint32_t from, uint32_t to);
+
+// Applies `.au` source to the graph: nodes with a new id are added, nodes
+// reusing an id replace that node, and wires are added.
+//
+// # Safety
+//
+// `graph` came from [`aurio_graph_new`]; `source` is a NUL-terminated
+// string.
+int aurio_graph_patch(AurioGraph *graph, const char *source);
+
+// Sets a parameter of node `node`, e.g. an oscillator's `freq`.
+//
+// # Safety
+//
+// `graph` came from [`aurio_graph_new`]; `name` is a NUL-terminated string.
+int aurio_graph_set_param(AurioGraph *graph, uint32_t node, const char *name, float value);
+
+// Renders `frames` frames into `output`, interleaved with `channels`
+// channels, every channel carrying the graph's output. Only the first call
+// with a larger block than before allocates.
+//
+// # Safety
+//
+// `graph` came from [`aurio_graph_new`]; `output` holds at least
+// `frames * channels` floats.
+int aurio_graph_process(AurioGraph *graph,
+                        float *output,
+                        uintptr_t frames,
+                        uintptr_t channels,
+                        float sample_rate);
+
+// The message of the last call on `graph` that failed, or null if the last
+// one succeeded. It stays valid until the next call on `graph`.
+//
+// # Safety
+//
+// `graph` came from [`aurio_graph_new`].
+const char *aurio_graph_last_error(const AurioGraph *graph);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* AURIO_H */
@@ -0,0 +1,80 @@
+use super::SampleBank;
+use crate::dsp::resample::Interpolation;
+use crate::events::ClipEvent;
+
+/// An audio clip playing on a track.
+#[derive(Debug, Clone)]
+pub struct ClipVoice {
+    clip: ClipEvent,
+    elapsed: u64,
+}
+
+impl ClipVoice {
+    pub fn new(clip: ClipEvent) -> Self {
+        Self { clip, elapsed: 0 }
+    }
+
+    /// Ends the clip once its fade-out has played from here, unless it ends
+    /// sooner anyway.
+    pub fn release(&mut self) {
+        let end = self.elapsed + self.clip.fade_out;
+        self.clip.length = self.clip.length.min(end);
+    }
+
+    /// Renders the next frame, or returns `None` once the clip has ended. A
+    /// clip whose sample isn't loaded ends straight away.
+    pub fn next_frame(&mut self, samples: &SampleBank, sample_rate: f32) -> Option<[f32; 2]> {
+        let sample = samples.get(&self.clip.sample_id)?;
+        let step = sample.sample_rate() as f64 / sample_rate as f64;
+        let start = self.clip.offset * sample.sample_rate() as f64;
+        let available = ((sample.num_frames() as f64 - start) / step).max(0.0) as u64;
+        let length = self.clip.length.min(available);
+        if self.elapsed >= length {
+            return None;
+        }
+
+        let fade = |frames: u64, distance: u64| {
+            if frames == 0 {
+                1.0
+            } else {
+                (distance as f32 / frames as f32).min(1.0)
+            }
+        };
+        let gain = self.clip.gain
+            * fade(self.clip.fade_in, self.elapsed)
+            * fade(self.clip.fade_out, length - self.elapsed);
+
+        let position = start + self.elapsed as f64 * step;
+        let [left, right] = sample.read(position, step, false, Interpolation::default(), None);
+        self.elapsed += 1;
+        Some([left * gain, right * gain])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::{SampleBuffer, SampleRegion};
+
+    #[test]
+    fn plays_from_offset_with_fades_until_audio_ends() {
+        let frames = vec![[1.0; 2]; 10];
+        let samples = SampleBank::from([(
+            "ones".to_string(),
+            SampleBuffer::new(frames, 10.0, SampleRegion::default()),
+        )]);
+        let mut voice = ClipVoice::new(ClipEvent {
+            sample_id: "ones".to_string(),
+            offset: 0.4,
+            length: 100,
+            fade_in: 2,
+            fade_out: 4,
+            gain: 1.0,
+        });
+
+        let output: Vec<f32> = std::iter::from_fn(|| voice.next_frame(&samples, 10.0))
+            .map(|[left, _]| left)
+            .collect();
+        assert_eq!(output, vec![0.0, 0.5, 1.0, 0.75, 0.5, 0.25]);
+    }
+}
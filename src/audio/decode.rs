@@ -0,0 +1,117 @@
+use std::path::Path;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::DecoderOptions;
+use symphonia::core::errors::Error;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+/// Extensions of the formats [`decode_file`] reads.
+pub const AUDIO_EXTENSIONS: &[&str] = &["wav", "flac", "mp3", "ogg"];
+
+/// A whole audio file as stereo frames at its own sample rate.
+#[derive(Debug, Clone)]
+pub struct DecodedAudio {
+    pub frames: Vec<[f32; 2]>,
+    pub sample_rate: u32,
+}
+
+/// Decodes the first audio track of a WAV, FLAC, MP3 or Ogg Vorbis file. Mono
+/// files are copied to both channels and only the first two channels of wider
+/// files are kept.
+pub fn decode_file(path: &Path) -> Result<DecodedAudio, String> {
+    let fail = |e: Error| format!("Failed to decode {}: {}", path.display(), e);
+
+    let file = std::fs::File::open(path)
+        .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension().and_then(|e| e.to_str()) {
+        hint.with_extension(extension);
+    }
+    let probed = symphonia::default::get_probe()
+        .format(
+            &hint,
+            MediaSourceStream::new(Box::new(file), Default::default()),
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .map_err(fail)?;
+    let mut format = probed.format;
+
+    let track = format
+        .default_track()
+        .ok_or_else(|| format!("{} has no audio track", path.display()))?;
+    let track_id = track.id;
+    let sample_rate = track
+        .codec_params
+        .sample_rate
+        .ok_or_else(|| format!("{} has no sample rate", path.display()))?;
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .map_err(fail)?;
+
+    let mut frames = Vec::new();
+    let mut buffer: Option<SampleBuffer<f32>> = None;
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(Error::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(fail(e)),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // A corrupt packet only loses its own frames.
+            Err(Error::DecodeError(_)) => continue,
+            Err(e) => return Err(fail(e)),
+        };
+
+        let spec = *decoded.spec();
+        let channels = spec.channels.count();
+        buffer.take_if(|b| b.capacity() < decoded.capacity() * channels);
+        let buffer =
+            buffer.get_or_insert_with(|| SampleBuffer::new(decoded.capacity() as u64, spec));
+        buffer.copy_interleaved_ref(decoded);
+        frames.extend(
+            buffer
+                .samples()
+                .chunks_exact(channels)
+                .map(|frame| [frame[0], frame[channels.min(2) - 1]]),
+        );
+    }
+
+    Ok(DecodedAudio {
+        frames,
+        sample_rate,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_wav_to_stereo_frames() {
+        let path = std::env::temp_dir().join(format!("aurio-decode-{}.wav", std::process::id()));
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 22_050,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for sample in [0i16, 16_384, -16_384] {
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let decoded = decode_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(decoded.sample_rate, 22_050);
+        assert_eq!(decoded.frames, vec![[0.0, 0.0], [0.5, 0.5], [-0.5, -0.5]]);
+    }
+}
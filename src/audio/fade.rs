@@ -0,0 +1,162 @@
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+/// How long the master output takes to fade to silence.
+pub const FADE_OUT_SECONDS: f32 = 0.02;
+
+/// A ramp of the master output down to silence, so the stream can be dropped
+/// without cutting a waveform mid-cycle. The engine starts it and the audio
+/// callback applies it; the gain is stored as `f32` bits.
+pub struct FadeOut {
+    active: AtomicBool,
+    gain: AtomicU32,
+}
+
+impl Default for FadeOut {
+    fn default() -> Self {
+        Self {
+            active: AtomicBool::new(false),
+            gain: AtomicU32::new(1.0f32.to_bits()),
+        }
+    }
+}
+
+impl FadeOut {
+    pub fn start(&self) {
+        self.active.store(true, Ordering::Relaxed);
+    }
+
+    /// Back to full gain, for the next time playback starts.
+    pub fn reset(&self) {
+        self.active.store(false, Ordering::Relaxed);
+        self.gain.store(1.0f32.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Whether the fade has rendered a silent frame; from then on the output
+    /// stays silent.
+    pub fn is_silent(&self) -> bool {
+        self.active.load(Ordering::Relaxed) && self.gain.load(Ordering::Relaxed) == 0
+    }
+
+    /// Ramps interleaved frames of `channels` channels while the fade runs.
+    pub fn apply(&self, data: &mut [f32], channels: usize, sample_rate: f32) {
+        if !self.active.load(Ordering::Relaxed) {
+            return;
+        }
+        let step = 1.0 / (FADE_OUT_SECONDS * sample_rate);
+        let mut gain = f32::from_bits(self.gain.load(Ordering::Relaxed));
+        for frame in data.chunks_exact_mut(channels) {
+            gain = (gain - step).max(0.0);
+            for sample in frame {
+                *sample *= gain;
+            }
+        }
+        self.gain.store(gain.to_bits(), Ordering::Relaxed);
+    }
+}
+
+/// Transport pause. The engine requests it; the audio callback ramps the
+/// output down over [`FADE_OUT_SECONDS`] and from then on stops rendering,
+/// so the playhead holds still until playback resumes and ramps back up.
+pub struct Pause {
+    paused: AtomicBool,
+    gain: AtomicU32,
+}
+
+impl Default for Pause {
+    fn default() -> Self {
+        Self {
+            paused: AtomicBool::new(false),
+            gain: AtomicU32::new(1.0f32.to_bits()),
+        }
+    }
+}
+
+impl Pause {
+    pub fn set(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+    }
+
+    /// Unpaused at full gain, for the next time playback starts.
+    pub fn reset(&self) {
+        self.paused.store(false, Ordering::Relaxed);
+        self.gain.store(1.0f32.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Whether the ramp down has finished, so blocks can be skipped.
+    pub fn is_halted(&self) -> bool {
+        self.paused.load(Ordering::Relaxed) && self.gain.load(Ordering::Relaxed) == 0
+    }
+
+    /// Ramps interleaved frames of `channels` channels towards silence while
+    /// paused, or back to full level after.
+    pub fn apply(&self, data: &mut [f32], channels: usize, sample_rate: f32) {
+        let mut gain = f32::from_bits(self.gain.load(Ordering::Relaxed));
+        let target = if self.paused.load(Ordering::Relaxed) {
+            0.0
+        } else {
+            1.0
+        };
+        if gain == target {
+            return;
+        }
+        let step = 1.0 / (FADE_OUT_SECONDS * sample_rate);
+        for frame in data.chunks_exact_mut(channels) {
+            gain = if target > gain {
+                (gain + step).min(target)
+            } else {
+                (gain - step).max(target)
+            };
+            for sample in frame {
+                *sample *= gain;
+            }
+        }
+        self.gain.store(gain.to_bits(), Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pausing_ramps_down_and_resuming_ramps_back_up() {
+        let pause = Pause::default();
+        pause.set(true);
+        assert!(!pause.is_halted());
+        let mut block = vec![1.0; 44];
+        pause.apply(&mut block, 2, 1000.0);
+        assert!((block[0] - 0.95).abs() < 1e-6);
+        assert_eq!(block[42], 0.0);
+        assert!(pause.is_halted());
+
+        pause.set(false);
+        assert!(!pause.is_halted());
+        let mut block = vec![1.0; 44];
+        pause.apply(&mut block, 2, 1000.0);
+        assert!((block[0] - 0.05).abs() < 1e-6);
+        assert_eq!(block[42], 1.0);
+    }
+
+    #[test]
+    fn fades_to_silence_and_stays_there() {
+        let fade = FadeOut::default();
+        let mut block = vec![1.0; 16];
+        fade.apply(&mut block, 2, 1000.0);
+        assert_eq!(block, vec![1.0; 16]);
+
+        fade.start();
+        let mut block = vec![1.0; 44];
+        fade.apply(&mut block, 2, 1000.0);
+        assert!((block[0] - 0.95).abs() < 1e-6);
+        assert_eq!(block[0], block[1]);
+        assert_eq!(block[42], 0.0);
+        assert!(fade.is_silent());
+
+        let mut block = vec![1.0; 4];
+        fade.apply(&mut block, 2, 1000.0);
+        assert_eq!(block, vec![0.0; 4]);
+
+        fade.reset();
+        assert!(!fade.is_silent());
+    }
+}
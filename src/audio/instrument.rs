@@ -1,6 +1,9 @@
+use super::SampleZone;
+use crate::dsp::resample::Interpolation;
+use crate::plugin::PluginRef;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum Wave {
     Sine,
     Square,
@@ -12,10 +15,29 @@ pub struct OscConfig {
     pub wave: Wave,
     pub gain: f32,
     pub semitone: i8,
+    /// -1 (left) to 1 (right), added to the position from the instrument's
+    /// spread.
+    #[serde(default)]
+    pub pan: f32,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Instrument {
-    MultiOsc { oscillators: Vec<OscConfig> },
-    Sampler { sample_id: String, root_pitch: u8 },
+    MultiOsc {
+        oscillators: Vec<OscConfig>,
+        /// 0 to 1: fans the oscillators out evenly from left to right.
+        #[serde(default)]
+        spread: f32,
+    },
+    /// Each note plays the first zone covering its key and velocity.
+    Sampler {
+        zones: Vec<SampleZone>,
+        /// How pitched samples are read between their frames.
+        #[serde(default)]
+        interpolation: Interpolation,
+    },
+    /// Plays the audio clips of its nodes' clip sequences; notes are ignored.
+    Audio,
+    /// A CLAP instrument; it gets the track's notes and does its own voicing.
+    Plugin(PluginRef),
 }
@@ -0,0 +1,55 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// How long after it's rendered the output is heard: what the stream reports,
+/// plus an offset for what it can't know about, like converters or a
+/// Bluetooth link. Shared by the stream, which keeps the report current, and
+/// whatever has to line up with what's heard.
+#[derive(Default)]
+pub struct OutputLatency {
+    /// Bit patterns of `f64` seconds.
+    reported: AtomicU64,
+    offset: AtomicU64,
+}
+
+impl OutputLatency {
+    pub fn report(&self, seconds: f64) {
+        self.reported.store(seconds.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn reported(&self) -> f64 {
+        f64::from_bits(self.reported.load(Ordering::Relaxed))
+    }
+
+    /// Negative offsets take some off the reported latency.
+    pub fn set_offset(&self, seconds: f64) {
+        self.offset.store(seconds.to_bits(), Ordering::Relaxed);
+    }
+
+    /// The latency compensated for, never below zero.
+    pub fn seconds(&self) -> f64 {
+        (self.reported() + f64::from_bits(self.offset.load(Ordering::Relaxed))).max(0.0)
+    }
+
+    pub fn samples(&self, sample_rate: f32) -> u64 {
+        (self.seconds() * sample_rate as f64).round() as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_offset_adds_to_the_report_without_going_negative() {
+        let latency = OutputLatency::default();
+        assert_eq!(latency.samples(48_000.0), 0);
+
+        latency.report(0.01);
+        latency.set_offset(0.005);
+        assert_eq!(latency.samples(48_000.0), 720);
+
+        latency.set_offset(-0.02);
+        assert_eq!(latency.reported(), 0.01);
+        assert_eq!(latency.seconds(), 0.0);
+    }
+}
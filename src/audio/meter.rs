@@ -0,0 +1,90 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// Level of a signal, as linear amplitudes.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Level {
+    /// Highest absolute sample since the level was last taken.
+    pub peak: f32,
+    /// RMS of the latest audio block.
+    pub rms: f32,
+}
+
+impl Level {
+    /// Whether the signal went over full scale.
+    pub fn clipped(&self) -> bool {
+        self.peak > 1.0
+    }
+}
+
+/// A level written by the audio thread and read elsewhere without locking.
+///
+/// Both values are stored as `f32` bit patterns. Bits of non-negative floats
+/// order the same way as the floats, so the peak can be held with `fetch_max`.
+#[derive(Default)]
+pub struct Meter {
+    peak: AtomicU32,
+    rms: AtomicU32,
+}
+
+impl Meter {
+    /// Measures one block of samples, in any channel layout.
+    pub fn record(&self, samples: impl IntoIterator<Item = f32>) {
+        let (mut peak, mut sum, mut count) = (0.0f32, 0.0f32, 0usize);
+        for sample in samples {
+            peak = peak.max(sample.abs());
+            sum += sample * sample;
+            count += 1;
+        }
+        if count == 0 {
+            return;
+        }
+        // NaN would break the ordering the peak hold relies on.
+        if peak.is_finite() {
+            self.peak.fetch_max(peak.to_bits(), Ordering::Relaxed);
+        }
+        let rms = (sum / count as f32).sqrt();
+        self.rms.store(rms.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Returns the level and starts holding a new peak.
+    pub fn take(&self) -> Level {
+        Level {
+            peak: f32::from_bits(self.peak.swap(0, Ordering::Relaxed)),
+            rms: f32::from_bits(self.rms.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// Post-fader meters of every track and the master output.
+#[derive(Default)]
+pub struct Meters {
+    pub master: Meter,
+    pub tracks: Vec<Meter>,
+}
+
+impl Meters {
+    pub fn new(num_tracks: usize) -> Self {
+        Self {
+            master: Meter::default(),
+            tracks: (0..num_tracks).map(|_| Meter::default()).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn holds_peak_until_taken() {
+        let meter = Meter::default();
+        meter.record([0.5, -1.5, 0.0, 0.0]);
+        meter.record([0.5, -0.5]);
+
+        let level = meter.take();
+        assert_eq!(level.peak, 1.5);
+        assert_eq!(level.rms, 0.5);
+        assert!(level.clipped());
+        assert_eq!(meter.take().peak, 0.0);
+    }
+}
@@ -0,0 +1,105 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+/// Click on every beat, computed from the sample position alone so the audio
+/// thread keeps no state for it.
+pub struct Metronome {
+    enabled: AtomicBool,
+    /// Bit pattern of the beat length in samples, as an `f64`.
+    samples_per_beat: AtomicU64,
+    sample_rate: AtomicU64,
+    /// Sample position of the first beat.
+    origin: AtomicU64,
+    /// Until this sample position it clicks even when disabled, counting in.
+    count_in_end: AtomicU64,
+}
+
+const CLICK_SECONDS: f64 = 0.03;
+const CLICK_FREQ: f64 = 1000.0;
+const CLICK_GAIN: f32 = 0.3;
+
+impl Metronome {
+    pub fn new(bpm: f32, sample_rate: f32) -> Self {
+        let metronome = Self {
+            enabled: AtomicBool::new(false),
+            samples_per_beat: AtomicU64::new(0),
+            sample_rate: AtomicU64::new(0),
+            origin: AtomicU64::new(0),
+            count_in_end: AtomicU64::new(0),
+        };
+        metronome.set_tempo(bpm, sample_rate);
+        metronome
+    }
+
+    pub fn set_tempo(&self, bpm: f32, sample_rate: f32) {
+        let samples_per_beat = 60.0 / bpm as f64 * sample_rate as f64;
+        self.samples_per_beat
+            .store(samples_per_beat.to_bits(), Ordering::Relaxed);
+        self.sample_rate
+            .store((sample_rate as f64).to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn set_origin(&self, sample: u64) {
+        self.origin.store(sample, Ordering::Relaxed);
+    }
+
+    pub fn set_count_in_end(&self, sample: u64) {
+        self.count_in_end.store(sample, Ordering::Relaxed);
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// The click's contribution at an absolute sample position.
+    pub fn sample(&self, position: u64) -> f32 {
+        if !self.is_enabled() && position >= self.count_in_end.load(Ordering::Relaxed) {
+            return 0.0;
+        }
+        let samples_per_beat = f64::from_bits(self.samples_per_beat.load(Ordering::Relaxed));
+        let sample_rate = f64::from_bits(self.sample_rate.load(Ordering::Relaxed));
+        if samples_per_beat <= 0.0 || sample_rate <= 0.0 {
+            return 0.0;
+        }
+
+        let Some(position) = position.checked_sub(self.origin.load(Ordering::Relaxed)) else {
+            return 0.0;
+        };
+        let t = (position as f64 % samples_per_beat) / sample_rate;
+        if t >= CLICK_SECONDS {
+            return 0.0;
+        }
+        let envelope = 1.0 - t / CLICK_SECONDS;
+        ((std::f64::consts::TAU * CLICK_FREQ * t).sin() * envelope * envelope) as f32 * CLICK_GAIN
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clicks_only_at_the_start_of_beats() {
+        let metronome = Metronome::new(120.0, 48_000.0);
+        assert_eq!(metronome.sample(10), 0.0);
+
+        metronome.set_enabled(true);
+        assert_ne!(metronome.sample(10), 0.0);
+        assert_ne!(metronome.sample(24_000 + 10), 0.0);
+        assert_eq!(metronome.sample(12_000), 0.0);
+    }
+
+    #[test]
+    fn counts_in_while_disabled() {
+        let metronome = Metronome::new(120.0, 48_000.0);
+        metronome.set_origin(1_000);
+        metronome.set_count_in_end(1_000 + 96_000);
+        assert_eq!(metronome.sample(10), 0.0);
+        assert_ne!(metronome.sample(1_010), 0.0);
+        assert_ne!(metronome.sample(1_000 + 72_000 + 10), 0.0);
+        assert_eq!(metronome.sample(1_000 + 96_000 + 10), 0.0);
+    }
+}
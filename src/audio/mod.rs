@@ -1,9 +1,41 @@
+mod clip;
+mod decode;
+mod fade;
 mod instrument;
+mod latency;
+mod meter;
+mod metronome;
+mod morph;
+mod output;
+mod pool;
+mod record;
+mod response;
+mod safety;
+mod sample;
 mod track;
 mod voice;
 
+pub use clip::ClipVoice;
+pub use decode::{AUDIO_EXTENSIONS, DecodedAudio, decode_file};
+pub use fade::{FADE_OUT_SECONDS, FadeOut, Pause};
 pub use instrument::{Instrument, OscConfig, Wave};
-pub use track::{NotePlaybackState, PlaybackState, TrackConfig};
+pub use latency::OutputLatency;
+pub use meter::{Level, Meter, Meters};
+pub use metronome::Metronome;
+pub use morph::{InstrumentSnapshot, MorphKnob, MorphSmoother, morph_into, morph_scratch};
+#[cfg(feature = "jack")]
+pub use output::name_jack_ports;
+#[cfg(not(feature = "audio-host"))]
+pub(crate) use output::no_audio_host;
+pub use output::{AudioBackend, AudioSettings, OutputDevice, Stream, output_devices};
+pub use pool::{RenderJob, RenderPool};
+pub use record::Recorder;
+pub use response::{KeyTracking, VelocityCurve, VelocityResponse};
+pub use safety::OutputGuard;
+pub use sample::{SampleBank, SampleBuffer, SampleMode, SampleRegion, SampleSpan, SampleZone};
+pub use track::{
+    ChokeMode, FadingTrack, NotePlaybackState, PlaybackState, TrackConfig, TrackUid, removed_tracks,
+};
 pub use voice::{ADSRConfig, EnvelopeState, NoteState};
 
 pub fn midi_to_freq(note: u8) -> f32 {
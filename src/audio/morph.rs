@@ -0,0 +1,270 @@
+use super::{ADSRConfig, Instrument, OscConfig, TrackConfig};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// A saved set of instrument parameters a track can morph towards.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstrumentSnapshot {
+    pub instrument: Instrument,
+    pub adsr: ADSRConfig,
+    pub volume: f32,
+    pub pan: f32,
+}
+
+/// Morph position shared between the control side and the audio thread.
+///
+/// Stored as the bit pattern of an `f32` so it can be written from the engine
+/// or a MIDI callback without locking.
+pub struct MorphKnob {
+    value: AtomicU32,
+}
+
+impl MorphKnob {
+    pub fn new(value: f32) -> Self {
+        Self {
+            value: AtomicU32::new(value.clamp(0.0, 1.0).to_bits()),
+        }
+    }
+
+    pub fn set(&self, value: f32) {
+        self.value
+            .store(value.clamp(0.0, 1.0).to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> f32 {
+        f32::from_bits(self.value.load(Ordering::Relaxed))
+    }
+}
+
+impl Default for MorphKnob {
+    fn default() -> Self {
+        Self::new(0.0)
+    }
+}
+
+/// One-pole smoothing of the knob, advanced once per audio block.
+pub struct MorphSmoother {
+    current: f32,
+}
+
+/// Time for the smoothed value to cover ~63% of a jump.
+const SMOOTHING_SECONDS: f32 = 0.02;
+
+impl MorphSmoother {
+    pub fn new(value: f32) -> Self {
+        Self { current: value }
+    }
+
+    pub fn next_block(&mut self, target: f32, block_frames: usize, sample_rate: f32) -> f32 {
+        let block_seconds = block_frames as f32 / sample_rate;
+        let coefficient = 1.0 - (-block_seconds / SMOOTHING_SECONDS).exp();
+        self.current += (target - self.current) * coefficient;
+        self.current
+    }
+}
+
+/// Writes the interpolation between `source` (position 0) and its morph target
+/// (position 1) into `output`, a [`morph_scratch`] of `source`, in place.
+///
+/// Continuous parameters are interpolated linearly; discrete ones (waveforms,
+/// semitone offsets, sample ids) switch over at the midpoint. When the two
+/// oscillator banks have different sizes, missing oscillators fade from silence.
+pub fn morph_into(output: &mut TrackConfig, source: &TrackConfig, position: f32) {
+    let Some(target) = &source.morph_target else {
+        return;
+    };
+    let t = position.clamp(0.0, 1.0);
+
+    output.volume = lerp(source.volume, target.volume, t);
+    output.pan = lerp(source.pan, target.pan, t);
+    output.adsr = ADSRConfig {
+        attack: lerp(source.adsr.attack, target.adsr.attack, t),
+        decay: lerp(source.adsr.decay, target.adsr.decay, t),
+        sustain: lerp(source.adsr.sustain, target.adsr.sustain, t),
+        release: lerp(source.adsr.release, target.adsr.release, t),
+        attack_curve: lerp(source.adsr.attack_curve, target.adsr.attack_curve, t),
+        decay_curve: lerp(source.adsr.decay_curve, target.adsr.decay_curve, t),
+        release_curve: lerp(source.adsr.release_curve, target.adsr.release_curve, t),
+    };
+
+    match (
+        &source.instrument,
+        &target.instrument,
+        &mut output.instrument,
+    ) {
+        (
+            Instrument::MultiOsc {
+                oscillators: a,
+                spread: a_spread,
+            },
+            Instrument::MultiOsc {
+                oscillators: b,
+                spread: b_spread,
+            },
+            Instrument::MultiOsc {
+                oscillators: out,
+                spread: out_spread,
+            },
+        ) if out.len() == a.len().max(b.len()) => {
+            *out_spread = lerp(*a_spread, *b_spread, t);
+            for (i, osc) in out.iter_mut().enumerate() {
+                morph_oscillator(osc, a.get(i), b.get(i), t);
+            }
+        }
+        // The scratch keeps the side that isn't playing in its own morph
+        // target, so switching over swaps the two instead of cloning either.
+        _ => {
+            if (t >= 0.5) != output.morph_swapped
+                && let Some(spare) = &mut output.morph_target
+            {
+                std::mem::swap(&mut output.instrument, &mut spare.instrument);
+                output.morph_swapped = !output.morph_swapped;
+            }
+        }
+    }
+}
+
+/// Builds the config the audio thread renders from: the morph shape, sized so
+/// that note state allocated at note-on stays valid for any knob position.
+pub fn morph_scratch(source: &TrackConfig) -> TrackConfig {
+    let mut scratch = source.clone();
+    if let (
+        Instrument::MultiOsc {
+            oscillators: a,
+            spread,
+        },
+        Some(InstrumentSnapshot {
+            instrument: Instrument::MultiOsc { oscillators: b, .. },
+            ..
+        }),
+    ) = (&source.instrument, &source.morph_target)
+    {
+        let longest = if a.len() >= b.len() { a } else { b };
+        scratch.instrument = Instrument::MultiOsc {
+            oscillators: longest.clone(),
+            spread: *spread,
+        };
+    }
+    scratch
+}
+
+fn morph_oscillator(out: &mut OscConfig, a: Option<&OscConfig>, b: Option<&OscConfig>, t: f32) {
+    let (a_gain, b_gain) = (a.map_or(0.0, |o| o.gain), b.map_or(0.0, |o| o.gain));
+    out.gain = lerp(a_gain, b_gain, t);
+    // A missing oscillator is silent, so it can take the other one's position.
+    let (a_pan, b_pan) = match (a, b) {
+        (Some(a), Some(b)) => (a.pan, b.pan),
+        (Some(only), None) | (None, Some(only)) => (only.pan, only.pan),
+        (None, None) => (0.0, 0.0),
+    };
+    out.pan = lerp(a_pan, b_pan, t);
+
+    let shape = match (a, b) {
+        (Some(a), Some(b)) => {
+            if t < 0.5 {
+                a
+            } else {
+                b
+            }
+        }
+        (Some(only), None) | (None, Some(only)) => only,
+        (None, None) => return,
+    };
+    out.wave = shape.wave;
+    out.semitone = shape.semitone;
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::Wave;
+
+    fn osc(wave: Wave, gain: f32) -> OscConfig {
+        OscConfig {
+            wave,
+            gain,
+            semitone: 0,
+            pan: 0.0,
+        }
+    }
+
+    fn adsr(attack: f32) -> ADSRConfig {
+        ADSRConfig::new(attack, 0.1, 0.5, 0.2)
+    }
+
+    fn morphing_track() -> TrackConfig {
+        let mut config = TrackConfig::new(
+            0,
+            Instrument::MultiOsc {
+                oscillators: vec![osc(Wave::Sine, 1.0)],
+                spread: 0.0,
+            },
+            adsr(0.0),
+        );
+        config.morph_target = Some(InstrumentSnapshot {
+            instrument: Instrument::MultiOsc {
+                oscillators: vec![osc(Wave::Saw, 0.0), osc(Wave::Square, 0.5)],
+                spread: 1.0,
+            },
+            adsr: adsr(1.0),
+            volume: 0.5,
+            pan: 1.0,
+        });
+        config
+    }
+
+    #[test]
+    fn interpolates_continuous_parameters() {
+        let source = morphing_track();
+        let mut output = morph_scratch(&source);
+
+        morph_into(&mut output, &source, 0.25);
+
+        assert_eq!(output.volume, 0.875);
+        assert_eq!(output.pan, 0.25);
+        assert_eq!(output.adsr.attack, 0.25);
+        let Instrument::MultiOsc {
+            oscillators,
+            spread,
+        } = &output.instrument
+        else {
+            panic!("expected MultiOsc");
+        };
+        assert_eq!(*spread, 0.25);
+        assert_eq!(oscillators.len(), 2);
+        assert_eq!(oscillators[0].gain, 0.75);
+        assert!(matches!(oscillators[0].wave, Wave::Sine));
+        assert_eq!(oscillators[1].gain, 0.125);
+    }
+
+    #[test]
+    fn instruments_that_cant_blend_switch_over_without_allocating() {
+        let mut source = morphing_track();
+        source.morph_target.as_mut().unwrap().instrument = Instrument::Audio;
+        let mut output = morph_scratch(&source);
+
+        crate::audit::no_alloc(|| {
+            morph_into(&mut output, &source, 0.75);
+            assert!(matches!(output.instrument, Instrument::Audio));
+            morph_into(&mut output, &source, 0.6);
+            assert!(matches!(output.instrument, Instrument::Audio));
+            morph_into(&mut output, &source, 0.25);
+        });
+        assert_eq!(output.num_oscillators(), 1);
+        assert_eq!(output.volume, 0.875);
+    }
+
+    #[test]
+    fn smoother_converges_on_target() {
+        let mut smoother = MorphSmoother::new(0.0);
+        let mut value = 0.0;
+        for _ in 0..100 {
+            value = smoother.next_block(1.0, 512, 48_000.0);
+        }
+        assert!((value - 1.0).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,299 @@
+use crate::AurioError;
+#[cfg(feature = "audio-host")]
+use cpal::traits::{DeviceTrait, HostTrait};
+use serde::{Deserialize, Serialize};
+
+/// A running input or output stream; dropping it stops it.
+#[cfg(feature = "audio-host")]
+pub type Stream = cpal::Stream;
+
+/// Without the `audio-host` feature no stream can be opened.
+#[cfg(not(feature = "audio-host"))]
+pub enum Stream {}
+
+/// The error for every device operation when built without `audio-host`.
+#[cfg(not(feature = "audio-host"))]
+pub(crate) fn no_audio_host() -> AurioError {
+    AurioError::audio_device("aurio was built without the audio-host feature")
+}
+
+/// Which audio system the engine plays through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum AudioBackend {
+    /// The platform default (ALSA, CoreAudio, WASAPI, ...).
+    #[default]
+    Default,
+    /// JACK, for routing between pro-audio apps on Linux. Needs aurio built
+    /// with the `jack` feature.
+    Jack,
+}
+
+impl AudioBackend {
+    /// Backends this build can open.
+    pub fn available() -> Vec<AudioBackend> {
+        let mut backends = vec![AudioBackend::Default];
+        if cfg!(feature = "jack") {
+            backends.push(AudioBackend::Jack);
+        }
+        backends
+    }
+
+    #[cfg(feature = "audio-host")]
+    pub fn host(self) -> Result<cpal::Host, AurioError> {
+        match self {
+            AudioBackend::Default => Ok(cpal::default_host()),
+            #[cfg(feature = "jack")]
+            AudioBackend::Jack => {
+                cpal::host_from_id(cpal::HostId::Jack).map_err(AurioError::audio_device)
+            }
+            #[cfg(not(feature = "jack"))]
+            AudioBackend::Jack => Err(AurioError::audio_device(
+                "aurio was built without JACK support",
+            )),
+        }
+    }
+}
+
+/// Sample rates offered in the settings when a device supports them.
+#[cfg(feature = "audio-host")]
+const COMMON_SAMPLE_RATES: [u32; 5] = [44100, 48000, 88200, 96000, 192000];
+
+/// An output device as listed in the audio settings.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputDevice {
+    pub name: String,
+    pub is_default: bool,
+    /// Common sample rates at least one of the device's configs supports.
+    pub sample_rates: Vec<u32>,
+    /// Smallest and largest buffer size in frames, when the host reports them.
+    pub buffer_sizes: Option<(u32, u32)>,
+}
+
+/// Lists the output devices of `backend`.
+#[cfg(not(feature = "audio-host"))]
+pub fn output_devices(_backend: AudioBackend) -> Result<Vec<OutputDevice>, AurioError> {
+    Err(no_audio_host())
+}
+
+/// Lists the output devices of `backend`.
+#[cfg(feature = "audio-host")]
+pub fn output_devices(backend: AudioBackend) -> Result<Vec<OutputDevice>, AurioError> {
+    let host = backend.host()?;
+    let default_name = host
+        .default_output_device()
+        .and_then(|device| device.description().ok())
+        .map(|description| description.name().to_string());
+
+    let mut devices = Vec::new();
+    for device in host.output_devices().map_err(AurioError::audio_device)? {
+        let Ok(description) = device.description() else {
+            continue;
+        };
+        let name = description.name().to_string();
+        let configs: Vec<_> = match device.supported_output_configs() {
+            Ok(configs) => configs.collect(),
+            Err(_) => Vec::new(),
+        };
+
+        let sample_rates = COMMON_SAMPLE_RATES
+            .into_iter()
+            .filter(|&rate| {
+                configs
+                    .iter()
+                    .any(|c| c.min_sample_rate() <= rate && rate <= c.max_sample_rate())
+            })
+            .collect();
+        let buffer_sizes = configs
+            .iter()
+            .filter_map(|c| match *c.buffer_size() {
+                cpal::SupportedBufferSize::Range { min, max } => Some((min, max)),
+                cpal::SupportedBufferSize::Unknown => None,
+            })
+            .reduce(|(min_a, max_a), (min_b, max_b)| (min_a.min(min_b), max_a.max(max_b)));
+
+        devices.push(OutputDevice {
+            is_default: default_name.as_deref() == Some(name.as_str()),
+            name,
+            sample_rates,
+            buffer_sizes,
+        });
+    }
+    Ok(devices)
+}
+
+/// How the engine opens its output stream.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AudioSettings {
+    pub backend: AudioBackend,
+    /// Output device by name; `None` uses the backend's default device.
+    pub device_name: Option<String>,
+    /// Stream sample rate; `None` keeps the device's default.
+    pub sample_rate: Option<u32>,
+    /// Buffer size in frames; `None` lets the host choose.
+    pub buffer_size: Option<u32>,
+    /// Also give every track its own stereo output after the master pair, so
+    /// tracks can be routed separately (e.g. to JACK ports). Channels 1-2 carry
+    /// the master mix and track `n` uses channels `2n + 3` and `2n + 4`.
+    /// Devices without that many channels play only the master mix.
+    pub track_outputs: bool,
+    /// Milliseconds added to the output latency the device reports, for
+    /// delays it can't know about. Negative takes some off.
+    #[serde(default)]
+    pub latency_offset_ms: f32,
+    /// Threads tracks are rendered on, the audio callback's own included.
+    /// Up to one renders every track in the callback.
+    #[serde(default)]
+    pub render_threads: usize,
+    /// Leaves out the DC blocker and safety limiter on the output, for
+    /// measuring or for a chain that has its own.
+    #[serde(default)]
+    pub bypass_output_guard: bool,
+}
+
+impl AudioSettings {
+    /// Output channels needed for `num_tracks` tracks with these settings.
+    pub fn channels(&self, num_tracks: usize) -> u16 {
+        if self.track_outputs {
+            (2 + 2 * num_tracks) as u16
+        } else {
+            2
+        }
+    }
+
+    /// What each output channel carries, in channel order, to name ports
+    /// by: the master pair, then with track outputs one pair per track of
+    /// `track_names`.
+    pub fn port_names(&self, track_names: &[String]) -> Vec<String> {
+        let pair = |name: &str| [format!("{name} L"), format!("{name} R")];
+        let mut names = pair("Master").to_vec();
+        if self.track_outputs {
+            names.extend(track_names.iter().flat_map(|name| pair(name)));
+        }
+        names
+    }
+
+    /// Opens the output device and picks a stream config for `num_tracks`.
+    #[cfg(feature = "audio-host")]
+    pub fn open_output(
+        &self,
+        num_tracks: usize,
+    ) -> Result<(cpal::Device, cpal::StreamConfig), AurioError> {
+        let host = self.backend.host()?;
+        let device = match &self.device_name {
+            Some(name) => host
+                .output_devices()
+                .map_err(AurioError::audio_device)?
+                .find(|device| {
+                    device
+                        .description()
+                        .is_ok_and(|description| description.name() == name)
+                })
+                .ok_or_else(|| {
+                    AurioError::audio_device(format!("Output device \"{}\" not found", name))
+                })?,
+            None => host
+                .default_output_device()
+                .ok_or_else(|| AurioError::audio_device("No output device"))?,
+        };
+
+        let mut config: cpal::StreamConfig = device
+            .default_output_config()
+            .map_err(AurioError::audio_device)?
+            .into();
+        if let Some(sample_rate) = self.sample_rate {
+            config.sample_rate = sample_rate;
+        }
+        if self.track_outputs {
+            let wanted = self.channels(num_tracks);
+            let supported = device
+                .supported_output_configs()
+                .map(|configs| configs.collect::<Vec<_>>())
+                .unwrap_or_default();
+            match fitting_channels(&supported, wanted, config.sample_rate) {
+                Some(channels) => config.channels = channels,
+                None => tracing::warn!(
+                    "The output has no {} channels to give tracks their own; \
+                     playing the master mix only",
+                    wanted
+                ),
+            }
+        }
+        if let Some(frames) = self.buffer_size {
+            config.buffer_size = cpal::BufferSize::Fixed(frames);
+        }
+        Ok((device, config))
+    }
+}
+
+/// The fewest channels of `supported` configs running at `sample_rate` that
+/// make room for `wanted`, since some hosts only offer a few counts.
+#[cfg(feature = "audio-host")]
+fn fitting_channels(
+    supported: &[cpal::SupportedStreamConfigRange],
+    wanted: u16,
+    sample_rate: u32,
+) -> Option<u16> {
+    supported
+        .iter()
+        .filter(|c| c.min_sample_rate() <= sample_rate && sample_rate <= c.max_sample_rate())
+        .map(|c| c.channels())
+        .filter(|&channels| channels >= wanted)
+        .min()
+}
+
+/// Labels the ports of the JACK client `client`, which cpal opened for the
+/// output, with `names` in channel order. cpal registers them as `out_0`,
+/// `out_1`, ..., so the names are set as aliases, which patchbays such as
+/// qjackctl can show in their place.
+#[cfg(feature = "jack")]
+pub fn name_jack_ports(client: &str, names: &[String]) -> Result<(), AurioError> {
+    let (jack, _) = jack::Client::new("aurio_ports", jack::ClientOptions::NO_START_SERVER)
+        .map_err(AurioError::audio_device)?;
+    for (i, name) in names.iter().enumerate() {
+        let port_name = format!("{client}:out_{i}");
+        let mut port = jack
+            .port_by_name(&port_name)
+            .ok_or_else(|| AurioError::audio_device(format!("No JACK port \"{}\"", port_name)))?;
+        if !port.aliases().is_ok_and(|aliases| aliases.contains(name)) {
+            port.set_alias(name).map_err(AurioError::audio_device)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn track_outputs_add_a_stereo_pair_per_track() {
+        let mut settings = AudioSettings::default();
+        assert_eq!(settings.channels(3), 2);
+        settings.track_outputs = true;
+        assert_eq!(settings.channels(3), 8);
+        assert_eq!(
+            settings.port_names(&["Drums".into()]),
+            ["Master L", "Master R", "Drums L", "Drums R"]
+        );
+    }
+
+    #[cfg(feature = "audio-host")]
+    #[test]
+    fn track_outputs_open_only_channel_counts_the_device_has() {
+        let config = |channels, rate| {
+            cpal::SupportedStreamConfigRange::new(
+                channels,
+                rate,
+                rate,
+                cpal::SupportedBufferSize::Unknown,
+                cpal::SampleFormat::F32,
+            )
+        };
+        // As JACK offers them: 10 channels for 4 tracks open as 16.
+        let jack = [config(2, 48_000), config(8, 48_000), config(16, 48_000)];
+        assert_eq!(fitting_channels(&jack, 10, 48_000), Some(16));
+        assert_eq!(fitting_channels(&jack, 10, 44_100), None);
+        // A stereo device stays stereo.
+        assert_eq!(fitting_channels(&[config(2, 48_000)], 4, 48_000), None);
+    }
+}
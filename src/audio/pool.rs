@@ -0,0 +1,142 @@
+use crossbeam::channel::{Receiver, Sender, TryRecvError, TrySendError};
+
+/// Jobs a pool can queue before the rest are run by the thread asking.
+const QUEUE_CAPACITY: usize = 256;
+
+/// Work a [`RenderPool`] hands out, like one track's block.
+pub trait RenderJob: Send + 'static {
+    /// What every job of a batch shares, cloned for each of them.
+    type Block: Clone + Send + 'static;
+
+    /// Does the job, the `index`th of its batch.
+    fn render(&mut self, index: usize, block: &Self::Block);
+}
+
+/// Helper threads that jobs are handed to, already boxed, so a batch moves
+/// through the queues without allocating or copying. The thread running a
+/// batch takes jobs too and spins for the rest, so it's as fast as the
+/// slowest job rather than as the sum of all of them.
+pub struct RenderPool<J: RenderJob> {
+    job_tx: Sender<(usize, Box<J>, J::Block)>,
+    job_rx: Receiver<(usize, Box<J>, J::Block)>,
+    done_rx: Receiver<(usize, Box<J>)>,
+    /// Jobs of the running batch as they finish, kept for its capacity.
+    finished: Vec<(usize, Box<J>)>,
+}
+
+impl<J: RenderJob> RenderPool<J> {
+    /// A pool rendering on `threads` threads, counting the one running the
+    /// batches, or `None` when that's all there is. The helpers stop once
+    /// the pool is dropped.
+    pub fn new(threads: usize) -> Option<Self> {
+        if threads < 2 {
+            return None;
+        }
+        let (job_tx, job_rx) =
+            crossbeam::channel::bounded::<(usize, Box<J>, J::Block)>(QUEUE_CAPACITY);
+        let (done_tx, done_rx) = crossbeam::channel::bounded(QUEUE_CAPACITY);
+        for i in 1..threads {
+            let (jobs, done) = (job_rx.clone(), done_tx.clone());
+            let spawned = std::thread::Builder::new()
+                .name(format!("aurio-render-{}", i))
+                .spawn(move || {
+                    for (index, mut job, block) in jobs {
+                        job.render(index, &block);
+                        drop(block);
+                        if done.send((index, job)).is_err() {
+                            break;
+                        }
+                    }
+                });
+            if let Err(e) = spawned {
+                tracing::warn!("Rendering on fewer threads: {}", e);
+                break;
+            }
+        }
+        Some(Self {
+            job_tx,
+            job_rx,
+            done_rx,
+            finished: Vec::with_capacity(QUEUE_CAPACITY),
+        })
+    }
+
+    /// Renders every job in `jobs`, leaving them there in the same order.
+    pub fn run(&mut self, jobs: &mut Vec<Box<J>>, block: &J::Block) {
+        let mut pending = 0;
+        for (index, job) in jobs.drain(..).enumerate() {
+            match self.job_tx.try_send((index, job, block.clone())) {
+                Ok(()) => pending += 1,
+                Err(TrySendError::Full((index, mut job, _)))
+                | Err(TrySendError::Disconnected((index, mut job, _))) => {
+                    job.render(index, block);
+                    self.finished.push((index, job));
+                }
+            }
+        }
+
+        // Jobs still queued are taken back and rendered here; for the ones
+        // a helper has, the thread spins rather than parking, which can block
+        // and allocates the first time.
+        while pending > 0 {
+            let finished = match self.job_rx.try_recv() {
+                Ok((index, mut job, _)) => {
+                    job.render(index, block);
+                    (index, job)
+                }
+                Err(_) => match self.done_rx.try_recv() {
+                    Ok(finished) => finished,
+                    Err(TryRecvError::Empty) => {
+                        std::hint::spin_loop();
+                        continue;
+                    }
+                    // Every helper is gone; whatever's still queued is ours.
+                    Err(TryRecvError::Disconnected) if !self.job_rx.is_empty() => continue,
+                    Err(TryRecvError::Disconnected) => break,
+                },
+            };
+            self.finished.push(finished);
+            pending -= 1;
+        }
+
+        self.finished.sort_unstable_by_key(|(index, _)| *index);
+        jobs.extend(self.finished.drain(..).map(|(_, job)| job));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    struct Square(Vec<u64>);
+
+    impl RenderJob for Square {
+        type Block = Arc<u64>;
+
+        fn render(&mut self, index: usize, block: &Arc<u64>) {
+            self.0.push(index as u64 * index as u64 + **block);
+        }
+    }
+
+    #[test]
+    fn jobs_come_back_rendered_in_order() {
+        assert!(RenderPool::<Square>::new(1).is_none());
+
+        let mut pool = RenderPool::new(4).unwrap();
+        let mut jobs: Vec<_> = (0..QUEUE_CAPACITY + 10)
+            .map(|_| Box::new(Square(Vec::new())))
+            .collect();
+        let block = Arc::new(1);
+        for _ in 0..3 {
+            pool.run(&mut jobs, &block);
+        }
+
+        assert_eq!(jobs.len(), QUEUE_CAPACITY + 10);
+        for (i, job) in jobs.iter().enumerate() {
+            assert_eq!(job.0, vec![(i * i) as u64 + 1; 3]);
+        }
+        // The helpers let go of the block once they're done with it.
+        assert_eq!(Arc::strong_count(&block), 1);
+    }
+}
@@ -0,0 +1,129 @@
+use super::{AudioBackend, Stream};
+use crate::AurioError;
+#[cfg(feature = "audio-host")]
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+#[cfg(feature = "audio-host")]
+use ringbuf::HeapRb;
+#[cfg(feature = "audio-host")]
+use ringbuf::traits::{Consumer, Observer, Producer, Split};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread::JoinHandle;
+
+/// Seconds of audio buffered between the input callback and the writer.
+#[cfg(feature = "audio-host")]
+const BUFFER_SECONDS: u32 = 2;
+
+/// Captures the default input device to a WAV file.
+///
+/// The input callback only copies into a ring buffer; a writer thread drains
+/// it to disk, so a slow disk drops audio instead of glitching the stream.
+pub struct Recorder {
+    stream: Stream,
+    stop: Arc<AtomicBool>,
+    dropped: Arc<AtomicU64>,
+    writer: JoinHandle<Result<(), String>>,
+    path: PathBuf,
+}
+
+impl Recorder {
+    #[cfg(not(feature = "audio-host"))]
+    pub fn start(_backend: AudioBackend, _path: &Path) -> Result<Self, AurioError> {
+        Err(super::no_audio_host())
+    }
+
+    #[cfg(feature = "audio-host")]
+    pub fn start(backend: AudioBackend, path: &Path) -> Result<Self, AurioError> {
+        let device = backend
+            .host()?
+            .default_input_device()
+            .ok_or_else(|| AurioError::audio_device("No input device"))?;
+        let config: cpal::StreamConfig = device
+            .default_input_config()
+            .map_err(AurioError::audio_device)?
+            .into();
+
+        let capacity = (config.sample_rate * config.channels as u32 * BUFFER_SECONDS) as usize;
+        let (mut producer, mut consumer) = HeapRb::<f32>::new(capacity).split();
+        let stop = Arc::new(AtomicBool::new(false));
+        let dropped = Arc::new(AtomicU64::new(0));
+
+        let spec = hound::WavSpec {
+            channels: config.channels,
+            sample_rate: config.sample_rate,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        let mut file = hound::WavWriter::create(path, spec).map_err(|e| match e {
+            hound::Error::IoError(e) => AurioError::project_io(path, e),
+            e => AurioError::ProjectFormat(e.to_string()),
+        })?;
+        let writer_stop = stop.clone();
+        let writer = std::thread::spawn(move || {
+            let fail = |e: hound::Error| format!("Failed to write recording: {}", e);
+            let mut chunk = vec![0.0f32; 4096];
+            loop {
+                // Checked before draining, so nothing pushed before the stop
+                // is left behind.
+                let stopping = writer_stop.load(Ordering::Acquire);
+                while !consumer.is_empty() {
+                    let n = consumer.pop_slice(&mut chunk);
+                    for &sample in &chunk[..n] {
+                        file.write_sample(sample).map_err(fail)?;
+                    }
+                }
+                if stopping {
+                    return file.finalize().map_err(fail);
+                }
+                std::thread::sleep(std::time::Duration::from_millis(10));
+            }
+        });
+
+        let callback_dropped = dropped.clone();
+        let stream = device
+            .build_input_stream(
+                &config,
+                move |data: &[f32], _: &cpal::InputCallbackInfo| {
+                    let written = producer.push_slice(data);
+                    if written < data.len() {
+                        callback_dropped
+                            .fetch_add((data.len() - written) as u64, Ordering::Relaxed);
+                    }
+                },
+                |err| tracing::error!("Input error: {}", err),
+                None,
+            )
+            .map_err(AurioError::audio_device)?;
+        stream.play().map_err(AurioError::audio_device)?;
+
+        Ok(Self {
+            stream,
+            stop,
+            dropped,
+            writer,
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Stops capturing and waits for the file to be written out.
+    pub fn finish(self) -> Result<PathBuf, String> {
+        // Without `audio-host` there's no stream to close.
+        #[cfg_attr(not(feature = "audio-host"), allow(clippy::drop_non_drop))]
+        drop(self.stream);
+        self.stop.store(true, Ordering::Release);
+        self.writer
+            .join()
+            .map_err(|_| "Recording writer panicked".to_string())??;
+
+        let dropped = self.dropped.load(Ordering::Relaxed);
+        if dropped > 0 {
+            tracing::warn!(
+                "Recording {}: dropped {} samples",
+                self.path.display(),
+                dropped
+            );
+        }
+        Ok(self.path)
+    }
+}
@@ -0,0 +1,133 @@
+use serde::{Deserialize, Serialize};
+
+/// Shape of a track's response to how hard notes are played.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum VelocityCurve {
+    #[default]
+    Linear,
+    /// Quiet notes come out louder, for a light touch.
+    Soft,
+    /// Notes have to be played hard to come out loud.
+    Hard,
+}
+
+impl VelocityCurve {
+    fn apply(self, x: f32) -> f32 {
+        match self {
+            VelocityCurve::Linear => x,
+            VelocityCurve::Soft => x.sqrt(),
+            VelocityCurve::Hard => x * x,
+        }
+    }
+}
+
+/// How a note's velocity maps to its level: along `curve`, from `min` for
+/// velocity 0 to `max` for 127.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VelocityResponse {
+    pub curve: VelocityCurve,
+    pub min: f32,
+    pub max: f32,
+}
+
+impl Default for VelocityResponse {
+    fn default() -> Self {
+        Self {
+            curve: VelocityCurve::Linear,
+            min: 0.0,
+            max: 1.0,
+        }
+    }
+}
+
+impl VelocityResponse {
+    pub fn gain(&self, velocity: u8) -> f32 {
+        let x = self.curve.apply(velocity.min(127) as f32 / 127.0);
+        self.min + (self.max - self.min) * x
+    }
+}
+
+/// Changes across the keyboard, per octave away from `center`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KeyTracking {
+    pub center: u8,
+    /// Level change in dB, positive for louder high notes.
+    pub amp_db: f32,
+    /// How much faster the envelope runs: at 1 it runs twice as fast an
+    /// octave up and half as fast an octave down, like a plucked string.
+    pub envelope: f32,
+}
+
+impl Default for KeyTracking {
+    fn default() -> Self {
+        Self {
+            center: 60,
+            amp_db: 0.0,
+            envelope: 0.0,
+        }
+    }
+}
+
+impl KeyTracking {
+    fn octaves(&self, pitch: u8) -> f32 {
+        (pitch as f32 - self.center as f32) / 12.0
+    }
+
+    pub fn gain(&self, pitch: u8) -> f32 {
+        if self.amp_db == 0.0 {
+            return 1.0;
+        }
+        10.0_f32.powf(self.amp_db * self.octaves(pitch) / 20.0)
+    }
+
+    /// Factor the envelope's clock runs at for `pitch`.
+    pub fn envelope_speed(&self, pitch: u8) -> f32 {
+        if self.envelope == 0.0 {
+            return 1.0;
+        }
+        2.0_f32.powf(self.envelope * self.octaves(pitch))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn velocity_maps_along_the_curve_between_min_and_max() {
+        let linear = VelocityResponse::default();
+        assert_eq!(linear.gain(127), 1.0);
+        assert_eq!(linear.gain(0), 0.0);
+
+        let soft = VelocityResponse {
+            curve: VelocityCurve::Soft,
+            min: 0.2,
+            max: 0.8,
+        };
+        let hard = VelocityResponse {
+            curve: VelocityCurve::Hard,
+            ..soft
+        };
+        assert_eq!(soft.gain(0), 0.2);
+        assert!((soft.gain(127) - 0.8).abs() < 1e-6);
+        assert!(soft.gain(64) > linear.gain(64) * 0.6 + 0.2);
+        assert!(hard.gain(64) < linear.gain(64) * 0.6 + 0.2);
+    }
+
+    #[test]
+    fn key_tracking_scales_by_octave_from_the_center() {
+        let tracking = KeyTracking {
+            center: 60,
+            amp_db: -6.0,
+            envelope: 1.0,
+        };
+        assert_eq!(tracking.gain(60), 1.0);
+        assert!((tracking.gain(72) - 0.501).abs() < 1e-3);
+        assert!((tracking.gain(48) - 1.995).abs() < 1e-3);
+        assert_eq!(tracking.envelope_speed(72), 2.0);
+        assert_eq!(tracking.envelope_speed(48), 0.5);
+        assert_eq!(KeyTracking::default().gain(100), 1.0);
+    }
+}
@@ -0,0 +1,112 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Corner of the DC blocker, in Hz: well under anything audible.
+const DC_CUTOFF: f32 = 5.0;
+
+/// Level the limiter never lets a sample past, about -0.3 dBFS.
+const CEILING: f32 = 0.966;
+
+/// Seconds the limiter takes to let go once the peaks are gone.
+const RELEASE: f32 = 0.1;
+
+/// One channel's DC blocker and limiter.
+#[derive(Debug, Clone, Copy)]
+struct ChannelGuard {
+    last_input: f32,
+    last_output: f32,
+    gain: f32,
+}
+
+impl Default for ChannelGuard {
+    fn default() -> Self {
+        Self {
+            last_input: 0.0,
+            last_output: 0.0,
+            gain: 1.0,
+        }
+    }
+}
+
+/// The last stage before the speakers: a high-pass taking out DC offsets and
+/// a brickwall limiter, so a broken patch or a runaway feedback loop can't
+/// send anything louder than [`CEILING`] or stuck off-centre. Samples that
+/// aren't finite come out as silence. The limiter attacks instantly and
+/// works on each channel alone; on a healthy mix it never acts.
+pub struct OutputGuard {
+    /// Set from elsewhere to pass the output through untouched.
+    bypassed: Arc<AtomicBool>,
+    channels: Vec<ChannelGuard>,
+}
+
+impl OutputGuard {
+    pub fn new(bypassed: Arc<AtomicBool>) -> Self {
+        Self {
+            bypassed,
+            channels: Vec::new(),
+        }
+    }
+
+    /// Guards interleaved frames of `channels` channels. Only allocates
+    /// when the channel count changes.
+    pub fn process(&mut self, data: &mut [f32], channels: usize, sample_rate: f32) {
+        if self.bypassed.load(Ordering::Relaxed) || channels == 0 {
+            return;
+        }
+        if self.channels.len() != channels {
+            crate::audit::permit_alloc(|| {
+                self.channels.resize(channels, ChannelGuard::default());
+            });
+        }
+        let pole = (-std::f32::consts::TAU * DC_CUTOFF / sample_rate).exp();
+        let release = 1.0 - (-1.0 / (RELEASE * sample_rate)).exp();
+        for frame in data.chunks_exact_mut(channels) {
+            for (sample, channel) in frame.iter_mut().zip(&mut self.channels) {
+                let input = if sample.is_finite() { *sample } else { 0.0 };
+                let output = input - channel.last_input + pole * channel.last_output;
+                channel.last_input = input;
+                channel.last_output = output;
+
+                let target = if output.abs() > CEILING {
+                    CEILING / output.abs()
+                } else {
+                    1.0
+                };
+                if target < channel.gain {
+                    channel.gain = target;
+                } else {
+                    channel.gain += (target - channel.gain) * release;
+                }
+                // Clamped too, in case rounding leaves it a hair over.
+                *sample = (output * channel.gain).clamp(-CEILING, CEILING);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offsets_fade_and_peaks_stay_under_the_ceiling() {
+        let bypassed = Arc::new(AtomicBool::new(false));
+        let mut guard = OutputGuard::new(bypassed.clone());
+
+        // A 0..1 saw on the left, something very loud on the right.
+        let mut data: Vec<f32> = (0..48_000)
+            .flat_map(|i| [(i % 100) as f32 / 100.0, 40.0 * (i as f32 * 0.05).sin()])
+            .collect();
+        data[10] = f32::NAN;
+        guard.process(&mut data, 2, 48_000.0);
+        assert!(data.iter().all(|s| s.is_finite() && s.abs() <= CEILING));
+        let tail = &data[data.len() - 9_600..];
+        let mean = tail.iter().step_by(2).sum::<f32>() / (tail.len() / 2) as f32;
+        assert!(mean.abs() < 0.01);
+
+        bypassed.store(true, Ordering::Relaxed);
+        let mut loud = [2.0; 4];
+        guard.process(&mut loud, 2, 48_000.0);
+        assert_eq!(loud, [2.0; 4]);
+    }
+}
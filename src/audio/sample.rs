@@ -0,0 +1,291 @@
+use crate::dsp::resample::{self, Interpolation};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Which part of a sample plays, in frames of the file.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SampleRegion {
+    /// First frame played.
+    #[serde(default)]
+    pub start: usize,
+    /// Frame playback stops at; `None` for the end of the file.
+    #[serde(default)]
+    pub end: Option<usize>,
+    /// Section repeated in [`SampleMode::Loop`]; defaults to the whole region.
+    #[serde(default)]
+    pub loop_start: Option<usize>,
+    #[serde(default)]
+    pub loop_end: Option<usize>,
+    /// Frames before the loop end that blend into the audio before the loop
+    /// start, hiding the seam.
+    #[serde(default)]
+    pub crossfade: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SampleMode {
+    /// Plays the region through once; note-offs are ignored.
+    #[default]
+    OneShot,
+    /// Plays into the loop and repeats it until the release has finished.
+    Loop,
+}
+
+/// A sample mapped onto a range of keys and velocities.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SampleZone {
+    pub sample_id: String,
+    /// Key the sample plays at its recorded pitch.
+    pub root_pitch: u8,
+    /// Lowest and highest key, inclusive.
+    #[serde(default = "full_range")]
+    pub keys: (u8, u8),
+    /// Lowest and highest velocity, inclusive.
+    #[serde(default = "full_range")]
+    pub velocities: (u8, u8),
+    #[serde(default = "unity_gain")]
+    pub gain: f32,
+    #[serde(default)]
+    pub mode: SampleMode,
+    /// Plays the region (and loop) backwards.
+    #[serde(default)]
+    pub reverse: bool,
+    /// Starting a note cuts off every note in the same group, on any track,
+    /// like an open hi-hat closed by the pedal.
+    #[serde(default)]
+    pub choke_group: Option<u8>,
+}
+
+fn full_range() -> (u8, u8) {
+    (0, 127)
+}
+
+fn unity_gain() -> f32 {
+    1.0
+}
+
+impl SampleZone {
+    pub fn new(sample_id: impl Into<String>, root_pitch: u8) -> Self {
+        Self {
+            sample_id: sample_id.into(),
+            root_pitch,
+            keys: full_range(),
+            velocities: full_range(),
+            gain: unity_gain(),
+            mode: SampleMode::default(),
+            reverse: false,
+            choke_group: None,
+        }
+    }
+
+    pub fn contains(&self, pitch: u8, velocity: u8) -> bool {
+        (self.keys.0..=self.keys.1).contains(&pitch)
+            && (self.velocities.0..=self.velocities.1).contains(&velocity)
+    }
+}
+
+/// Decoded sample frames with the region to play.
+#[derive(Debug, Clone)]
+pub struct SampleBuffer {
+    frames: Vec<[f32; 2]>,
+    sample_rate: f32,
+    region: SampleRegion,
+}
+
+/// Loaded samples by [`SampleRef`](crate::SampleRef) id.
+pub type SampleBank = HashMap<String, SampleBuffer>;
+
+/// Region bounds resolved against the file length, in playback order: for a
+/// reversed sample, frame `start` is the last frame of the region.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SampleSpan {
+    pub start: f64,
+    pub end: f64,
+    pub loop_start: f64,
+    pub loop_end: f64,
+    pub crossfade: f64,
+}
+
+impl SampleSpan {
+    pub fn loop_len(&self) -> f64 {
+        self.loop_end - self.loop_start
+    }
+}
+
+impl SampleBuffer {
+    pub fn new(frames: Vec<[f32; 2]>, sample_rate: f32, region: SampleRegion) -> Self {
+        Self {
+            frames,
+            sample_rate,
+            region,
+        }
+    }
+
+    /// Decodes any format [`decode_file`](super::decode_file) reads.
+    pub fn load(path: &Path, region: SampleRegion) -> Result<Self, String> {
+        let audio = super::decode_file(path)?;
+        Ok(Self::new(audio.frames, audio.sample_rate as f32, region))
+    }
+
+    pub fn sample_rate(&self) -> f32 {
+        self.sample_rate
+    }
+
+    pub fn num_frames(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// The region clamped to the file, with the loop inside it and the
+    /// crossfade short enough to have audio before the loop start.
+    pub fn span(&self, reverse: bool) -> SampleSpan {
+        let len = self.frames.len();
+        let end = self.region.end.unwrap_or(len).min(len);
+        let start = self.region.start.min(end);
+        let loop_end = self.region.loop_end.unwrap_or(end).clamp(start, end);
+        let loop_start = self
+            .region
+            .loop_start
+            .unwrap_or(start)
+            .clamp(start, loop_end);
+
+        // Mirror the region so playback always runs forwards.
+        let (start, end, loop_start, loop_end) = if reverse {
+            let flip = |frame: usize| len - frame;
+            (flip(end), flip(start), flip(loop_end), flip(loop_start))
+        } else {
+            (start, end, loop_start, loop_end)
+        };
+        let crossfade = self
+            .region
+            .crossfade
+            .min(loop_end - loop_start)
+            .min(loop_start);
+
+        SampleSpan {
+            start: start as f64,
+            end: end as f64,
+            loop_start: loop_start as f64,
+            loop_end: loop_end as f64,
+            crossfade: crossfade as f64,
+        }
+    }
+
+    /// The frame at `position`, counted backwards from the end of the file
+    /// when `reverse` is set, read between frames with `interpolation` for
+    /// reads moving `step` frames at a time. Silent before the file starts.
+    /// With a `looped` span, frames past its loop end are read from the
+    /// loop start, as playback goes on to them.
+    pub fn read(
+        &self,
+        position: f64,
+        step: f64,
+        reverse: bool,
+        interpolation: Interpolation,
+        looped: Option<&SampleSpan>,
+    ) -> [f32; 2] {
+        if position < 0.0 {
+            return [0.0; 2];
+        }
+        let wrap = looped
+            .map(|span| (span.loop_start as i64, span.loop_end as i64))
+            .filter(|(start, end)| end > start);
+        let frame = |i: i64| {
+            let i = match wrap {
+                Some((start, end)) if i >= end => start + (i - end) % (end - start),
+                _ => i,
+            };
+            let i = usize::try_from(i).ok().and_then(|i| {
+                if reverse {
+                    self.frames.len().checked_sub(i + 1)
+                } else {
+                    Some(i)
+                }
+            });
+            i.and_then(|i| self.frames.get(i))
+                .copied()
+                .unwrap_or([0.0; 2])
+        };
+        resample::interpolate(frame, position, step, interpolation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ramp(region: SampleRegion) -> SampleBuffer {
+        let frames = (0..10).map(|i| [i as f32, -(i as f32)]).collect();
+        SampleBuffer::new(frames, 48_000.0, region)
+    }
+
+    #[test]
+    fn span_clamps_region_to_file() {
+        let sample = ramp(SampleRegion {
+            start: 2,
+            end: Some(20),
+            loop_start: Some(4),
+            loop_end: Some(8),
+            crossfade: 6,
+        });
+        assert_eq!(
+            sample.span(false),
+            SampleSpan {
+                start: 2.0,
+                end: 10.0,
+                loop_start: 4.0,
+                loop_end: 8.0,
+                crossfade: 4.0,
+            }
+        );
+        assert_eq!(
+            sample.span(true),
+            SampleSpan {
+                start: 0.0,
+                end: 8.0,
+                loop_start: 2.0,
+                loop_end: 6.0,
+                crossfade: 2.0,
+            }
+        );
+    }
+
+    #[test]
+    fn reads_interpolate_in_either_direction() {
+        let sample = ramp(SampleRegion::default());
+        let close =
+            |a: [f32; 2], b: [f32; 2]| (a[0] - b[0]).abs() < 1e-5 && (a[1] - b[1]).abs() < 1e-5;
+        let read =
+            |position, reverse| sample.read(position, 1.0, reverse, Interpolation::Sinc, None);
+        assert!(close(read(2.0, false), [2.0, -2.0]));
+        assert!(close(read(0.0, true), [9.0, -9.0]));
+        assert!(close(read(12.0, true), [0.0, 0.0]));
+        assert_eq!(read(-1.0, false), [0.0, 0.0]);
+        assert!(close(
+            sample.read(2.5, 1.0, false, Interpolation::Linear, None),
+            [2.5, -2.5]
+        ));
+
+        // Halfway between frames of a slow wave lands on the wave.
+        let frames = (0..64).map(|i| [(i as f32 * 0.1).sin(); 2]).collect();
+        let wave = SampleBuffer::new(frames, 48_000.0, SampleRegion::default());
+        let read = wave.read(30.5, 1.0, false, Interpolation::Sinc, None);
+        assert!((read[0] - 3.05f32.sin()).abs() < 1e-3);
+    }
+
+    #[test]
+    fn looped_reads_carry_on_into_the_loop_start() {
+        // Four cycles of a wave that loops seamlessly at the end of the file.
+        let frames = (0..64)
+            .map(|i| [(i as f32 * std::f32::consts::TAU / 16.0).sin(); 2])
+            .collect();
+        let wave = SampleBuffer::new(frames, 48_000.0, SampleRegion::default());
+        let span = wave.span(false);
+        let expected = (63.5f32 * std::f32::consts::TAU / 16.0).sin();
+        let looped = wave.read(63.5, 1.0, false, Interpolation::Sinc, Some(&span));
+        assert!((looped[0] - expected).abs() < 1e-4);
+        // Without the loop, the silence after the file pulls it off the wave.
+        let unlooped = wave.read(63.5, 1.0, false, Interpolation::Sinc, None);
+        assert!((unlooped[0] - expected).abs() > 0.01);
+    }
+}
@@ -1,30 +1,103 @@
 use super::voice::{ADSRConfig, EnvelopeState};
-use super::{Instrument, Wave, midi_to_freq};
+use super::{
+    ClipVoice, FADE_OUT_SECONDS, Instrument, InstrumentSnapshot, KeyTracking, SampleBank,
+    SampleMode, SampleZone, VelocityResponse, Wave, midi_to_freq,
+};
+use crate::events::{NoteExpression, NoteModulation};
+use serde::{Deserialize, Serialize};
+
+/// What a track does with the notes still sounding when its node ends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ChokeMode {
+    /// Notes go into their release, so their tails ring into the next node.
+    #[default]
+    Release,
+    /// Notes stop dead, for tracks that must not overlap themselves.
+    Kill,
+}
+
+/// Tells a track apart from every other for as long as the program runs,
+/// unlike its index, which changes as tracks around it come and go. Each new
+/// or loaded track gets a new one; copies of a project keep them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TrackUid(u64);
+
+impl TrackUid {
+    pub fn new() -> Self {
+        static NEXT: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+        Self(NEXT.fetch_add(1, std::sync::atomic::Ordering::Relaxed))
+    }
+}
+
+impl Default for TrackUid {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct TrackConfig {
     pub id: usize,
+    pub uid: TrackUid,
     pub instrument: Instrument,
     pub adsr: ADSRConfig,
     pub volume: f32,
     pub pan: f32,
+    pub morph_target: Option<InstrumentSnapshot>,
+    /// In a config built by [`morph_scratch`](super::morph_scratch) for
+    /// instruments that can't be blended, whether `instrument` holds the
+    /// morph target's and `morph_target` the source's.
+    pub morph_swapped: bool,
+    pub mute: bool,
+    pub solo: bool,
+    /// Post-fader level sent to each bus.
+    pub sends: Vec<f32>,
+    pub choke: ChokeMode,
+    pub velocity: VelocityResponse,
+    pub key_tracking: KeyTracking,
 }
 
 impl TrackConfig {
     pub fn new(id: usize, instrument: Instrument, adsr: ADSRConfig) -> Self {
         Self {
             id,
+            uid: TrackUid::new(),
             instrument,
             adsr,
             volume: 1.0,
             pan: 0.0,
+            morph_target: None,
+            morph_swapped: false,
+            mute: false,
+            solo: false,
+            sends: Vec::new(),
+            choke: ChokeMode::default(),
+            velocity: VelocityResponse::default(),
+            key_tracking: KeyTracking::default(),
         }
     }
 
     pub fn num_oscillators(&self) -> usize {
         match &self.instrument {
-            Instrument::MultiOsc { oscillators } => oscillators.len(),
-            Instrument::Sampler { .. } => 0,
+            Instrument::MultiOsc { oscillators, .. } => oscillators.len(),
+            Instrument::Sampler { .. } | Instrument::Audio | Instrument::Plugin(_) => 0,
+        }
+    }
+
+    /// Index of the sampler zone a note plays.
+    pub fn sample_zone(&self, pitch: u8, velocity: u8) -> Option<usize> {
+        match &self.instrument {
+            Instrument::Sampler { zones, .. } => {
+                zones.iter().position(|z| z.contains(pitch, velocity))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn zone(&self, index: usize) -> Option<&SampleZone> {
+        match &self.instrument {
+            Instrument::Sampler { zones, .. } => zones.get(index),
+            _ => None,
         }
     }
 }
@@ -35,100 +108,406 @@ pub struct NotePlaybackState {
     pub envelope_state: EnvelopeState,
     pub envelope_level: f32,
     pub oscillator_phases: Vec<f32>,
-    pub sample_position: f32,
+    /// Frames played since the start of the sample region, before looping.
+    pub sample_position: f64,
+    /// Sampler zone chosen at note-on.
+    pub sample_zone: Option<usize>,
+    /// Remaining gain of a note fading out after being choked.
+    pub choke: Option<f32>,
+    /// Added to the pan of everything the note plays.
+    pub pan: f32,
+    /// Frequency ratio the note is detuned by.
+    pub detune: f32,
+    /// Key pressure from an expressive controller, which swells the note by
+    /// up to double.
+    pub pressure: f32,
 }
 
 impl NotePlaybackState {
-    pub fn new(velocity: u8, num_oscillators: usize) -> Self {
+    /// A note starting with `oscillator_phases`, which should all be zero.
+    pub fn new(velocity: u8, oscillator_phases: Vec<f32>) -> Self {
         Self {
             velocity,
             envelope_state: EnvelopeState::Attack { time: 0.0 },
             envelope_level: 0.0,
-            oscillator_phases: vec![0.0; num_oscillators],
+            oscillator_phases,
             sample_position: 0.0,
+            sample_zone: None,
+            choke: None,
+            pan: 0.0,
+            detune: 1.0,
+            pressure: 0.0,
         }
     }
 }
 
+/// How long a choked note takes to fade out.
+const CHOKE_SECONDS: f32 = 0.005;
+
+/// Clips that can play at once on a track; more are dropped.
+const MAX_CLIPS: usize = 16;
+
+/// Oscillators a note has room for before starting it allocates.
+const SPARE_OSCILLATORS: usize = 8;
+
 pub struct PlaybackState {
     pub notes: [Option<NotePlaybackState>; 128],
+    pub clips: Vec<ClipVoice>,
+    /// Phase buffers for notes to start with, handed back when they end, so
+    /// notes come and go without allocating.
+    spare_phases: Vec<Vec<f32>>,
 }
 
 impl PlaybackState {
     pub fn new() -> Self {
         Self {
             notes: std::array::from_fn(|_| None),
+            clips: Vec::with_capacity(MAX_CLIPS),
+            spare_phases: (0..128)
+                .map(|_| Vec::with_capacity(SPARE_OSCILLATORS))
+                .collect(),
+        }
+    }
+
+    /// Stops the note on `pitch` dead, keeping its phase buffer.
+    fn end_note(&mut self, pitch: u8) {
+        if let Some(note) = self.notes[pitch as usize].take() {
+            self.spare_phases.push(note.oscillator_phases);
+        }
+    }
+
+    pub fn start_clip(&mut self, clip: ClipVoice) {
+        if self.clips.len() < MAX_CLIPS {
+            self.clips.push(clip);
         }
     }
 
-    pub fn note_on(&mut self, pitch: u8, velocity: u8, num_oscillators: usize) {
-        self.notes[pitch as usize] = Some(NotePlaybackState::new(velocity, num_oscillators));
+    pub fn note_on(
+        &mut self,
+        pitch: u8,
+        velocity: u8,
+        expression: NoteExpression,
+        config: &TrackConfig,
+    ) {
+        self.end_note(pitch);
+        let mut phases = self.spare_phases.pop().unwrap_or_default();
+        phases.clear();
+        phases.resize(config.num_oscillators(), 0.0);
+
+        let mut state = NotePlaybackState::new(velocity, phases);
+        state.sample_zone = config.sample_zone(pitch, velocity);
+        state.pan = expression.pan;
+        state.detune = 2.0_f32.powf(expression.detune_cents / 1200.0);
+        self.notes[pitch as usize] = Some(state);
+    }
+
+    /// Applies a bend, pressure or slide to a sounding note. Slide pans it,
+    /// as built-in instruments have no timbre control of their own.
+    pub fn modulate(&mut self, pitch: u8, modulation: NoteModulation) {
+        let Some(state) = &mut self.notes[pitch.min(127) as usize] else {
+            return;
+        };
+        match modulation {
+            NoteModulation::Bend { semitones } => state.detune = 2.0_f32.powf(semitones / 12.0),
+            NoteModulation::Pressure(pressure) => state.pressure = pressure,
+            NoteModulation::Slide(slide) => state.pan = slide * 2.0 - 1.0,
+        }
     }
 
-    pub fn note_off(&mut self, pitch: u8) {
+    /// Releases the note, unless it plays a one-shot sample through to its end.
+    pub fn note_off(&mut self, pitch: u8, config: &TrackConfig) {
         if let Some(state) = &mut self.notes[pitch as usize] {
-            state.envelope_state = EnvelopeState::Release { time: 0.0 };
+            let one_shot = state
+                .sample_zone
+                .and_then(|i| config.zone(i))
+                .is_some_and(|zone| zone.mode == SampleMode::OneShot);
+            if !one_shot {
+                state.envelope_state = EnvelopeState::Release { time: 0.0 };
+            }
+        }
+    }
+
+    /// Quickly fades out every note playing a zone in choke `group`.
+    pub fn choke(&mut self, group: u8, config: &TrackConfig) {
+        for state in self.notes.iter_mut().flatten() {
+            let zone = state.sample_zone.and_then(|i| config.zone(i));
+            if zone.is_some_and(|zone| zone.choke_group == Some(group)) {
+                state.choke.get_or_insert(1.0);
+            }
         }
     }
 
-    pub fn stop_all(&mut self) {
-        for note in &mut self.notes {
-            *note = None;
+    /// Releases every note, as if each got its note-off, and fades clips out
+    /// over their own fade-out.
+    pub fn release_all(&mut self, config: &TrackConfig) {
+        for pitch in 0..128 {
+            self.note_off(pitch, config);
         }
+        for clip in &mut self.clips {
+            clip.release();
+        }
+    }
+
+    /// Silences every note and clip at once.
+    pub fn kill_all(&mut self) {
+        for pitch in 0..128 {
+            self.end_note(pitch);
+        }
+        crate::audit::permit_alloc(|| self.clips.clear());
+    }
+
+    /// Renders one stereo frame of every sounding note and clip, before the
+    /// track's volume and pan.
+    pub fn render_frame(
+        &mut self,
+        config: &TrackConfig,
+        samples: &SampleBank,
+        sample_rate: f32,
+    ) -> [f32; 2] {
+        let (mut left, mut right) = ([0.0], [0.0]);
+        self.render_block(config, samples, sample_rate, &mut left, &mut right);
+        [left[0], right[0]]
     }
 
-    pub fn render_sample(&mut self, config: &TrackConfig, sample_rate: f32) -> f32 {
-        let mut output = 0.0;
+    /// Renders every sounding note and clip into `left` and `right`, before
+    /// the track's volume and pan. Each note is rendered a chunk at a time,
+    /// with its pitch, pan and waveform worked out once per chunk rather than
+    /// once per frame.
+    pub fn render_block(
+        &mut self,
+        config: &TrackConfig,
+        samples: &SampleBank,
+        sample_rate: f32,
+        left: &mut [f32],
+        right: &mut [f32],
+    ) {
+        left.fill(0.0);
+        right.fill(0.0);
 
         for pitch in 0..128u8 {
-            let should_remove = if let Some(state) = &mut self.notes[pitch as usize] {
-                let envelope = calculate_envelope_from_playback(state, &config.adsr);
-                let velocity_scale = state.velocity as f32 / 127.0;
-
-                match &config.instrument {
-                    Instrument::MultiOsc { oscillators } => {
-                        for (i, osc) in oscillators.iter().enumerate() {
-                            let note = (pitch as i8 + osc.semitone) as u8;
-                            let freq = midi_to_freq(note);
-
-                            let phase = state.oscillator_phases[i];
-                            let sample = match osc.wave {
-                                Wave::Sine => (phase * 2.0 * std::f32::consts::PI).sin(),
-                                Wave::Square => {
-                                    if phase < 0.5 {
-                                        -1.0
-                                    } else {
-                                        1.0
-                                    }
-                                }
-                                Wave::Saw => phase * 2.0 - 1.0,
-                            };
-
-                            output += sample * envelope * velocity_scale * osc.gain;
-
-                            state.oscillator_phases[i] += freq / sample_rate;
-                            if state.oscillator_phases[i] >= 1.0 {
-                                state.oscillator_phases[i] -= 1.0;
-                            }
+            let Some(state) = &mut self.notes[pitch as usize] else {
+                continue;
+            };
+            if render_note(pitch, state, config, samples, sample_rate, left, right) {
+                self.end_note(pitch);
+            }
+        }
+
+        let mut i = 0;
+        while i < self.clips.len() {
+            let clip = &mut self.clips[i];
+            let mut ended = false;
+            for (left, right) in left.iter_mut().zip(right.iter_mut()) {
+                let Some([l, r]) = clip.next_frame(samples, sample_rate) else {
+                    ended = true;
+                    break;
+                };
+                *left += l;
+                *right += r;
+            }
+            if ended {
+                let clip = self.clips.swap_remove(i);
+                // Its sample id was allocated by the timing thread.
+                crate::audit::permit_alloc(|| drop(clip));
+            } else {
+                i += 1;
+            }
+        }
+    }
+}
+
+/// Frames of a note rendered together, sized so their levels fit on the stack.
+const CHUNK: usize = 64;
+
+/// Adds a note to `left` and `right`. Returns whether it has finished, in
+/// which case it stops partway through.
+fn render_note(
+    pitch: u8,
+    state: &mut NotePlaybackState,
+    config: &TrackConfig,
+    samples: &SampleBank,
+    sample_rate: f32,
+    left: &mut [f32],
+    right: &mut [f32],
+) -> bool {
+    let velocity_scale = config.velocity.gain(state.velocity)
+        * config.key_tracking.gain(pitch)
+        * (1.0 + state.pressure);
+    let envelope_rate = sample_rate / config.key_tracking.envelope_speed(pitch);
+
+    for (left, right) in left.chunks_mut(CHUNK).zip(right.chunks_mut(CHUNK)) {
+        let mut levels = [0.0; CHUNK];
+        let (live, mut finished) = note_levels(
+            state,
+            config,
+            velocity_scale,
+            envelope_rate,
+            sample_rate,
+            &mut levels[..left.len()],
+        );
+        let (levels, left, right) = (&levels[..live], &mut left[..live], &mut right[..live]);
+
+        match &config.instrument {
+            Instrument::MultiOsc {
+                oscillators,
+                spread,
+            } => {
+                // Oscillators added while the note sounds join the next one.
+                let playing = state.oscillator_phases.len();
+                for (i, osc) in oscillators.iter().enumerate().take(playing) {
+                    let note = (pitch as i8 + osc.semitone) as u8;
+                    let step = midi_to_freq(note) * state.detune / sample_rate;
+                    let pan = oscillator_pan(i, oscillators.len(), *spread, osc.pan + state.pan);
+                    let oscillator = Oscillator {
+                        phase: &mut state.oscillator_phases[i],
+                        step,
+                        gain: osc.gain,
+                        balance: balance(pan),
+                    };
+                    match osc.wave {
+                        Wave::Sine => oscillator.render(
+                            |phase| (phase * 2.0 * std::f32::consts::PI).sin(),
+                            levels,
+                            left,
+                            right,
+                        ),
+                        Wave::Square => oscillator.render(
+                            |phase| if phase < 0.5 { -1.0 } else { 1.0 },
+                            levels,
+                            left,
+                            right,
+                        ),
+                        Wave::Saw => {
+                            oscillator.render(|phase| phase * 2.0 - 1.0, levels, left, right)
                         }
                     }
-                    Instrument::Sampler { .. } => {
-                        // TODO: Implement sampler rendering
-                    }
                 }
+            }
+            Instrument::Sampler {
+                zones,
+                interpolation,
+            } => {
+                if let Some(zone) = state.sample_zone.and_then(|i| zones.get(i))
+                    && let Some(sample) = samples.get(&zone.sample_id)
+                {
+                    let reverse = zone.reverse;
+                    let span = sample.span(reverse);
+                    let looping = zone.mode == SampleMode::Loop && span.loop_len() > 0.0;
+                    let fade_start = span.loop_end - span.crossfade;
+                    let (l_gain, r_gain) = balance(state.pan);
+                    let semitones = pitch as f64 - zone.root_pitch as f64;
+                    let step = 2.0_f64.powf(semitones / 12.0)
+                        * state.detune as f64
+                        * sample.sample_rate() as f64
+                        / sample_rate as f64;
 
-                advance_envelope_one_sample_playback(state, &config.adsr, sample_rate);
-                matches!(state.envelope_state, EnvelopeState::Release { time } if time > config.adsr.release)
-            } else {
-                false
-            };
+                    for ((level, left), right) in levels.iter().zip(left).zip(right) {
+                        let mut position = span.start + state.sample_position;
+                        if looping && position >= span.loop_end {
+                            position = span.loop_start
+                                + (position - span.loop_start).rem_euclid(span.loop_len());
+                            state.sample_position = position - span.start;
+                        }
+                        if !looping && position >= span.end {
+                            finished = true;
+                            break;
+                        }
 
-            if should_remove {
-                self.notes[pitch as usize] = None;
+                        let looped = looping.then_some(&span);
+                        let mut frame =
+                            sample.read(position, step, reverse, *interpolation, looped);
+                        if looping && span.crossfade > 0.0 && position > fade_start {
+                            let fade = ((position - fade_start) / span.crossfade) as f32;
+                            let before = sample.read(
+                                position - span.loop_len(),
+                                step,
+                                reverse,
+                                *interpolation,
+                                looped,
+                            );
+                            for (out, before) in frame.iter_mut().zip(before) {
+                                *out += (before - *out) * fade;
+                            }
+                        }
+                        let gain = level * zone.gain;
+                        *left += frame[0] * gain * l_gain;
+                        *right += frame[1] * gain * r_gain;
+                        state.sample_position += step;
+                    }
+                }
             }
+            Instrument::Audio => finished = true,
+            // Rendered by the plugin host; notes never reach this state.
+            Instrument::Plugin(_) => {}
+        }
+
+        if finished {
+            return true;
+        }
+    }
+    false
+}
+
+/// Fills `levels` with a note's level for each frame: its envelope scaled by
+/// velocity, and by the fade of a choked note. Returns how many frames the
+/// note lasts, and whether it ends after them.
+fn note_levels(
+    state: &mut NotePlaybackState,
+    config: &TrackConfig,
+    velocity_scale: f32,
+    envelope_rate: f32,
+    sample_rate: f32,
+    levels: &mut [f32],
+) -> (usize, bool) {
+    for (i, level) in levels.iter_mut().enumerate() {
+        let envelope = calculate_envelope_from_playback(state, &config.adsr);
+        *level = envelope * velocity_scale * state.choke.unwrap_or(1.0);
+
+        let mut finished = false;
+        if let Some(gain) = &mut state.choke {
+            *gain -= 1.0 / (CHOKE_SECONDS * sample_rate);
+            finished = *gain <= 0.0;
+        }
+        advance_envelope_one_sample_playback(state, &config.adsr, envelope_rate);
+        if finished
+            || matches!(state.envelope_state, EnvelopeState::Release { time } if time > config.adsr.release)
+        {
+            return (i + 1, true);
         }
+    }
+    (levels.len(), false)
+}
 
-        output
+/// One of a note's oscillators, ready to run for a chunk.
+struct Oscillator<'a> {
+    phase: &'a mut f32,
+    /// Phase advanced per frame.
+    step: f32,
+    gain: f32,
+    balance: (f32, f32),
+}
+
+impl Oscillator<'_> {
+    /// Adds the oscillator to `left` and `right` at `levels`. The waveform is
+    /// passed in so each one gets a loop of its own.
+    fn render(
+        self,
+        wave: impl Fn(f32) -> f32,
+        levels: &[f32],
+        left: &mut [f32],
+        right: &mut [f32],
+    ) {
+        let (l_gain, r_gain) = self.balance;
+        for ((level, left), right) in levels.iter().zip(left).zip(right) {
+            let sample = wave(*self.phase) * level * self.gain;
+            *left += sample * l_gain;
+            *right += sample * r_gain;
+
+            *self.phase += self.step;
+            if *self.phase >= 1.0 {
+                *self.phase -= 1.0;
+            }
+        }
     }
 }
 
@@ -138,35 +517,34 @@ impl Default for PlaybackState {
     }
 }
 
+/// Where oscillator `index` of `count` sits: its own pan plus its share of the
+/// spread, which runs from hard left for the first to hard right for the last.
+fn oscillator_pan(index: usize, count: usize, spread: f32, pan: f32) -> f32 {
+    let position = if count > 1 {
+        index as f32 / (count - 1) as f32 * 2.0 - 1.0
+    } else {
+        0.0
+    };
+    (position * spread + pan).clamp(-1.0, 1.0)
+}
+
+/// Balance law that leaves a centred signal at unity gain, so oscillators
+/// without pan or spread sound exactly as they did when voices were mono.
+fn balance(pan: f32) -> (f32, f32) {
+    ((1.0 - pan).min(1.0), (1.0 + pan).min(1.0))
+}
+
 fn calculate_envelope_from_playback(state: &NotePlaybackState, adsr: &ADSRConfig) -> f32 {
     match &state.envelope_state {
-        EnvelopeState::Attack { time } => {
-            if adsr.attack == 0.0 {
-                1.0
-            } else {
-                (time / adsr.attack).min(1.0)
-            }
-        }
-        EnvelopeState::Decay { time } => {
-            let decay_progress = if adsr.decay == 0.0 {
-                1.0
-            } else {
-                (time / adsr.decay).min(1.0)
-            };
-            1.0 - (1.0 - adsr.sustain) * decay_progress
-        }
+        EnvelopeState::Attack { time } => adsr.attack_level(*time),
+        EnvelopeState::Decay { time } => adsr.decay_level(*time),
         EnvelopeState::Sustain => adsr.sustain,
-        EnvelopeState::Release { time } => {
-            let release_progress = if adsr.release == 0.0 {
-                1.0
-            } else {
-                (time / adsr.release).min(1.0)
-            };
-            state.envelope_level * (1.0 - release_progress)
-        }
+        EnvelopeState::Release { time } => adsr.release_level(state.envelope_level, *time),
     }
 }
 
+/// Moves the envelope on a sample. `envelope_level` follows the envelope up
+/// to the release and then holds the level the release started from.
 fn advance_envelope_one_sample_playback(
     state: &mut NotePlaybackState,
     adsr: &ADSRConfig,
@@ -181,11 +559,7 @@ fn advance_envelope_one_sample_playback(
                 state.envelope_state = EnvelopeState::Decay { time: 0.0 };
                 state.envelope_level = 1.0;
             } else {
-                state.envelope_level = if adsr.attack == 0.0 {
-                    1.0
-                } else {
-                    (*time / adsr.attack).min(1.0)
-                };
+                state.envelope_level = adsr.attack_level(*time);
             }
         }
         EnvelopeState::Decay { time } => {
@@ -194,12 +568,7 @@ fn advance_envelope_one_sample_playback(
                 state.envelope_state = EnvelopeState::Sustain;
                 state.envelope_level = adsr.sustain;
             } else {
-                let decay_progress = if adsr.decay == 0.0 {
-                    1.0
-                } else {
-                    (*time / adsr.decay).min(1.0)
-                };
-                state.envelope_level = 1.0 - (1.0 - adsr.sustain) * decay_progress;
+                state.envelope_level = adsr.decay_level(*time);
             }
         }
         EnvelopeState::Sustain => {
@@ -207,12 +576,298 @@ fn advance_envelope_one_sample_playback(
         }
         EnvelopeState::Release { time } => {
             *time += dt;
-            let release_progress = if adsr.release == 0.0 {
-                1.0
-            } else {
-                (*time / adsr.release).min(1.0)
-            };
-            state.envelope_level *= 1.0 - release_progress;
         }
     }
 }
+
+/// A track taken out of the project while playing, rendered with the config
+/// it last had as it fades out over [`FADE_OUT_SECONDS`].
+pub struct FadingTrack {
+    pub state: PlaybackState,
+    pub config: TrackConfig,
+    gain: f32,
+}
+
+impl FadingTrack {
+    pub fn new(state: PlaybackState, config: TrackConfig) -> Self {
+        Self {
+            state,
+            config,
+            gain: 1.0,
+        }
+    }
+
+    pub fn is_silent(&self) -> bool {
+        self.gain == 0.0
+    }
+
+    pub fn render_frame(&mut self, samples: &SampleBank, sample_rate: f32) -> [f32; 2] {
+        if self.is_silent() {
+            return [0.0; 2];
+        }
+        self.gain = (self.gain - 1.0 / (FADE_OUT_SECONDS * sample_rate)).max(0.0);
+        let [left, right] = self.state.render_frame(&self.config, samples, sample_rate);
+        [left * self.gain, right * self.gain]
+    }
+}
+
+/// Fading tracks, with fresh states, for the tracks in `old` that aren't in
+/// `new`, matched by [`TrackUid`]. A removed track's state is swapped for the
+/// fresh one, so its notes fade out with the config it last had.
+pub fn removed_tracks(old: &[TrackConfig], new: &[TrackConfig]) -> Vec<FadingTrack> {
+    old.iter()
+        .filter(|config| !new.iter().any(|c| c.uid == config.uid))
+        .map(|config| FadingTrack::new(PlaybackState::new(), config.clone()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::{OscConfig, SampleBuffer, SampleRegion};
+    use crate::dsp::resample::Interpolation;
+
+    fn adsr() -> ADSRConfig {
+        ADSRConfig::new(0.0, 0.0, 1.0, 0.1)
+    }
+
+    fn square(pan: f32) -> OscConfig {
+        OscConfig {
+            wave: Wave::Square,
+            gain: 1.0,
+            semitone: 0,
+            pan,
+        }
+    }
+
+    fn render(oscillators: Vec<OscConfig>, spread: f32) -> [f32; 2] {
+        let config = TrackConfig::new(
+            0,
+            Instrument::MultiOsc {
+                oscillators,
+                spread,
+            },
+            adsr(),
+        );
+        let mut state = PlaybackState::new();
+        state.note_on(69, 127, NoteExpression::default(), &config);
+        state.render_frame(&config, &SampleBank::new(), 48_000.0)
+    }
+
+    #[test]
+    fn centred_oscillator_renders_the_same_on_both_sides() {
+        let [left, right] = render(vec![square(0.0)], 0.0);
+        assert_eq!(left, -1.0);
+        assert_eq!(right, -1.0);
+    }
+
+    #[test]
+    fn pan_and_spread_place_oscillators() {
+        let [left, right] = render(vec![square(1.0)], 0.0);
+        assert_eq!((left, right), (0.0, -1.0));
+
+        let [left, right] = render(vec![square(0.0), square(0.0)], 1.0);
+        assert_eq!((left, right), (-1.0, -1.0));
+
+        let [left, right] = render(vec![square(0.0), square(0.0)], 0.5);
+        assert_eq!((left, right), (-1.5, -1.5));
+    }
+
+    #[test]
+    fn released_notes_ring_out_and_killed_ones_stop() {
+        let config = TrackConfig::new(
+            0,
+            Instrument::MultiOsc {
+                oscillators: vec![square(0.0)],
+                spread: 0.0,
+            },
+            adsr(),
+        );
+        let mut state = PlaybackState::new();
+        state.note_on(69, 127, NoteExpression::default(), &config);
+        state.note_on(72, 127, NoteExpression::default(), &config);
+        let samples = SampleBank::new();
+        state.render_frame(&config, &samples, 48_000.0);
+        state.release_all(&config);
+        assert_ne!(state.render_frame(&config, &samples, 48_000.0), [0.0; 2]);
+        assert!(
+            state
+                .notes
+                .iter()
+                .flatten()
+                .all(|note| matches!(note.envelope_state, EnvelopeState::Release { .. }))
+        );
+
+        // Gone once the 0.1 s release has passed.
+        for _ in 0..5_000 {
+            state.render_frame(&config, &samples, 48_000.0);
+        }
+        assert!(state.notes.iter().all(Option::is_none));
+
+        state.note_on(69, 127, NoteExpression::default(), &config);
+        state.kill_all();
+        assert_eq!(state.render_frame(&config, &samples, 48_000.0), [0.0; 2]);
+    }
+
+    #[test]
+    fn removed_tracks_are_told_apart_by_uid_and_fade_out() {
+        let config = |id| {
+            TrackConfig::new(
+                id,
+                Instrument::MultiOsc {
+                    oscillators: vec![square(0.0)],
+                    spread: 0.0,
+                },
+                adsr(),
+            )
+        };
+        let old = [config(0), config(1)];
+        // The first track is removed, so the second takes its index.
+        let mut moved = old[1].clone();
+        moved.id = 0;
+        let new = [moved, config(1)];
+        let mut removed = removed_tracks(&old, &new);
+        let [fading] = &mut removed[..] else {
+            panic!("one track was removed");
+        };
+        assert_eq!(fading.config.uid, old[0].uid);
+
+        let mut state = PlaybackState::new();
+        state.note_on(60, 127, NoteExpression::default(), &old[0]);
+        std::mem::swap(&mut state, &mut fading.state);
+        let samples = SampleBank::new();
+        assert_ne!(fading.render_frame(&samples, 48_000.0), [0.0; 2]);
+        for _ in 0..(FADE_OUT_SECONDS * 48_000.0) as usize {
+            fading.render_frame(&samples, 48_000.0);
+        }
+        assert!(fading.is_silent());
+    }
+
+    #[test]
+    fn blocks_render_like_frames_one_at_a_time() {
+        let wave = |wave, semitone| OscConfig {
+            wave,
+            gain: 0.5,
+            semitone,
+            pan: 0.0,
+        };
+        let config = TrackConfig::new(
+            0,
+            Instrument::MultiOsc {
+                oscillators: vec![wave(Wave::Sine, 0), wave(Wave::Saw, 7), square(0.0)],
+                spread: 0.6,
+            },
+            ADSRConfig::new(0.001, 0.002, 0.5, 0.003),
+        );
+        let samples = SampleBank::new();
+        let start = |state: &mut PlaybackState| {
+            state.note_on(60, 100, NoteExpression::default(), &config);
+            state.note_on(67, 80, NoteExpression::default(), &config);
+        };
+
+        let mut by_frame = PlaybackState::new();
+        start(&mut by_frame);
+        let mut expected: Vec<_> = (0..200)
+            .map(|_| by_frame.render_frame(&config, &samples, 48_000.0))
+            .collect();
+        by_frame.release_all(&config);
+        expected.extend((0..300).map(|_| by_frame.render_frame(&config, &samples, 48_000.0)));
+
+        let mut by_block = PlaybackState::new();
+        start(&mut by_block);
+        let (mut left, mut right) = (vec![0.0; 500], vec![0.0; 500]);
+        by_block.render_block(
+            &config,
+            &samples,
+            48_000.0,
+            &mut left[..200],
+            &mut right[..200],
+        );
+        by_block.release_all(&config);
+        by_block.render_block(
+            &config,
+            &samples,
+            48_000.0,
+            &mut left[200..],
+            &mut right[200..],
+        );
+
+        for ([l, r], (left, right)) in expected.iter().zip(left.iter().zip(&right)) {
+            assert!((l - left).abs() < 1e-5 && (r - right).abs() < 1e-5);
+        }
+        assert_ne!(expected[250], [0.0; 2]);
+        assert!(
+            by_frame
+                .notes
+                .iter()
+                .chain(&by_block.notes)
+                .all(Option::is_none)
+        );
+    }
+
+    fn render_sampler(mode: SampleMode, region: SampleRegion, frames: usize) -> Vec<f32> {
+        let ramp = (0..8).map(|i| [i as f32; 2]).collect();
+        let samples = SampleBank::from([(
+            "ramp".to_string(),
+            SampleBuffer::new(ramp, 48_000.0, region),
+        )]);
+        let sampler = Instrument::Sampler {
+            zones: vec![SampleZone {
+                mode,
+                ..SampleZone::new("ramp", 60)
+            }],
+            interpolation: Interpolation::Sinc,
+        };
+        let config = TrackConfig::new(0, sampler, adsr());
+        let mut state = PlaybackState::new();
+        state.note_on(60, 127, NoteExpression::default(), &config);
+        (0..frames)
+            .map(|_| state.render_frame(&config, &samples, 48_000.0)[0])
+            .collect()
+    }
+
+    #[test]
+    fn sampler_plays_region_once_or_loops_it() {
+        let region = SampleRegion {
+            start: 2,
+            end: Some(4),
+            ..Default::default()
+        };
+        let output = render_sampler(SampleMode::OneShot, region, 4);
+        assert_eq!(output, vec![2.0, 3.0, 0.0, 0.0]);
+
+        let region = SampleRegion {
+            start: 2,
+            loop_start: Some(4),
+            loop_end: Some(6),
+            ..Default::default()
+        };
+        let output = render_sampler(SampleMode::Loop, region, 8);
+        assert_eq!(output, vec![2.0, 3.0, 4.0, 5.0, 4.0, 5.0, 4.0, 5.0]);
+    }
+
+    #[test]
+    fn zones_are_picked_by_key_and_velocity() {
+        let zone = |id: &str, keys, velocities| SampleZone {
+            keys,
+            velocities,
+            ..SampleZone::new(id, 60)
+        };
+        let config = TrackConfig::new(
+            0,
+            Instrument::Sampler {
+                zones: vec![
+                    zone("kick", (36, 36), (0, 127)),
+                    zone("snare-soft", (38, 38), (0, 63)),
+                    zone("snare-hard", (38, 38), (64, 127)),
+                ],
+                interpolation: Interpolation::Sinc,
+            },
+            adsr(),
+        );
+        assert_eq!(config.sample_zone(36, 100), Some(0));
+        assert_eq!(config.sample_zone(38, 40), Some(1));
+        assert_eq!(config.sample_zone(38, 64), Some(2));
+        assert_eq!(config.sample_zone(40, 100), None);
+    }
+}
@@ -1,11 +1,51 @@
+use crate::core::envelope::{progress, shape};
 use serde::{Deserialize, Serialize};
 
+/// Envelope times are in seconds. Each segment's curve runs from -1 to 1:
+/// positive amounts move fast at first and settle slowly, like a natural
+/// decay, negative ones start slowly and speed up, and 0 is a straight line.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ADSRConfig {
     pub attack: f32,
     pub decay: f32,
     pub sustain: f32,
     pub release: f32,
+    #[serde(default)]
+    pub attack_curve: f32,
+    #[serde(default)]
+    pub decay_curve: f32,
+    #[serde(default)]
+    pub release_curve: f32,
+}
+
+impl ADSRConfig {
+    /// An envelope with straight segments.
+    pub fn new(attack: f32, decay: f32, sustain: f32, release: f32) -> Self {
+        Self {
+            attack,
+            decay,
+            sustain,
+            release,
+            attack_curve: 0.0,
+            decay_curve: 0.0,
+            release_curve: 0.0,
+        }
+    }
+
+    /// Level `time` seconds into the attack.
+    pub fn attack_level(&self, time: f32) -> f32 {
+        shape(progress(time, self.attack), self.attack_curve)
+    }
+
+    /// Level `time` seconds into the decay.
+    pub fn decay_level(&self, time: f32) -> f32 {
+        1.0 - (1.0 - self.sustain) * shape(progress(time, self.decay), self.decay_curve)
+    }
+
+    /// Level `time` seconds into a release that started at level `from`.
+    pub fn release_level(&self, from: f32, time: f32) -> f32 {
+        from * (1.0 - shape(progress(time, self.release), self.release_curve))
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -36,3 +76,37 @@ impl NoteState {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn curves_bend_segments_between_fixed_ends() {
+        for curve in [-1.0, -0.3, 0.0, 0.5, 1.0] {
+            assert_eq!(shape(0.0, curve), 0.0);
+            assert!((shape(1.0, curve) - 1.0).abs() < 1e-6);
+        }
+        assert_eq!(shape(0.25, 0.0), 0.25);
+        assert!(shape(0.25, 1.0) > 0.25);
+        assert!(shape(0.25, -1.0) < 0.25);
+
+        let mut adsr = ADSRConfig::new(0.5, 0.5, 0.5, 0.5);
+        assert_eq!(adsr.attack_level(0.25), 0.5);
+        assert_eq!(adsr.decay_level(0.25), 0.75);
+        assert_eq!(adsr.release_level(0.8, 0.25), 0.4);
+        adsr.release_curve = 1.0;
+        assert!(adsr.release_level(0.8, 0.25) < 0.4);
+        assert_eq!(adsr.release_level(0.8, 0.5), 0.0);
+    }
+
+    #[test]
+    fn envelopes_saved_without_curves_load_linear() {
+        let adsr: ADSRConfig =
+            ron::from_str("(attack: 0.01, decay: 0.1, sustain: 0.7, release: 0.2)").unwrap();
+        assert_eq!(
+            (adsr.attack_curve, adsr.decay_curve, adsr.release_curve),
+            (0.0, 0.0, 0.0)
+        );
+    }
+}
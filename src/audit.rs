@@ -0,0 +1,32 @@
+//! Real-time checks on the audio callback. Built with the `alloc-audit`
+//! feature, any allocation or free inside [`no_alloc`] aborts with a message,
+//! so a change that brings one into the callback shows up the first time it
+//! runs. Without the feature these just run what they're given.
+
+#[cfg(feature = "alloc-audit")]
+#[global_allocator]
+static ALLOCATOR: assert_no_alloc::AllocDisabler = assert_no_alloc::AllocDisabler;
+
+/// Runs `f`, which must not touch the allocator.
+#[cfg(feature = "alloc-audit")]
+pub fn no_alloc<T>(f: impl FnOnce() -> T) -> T {
+    assert_no_alloc::assert_no_alloc(f)
+}
+
+#[cfg(not(feature = "alloc-audit"))]
+pub fn no_alloc<T>(f: impl FnOnce() -> T) -> T {
+    f()
+}
+
+/// Runs `f` inside [`no_alloc`] with the allocator allowed again, for the
+/// few places that may use it: one-off setup on a new audio thread, and
+/// freeing what another thread allocated when it can't be sent back.
+#[cfg(feature = "alloc-audit")]
+pub fn permit_alloc<T>(f: impl FnOnce() -> T) -> T {
+    assert_no_alloc::permit_alloc(f)
+}
+
+#[cfg(not(feature = "alloc-audit"))]
+pub fn permit_alloc<T>(f: impl FnOnce() -> T) -> T {
+    f()
+}
@@ -0,0 +1,341 @@
+//! Static checks of projects and `.au` patches, run by `aurio check` before
+//! going on stage.
+
+use crate::{Project, audio, dsp, scripting, timing};
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// Plays, but probably not as intended.
+    Warning,
+    /// Fails to load, plays silent or stalls.
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// What the problem belongs to, e.g. `track 0 (Drums), node verse`; empty
+    /// for the file as a whole.
+    pub location: String,
+    pub message: String,
+}
+
+impl Diagnostic {
+    fn error(location: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Error,
+            location: location.into(),
+            message: message.into(),
+        }
+    }
+
+    fn warning(location: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Warning,
+            location: location.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        if self.location.is_empty() {
+            write!(f, "{}: {}", severity, self.message)
+        } else {
+            write!(f, "{}: {}: {}", severity, self.location, self.message)
+        }
+    }
+}
+
+/// Parses a `.au` patch and looks for nodes that can't reach the output.
+pub fn check_patch(path: &Path) -> Vec<Diagnostic> {
+    let graph = match dsp::load_file(path) {
+        Ok(graph) => graph,
+        Err(e) => return vec![Diagnostic::error("", e.to_string())],
+    };
+
+    let mut diagnostics = Vec::new();
+    let outputs: Vec<_> = graph
+        .nodes
+        .iter()
+        .filter(|node| node.inner.is_output())
+        .collect();
+    if outputs.is_empty() {
+        diagnostics.push(Diagnostic::warning("", "no Out node; the patch is silent"));
+    }
+    // Sorting put the nodes in processing order; report them as written.
+    let mut nodes: Vec<_> = graph.nodes.iter().collect();
+    nodes.sort_by_key(|node| node.id);
+    for node in nodes {
+        let location = format!("node [{}] {}", node.id, node.kind);
+        let wired_in = graph.wires.iter().any(|w| w.to_node_id == node.id);
+        let wired_out = graph.wires.iter().any(|w| w.from_node_id == node.id);
+        if node.inner.is_output() && !wired_in {
+            diagnostics.push(Diagnostic::warning(
+                location,
+                "nothing is wired in; the patch is silent",
+            ));
+        } else if !node.inner.is_output() && !wired_out {
+            diagnostics.push(Diagnostic::warning(location, "output isn't wired anywhere"));
+        }
+    }
+    diagnostics
+}
+
+/// Loads a project and checks what would only fail once it plays: state
+/// graphs, script syntax, every generated pattern run once, and the files
+/// samples and plugins point to.
+pub fn check_project(path: &Path) -> Vec<Diagnostic> {
+    let project = match Project::load(path) {
+        Ok(project) => project,
+        Err(e) => return vec![Diagnostic::error("", e.to_string())],
+    };
+
+    let mut diagnostics = Vec::new();
+    let mut sample_ids = HashSet::new();
+    for sample in &project.sample_library {
+        let location = format!("sample {}", sample.id);
+        if !sample_ids.insert(sample.id.as_str()) {
+            diagnostics.push(Diagnostic::error(&location, "duplicate sample id"));
+        }
+        if !sample.resolve(path).is_file() {
+            diagnostics.push(Diagnostic::error(
+                location,
+                format!("{} not found", sample.path),
+            ));
+        }
+    }
+
+    let lua_runtime = match scripting::LuaRuntime::new() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            diagnostics.push(Diagnostic::error("", format!("Lua failed to start: {}", e)));
+            return diagnostics;
+        }
+    };
+    for (track_id, track) in project.tracks.iter().enumerate() {
+        let location = format!("track {} ({})", track_id, track.name);
+        let plugins = match &track.instrument {
+            audio::Instrument::Plugin(plugin) => Some(plugin),
+            _ => None,
+        };
+        for plugin in plugins.into_iter().chain(&track.effects) {
+            if !plugin.path.exists() {
+                diagnostics.push(Diagnostic::error(
+                    &location,
+                    format!("plugin {} not found", plugin.path.display()),
+                ));
+            }
+        }
+        if let audio::Instrument::Sampler { zones, .. } = &track.instrument {
+            for zone in zones {
+                if !sample_ids.contains(zone.sample_id.as_str()) {
+                    diagnostics.push(Diagnostic::error(
+                        &location,
+                        format!("zone plays unknown sample {}", zone.sample_id),
+                    ));
+                }
+            }
+        }
+        check_graph(
+            &project,
+            track_id,
+            (&track.initial_node, &track.graph),
+            &location,
+            &sample_ids,
+            &lua_runtime,
+            &mut diagnostics,
+        );
+        for layer in &track.layers {
+            check_graph(
+                &project,
+                track_id,
+                (&layer.initial_node, &layer.graph),
+                &format!("{}, layer {}", location, layer.name),
+                &sample_ids,
+                &lua_runtime,
+                &mut diagnostics,
+            );
+        }
+    }
+    diagnostics
+}
+
+/// Checks a track's own graph or one of its layers, given with its initial
+/// node.
+fn check_graph(
+    project: &Project,
+    track_id: usize,
+    (initial_node, graph): (&str, &timing::StateGraph),
+    location: &str,
+    sample_ids: &HashSet<&str>,
+    lua_runtime: &scripting::LuaRuntime,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let mut node_ids = HashSet::new();
+    for node in &graph.nodes {
+        if !node_ids.insert(node.id.as_str()) {
+            diagnostics.push(Diagnostic::error(
+                location,
+                format!("duplicate node {}", node.id),
+            ));
+        }
+    }
+    if graph.get_node(initial_node).is_none() {
+        diagnostics.push(Diagnostic::error(
+            location,
+            format!("initial node {} doesn't exist", initial_node),
+        ));
+    }
+
+    for edge in &graph.edges {
+        let edge_location = format!("{}, edge {} -> {}", location, edge.from, edge.to);
+        for end in [&edge.from, &edge.to] {
+            if !node_ids.contains(end.as_str()) {
+                diagnostics.push(Diagnostic::error(
+                    &edge_location,
+                    format!("unknown node {}", end),
+                ));
+            }
+        }
+        if !edge.condition.trim().is_empty() {
+            let code = format!("return ({})", edge.condition);
+            if let Err(error) = compile(lua_runtime, &code, "condition") {
+                diagnostics.push(Diagnostic::error(&edge_location, error.to_string()));
+            }
+        }
+        if let Some(code) = &edge.inlet_hook
+            && let Err(error) = compile(lua_runtime, code, "inlet hook")
+        {
+            diagnostics.push(Diagnostic::error(&edge_location, error.to_string()));
+        }
+    }
+
+    // Nodes no edge leads to from the initial node never play.
+    let mut reached = HashSet::from([initial_node]);
+    let mut queue = VecDeque::from([initial_node]);
+    while let Some(node_id) = queue.pop_front() {
+        for edge in graph.get_outgoing_edges(node_id) {
+            if reached.insert(edge.to.as_str()) {
+                queue.push_back(edge.to.as_str());
+            }
+        }
+    }
+
+    let variables = scripting::VariableStore::new();
+    for node in &graph.nodes {
+        let node_location = format!("{}, node {}", location, node.id);
+        if !reached.contains(node.id.as_str()) {
+            diagnostics.push(Diagnostic::warning(
+                &node_location,
+                "unreachable from the initial node",
+            ));
+        }
+        if node
+            .sequence
+            .duration_samples(project.bpm, project.sample_rate as f32)
+            == 0
+        {
+            diagnostics.push(Diagnostic::error(&node_location, "sequence has no length"));
+        }
+        for (hook, code) in &node.hooks {
+            if let Err(error) = compile(lua_runtime, code, &format!("{:?} hook", hook)) {
+                diagnostics.push(Diagnostic::error(&node_location, error.to_string()));
+            }
+        }
+
+        for (_, part) in node.sequence.parts() {
+            match part {
+                timing::Sequence::Generated(pattern) => {
+                    let context = scripting::PatternContext {
+                        track_id,
+                        node_id: &node.id,
+                        start_sample: 0,
+                        bpm: project.bpm,
+                        sample_rate: project.sample_rate as f32,
+                        time_signature: pattern.time_signature,
+                        loop_count: 0,
+                        repeat: node.repeat,
+                        seed: project.seed,
+                        pattern_seed: pattern.seed,
+                        variables: &variables,
+                    };
+                    if let Err(e) = part.get_events(Some(lua_runtime), &context) {
+                        diagnostics.push(Diagnostic::error(
+                            &node_location,
+                            scripting::ScriptError::from_lua(&e).to_string(),
+                        ));
+                    }
+                    // Whatever the pattern asked of the engine doesn't apply here.
+                    lua_runtime.drain_actions().for_each(drop);
+                }
+                timing::Sequence::Clips(pattern) => {
+                    for clip in &pattern.clips {
+                        if !sample_ids.contains(clip.sample_id.as_str()) {
+                            diagnostics.push(Diagnostic::error(
+                                &node_location,
+                                format!("clip plays unknown sample {}", clip.sample_id),
+                            ));
+                        }
+                    }
+                }
+                timing::Sequence::Static(_) | timing::Sequence::Chain(_) => {}
+            }
+        }
+    }
+}
+
+/// Compiles a hook or condition without running it, since it may depend on
+/// state only playback builds up.
+fn compile(
+    lua_runtime: &scripting::LuaRuntime,
+    code: &str,
+    chunk: &str,
+) -> Result<(), scripting::ScriptError> {
+    lua_runtime
+        .lua
+        .load(code)
+        .set_name(format!("={}", chunk))
+        .into_function()
+        .map(drop)
+        .map_err(|e| scripting::ScriptError::from_lua(&e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patch_warns_about_unwired_nodes() {
+        let path = std::env::temp_dir().join(format!("aurio-check-{}.au", std::process::id()));
+        std::fs::write(&path, "[0] Osc Sine 440.0\n[1] Gain 0.5\n[2] Out\n0->1\n").unwrap();
+        let diagnostics = check_patch(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            diagnostics,
+            vec![
+                Diagnostic::warning("node [1] Gain", "output isn't wired anywhere"),
+                Diagnostic::warning("node [2] Out", "nothing is wired in; the patch is silent"),
+            ]
+        );
+    }
+
+    #[test]
+    fn tutorial_project_is_clean() {
+        let path = std::env::temp_dir().join(format!("aurio-check-{}.aurio", std::process::id()));
+        crate::templates::tutorial("Check").save(&path).unwrap();
+        let diagnostics = check_project(&path);
+        std::fs::remove_dir_all(&path).unwrap();
+
+        assert_eq!(diagnostics, vec![]);
+    }
+}
@@ -0,0 +1,140 @@
+//! Timing inside a graph: a clock sending gate pulses and a step sequencer
+//! they move along, so a patch can play rhythms on its own.
+
+use super::graph::{AudioNode, ProcessContext};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Input level a gate has to rise past to count as a pulse.
+const GATE_THRESHOLD: f32 = 0.5;
+
+/// Sends `per_beat` gate pulses a beat at `bpm`: 1 for the first half of
+/// each pulse, 0 for the second.
+#[derive(Debug, Clone)]
+pub struct Clock {
+    pub bpm: f32,
+    pub per_beat: f32,
+    /// How far through the current pulse, from 0 to 1.
+    phase: f32,
+}
+
+impl Clock {
+    pub fn new(bpm: f32, per_beat: f32) -> Self {
+        Self {
+            bpm,
+            per_beat,
+            phase: 0.0,
+        }
+    }
+
+    /// The gate for the next sample.
+    pub fn next(&mut self, sample_rate: f32) -> f32 {
+        let gate = if self.phase < 0.5 { 1.0 } else { 0.0 };
+        self.phase += self.bpm / 60.0 * self.per_beat / sample_rate;
+        while self.phase >= 1.0 {
+            self.phase -= 1.0;
+        }
+        gate
+    }
+}
+
+impl AudioNode for Clock {
+    fn process(&mut self, _inputs: &[&[f32]], output: &mut [f32], ctx: &ProcessContext) {
+        for out in output.iter_mut() {
+            *out = self.next(ctx.sample_rate);
+        }
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> Result<(), String> {
+        match name {
+            "bpm" => self.bpm = value,
+            _ => return Err(format!("no parameter '{name}'")),
+        }
+        Ok(())
+    }
+
+    fn to_args(&self) -> Option<Vec<String>> {
+        Some(vec![format!("{}", self.bpm), format!("{}", self.per_beat)])
+    }
+}
+
+/// Steps through `steps` on every rising edge of the sum of its inputs,
+/// wrapping round at the end, and outputs the current step: a frequency to
+/// wire into an oscillator's `freq`, or a gate to multiply with the clock.
+/// Holds the first step until the first pulse, which plays it.
+#[derive(Debug, Clone)]
+pub struct Seq {
+    pub steps: Vec<f32>,
+    /// The step playing; `None` before the first pulse.
+    current: Option<usize>,
+    gate: bool,
+}
+
+impl Seq {
+    pub fn new(steps: Vec<f32>) -> Self {
+        Self {
+            steps,
+            current: None,
+            gate: false,
+        }
+    }
+
+    /// Moves to the next step.
+    pub fn advance(&mut self) {
+        let len = self.steps.len().max(1);
+        self.current = Some(self.current.map_or(0, |step| (step + 1) % len));
+    }
+
+    /// The value of the current step, 0 without any.
+    pub fn value(&self) -> f32 {
+        self.steps
+            .get(self.current.unwrap_or(0))
+            .copied()
+            .unwrap_or(0.0)
+    }
+}
+
+impl AudioNode for Seq {
+    fn process(&mut self, inputs: &[&[f32]], output: &mut [f32], _ctx: &ProcessContext) {
+        for (i, out) in output.iter_mut().enumerate() {
+            let gate: f32 = inputs.iter().filter_map(|input| input.get(i)).sum();
+            let open = gate >= GATE_THRESHOLD;
+            if open && !self.gate {
+                self.advance();
+            }
+            self.gate = open;
+            *out = self.value();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clocks_pulse_on_their_divisions() {
+        // 60 BPM in eighths at 8 Hz: one pulse every 4 samples.
+        let mut clock = Clock::new(60.0, 2.0);
+        let gates: Vec<f32> = (0..8).map(|_| clock.next(8.0)).collect();
+        assert_eq!(gates, [1.0, 1.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn sequences_step_on_rising_edges() {
+        let mut seq = Seq::new(alloc::vec![110.0, 220.0, 330.0]);
+        let ctx = ProcessContext { sample_rate: 8.0 };
+        let mut clock = Clock::new(60.0, 2.0);
+        let mut gates = [0.0; 16];
+        clock.process(&[], &mut gates, &ctx);
+
+        let mut output = [0.0; 16];
+        seq.process(&[&gates], &mut output, &ctx);
+        assert_eq!(output[..4], [110.0; 4]);
+        assert_eq!(output[4..8], [220.0; 4]);
+        assert_eq!(output[8..12], [330.0; 4]);
+        assert_eq!(output[12..], [110.0; 4]);
+    }
+}
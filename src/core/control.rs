@@ -0,0 +1,244 @@
+//! Nodes for control signals: a constant, the arithmetic to combine and map
+//! modulation before it's wired into a parameter, and a slew limiter to
+//! smooth it.
+
+use super::dynamics::coefficient;
+use super::graph::{AudioNode, ProcessContext};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Outputs `value` on every sample, e.g. a fixed offset or a level to turn
+/// live with `set`.
+pub struct Const {
+    pub value: f32,
+}
+
+impl AudioNode for Const {
+    fn process(&mut self, _inputs: &[&[f32]], output: &mut [f32], _ctx: &ProcessContext) {
+        output.fill(self.value);
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> Result<(), String> {
+        match name {
+            "value" => self.value = value,
+            _ => return Err(format!("no parameter '{name}'")),
+        }
+        Ok(())
+    }
+
+    fn to_args(&self) -> Option<Vec<String>> {
+        Some(vec![format!("{}", self.value)])
+    }
+}
+
+/// Sums its inputs and adds `offset`.
+pub struct Add {
+    pub offset: f32,
+}
+
+impl AudioNode for Add {
+    fn process(&mut self, inputs: &[&[f32]], output: &mut [f32], _ctx: &ProcessContext) {
+        output.fill(self.offset);
+        for input in inputs {
+            for (out, sample) in output.iter_mut().zip(input.iter()) {
+                *out += sample;
+            }
+        }
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> Result<(), String> {
+        match name {
+            "offset" => self.offset = value,
+            _ => return Err(format!("no parameter '{name}'")),
+        }
+        Ok(())
+    }
+
+    fn to_args(&self) -> Option<Vec<String>> {
+        Some(vec![format!("{}", self.offset)])
+    }
+}
+
+/// Multiplies its inputs together, for ring modulation or one signal
+/// shaping another's level. Silent without inputs.
+pub struct Mul;
+
+impl AudioNode for Mul {
+    fn process(&mut self, inputs: &[&[f32]], output: &mut [f32], _ctx: &ProcessContext) {
+        output.fill(0.0);
+        let Some((first, rest)) = inputs.split_first() else {
+            return;
+        };
+        for (out, sample) in output.iter_mut().zip(first.iter()) {
+            *out = *sample;
+        }
+        for input in rest {
+            for (i, out) in output.iter_mut().enumerate() {
+                *out *= input.get(i).copied().unwrap_or(0.0);
+            }
+        }
+    }
+}
+
+/// Maps the sum of its inputs to `input * factor + offset`, e.g. an LFO's
+/// -1 to 1 onto a range of frequencies.
+pub struct Scale {
+    pub factor: f32,
+    pub offset: f32,
+}
+
+impl AudioNode for Scale {
+    fn process(&mut self, inputs: &[&[f32]], output: &mut [f32], _ctx: &ProcessContext) {
+        for (i, out) in output.iter_mut().enumerate() {
+            let input: f32 = inputs.iter().filter_map(|input| input.get(i)).sum();
+            *out = input * self.factor + self.offset;
+        }
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> Result<(), String> {
+        match name {
+            "factor" => self.factor = value,
+            "offset" => self.offset = value,
+            _ => return Err(format!("no parameter '{name}'")),
+        }
+        Ok(())
+    }
+
+    fn to_args(&self) -> Option<Vec<String>> {
+        Some(vec![format!("{}", self.factor), format!("{}", self.offset)])
+    }
+}
+
+/// Maps the sum of its inputs from audio's -1 to 1 onto 0 to 1, the range
+/// of a control signal: an oscillator through it is a unipolar LFO.
+pub struct Unipolar;
+
+impl AudioNode for Unipolar {
+    fn process(&mut self, inputs: &[&[f32]], output: &mut [f32], _ctx: &ProcessContext) {
+        for (i, out) in output.iter_mut().enumerate() {
+            let input: f32 = inputs.iter().filter_map(|input| input.get(i)).sum();
+            *out = (input + 1.0) * 0.5;
+        }
+    }
+}
+
+/// Maps the sum of its inputs from a control signal's 0 to 1 onto -1 to 1,
+/// e.g. an envelope turned into a sweep either side of a frequency.
+pub struct Bipolar;
+
+impl AudioNode for Bipolar {
+    fn process(&mut self, inputs: &[&[f32]], output: &mut [f32], _ctx: &ProcessContext) {
+        for (i, out) in output.iter_mut().enumerate() {
+            let input: f32 = inputs.iter().filter_map(|input| input.get(i)).sum();
+            *out = input * 2.0 - 1.0;
+        }
+    }
+}
+
+/// Follows the sum of its inputs smoothly, settling over `rise` seconds
+/// going up and `fall` seconds going down: glides between sequenced pitches,
+/// or lag on a stepped control signal. Starts where its input does.
+#[derive(Debug, Clone)]
+pub struct Slew {
+    pub rise: f32,
+    pub fall: f32,
+    value: Option<f32>,
+}
+
+impl Slew {
+    pub fn new(rise: f32, fall: f32) -> Self {
+        Self {
+            rise,
+            fall,
+            value: None,
+        }
+    }
+
+    /// The value after one more sample heading for `target`.
+    pub fn next(&mut self, target: f32, sample_rate: f32) -> f32 {
+        let value = match self.value {
+            None => target,
+            Some(value) => {
+                let seconds = if target > value { self.rise } else { self.fall };
+                target + coefficient(seconds, sample_rate) * (value - target)
+            }
+        };
+        self.value = Some(value);
+        value
+    }
+}
+
+impl AudioNode for Slew {
+    fn process(&mut self, inputs: &[&[f32]], output: &mut [f32], ctx: &ProcessContext) {
+        for (i, out) in output.iter_mut().enumerate() {
+            let target = inputs.iter().filter_map(|input| input.get(i)).sum();
+            *out = self.next(target, ctx.sample_rate);
+        }
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> Result<(), String> {
+        match name {
+            "rise" => self.rise = value,
+            "fall" => self.fall = value,
+            _ => return Err(format!("no parameter '{name}'")),
+        }
+        Ok(())
+    }
+
+    fn to_args(&self) -> Option<Vec<String>> {
+        Some(vec![format!("{}", self.rise), format!("{}", self.fall)])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CTX: ProcessContext = ProcessContext {
+        sample_rate: 1_000.0,
+    };
+
+    #[test]
+    fn control_nodes_combine_their_inputs() {
+        let a = [1.0, 2.0, 3.0];
+        let b = [0.5, -1.0, 2.0];
+        let mut output = [0.0; 3];
+
+        Const { value: 4.0 }.process(&[], &mut output, &CTX);
+        assert_eq!(output, [4.0; 3]);
+        Add { offset: 1.0 }.process(&[&a, &b], &mut output, &CTX);
+        assert_eq!(output, [2.5, 2.0, 6.0]);
+        Mul.process(&[&a, &b], &mut output, &CTX);
+        assert_eq!(output, [0.5, -2.0, 6.0]);
+        Mul.process(&[], &mut output, &CTX);
+        assert_eq!(output, [0.0; 3]);
+        Scale {
+            factor: 100.0,
+            offset: 440.0,
+        }
+        .process(&[&b], &mut output, &CTX);
+        assert_eq!(output, [490.0, 340.0, 640.0]);
+        Unipolar.process(&[&[-1.0, 0.0, 0.5], &[0.0, 0.0, 0.5]], &mut output, &CTX);
+        assert_eq!(output, [0.0, 0.5, 1.0]);
+        Bipolar.process(&[&[0.0, 0.5, 1.0]], &mut output, &CTX);
+        assert_eq!(output, [-1.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn slews_glide_up_and_jump_down() {
+        let mut slew = Slew::new(0.01, 0.0);
+        assert_eq!(slew.next(220.0, 1_000.0), 220.0);
+
+        let mut value = 220.0;
+        for _ in 0..10 {
+            let next = slew.next(440.0, 1_000.0);
+            assert!(next > value && next < 440.0);
+            value = next;
+        }
+        // Ten samples is one rise time constant.
+        assert!((value - (440.0 - 220.0 * (-1.0f32).exp())).abs() < 0.01);
+        assert_eq!(slew.next(110.0, 1_000.0), 110.0);
+    }
+}
@@ -0,0 +1,176 @@
+//! Control signals derived from audio: an envelope follower tracking a
+//! signal's level and a gate detector opening while it's loud, for
+//! sidechain-style and audio-reactive patches.
+
+use super::graph::{AudioNode, ProcessContext};
+use super::math;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Level the gate closes at, as a fraction of the one it opens at, so a
+/// signal hovering around the threshold doesn't chatter.
+const GATE_HYSTERESIS: f32 = 0.5;
+
+/// Seconds the gate's own follower takes to rise, fast enough to catch
+/// transients.
+const GATE_ATTACK: f32 = 0.001;
+
+/// Smoothing coefficient for a one-pole filter settling in `seconds`.
+pub(super) fn coefficient(seconds: f32, sample_rate: f32) -> f32 {
+    if seconds <= 0.0 {
+        0.0
+    } else {
+        math::exp(-1.0 / (seconds * sample_rate))
+    }
+}
+
+/// Follows the level of the sum of its inputs, rising over `attack` and
+/// falling over `release` seconds. Outputs from 0 up.
+#[derive(Debug, Clone)]
+pub struct EnvelopeFollower {
+    pub attack: f32,
+    pub release: f32,
+    level: f32,
+}
+
+impl EnvelopeFollower {
+    pub fn new(attack: f32, release: f32) -> Self {
+        Self {
+            attack,
+            release,
+            level: 0.0,
+        }
+    }
+
+    /// The level after one more sample of `input`.
+    pub fn next(&mut self, input: f32, sample_rate: f32) -> f32 {
+        let input = math::abs(input);
+        let seconds = if input > self.level {
+            self.attack
+        } else {
+            self.release
+        };
+        let coef = coefficient(seconds, sample_rate);
+        self.level = coef * self.level + (1.0 - coef) * input;
+        self.level
+    }
+}
+
+impl AudioNode for EnvelopeFollower {
+    fn process(&mut self, inputs: &[&[f32]], output: &mut [f32], ctx: &ProcessContext) {
+        for (i, out) in output.iter_mut().enumerate() {
+            let input = inputs.iter().filter_map(|input| input.get(i)).sum();
+            *out = self.next(input, ctx.sample_rate);
+        }
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> Result<(), String> {
+        match name {
+            "attack" => self.attack = value,
+            "release" => self.release = value,
+            _ => return Err(format!("no parameter '{name}'")),
+        }
+        Ok(())
+    }
+
+    fn to_args(&self) -> Option<Vec<String>> {
+        Some(vec![
+            format!("{}", self.attack),
+            format!("{}", self.release),
+        ])
+    }
+}
+
+/// Outputs 1 while the level of the sum of its inputs is over `threshold`
+/// and 0 otherwise. Once open it stays open until the level, falling over
+/// `release` seconds, drops to half the threshold.
+#[derive(Debug, Clone)]
+pub struct Gate {
+    pub threshold: f32,
+    follower: EnvelopeFollower,
+    open: bool,
+}
+
+impl Gate {
+    pub fn new(threshold: f32, release: f32) -> Self {
+        Self {
+            threshold,
+            follower: EnvelopeFollower::new(GATE_ATTACK, release),
+            open: false,
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    /// The gate after one more sample of `input`: 1 when open, else 0.
+    pub fn next(&mut self, input: f32, sample_rate: f32) -> f32 {
+        let level = self.follower.next(input, sample_rate);
+        if self.open {
+            self.open = level >= self.threshold * GATE_HYSTERESIS;
+        } else {
+            self.open = level >= self.threshold;
+        }
+        if self.open { 1.0 } else { 0.0 }
+    }
+}
+
+impl AudioNode for Gate {
+    fn process(&mut self, inputs: &[&[f32]], output: &mut [f32], ctx: &ProcessContext) {
+        for (i, out) in output.iter_mut().enumerate() {
+            let input = inputs.iter().filter_map(|input| input.get(i)).sum();
+            *out = self.next(input, ctx.sample_rate);
+        }
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> Result<(), String> {
+        match name {
+            "threshold" => self.threshold = value,
+            "release" => self.follower.release = value,
+            _ => return Err(format!("no parameter '{name}'")),
+        }
+        Ok(())
+    }
+
+    fn to_args(&self) -> Option<Vec<String>> {
+        Some(vec![
+            format!("{}", self.threshold),
+            format!("{}", self.follower.release),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn followers_rise_on_attack_and_fall_on_release() {
+        let mut follower = EnvelopeFollower::new(0.0, 0.01);
+        // No attack time: the level jumps to the input, sign and all ignored.
+        assert_eq!(follower.next(-0.8, 1_000.0), 0.8);
+
+        let mut level = 0.8;
+        for _ in 0..10 {
+            let next = follower.next(0.0, 1_000.0);
+            assert!(next < level && next > 0.0);
+            level = next;
+        }
+        // Ten samples is one release time constant.
+        assert!((level - 0.8 * (-1.0f32).exp()).abs() < 1e-4);
+    }
+
+    #[test]
+    fn gates_open_over_the_threshold_and_close_at_half_of_it() {
+        let mut gate = Gate::new(0.5, 0.0);
+        gate.follower.attack = 0.0;
+        assert_eq!(gate.next(0.4, 1_000.0), 0.0);
+        assert_eq!(gate.next(0.6, 1_000.0), 1.0);
+        assert_eq!(gate.next(0.3, 1_000.0), 1.0);
+        assert_eq!(gate.next(0.2, 1_000.0), 0.0);
+        assert!(!gate.is_open());
+    }
+}
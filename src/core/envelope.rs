@@ -0,0 +1,145 @@
+//! ADSR envelopes, as levels along each segment and as a runner that
+//! produces one level per sample.
+
+use super::math;
+
+/// How far a curve amount of 1 bends a segment away from a straight line.
+const CURVE_STEEPNESS: f32 = 6.0;
+
+/// How far through a segment `duration` long `time` is, from 0 to 1. A
+/// segment of no length is over straight away.
+pub fn progress(time: f32, duration: f32) -> f32 {
+    if duration <= 0.0 {
+        1.0
+    } else {
+        (time / duration).clamp(0.0, 1.0)
+    }
+}
+
+/// Bends `progress` by `curve`, keeping both ends where they are.
+pub fn shape(progress: f32, curve: f32) -> f32 {
+    let k = curve.clamp(-1.0, 1.0) * CURVE_STEEPNESS;
+    if math::abs(k) < 1e-3 {
+        progress
+    } else {
+        (1.0 - math::exp(-k * progress)) / (1.0 - math::exp(-k))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Stage {
+    Idle,
+    Attack,
+    Decay,
+    Sustain,
+    Release { from: f32 },
+}
+
+/// An ADSR envelope stepped sample by sample. Times are in seconds and each
+/// curve runs from -1 to 1, as in the engine's envelopes.
+#[derive(Debug, Clone)]
+pub struct Envelope {
+    pub attack: f32,
+    pub decay: f32,
+    pub sustain: f32,
+    pub release: f32,
+    pub attack_curve: f32,
+    pub decay_curve: f32,
+    pub release_curve: f32,
+    stage: Stage,
+    /// Seconds into the current stage.
+    time: f32,
+    level: f32,
+}
+
+impl Envelope {
+    /// An envelope with straight segments, silent until [`Envelope::gate_on`].
+    pub fn new(attack: f32, decay: f32, sustain: f32, release: f32) -> Self {
+        Self {
+            attack,
+            decay,
+            sustain,
+            release,
+            attack_curve: 0.0,
+            decay_curve: 0.0,
+            release_curve: 0.0,
+            stage: Stage::Idle,
+            time: 0.0,
+            level: 0.0,
+        }
+    }
+
+    /// Starts the attack over.
+    pub fn gate_on(&mut self) {
+        self.stage = Stage::Attack;
+        self.time = 0.0;
+    }
+
+    /// Releases from the current level.
+    pub fn gate_off(&mut self) {
+        if self.stage != Stage::Idle {
+            self.stage = Stage::Release { from: self.level };
+            self.time = 0.0;
+        }
+    }
+
+    /// Whether the release is over, or the gate never opened.
+    pub fn is_idle(&self) -> bool {
+        self.stage == Stage::Idle
+    }
+
+    /// The level for the next sample.
+    pub fn next(&mut self, sample_rate: f32) -> f32 {
+        self.level = match self.stage {
+            Stage::Idle => 0.0,
+            Stage::Attack => shape(progress(self.time, self.attack), self.attack_curve),
+            Stage::Decay => {
+                let decayed = shape(progress(self.time, self.decay), self.decay_curve);
+                1.0 - (1.0 - self.sustain) * decayed
+            }
+            Stage::Sustain => self.sustain,
+            Stage::Release { from } => {
+                from * (1.0 - shape(progress(self.time, self.release), self.release_curve))
+            }
+        };
+
+        self.time += 1.0 / sample_rate;
+        let over = |duration: f32| self.time >= duration;
+        match self.stage {
+            Stage::Attack if over(self.attack) => {
+                self.stage = Stage::Decay;
+                self.time = 0.0;
+            }
+            Stage::Decay if over(self.decay) => self.stage = Stage::Sustain,
+            Stage::Release { .. } if over(self.release) => self.stage = Stage::Idle,
+            _ => {}
+        }
+        self.level
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn envelopes_step_through_their_stages() {
+        let mut envelope = Envelope::new(0.002, 0.002, 0.5, 0.002);
+        let sample_rate = 1_000.0;
+        assert_eq!(envelope.next(sample_rate), 0.0);
+
+        envelope.gate_on();
+        let attack: Vec<f32> = (0..2).map(|_| envelope.next(sample_rate)).collect();
+        assert_eq!(attack, [0.0, 0.5]);
+        let decay: Vec<f32> = (0..2).map(|_| envelope.next(sample_rate)).collect();
+        assert_eq!(decay, [1.0, 0.75]);
+        assert_eq!(envelope.next(sample_rate), 0.5);
+
+        envelope.gate_off();
+        assert_eq!(envelope.next(sample_rate), 0.5);
+        assert_eq!(envelope.next(sample_rate), 0.25);
+        assert!(envelope.is_idle());
+        assert_eq!(envelope.next(sample_rate), 0.0);
+    }
+}
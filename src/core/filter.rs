@@ -0,0 +1,127 @@
+//! Biquad filters, with the coefficients of the RBJ audio EQ cookbook.
+
+use super::graph::{AudioNode, ProcessContext};
+use super::math;
+use alloc::format;
+use alloc::string::String;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterMode {
+    Lowpass,
+    Highpass,
+    Bandpass,
+}
+
+/// A second-order filter. As a node it filters the sum of its inputs;
+/// `cutoff` and `q` can change while it runs.
+#[derive(Debug, Clone)]
+pub struct Biquad {
+    pub mode: FilterMode,
+    pub cutoff: f32,
+    pub q: f32,
+    /// Sample rate the coefficients were worked out for; 0 until the first
+    /// sample, and after a parameter change.
+    tuned_for: f32,
+    b: [f32; 3],
+    a: [f32; 2],
+    /// Last two inputs and outputs.
+    x: [f32; 2],
+    y: [f32; 2],
+}
+
+impl Biquad {
+    pub fn new(mode: FilterMode, cutoff: f32, q: f32) -> Self {
+        Self {
+            mode,
+            cutoff,
+            q,
+            tuned_for: 0.0,
+            b: [0.0; 3],
+            a: [0.0; 2],
+            x: [0.0; 2],
+            y: [0.0; 2],
+        }
+    }
+
+    fn tune(&mut self, sample_rate: f32) {
+        let cutoff = self.cutoff.clamp(1.0, sample_rate * 0.49);
+        let w0 = 2.0 * ::core::f32::consts::PI * cutoff / sample_rate;
+        let (sin, cos) = (math::sin(w0), math::cos(w0));
+        let alpha = sin / (2.0 * self.q.max(0.01));
+        let b = match self.mode {
+            FilterMode::Lowpass => [(1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0],
+            FilterMode::Highpass => [(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0],
+            FilterMode::Bandpass => [alpha, 0.0, -alpha],
+        };
+        let a0 = 1.0 + alpha;
+        self.b = b.map(|b| b / a0);
+        self.a = [-2.0 * cos / a0, (1.0 - alpha) / a0];
+        self.tuned_for = sample_rate;
+    }
+
+    /// Filters one sample.
+    pub fn next(&mut self, input: f32, sample_rate: f32) -> f32 {
+        if self.tuned_for != sample_rate {
+            self.tune(sample_rate);
+        }
+        let [b0, b1, b2] = self.b;
+        let [a1, a2] = self.a;
+        let output = b0 * input + b1 * self.x[0] + b2 * self.x[1] - a1 * self.y[0] - a2 * self.y[1];
+        self.x = [input, self.x[0]];
+        self.y = [output, self.y[0]];
+        output
+    }
+}
+
+impl AudioNode for Biquad {
+    fn process(&mut self, inputs: &[&[f32]], output: &mut [f32], ctx: &ProcessContext) {
+        for (i, out) in output.iter_mut().enumerate() {
+            let input = inputs.iter().filter_map(|input| input.get(i)).sum();
+            *out = self.next(input, ctx.sample_rate);
+        }
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> Result<(), String> {
+        match name {
+            "cutoff" => self.cutoff = value,
+            "q" => self.q = value,
+            _ => return Err(format!("no parameter '{name}'")),
+        }
+        self.tuned_for = 0.0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{Oscillator, Wave};
+    use alloc::vec::Vec;
+
+    /// Level of a sine at `freq` through `filter`, once it has settled.
+    fn level(mut filter: Biquad, freq: f32) -> f32 {
+        let sample_rate = 48_000.0;
+        let mut osc = Oscillator::new(Wave::Sine, freq);
+        let samples: Vec<f32> = (0..9_600)
+            .map(|_| filter.next(osc.next(sample_rate), sample_rate))
+            .collect();
+        samples[4_800..]
+            .iter()
+            .fold(0.0, |peak, s| s.abs().max(peak))
+    }
+
+    #[test]
+    fn filters_pass_their_band_and_cut_the_rest() {
+        let lowpass = Biquad::new(FilterMode::Lowpass, 500.0, 0.707);
+        assert!((level(lowpass.clone(), 100.0) - 1.0).abs() < 0.05);
+        assert!(level(lowpass, 8_000.0) < 0.01);
+
+        let highpass = Biquad::new(FilterMode::Highpass, 2_000.0, 0.707);
+        assert!(level(highpass.clone(), 100.0) < 0.01);
+        assert!((level(highpass, 8_000.0) - 1.0).abs() < 0.05);
+
+        let bandpass = Biquad::new(FilterMode::Bandpass, 1_000.0, 2.0);
+        assert!(level(bandpass.clone(), 1_000.0) > 0.9);
+        assert!(level(bandpass, 10_000.0) < 0.2);
+    }
+}
@@ -0,0 +1,494 @@
+//! Graphs of nodes processed one block at a time, in an order worked out
+//! when the graph is built so processing only reads and writes buffers.
+
+use ::core::fmt;
+use ::core::mem::{self, ManuallyDrop};
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// What a node gets to know about the block it's processing.
+#[derive(Debug, Clone, Copy)]
+pub struct ProcessContext {
+    pub sample_rate: f32,
+}
+
+/// A DSP node: fills `output` from its wired `inputs`, one block at a time.
+pub trait AudioNode: Send {
+    fn process(&mut self, inputs: &[&[f32]], output: &mut [f32], ctx: &ProcessContext);
+
+    /// The node whose output the graph plays.
+    fn is_output(&self) -> bool {
+        false
+    }
+
+    /// The input of the graph, fed from outside it, that the node passes on.
+    fn graph_input(&self) -> Option<usize> {
+        None
+    }
+
+    /// Changes a parameter while the graph runs, e.g. an oscillator's `freq`.
+    fn set_param(&mut self, name: &str, _value: f32) -> Result<(), String> {
+        Err(format!("no parameter '{name}'"))
+    }
+
+    /// The arguments a patch line would build the node with as it is now,
+    /// parameters set since included, or `None` to keep the ones it was
+    /// built from.
+    fn to_args(&self) -> Option<Vec<String>> {
+        None
+    }
+
+    /// Scales the node's inputs, in the order they're wired, for wires with
+    /// a weight. Only nodes mixing their inputs take weights other than 1.
+    fn set_input_weights(&mut self, weights: &[f32]) -> Result<(), String> {
+        if weights.iter().all(|&weight| weight == 1.0) {
+            Ok(())
+        } else {
+            Err("doesn't weight its inputs".into())
+        }
+    }
+
+    /// Where a node easing between values, such as a gain, has got to, so
+    /// the node replacing it on a reload can start from there.
+    fn ramp_state(&self) -> Option<f32> {
+        None
+    }
+
+    /// Eases in from `value`, the [`ramp_state`](Self::ramp_state) of the
+    /// node this one replaces.
+    fn ramp_from(&mut self, _value: f32) {}
+
+    /// Gets ready for blocks of `frames` at `ctx`'s sample rate with `wires`
+    /// wired in, signal and parameter ones together, allocating whatever
+    /// that takes so processing doesn't. Graphs call it before their first
+    /// block and again when the block size or their wires change; a node
+    /// processed on its own is prepared by hand.
+    fn prepare(&mut self, _frames: usize, _wires: usize, _ctx: &ProcessContext) {}
+
+    /// A MIDI note played into the node between blocks, let go at velocity
+    /// 0, for nodes that take notes such as [`MidiIn`](super::MidiIn) and
+    /// the [`Poly`](super::Poly)s it's wired into.
+    fn note(&mut self, _pitch: u8, _velocity: u8) {}
+
+    /// Parameters wires can drive sample by sample, e.g. an oscillator's
+    /// `freq`. A [`Link`] with `port` `i + 1` feeds the `i`th.
+    fn param_inputs(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    /// [`process`](Self::process), with `params` pairing an index into
+    /// [`param_inputs`](Self::param_inputs) with the block wired into it.
+    /// Nodes without parameter inputs don't need to implement it.
+    fn process_modulated(
+        &mut self,
+        inputs: &[&[f32]],
+        _params: &[(usize, &[f32])],
+        output: &mut [f32],
+        ctx: &ProcessContext,
+    ) {
+        self.process(inputs, output, ctx);
+    }
+}
+
+/// A wire from node `from` into node `to`: into its signal input when `port`
+/// is 0, or into its parameter input `param_inputs()[port - 1]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Link {
+    pub from: usize,
+    pub to: usize,
+    pub port: usize,
+}
+
+impl Link {
+    /// A wire into `to`'s signal input.
+    pub fn new(from: usize, to: usize) -> Self {
+        Self { from, to, port: 0 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GraphError {
+    /// The wires loop back on themselves.
+    Cycle,
+    /// A wire names a node index past the end.
+    MissingNode(usize),
+}
+
+impl fmt::Display for GraphError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GraphError::Cycle => write!(f, "Cycle detected"),
+            GraphError::MissingNode(node) => write!(f, "Couldn't find node {}", node),
+        }
+    }
+}
+
+/// Orders `num_nodes` nodes wired by `(from, to)` index pairs so each comes
+/// after every node wired into it. Returns node indices in that order.
+pub fn sort(num_nodes: usize, wires: &[(usize, usize)]) -> Result<Vec<usize>, GraphError> {
+    let mut in_degree = vec![0usize; num_nodes];
+    for &(from, to) in wires {
+        if from >= num_nodes {
+            return Err(GraphError::MissingNode(from));
+        }
+        *in_degree.get_mut(to).ok_or(GraphError::MissingNode(to))? += 1;
+    }
+
+    let mut queue: Vec<usize> = (0..num_nodes).filter(|&i| in_degree[i] == 0).collect();
+    let mut order = Vec::with_capacity(num_nodes);
+    while let Some(node) = queue.pop() {
+        order.push(node);
+        for &(from, to) in wires {
+            if from == node {
+                in_degree[to] -= 1;
+                if in_degree[to] == 0 {
+                    queue.push(to);
+                }
+            }
+        }
+    }
+
+    if order.len() != num_nodes {
+        return Err(GraphError::Cycle);
+    }
+    Ok(order)
+}
+
+/// For nodes in the processing `order` [`sort`] gave, the positions in that
+/// order of the nodes wired into each, with the port they're wired into.
+pub fn routes(order: &[usize], links: &[Link]) -> Vec<Vec<(usize, usize)>> {
+    let mut position = vec![0; order.len()];
+    for (i, &node) in order.iter().enumerate() {
+        position[node] = i;
+    }
+    let mut routes = vec![Vec::new(); order.len()];
+    for link in links {
+        routes[position[link.to]].push((position[link.from], link.port));
+    }
+    routes
+}
+
+/// Room for the blocks wired into one node at a time, kept between blocks so
+/// gathering them doesn't allocate.
+#[derive(Default)]
+pub struct Inputs {
+    /// Always empty between calls to [`Inputs::gather`], so no slice in them
+    /// outlives the block it's from.
+    signals: Vec<&'static [f32]>,
+    params: Vec<(usize, &'static [f32])>,
+}
+
+impl Inputs {
+    /// Room for `wires` wires into a node, such as its graph input and the
+    /// longest of the [`routes`].
+    pub fn new(wires: usize) -> Self {
+        Self {
+            signals: Vec::with_capacity(wires),
+            params: Vec::with_capacity(wires),
+        }
+    }
+
+    /// Room for the node with the most wires on `routes`, and a graph input.
+    pub fn for_routes(routes: &[Vec<(usize, usize)>]) -> Self {
+        Self::new(routes.iter().map(Vec::len).max().unwrap_or(0) + 1)
+    }
+
+    /// Runs `gather` with empty signal and parameter inputs to fill with
+    /// blocks that live as long as the call, e.g. to process a node with.
+    pub fn gather<'a, R>(
+        &mut self,
+        gather: impl FnOnce(&mut Vec<&'a [f32]>, &mut Vec<(usize, &'a [f32])>) -> R,
+    ) -> R {
+        let mut signals = recycle(mem::take(&mut self.signals));
+        let mut params = recycle(mem::take(&mut self.params));
+        let result = gather(&mut signals, &mut params);
+        self.signals = recycle(signals);
+        self.params = recycle(params);
+        result
+    }
+}
+
+/// Empties `items` and hands its allocation back for another type of the
+/// same size and alignment, such as slices borrowed for another lifetime.
+fn recycle<T, U>(mut items: Vec<T>) -> Vec<U> {
+    const {
+        assert!(size_of::<T>() == size_of::<U>() && align_of::<T>() == align_of::<U>());
+    }
+    items.clear();
+    let mut items = ManuallyDrop::new(items);
+    // SAFETY: the vector is empty, so no `T` is read as a `U`, and its
+    // allocation, if it has one, fits as many `U`s as `T`s.
+    unsafe { Vec::from_raw_parts(items.as_mut_ptr().cast(), 0, items.capacity()) }
+}
+
+/// Gives `buffers` one block of `frames` per node, unless it has them;
+/// whether it had to.
+fn size_buffers(buffers: &mut Vec<Vec<f32>>, nodes: usize, frames: usize) -> bool {
+    let resize = buffers.len() != nodes || buffers.first().map(Vec::len) != Some(frames);
+    if resize {
+        *buffers = vec![vec![0.0; frames]; nodes];
+    }
+    resize
+}
+
+/// How many wires [`process_in_order`] gathers for `node`: those on its
+/// route, and the graph input it passes on.
+fn wires_into(node: &dyn AudioNode, route: &[(usize, usize)]) -> usize {
+    route.len() + usize::from(node.graph_input().is_some())
+}
+
+/// Sizes `buffers` as [`process_in_order`] would and
+/// [prepares](AudioNode::prepare) `nodes` for blocks of `frames`, so the
+/// first block processed doesn't allocate.
+pub fn prepare_in_order<'a>(
+    nodes: impl Iterator<Item = &'a mut Box<dyn AudioNode>>,
+    routes: &[Vec<(usize, usize)>],
+    buffers: &mut Vec<Vec<f32>>,
+    frames: usize,
+    ctx: &ProcessContext,
+) {
+    size_buffers(buffers, routes.len(), frames);
+    for (node, route) in nodes.zip(routes) {
+        node.prepare(frames, wires_into(node.as_ref(), route), ctx);
+    }
+}
+
+/// Processes `nodes`, already in processing order, into `buffers`, one per
+/// node, and copies the output node's buffer into `output`. `routes` come
+/// from [`routes`], each node's wires are gathered in `inputs`, and
+/// `graph_inputs` go to the nodes passing them on. Nodes paired with `true`
+/// are bypassed: they aren't processed and pass the sum of their inputs
+/// straight on, so one without any goes silent. The buffers are only
+/// reallocated, and the nodes [prepared](AudioNode::prepare), when the node
+/// count or the block size changes.
+pub fn process_in_order<'a>(
+    nodes: impl Iterator<Item = (&'a mut Box<dyn AudioNode>, bool)>,
+    routes: &[Vec<(usize, usize)>],
+    buffers: &mut Vec<Vec<f32>>,
+    inputs: &mut Inputs,
+    graph_inputs: &[&[f32]],
+    output: &mut [f32],
+    ctx: &ProcessContext,
+) {
+    let prepare = size_buffers(buffers, routes.len(), output.len());
+    if !prepare {
+        for buf in buffers.iter_mut() {
+            buf.fill(0.0);
+        }
+    }
+
+    for (i, ((node, bypassed), route)) in nodes.zip(routes).enumerate() {
+        if prepare {
+            node.prepare(output.len(), wires_into(node.as_ref(), route), ctx);
+        }
+        // Sorted, so every input is an earlier node.
+        let (before, rest) = buffers.split_at_mut(i);
+        let current = &mut rest[0];
+
+        inputs.gather(|signals, params| {
+            if let Some(input) = node.graph_input().and_then(|index| graph_inputs.get(index)) {
+                signals.push(input);
+            }
+            for &(from, port) in route {
+                if port == 0 {
+                    signals.push(&before[from]);
+                } else {
+                    params.push((port - 1, &before[from]));
+                }
+            }
+
+            if bypassed {
+                for input in signals.iter() {
+                    for (out, sample) in current.iter_mut().zip(*input) {
+                        *out += sample;
+                    }
+                }
+            } else if params.is_empty() {
+                node.process(signals, current, ctx);
+            } else {
+                node.process_modulated(signals, params, current, ctx);
+            }
+        });
+        if node.is_output() {
+            output.copy_from_slice(current);
+        }
+    }
+}
+
+/// Nodes and the wires between them, ready to process. Nodes keep the index
+/// they were given in.
+pub struct Graph {
+    /// In processing order.
+    nodes: Vec<Box<dyn AudioNode>>,
+    /// Where each node given to [`Graph::new`] ended up in `nodes`.
+    positions: Vec<usize>,
+    routes: Vec<Vec<(usize, usize)>>,
+    buffers: Vec<Vec<f32>>,
+    inputs: Inputs,
+    /// Whether each node in `nodes` is bypassed.
+    bypassed: Vec<bool>,
+}
+
+impl Graph {
+    /// Links are between indices into `nodes`.
+    pub fn new(nodes: Vec<Box<dyn AudioNode>>, links: &[Link]) -> Result<Self, GraphError> {
+        let wires: Vec<(usize, usize)> = links.iter().map(|link| (link.from, link.to)).collect();
+        let order = sort(nodes.len(), &wires)?;
+        let routes = routes(&order, links);
+
+        let mut positions = vec![0; order.len()];
+        for (i, &node) in order.iter().enumerate() {
+            positions[node] = i;
+        }
+        let mut slots: Vec<Option<Box<dyn AudioNode>>> = nodes.into_iter().map(Some).collect();
+        let nodes = order
+            .iter()
+            .filter_map(|&node| slots[node].take())
+            .collect();
+
+        Ok(Self {
+            bypassed: vec![false; order.len()],
+            nodes,
+            positions,
+            inputs: Inputs::for_routes(&routes),
+            routes,
+            buffers: Vec::new(),
+        })
+    }
+
+    /// Bypasses the node given at `index` to [`Graph::new`], or brings it
+    /// back, as [`process_in_order`] describes.
+    pub fn set_bypass(&mut self, index: usize, bypass: bool) {
+        if let Some(&position) = self.positions.get(index) {
+            self.bypassed[position] = bypass;
+        }
+    }
+
+    /// The node given at `index` to [`Graph::new`].
+    pub fn node_mut(&mut self, index: usize) -> Option<&mut Box<dyn AudioNode>> {
+        let position = *self.positions.get(index)?;
+        self.nodes.get_mut(position)
+    }
+
+    /// Allocates the buffers for blocks of `frames` and prepares the nodes,
+    /// so the first [`Graph::process`] doesn't have to.
+    pub fn prepare(&mut self, frames: usize, ctx: &ProcessContext) {
+        prepare_in_order(
+            self.nodes.iter_mut(),
+            &self.routes,
+            &mut self.buffers,
+            frames,
+            ctx,
+        );
+    }
+
+    pub fn process(&mut self, output: &mut [f32], ctx: &ProcessContext) {
+        self.process_with(&[], output, ctx);
+    }
+
+    /// Processes a block with `inputs` fed to the nodes passing on the
+    /// graph's inputs, such as [`Input`](super::Input)s.
+    pub fn process_with(&mut self, inputs: &[&[f32]], output: &mut [f32], ctx: &ProcessContext) {
+        process_in_order(
+            self.nodes.iter_mut().zip(self.bypassed.iter().copied()),
+            &self.routes,
+            &mut self.buffers,
+            &mut self.inputs,
+            inputs,
+            output,
+            ctx,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{Gain, Oscillator, Output, Wave};
+
+    #[test]
+    fn nodes_run_after_their_inputs() {
+        let order = sort(4, &[(2, 1), (1, 0), (3, 1)]).unwrap();
+        let position = |node| order.iter().position(|&n| n == node).unwrap();
+        assert!(position(2) < position(1));
+        assert!(position(3) < position(1));
+        assert!(position(1) < position(0));
+
+        assert_eq!(sort(2, &[(0, 1), (1, 0)]), Err(GraphError::Cycle));
+        assert_eq!(sort(2, &[(0, 2)]), Err(GraphError::MissingNode(2)));
+    }
+
+    #[test]
+    fn nodes_take_any_number_of_wires() {
+        let mut nodes: Vec<Box<dyn AudioNode>> = vec![Box::new(Output), Box::new(Gain::new(1.0))];
+        let mut links = vec![Link::new(1, 0)];
+        for i in 0..40 {
+            nodes.push(Box::new(Oscillator::new(Wave::Square, 0.0)));
+            links.push(Link::new(i + 2, 1));
+        }
+        let mut graph = Graph::new(nodes, &links).unwrap();
+        let mut output = [0.0; 4];
+        graph.process(
+            &mut output,
+            &ProcessContext {
+                sample_rate: 1_000.0,
+            },
+        );
+        assert_eq!(output, [-40.0; 4]);
+    }
+
+    #[test]
+    fn graphs_play_their_output_node() {
+        let nodes: Vec<Box<dyn AudioNode>> = vec![
+            Box::new(Output),
+            Box::new(Gain::new(0.5)),
+            // At 0 Hz a square wave stays at -1.
+            Box::new(Oscillator::new(Wave::Square, 0.0)),
+        ];
+        let mut graph = Graph::new(nodes, &[Link::new(2, 1), Link::new(1, 0)]).unwrap();
+        let ctx = ProcessContext {
+            sample_rate: 1_000.0,
+        };
+        let mut output = [0.0; 4];
+        graph.process(&mut output, &ctx);
+        assert_eq!(output, [-0.5; 4]);
+
+        // Gain changes ramp over 10 ms.
+        graph.node_mut(1).unwrap().set_param("gain", 2.0).unwrap();
+        let mut output = [0.0; 12];
+        graph.process(&mut output, &ctx);
+        assert!(output[0] < -0.5 && output[0] > -2.0);
+        assert_eq!(output[9..], [-2.0; 3]);
+    }
+
+    #[test]
+    fn wires_into_parameters_modulate_them() {
+        let nodes: Vec<Box<dyn AudioNode>> = vec![
+            Box::new(Output),
+            Box::new(Oscillator::new(Wave::Square, 0.0)),
+            Box::new(Gain::new(-250.0)),
+            Box::new(Oscillator::new(Wave::Square, 0.0)),
+        ];
+        // A constant 250 Hz into the 0 Hz square's frequency: a quarter
+        // cycle per sample.
+        let links = [
+            Link::new(3, 2),
+            Link {
+                from: 2,
+                to: 1,
+                port: 1,
+            },
+            Link::new(1, 0),
+        ];
+        let mut graph = Graph::new(nodes, &links).unwrap();
+        let ctx = ProcessContext {
+            sample_rate: 1_000.0,
+        };
+        let mut output = [0.0; 4];
+        graph.process(&mut output, &ctx);
+        assert_eq!(output, [-1.0, -1.0, 1.0, 1.0]);
+    }
+}
@@ -0,0 +1,45 @@
+//! The float functions std has as methods, from libm without std.
+
+#[cfg(not(feature = "no_std"))]
+pub fn sin(x: f32) -> f32 {
+    x.sin()
+}
+
+#[cfg(not(feature = "no_std"))]
+pub fn cos(x: f32) -> f32 {
+    x.cos()
+}
+
+#[cfg(not(feature = "no_std"))]
+pub fn exp(x: f32) -> f32 {
+    x.exp()
+}
+
+#[cfg(not(feature = "no_std"))]
+pub fn ln(x: f32) -> f32 {
+    x.ln()
+}
+
+#[cfg(not(feature = "no_std"))]
+pub fn round(x: f32) -> f32 {
+    x.round()
+}
+
+#[cfg(not(feature = "no_std"))]
+pub fn abs(x: f32) -> f32 {
+    x.abs()
+}
+
+#[cfg(not(feature = "no_std"))]
+pub fn rem_euclid(x: f32, y: f32) -> f32 {
+    x.rem_euclid(y)
+}
+
+#[cfg(feature = "no_std")]
+pub fn rem_euclid(x: f32, y: f32) -> f32 {
+    let r = libm::fmodf(x, y);
+    if r < 0.0 { r + abs(y) } else { r }
+}
+
+#[cfg(feature = "no_std")]
+pub use libm::{cosf as cos, expf as exp, fabsf as abs, logf as ln, roundf as round, sinf as sin};
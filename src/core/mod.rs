@@ -0,0 +1,38 @@
+//! The DSP that needs neither std nor the engine: [`AudioNode`]s for
+//! sound, control signals and rhythm, and the graphs that run them,
+//! polyphonic voices included. It only allocates when a graph is built or
+//! its block size or sample rate changes, never while processing, and with
+//! the `no_std` feature it is all aurio builds, for embedded targets such
+//! as Daisy-class boards.
+//!
+//! Audio is bipolar, from -1 to 1, whatever makes it; control signals
+//! (envelopes, gates, followers) are unipolar, from 0 to 1. [`Unipolar`]
+//! and [`Bipolar`] convert between the two, e.g. to use an oscillator as a
+//! 0 to 1 LFO.
+
+pub mod clock;
+pub mod control;
+pub mod dynamics;
+pub mod envelope;
+pub mod filter;
+pub mod graph;
+mod math;
+pub mod osc;
+pub mod oversample;
+pub mod pluck;
+pub mod poly;
+pub mod quantize;
+pub mod random;
+
+pub use clock::{Clock, Seq};
+pub use control::{Add, Bipolar, Const, Mul, Scale, Slew, Unipolar};
+pub use dynamics::{EnvelopeFollower, Gate};
+pub use envelope::Envelope;
+pub use filter::{Biquad, FilterMode};
+pub use graph::{AudioNode, Graph, GraphError, Inputs, Link, ProcessContext};
+pub use osc::{Gain, Input, Oscillator, Output, Wave};
+pub use oversample::{Oversampled, oversample};
+pub use pluck::Pluck;
+pub use poly::{MidiIn, Poly};
+pub use quantize::Quantizer;
+pub use random::{Random, Rng, SampleHold};
@@ -0,0 +1,288 @@
+//! Sound sources and mixers: the nodes `.au` patches are built from.
+
+use super::graph::{AudioNode, ProcessContext};
+use super::math;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+pub enum Wave {
+    Sine,
+    Square,
+    Saw,
+}
+
+pub struct Oscillator {
+    pub wave: Wave,
+    pub freq: f32,
+    phase: f32,
+}
+
+impl Oscillator {
+    pub fn new(wave: Wave, freq: f32) -> Self {
+        Self {
+            wave,
+            freq,
+            phase: 0.0,
+        }
+    }
+
+    /// The next sample, from -1 to 1.
+    pub fn next(&mut self, sample_rate: f32) -> f32 {
+        self.next_at(self.freq, sample_rate)
+    }
+
+    /// The next sample at `freq` instead of the set frequency, for frequency
+    /// modulation. Negative frequencies run the wave backwards.
+    pub fn next_at(&mut self, freq: f32, sample_rate: f32) -> f32 {
+        let sample = match self.wave {
+            Wave::Sine => math::sin(self.phase * 2.0 * ::core::f32::consts::PI),
+            Wave::Square => {
+                if self.phase < 0.5 {
+                    -1.0
+                } else {
+                    1.0
+                }
+            }
+            Wave::Saw => self.phase * 2.0 - 1.0,
+        };
+
+        // Frequencies past the sample rate step more than one cycle.
+        self.phase = math::rem_euclid(self.phase + freq / sample_rate, 1.0);
+        sample
+    }
+}
+
+/// The sum of the blocks wired into a parameter, at sample `i`.
+pub(super) fn modulation(params: &[(usize, &[f32])], i: usize) -> f32 {
+    params
+        .iter()
+        .map(|(_, block)| block.get(i).copied().unwrap_or(0.0))
+        .sum()
+}
+
+impl AudioNode for Oscillator {
+    fn process(&mut self, _inputs: &[&[f32]], output: &mut [f32], ctx: &ProcessContext) {
+        for out in output.iter_mut() {
+            *out = self.next(ctx.sample_rate);
+        }
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> Result<(), String> {
+        match name {
+            "freq" => self.freq = value,
+            _ => return Err(format!("no parameter '{name}'")),
+        }
+        Ok(())
+    }
+
+    fn to_args(&self) -> Option<Vec<String>> {
+        let wave = match self.wave {
+            Wave::Sine => "Sine",
+            Wave::Square => "Square",
+            Wave::Saw => "Saw",
+        };
+        Some(vec![wave.into(), format!("{}", self.freq)])
+    }
+
+    fn param_inputs(&self) -> &'static [&'static str] {
+        &["freq"]
+    }
+
+    /// Wires into `freq` add to the set frequency, in Hz.
+    fn process_modulated(
+        &mut self,
+        _inputs: &[&[f32]],
+        params: &[(usize, &[f32])],
+        output: &mut [f32],
+        ctx: &ProcessContext,
+    ) {
+        for (i, out) in output.iter_mut().enumerate() {
+            *out = self.next_at(self.freq + modulation(params, i), ctx.sample_rate);
+        }
+    }
+}
+
+/// Seconds a gain takes to move to a new value, so changes don't click.
+const GAIN_RAMP: f32 = 0.01;
+
+/// Sums its inputs, each scaled by its weight, and scales the result. Moves
+/// to a new `value` over [`GAIN_RAMP`] instead of jumping.
+pub struct Gain {
+    pub value: f32,
+    /// One per input, in wire order; inputs past the end weigh 1.
+    weights: Vec<f32>,
+    /// The factor applied to the last sample, on its way to `target`.
+    current: f32,
+    target: f32,
+    step: f32,
+    /// Samples left until `current` reaches `target`.
+    remaining: u32,
+}
+
+impl Gain {
+    pub fn new(value: f32) -> Self {
+        Self {
+            value,
+            weights: Vec::new(),
+            current: value,
+            target: value,
+            step: 0.0,
+            remaining: 0,
+        }
+    }
+
+    /// The factor for the next sample, a step further along any ramp.
+    fn next_factor(&mut self, sample_rate: f32) -> f32 {
+        if self.value != self.target {
+            self.target = self.value;
+            let steps = (GAIN_RAMP * sample_rate).max(1.0);
+            self.remaining = steps as u32;
+            self.step = (self.target - self.current) / steps;
+        }
+        if self.remaining > 0 {
+            self.remaining -= 1;
+            self.current = if self.remaining == 0 {
+                self.target
+            } else {
+                self.current + self.step
+            };
+        }
+        self.current
+    }
+
+    /// Fills `output` with the weighted sum of `inputs`.
+    fn mix(&self, inputs: &[&[f32]], output: &mut [f32]) {
+        output.fill(0.0);
+        for (k, input) in inputs.iter().enumerate() {
+            let weight = self.weights.get(k).copied().unwrap_or(1.0);
+            for (out, sample) in output.iter_mut().zip(input.iter()) {
+                *out += sample * weight;
+            }
+        }
+    }
+}
+
+impl AudioNode for Gain {
+    fn process(&mut self, inputs: &[&[f32]], output: &mut [f32], ctx: &ProcessContext) {
+        self.process_modulated(inputs, &[], output, ctx);
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> Result<(), String> {
+        match name {
+            "gain" => self.value = value,
+            _ => return Err(format!("no parameter '{name}'")),
+        }
+        Ok(())
+    }
+
+    fn to_args(&self) -> Option<Vec<String>> {
+        Some(vec![format!("{}", self.value)])
+    }
+
+    fn set_input_weights(&mut self, weights: &[f32]) -> Result<(), String> {
+        self.weights = weights.to_vec();
+        Ok(())
+    }
+
+    fn ramp_state(&self) -> Option<f32> {
+        Some(self.current)
+    }
+
+    fn ramp_from(&mut self, value: f32) {
+        self.current = value;
+        // Set apart from `value`, so the next block starts the ramp.
+        self.target = f32::NAN;
+    }
+
+    fn param_inputs(&self) -> &'static [&'static str] {
+        &["gain"]
+    }
+
+    /// Wires into `gain` add to the set factor, for amplitude modulation.
+    fn process_modulated(
+        &mut self,
+        inputs: &[&[f32]],
+        params: &[(usize, &[f32])],
+        output: &mut [f32],
+        ctx: &ProcessContext,
+    ) {
+        self.mix(inputs, output);
+        for (i, out) in output.iter_mut().enumerate() {
+            *out *= self.next_factor(ctx.sample_rate) + modulation(params, i);
+        }
+    }
+}
+
+/// Fills `output` with the sum of the `inputs` as long as it.
+fn sum(inputs: &[&[f32]], output: &mut [f32]) {
+    let len = output.len();
+    output.fill(0.0);
+    for input in inputs.iter().filter(|input| input.len() == len) {
+        for (out, sample) in output.iter_mut().zip(input.iter()) {
+            *out += sample;
+        }
+    }
+}
+
+/// Sums its inputs into what the graph plays.
+pub struct Output;
+
+impl AudioNode for Output {
+    fn process(&mut self, inputs: &[&[f32]], output: &mut [f32], _ctx: &ProcessContext) {
+        sum(inputs, output);
+    }
+
+    fn is_output(&self) -> bool {
+        true
+    }
+}
+
+/// Passes on what's fed into the graph's input `index`, such as a voice's
+/// gate in a [`Poly`](super::Poly), along with anything wired into it.
+pub struct Input {
+    pub index: usize,
+}
+
+impl AudioNode for Input {
+    fn process(&mut self, inputs: &[&[f32]], output: &mut [f32], _ctx: &ProcessContext) {
+        sum(inputs, output);
+    }
+
+    fn graph_input(&self) -> Option<usize> {
+        Some(self.index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_wave_swings_either_side_of_zero() {
+        for wave in [Wave::Sine, Wave::Square, Wave::Saw] {
+            let mut osc = Oscillator::new(wave, 10.0);
+            let samples: Vec<f32> = (0..1_000).map(|_| osc.next(1_000.0)).collect();
+            let mean = samples.iter().sum::<f32>() / samples.len() as f32;
+            assert!(mean.abs() < 0.02);
+            assert!(samples.iter().all(|sample| sample.abs() <= 1.0));
+            assert!(samples.iter().any(|&sample| sample < -0.9));
+            assert!(samples.iter().any(|&sample| sample > 0.9));
+        }
+    }
+
+    #[test]
+    fn frequencies_past_the_sample_rate_keep_the_phase_in_a_cycle() {
+        let mut osc = Oscillator::new(Wave::Saw, 0.0);
+        for freq in [2_500.0, -3_700.0, 1_000.0, -1_000.0] {
+            osc.next_at(freq, 1_000.0);
+            assert!((0.0..=1.0).contains(&osc.phase), "{}", osc.phase);
+        }
+        // 2.5 cycles forward less 3.7 back leaves it 0.8 of a cycle along.
+        let mut osc = Oscillator::new(Wave::Saw, 0.0);
+        osc.next_at(2_500.0, 1_000.0);
+        osc.next_at(-3_700.0, 1_000.0);
+        assert!((osc.next(1_000.0) - 0.6).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,296 @@
+//! Oversampling: a node run at a multiple of the sample rate, so what a
+//! nonlinear or frequency-modulated node makes above half the sample rate is
+//! filtered out instead of folding back down as aliasing.
+
+use super::graph::{AudioNode, Inputs, ProcessContext};
+use super::math;
+use ::core::f32::consts::PI;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Taps in each halfband filter: about 15 samples of delay at the higher
+/// rate, on the way up and again on the way down.
+const TAPS: usize = 31;
+
+/// A windowed-sinc lowpass at a quarter of its sample rate, with every
+/// other tap but the middle one zero, and a gain of 1 at DC.
+fn halfband_taps() -> [f32; TAPS] {
+    let middle = (TAPS / 2) as i32;
+    let mut taps = [0.0; TAPS];
+    for (k, tap) in taps.iter_mut().enumerate() {
+        let n = k as i32 - middle;
+        let sinc = if n == 0 {
+            1.0
+        } else if n % 2 == 0 {
+            0.0
+        } else {
+            let x = n as f32 * PI / 2.0;
+            math::sin(x) / x
+        };
+        // Blackman window.
+        let phase = 2.0 * PI * k as f32 / (TAPS - 1) as f32;
+        *tap = sinc * (0.42 - 0.5 * math::cos(phase) + 0.08 * math::cos(2.0 * phase));
+    }
+    let sum: f32 = taps.iter().sum();
+    for tap in &mut taps {
+        *tap /= sum;
+    }
+    taps
+}
+
+/// One halfband filter's state, run at the higher of the two rates.
+#[derive(Debug, Clone)]
+struct Halfband {
+    /// The last [`TAPS`] samples twice over, newest first from `position`,
+    /// so they're always in one piece.
+    history: [f32; 2 * TAPS],
+    position: usize,
+}
+
+impl Halfband {
+    fn new() -> Self {
+        Self {
+            history: [0.0; 2 * TAPS],
+            position: 0,
+        }
+    }
+
+    fn next(&mut self, taps: &[f32; TAPS], sample: f32) -> f32 {
+        self.position = (self.position + TAPS - 1) % TAPS;
+        self.history[self.position] = sample;
+        self.history[self.position + TAPS] = sample;
+        self.history[self.position..self.position + TAPS]
+            .iter()
+            .zip(taps)
+            .map(|(sample, tap)| sample * tap)
+            .sum()
+    }
+
+    /// Doubles `input`'s rate into `output`, twice its length.
+    fn upsample(&mut self, taps: &[f32; TAPS], input: &[f32], output: &mut [f32]) {
+        for (pair, sample) in output.chunks_exact_mut(2).zip(input) {
+            // Every other sample is a zero, so the rest make up for it.
+            pair[0] = self.next(taps, 2.0 * sample);
+            pair[1] = self.next(taps, 0.0);
+        }
+    }
+
+    /// Halves `input`'s rate into `output`, half its length.
+    fn downsample(&mut self, taps: &[f32; TAPS], input: &[f32], output: &mut [f32]) {
+        for (out, pair) in output.iter_mut().zip(input.chunks_exact(2)) {
+            *out = self.next(taps, pair[0]);
+            self.next(taps, pair[1]);
+        }
+    }
+}
+
+/// Runs a node at twice the sample rate: the blocks wired into it are
+/// upsampled, and its output filtered and downsampled again, which delays it
+/// by 15 samples. Everything else, such as its parameters, is the
+/// node's own. [`oversample`] nests them for four times and more. It's silent
+/// for blocks and wires [`AudioNode::prepare`] didn't make room for.
+pub struct Oversampled {
+    inner: Box<dyn AudioNode>,
+    taps: [f32; TAPS],
+    /// One per wire in: the signal inputs in order, then the parameters.
+    up: Vec<Halfband>,
+    down: Halfband,
+    /// Each wire's block at the doubled rate, allocated in
+    /// [`AudioNode::prepare`].
+    inputs: Vec<Vec<f32>>,
+    /// Room for gathering the doubled blocks to process the node with.
+    wires: Inputs,
+    /// The node's output at the doubled rate.
+    output: Vec<f32>,
+}
+
+impl Oversampled {
+    pub fn new(inner: Box<dyn AudioNode>) -> Self {
+        Self {
+            inner,
+            taps: halfband_taps(),
+            up: Vec::new(),
+            down: Halfband::new(),
+            inputs: Vec::new(),
+            wires: Inputs::default(),
+            output: Vec::new(),
+        }
+    }
+}
+
+/// `node` run at `factor` times the sample rate, rounded down to a power of
+/// two; 1 or less leaves it as it is.
+pub fn oversample(node: Box<dyn AudioNode>, factor: u32) -> Box<dyn AudioNode> {
+    if factor < 2 {
+        node
+    } else {
+        oversample(Box::new(Oversampled::new(node)), factor / 2)
+    }
+}
+
+impl AudioNode for Oversampled {
+    fn process(&mut self, inputs: &[&[f32]], output: &mut [f32], ctx: &ProcessContext) {
+        self.process_modulated(inputs, &[], output, ctx);
+    }
+
+    fn is_output(&self) -> bool {
+        self.inner.is_output()
+    }
+
+    fn graph_input(&self) -> Option<usize> {
+        self.inner.graph_input()
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> Result<(), String> {
+        self.inner.set_param(name, value)
+    }
+
+    fn to_args(&self) -> Option<Vec<String>> {
+        self.inner.to_args()
+    }
+
+    fn set_input_weights(&mut self, weights: &[f32]) -> Result<(), String> {
+        self.inner.set_input_weights(weights)
+    }
+
+    fn ramp_state(&self) -> Option<f32> {
+        self.inner.ramp_state()
+    }
+
+    fn ramp_from(&mut self, value: f32) {
+        self.inner.ramp_from(value);
+    }
+
+    /// Sizes the doubled blocks for blocks of `frames` and `wires` wires in,
+    /// unless they are, keeping the filters of the wires it had.
+    fn prepare(&mut self, frames: usize, wires: usize, ctx: &ProcessContext) {
+        if self.output.len() != 2 * frames || self.inputs.len() != wires {
+            self.up.resize(wires, Halfband::new());
+            self.inputs = vec![vec![0.0; 2 * frames]; wires];
+            self.wires = Inputs::new(wires);
+            self.output = vec![0.0; 2 * frames];
+        }
+        let ctx = ProcessContext {
+            sample_rate: ctx.sample_rate * 2.0,
+        };
+        self.inner.prepare(2 * frames, wires, &ctx);
+    }
+
+    fn note(&mut self, pitch: u8, velocity: u8) {
+        self.inner.note(pitch, velocity);
+    }
+
+    fn param_inputs(&self) -> &'static [&'static str] {
+        self.inner.param_inputs()
+    }
+
+    fn process_modulated(
+        &mut self,
+        inputs: &[&[f32]],
+        params: &[(usize, &[f32])],
+        output: &mut [f32],
+        ctx: &ProcessContext,
+    ) {
+        if self.output.len() != 2 * output.len() || self.inputs.len() < inputs.len() + params.len()
+        {
+            output.fill(0.0);
+            return;
+        }
+        let wires = inputs.iter().chain(params.iter().map(|(_, block)| block));
+        for ((block, up), filter) in wires.zip(&mut self.inputs).zip(&mut self.up) {
+            filter.upsample(&self.taps, block, up);
+        }
+
+        let ctx = ProcessContext {
+            sample_rate: ctx.sample_rate * 2.0,
+        };
+        let (up_inputs, up_params) = self.inputs.split_at(inputs.len());
+        self.wires.gather(|signals, modulation| {
+            signals.extend(up_inputs.iter().map(Vec::as_slice));
+            modulation.extend(
+                params
+                    .iter()
+                    .zip(up_params)
+                    .map(|((param, _), up)| (*param, up.as_slice())),
+            );
+            if modulation.is_empty() {
+                self.inner.process(signals, &mut self.output, &ctx);
+            } else {
+                self.inner
+                    .process_modulated(signals, modulation, &mut self.output, &ctx);
+            }
+        });
+        self.down.downsample(&self.taps, &self.output, output);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{Oscillator, Scale, Wave};
+
+    /// Outputs the sample rate it's run at.
+    struct Rate;
+
+    impl AudioNode for Rate {
+        fn process(&mut self, _inputs: &[&[f32]], output: &mut [f32], ctx: &ProcessContext) {
+            output.fill(ctx.sample_rate);
+        }
+    }
+
+    #[test]
+    fn oversampled_nodes_run_faster_and_pass_the_band_through() {
+        let ctx = ProcessContext {
+            sample_rate: 1_000.0,
+        };
+        let mut rate = oversample(Box::new(Rate), 4);
+        let mut output = [0.0; 64];
+        rate.process(&[], &mut output, &ctx);
+        assert_eq!(output, [0.0; 64]);
+        rate.prepare(output.len(), 0, &ctx);
+        rate.process(&[], &mut output, &ctx);
+        assert!((output[63] - 4_000.0).abs() < 1.0);
+
+        // A tone well under half the sample rate comes out as it went in,
+        // 15 samples late.
+        let mut tone = Oscillator::new(Wave::Sine, 50.0);
+        let input: Vec<f32> = (0..64).map(|_| tone.next(ctx.sample_rate)).collect();
+        let mut scale = oversample(
+            Box::new(Scale {
+                factor: 1.0,
+                offset: 0.0,
+            }),
+            2,
+        );
+        scale.prepare(output.len(), 1, &ctx);
+        scale.process(&[&input], &mut output, &ctx);
+        for (out, sample) in output[15..].iter().zip(&input) {
+            assert!((out - sample).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn oversampling_keeps_harmonics_from_folding_back() {
+        // A 7 kHz square's harmonics run past 24 kHz and fold back down in
+        // between its own; its 7th, at 49 kHz, lands at 1 kHz.
+        let ctx = ProcessContext {
+            sample_rate: 48_000.0,
+        };
+        let alias_level = |factor| {
+            let mut square = oversample(Box::new(Oscillator::new(Wave::Square, 7_000.0)), factor);
+            let mut output = vec![0.0; 4_800];
+            square.prepare(output.len(), 0, &ctx);
+            square.process(&[], &mut output, &ctx);
+            let (mut re, mut im) = (0.0, 0.0);
+            for (i, sample) in output.iter().enumerate() {
+                let phase = 2.0 * PI * 1_000.0 * i as f32 / ctx.sample_rate;
+                re += sample * math::cos(phase);
+                im += sample * math::sin(phase);
+            }
+            (re * re + im * im) / output.len() as f32
+        };
+        assert!(alias_level(4) < alias_level(1) / 4.0);
+    }
+}
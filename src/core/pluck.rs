@@ -0,0 +1,243 @@
+//! Karplus-Strong plucked strings: a burst of noise circulating through a
+//! delay line tuned to the pitch, softened a little on every pass. An allpass
+//! in the loop makes up the fraction of a sample the line can't, so high
+//! strings stay in tune.
+
+use super::graph::{AudioNode, ProcessContext};
+use super::math;
+use super::osc::modulation;
+use super::random::Rng;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Lowest pitch the delay line has room for, in Hz.
+const LOWEST_FREQ: f32 = 20.0;
+
+/// Level a string has died down to after `decay` seconds: -60 dB.
+const DECAY_LEVEL: f32 = 0.001;
+
+/// Input level a gate has to rise past to pluck.
+const GATE_THRESHOLD: f32 = 0.5;
+
+/// Least delay left to the allpass: it drifts out of tune at the top of the
+/// band when asked for much less.
+const MIN_FRACTION: f32 = 0.1;
+
+/// A plucked string at `freq` Hz, ringing for about `decay` seconds. It's
+/// plucked on every rising edge of the sum of its inputs, e.g. a clock's
+/// gates; pitch and decay changes, wired or set, apply from the next pluck.
+/// It's silent until [`AudioNode::prepare`] has made room for the string.
+#[derive(Debug, Clone)]
+pub struct Pluck {
+    pub freq: f32,
+    pub decay: f32,
+    /// Room for [`LOWEST_FREQ`], allocated in [`AudioNode::prepare`].
+    line: Vec<f32>,
+    sized_for: f32,
+    /// Whole samples in one period of the current pluck; 0 before the first.
+    period: usize,
+    position: usize,
+    /// Gain on every pass around the line.
+    feedback: f32,
+    /// The allpass's coefficient, for the fraction of a sample left over.
+    tuning: f32,
+    /// The allpass's last input and output.
+    allpass: (f32, f32),
+    gate: bool,
+    noise: Rng,
+}
+
+impl Pluck {
+    pub fn new(freq: f32, decay: f32) -> Self {
+        Self {
+            freq,
+            decay,
+            line: Vec::new(),
+            sized_for: 0.0,
+            period: 0,
+            position: 0,
+            feedback: 0.0,
+            tuning: 0.0,
+            allpass: (0.0, 0.0),
+            gate: false,
+            noise: Rng::new(0),
+        }
+    }
+
+    /// Fills one period of the line with noise.
+    pub fn pluck(&mut self, sample_rate: f32) {
+        self.pluck_at(self.freq, sample_rate);
+    }
+
+    /// Plucks at `freq` instead of the set frequency. Pitches below what
+    /// the line was prepared for are clamped to the lowest it holds.
+    pub fn pluck_at(&mut self, freq: f32, sample_rate: f32) {
+        if self.line.len() < 2 {
+            return;
+        }
+        let period = sample_rate / freq.max(LOWEST_FREQ);
+        // Averaging neighbours takes half a sample off the loop; the allpass
+        // adds the fraction back.
+        self.period = ((period + 0.5 - MIN_FRACTION) as usize).clamp(2, self.line.len());
+        // Only short of it past the top of the band, where the clamp gives.
+        let fraction = (period + 0.5 - self.period as f32).max(MIN_FRACTION);
+        self.tuning = (1.0 - fraction) / (1.0 + fraction);
+        self.allpass = (0.0, 0.0);
+        self.position = 0;
+        self.feedback = if self.decay > 0.0 {
+            math::exp(math::ln(DECAY_LEVEL) * period / (self.decay * sample_rate))
+        } else {
+            0.0
+        };
+        for sample in &mut self.line[..self.period] {
+            *sample = self.noise.next_bipolar();
+        }
+    }
+
+    /// The next sample of the string, silent until it's first plucked.
+    pub fn next_sample(&mut self) -> f32 {
+        if self.period == 0 {
+            return 0.0;
+        }
+        let next = (self.position + 1) % self.period;
+        let sample = self.line[self.position];
+        // Averaging neighbours damps the highs faster than the lows.
+        let averaged = (sample + self.line[next]) * 0.5;
+        let (last_in, last_out) = self.allpass;
+        let tuned = self.tuning * (averaged - last_out) + last_in;
+        self.allpass = (averaged, tuned);
+        self.line[self.position] = tuned * self.feedback;
+        self.position = next;
+        sample
+    }
+}
+
+impl AudioNode for Pluck {
+    fn process(&mut self, inputs: &[&[f32]], output: &mut [f32], ctx: &ProcessContext) {
+        self.process_modulated(inputs, &[], output, ctx);
+    }
+
+    fn param_inputs(&self) -> &'static [&'static str] {
+        &["freq"]
+    }
+
+    /// Makes room in the line for [`LOWEST_FREQ`] at the sample rate,
+    /// unless it already has it.
+    fn prepare(&mut self, _frames: usize, _wires: usize, ctx: &ProcessContext) {
+        if self.sized_for != ctx.sample_rate {
+            self.line = vec![0.0; (ctx.sample_rate / LOWEST_FREQ) as usize + 1];
+            self.sized_for = ctx.sample_rate;
+            self.period = 0;
+        }
+    }
+
+    /// Wires into `freq` add to the set frequency of the plucks they're
+    /// there for, e.g. a sequencer's notes.
+    fn process_modulated(
+        &mut self,
+        inputs: &[&[f32]],
+        params: &[(usize, &[f32])],
+        output: &mut [f32],
+        ctx: &ProcessContext,
+    ) {
+        for (i, out) in output.iter_mut().enumerate() {
+            let gate: f32 = inputs.iter().filter_map(|input| input.get(i)).sum();
+            let open = gate >= GATE_THRESHOLD;
+            if open && !self.gate {
+                self.pluck_at(self.freq + modulation(params, i), ctx.sample_rate);
+            }
+            self.gate = open;
+            *out = self.next_sample();
+        }
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> Result<(), String> {
+        match name {
+            "freq" => self.freq = value,
+            "decay" => self.decay = value,
+            _ => return Err(format!("no parameter '{name}'")),
+        }
+        Ok(())
+    }
+
+    fn to_args(&self) -> Option<Vec<String>> {
+        Some(vec![format!("{}", self.freq), format!("{}", self.decay)])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prepared(freq: f32, decay: f32, sample_rate: f32) -> Pluck {
+        let mut string = Pluck::new(freq, decay);
+        string.prepare(0, 0, &ProcessContext { sample_rate });
+        string
+    }
+
+    #[test]
+    fn strings_ring_at_their_pitch_and_die_down() {
+        let mut string = Pluck::new(100.0, 0.5);
+        string.pluck(1_000.0);
+        assert_eq!(string.next_sample(), 0.0);
+
+        let mut string = prepared(100.0, 0.5, 1_000.0);
+        assert_eq!(string.next_sample(), 0.0);
+
+        string.pluck(1_000.0);
+        let first: Vec<f32> = (0..10).map(|_| string.next_sample()).collect();
+        assert!(first.iter().any(|&sample| sample != 0.0));
+        // One period later the burst comes round again, averaged and quieter.
+        let second: Vec<f32> = (0..10).map(|_| string.next_sample()).collect();
+        let energy = |samples: &[f32]| samples.iter().map(|s| s * s).sum::<f32>();
+        assert!(energy(&second) < energy(&first));
+
+        for _ in 0..1_000 {
+            string.next_sample();
+        }
+        assert!(string.next_sample().abs() < 0.01);
+    }
+
+    #[test]
+    fn rising_gates_pluck() {
+        let mut string = prepared(250.0, 1.0, 1_000.0);
+        let ctx = ProcessContext {
+            sample_rate: 1_000.0,
+        };
+        let mut output = [0.0; 4];
+        string.process(&[&[0.0; 4]], &mut output, &ctx);
+        assert_eq!(output, [0.0; 4]);
+        string.process(&[&[0.0, 1.0, 1.0, 1.0]], &mut output, &ctx);
+        assert_eq!(output[0], 0.0);
+        assert!(output[1..].iter().any(|&sample| sample != 0.0));
+    }
+
+    #[test]
+    fn high_strings_stay_in_tune() {
+        // 27.27 samples a period: the line alone could only ring at 26.5
+        // or 27.5 of them.
+        let sample_rate = 48_000.0;
+        let mut string = prepared(1_760.0, 4.0, sample_rate);
+        string.pluck(sample_rate);
+        // Once the highs have died away it's close to a sine, whose period
+        // is where it best matches itself.
+        let samples: Vec<f32> = (0..6_000).map(|_| string.next_sample()).collect();
+        let window = &samples[4_000..5_000];
+        let matching = |lag: usize| -> f32 {
+            window
+                .iter()
+                .zip(&samples[4_000 + lag..])
+                .map(|(a, b)| a * b)
+                .sum()
+        };
+        let lag = (20..35)
+            .max_by(|&a, &b| matching(a).total_cmp(&matching(b)))
+            .unwrap();
+        let (before, at, after) = (matching(lag - 1), matching(lag), matching(lag + 1));
+        let period = lag as f32 + 0.5 * (before - after) / (before - 2.0 * at + after);
+        let cents = 1_200.0 * math::ln(sample_rate / 1_760.0 / period) / core::f32::consts::LN_2;
+        assert!(cents.abs() < 2.0, "{cents} cents out");
+    }
+}
@@ -0,0 +1,355 @@
+//! Polyphony: copies of a voice graph sharing out the notes played into
+//! them, so each note rings on under the ones after it, and the MIDI notes
+//! they can be played from.
+
+use super::graph::{AudioNode, Graph, ProcessContext};
+use super::math;
+use super::osc::modulation;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Input level a gate has to rise past to start a note.
+const GATE_THRESHOLD: f32 = 0.5;
+
+/// The graph input a voice's gate comes in on: 1 while its note is held.
+pub const VOICE_GATE: usize = 0;
+/// The graph input a voice's note frequency comes in on, in Hz.
+pub const VOICE_FREQ: usize = 1;
+/// The graph input a voice's note comes in on, as a MIDI note number.
+pub const VOICE_NOTE: usize = 2;
+
+/// The frequency of MIDI note `pitch`, in Hz.
+fn midi_freq(pitch: u8) -> f32 {
+    440.0 * math::exp((pitch as f32 - 69.0) / 12.0 * ::core::f32::consts::LN_2)
+}
+
+struct Voice {
+    graph: Graph,
+    /// The block's gate, frequency and note, by graph input.
+    inputs: [Vec<f32>; 3],
+    output: Vec<f32>,
+    held: bool,
+    freq: f32,
+    note: f32,
+    /// The MIDI note it's playing, if it came from MIDI.
+    pitch: Option<u8>,
+    /// When its note started, or was let go once it was, counted in notes.
+    since: u64,
+}
+
+impl Voice {
+    /// Nothing came out of it last block, so a note can start on it without
+    /// cutting one off.
+    fn is_silent(&self) -> bool {
+        self.output.iter().all(|&sample| sample == 0.0)
+    }
+}
+
+/// Plays a note on every rising edge of the sum of its inputs, at the
+/// frequency wired into its `freq`, and holds it until the gate falls. Each
+/// note goes to a silent voice if there is one, or else the one let go
+/// longest ago, and only when every voice is held to the one held longest.
+/// The voice picks up the gate, frequency and note through its
+/// [`Input`](super::Input)s; their outputs are summed. Once MIDI notes have
+/// reached it through a [`MidiIn`], they play it instead of its gate.
+pub struct Poly {
+    voices: Vec<Voice>,
+    /// The voice the last note from the gate went to.
+    current: usize,
+    gate: bool,
+    /// Notes started and let go so far, to tell which voice came first.
+    events: u64,
+    from_midi: bool,
+}
+
+impl Poly {
+    pub fn new(voices: Vec<Graph>) -> Self {
+        let voices = voices
+            .into_iter()
+            .map(|graph| Voice {
+                graph,
+                inputs: [Vec::new(), Vec::new(), Vec::new()],
+                output: Vec::new(),
+                held: false,
+                freq: 0.0,
+                note: 0.0,
+                pitch: None,
+                since: 0,
+            })
+            .collect();
+        Self {
+            voices,
+            current: 0,
+            gate: false,
+            events: 0,
+            from_midi: false,
+        }
+    }
+
+    pub fn voices(&self) -> usize {
+        self.voices.len()
+    }
+
+    /// Hands a note at `freq` to the voice it's best taken from, returning
+    /// which one that is.
+    fn note_on(&mut self, freq: f32, note: f32, pitch: Option<u8>) -> usize {
+        let index = self
+            .voices
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, voice)| (voice.held, !voice.is_silent(), voice.since))
+            .map_or(0, |(index, _)| index);
+        self.events += 1;
+        let voice = &mut self.voices[index];
+        voice.held = true;
+        voice.freq = freq;
+        voice.note = note;
+        voice.pitch = pitch;
+        voice.since = self.events;
+        index
+    }
+
+    fn note_off(&mut self, index: usize) {
+        self.events += 1;
+        let voice = &mut self.voices[index];
+        voice.held = false;
+        voice.since = self.events;
+    }
+}
+
+/// The MIDI notes played into a patch, the last one played first: it
+/// outputs [`VOICE_GATE`], 1 while any is held, or the last one's
+/// [`VOICE_FREQ`] or [`VOICE_NOTE`], kept after it's let go so its release
+/// rings at its pitch. Wired into a [`Poly`], it plays each note on a voice
+/// of its own.
+pub struct MidiIn {
+    pub index: usize,
+    /// Notes held, in the order they were played.
+    held: [u8; 128],
+    count: usize,
+    last: Option<u8>,
+}
+
+impl MidiIn {
+    pub fn new(index: usize) -> Self {
+        Self {
+            index,
+            held: [0; 128],
+            count: 0,
+            last: None,
+        }
+    }
+
+    fn release(&mut self, pitch: u8) {
+        if let Some(at) = self.held[..self.count].iter().position(|&p| p == pitch) {
+            self.held.copy_within(at + 1..self.count, at);
+            self.count -= 1;
+        }
+    }
+}
+
+impl AudioNode for MidiIn {
+    fn process(&mut self, _inputs: &[&[f32]], output: &mut [f32], _ctx: &ProcessContext) {
+        let value = match self.index {
+            VOICE_GATE if self.count > 0 => 1.0,
+            VOICE_GATE => 0.0,
+            VOICE_FREQ => self.last.map_or(0.0, midi_freq),
+            _ => self.last.map_or(0.0, f32::from),
+        };
+        output.fill(value);
+    }
+
+    fn note(&mut self, pitch: u8, velocity: u8) {
+        if pitch > 127 {
+            return;
+        }
+        self.release(pitch);
+        if velocity > 0 {
+            self.held[self.count] = pitch;
+            self.count += 1;
+            self.last = Some(pitch);
+        } else if self.count > 0 && self.last == Some(pitch) {
+            self.last = Some(self.held[self.count - 1]);
+        }
+    }
+}
+
+impl AudioNode for Poly {
+    fn process(&mut self, inputs: &[&[f32]], output: &mut [f32], ctx: &ProcessContext) {
+        self.process_modulated(inputs, &[], output, ctx);
+    }
+
+    /// Sizes the voices' buffers for blocks of `frames`, unless they are,
+    /// and prepares their graphs.
+    fn prepare(&mut self, frames: usize, _wires: usize, ctx: &ProcessContext) {
+        for voice in &mut self.voices {
+            if voice.output.len() != frames {
+                voice.inputs = [vec![0.0; frames], vec![0.0; frames], vec![0.0; frames]];
+                voice.output = vec![0.0; frames];
+            }
+            voice.graph.prepare(frames, ctx);
+        }
+    }
+
+    fn note(&mut self, pitch: u8, velocity: u8) {
+        self.from_midi = true;
+        // Played again, the note moves to a voice of its own.
+        if let Some(index) = self
+            .voices
+            .iter()
+            .position(|voice| voice.held && voice.pitch == Some(pitch))
+        {
+            self.note_off(index);
+        }
+        if velocity > 0 {
+            self.note_on(midi_freq(pitch), pitch as f32, Some(pitch));
+        }
+    }
+
+    fn param_inputs(&self) -> &'static [&'static str] {
+        &["freq"]
+    }
+
+    fn process_modulated(
+        &mut self,
+        inputs: &[&[f32]],
+        params: &[(usize, &[f32])],
+        output: &mut [f32],
+        ctx: &ProcessContext,
+    ) {
+        output.fill(0.0);
+        // Unprepared for blocks this size, it stays silent rather than
+        // allocating.
+        if self.voices.first().map(|voice| voice.output.len()) != Some(output.len()) {
+            return;
+        }
+
+        for i in 0..output.len() {
+            let gate: f32 = inputs.iter().filter_map(|input| input.get(i)).sum();
+            let open = gate >= GATE_THRESHOLD && !self.from_midi;
+            if open && !self.gate {
+                let freq = modulation(params, i);
+                let note = if freq > 0.0 {
+                    69.0 + 12.0 * math::ln(freq / 440.0) / ::core::f32::consts::LN_2
+                } else {
+                    0.0
+                };
+                self.current = self.note_on(freq, note, None);
+            } else if !open && self.gate {
+                self.note_off(self.current);
+            }
+            self.gate = open;
+
+            for voice in &mut self.voices {
+                voice.inputs[VOICE_GATE][i] = if voice.held { 1.0 } else { 0.0 };
+                voice.inputs[VOICE_FREQ][i] = voice.freq;
+                voice.inputs[VOICE_NOTE][i] = voice.note;
+            }
+        }
+
+        for voice in &mut self.voices {
+            let [gate, freq, note] = &voice.inputs;
+            voice
+                .graph
+                .process_with(&[gate, freq, note], &mut voice.output, ctx);
+            for (out, sample) in output.iter_mut().zip(&voice.output) {
+                *out += sample;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{Input, Link, Mul, Output};
+    use alloc::boxed::Box;
+
+    #[test]
+    fn notes_take_turns_at_the_voices() {
+        // Voices playing their frequency while their gate is held.
+        let voice = || {
+            let nodes: Vec<Box<dyn AudioNode>> = vec![
+                Box::new(Input { index: VOICE_FREQ }),
+                Box::new(Input { index: VOICE_GATE }),
+                Box::new(Mul),
+                Box::new(Output),
+            ];
+            let links = [Link::new(0, 2), Link::new(1, 2), Link::new(2, 3)];
+            Graph::new(nodes, &links).unwrap()
+        };
+        let mut poly = Poly::new(vec![voice(), voice()]);
+        let ctx = ProcessContext { sample_rate: 8.0 };
+        let mut output = [0.0; 6];
+        poly.prepare(output.len(), 2, &ctx);
+        poly.process_modulated(
+            &[&[1.0, 0.0, 1.0, 1.0, 0.0, 1.0]],
+            &[(0, &[440.0, 0.0, 220.0, 330.0, 0.0, 880.0])],
+            &mut output,
+            &ctx,
+        );
+        // Frequencies are taken as the notes start, and the third note goes
+        // back to the first voice, let go longest ago.
+        assert_eq!(output, [440.0, 0.0, 220.0, 220.0, 0.0, 880.0]);
+        assert!((poly.voices[0].note - 81.0).abs() < 1e-4);
+        assert_eq!(poly.voices[1].freq, 220.0);
+    }
+
+    #[test]
+    fn midi_notes_take_free_voices_before_held_ones() {
+        // Voices ringing on at their note number after they're let go.
+        let voice = || {
+            let nodes: Vec<Box<dyn AudioNode>> =
+                vec![Box::new(Input { index: VOICE_NOTE }), Box::new(Output)];
+            Graph::new(nodes, &[Link::new(0, 1)]).unwrap()
+        };
+        let mut poly = Poly::new(vec![voice(), voice(), voice()]);
+        let ctx = ProcessContext { sample_rate: 8.0 };
+        let mut output = [0.0; 2];
+        poly.prepare(output.len(), 1, &ctx);
+        let pitches = |poly: &Poly| -> Vec<Option<u8>> {
+            poly.voices.iter().map(|voice| voice.pitch).collect()
+        };
+
+        poly.note(60, 100);
+        poly.process(&[], &mut output, &ctx);
+        assert_eq!(output, [60.0; 2]);
+        // The silent voices go first, then the one let go.
+        poly.note(64, 100);
+        poly.note(67, 100);
+        poly.note(64, 0);
+        poly.process(&[], &mut output, &ctx);
+        poly.note(72, 100);
+        assert_eq!(pitches(&poly), [Some(60), Some(72), Some(67)]);
+        // With every voice held, the one held longest.
+        poly.note(76, 100);
+        assert_eq!(pitches(&poly), [Some(76), Some(72), Some(67)]);
+        // Gates are ignored once notes come from MIDI.
+        poly.process(&[&[1.0, 0.0]], &mut output, &ctx);
+        assert_eq!(output, [(76 + 72 + 67) as f32; 2]);
+    }
+
+    #[test]
+    fn midi_in_follows_the_last_note_held() {
+        let ctx = ProcessContext { sample_rate: 8.0 };
+        let mut output = [0.0; 1];
+        let mut read = |node: &mut MidiIn| {
+            node.process(&[], &mut output, &ctx);
+            output[0]
+        };
+        let mut gate = MidiIn::new(VOICE_GATE);
+        let mut note = MidiIn::new(VOICE_NOTE);
+        for (pitch, velocity) in [(60, 100), (64, 100), (64, 0)] {
+            gate.note(pitch, velocity);
+            note.note(pitch, velocity);
+        }
+        assert_eq!((read(&mut gate), read(&mut note)), (1.0, 60.0));
+        gate.note(60, 0);
+        note.note(60, 0);
+        assert_eq!((read(&mut gate), read(&mut note)), (0.0, 60.0));
+
+        let mut freq = MidiIn::new(VOICE_FREQ);
+        freq.note(69, 100);
+        assert_eq!(read(&mut freq), 440.0);
+    }
+}
@@ -0,0 +1,104 @@
+//! Snapping pitch signals to a scale, so random or swept sources play in
+//! key.
+
+use super::graph::{AudioNode, ProcessContext};
+use super::math;
+
+/// Scales by name, as semitones above the root; the same ones pattern
+/// scripts' `scale()` knows.
+pub const SCALES: [(&str, &[u8]); 13] = [
+    ("major", &[0, 2, 4, 5, 7, 9, 11]),
+    ("minor", &[0, 2, 3, 5, 7, 8, 10]),
+    ("harmonic_minor", &[0, 2, 3, 5, 7, 8, 11]),
+    ("melodic_minor", &[0, 2, 3, 5, 7, 9, 11]),
+    ("dorian", &[0, 2, 3, 5, 7, 9, 10]),
+    ("phrygian", &[0, 1, 3, 5, 7, 8, 10]),
+    ("lydian", &[0, 2, 4, 6, 7, 9, 11]),
+    ("mixolydian", &[0, 2, 4, 5, 7, 9, 10]),
+    ("locrian", &[0, 1, 3, 5, 6, 8, 10]),
+    ("major_pentatonic", &[0, 2, 4, 7, 9]),
+    ("minor_pentatonic", &[0, 3, 5, 7, 10]),
+    ("blues", &[0, 3, 5, 6, 7, 10]),
+    ("chromatic", &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11]),
+];
+
+/// The intervals of the scale called `name`.
+pub fn scale(name: &str) -> Option<&'static [u8]> {
+    SCALES
+        .iter()
+        .find(|(scale, _)| *scale == name)
+        .map(|&(_, intervals)| intervals)
+}
+
+/// Snaps the frequency summed from its inputs to the nearest note of a scale
+/// starting on pitch class `root` (0 for C up to 11 for B). Inputs at or
+/// below 0 Hz come out as 0.
+#[derive(Debug, Clone)]
+pub struct Quantizer {
+    /// Which of the twelve semitones above C are in the scale.
+    notes: [bool; 12],
+}
+
+impl Quantizer {
+    /// A scale from its `intervals` above `root`. Without any, every semitone
+    /// is in it.
+    pub fn new(intervals: &[u8], root: u8) -> Self {
+        let mut notes = [intervals.is_empty(); 12];
+        for &interval in intervals {
+            notes[(root as usize + interval as usize) % 12] = true;
+        }
+        Self { notes }
+    }
+
+    /// The MIDI note of the scale nearest to fractional note `pitch`.
+    pub fn snap(&self, pitch: f32) -> i32 {
+        let nearest = math::round(pitch) as i32;
+        (0..=6)
+            .flat_map(|distance| [nearest - distance, nearest + distance])
+            .filter(|&note| self.notes[note.rem_euclid(12) as usize])
+            .min_by(|a, b| {
+                let distance = |note: &i32| math::abs(*note as f32 - pitch);
+                distance(a).total_cmp(&distance(b))
+            })
+            .unwrap_or(nearest)
+    }
+
+    /// `freq` snapped to the scale.
+    pub fn quantize(&self, freq: f32) -> f32 {
+        if freq <= 0.0 {
+            return 0.0;
+        }
+        let pitch = 69.0 + 12.0 * math::ln(freq / 440.0) / ::core::f32::consts::LN_2;
+        let note = self.snap(pitch);
+        440.0 * math::exp((note - 69) as f32 / 12.0 * ::core::f32::consts::LN_2)
+    }
+}
+
+impl AudioNode for Quantizer {
+    fn process(&mut self, inputs: &[&[f32]], output: &mut [f32], _ctx: &ProcessContext) {
+        for (i, out) in output.iter_mut().enumerate() {
+            let freq = inputs.iter().filter_map(|input| input.get(i)).sum();
+            *out = self.quantize(freq);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pitches_snap_to_the_nearest_note_of_the_scale() {
+        // A minor pentatonic: A C D E G.
+        let quantizer = Quantizer::new(scale("minor_pentatonic").unwrap(), 9);
+        assert_eq!(quantizer.snap(69.0), 69);
+        assert_eq!(quantizer.snap(70.4), 69);
+        assert_eq!(quantizer.snap(70.6), 72);
+        assert_eq!(quantizer.snap(66.0), 67);
+
+        assert!((quantizer.quantize(450.0) - 440.0).abs() < 0.01);
+        assert!((quantizer.quantize(512.0) - 523.25).abs() < 0.01);
+        assert_eq!(quantizer.quantize(-3.0), 0.0);
+        assert!(scale("klingon").is_none());
+    }
+}
@@ -0,0 +1,205 @@
+//! Randomness for generative patches: a small seeded generator, a random
+//! control source and a sample-and-hold.
+
+use super::graph::{AudioNode, ProcessContext};
+use super::osc::modulation;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Input level a trigger has to rise past to count.
+const TRIGGER_THRESHOLD: f32 = 0.5;
+
+/// A xorshift generator: fast and plenty random for sound, and the same
+/// sequence every time for the same seed.
+#[derive(Debug, Clone)]
+pub struct Rng(u32);
+
+impl Rng {
+    pub fn new(seed: u32) -> Self {
+        // xorshift never leaves 0.
+        Self(if seed == 0 { 0x9E37_79B9 } else { seed })
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0
+    }
+
+    /// A value from -1 to 1.
+    pub fn next_bipolar(&mut self) -> f32 {
+        self.next_u32() as f32 / u32::MAX as f32 * 2.0 - 1.0
+    }
+
+    /// A value from `min` to `max`.
+    pub fn next_in(&mut self, min: f32, max: f32) -> f32 {
+        min + (self.next_bipolar() + 1.0) * 0.5 * (max - min)
+    }
+}
+
+/// Picks a new value from `min` to `max` `rate` times a second, and either
+/// jumps to it or glides there over the following period.
+#[derive(Debug, Clone)]
+pub struct Random {
+    pub rate: f32,
+    pub min: f32,
+    pub max: f32,
+    pub smooth: bool,
+    /// What `rng` started from, so the node can be rebuilt to play the same.
+    seed: u32,
+    rng: Rng,
+    /// How far through the current period, from 0 to 1.
+    phase: f32,
+    from: f32,
+    to: f32,
+}
+
+impl Random {
+    pub fn new(rate: f32, min: f32, max: f32, smooth: bool, seed: u32) -> Self {
+        let mut rng = Rng::new(seed);
+        let from = rng.next_in(min, max);
+        let to = rng.next_in(min, max);
+        Self {
+            rate,
+            min,
+            max,
+            smooth,
+            seed,
+            rng,
+            phase: 0.0,
+            from,
+            to,
+        }
+    }
+
+    /// The value for the next sample.
+    pub fn next(&mut self, sample_rate: f32) -> f32 {
+        let value = if self.smooth {
+            self.from + (self.to - self.from) * self.phase
+        } else {
+            self.from
+        };
+        self.phase += self.rate / sample_rate;
+        while self.phase >= 1.0 {
+            self.phase -= 1.0;
+            self.from = self.to;
+            self.to = self.rng.next_in(self.min, self.max);
+        }
+        value
+    }
+}
+
+impl AudioNode for Random {
+    fn process(&mut self, _inputs: &[&[f32]], output: &mut [f32], ctx: &ProcessContext) {
+        for out in output.iter_mut() {
+            *out = self.next(ctx.sample_rate);
+        }
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> Result<(), String> {
+        match name {
+            "rate" => self.rate = value,
+            "min" => self.min = value,
+            "max" => self.max = value,
+            _ => return Err(format!("no parameter '{name}'")),
+        }
+        Ok(())
+    }
+
+    fn to_args(&self) -> Option<Vec<String>> {
+        let mode = if self.smooth { "Smooth" } else { "Step" };
+        Some(vec![
+            mode.into(),
+            format!("{}", self.rate),
+            format!("{}", self.min),
+            format!("{}", self.max),
+            format!("{}", self.seed),
+        ])
+    }
+}
+
+/// Holds the sum of its inputs from each rising edge wired into its
+/// `trigger` until the next, e.g. noise sampled on a clock's pulses.
+#[derive(Debug, Clone, Default)]
+pub struct SampleHold {
+    held: f32,
+    trigger: bool,
+}
+
+impl SampleHold {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl AudioNode for SampleHold {
+    fn process(&mut self, _inputs: &[&[f32]], output: &mut [f32], _ctx: &ProcessContext) {
+        output.fill(self.held);
+    }
+
+    fn param_inputs(&self) -> &'static [&'static str] {
+        &["trigger"]
+    }
+
+    fn process_modulated(
+        &mut self,
+        inputs: &[&[f32]],
+        params: &[(usize, &[f32])],
+        output: &mut [f32],
+        _ctx: &ProcessContext,
+    ) {
+        for (i, out) in output.iter_mut().enumerate() {
+            let trigger = modulation(params, i) >= TRIGGER_THRESHOLD;
+            if trigger && !self.trigger {
+                self.held = inputs.iter().filter_map(|input| input.get(i)).sum();
+            }
+            self.trigger = trigger;
+            *out = self.held;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn random_sources_repeat_for_a_seed_and_stay_in_range() {
+        let values = |smooth| {
+            let mut random = Random::new(250.0, 2.0, 4.0, smooth, 7);
+            (0..16)
+                .map(|_| random.next(1_000.0))
+                .collect::<alloc::vec::Vec<_>>()
+        };
+        let stepped = values(false);
+        assert_eq!(stepped, values(false));
+        assert!(stepped.iter().all(|&value| (2.0..=4.0).contains(&value)));
+        // A new value every 4 samples.
+        assert_eq!(stepped[0], stepped[3]);
+        assert_ne!(stepped[3], stepped[4]);
+
+        let smooth = values(true);
+        assert_eq!(smooth[0], stepped[0]);
+        assert_eq!(smooth[4], stepped[4]);
+        assert!((smooth[2] - (stepped[0] + stepped[4]) / 2.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn samples_are_held_between_triggers() {
+        let ctx = ProcessContext { sample_rate: 8.0 };
+        let mut hold = SampleHold::new();
+        let mut output = [0.0; 4];
+        hold.process_modulated(
+            &[&[1.0, 2.0, 3.0, 4.0]],
+            &[(0, &[1.0, 1.0, 0.0, 1.0])],
+            &mut output,
+            &ctx,
+        );
+        assert_eq!(output, [1.0, 1.0, 1.0, 4.0]);
+        hold.process(&[&[5.0; 4]], &mut output, &ctx);
+        assert_eq!(output, [4.0; 4]);
+    }
+}
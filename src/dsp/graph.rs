@@ -0,0 +1,354 @@
+use super::{AudioNode, ProcessContext, strip_comment};
+use crate::AurioError;
+use crate::core::graph::{Graph, Inputs, Link, prepare_in_order, process_in_order, routes, sort};
+use std::collections::HashMap;
+use std::fmt::Write;
+
+pub struct Node {
+    pub id: u32,
+    /// Registered type name, e.g. `Osc`.
+    pub kind: String,
+    /// The arguments after the type, as written.
+    pub args: Vec<String>,
+    /// The lines between the braces of a `Poly { ... }`.
+    pub body: Option<Vec<String>>,
+    /// Left out of the graph for now (`[2]! Gain 0.5`): its input passes
+    /// straight through, and a node without one goes silent.
+    pub bypass: bool,
+    /// How many times the sample rate the node runs at (`[2]x4 Osc ...`), 1
+    /// unless it's oversampled. `inner` is already wrapped for it.
+    pub oversample: u32,
+    /// A parameter was set since the node was built, so [`AudioGraph::to_source`]
+    /// asks the node for its arguments instead of using `args`.
+    pub params_set: bool,
+    pub inner: Box<dyn AudioNode>,
+}
+
+pub struct Wire {
+    pub from_node_id: u32,
+    pub from_output_idx: usize,
+    pub to_node_id: u32,
+    /// The parameter input the wire drives (`1 -> 0.freq`), or `None` for
+    /// the node's signal input.
+    pub to_param: Option<String>,
+    /// Scales what the wire carries (`0 -> 2 * 0.5`), for nodes that mix
+    /// their inputs.
+    pub weight: f32,
+}
+
+/// Frames a graph processes at a time, however many each call asks for, so
+/// it plays the same with any device buffer size.
+pub const BLOCK_SIZE: usize = 64;
+
+pub struct AudioGraph {
+    pub nodes: Vec<Node>,
+    pub wires: Vec<Wire>,
+    pub is_sorted: bool,
+    pub buffers: Vec<Vec<f32>>,
+    /// Positions in `nodes` of each node's inputs and the ports they're
+    /// wired into, once sorted.
+    routes: Vec<Vec<(usize, usize)>>,
+    /// Room for the wires into the node with the most, sized with `routes`.
+    inputs: Inputs,
+    /// The last block processed, played out over as many calls as it takes.
+    block: Vec<f32>,
+    /// How much of `block` has been played.
+    played: usize,
+    /// The node played instead of the output, and for how many more seconds
+    /// if it's only for a while.
+    probe: Option<(u32, Option<f32>)>,
+}
+
+impl AudioGraph {
+    pub fn new(nodes: Vec<Node>, wires: Vec<Wire>) -> Self {
+        Self {
+            nodes,
+            wires,
+            is_sorted: false,
+            buffers: Vec::new(),
+            routes: Vec::new(),
+            inputs: Inputs::default(),
+            block: vec![0.0; BLOCK_SIZE],
+            played: BLOCK_SIZE,
+            probe: None,
+        }
+    }
+
+    /// Plays node `node_id`'s output instead of the graph's, clipped to -1..1
+    /// since it may be a control signal, for `seconds` or until probed
+    /// again; `None` plays the output again. The probe also ends if the node
+    /// is patched out.
+    pub fn probe(&mut self, node_id: Option<u32>, seconds: Option<f32>) -> Result<(), String> {
+        if let Some(id) = node_id
+            && !self.nodes.iter().any(|n| n.id == id)
+        {
+            return Err(format!("no node {id}"));
+        }
+        self.probe = node_id.map(|id| (id, seconds));
+        Ok(())
+    }
+
+    /// The node being probed, if any.
+    pub fn probed(&self) -> Option<u32> {
+        self.probe.map(|(id, _)| id)
+    }
+
+    /// Sets a parameter of node `node_id` without rebuilding the graph.
+    pub fn set_param(&mut self, node_id: u32, name: &str, value: f32) -> Result<(), String> {
+        let node = self
+            .nodes
+            .iter_mut()
+            .find(|n| n.id == node_id)
+            .ok_or_else(|| format!("no node {node_id}"))?;
+        node.inner.set_param(name, value)?;
+        node.params_set = true;
+        Ok(())
+    }
+
+    /// Plays MIDI note `pitch` into the graph, let go at velocity 0: every
+    /// `MidiIn` takes it and hands it on to the nodes it's wired into, such
+    /// as a `Poly`.
+    pub fn note(&mut self, pitch: u8, velocity: u8) {
+        for i in 0..self.nodes.len() {
+            if self.nodes[i].kind != "MidiIn" {
+                continue;
+            }
+            self.nodes[i].inner.note(pitch, velocity);
+            let id = self.nodes[i].id;
+            for wire in self.wires.iter().filter(|wire| wire.from_node_id == id) {
+                if let Some(node) = self.nodes.iter_mut().find(|n| n.id == wire.to_node_id) {
+                    node.inner.note(pitch, velocity);
+                }
+            }
+        }
+    }
+
+    /// Bypasses node `node_id`, or brings it back, without rebuilding the
+    /// graph.
+    pub fn set_bypass(&mut self, node_id: u32, bypass: bool) -> Result<(), String> {
+        let node = self
+            .nodes
+            .iter_mut()
+            .find(|n| n.id == node_id)
+            .ok_or_else(|| format!("no node {node_id}"))?;
+        node.bypass = bypass;
+        Ok(())
+    }
+
+    /// The graph written out as a `.au` patch, nodes by id and then the
+    /// wires, which parses back into the same graph.
+    pub fn to_source(&self) -> String {
+        let mut nodes: Vec<&Node> = self.nodes.iter().collect();
+        nodes.sort_by_key(|node| node.id);
+
+        // Writing to a `String` can't fail.
+        let mut source = String::new();
+        for node in nodes {
+            let bypass = if node.bypass { "!" } else { "" };
+            let _ = write!(source, "[{}]{}", node.id, bypass);
+            if node.oversample > 1 {
+                let _ = write!(source, "x{}", node.oversample);
+            }
+            let _ = write!(source, " {}", node.kind);
+            let args = node.params_set.then(|| node.inner.to_args()).flatten();
+            for arg in args.as_ref().unwrap_or(&node.args) {
+                let _ = write!(source, " {arg}");
+            }
+            if let Some(body) = &node.body {
+                source.push_str(" {\n");
+                for line in body.iter().map(|line| strip_comment(line).trim()) {
+                    if !line.is_empty() {
+                        let _ = writeln!(source, "    {line}");
+                    }
+                }
+                source.push('}');
+            }
+            source.push('\n');
+        }
+        if !self.wires.is_empty() {
+            source.push('\n');
+        }
+        for wire in &self.wires {
+            let _ = write!(source, "{}->{}", wire.from_node_id, wire.to_node_id);
+            if let Some(param) = &wire.to_param {
+                let _ = write!(source, ".{param}");
+            }
+            if wire.weight != 1.0 {
+                let _ = write!(source, " * {}", wire.weight);
+            }
+            source.push('\n');
+        }
+        source
+    }
+
+    /// Starts nodes easing between values, such as gains, from where the
+    /// nodes with the same id and type in `previous` had got to, so
+    /// reloading a patch doesn't click.
+    pub fn inherit_ramps(&mut self, previous: &AudioGraph) {
+        for node in &mut self.nodes {
+            let old = previous
+                .nodes
+                .iter()
+                .find(|old| old.id == node.id && old.kind == node.kind);
+            if let Some(value) = old.and_then(|old| old.inner.ramp_state()) {
+                node.inner.ramp_from(value);
+            }
+        }
+    }
+
+    /// Sizes the graph's buffers and prepares its nodes for blocks at
+    /// `ctx`'s sample rate, so the first [`process`](Self::process) doesn't
+    /// allocate: for a graph about to be handed to the audio thread.
+    /// Patching it undoes this.
+    pub fn prepare(&mut self, ctx: &ProcessContext) {
+        prepare_in_order(
+            self.nodes.iter_mut().map(|node| &mut node.inner),
+            &self.routes,
+            &mut self.buffers,
+            BLOCK_SIZE,
+            ctx,
+        );
+    }
+
+    /// Fills `output`, processing [`BLOCK_SIZE`] frames whenever the last
+    /// block runs out. Parameter changes and patches apply from the next
+    /// block.
+    pub fn process(&mut self, output: &mut [f32], ctx: &ProcessContext) {
+        if !self.is_sorted {
+            panic!("Graph must be sorted before being used");
+        }
+        let mut written = 0;
+        while written < output.len() {
+            if self.played == BLOCK_SIZE {
+                // A graph without an `Out` node leaves it silent.
+                self.block.fill(0.0);
+                process_in_order(
+                    self.nodes
+                        .iter_mut()
+                        .map(|node| (&mut node.inner, node.bypass)),
+                    &self.routes,
+                    &mut self.buffers,
+                    &mut self.inputs,
+                    &[],
+                    &mut self.block,
+                    ctx,
+                );
+                self.play_probe(ctx);
+                self.played = 0;
+            }
+            let frames = (BLOCK_SIZE - self.played).min(output.len() - written);
+            output[written..written + frames]
+                .copy_from_slice(&self.block[self.played..self.played + frames]);
+            self.played += frames;
+            written += frames;
+        }
+    }
+
+    /// Copies the probed node's block over the output's, and ends the probe
+    /// once its time is up or the node is gone.
+    fn play_probe(&mut self, ctx: &ProcessContext) {
+        let Some((id, seconds)) = &mut self.probe else {
+            return;
+        };
+        let Some(index) = self.nodes.iter().position(|n| n.id == *id) else {
+            self.probe = None;
+            return;
+        };
+        for (out, sample) in self.block.iter_mut().zip(&self.buffers[index]) {
+            *out = sample.clamp(-1.0, 1.0);
+        }
+        if let Some(seconds) = seconds {
+            *seconds -= BLOCK_SIZE as f32 / ctx.sample_rate;
+            if *seconds <= 0.0 {
+                self.probe = None;
+            }
+        }
+    }
+
+    /// Puts the nodes in processing order and works out where each one's
+    /// inputs come from.
+    pub(super) fn sort(&mut self) -> Result<(), AurioError> {
+        let links = links(&mut self.nodes, &self.wires)?;
+        let wires: Vec<(usize, usize)> = links.iter().map(|link| (link.from, link.to)).collect();
+        let order = sort(self.nodes.len(), &wires).map_err(|e| AurioError::Graph(e.to_string()))?;
+        self.routes = routes(&order, &links);
+        self.inputs = Inputs::for_routes(&self.routes);
+        // The wires into nodes may have changed, so the next block prepares
+        // them again.
+        self.buffers.clear();
+
+        let mut slots: Vec<Option<Node>> = self.nodes.drain(..).map(Some).collect();
+        self.nodes = order.iter().filter_map(|&i| slots[i].take()).collect();
+        self.is_sorted = true;
+        Ok(())
+    }
+}
+
+/// Builds a [`Graph`] of `nodes` and the `wires` between them, such as one
+/// voice of a `Poly`.
+pub(super) fn voice_graph(mut nodes: Vec<Node>, wires: &[Wire]) -> Result<Graph, AurioError> {
+    let links = links(&mut nodes, wires)?;
+    let bypassed: Vec<bool> = nodes.iter().map(|node| node.bypass).collect();
+    let inner = nodes.into_iter().map(|node| node.inner).collect();
+    let mut graph = Graph::new(inner, &links).map_err(|e| AurioError::Graph(e.to_string()))?;
+    for (index, bypass) in bypassed.into_iter().enumerate() {
+        graph.set_bypass(index, bypass);
+    }
+    Ok(graph)
+}
+
+/// Resolves `wires` to links between indices into `nodes`, and hands each
+/// node the weights of the wires into it.
+fn links(nodes: &mut [Node], wires: &[Wire]) -> Result<Vec<Link>, AurioError> {
+    let index: HashMap<u32, usize> = nodes
+        .iter()
+        .enumerate()
+        .map(|(i, node)| (node.id, i))
+        .collect();
+    let links = wires
+        .iter()
+        .map(|wire| {
+            let find = |id| {
+                index
+                    .get(&id)
+                    .copied()
+                    .ok_or_else(|| AurioError::Graph(format!("Couldn't find node id {}", id)))
+            };
+            let (from, to) = (find(wire.from_node_id)?, find(wire.to_node_id)?);
+            let port = match &wire.to_param {
+                None => 0,
+                Some(_) if wire.weight != 1.0 => {
+                    return Err(AurioError::Graph(format!(
+                        "wire into node {} can't weight a parameter",
+                        wire.to_node_id
+                    )));
+                }
+                Some(param) => param_port(&nodes[to], param)?,
+            };
+            Ok(Link { from, to, port })
+        })
+        .collect::<Result<Vec<_>, AurioError>>()?;
+
+    for (to, node) in nodes.iter_mut().enumerate() {
+        let weights: Vec<f32> = wires
+            .iter()
+            .zip(&links)
+            .filter(|(_, link)| link.to == to && link.port == 0)
+            .map(|(wire, _)| wire.weight)
+            .collect();
+        node.inner
+            .set_input_weights(&weights)
+            .map_err(|e| AurioError::Graph(format!("node {}: {}", node.id, e)))?;
+    }
+    Ok(links)
+}
+
+/// The port a wire into `node`'s parameter `param` uses.
+fn param_port(node: &Node, param: &str) -> Result<usize, AurioError> {
+    node.inner
+        .param_inputs()
+        .iter()
+        .position(|&name| name == param)
+        .map(|i| i + 1)
+        .ok_or_else(|| AurioError::Graph(format!("node {} has no input '{}'", node.id, param)))
+}
@@ -0,0 +1,116 @@
+//! Block-based DSP graphs, written as `.au` patches.
+//!
+//! A patch lists nodes (`[id] Type args...`) and the wires between them
+//! (`0->2, 1->2`). Node types come from a [`NodeRegistry`]: the built-ins are
+//! registered by default and other crates can add their own [`AudioNode`]s.
+//! `[id] Poly <voices> { ... }` wraps a patch of its own, copied for each
+//! voice, and plays it from a `MidiIn` wired into it or from gates and
+//! frequencies. `[id]x2` or `[id]x4` oversamples a node, for less aliasing from
+//! FM and other nonlinear nodes.
+//!
+//! `#` starts a comment at the start of a line or after whitespace. Anywhere
+//! else it's part of a word, for sharps such as `C#4`, so `Gain 0.5#note`
+//! needs a space before its comment.
+
+mod graph;
+mod nodes;
+mod parser;
+pub mod resample;
+
+pub use crate::core::{AudioNode, ProcessContext};
+pub use graph::{AudioGraph, BLOCK_SIZE, Node, Wire};
+pub use nodes::{
+    Add, Bipolar, Const, EnvelopeFollower, Gain, Gate, Mul, Oscillator, Output, Scale, Unipolar,
+    Wave,
+};
+pub(crate) use parser::strip_comment;
+pub use parser::{load_file, parse_file, parse_with, patch_graph, patch_graph_with};
+
+use std::collections::HashMap;
+
+/// Builds a node from the arguments following its type in a patch.
+pub type NodeConstructor = Box<dyn Fn(&[&str]) -> Result<Box<dyn AudioNode>, String> + Send + Sync>;
+
+/// Node types the parser knows, by the name used in patches.
+pub struct NodeRegistry {
+    constructors: HashMap<String, NodeConstructor>,
+    /// The least an oversampled node runs at, whatever its patch asks for.
+    min_oversampling: u32,
+}
+
+impl NodeRegistry {
+    /// A registry without any node types, not even the built-ins.
+    pub fn empty() -> Self {
+        Self {
+            constructors: HashMap::new(),
+            min_oversampling: 1,
+        }
+    }
+
+    /// Runs every node marked for oversampling (`[2]x2 ...`) at least
+    /// `factor` times the sample rate, 2 or 4: a quality setting for offline
+    /// renders, which can afford more than playing live. Nodes that aren't
+    /// marked stay as they are.
+    pub fn set_min_oversampling(&mut self, factor: u32) {
+        self.min_oversampling = factor;
+    }
+
+    /// `node`, built for a patch asking for `factor` times the sample rate.
+    fn oversample(&self, node: Box<dyn AudioNode>, factor: u32) -> Box<dyn AudioNode> {
+        if factor > 1 {
+            crate::core::oversample(node, factor.max(self.min_oversampling))
+        } else {
+            node
+        }
+    }
+
+    /// Adds `name`, replacing any node type already registered under it.
+    pub fn register<F>(&mut self, name: &str, constructor: F)
+    where
+        F: Fn(&[&str]) -> Result<Box<dyn AudioNode>, String> + Send + Sync + 'static,
+    {
+        self.constructors
+            .insert(name.to_string(), Box::new(constructor));
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.constructors.contains_key(name)
+    }
+
+    pub fn create(&self, name: &str, args: &[&str]) -> Result<Box<dyn AudioNode>, String> {
+        let constructor = self
+            .constructors
+            .get(name)
+            .ok_or_else(|| format!("unknown node type '{name}'"))?;
+        constructor(args)
+    }
+}
+
+impl Default for NodeRegistry {
+    /// The built-in nodes, registered below under the names patches use.
+    /// `Poly { ... }` isn't one of them: the parser builds it itself.
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        registry.register("Osc", nodes::Oscillator::from_args);
+        registry.register("Pluck", nodes::Pluck::from_args);
+        registry.register("Gain", nodes::Gain::from_args);
+        registry.register("Out", |_| Ok(Box::new(nodes::Output)));
+        registry.register("Const", nodes::Const::from_args);
+        registry.register("Add", nodes::Add::from_args);
+        registry.register("Mul", |_| Ok(Box::new(nodes::Mul)));
+        registry.register("Scale", nodes::Scale::from_args);
+        registry.register("Uni", |_| Ok(Box::new(nodes::Unipolar)));
+        registry.register("Bi", |_| Ok(Box::new(nodes::Bipolar)));
+        registry.register("Slew", nodes::Slew::from_args);
+        registry.register("Quant", nodes::Quantizer::from_args);
+        registry.register("Follow", nodes::EnvelopeFollower::from_args);
+        registry.register("Gate", nodes::Gate::from_args);
+        registry.register("Clock", nodes::Clock::from_args);
+        registry.register("Seq", nodes::Seq::from_args);
+        registry.register("Random", nodes::Random::from_args);
+        registry.register("SampleHold", |_| Ok(Box::new(nodes::SampleHold::new())));
+        registry.register("In", nodes::Input::from_args);
+        registry.register("MidiIn", nodes::MidiIn::from_args);
+        registry
+    }
+}
@@ -0,0 +1,252 @@
+use super::AudioNode;
+use crate::core::poly::{VOICE_FREQ, VOICE_GATE, VOICE_NOTE};
+pub use crate::core::{
+    Add, Bipolar, Clock, Const, EnvelopeFollower, Gain, Gate, Input, MidiIn, Mul, Oscillator,
+    Output, Pluck, Poly, Quantizer, Random, SampleHold, Scale, Seq, Slew, Unipolar, Wave,
+};
+use std::hash::{BuildHasher, RandomState};
+
+impl Oscillator {
+    /// `Osc <Sine|Square|Saw> <frequency>`
+    pub(super) fn from_args(args: &[&str]) -> Result<Box<dyn AudioNode>, String> {
+        let wave = match *args.first().ok_or("missing wave type")? {
+            "Sine" => Wave::Sine,
+            "Square" => Wave::Square,
+            "Saw" => Wave::Saw,
+            other => return Err(format!("unknown wave '{other}'")),
+        };
+        let freq: f32 = args
+            .get(1)
+            .ok_or("missing frequency")?
+            .parse()
+            .map_err(|_| "invalid frequency")?;
+        Ok(Box::new(Self::new(wave, freq)))
+    }
+}
+
+impl Gain {
+    /// `Gain <factor>`, or `Gain <decibels>dB`
+    pub(super) fn from_args(args: &[&str]) -> Result<Box<dyn AudioNode>, String> {
+        let arg = args.first().ok_or("missing gain")?.to_ascii_lowercase();
+        let value = match arg.strip_suffix("db") {
+            Some(db) => {
+                let db: f32 = db.parse().map_err(|_| "invalid gain")?;
+                10f32.powf(db / 20.0)
+            }
+            None => arg.parse().map_err(|_| "invalid gain")?,
+        };
+        Ok(Box::new(Self::new(value)))
+    }
+}
+
+/// The voice input named `gate`, `freq` or `note`.
+fn voice_input(name: &str) -> Result<usize, String> {
+    match name {
+        "gate" => Ok(VOICE_GATE),
+        "freq" => Ok(VOICE_FREQ),
+        "note" => Ok(VOICE_NOTE),
+        other => Err(format!("unknown voice input '{other}'")),
+    }
+}
+
+impl Input {
+    /// `In <gate|freq|note>`, inside a `Poly` body
+    pub(super) fn from_args(args: &[&str]) -> Result<Box<dyn AudioNode>, String> {
+        let index = voice_input(args.first().ok_or("missing voice input")?)?;
+        Ok(Box::new(Self { index }))
+    }
+}
+
+impl MidiIn {
+    /// `MidiIn [gate|freq|note]`, the gate unless it says otherwise
+    pub(super) fn from_args(args: &[&str]) -> Result<Box<dyn AudioNode>, String> {
+        let index = args
+            .first()
+            .map_or(Ok(VOICE_GATE), |arg| voice_input(arg))?;
+        Ok(Box::new(Self::new(index)))
+    }
+}
+
+/// Parses the `index`th argument as a number, or `default` when it's absent.
+fn number_arg(
+    args: &[&str],
+    index: usize,
+    name: &str,
+    default: Option<f32>,
+) -> Result<f32, String> {
+    match args.get(index) {
+        Some(arg) => arg.parse().map_err(|_| format!("invalid {name}")),
+        None => default.ok_or_else(|| format!("missing {name}")),
+    }
+}
+
+impl Const {
+    /// `Const <value>`
+    pub(super) fn from_args(args: &[&str]) -> Result<Box<dyn AudioNode>, String> {
+        let value = number_arg(args, 0, "value", None)?;
+        Ok(Box::new(Self { value }))
+    }
+}
+
+impl Add {
+    /// `Add [offset]`
+    pub(super) fn from_args(args: &[&str]) -> Result<Box<dyn AudioNode>, String> {
+        let offset = number_arg(args, 0, "offset", Some(0.0))?;
+        Ok(Box::new(Self { offset }))
+    }
+}
+
+impl Scale {
+    /// `Scale <factor> [offset]`
+    pub(super) fn from_args(args: &[&str]) -> Result<Box<dyn AudioNode>, String> {
+        let factor = number_arg(args, 0, "factor", None)?;
+        let offset = number_arg(args, 1, "offset", Some(0.0))?;
+        Ok(Box::new(Self { factor, offset }))
+    }
+}
+
+impl Slew {
+    /// `Slew <rise> [fall]`, in seconds; the fall defaults to the rise
+    pub(super) fn from_args(args: &[&str]) -> Result<Box<dyn AudioNode>, String> {
+        let rise = number_arg(args, 0, "rise", None)?;
+        let fall = number_arg(args, 1, "fall", Some(rise))?;
+        Ok(Box::new(Self::new(rise, fall)))
+    }
+}
+
+impl EnvelopeFollower {
+    /// `Follow <attack> <release>`, in seconds
+    pub(super) fn from_args(args: &[&str]) -> Result<Box<dyn AudioNode>, String> {
+        let attack = number_arg(args, 0, "attack", None)?;
+        let release = number_arg(args, 1, "release", None)?;
+        Ok(Box::new(Self::new(attack, release)))
+    }
+}
+
+impl Gate {
+    /// `Gate <threshold> [release]`, the release in seconds
+    pub(super) fn from_args(args: &[&str]) -> Result<Box<dyn AudioNode>, String> {
+        let threshold = number_arg(args, 0, "threshold", None)?;
+        let release = number_arg(args, 1, "release", Some(0.05))?;
+        Ok(Box::new(Self::new(threshold, release)))
+    }
+}
+
+impl Pluck {
+    /// `Pluck <frequency> [decay]`, the decay in seconds
+    pub(super) fn from_args(args: &[&str]) -> Result<Box<dyn AudioNode>, String> {
+        let freq = number_arg(args, 0, "frequency", None)?;
+        let decay = number_arg(args, 1, "decay", Some(1.0))?;
+        Ok(Box::new(Self::new(freq, decay)))
+    }
+}
+
+impl Clock {
+    /// `Clock <bpm> [pulses per beat]`
+    pub(super) fn from_args(args: &[&str]) -> Result<Box<dyn AudioNode>, String> {
+        let bpm = number_arg(args, 0, "bpm", None)?;
+        let per_beat = number_arg(args, 1, "pulses per beat", Some(1.0))?;
+        Ok(Box::new(Self::new(bpm, per_beat)))
+    }
+}
+
+impl Seq {
+    /// `Seq <step> [step ...]`, each a number or a note such as `A4` or `C#3`,
+    /// played as its frequency
+    pub(super) fn from_args(args: &[&str]) -> Result<Box<dyn AudioNode>, String> {
+        if args.is_empty() {
+            return Err("missing steps".to_string());
+        }
+        let steps = args
+            .iter()
+            .map(|&arg| {
+                arg.parse()
+                    .or_else(|_| note_freq(arg))
+                    .map_err(|_| format!("invalid step '{arg}'"))
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Box::new(Self::new(steps)))
+    }
+}
+
+impl Random {
+    /// `Random <Step|Smooth> <rate> [min max] [seed]`, the rate in new values
+    /// a second; the range is -1 to 1 by default, and without a seed every
+    /// run differs
+    pub(super) fn from_args(args: &[&str]) -> Result<Box<dyn AudioNode>, String> {
+        let smooth = match *args.first().ok_or("missing Step or Smooth")? {
+            "Step" => false,
+            "Smooth" => true,
+            other => return Err(format!("unknown random mode '{other}'")),
+        };
+        let rate = number_arg(args, 1, "rate", None)?;
+        let min = number_arg(args, 2, "min", Some(-1.0))?;
+        let max = number_arg(args, 3, "max", Some(1.0))?;
+        let seed = match args.get(4) {
+            Some(seed) => seed.parse().map_err(|_| "invalid seed")?,
+            None => RandomState::new().hash_one(0u8) as u32,
+        };
+        Ok(Box::new(Self::new(rate, min, max, smooth, seed)))
+    }
+}
+
+impl Quantizer {
+    /// `Quant <scale> [root]`, the scale one of pattern scripts' (`minor`,
+    /// `major_pentatonic`, ...) and the root a note without octave, C by
+    /// default
+    pub(super) fn from_args(args: &[&str]) -> Result<Box<dyn AudioNode>, String> {
+        let name = args.first().ok_or("missing scale")?;
+        let intervals = crate::core::quantize::scale(name).ok_or_else(|| {
+            let names: Vec<_> = crate::core::quantize::SCALES
+                .iter()
+                .map(|(name, _)| *name)
+                .collect();
+            format!("unknown scale '{name}' (expected {})", names.join(", "))
+        })?;
+        let root = match args.get(1) {
+            Some(root) => match pitch_class(root) {
+                Ok((semitone, "")) => semitone.rem_euclid(12) as u8,
+                _ => return Err(format!("invalid root '{root}'")),
+            },
+            None => 0,
+        };
+        Ok(Box::new(Self::new(intervals, root)))
+    }
+}
+
+/// The semitone above C of a note name's letter and optional `#` or `b`,
+/// and what follows them.
+fn pitch_class(name: &str) -> Result<(i32, &str), ()> {
+    let mut chars = name.chars();
+    let semitone: i32 = match chars.next().ok_or(())?.to_ascii_uppercase() {
+        'C' => 0,
+        'D' => 2,
+        'E' => 4,
+        'F' => 5,
+        'G' => 7,
+        'A' => 9,
+        'B' => 11,
+        _ => return Err(()),
+    };
+    let rest = chars.as_str();
+    Ok(match rest.strip_prefix('#') {
+        Some(rest) => (semitone + 1, rest),
+        None => match rest.strip_prefix('b') {
+            Some(rest) => (semitone - 1, rest),
+            None => (semitone, rest),
+        },
+    })
+}
+
+/// The frequency of a note name: a letter, an optional `#` or `b` and an
+/// octave, with `A4` at 440 Hz.
+fn note_freq(name: &str) -> Result<f32, ()> {
+    let (semitone, octave) = pitch_class(name)?;
+    let octave: i32 = octave.parse().map_err(|_| ())?;
+    let note = (octave + 1) * 12 + semitone;
+    let note = u8::try_from(note)
+        .ok()
+        .filter(|&note| note <= 127)
+        .ok_or(())?;
+    Ok(440.0 * 2.0_f32.powf((note as f32 - 69.0) / 12.0))
+}
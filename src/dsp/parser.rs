@@ -0,0 +1,963 @@
+use std::collections::HashSet;
+use std::path::Path;
+
+use super::graph::voice_graph;
+use super::nodes::Poly;
+use super::{AudioGraph, Node, NodeRegistry, Wire};
+use crate::AurioError;
+
+/// Cuts a line at a `#` starting it or following whitespace, so notes such
+/// as `C#4` aren't taken for comments.
+pub(crate) fn strip_comment(s: &str) -> &str {
+    let start = s
+        .char_indices()
+        .find(|&(i, c)| c == '#' && s[..i].chars().next_back().is_none_or(char::is_whitespace))
+        .map_or(s.len(), |(i, _)| i);
+    &s[..start]
+}
+
+/// A node line split into its parts.
+struct Header<'a> {
+    id: u32,
+    /// Marked with a `!` after the id.
+    bypass: bool,
+    /// Marked with `x2` or `x4` after the id and any `!`; 1 otherwise.
+    oversample: u32,
+    kind: &'a str,
+    args: Vec<&'a str>,
+}
+
+/// Splits a node line into its id, bypass and oversampling marks, type and
+/// arguments.
+fn parse_header(line: &str) -> Result<Header<'_>, String> {
+    let end = line.find(']').ok_or("missing ']'")?;
+    let id: u32 = line[1..end].trim().parse().map_err(|_| "invalid node id")?;
+
+    let rest = &line[end + 1..];
+    let (bypass, rest) = match rest.strip_prefix('!') {
+        Some(rest) => (true, rest),
+        None => (false, rest),
+    };
+    let (oversample, rest) = match rest.strip_prefix('x') {
+        Some(rest) if rest.starts_with(|c: char| c.is_ascii_digit()) => {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            let oversample = match &rest[..end] {
+                "2" => 2,
+                "4" => 4,
+                other => return Err(format!("invalid oversampling 'x{other}' (x2 or x4)")),
+            };
+            (oversample, &rest[end..])
+        }
+        _ => (1, rest),
+    };
+    let mut parts = rest.split_whitespace();
+
+    let kind = parts.next().ok_or("missing node type")?;
+    Ok(Header {
+        id,
+        bypass,
+        oversample,
+        kind,
+        args: parts.collect(),
+    })
+}
+
+fn parse_node(line: &str, registry: &NodeRegistry) -> Result<Node, String> {
+    let Header {
+        id,
+        bypass,
+        oversample,
+        kind,
+        args,
+    } = parse_header(line)?;
+    if kind == "Poly" {
+        return Err("Poly needs a { ... } body".to_string());
+    }
+    let inner = registry.create(kind, &args)?;
+
+    Ok(Node {
+        id,
+        kind: kind.to_string(),
+        args: args.iter().map(|arg| arg.to_string()).collect(),
+        body: None,
+        bypass,
+        oversample,
+        params_set: false,
+        inner: registry.oversample(inner, oversample),
+    })
+}
+
+/// `[id] Poly <voices> {` on line `number`, with the voice patch in `body`,
+/// the lines from the next one up to the closing `}`.
+fn parse_poly(
+    line: &str,
+    body: &[&str],
+    number: usize,
+    registry: &NodeRegistry,
+) -> Result<Node, AurioError> {
+    let at_line = |message| AurioError::Parse {
+        line: number,
+        message,
+    };
+    let Header {
+        id,
+        bypass,
+        oversample,
+        kind,
+        args,
+    } = parse_header(line.trim_end_matches('{')).map_err(at_line)?;
+    if kind != "Poly" {
+        return Err(at_line(format!("{kind} doesn't take a {{ ... }} body")));
+    }
+    let voices: usize = match args.first() {
+        Some(arg) => arg
+            .parse()
+            .ok()
+            .filter(|&voices| voices > 0)
+            .ok_or_else(|| at_line("invalid voice count".to_string()))?,
+        None => return Err(at_line("missing voice count".to_string())),
+    };
+
+    let graphs = (0..voices)
+        .map(|_| {
+            let (nodes, wires) = parse_block(body, number + 1, registry)?;
+            validate_wires(&nodes, &wires)?;
+            voice_graph(nodes, &wires)
+        })
+        .collect::<Result<Vec<_>, AurioError>>()?;
+    Ok(Node {
+        id,
+        kind: kind.to_string(),
+        args: args.iter().map(|arg| arg.to_string()).collect(),
+        body: Some(body.iter().map(|line| line.to_string()).collect()),
+        bypass,
+        oversample,
+        params_set: false,
+        inner: registry.oversample(Box::new(Poly::new(graphs)), oversample),
+    })
+}
+
+fn parse_wires(line: &str) -> Result<Vec<Wire>, String> {
+    let mut wires = Vec::new();
+
+    for part in line.split(',') {
+        let part = part.trim();
+        if part.is_empty() {
+            continue;
+        }
+
+        let (from, to) = part
+            .split_once("->")
+            .ok_or("invalid wire syntax, expected a->b")?;
+        // `a->b*0.5` mixes a in at half level.
+        let (to, weight) = match to.split_once('*') {
+            Some((to, weight)) => (
+                to,
+                weight.trim().parse().map_err(|_| "invalid wire weight")?,
+            ),
+            None => (to, 1.0),
+        };
+        // `a->b.freq` drives one of b's parameters instead of its input.
+        let (to, to_param) = match to.split_once('.') {
+            Some((to, param)) if !param.trim().is_empty() => (to, Some(param.trim().to_string())),
+            Some(_) => return Err("missing parameter name after '.'".to_string()),
+            None => (to, None),
+        };
+
+        let from_node_id: u32 = from.trim().parse().map_err(|_| "invalid wire source")?;
+        let to_node_id: u32 = to.trim().parse().map_err(|_| "invalid wire destination")?;
+
+        wires.push(Wire {
+            from_node_id,
+            from_output_idx: 0,
+            to_node_id,
+            to_param,
+            weight,
+        });
+    }
+
+    Ok(wires)
+}
+
+fn validate_wires(nodes: &[Node], wires: &[Wire]) -> Result<(), AurioError> {
+    let mut ids = HashSet::new();
+    for node in nodes {
+        if !ids.insert(node.id) {
+            return Err(AurioError::Graph(format!("duplicate node id {}", node.id)));
+        }
+    }
+
+    for wire in wires {
+        if !ids.contains(&wire.from_node_id) {
+            return Err(AurioError::Graph(format!(
+                "wire references unknown source node {}",
+                wire.from_node_id
+            )));
+        }
+        if !ids.contains(&wire.to_node_id) {
+            return Err(AurioError::Graph(format!(
+                "wire references unknown destination node {}",
+                wire.to_node_id
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// Parses a patch using the built-in node types.
+pub fn parse_file(content: &str) -> Result<AudioGraph, AurioError> {
+    parse_with(content, &NodeRegistry::default())
+}
+
+/// Reads and parses a `.au` file using the built-in node types.
+pub fn load_file(path: &Path) -> Result<AudioGraph, AurioError> {
+    let content = std::fs::read_to_string(path).map_err(|e| AurioError::project_io(path, e))?;
+    parse_file(&content)
+}
+
+fn parse_lines(
+    content: &str,
+    registry: &NodeRegistry,
+) -> Result<(Vec<Node>, Vec<Wire>), AurioError> {
+    let lines: Vec<&str> = content.lines().collect();
+    parse_block(&lines, 1, registry)
+}
+
+/// Parses `lines`, the first of which is line `first` of the file.
+fn parse_block(
+    lines: &[&str],
+    first: usize,
+    registry: &NodeRegistry,
+) -> Result<(Vec<Node>, Vec<Wire>), AurioError> {
+    let mut nodes = Vec::new();
+    let mut wires = Vec::new();
+
+    let mut index = 0;
+    while index < lines.len() {
+        let number = first + index;
+        let line = strip_comment(lines[index]).trim();
+        index += 1;
+        if line.is_empty() {
+            continue;
+        }
+
+        let at_line = |message| AurioError::Parse {
+            line: number,
+            message,
+        };
+        if line.starts_with('[') && line.ends_with('{') {
+            let len =
+                body_len(&lines[index..]).ok_or_else(|| at_line("missing '}'".to_string()))?;
+            nodes.push(parse_poly(
+                line,
+                &lines[index..index + len],
+                number,
+                registry,
+            )?);
+            index += len + 1;
+        } else if line.starts_with('[') {
+            nodes.push(parse_node(line, registry).map_err(at_line)?);
+        } else if line == "}" {
+            return Err(at_line("unexpected '}'".to_string()));
+        } else {
+            wires.extend(parse_wires(line).map_err(at_line)?);
+        }
+    }
+    Ok((nodes, wires))
+}
+
+/// How many of `lines` come before the `}` closing a body they start in.
+fn body_len(lines: &[&str]) -> Option<usize> {
+    let mut depth = 0;
+    for (i, line) in lines.iter().enumerate() {
+        let line = strip_comment(line).trim();
+        if line.ends_with('{') {
+            depth += 1;
+        } else if line == "}" {
+            if depth == 0 {
+                return Some(i);
+            }
+            depth -= 1;
+        }
+    }
+    None
+}
+
+/// Parses a patch, looking node types up in `registry`.
+pub fn parse_with(content: &str, registry: &NodeRegistry) -> Result<AudioGraph, AurioError> {
+    let (nodes, wires) = parse_lines(content, registry)?;
+    validate_wires(&nodes, &wires)?;
+
+    let mut graph = AudioGraph::new(nodes, wires);
+    graph.sort()?;
+    Ok(graph)
+}
+
+/// Applies a patch fragment to a running graph using the built-in node types.
+pub fn patch_graph(graph: &mut AudioGraph, fragment: &str) -> Result<(), AurioError> {
+    patch_graph_with(graph, fragment, &NodeRegistry::default())
+}
+
+/// Applies a patch fragment to a running graph: nodes with a new id are added,
+/// nodes reusing an id replace that node and keep its wires, and wires are
+/// added, or take the fragment's weight when they're already there. Nothing
+/// changes when the fragment doesn't parse or would close a
+/// cycle.
+pub fn patch_graph_with(
+    graph: &mut AudioGraph,
+    fragment: &str,
+    registry: &NodeRegistry,
+) -> Result<(), AurioError> {
+    let (nodes, wires) = parse_lines(fragment, registry)?;
+    validate_wires(&nodes, &[])?;
+
+    let existing: HashSet<u32> = graph.nodes.iter().map(|n| n.id).collect();
+    let (replaced, added): (Vec<Node>, Vec<Node>) =
+        nodes.into_iter().partition(|n| existing.contains(&n.id));
+    let added_ids: HashSet<u32> = added.iter().map(|n| n.id).collect();
+
+    let num_wires = graph.wires.len();
+    graph.nodes.extend(added);
+    // Wires already there take the fragment's weight, the old one kept in
+    // case it doesn't apply.
+    let mut reweighted = Vec::new();
+    for wire in wires {
+        let existing = graph.wires.iter().position(|w| {
+            (w.from_node_id, w.to_node_id, &w.to_param)
+                == (wire.from_node_id, wire.to_node_id, &wire.to_param)
+        });
+        match existing {
+            Some(i) => {
+                reweighted.push((i, graph.wires[i].weight));
+                graph.wires[i].weight = wire.weight;
+            }
+            None => graph.wires.push(wire),
+        }
+    }
+    // Swapped in before sorting, so wires into parameters are checked
+    // against the new nodes.
+    let mut previous = Vec::new();
+    for node in replaced {
+        if let Some(slot) = graph.nodes.iter_mut().find(|n| n.id == node.id) {
+            let mut node = node;
+            if slot.kind == node.kind
+                && let Some(value) = slot.inner.ramp_state()
+            {
+                node.inner.ramp_from(value);
+            }
+            previous.push(std::mem::replace(slot, node));
+        }
+    }
+    if let Err(e) = validate_wires(&graph.nodes, &graph.wires).and_then(|()| graph.sort()) {
+        for node in previous {
+            if let Some(slot) = graph.nodes.iter_mut().find(|n| n.id == node.id) {
+                *slot = node;
+            }
+        }
+        graph.nodes.retain(|n| !added_ids.contains(&n.id));
+        graph.wires.truncate(num_wires);
+        for (i, weight) in reweighted.into_iter().rev() {
+            graph.wires[i].weight = weight;
+        }
+        // Sorting may have handed nodes the new weights already; the graph as
+        // it was sorts again.
+        graph.sort()?;
+        return Err(e);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dsp::{AudioNode, BLOCK_SIZE, ProcessContext};
+
+    const CTX: ProcessContext = ProcessContext {
+        sample_rate: 44100.0,
+    };
+
+    #[test]
+    fn parses_basic_nodes() {
+        let input = r#"
+            [0] Osc Sine 330.0
+            [1] Osc Saw 220.0
+            [2] Gain 0.2
+            [3] Out
+        "#;
+
+        let graph = parse_file(input).unwrap();
+        assert_eq!(graph.nodes.len(), 4);
+
+        let mut nodes = node_values(&graph);
+        nodes.sort();
+        assert_eq!(nodes, ["Gain 0.2", "Osc Saw 220", "Osc Sine 330", "Out"]);
+    }
+
+    /// Each node's type and the values it holds, as `to_args` gives them.
+    fn node_values(graph: &AudioGraph) -> Vec<String> {
+        graph
+            .nodes
+            .iter()
+            .map(|n| {
+                let args = n.inner.to_args().unwrap_or_default();
+                [n.kind.clone()]
+                    .into_iter()
+                    .chain(args)
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sorts_nodes() {
+        let input = r#"
+            [0] Osc Sine 330.0
+            [1] Osc Saw 220.0
+            [2] Gain 0.2
+            [3] Out
+
+            0->2,
+            1->2,
+            2->3,
+        "#;
+
+        let graph = parse_file(input).unwrap();
+        assert_eq!(graph.nodes.len(), 4);
+        assert_eq!(graph.wires.len(), 3);
+
+        let mut nodes = node_values(&graph);
+        nodes[..2].sort();
+        assert_eq!(nodes, ["Osc Saw 220", "Osc Sine 330", "Gain 0.2", "Out"]);
+    }
+
+    #[test]
+    fn valid_wires_pass_validation() {
+        let input = r#"
+        [0] Out
+        [1] Out
+        0->1
+    "#;
+
+        let graph = parse_file(input).unwrap();
+        assert_eq!(graph.wires.len(), 1);
+        assert_eq!(graph.nodes.len(), 2);
+        assert_eq!(graph.wires[0].from_node_id, 0);
+        assert_eq!(graph.wires[0].to_node_id, 1);
+        assert!(graph.nodes.iter().all(|n| n.inner.is_output()));
+    }
+
+    #[test]
+    fn ignores_comments_and_blank_lines() {
+        let input = r#"
+            # full line comment
+
+            [0] Gain 0.5
+            [1] Out  # trailing comment
+
+            0->1, # wire comment
+        "#;
+
+        let graph = parse_file(input).unwrap();
+        assert_eq!(graph.nodes.len(), 2);
+        assert_eq!(graph.wires.len(), 1);
+
+        // Without whitespace before it, `#` is part of the argument.
+        assert_eq!(strip_comment("[0] Gain 0.5#note"), "[0] Gain 0.5#note");
+        let err = parse_file("[0] Gain 0.5#note").err().unwrap();
+        assert_eq!(err.to_string(), "line 1: invalid gain");
+    }
+
+    #[test]
+    fn errors_on_unknown_node_type() {
+        let input = "[0] Foo 123";
+
+        let err = parse_file(input).err().unwrap();
+        assert!(err.to_string().contains("unknown node type"));
+    }
+
+    #[test]
+    fn errors_on_invalid_wire() {
+        let input = "0=>1";
+
+        let err = parse_file(input).err().unwrap();
+        assert!(err.to_string().contains("invalid wire syntax"));
+    }
+
+    #[test]
+    fn errors_name_the_line_and_duplicate_ids() {
+        let err = parse_file("[0] Out\n\n[1] Foo").err().unwrap();
+        assert!(matches!(err, AurioError::Parse { line: 3, .. }));
+        assert!(err.to_string().starts_with("line 3: "));
+
+        let err = parse_file("[0] Out\n[0] Gain 0.5").err().unwrap();
+        assert_eq!(err.to_string(), "duplicate node id 0");
+    }
+
+    #[test]
+    fn fragments_patch_a_running_graph() {
+        let mut graph = parse_file("[0] Osc Sine 330.0\n[1] Out\n0->1").unwrap();
+
+        patch_graph(&mut graph, "[2] Gain 0.5\n[1] Gain 2.0\n2->1").unwrap();
+        assert_eq!(graph.nodes.len(), 3);
+        assert_eq!(graph.wires.len(), 2);
+        assert!(graph.nodes.iter().all(|n| !n.inner.is_output()));
+
+        let err = patch_graph(&mut graph, "[3] Out\n1->2, 2->1")
+            .err()
+            .unwrap();
+        assert!(matches!(err, AurioError::Graph(_)));
+        assert_eq!(err.to_string(), "Cycle detected");
+        assert_eq!(graph.nodes.len(), 3);
+        assert_eq!(graph.wires.len(), 2);
+    }
+
+    #[test]
+    fn wires_drive_named_parameters() {
+        let input = r#"
+            [0] Osc Sine 110.0
+            [1] Osc Sine 3.0
+            [2] Gain 20.0
+            [3] Out
+            1->2, 2->0.freq, 0->3
+        "#;
+        let graph = parse_file(input).unwrap();
+        assert_eq!(graph.wires[1].to_param.as_deref(), Some("freq"));
+        assert_eq!(graph.wires[2].to_param, None);
+
+        let err = parse_file("[0] Out\n[1] Gain 1.0\n1->0.freq")
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "node 0 has no input 'freq'");
+        let err = parse_file("[0] Out\n[1] Gain 1.0\n1->0.").err().unwrap();
+        assert!(err.to_string().contains("missing parameter name"));
+    }
+
+    #[test]
+    fn patching_in_a_node_without_a_wired_parameter_fails() {
+        let mut graph =
+            parse_file("[0] Osc Sine 1.0\n[1] Osc Saw 110\n[2] Out\n0->1.freq, 1->2").unwrap();
+
+        let err = patch_graph(&mut graph, "[1] Gain 0.5").err().unwrap();
+        assert_eq!(err.to_string(), "node 1 has no input 'freq'");
+        patch_graph(&mut graph, "[1] Osc Square 220").unwrap();
+        assert_eq!(graph.nodes.len(), 3);
+    }
+
+    #[test]
+    fn gains_take_decibels_and_weighted_wires() {
+        let input = r#"
+            [0] Const 1.0
+            [1] Const 2.0
+            [2] Gain -6dB
+            [3] Out
+            0->2 * 0.5, 1->2, 2->3
+        "#;
+        let mut graph = parse_file(input).unwrap();
+        assert_eq!(graph.wires[0].weight, 0.5);
+        let mut output = [0.0; 1];
+        graph.process(&mut output, &CTX);
+        assert!((output[0] - 2.5 * 0.501).abs() < 1e-3);
+
+        let err = parse_file(
+            "[0] Const 1
+[1] Out
+0->1*2",
+        )
+        .err()
+        .unwrap();
+        assert_eq!(err.to_string(), "node 1: doesn't weight its inputs");
+        let err = parse_file(
+            "[0] Const 1
+[1] Osc Sine 1
+0->1.freq*2",
+        )
+        .err()
+        .unwrap();
+        assert!(err.to_string().contains("can't weight a parameter"));
+        assert!(parse_file("[0] Gain 3db").is_ok());
+
+        // Patching a wire that's there already changes its weight, unless
+        // the node can't take it. Blocks already processed play out first.
+        patch_graph(&mut graph, "0->2 * 0.25").unwrap();
+        assert_eq!(graph.wires.len(), 3);
+        let mut block = [0.0; BLOCK_SIZE];
+        graph.process(&mut block, &CTX);
+        assert!((block[BLOCK_SIZE - 1] - 2.25 * 0.501).abs() < 1e-3);
+        patch_graph(&mut graph, "2->3 * 2").unwrap_err();
+        assert_eq!(graph.wires[2].weight, 1.0);
+        graph.process(&mut block, &CTX);
+        assert!((block[BLOCK_SIZE - 1] - 2.25 * 0.501).abs() < 1e-3);
+        let err = parse_file("[0] Gain loud dB").err().unwrap();
+        assert!(err.to_string().contains("invalid gain"));
+    }
+
+    #[test]
+    fn replaced_gains_ramp_from_the_old_level() {
+        let mut graph = parse_file("[0] Const 1.0\n[1] Gain 0.0\n[2] Out\n0->1, 1->2").unwrap();
+        let mut output = [0.0; BLOCK_SIZE];
+        graph.process(&mut output, &CTX);
+
+        patch_graph(&mut graph, "[1] Gain 1.0").unwrap();
+        graph.process(&mut output, &CTX);
+        assert!(output[0] > 0.0 && output[0] < 0.01);
+        let level = output[BLOCK_SIZE - 1];
+
+        let mut reloaded = parse_file("[0] Const 1.0\n[1] Gain 0.5\n[2] Out\n0->1, 1->2").unwrap();
+        reloaded.inherit_ramps(&graph);
+        reloaded.process(&mut output, &CTX);
+        assert!((output[0] - level).abs() < 0.01);
+    }
+
+    #[test]
+    fn bypassed_nodes_pass_their_input_through() {
+        let input = "[0] Const 1.0\n[1]! Gain 0.25\n[2]! Const 3.0\n[3] Out\n0->1, 1->3, 2->3";
+        let mut graph = parse_file(input).unwrap();
+        let mut output = [0.0; BLOCK_SIZE];
+        graph.process(&mut output, &CTX);
+        // The gain passes 1 on as it is, and the source without inputs is
+        // silent.
+        assert_eq!(output, [1.0; BLOCK_SIZE]);
+        assert!(graph.to_source().contains("[1]! Gain 0.25\n"));
+
+        graph.set_bypass(1, false).unwrap();
+        graph.set_bypass(2, false).unwrap();
+        graph.process(&mut output, &CTX);
+        assert_eq!(output, [3.25; BLOCK_SIZE]);
+        assert!(graph.set_bypass(9, true).is_err());
+
+        patch_graph(&mut graph, "[2]! Const 3.0").unwrap();
+        graph.process(&mut output, &CTX);
+        assert_eq!(output, [0.25; BLOCK_SIZE]);
+    }
+
+    #[test]
+    fn oversampled_nodes_keep_their_mark_and_wires() {
+        let input = "[0] Osc Sine 2\n[1]!x4 Osc Sine 440\n[2]x2 Gain 0.5\n[3] Out\n\
+                     0->1.freq, 1->2, 2->3";
+        let mut graph = parse_file(input).unwrap();
+        assert_eq!(graph.nodes.iter().map(|n| n.oversample).max(), Some(4));
+        assert!(
+            graph
+                .to_source()
+                .starts_with("[0] Osc Sine 2\n[1]!x4 Osc Sine 440\n[2]x2 Gain")
+        );
+        graph.set_param(2, "gain", 0.25).unwrap();
+
+        assert!(parse_file("[0]x3 Osc Sine 440").is_err());
+        assert!(parse_file("[0] Osc Sine 440\n[1]x2 Out\n0->1").is_ok());
+
+        let mut registry = NodeRegistry::default();
+        registry.set_min_oversampling(4);
+        let mut offline = parse_with(
+            "[0] Const 0.5\n[1]x2 Gain 1\n[2] Out\n0->1, 1->2",
+            &registry,
+        )
+        .unwrap();
+        assert_eq!(offline.to_source().lines().nth(1), Some("[1]x2 Gain 1"));
+        let mut output = [0.0; BLOCK_SIZE];
+        offline.process(&mut output, &CTX);
+        assert!((output[BLOCK_SIZE - 1] - 0.5).abs() < 1e-3);
+    }
+
+    #[test]
+    fn probes_play_a_node_instead_of_the_output() {
+        let input = "[0] Const 0.5\n[1] Const 3.0\n[2] Add\n[3] Out\n0->2, 1->2, 2->3";
+        let mut graph = parse_file(input).unwrap();
+        let mut output = [0.0; BLOCK_SIZE];
+        graph.probe(Some(0), None).unwrap();
+        graph.process(&mut output, &CTX);
+        assert_eq!(output, [0.5; BLOCK_SIZE]);
+        // Clipped, in case it's a control signal.
+        graph.probe(Some(1), None).unwrap();
+        graph.process(&mut output, &CTX);
+        assert_eq!(output, [1.0; BLOCK_SIZE]);
+        assert!(graph.probe(Some(9), None).is_err());
+        graph.probe(None, None).unwrap();
+        graph.process(&mut output, &CTX);
+        assert_eq!(output, [3.5; BLOCK_SIZE]);
+
+        // Probes for a while play the output again after it.
+        let ctx = ProcessContext {
+            sample_rate: BLOCK_SIZE as f32,
+        };
+        graph.probe(Some(0), Some(2.0)).unwrap();
+        for _ in 0..2 {
+            graph.process(&mut output, &ctx);
+            assert_eq!(output, [0.5; BLOCK_SIZE]);
+        }
+        assert_eq!(graph.probed(), None);
+        graph.process(&mut output, &ctx);
+        assert_eq!(output, [3.5; BLOCK_SIZE]);
+    }
+
+    #[test]
+    fn graphs_play_the_same_whatever_the_buffer_size() {
+        let input = "[0] Osc Saw 440.0\n[1] Gain 0.5\n[2] Out\n0->1, 1->2";
+        let mut whole = [0.0; 300];
+        parse_file(input).unwrap().process(&mut whole, &CTX);
+
+        let mut graph = parse_file(input).unwrap();
+        let mut pieces = [0.0; 300];
+        let mut start = 0;
+        for frames in [1, 63, 100, 7, 129] {
+            graph.process(&mut pieces[start..start + frames], &CTX);
+            start += frames;
+        }
+        assert_eq!(pieces, whole);
+
+        // Changes apply from the next block.
+        graph.set_param(1, "gain", 0.0).unwrap();
+        let mut output = [0.0; 2];
+        graph.process(&mut output, &CTX);
+        assert_ne!(output[0], 0.0);
+    }
+
+    #[test]
+    fn poly_bodies_play_one_note_per_voice() {
+        let input = r#"
+            [0] Clock 60 1
+            [1] Seq 100 200
+            [2] Poly 2 {
+                [0] In freq
+                [1] In gate   # held for half of each beat
+                [2] Mul
+                [3] Out
+                0->2, 1->2, 2->3
+            }
+            [3] Out
+            0->1, 0->2, 1->2.freq, 2->3
+        "#;
+        let mut graph = parse_file(input).unwrap();
+        let ctx = ProcessContext { sample_rate: 4.0 };
+        let mut output = [0.0; 8];
+        graph.process(&mut output, &ctx);
+        assert_eq!(output, [100.0, 100.0, 0.0, 0.0, 200.0, 200.0, 0.0, 0.0]);
+
+        // A MidiIn wired in hands it each note for a voice of its own.
+        let mut graph = parse_file(
+            "[0] MidiIn\n[1] Poly 2 {\n[0] In note\n[1] Out\n0->1\n}\n[2] Out\n0->1, 1->2",
+        )
+        .unwrap();
+        graph.note(60, 100);
+        graph.note(64, 100);
+        let mut output = [0.0; BLOCK_SIZE];
+        graph.process(&mut output, &ctx);
+        assert_eq!(output[0], 124.0);
+
+        let err = parse_file("[0] Out\n[1] Poly 2 {\n[0] Out\n[1] Foo\n}")
+            .err()
+            .unwrap();
+        assert!(matches!(err, AurioError::Parse { line: 4, .. }));
+        let err = parse_file("[0] Poly 2 {\n[0] Out").err().unwrap();
+        assert_eq!(err.to_string(), "line 1: missing '}'");
+        let err = parse_file("[0] Poly 0 {\n}").err().unwrap();
+        assert!(err.to_string().contains("invalid voice count"));
+        let err = parse_file("[0] Gain 1 {\n}").err().unwrap();
+        assert!(err.to_string().contains("doesn't take a { ... } body"));
+        let err = parse_file("[0] Poly 2").err().unwrap();
+        assert!(err.to_string().contains("needs a { ... } body"));
+    }
+
+    #[test]
+    fn graphs_write_back_to_source() {
+        let input = r#"
+            # Comments don't survive.
+            [3] Out
+            [0] Clock 120 4
+            [1] Seq A3 C#4
+            [2] Poly 2 {
+                [0] In freq
+                [1] Osc Saw 0   # nor do these
+                [2] Out
+
+                0->1.freq, 1->2
+            }
+            [4] Gain -6dB
+            0->1, 0->2, 1->2.freq, 2->4 * 0.5, 4->3
+        "#;
+        let source = parse_file(input).unwrap().to_source();
+        assert_eq!(
+            source,
+            "[0] Clock 120 4\n\
+             [1] Seq A3 C#4\n\
+             [2] Poly 2 {\n    [0] In freq\n    [1] Osc Saw 0\n    [2] Out\n    0->1.freq, 1->2\n}\n\
+             [3] Out\n\
+             [4] Gain -6dB\n\
+             \n0->1\n0->2\n1->2.freq\n2->4 * 0.5\n4->3\n"
+        );
+        let mut reparsed = parse_file(&source).unwrap();
+        assert_eq!(reparsed.to_source(), source);
+        assert_eq!(reparsed.wires[3].weight, 0.5);
+
+        // Parameters set since are written as the nodes have them.
+        reparsed.set_param(0, "bpm", 90.0).unwrap();
+        reparsed.set_param(4, "gain", 0.25).unwrap();
+        let source = reparsed.to_source();
+        assert!(source.contains("[0] Clock 90 4\n"));
+        assert!(source.contains("[4] Gain 0.25\n"));
+        let random = parse_file("[0] Random Smooth 2 0 1 7").unwrap().to_source();
+        let mut graph = parse_file(&random).unwrap();
+        graph.set_param(0, "max", 3.0).unwrap();
+        assert_eq!(graph.to_source(), "[0] Random Smooth 2 0 3 7\n");
+    }
+
+    #[test]
+    fn control_nodes_map_modulation() {
+        // An LFO mapped onto 200-600 Hz, summed with a 100 Hz offset.
+        let input = r#"
+            [0] Osc Sine 2.0
+            [1] Scale 200.0 400.0
+            [2] Const 100.0
+            [3] Add
+            [4] Mul
+            [5] Out
+            0->1, 1->3, 2->3, 3->4, 2->4, 4->5
+        "#;
+        let mut graph = parse_file(input).unwrap();
+        let mut output = [0.0; 1];
+        graph.process(&mut output, &CTX);
+        // The sine starts at 0: (0 * 200 + 400 + 100) * 100.
+        assert_eq!(output, [50_000.0]);
+    }
+
+    #[test]
+    fn followers_and_gates_track_their_input() {
+        // A full-scale square, so its level is always 1.
+        let input = r#"
+            [0] Osc Square 100.0
+            [1] Follow 0.0 0.1
+            [2] Gate 0.5
+            [3] Mul
+            [4] Out
+            0->1, 0->2, 1->3, 2->3, 3->4
+        "#;
+        let mut graph = parse_file(input).unwrap();
+        let mut output = [0.0; 512];
+        graph.process(&mut output, &CTX);
+        // The gate takes a moment to open.
+        assert_eq!(output[0], 0.0);
+        assert_eq!(output[511], 1.0);
+    }
+
+    #[test]
+    fn clocked_sequences_play_notes() {
+        let input = r#"
+            [0] Clock 120 4
+            [1] Seq A4 440 C#5 Eb3 1
+            [2] Out
+            0->1, 1->2
+        "#;
+        let mut graph = parse_file(input).unwrap();
+        let mut output = [0.0; 1];
+        graph.process(&mut output, &CTX);
+        assert_eq!(output, [440.0]);
+    }
+
+    #[test]
+    fn quantizers_keep_pitches_in_key() {
+        let input = r#"
+            [0] Const 450.0
+            [1] Quant minor_pentatonic A
+            [2] Out
+            0->1, 1->2
+        "#;
+        let mut graph = parse_file(input).unwrap();
+        let mut output = [0.0; 1];
+        graph.process(&mut output, &CTX);
+        assert!((output[0] - 440.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn seeded_random_patches_repeat() {
+        let input = r#"
+            [0] Random Smooth 5 200 800 42
+            [1] Clock 240
+            [2] SampleHold
+            [3] Quant major C
+            [4] Out
+            0->2, 1->2.trigger, 2->3, 3->4
+        "#;
+        let render = || {
+            let mut graph = parse_file(input).unwrap();
+            let mut output = [0.0; 64];
+            graph.process(&mut output, &CTX);
+            output
+        };
+        let output = render();
+        assert_eq!(output, render());
+        assert!(output.iter().all(|&freq| (190.0..=830.0).contains(&freq)));
+    }
+
+    #[test]
+    fn node_arguments_that_dont_parse_say_why() {
+        let cases = [
+            ("Const", "missing value"),
+            ("Add x", "invalid offset"),
+            ("Scale", "missing factor"),
+            ("Slew", "missing rise"),
+            ("Follow 0.01", "missing release"),
+            ("Gate loud", "invalid threshold"),
+            ("Pluck", "missing frequency"),
+            ("Clock", "missing bpm"),
+            ("Clock 120 x", "invalid pulses per beat"),
+            ("Seq", "missing steps"),
+            ("Seq A4 H2", "invalid step 'H2'"),
+            ("Quant klingon", "unknown scale 'klingon'"),
+            ("Quant major A4", "invalid root 'A4'"),
+            ("Random Wobbly 5", "unknown random mode 'Wobbly'"),
+            ("Random Step", "missing rate"),
+            ("Random Step 5 0 1 x", "invalid seed"),
+        ];
+        for (line, expected) in cases {
+            let err = parse_file(&format!("[0] {line}")).err().unwrap();
+            assert!(err.to_string().contains(expected), "{line}: {err}");
+        }
+    }
+
+    #[test]
+    fn load_file_reports_the_unreadable_path() {
+        let err = load_file(Path::new("no/such/patch.au")).err().unwrap();
+        assert!(matches!(err, AurioError::ProjectIo { .. }));
+        assert!(err.to_string().starts_with("no/such/patch.au: "));
+    }
+
+    #[test]
+    fn errors_on_missing_osc_params() {
+        let input = "[0] Osc Sine";
+
+        let err = parse_file(input).err().unwrap();
+        assert!(err.to_string().contains("missing frequency"));
+    }
+
+    #[test]
+    fn registered_nodes_are_parsed_and_processed() {
+        struct Hold(f32);
+
+        impl AudioNode for Hold {
+            fn process(&mut self, _inputs: &[&[f32]], output: &mut [f32], _ctx: &ProcessContext) {
+                output.fill(self.0);
+            }
+        }
+
+        let mut registry = NodeRegistry::default();
+        registry.register("Hold", |args| {
+            let value = args.first().ok_or("missing value")?;
+            Ok(Box::new(Hold(value.parse().map_err(|_| "invalid value")?)))
+        });
+
+        let input = r#"
+            [0] Hold 0.5
+            [1] Gain 2.0
+            [2] Out
+            0->1, 1->2
+        "#;
+        assert!(parse_file(input).is_err());
+
+        let mut graph = parse_with(input, &registry).unwrap();
+        let mut output = [0.0; 8];
+        graph.process(&mut output, &CTX);
+        assert_eq!(output, [1.0; 8]);
+    }
+}
@@ -1,46 +1,352 @@
-use crate::{Project, audio, events, scripting, timing};
+//! The engine thread: loads projects, runs the state graphs and Lua patterns
+//! on a timing thread and renders tracks in the audio callback. Everything
+//! goes through the channels of the [`EngineHandle`] [`spawn_engine`] returns.
+
+use crate::audio::RenderJob;
+use crate::{
+    AurioError, Project, SampleRef, audio, audit, dsp, events, midi, osc, plugin, scripting, sync,
+    timing,
+};
 use arc_swap::ArcSwap;
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+#[cfg(feature = "audio-host")]
+use cpal::traits::{DeviceTrait, StreamTrait};
 use crossbeam::channel::{Receiver, Sender};
+use parking_lot::Mutex;
 use ringbuf::{
     HeapCons, HeapProd, HeapRb,
-    traits::{Consumer, Producer, Split},
+    traits::{Consumer, Split},
 };
 use std::path::PathBuf;
 use std::sync::{
     Arc,
-    atomic::{AtomicU64, Ordering},
+    atomic::{AtomicBool, AtomicU64, Ordering},
 };
 
+/// What the engine can be asked to do, sent through
+/// [`EngineHandle::command_tx`].
 #[derive(Debug, Clone)]
 pub enum EngineCommand {
     LoadProject(PathBuf),
     ReloadProject(Project),
+    /// Like `ReloadProject` after tracks were added, removed or reordered.
+    /// Sequencing and plugins are set up per track, so playback restarts.
+    RestructureProject(Project),
+    /// Sent by the project watcher when files under the project directory change.
+    ScriptsChanged(Vec<PathBuf>),
     Play,
     Pause,
     Stop,
-    SetVariable { name: String, value: f64 },
+    SetVariable {
+        name: String,
+        value: f64,
+    },
+    /// Moves the arrangement to a section once the current one ends.
+    GoToSection {
+        section: String,
+    },
+    /// Moves a track to a node at the moment `timing` picks.
+    TriggerTransition {
+        track_id: usize,
+        node_id: String,
+        timing: timing::TransitionTiming,
+    },
+    SetMorph {
+        track_id: usize,
+        value: f32,
+    },
+    SetBpm {
+        bpm: f32,
+    },
+    SetTrackParam {
+        track_id: usize,
+        param: scripting::TrackParam,
+        value: f32,
+    },
+    SetBusVolume {
+        bus: usize,
+        volume: f32,
+    },
+    /// Sound design from the instrument panel, swapped into the running track.
+    SetInstrument {
+        track_id: usize,
+        instrument: audio::Instrument,
+        adsr: audio::ADSRConfig,
+    },
+    SetMetronome {
+        enabled: bool,
+    },
+    /// A `.au` patch, as source, to play over the project, from the patch
+    /// editor; `None` takes it out. A new one eases in from the one before.
+    SetPatch(Option<String>),
+    /// Plays one node of the patch editor's patch instead of its output, or
+    /// the output again with `None`. Stays on through edits to the patch.
+    ProbePatch(Option<u32>),
+    /// Chooses the audio backend and output layout. A running stream is
+    /// rebuilt without interrupting playback.
+    SetAudioSettings(audio::AudioSettings),
+    /// Picks the output device, sample rate and buffer size (`None` for the
+    /// defaults). A running stream is rebuilt without interrupting playback.
+    SetAudioConfig {
+        device_name: Option<String>,
+        sample_rate: Option<u32>,
+        buffer_size: Option<u32>,
+    },
+    /// Asks for [`EngineUpdate::AudioDevices`] for the current backend.
+    ListAudioDevices,
+    /// Sent by the stream's error callback when its device goes away.
+    AudioStreamLost,
+    SetPluginParam {
+        track_id: usize,
+        slot: plugin::PluginSlot,
+        param_id: u32,
+        value: f64,
+    },
+    /// Decodes an audio file into the project's samples directory and adds it
+    /// to the sample library.
+    ImportSample(PathBuf),
+    /// Records the default input into a new sample named after the track,
+    /// until [`EngineCommand::StopRecording`] or `Stop`.
+    RecordInput {
+        track_id: usize,
+    },
+    StopRecording,
+    /// Plays a note on a track right away, outside its sequences, to audition
+    /// it. Opens the output when nothing is playing.
+    NoteOn {
+        track_id: usize,
+        pitch: u8,
+        velocity: u8,
+    },
+    NoteOff {
+        track_id: usize,
+        pitch: u8,
+    },
+    /// Bends, presses or slides a note started with `NoteOn`.
+    NoteModulation {
+        track_id: usize,
+        pitch: u8,
+        modulation: events::NoteModulation,
+    },
+    /// Binds the next incoming CC to `target`.
+    MidiLearn {
+        target: midi::MidiTarget,
+    },
+    CancelMidiLearn,
+    ClearMidiMapping {
+        target: midi::MidiTarget,
+    },
+    /// Sent by the MIDI input for every control change.
+    MidiCc {
+        channel: u8,
+        controller: u8,
+        value: u8,
+    },
+    /// Sent by the MIDI input for notes, bends and pressure.
+    MidiNote {
+        channel: u8,
+        message: events::MidiMessage,
+    },
+    /// Fades the output out, stops playback and ends the engine thread, then
+    /// signals `done`.
+    Shutdown {
+        done: Sender<()>,
+    },
+    /// Sent by the timing thread when it panics; playback fades out and stops.
+    TimingCrashed {
+        message: String,
+    },
+}
+
+impl EngineCommand {
+    /// The variant's name, for logs.
+    pub fn name(&self) -> &'static str {
+        match self {
+            EngineCommand::LoadProject(_) => "LoadProject",
+            EngineCommand::ReloadProject(_) => "ReloadProject",
+            EngineCommand::RestructureProject(_) => "RestructureProject",
+            EngineCommand::ScriptsChanged(_) => "ScriptsChanged",
+            EngineCommand::Play => "Play",
+            EngineCommand::Pause => "Pause",
+            EngineCommand::Stop => "Stop",
+            EngineCommand::SetVariable { .. } => "SetVariable",
+            EngineCommand::GoToSection { .. } => "GoToSection",
+            EngineCommand::TriggerTransition { .. } => "TriggerTransition",
+            EngineCommand::SetMorph { .. } => "SetMorph",
+            EngineCommand::SetBpm { .. } => "SetBpm",
+            EngineCommand::SetTrackParam { .. } => "SetTrackParam",
+            EngineCommand::SetBusVolume { .. } => "SetBusVolume",
+            EngineCommand::SetInstrument { .. } => "SetInstrument",
+            EngineCommand::SetMetronome { .. } => "SetMetronome",
+            EngineCommand::SetPatch(_) => "SetPatch",
+            EngineCommand::ProbePatch(_) => "ProbePatch",
+            EngineCommand::SetAudioSettings(_) => "SetAudioSettings",
+            EngineCommand::SetAudioConfig { .. } => "SetAudioConfig",
+            EngineCommand::ListAudioDevices => "ListAudioDevices",
+            EngineCommand::AudioStreamLost => "AudioStreamLost",
+            EngineCommand::SetPluginParam { .. } => "SetPluginParam",
+            EngineCommand::ImportSample(_) => "ImportSample",
+            EngineCommand::RecordInput { .. } => "RecordInput",
+            EngineCommand::StopRecording => "StopRecording",
+            EngineCommand::NoteOn { .. } => "NoteOn",
+            EngineCommand::NoteOff { .. } => "NoteOff",
+            EngineCommand::NoteModulation { .. } => "NoteModulation",
+            EngineCommand::MidiLearn { .. } => "MidiLearn",
+            EngineCommand::CancelMidiLearn => "CancelMidiLearn",
+            EngineCommand::ClearMidiMapping { .. } => "ClearMidiMapping",
+            EngineCommand::MidiCc { .. } => "MidiCc",
+            EngineCommand::MidiNote { .. } => "MidiNote",
+            EngineCommand::Shutdown { .. } => "Shutdown",
+            EngineCommand::TimingCrashed { .. } => "TimingCrashed",
+        }
+    }
 }
 
+/// What the engine reports back, received from [`EngineHandle::update_rx`].
 #[derive(Debug, Clone)]
 pub enum EngineUpdate {
-    ProjectLoaded { project: Project },
-    CurrentNodes { track_nodes: Vec<(usize, String)> },
-    PlaybackState { playing: bool },
-    Error { message: String },
+    ProjectLoaded {
+        project: Project,
+    },
+    CurrentNodes {
+        track_nodes: Vec<(usize, String)>,
+    },
+    /// Node each track's own graph is playing and how many beats into its
+    /// sequence it is, by track index. Sent regularly during playback.
+    Playhead {
+        positions: Vec<(String, f32)>,
+    },
+    /// The arrangement moved on to another section.
+    Section {
+        section: String,
+    },
+    /// The event ring stayed full while a track's sequence was scheduled,
+    /// and `count` of its events were dropped.
+    EventsDropped {
+        track_id: usize,
+        node_id: String,
+        count: usize,
+    },
+    PlaybackState {
+        playing: bool,
+    },
+    BpmChanged {
+        bpm: f32,
+    },
+    TrackParamChanged {
+        track_id: usize,
+        param: scripting::TrackParam,
+        value: f32,
+    },
+    MetronomeState {
+        enabled: bool,
+    },
+    /// The target waiting for a CC, or `None` once learning ends.
+    MidiLearning {
+        target: Option<midi::MidiTarget>,
+    },
+    MidiMappingsChanged {
+        mappings: Vec<midi::MidiMapping>,
+    },
+    /// A pattern, hook or condition of `node_id` failed.
+    ScriptError {
+        track_id: usize,
+        node_id: String,
+        error: scripting::ScriptError,
+    },
+    /// Answer to [`EngineCommand::ListAudioDevices`].
+    AudioDevices {
+        devices: Vec<audio::OutputDevice>,
+    },
+    /// Seconds the output device reports between rendering audio and it
+    /// being heard, before the configured offset.
+    OutputLatency {
+        seconds: f64,
+    },
+    /// The device playback runs on, or `None` while waiting for one to come
+    /// back after a disconnect.
+    AudioDeviceChanged {
+        device: Option<String>,
+    },
+    /// Parameters of a plugin that just loaded, with the values it runs with.
+    PluginParams {
+        track_id: usize,
+        slot: plugin::PluginSlot,
+        params: Vec<plugin::PluginParam>,
+    },
+    /// A sample added to the library by an import or a recording.
+    SampleAdded {
+        sample: SampleRef,
+    },
+    /// Post-fader levels since the previous update.
+    Meters {
+        master: audio::Level,
+        tracks: Vec<audio::Level>,
+    },
+    /// The track being recorded for, or `None` once recording stops.
+    Recording {
+        track_id: Option<usize>,
+    },
+    /// The engine thread panicked and started over without a project; it
+    /// needs `LoadProject` again.
+    Restarted,
+    Error {
+        message: String,
+    },
 }
 
+/// A running engine. It plays until [`EngineHandle::shutdown`] or until both
+/// ends are dropped.
 pub struct EngineHandle {
     pub command_tx: Sender<EngineCommand>,
     pub update_rx: Receiver<EngineUpdate>,
 }
 
+impl EngineHandle {
+    /// Has the engine fade out and stop, waiting at most `wait` for it.
+    /// Returns whether it finished in time.
+    pub fn shutdown(&self, wait: std::time::Duration) -> bool {
+        let (done, finished) = crossbeam::channel::bounded(1);
+        self.command_tx
+            .send(EngineCommand::Shutdown { done })
+            .is_ok()
+            && finished.recv_timeout(wait).is_ok()
+    }
+}
+
+/// Times the engine thread starts over after panicking before it gives up,
+/// so a project that crashes it on load doesn't do so forever.
+const MAX_ENGINE_RESTARTS: usize = 3;
+
+/// Starts the engine thread, restarting it when it panics.
+///
+/// ```no_run
+/// use aurio::{EngineCommand, EngineUpdate, spawn_engine};
+///
+/// let engine = spawn_engine();
+/// let _ = engine.command_tx.send(EngineCommand::LoadProject("Song.aurio".into()));
+/// let _ = engine.command_tx.send(EngineCommand::Play);
+/// for update in engine.update_rx.iter() {
+///     if let EngineUpdate::Error { message } = update {
+///         eprintln!("{}", message);
+///     }
+/// }
+/// ```
 pub fn spawn_engine() -> EngineHandle {
+    spawn_engine_with(true)
+}
+
+/// Starts the engine thread. A panic in it is reported as an
+/// [`EngineUpdate::Error`] and its audio stops; with `restart`, it then
+/// starts over and sends [`EngineUpdate::Restarted`].
+pub fn spawn_engine_with(restart: bool) -> EngineHandle {
     let (command_tx, command_rx) = crossbeam::channel::unbounded();
     let (update_tx, update_rx) = crossbeam::channel::unbounded();
 
+    let engine_tx = command_tx.clone();
     std::thread::spawn(move || {
-        engine_thread(command_rx, update_tx);
+        supervise_engine(restart, &update_tx, || {
+            engine_thread(command_rx.clone(), engine_tx.clone(), update_tx.clone())
+        });
     });
 
     EngineHandle {
@@ -49,340 +355,3359 @@ pub fn spawn_engine() -> EngineHandle {
     }
 }
 
+/// Runs `engine` until it returns, reporting each panic in it and, with
+/// `restart`, running it again up to [`MAX_ENGINE_RESTARTS`] times.
+fn supervise_engine(restart: bool, update_tx: &Sender<EngineUpdate>, mut engine: impl FnMut()) {
+    for restarts in 0.. {
+        // Unwinding drops the engine's state, stream included, so the
+        // output stops rather than repeating its last buffer.
+        let Err(payload) = std::panic::catch_unwind(std::panic::AssertUnwindSafe(&mut engine))
+        else {
+            return;
+        };
+        let message = panic_message(payload.as_ref());
+        tracing::error!("Engine thread panicked: {}", message);
+        let _ = update_tx.send(EngineUpdate::PlaybackState { playing: false });
+        let _ = update_tx.send(EngineUpdate::Recording { track_id: None });
+        let _ = update_tx.send(EngineUpdate::Error {
+            message: format!("The engine crashed: {}", message),
+        });
+        if !restart || restarts == MAX_ENGINE_RESTARTS {
+            return;
+        }
+        let _ = update_tx.send(EngineUpdate::Restarted);
+    }
+}
+
+/// The text a panic was raised with.
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
 struct EngineState {
     project: Option<Project>,
-    track_configs: Option<Arc<ArcSwap<Vec<audio::TrackConfig>>>>,
+    track_configs: Option<TrackConfigs>,
     sample_counter: Option<Arc<AtomicU64>>,
-    lua_runtime: Option<scripting::LuaRuntime>,
-    audio_stream: Option<cpal::Stream>,
+    morph_knobs: Option<Arc<Vec<audio::MorphKnob>>>,
+    /// Queues actions for the timing thread, which owns the script state.
+    script_tx: Option<Sender<scripting::ScriptAction>>,
+    /// Hands edited state graphs to the timing thread.
+    graph_tx: Option<Sender<Vec<LaneGraph>>>,
+    /// Parameter changes for plugins on the audio thread.
+    plugin_tx: Option<Sender<PluginCommand>>,
+    /// Notes played live on the audio thread.
+    audition_tx: Option<Sender<events::Event>>,
+    bus_volumes: Option<Arc<ArcSwap<Vec<f32>>>>,
+    /// The renderer runs without a sequencer, only for auditioning notes.
+    auditioning: bool,
+    meters: Option<Arc<audio::Meters>>,
+    /// Decoded sample library, swapped when sample files change.
+    samples: Option<Arc<ArcSwap<audio::SampleBank>>>,
+    /// When levels were last sent to the UI.
+    meters_sent: std::time::Instant,
+    project_path: Option<PathBuf>,
+    script_watcher: Option<notify::RecommendedWatcher>,
+    metronome: Arc<audio::Metronome>,
+    /// The patch from the patch editor while no renderer has it; renderers
+    /// take it when they start and it comes back here when they stop.
+    patch: Option<Box<dsp::AudioGraph>>,
+    /// Patch changes for the renderer, which applies them between blocks.
+    patch_tx: Sender<PatchChange>,
+    /// The engine's end, for changes a stopped renderer didn't get to.
+    patch_rx: Receiver<PatchChange>,
+    /// Node ids of the patch last set, to check probes against.
+    patch_nodes: Vec<u32>,
+    /// Given to renderers for what they're done with.
+    retire_tx: Sender<Retired>,
+    /// Freed here each time round, so the audio thread doesn't have to.
+    retired_rx: Receiver<Retired>,
+    fade_out: Arc<audio::FadeOut>,
+    pause: Arc<audio::Pause>,
+    midi_learn: Option<midi::MidiTarget>,
+    note_router: midi::NoteRouter,
+    osc_server: Option<osc::OscServer>,
+    /// Tempo session the transport quantizes its start to.
+    timeline: sync::Timeline,
+    /// Joined while the project syncs its tempo; the timeline and tempo
+    /// follow it.
+    #[cfg(feature = "link")]
+    link: Option<sync::LinkSession>,
+    audio_settings: audio::AudioSettings,
+    /// Kept current by the stream; playback schedules events ahead by it.
+    latency: Arc<audio::OutputLatency>,
+    /// Set while the audio settings bypass the output guard.
+    output_guard_bypass: Arc<AtomicBool>,
+    /// Latency last sent to the UI, in seconds.
+    latency_sent: f64,
+    /// Renderer state shared with the stream, so the stream can be rebuilt.
+    audio_state: Option<Arc<Mutex<AudioState>>>,
+    audio_stream: Option<audio::Stream>,
+    /// Name of the device the stream is open on.
+    audio_device: Option<String>,
+    /// Sample rate the output last opened at, which renderers are built for
+    /// unless the audio settings ask for one.
+    output_rate: Option<u32>,
+    /// Sample rate the running renderer was built for.
+    render_rate: Option<u32>,
+    /// When to next look for the configured device, while playing without it.
+    reconnect_at: Option<std::time::Instant>,
+    /// Input capture in progress, with its track and library entry.
+    recording: Option<(usize, audio::Recorder, SampleRef)>,
     playing: bool,
 }
 
-fn engine_thread(command_rx: Receiver<EngineCommand>, update_tx: Sender<EngineUpdate>) {
-    let mut state = EngineState {
-        project: None,
-        track_configs: None,
-        sample_counter: None,
-        lua_runtime: None,
-        audio_stream: None,
-        playing: false,
-    };
-
-    loop {
-        match command_rx.recv_timeout(std::time::Duration::from_millis(50)) {
-            Ok(EngineCommand::LoadProject(path)) => match Project::load(&path) {
-                Ok(project) => {
-                    println!("Project loaded successfully");
+impl EngineState {
+    fn new() -> Self {
+        let (patch_tx, patch_rx) = crossbeam::channel::bounded(AUDIO_QUEUE);
+        let (retire_tx, retired_rx) = crossbeam::channel::bounded(AUDIO_QUEUE);
+        EngineState {
+            project: None,
+            track_configs: None,
+            sample_counter: None,
+            morph_knobs: None,
+            script_tx: None,
+            graph_tx: None,
+            plugin_tx: None,
+            audition_tx: None,
+            bus_volumes: None,
+            auditioning: false,
+            meters: None,
+            samples: None,
+            meters_sent: std::time::Instant::now(),
+            project_path: None,
+            script_watcher: None,
+            metronome: Arc::new(audio::Metronome::new(120.0, 44100.0)),
+            patch: None,
+            patch_tx,
+            patch_rx,
+            patch_nodes: Vec::new(),
+            retire_tx,
+            retired_rx,
+            fade_out: Arc::new(audio::FadeOut::default()),
+            pause: Arc::new(audio::Pause::default()),
+            midi_learn: None,
+            note_router: midi::NoteRouter::default(),
+            osc_server: None,
+            timeline: sync::Timeline::new(120.0, std::time::Instant::now()),
+            #[cfg(feature = "link")]
+            link: None,
+            audio_settings: audio::AudioSettings::default(),
+            latency: Arc::new(audio::OutputLatency::default()),
+            output_guard_bypass: Arc::default(),
+            latency_sent: 0.0,
+            audio_state: None,
+            audio_stream: None,
+            audio_device: None,
+            output_rate: None,
+            render_rate: None,
+            reconnect_at: None,
+            recording: None,
+            playing: false,
+        }
+    }
+}
 
-                    state.audio_stream = None;
-                    state.playing = false;
+fn engine_thread(
+    command_rx: Receiver<EngineCommand>,
+    command_tx: Sender<EngineCommand>,
+    update_tx: Sender<EngineUpdate>,
+) {
+    let mut state = EngineState::new();
+    let _midi_inputs = midi::connect_inputs(command_tx.clone());
 
-                    let _ = update_tx.send(EngineUpdate::ProjectLoaded {
-                        project: project.clone(),
-                    });
+    loop {
+        let received = command_rx.recv_timeout(std::time::Duration::from_millis(50));
+        let _command = received
+            .as_ref()
+            .ok()
+            .map(|command| tracing::info_span!("command", kind = command.name()).entered());
+        match received {
+            Ok(EngineCommand::LoadProject(path)) => {
+                load_project(&mut state, path, &command_tx, &update_tx)
+            }
+            Ok(EngineCommand::ReloadProject(mut project)) => {
+                tracing::info!("Reloading project with updated sequences");
 
-                    state.project = Some(project);
-                }
-                Err(e) => {
+                // Script files stay authoritative over the code the UI holds.
+                if let Some(ref path) = state.project_path
+                    && let Err(e) = project.load_scripts(path)
+                {
                     let _ = update_tx.send(EngineUpdate::Error {
-                        message: format!("Failed to load project: {}", e),
+                        message: format!("Failed to reload scripts: {}", e),
                     });
                 }
-            },
-            Ok(EngineCommand::ReloadProject(project)) => {
-                println!("Reloading project with updated sequences");
 
-                if let Some(ref track_configs) = state.track_configs {
-                    let new_configs: Vec<audio::TrackConfig> = project
-                        .tracks
-                        .iter()
-                        .map(|track_data| {
-                            let mut config = audio::TrackConfig::new(
-                                track_data.id,
-                                track_data.instrument.clone(),
-                                track_data.adsr.clone(),
-                            );
-                            config.volume = track_data.volume;
-                            config.pan = track_data.pan;
-                            config
-                        })
-                        .collect();
-
-                    track_configs.store(Arc::new(new_configs));
-                    println!("Hot-swapped track configs");
+                if let Some(ref mut track_configs) = state.track_configs {
+                    track_configs.store(build_track_configs(&project));
+                    tracing::info!("Hot-swapped track configs");
+                }
+                if let Some(ref bus_volumes) = state.bus_volumes {
+                    bus_volumes.store(Arc::new(project.buses.iter().map(|b| b.volume).collect()));
                 }
+                send_graphs(&state, &project);
 
+                let library_changed = state
+                    .project
+                    .as_ref()
+                    .is_none_or(|old| old.sample_library != project.sample_library);
                 state.project = Some(project);
+                if library_changed {
+                    reload_samples(&state, &update_tx);
+                }
+            }
+            Ok(EngineCommand::RestructureProject(project)) => {
+                let restart = state.playing;
+                if state.audio_state.is_some() {
+                    stop_audio(&mut state);
+                    finish_recording(&mut state, &update_tx);
+                    let _ = update_tx.send(EngineUpdate::PlaybackState { playing: false });
+                }
+                let _ = command_tx.send(EngineCommand::ReloadProject(project));
+                if restart {
+                    let _ = command_tx.send(EngineCommand::Play);
+                }
             }
-            Ok(EngineCommand::Play) => {
-                if let Some(ref project) = state.project {
-                    if state.audio_stream.is_none() {
-                        match setup_audio(project) {
-                            Ok((stream, configs, counter, lua)) => {
-                                state.audio_stream = Some(stream);
-                                state.track_configs = Some(configs);
-                                state.sample_counter = Some(counter);
-                                state.lua_runtime = Some(lua);
-                                state.playing = true;
-
-                                let _ =
-                                    update_tx.send(EngineUpdate::PlaybackState { playing: true });
-                            }
-                            Err(e) => {
-                                let _ = update_tx.send(EngineUpdate::Error {
-                                    message: format!("Failed to start audio: {}", e),
-                                });
-                            }
+            Ok(EngineCommand::ScriptsChanged(paths)) => {
+                if let (Some(project), Some(project_path)) = (&state.project, &state.project_path)
+                    && project
+                        .sample_library
+                        .iter()
+                        .any(|sample| paths.contains(&sample.resolve(project_path)))
+                {
+                    tracing::info!("Samples changed, reloading");
+                    reload_samples(&state, &update_tx);
+                }
+                if let (Some(project), Some(project_path)) =
+                    (&mut state.project, &state.project_path)
+                    && project
+                        .script_files(project_path)
+                        .iter()
+                        .any(|script| paths.contains(script))
+                {
+                    match project.load_scripts(project_path) {
+                        Ok(()) => {
+                            tracing::info!("Lua patterns changed, reloading");
+                            let project = project.clone();
+                            send_graphs(&state, &project);
+                        }
+                        Err(e) => {
+                            let _ = update_tx.send(EngineUpdate::Error {
+                                message: format!("Failed to reload scripts: {}", e),
+                            });
                         }
-                    } else {
-                        state.playing = true;
-                        let _ = update_tx.send(EngineUpdate::PlaybackState { playing: true });
                     }
                 }
             }
+            Ok(EngineCommand::Play) => play(&mut state, &command_tx, &update_tx),
+
+            Ok(EngineCommand::NoteOn {
+                track_id,
+                pitch,
+                velocity,
+            }) => {
+                start_auditioning(&mut state, &command_tx, &update_tx);
+                if let Some(ref audition_tx) = state.audition_tx {
+                    let _ = audition_tx.try_send(events::Event::MidiEvent {
+                        track_id,
+                        pitch,
+                        velocity,
+                        is_note_on: true,
+                        expression: events::NoteExpression::default(),
+                    });
+                }
+            }
+
+            Ok(EngineCommand::NoteOff { track_id, pitch }) => {
+                if let Some(ref audition_tx) = state.audition_tx {
+                    let _ = audition_tx.try_send(events::Event::MidiEvent {
+                        track_id,
+                        pitch,
+                        velocity: 0,
+                        is_note_on: false,
+                        expression: events::NoteExpression::default(),
+                    });
+                }
+            }
+
+            Ok(EngineCommand::NoteModulation {
+                track_id,
+                pitch,
+                modulation,
+            }) => {
+                if let Some(ref audition_tx) = state.audition_tx {
+                    let _ = audition_tx.try_send(events::Event::NoteModulation {
+                        track_id,
+                        pitch,
+                        modulation,
+                    });
+                }
+            }
 
             Ok(EngineCommand::Pause) => {
+                // Audio auditioning while stopped has no transport to pause.
+                if !state.auditioning {
+                    state.pause.set(true);
+                }
                 state.playing = false;
                 let _ = update_tx.send(EngineUpdate::PlaybackState { playing: false });
             }
 
             Ok(EngineCommand::Stop) => {
-                state.audio_stream = None;
-                state.track_configs = None;
-                state.sample_counter = None;
-                state.playing = false;
+                stop_audio(&mut state);
+                finish_recording(&mut state, &update_tx);
                 let _ = update_tx.send(EngineUpdate::PlaybackState { playing: false });
                 let _ = update_tx.send(EngineUpdate::CurrentNodes {
                     track_nodes: vec![],
                 });
             }
 
-            Ok(EngineCommand::SetVariable { .. }) => {
-                // TODO
+            Ok(EngineCommand::SetVariable { name, value }) => {
+                if let Some(ref script_tx) = state.script_tx {
+                    let _ = script_tx.send(scripting::ScriptAction::SetVariable {
+                        scope: scripting::VariableScope::Global,
+                        name,
+                        value: scripting::LuaValue::Number(value),
+                    });
+                }
             }
 
-            Err(crossbeam::channel::RecvTimeoutError::Timeout) => {
-                // Timeout - continue to send updates
+            Ok(EngineCommand::GoToSection { section }) => {
+                if let Some(ref script_tx) = state.script_tx {
+                    let _ = script_tx.send(scripting::ScriptAction::GoToSection(section));
+                }
             }
-            Err(crossbeam::channel::RecvTimeoutError::Disconnected) => break,
-        }
-    }
-}
-
-struct TimingState {
-    graphs: Vec<timing::StateGraph>,
-    current_nodes: Vec<String>,
-    sequence_end_samples: Vec<u64>,
-}
 
-struct AudioState {
-    playback_states: Vec<audio::PlaybackState>,
-    pending_event: Option<events::ScheduledEvent>,
-    consumer: HeapCons<events::ScheduledEvent>,
-    track_configs: Arc<ArcSwap<Vec<audio::TrackConfig>>>,
-    sample_rate: f32,
-    num_channels: usize,
-}
+            Ok(EngineCommand::TriggerTransition {
+                track_id,
+                node_id,
+                timing,
+            }) => {
+                if let Some(ref script_tx) = state.script_tx {
+                    let _ = script_tx.send(scripting::ScriptAction::TriggerTransition {
+                        track_id,
+                        node_id,
+                        timing,
+                    });
+                }
+            }
 
-fn setup_audio(
-    project: &Project,
-) -> Result<
-    (
-        cpal::Stream,
-        Arc<ArcSwap<Vec<audio::TrackConfig>>>,
-        Arc<AtomicU64>,
-        scripting::LuaRuntime,
-    ),
-    Box<dyn std::error::Error>,
-> {
-    let lua_runtime = scripting::LuaRuntime::new()?;
+            Ok(EngineCommand::SetMorph { track_id, value }) => {
+                if let Some(knob) = state.morph_knobs.as_ref().and_then(|k| k.get(track_id)) {
+                    knob.set(value);
+                }
+            }
 
-    let track_configs: Vec<audio::TrackConfig> = project
-        .tracks
-        .iter()
-        .map(|track_data| {
-            let mut config = audio::TrackConfig::new(
-                track_data.id,
-                track_data.instrument.clone(),
-                track_data.adsr.clone(),
-            );
-            config.volume = track_data.volume;
-            config.pan = track_data.pan;
-            config
-        })
-        .collect();
+            Ok(EngineCommand::SetBpm { bpm }) => {
+                #[cfg(feature = "link")]
+                if let Some(ref mut link) = state.link {
+                    link.set_tempo(bpm as f64);
+                }
+                set_bpm(&mut state, bpm, &update_tx);
+            }
 
-    let track_configs = Arc::new(ArcSwap::from_pointee(track_configs));
-    let sample_counter = Arc::new(AtomicU64::new(0));
+            Ok(EngineCommand::SetInstrument {
+                track_id,
+                instrument,
+                adsr,
+            }) => {
+                if let Some(ref mut track_configs) = state.track_configs {
+                    track_configs.update(|configs| {
+                        if let Some(config) = configs.get_mut(track_id) {
+                            config.instrument = instrument.clone();
+                            config.adsr = adsr.clone();
+                        }
+                    });
+                }
+                if let Some(track) = state
+                    .project
+                    .as_mut()
+                    .and_then(|p| p.tracks.get_mut(track_id))
+                {
+                    track.instrument = instrument;
+                    track.adsr = adsr;
+                }
+            }
 
-    let bpm = project.bpm;
-    let sample_rate = project.sample_rate as f32;
+            Ok(EngineCommand::SetTrackParam {
+                track_id,
+                param,
+                value,
+            }) => {
+                let value = set_track_param(&mut state, track_id, param, value);
+                let _ = update_tx.send(EngineUpdate::TrackParamChanged {
+                    track_id,
+                    param,
+                    value,
+                });
+            }
 
-    let ring_buffer = HeapRb::<events::ScheduledEvent>::new(4096);
-    let (mut producer, consumer) = ring_buffer.split();
+            Ok(EngineCommand::SetBusVolume { bus, volume }) => {
+                let volume = volume.max(0.0);
+                if let Some(bus) = state.project.as_mut().and_then(|p| p.buses.get_mut(bus)) {
+                    bus.volume = volume;
+                }
+                if let Some(ref bus_volumes) = state.bus_volumes {
+                    bus_volumes.rcu(|volumes| {
+                        let mut volumes = Vec::clone(volumes);
+                        if let Some(slot) = volumes.get_mut(bus) {
+                            *slot = volume;
+                        }
+                        volumes
+                    });
+                }
+            }
 
-    let mut timing_state = TimingState {
-        graphs: project.tracks.iter().map(|t| t.graph.clone()).collect(),
-        current_nodes: project
-            .tracks
-            .iter()
-            .map(|t| t.initial_node.clone())
-            .collect(),
-        sequence_end_samples: Vec::new(),
-    };
+            Ok(EngineCommand::SetMetronome { enabled }) => {
+                state.metronome.set_enabled(enabled);
+                let _ = update_tx.send(EngineUpdate::MetronomeState { enabled });
+            }
 
-    for (track_id, (graph, current_node)) in timing_state
-        .graphs
-        .iter()
-        .zip(timing_state.current_nodes.iter())
-        .enumerate()
-    {
-        if let Some(node) = graph.get_node(current_node) {
-            let _ = timing::schedule_sequence_events(
-                &node.sequence,
-                track_id,
-                0,
-                bpm,
-                sample_rate,
-                &mut producer,
-                Some(&lua_runtime),
-            );
-            let duration = node.sequence.duration_samples(bpm, sample_rate);
-            timing_state.sequence_end_samples.push(duration as u64);
-        } else {
-            timing_state.sequence_end_samples.push(u64::MAX);
-        }
-    }
+            Ok(EngineCommand::SetPatch(source)) => {
+                match source.as_deref().map(dsp::parse_file).transpose() {
+                    Ok(graph) => set_patch(&mut state, graph, &command_tx, &update_tx),
+                    Err(e) => {
+                        let _ = update_tx.send(EngineUpdate::Error {
+                            message: format!("Patch error: {}", e),
+                        });
+                    }
+                }
+            }
 
-    let counter_timing = sample_counter.clone();
-    let lua_timing = scripting::LuaRuntime::new()?;
+            Ok(EngineCommand::ProbePatch(node_id)) => {
+                match node_id.filter(|id| !state.patch_nodes.contains(id)) {
+                    Some(id) => {
+                        let _ = update_tx.send(EngineUpdate::Error {
+                            message: format!("Patch error: no node {}", id),
+                        });
+                    }
+                    None => send_patch(&mut state, PatchChange::Probe(node_id)),
+                }
+            }
 
-    std::thread::spawn(move || {
-        timing_thread(
-            timing_state,
-            producer,
-            counter_timing,
-            bpm,
-            sample_rate,
-            lua_timing,
-        );
-    });
+            Ok(EngineCommand::SetAudioSettings(settings)) => {
+                state
+                    .latency
+                    .set_offset(settings.latency_offset_ms as f64 / 1000.0);
+                state
+                    .output_guard_bypass
+                    .store(settings.bypass_output_guard, Ordering::Relaxed);
+                // The offset and the guard alone don't need the stream reopened.
+                let reopen = settings
+                    != audio::AudioSettings {
+                        latency_offset_ms: settings.latency_offset_ms,
+                        bypass_output_guard: settings.bypass_output_guard,
+                        ..state.audio_settings.clone()
+                    };
+                state.audio_settings = settings;
+                if reopen && let Err(e) = reconnect_audio(&mut state, &command_tx, &update_tx) {
+                    let _ = update_tx.send(EngineUpdate::Error {
+                        message: format!("Failed to restart audio: {}", e),
+                    });
+                }
+            }
 
-    let host = cpal::default_host();
-    let device = host.default_output_device().ok_or("No output device")?;
-    let config = device.default_output_config()?;
-    let stream_config: cpal::StreamConfig = config.into();
+            Ok(EngineCommand::SetAudioConfig {
+                device_name,
+                sample_rate,
+                buffer_size,
+            }) => {
+                state.audio_settings.device_name = device_name;
+                state.audio_settings.sample_rate = sample_rate;
+                state.audio_settings.buffer_size = buffer_size;
+                if let Err(e) = reconnect_audio(&mut state, &command_tx, &update_tx) {
+                    let _ = update_tx.send(EngineUpdate::Error {
+                        message: format!("Failed to restart audio: {}", e),
+                    });
+                }
+            }
 
-    let num_channels = stream_config.channels as usize;
-    println!(
-        "Audio output: {} channels, {} Hz",
-        num_channels, sample_rate
-    );
+            Ok(EngineCommand::SetPluginParam {
+                track_id,
+                slot,
+                param_id,
+                value,
+            }) => {
+                if let Some(param) = state
+                    .project
+                    .as_mut()
+                    .and_then(|p| p.tracks.get_mut(track_id))
+                    .and_then(|track| track.plugin_mut(slot))
+                    .and_then(|plugin| plugin.params.iter_mut().find(|p| p.id == param_id))
+                {
+                    param.value = value;
+                }
+                if let Some(ref plugin_tx) = state.plugin_tx {
+                    let _ = plugin_tx.try_send((
+                        track_id,
+                        slot,
+                        plugin::PluginEvent::Param {
+                            id: param_id,
+                            value,
+                        },
+                    ));
+                }
+            }
 
-    let configs_snapshot = track_configs.load();
-    let playback_states: Vec<audio::PlaybackState> = configs_snapshot
-        .iter()
-        .map(|_| audio::PlaybackState::new())
-        .collect();
+            Ok(EngineCommand::AudioStreamLost) => {
+                if state.audio_stream.is_some() {
+                    // Drop the dead stream now; with no device left the loop
+                    // below keeps rescanning until one shows up.
+                    if let Err(e) = reconnect_audio(&mut state, &command_tx, &update_tx) {
+                        tracing::warn!("No audio device available: {}", e);
+                    }
+                }
+            }
 
-    let mut audio_state = AudioState {
-        playback_states,
-        pending_event: None,
-        consumer,
-        track_configs: track_configs.clone(),
-        sample_rate,
-        num_channels,
-    };
+            Ok(EngineCommand::ListAudioDevices) => {
+                match audio::output_devices(state.audio_settings.backend) {
+                    Ok(devices) => {
+                        let _ = update_tx.send(EngineUpdate::AudioDevices { devices });
+                    }
+                    Err(e) => {
+                        let _ = update_tx.send(EngineUpdate::Error {
+                            message: format!("Failed to list audio devices: {}", e),
+                        });
+                    }
+                }
+            }
 
-    let counter_audio = sample_counter.clone();
+            Ok(EngineCommand::ImportSample(source)) => {
+                if let (Some(project), Some(project_path)) =
+                    (&mut state.project, &state.project_path)
+                {
+                    match project.import_sample(project_path, &source) {
+                        Ok(sample) => {
+                            let _ = update_tx.send(EngineUpdate::SampleAdded { sample });
+                        }
+                        Err(e) => {
+                            let _ = update_tx.send(EngineUpdate::Error {
+                                message: format!("Failed to import sample: {}", e),
+                            });
+                        }
+                    }
+                }
+            }
 
-    let stream = device.build_output_stream(
-        &stream_config,
-        move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
-            audio_callback(data, &mut audio_state, &counter_audio);
-        },
-        |err| eprintln!("Audio error: {}", err),
-        None,
-    )?;
+            Ok(EngineCommand::RecordInput { track_id }) => {
+                if state.recording.is_none()
+                    && let (Some(project), Some(project_path)) =
+                        (&state.project, &state.project_path)
+                    && let Some(track) = project.tracks.get(track_id)
+                {
+                    let sample = project.new_sample(&track.name);
+                    let path = sample.resolve(project_path);
+                    let samples_dir = project_path.join("samples");
+                    let result = std::fs::create_dir_all(&samples_dir)
+                        .map_err(|e| AurioError::project_io(samples_dir, e))
+                        .and_then(|()| audio::Recorder::start(state.audio_settings.backend, &path));
+                    match result {
+                        Ok(recorder) => {
+                            state.recording = Some((track_id, recorder, sample));
+                            let _ = update_tx.send(EngineUpdate::Recording {
+                                track_id: Some(track_id),
+                            });
+                        }
+                        Err(e) => {
+                            let _ = update_tx.send(EngineUpdate::Error {
+                                message: format!("Failed to start recording: {}", e),
+                            });
+                        }
+                    }
+                }
+            }
 
-    stream.play()?;
+            Ok(EngineCommand::StopRecording) => finish_recording(&mut state, &update_tx),
 
-    Ok((stream, track_configs, sample_counter, lua_runtime))
-}
+            Ok(EngineCommand::MidiLearn { target }) => {
+                if state.project.is_some() {
+                    state.midi_learn = Some(target.clone());
+                    let _ = update_tx.send(EngineUpdate::MidiLearning {
+                        target: Some(target),
+                    });
+                }
+            }
 
-fn timing_thread(
-    mut state: TimingState,
-    mut producer: HeapProd<events::ScheduledEvent>,
-    sample_counter: Arc<AtomicU64>,
-    bpm: f32,
-    sample_rate: f32,
-    lua_runtime: scripting::LuaRuntime,
-) {
-    loop {
-        let current_sample = sample_counter.load(Ordering::Relaxed);
+            Ok(EngineCommand::CancelMidiLearn) => {
+                state.midi_learn = None;
+                let _ = update_tx.send(EngineUpdate::MidiLearning { target: None });
+            }
 
-        for track_id in 0..state.graphs.len() {
-            let end_sample = state.sequence_end_samples[track_id];
-            if current_sample >= end_sample {
-                let current_node = &state.current_nodes[track_id];
-                let graph = &state.graphs[track_id];
-                let edges = graph.get_outgoing_edges(current_node);
+            Ok(EngineCommand::ClearMidiMapping { target }) => {
+                if let Some(ref mut project) = state.project {
+                    project.midi_mappings.retain(|m| m.target != target);
+                    let _ = update_tx.send(EngineUpdate::MidiMappingsChanged {
+                        mappings: project.midi_mappings.clone(),
+                    });
+                }
+            }
 
-                let next_node = if let Some(edge) = edges.first() {
-                    edge.to.clone()
+            Ok(EngineCommand::MidiCc {
+                channel,
+                controller,
+                value,
+            }) => {
+                let Some(ref mut project) = state.project else {
+                    continue;
+                };
+                if let Some(commands) =
+                    state
+                        .note_router
+                        .slide(&project.midi_input, channel, controller, value)
+                {
+                    for command in commands {
+                        let _ = command_tx.send(command);
+                    }
+                } else if let Some(target) = state.midi_learn.take() {
+                    learn_mapping(project, midi::MidiSource::Cc, channel, controller, target);
+                    let _ = update_tx.send(EngineUpdate::MidiLearning { target: None });
+                    let _ = update_tx.send(EngineUpdate::MidiMappingsChanged {
+                        mappings: project.midi_mappings.clone(),
+                    });
                 } else {
-                    current_node.clone()
+                    run_mappings(
+                        project,
+                        midi::MidiSource::Cc,
+                        channel,
+                        controller,
+                        value,
+                        &command_tx,
+                    );
+                }
+            }
+
+            Ok(EngineCommand::MidiNote { channel, message }) => {
+                let Some(ref mut project) = state.project else {
+                    continue;
                 };
+                // Notes bound to a mapping drive it instead of playing.
+                let (pitch, velocity) = match message {
+                    events::MidiMessage::NoteOn { pitch, velocity } => (Some(pitch), velocity),
+                    events::MidiMessage::NoteOff { pitch } => (Some(pitch), 0),
+                    _ => (None, 0),
+                };
+                if let Some(pitch) = pitch
+                    && velocity > 0
+                    && let Some(target) = state.midi_learn.take()
+                {
+                    learn_mapping(project, midi::MidiSource::Note, channel, pitch, target);
+                    let _ = update_tx.send(EngineUpdate::MidiLearning { target: None });
+                    let _ = update_tx.send(EngineUpdate::MidiMappingsChanged {
+                        mappings: project.midi_mappings.clone(),
+                    });
+                } else if !pitch.is_some_and(|pitch| {
+                    run_mappings(
+                        project,
+                        midi::MidiSource::Note,
+                        channel,
+                        pitch,
+                        velocity,
+                        &command_tx,
+                    )
+                }) {
+                    for command in state
+                        .note_router
+                        .handle(&project.midi_input, channel, message)
+                    {
+                        let _ = command_tx.send(command);
+                    }
+                    // The patch's MidiIn nodes hear every note too.
+                    if let Some(pitch) = pitch
+                        && !state.patch_nodes.is_empty()
+                    {
+                        send_patch(&mut state, PatchChange::Note(pitch, velocity));
+                    }
+                }
+            }
 
-                println!(
-                    "Track {}: transitioning from {} to {}",
-                    track_id, current_node, next_node
-                );
+            Ok(EngineCommand::Shutdown { done }) => {
+                fade_out_audio(&state);
+                finish_recording(&mut state, &update_tx);
+                stop_audio(&mut state);
+                let _ = done.send(());
+                break;
+            }
 
-                let _ = producer.try_push(events::ScheduledEvent {
-                    sample_timestamp: current_sample,
-                    event: events::Event::StopAllNotes { track_id },
+            Ok(EngineCommand::TimingCrashed { message }) => {
+                tracing::error!("Timing thread panicked: {}", message);
+                if state.audio_state.is_some() {
+                    fade_out_audio(&state);
+                    stop_audio(&mut state);
+                    finish_recording(&mut state, &update_tx);
+                    let _ = update_tx.send(EngineUpdate::PlaybackState { playing: false });
+                    let _ = update_tx.send(EngineUpdate::CurrentNodes {
+                        track_nodes: vec![],
+                    });
+                }
+                let _ = update_tx.send(EngineUpdate::Error {
+                    message: format!("The sequencer crashed and playback stopped: {}", message),
                 });
+            }
 
-                state.current_nodes[track_id] = next_node.clone();
-
-                if let Some(node) = graph.get_node(&next_node) {
-                    let _ = timing::schedule_sequence_events(
-                        &node.sequence,
-                        track_id,
-                        current_sample,
-                        bpm,
-                        sample_rate,
-                        &mut producer,
-                        Some(&lua_runtime),
-                    );
-
-                    let duration = node.sequence.duration_samples(bpm, sample_rate);
-                    state.sequence_end_samples[track_id] = current_sample + duration as u64;
+            Err(crossbeam::channel::RecvTimeoutError::Timeout) => {
+                if let Some(at) = state.reconnect_at
+                    && std::time::Instant::now() >= at
+                {
+                    rescan_audio(&mut state, &command_tx, &update_tx);
                 }
             }
+            Err(crossbeam::channel::RecvTimeoutError::Disconnected) => break,
         }
-    }
-}
 
-fn audio_callback(data: &mut [f32], state: &mut AudioState, sample_counter: &Arc<AtomicU64>) {
-    let num_frames = data.len() / state.num_channels;
-    let current_sample = sample_counter.load(Ordering::Relaxed);
-    let buffer_end = current_sample + num_frames as u64;
+        state.retired_rx.try_iter().for_each(drop);
+        if let Some(ref mut track_configs) = state.track_configs {
+            track_configs.flush();
+        }
 
-    let configs = state.track_configs.load();
-    let mut events: Vec<events::ScheduledEvent> = Vec::with_capacity(64);
-    if let Some(ev) = state.pending_event.take() {
-        if ev.sample_timestamp < buffer_end {
-            events.push(ev);
-        } else {
-            state.pending_event = Some(ev);
+        if let Some(ref meters) = state.meters
+            && state.meters_sent.elapsed() >= METER_INTERVAL
+        {
+            state.meters_sent = std::time::Instant::now();
+            let _ = update_tx.send(EngineUpdate::Meters {
+                master: meters.master.take(),
+                tracks: meters.tracks.iter().map(audio::Meter::take).collect(),
+            });
+        }
+
+        let latency = state.latency.reported();
+        if state.audio_stream.is_some() && (latency - state.latency_sent).abs() >= LATENCY_CHANGE {
+            state.latency_sent = latency;
+            let _ = update_tx.send(EngineUpdate::OutputLatency { seconds: latency });
+        }
+
+        #[cfg(feature = "link")]
+        follow_link(&mut state, &update_tx);
+    }
+}
+
+/// Moves the project, the beat grid and whatever's playing to `bpm`.
+fn set_bpm(state: &mut EngineState, bpm: f32, update_tx: &Sender<EngineUpdate>) {
+    state
+        .timeline
+        .set_tempo(bpm as f64, std::time::Instant::now());
+    let rate = playback_rate(state);
+    if let Some(ref mut project) = state.project {
+        project.bpm = bpm;
+        state.metronome.set_tempo(bpm, rate as f32);
+    }
+    if let Some(ref script_tx) = state.script_tx {
+        let _ = script_tx.send(scripting::ScriptAction::SetBpm(bpm));
+    }
+    let _ = update_tx.send(EngineUpdate::BpmChanged { bpm });
+}
+
+/// Joins the Ableton Link session while the project syncs its tempo, and
+/// leaves it otherwise. Joined, the beat grid is the session's, and the
+/// project takes its tempo when another app changes it.
+#[cfg(feature = "link")]
+fn follow_link(state: &mut EngineState, update_tx: &Sender<EngineUpdate>) {
+    let Some(bpm) = state
+        .project
+        .as_ref()
+        .filter(|project| project.tempo_sync)
+        .map(|project| project.bpm)
+    else {
+        state.link = None;
+        return;
+    };
+    let link = state
+        .link
+        .get_or_insert_with(|| sync::LinkSession::join(bpm as f64));
+    state.timeline = link.timeline(std::time::Instant::now(), sync::DEFAULT_QUANTUM);
+    let tempo = state.timeline.bpm() as f32;
+    if (tempo - bpm).abs() >= LINK_TEMPO_CHANGE {
+        set_bpm(state, tempo, update_tx);
+    }
+}
+
+/// Smallest difference from the Link session's tempo the project follows, in
+/// BPM.
+#[cfg(feature = "link")]
+const LINK_TEMPO_CHANGE: f32 = 0.01;
+
+/// Swaps in the project at `path`, stopping whatever was playing.
+fn load_project(
+    state: &mut EngineState,
+    path: PathBuf,
+    command_tx: &Sender<EngineCommand>,
+    update_tx: &Sender<EngineUpdate>,
+) {
+    match Project::load(&path) {
+        Ok(project) => {
+            tracing::info!("Project loaded successfully");
+
+            // Torn down as Stop does, so the next Play starts the new project
+            // rather than resuming the old one's renderer.
+            let was_playing = state.audio_state.is_some();
+            fade_out_audio(state);
+            stop_audio(state);
+            finish_recording(state, update_tx);
+            if was_playing {
+                let _ = update_tx.send(EngineUpdate::PlaybackState { playing: false });
+            }
+
+            // Canonical, so script paths compare equal to the watcher's.
+            let path = std::fs::canonicalize(&path).unwrap_or(path);
+            state.script_watcher = match watch_scripts(&path, command_tx.clone()) {
+                Ok(watcher) => Some(watcher),
+                Err(e) => {
+                    tracing::warn!("Failed to watch {}: {}", path.display(), e);
+                    None
+                }
+            };
+            state.project_path = Some(path);
+            state.midi_learn = None;
+
+            state.osc_server = None;
+            if let Some(port) = project.osc_port {
+                match osc::OscServer::start(port, command_tx.clone()) {
+                    Ok(server) => state.osc_server = Some(server),
+                    Err(e) => {
+                        let _ = update_tx.send(EngineUpdate::Error {
+                            message: format!("Failed to open OSC port {}: {}", port, e),
+                        });
+                    }
+                }
+            }
+
+            let _ = update_tx.send(EngineUpdate::ProjectLoaded {
+                project: project.clone(),
+            });
+
+            state.project = Some(project);
+        }
+        Err(e) => {
+            let _ = update_tx.send(EngineUpdate::Error {
+                message: format!("Failed to load project: {}", e),
+            });
+        }
+    }
+}
+
+/// Starts playback, or resumes it where it was paused.
+fn play(
+    state: &mut EngineState,
+    command_tx: &Sender<EngineCommand>,
+    update_tx: &Sender<EngineUpdate>,
+) {
+    if state.auditioning {
+        stop_audio(state);
+    }
+    if state.project.is_some() {
+        if state.audio_state.is_none() {
+            match start_playback(state, command_tx, update_tx) {
+                Ok(()) => {
+                    state.playing = true;
+                    let _ = update_tx.send(EngineUpdate::PlaybackState { playing: true });
+                }
+                Err(e) => {
+                    let _ = update_tx.send(EngineUpdate::Error {
+                        message: format!("Failed to start audio: {}", e),
+                    });
+                }
+            }
+        } else {
+            state.pause.set(false);
+            state.playing = true;
+            let _ = update_tx.send(EngineUpdate::PlaybackState { playing: true });
+        }
+    }
+}
+
+/// Smallest change in the reported latency worth telling the UI about, in
+/// seconds.
+const LATENCY_CHANGE: f64 = 0.0005;
+
+/// Longest a shutdown waits for the output to fade out.
+const SHUTDOWN_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(250);
+
+/// Events the timing thread can queue ahead of the renderer.
+const EVENT_BUFFER_SIZE: usize = 4096;
+
+/// Longest the timing thread waits for the renderer to make room in a full
+/// event ring before dropping events.
+const EVENT_BUFFER_WAIT: std::time::Duration = std::time::Duration::from_millis(20);
+
+/// How often the timing thread sends the playhead to the UI.
+const PLAYHEAD_INTERVAL: std::time::Duration = std::time::Duration::from_millis(30);
+
+/// How often levels are sent to the UI while the renderer runs.
+const METER_INTERVAL: std::time::Duration = std::time::Duration::from_millis(50);
+
+/// Stops a recording in progress and adds the take to the sample library.
+fn finish_recording(state: &mut EngineState, update_tx: &Sender<EngineUpdate>) {
+    let Some((_, recorder, sample)) = state.recording.take() else {
+        return;
+    };
+    match recorder.finish() {
+        Ok(_) => {
+            if let Some(ref mut project) = state.project {
+                project.sample_library.push(sample.clone());
+            }
+            let _ = update_tx.send(EngineUpdate::SampleAdded { sample });
+        }
+        Err(message) => {
+            let _ = update_tx.send(EngineUpdate::Error { message });
+        }
+    }
+    let _ = update_tx.send(EngineUpdate::Recording { track_id: None });
+}
+
+/// Applies a track parameter change and returns the value actually set.
+fn set_track_param(
+    state: &mut EngineState,
+    track_id: usize,
+    param: scripting::TrackParam,
+    value: f32,
+) -> f32 {
+    let value = param.clamp(value);
+
+    if let Some(track) = state
+        .project
+        .as_mut()
+        .and_then(|p| p.tracks.get_mut(track_id))
+    {
+        track.set_param(param, value);
+    }
+    if let (Some(track_configs), Some(morph_knobs)) = (&mut state.track_configs, &state.morph_knobs)
+    {
+        apply_track_param(track_configs, morph_knobs, track_id, param, value);
+    }
+    value
+}
+
+/// Hands an already clamped track parameter to the renderer.
+fn apply_track_param(
+    track_configs: &mut TrackConfigs,
+    morph_knobs: &[audio::MorphKnob],
+    track_id: usize,
+    param: scripting::TrackParam,
+    value: f32,
+) {
+    use scripting::TrackParam;
+
+    if param == TrackParam::Morph {
+        if let Some(knob) = morph_knobs.get(track_id) {
+            knob.set(value);
+        }
+        return;
+    }
+    track_configs.update(|configs| {
+        if let Some(config) = configs.get_mut(track_id) {
+            match param {
+                TrackParam::Volume => config.volume = value,
+                TrackParam::Pan => config.pan = value,
+                TrackParam::Morph => {}
+                TrackParam::Mute => config.mute = value != 0.0,
+                TrackParam::Solo => config.solo = value != 0.0,
+                TrackParam::Send(bus) => {
+                    if config.sends.len() <= bus {
+                        config.sends.resize(bus + 1, 0.0);
+                    }
+                    config.sends[bus] = value;
+                }
+            }
+        }
+    });
+}
+
+/// Binds a CC or note to `target`. One control drives one target, so older
+/// bindings of either are dropped.
+fn learn_mapping(
+    project: &mut Project,
+    source: midi::MidiSource,
+    channel: u8,
+    controller: u8,
+    target: midi::MidiTarget,
+) {
+    project.midi_mappings.retain(|m| {
+        m.target != target && (m.source, m.channel, m.controller) != (source, channel, controller)
+    });
+    let mapping = midi::MidiMapping {
+        source,
+        channel,
+        controller,
+        target,
+    };
+    tracing::info!(
+        "MIDI {} -> {}",
+        mapping.source_label(),
+        mapping.target.label()
+    );
+    project.midi_mappings.push(mapping);
+}
+
+/// Sends what the mappings of a CC or note ask for. Returns whether it has
+/// any.
+fn run_mappings(
+    project: &Project,
+    source: midi::MidiSource,
+    channel: u8,
+    controller: u8,
+    value: u8,
+    command_tx: &Sender<EngineCommand>,
+) -> bool {
+    let mut mapped = false;
+    for mapping in project
+        .midi_mappings
+        .iter()
+        .filter(|m| (m.source, m.channel, m.controller) == (source, channel, controller))
+    {
+        mapped = true;
+        if let Some(command) = mapping.target.command(source, value) {
+            let _ = command_tx.send(command);
+        }
+    }
+    mapped
+}
+
+/// State of every graph the timing thread runs, by lane: each track's own
+/// graph first, in track order so a track's id is also its lane, then the
+/// tracks' layers.
+struct TimingState {
+    graphs: Vec<timing::StateGraph>,
+    /// What each lane runs, to find it again when a reload moves it.
+    lane_keys: Vec<LaneKey>,
+    /// Track each lane belongs to.
+    lane_tracks: Vec<usize>,
+    arrangement: Option<ArrangementState>,
+    current_nodes: Vec<String>,
+    sequence_end_samples: Vec<u64>,
+    loop_counts: Vec<u64>,
+    /// Node a script asked each track to move to at its next sequence boundary.
+    pending_transitions: Vec<Option<String>>,
+    /// Lanes asked to move on before their sequence ends, and when.
+    timed_transitions: Vec<(usize, timing::TransitionTiming)>,
+    /// Shared with the audio thread, which drops what cut sequences queued.
+    cuts: Arc<events::SequenceCuts>,
+    variables: scripting::VariableStore,
+    bpm: f32,
+    sample_rate: f32,
+    seed: u64,
+    update_tx: Sender<EngineUpdate>,
+    /// When the playhead was last sent to the UI.
+    playhead_sent: std::time::Instant,
+    /// Samples events are scheduled ahead of the stream by: the output
+    /// latency when playback started, held back from the playhead so it
+    /// shows what's being heard. Zero when rendering offline.
+    ahead: u64,
+    /// Runs the patterns tracks may play next ahead of their transitions.
+    /// Left out when rendering offline, where nothing can be late.
+    prefetcher: Option<timing::PatternPrefetcher>,
+    /// How long scheduling waits for room in a full event ring. Offline
+    /// rendering doesn't drain the ring while it waits, so it doesn't.
+    overflow_wait: std::time::Duration,
+    /// Shared with the audio thread, for what each track does with its notes
+    /// at a node's end.
+    track_configs: Arc<ArcSwap<Vec<audio::TrackConfig>>>,
+}
+
+/// Track id the arrangement's hooks and conditions run with. No track has
+/// it, so they only get to see global variables.
+const ARRANGEMENT_TRACK: usize = usize::MAX;
+
+/// Where the timing thread is in the project's arrangement.
+struct ArrangementState {
+    arrangement: timing::Arrangement,
+    section: String,
+    end_sample: u64,
+    loop_count: u64,
+    /// Section a script or controller asked for, taken when this one ends.
+    pending: Option<String>,
+}
+
+impl TimingState {
+    fn report_script_error(&self, track_id: usize, node_id: &str, error: scripting::ScriptError) {
+        tracing::warn!("Track {} node {}: {}", track_id, node_id, error);
+        let _ = self.update_tx.send(EngineUpdate::ScriptError {
+            track_id,
+            node_id: node_id.to_string(),
+            error,
+        });
+    }
+
+    /// Whether `lane` runs one of a track's layers rather than its own graph.
+    fn is_layer(&self, lane: usize) -> bool {
+        self.lane_tracks[lane] != lane
+    }
+
+    fn choke_mode(&self, track_id: usize) -> audio::ChokeMode {
+        self.track_configs
+            .load()
+            .get(track_id)
+            .map_or_else(Default::default, |config| config.choke)
+    }
+
+    fn report_dropped_events(&self, track_id: usize, node_id: &str, count: usize) {
+        tracing::warn!(
+            "Track {} node {}: event buffer full, dropped {} events",
+            track_id,
+            node_id,
+            count
+        );
+        let _ = self.update_tx.send(EngineUpdate::EventsDropped {
+            track_id,
+            node_id: node_id.to_string(),
+            count,
+        });
+    }
+
+    fn schedule(
+        &self,
+        lane: usize,
+        node: &timing::Node,
+        start_sample: u64,
+        producer: &mut HeapProd<events::ScheduledEvent>,
+        lua_runtime: &scripting::LuaRuntime,
+    ) {
+        let _schedule = tracing::debug_span!(
+            "schedule",
+            track = self.lane_tracks[lane],
+            node = %node.id,
+            start_sample
+        )
+        .entered();
+        let context = self.pattern_context(lane, node, start_sample);
+        let prefetched = match (&node.sequence, &self.prefetcher) {
+            (timing::Sequence::Generated(pattern), Some(prefetcher)) => {
+                prefetcher.take(pattern, &context)
+            }
+            _ => None,
+        };
+        let result = match prefetched {
+            Some(prefetched) => {
+                let script_tx = lua_runtime.action_sender();
+                for action in prefetched.actions {
+                    let _ = script_tx.send(action);
+                }
+                prefetched.events.and_then(|events| {
+                    timing::schedule_pattern_events(
+                        &node.sequence,
+                        events,
+                        &context,
+                        producer,
+                        self.overflow_wait,
+                    )
+                })
+            }
+            None => timing::schedule_sequence_events(
+                &node.sequence,
+                &context,
+                producer,
+                Some(lua_runtime),
+                self.overflow_wait,
+            ),
+        };
+        let track_id = self.lane_tracks[lane];
+        match result {
+            Ok(()) => {}
+            Err(timing::SchedulerError::Script(e)) => {
+                self.report_script_error(track_id, &node.id, scripting::ScriptError::from_lua(&e))
+            }
+            Err(timing::SchedulerError::ScriptTimeout) => self.report_script_error(
+                track_id,
+                &node.id,
+                scripting::ScriptError::timeout(scripting::SCRIPT_TIME_BUDGET),
+            ),
+            Err(timing::SchedulerError::BufferFull { dropped }) => {
+                self.report_dropped_events(track_id, &node.id, dropped)
+            }
+        }
+    }
+
+    /// Has the prefetcher run the generated patterns a lane may play once
+    /// its current sequence ends: the node it's on, one more time round, and
+    /// any node it could transition to.
+    fn prefetch_next(&self, lane: usize) {
+        let Some(prefetcher) = &self.prefetcher else {
+            return;
+        };
+        let graph = &self.graphs[lane];
+        let current_node = &self.current_nodes[lane];
+        let start_sample = self.sequence_end_samples[lane];
+
+        let mut candidates: Vec<&str> = vec![current_node];
+        candidates.extend(self.pending_transitions[lane].as_deref());
+        candidates.extend(
+            graph
+                .get_outgoing_edges(current_node)
+                .into_iter()
+                .map(|edge| edge.to.as_str()),
+        );
+        candidates.dedup();
+        for node_id in candidates {
+            if let Some(node) = graph.get_node(node_id)
+                && let timing::Sequence::Generated(pattern) = &node.sequence
+            {
+                let mut context = self.pattern_context(lane, node, start_sample);
+                context.loop_count = if node_id == current_node {
+                    self.loop_counts[lane] + 1
+                } else {
+                    0
+                };
+                prefetcher.request(pattern, &context);
+            }
+        }
+    }
+
+    /// Sends the position of every track, at most every `PLAYHEAD_INTERVAL`.
+    fn send_playhead(&mut self, current_sample: u64) {
+        if self.playhead_sent.elapsed() < PLAYHEAD_INTERVAL {
+            return;
+        }
+        self.playhead_sent = std::time::Instant::now();
+        let current_sample = current_sample.saturating_sub(self.ahead);
+
+        let samples_per_quarter = 60.0 / self.bpm as f64 * self.sample_rate as f64;
+        let positions = self
+            .graphs
+            .iter()
+            .zip(&self.current_nodes)
+            .zip(&self.sequence_end_samples)
+            .enumerate()
+            .filter(|&(lane, _)| !self.is_layer(lane))
+            .map(|(_, ((graph, node_id), &end_sample))| {
+                let beat = graph.get_node(node_id).map_or(0.0, |node| {
+                    let duration = node.sequence.duration_samples(self.bpm, self.sample_rate);
+                    let start = end_sample.saturating_sub(duration);
+                    let beat_unit = node.sequence.time_signature().1 as f64;
+                    let quarters =
+                        current_sample.saturating_sub(start) as f64 / samples_per_quarter;
+                    (quarters * beat_unit / 4.0) as f32
+                });
+                (node_id.clone(), beat)
+            })
+            .collect();
+        let _ = self.update_tx.send(EngineUpdate::Playhead { positions });
+    }
+
+    fn section_context<'a>(
+        &'a self,
+        node: &'a timing::Node,
+        start_sample: u64,
+    ) -> scripting::PatternContext<'a> {
+        scripting::PatternContext {
+            track_id: ARRANGEMENT_TRACK,
+            node_id: &node.id,
+            start_sample,
+            bpm: self.bpm,
+            sample_rate: self.sample_rate,
+            time_signature: node.sequence.time_signature(),
+            loop_count: self.arrangement.as_ref().map_or(0, |song| song.loop_count),
+            repeat: node.repeat,
+            seed: self.seed,
+            pattern_seed: 0,
+            variables: &self.variables,
+        }
+    }
+
+    fn pattern_context<'a>(
+        &'a self,
+        lane: usize,
+        node: &'a timing::Node,
+        start_sample: u64,
+    ) -> scripting::PatternContext<'a> {
+        scripting::PatternContext {
+            track_id: self.lane_tracks[lane],
+            node_id: &node.id,
+            start_sample,
+            bpm: self.bpm,
+            sample_rate: self.sample_rate,
+            time_signature: node.sequence.time_signature(),
+            loop_count: self.loop_counts[lane],
+            repeat: node.repeat,
+            seed: self.seed,
+            pattern_seed: node.sequence.pattern_seed(),
+            variables: &self.variables,
+        }
+    }
+}
+
+struct AudioState {
+    /// Every track's voices, plugins and signal, in track order.
+    tracks: Vec<Box<TrackRender>>,
+    /// Helper threads tracks are rendered on, when there are any.
+    render_pool: Option<audio::RenderPool<TrackRender>>,
+    /// Tracks a reload took out while they were playing, until they've
+    /// faded; room for [`MAX_FADING`].
+    fading_tracks: Vec<Box<audio::FadingTrack>>,
+    pending_event: Option<events::ScheduledEvent>,
+    /// Events due in the current block, kept for their capacity.
+    events: Vec<events::ScheduledEvent>,
+    consumer: HeapCons<events::ScheduledEvent>,
+    cuts: Arc<events::SequenceCuts>,
+    /// Samples events and clicks are rendered before their timestamps, so
+    /// they're heard at them; see [`TimingState::ahead`].
+    ahead: u64,
+    /// Hot-swapped track configs, ready to switch over to.
+    reload_rx: Receiver<TrackReload>,
+    /// The configs `render_configs` was derived from.
+    render_source: Arc<Vec<audio::TrackConfig>>,
+    /// Per-block copies of the track configs with morphing applied, shared
+    /// with the threads rendering the tracks.
+    render_configs: Arc<Vec<audio::TrackConfig>>,
+    morph_knobs: Arc<Vec<audio::MorphKnob>>,
+    morph_smoothers: Vec<audio::MorphSmoother>,
+    sample_rate: f32,
+    num_channels: usize,
+    /// Tracks also get their own stereo pair after the master channels.
+    track_outputs: bool,
+    metronome: Arc<audio::Metronome>,
+    /// The patch editor's patch, mixed into the master pair.
+    patch: Option<Box<dsp::AudioGraph>>,
+    patch_rx: Receiver<PatchChange>,
+    /// Room for [`MAX_BLOCK`] frames of the patch.
+    patch_buffer: Vec<f32>,
+    /// Automation of the patch's nodes due in the current block, in order.
+    patch_changes: Vec<PatchParam>,
+    /// Sends what the audio thread is done with to be freed elsewhere.
+    retire_tx: Sender<Retired>,
+    /// DC blocker and safety limiter on everything that goes out.
+    output_guard: audio::OutputGuard,
+    fade_out: Arc<audio::FadeOut>,
+    pause: Arc<audio::Pause>,
+    plugin_rx: Receiver<PluginCommand>,
+    audition_rx: Receiver<events::Event>,
+    samples: Arc<ArcSwap<audio::SampleBank>>,
+    /// The bank blocks are rendered with, held so a swapped-out one is
+    /// retired rather than freed with the last block using it.
+    sample_bank: Arc<audio::SampleBank>,
+    meters: Arc<audio::Meters>,
+    bus_volumes: Arc<ArcSwap<Vec<f32>>>,
+    /// Effects of every bus, in bus order.
+    bus_effects: Vec<Vec<plugin::PluginInstance>>,
+    /// What tracks sent to each bus this block, then the bus's output, with
+    /// room for [`MAX_BLOCK`] frames.
+    bus_buffers: Vec<[Vec<f32>; 2]>,
+}
+
+/// A patch node's parameter set by automation, at a frame of the block.
+struct PatchParam {
+    frame: u32,
+    node: u32,
+    param: timing::ParamName,
+    value: f32,
+}
+
+/// An event for the plugin in a track's slot.
+type PluginCommand = (usize, plugin::PluginSlot, plugin::PluginEvent);
+
+/// A change to the patch editor's patch, made by whoever holds it.
+enum PatchChange {
+    /// A patch prepared on the engine thread, or none to stop playing it.
+    Swap(Option<Box<dsp::AudioGraph>>),
+    Probe(Option<u32>),
+    /// A MIDI note for the patch's `MidiIn` nodes, let go at velocity 0.
+    Note(u8, u8),
+}
+
+impl PatchChange {
+    /// Applies the change to `patch`, returning the graph it replaced.
+    fn apply(self, patch: &mut Option<Box<dsp::AudioGraph>>) -> Option<Box<dsp::AudioGraph>> {
+        match self {
+            PatchChange::Swap(mut graph) => {
+                if let (Some(graph), Some(old)) = (&mut graph, patch.as_deref()) {
+                    graph.inherit_ramps(old);
+                    // The probed node may have been edited out.
+                    let _ = graph.probe(old.probed(), None);
+                }
+                std::mem::replace(patch, graph)
+            }
+            PatchChange::Probe(node_id) => {
+                if let Some(graph) = patch {
+                    // Checked against the patch's nodes before it was sent.
+                    let _ = graph.probe(node_id, None);
+                }
+                None
+            }
+            PatchChange::Note(pitch, velocity) => {
+                if let Some(graph) = patch {
+                    graph.note(pitch, velocity);
+                }
+                None
+            }
+        }
+    }
+}
+
+/// What the audio thread is done with, sent back to be freed on the engine
+/// thread. Nothing reads it; it's only there to be dropped.
+#[expect(dead_code)]
+enum Retired {
+    Patch(Box<dsp::AudioGraph>),
+    /// A reload once it's been applied, holding what it replaced.
+    Reload(TrackReload),
+    Fading(Box<audio::FadingTrack>),
+    Samples(Arc<audio::SampleBank>),
+    /// Events can hold strings from the timing thread.
+    Event(events::ScheduledEvent),
+}
+
+/// The track configs the renderer plays, changed only on the engine thread.
+/// Each change goes to the audio thread as a [`TrackReload`] built from the
+/// configs it was sent last, so it's applied there without allocating.
+struct TrackConfigs {
+    /// The latest configs, which the timing thread reads too.
+    current: Arc<ArcSwap<Vec<audio::TrackConfig>>>,
+    /// The configs the audio thread has, or will once it takes the reloads
+    /// queued for it.
+    sent: Arc<Vec<audio::TrackConfig>>,
+    reload_tx: Sender<TrackReload>,
+}
+
+impl TrackConfigs {
+    fn new(configs: Vec<audio::TrackConfig>) -> (Self, Receiver<TrackReload>) {
+        let (reload_tx, reload_rx) = crossbeam::channel::bounded(AUDIO_QUEUE);
+        let sent = Arc::new(configs);
+        let configs = Self {
+            current: Arc::new(ArcSwap::new(sent.clone())),
+            sent,
+            reload_tx,
+        };
+        (configs, reload_rx)
+    }
+
+    fn store(&mut self, configs: Vec<audio::TrackConfig>) {
+        self.current.store(Arc::new(configs));
+        self.flush();
+    }
+
+    fn update(&mut self, f: impl FnOnce(&mut Vec<audio::TrackConfig>)) {
+        let mut configs = Vec::clone(&self.current.load());
+        f(&mut configs);
+        self.store(configs);
+    }
+
+    /// Sends the audio thread the latest configs, unless it has them. When
+    /// its queue is full, they're sent on a later try.
+    fn flush(&mut self) {
+        let current = self.current.load_full();
+        if Arc::ptr_eq(&current, &self.sent) {
+            return;
+        }
+        let reload = TrackReload::new(&self.sent, current.clone());
+        if self.reload_tx.try_send(reload).is_ok() {
+            self.sent = current;
+        }
+    }
+}
+
+/// A switch from one set of track configs to the next, with everything the
+/// audio thread needs built beforehand. Once applied, it holds what it
+/// replaced and is retired.
+struct TrackReload {
+    source: Arc<Vec<audio::TrackConfig>>,
+    render_configs: Arc<Vec<audio::TrackConfig>>,
+    /// The rest is only filled in when tracks were added, removed or
+    /// reordered. The new track list, with room for every track.
+    tracks: Vec<Box<TrackRender>>,
+    /// Room for the old track list, to take the tracks kept out of.
+    slots: Vec<Option<Box<TrackRender>>>,
+    /// Renders for the tracks that are new.
+    added: Vec<Box<TrackRender>>,
+    /// Tracks taken out, to fade out as the old ones did; what there's no
+    /// room for in [`AudioState::fading_tracks`] is cut off.
+    removed: Vec<Box<audio::FadingTrack>>,
+    /// Room for a smoother per track, when there are more than before.
+    morph_smoothers: Vec<audio::MorphSmoother>,
+}
+
+impl TrackReload {
+    fn new(old: &[audio::TrackConfig], source: Arc<Vec<audio::TrackConfig>>) -> Self {
+        let mut reload = Self {
+            render_configs: Arc::new(source.iter().map(audio::morph_scratch).collect()),
+            tracks: Vec::new(),
+            slots: Vec::new(),
+            added: Vec::new(),
+            removed: Vec::new(),
+            morph_smoothers: Vec::new(),
+            source,
+        };
+        let new = &reload.source;
+        if !old.iter().map(|c| c.uid).eq(new.iter().map(|c| c.uid)) {
+            reload.tracks = Vec::with_capacity(new.len());
+            reload.slots = Vec::with_capacity(old.len());
+            reload.added = new
+                .iter()
+                .filter(|config| !old.iter().any(|c| c.uid == config.uid))
+                .map(|_| TrackRender::new(Default::default()))
+                .collect();
+            reload.removed = audio::removed_tracks(old, new)
+                .into_iter()
+                .map(Box::new)
+                .collect();
+            if new.len() > old.len() {
+                reload.morph_smoothers = Vec::with_capacity(new.len());
+            }
+        }
+        reload
+    }
+}
+
+/// Sends `retired` to be freed off the audio thread. Only when the queue is
+/// full, or nothing drains it as in offline renders, is it freed here.
+fn retire(retire_tx: &Sender<Retired>, retired: Retired) {
+    if let Err(e) = retire_tx.try_send(retired) {
+        audit::permit_alloc(|| drop(e.into_inner()));
+    }
+}
+
+/// Plugin changes and auditioned notes that can wait for the audio thread;
+/// more are dropped. Bounded queues don't allocate as they're used.
+const AUDIO_QUEUE: usize = 1024;
+
+/// Events applied in one block; any more due in it wait for the next.
+const BLOCK_EVENTS: usize = 256;
+
+/// Longest block rendered at once, which buffers and plugins are sized for;
+/// longer callbacks are rendered a block at a time.
+const MAX_BLOCK: usize = 4096;
+
+/// Removed tracks that fade out at once; past that, they're cut off.
+const MAX_FADING: usize = 64;
+
+type AudioHandles = (
+    Arc<Mutex<AudioState>>,
+    TrackConfigs,
+    Arc<AtomicU64>,
+    Arc<Vec<audio::MorphKnob>>,
+    Sender<scripting::ScriptAction>,
+    Sender<Vec<LaneGraph>>,
+    Sender<PluginCommand>,
+    Sender<events::Event>,
+    Arc<ArcSwap<Vec<f32>>>,
+    Arc<audio::Meters>,
+    Arc<ArcSwap<audio::SampleBank>>,
+);
+
+/// Watches the project directory so edits to pattern scripts are picked up live.
+fn watch_scripts(
+    project_path: &std::path::Path,
+    command_tx: Sender<EngineCommand>,
+) -> notify::Result<notify::RecommendedWatcher> {
+    use notify::Watcher;
+
+    let mut watcher = notify::RecommendedWatcher::new(
+        move |res: notify::Result<notify::Event>| match res {
+            Ok(event) => {
+                if event.kind.is_modify() || event.kind.is_create() {
+                    let _ = command_tx.send(EngineCommand::ScriptsChanged(event.paths));
+                }
+            }
+            Err(e) => tracing::warn!("Watch error: {}", e),
+        },
+        notify::Config::default(),
+    )?;
+    watcher.watch(project_path, notify::RecursiveMode::Recursive)?;
+    Ok(watcher)
+}
+
+/// Gives the timing thread the project's current state graphs. Tracks switch
+/// over at their next sequence boundary, where generated patterns run again;
+/// tracks that weren't playing before start where the playhead is.
+fn send_graphs(state: &EngineState, project: &Project) {
+    if let Some(ref graph_tx) = state.graph_tx {
+        let _ = graph_tx.send(lane_graphs(project));
+    }
+}
+
+/// What a timing lane runs, across reloads that move its track: the track,
+/// and which of its layers, or none for the track's own graph.
+type LaneKey = (audio::TrackUid, Option<usize>);
+
+/// A lane's graph as the timing thread is sent it on a reload.
+struct LaneGraph {
+    key: LaneKey,
+    track_id: usize,
+    initial_node: String,
+    graph: timing::StateGraph,
+}
+
+fn lane_graphs(project: &Project) -> Vec<LaneGraph> {
+    timing_lanes(project)
+        .map(|(key, track_id, initial_node, graph)| LaneGraph {
+            key,
+            track_id,
+            initial_node: initial_node.to_string(),
+            graph: graph.clone(),
+        })
+        .collect()
+}
+
+/// Every state graph the timing thread runs, by lane, with its key, track and
+/// initial node: the tracks' own graphs, then their layers.
+fn timing_lanes(
+    project: &Project,
+) -> impl Iterator<Item = (LaneKey, usize, &str, &timing::StateGraph)> {
+    let graphs = project
+        .tracks
+        .iter()
+        .map(|t| ((t.uid, None), t.id, t.initial_node.as_str(), &t.graph));
+    let layers = project.tracks.iter().flat_map(|t| {
+        t.layers.iter().enumerate().map(|(i, layer)| {
+            (
+                (t.uid, Some(i)),
+                t.id,
+                layer.initial_node.as_str(),
+                &layer.graph,
+            )
+        })
+    });
+    graphs.chain(layers)
+}
+
+fn build_track_configs(project: &Project) -> Vec<audio::TrackConfig> {
+    project
+        .tracks
+        .iter()
+        .map(|track_data| {
+            let mut config = audio::TrackConfig::new(
+                track_data.id,
+                track_data.instrument.clone(),
+                track_data.adsr.clone(),
+            );
+            config.uid = track_data.uid;
+            config.volume = track_data.volume;
+            config.pan = track_data.pan;
+            config.morph_target = track_data.morph.clone();
+            config.mute = track_data.mute;
+            config.solo = track_data.solo;
+            config.sends = track_data.sends.clone();
+            config.choke = track_data.choke;
+            config.velocity = track_data.velocity;
+            config.key_tracking = track_data.key_tracking;
+            config
+        })
+        .collect()
+}
+
+/// Playback before it's attached to a device or a timing thread: the renderer,
+/// and the timing side with every track's first sequence already scheduled.
+struct Playback {
+    audio_state: AudioState,
+    track_configs: TrackConfigs,
+    timing_state: TimingState,
+    producer: HeapProd<events::ScheduledEvent>,
+    lua_runtime: scripting::LuaRuntime,
+    plugin_tx: Sender<PluginCommand>,
+    audition_tx: Sender<events::Event>,
+}
+
+#[allow(clippy::too_many_arguments)]
+fn prepare_playback(
+    project: &Project,
+    project_path: Option<&std::path::Path>,
+    command_tx: &Sender<EngineCommand>,
+    update_tx: Sender<EngineUpdate>,
+    metronome: Arc<audio::Metronome>,
+    fade_out: Arc<audio::FadeOut>,
+    pause: Arc<audio::Pause>,
+    start_offset: u64,
+) -> Result<Playback, AurioError> {
+    let lua_runtime = scripting::LuaRuntime::new()?;
+
+    let (track_configs, reload_rx) = TrackConfigs::new(build_track_configs(project));
+    let morph_knobs: Arc<Vec<audio::MorphKnob>> = Arc::new(
+        project
+            .tracks
+            .iter()
+            .map(|_| audio::MorphKnob::default())
+            .collect(),
+    );
+
+    let bpm = project.bpm;
+    let sample_rate = project.sample_rate as f32;
+
+    let plugins = load_plugins(project, &update_tx);
+    let bus_effects = load_bus_effects(project, &update_tx);
+    let meters = Arc::new(audio::Meters::new(project.tracks.len()));
+    let samples = Arc::new(ArcSwap::from_pointee(load_samples(
+        project,
+        project_path,
+        &update_tx,
+    )));
+    let (plugin_tx, plugin_rx) = crossbeam::channel::bounded(AUDIO_QUEUE);
+    let (audition_tx, audition_rx) = crossbeam::channel::bounded(AUDIO_QUEUE);
+
+    let ring_buffer = HeapRb::<events::ScheduledEvent>::new(EVENT_BUFFER_SIZE);
+    let (mut producer, consumer) = ring_buffer.split();
+
+    let lanes: Vec<_> = timing_lanes(project).collect();
+    let cuts = Arc::new(events::SequenceCuts::new(project.tracks.len()));
+    let mut timing_state = TimingState {
+        graphs: lanes.iter().map(|(.., graph)| (*graph).clone()).collect(),
+        lane_keys: lanes.iter().map(|&(key, ..)| key).collect(),
+        lane_tracks: lanes.iter().map(|&(_, track_id, ..)| track_id).collect(),
+        current_nodes: lanes
+            .iter()
+            .map(|(_, _, initial_node, _)| initial_node.to_string())
+            .collect(),
+        sequence_end_samples: Vec::new(),
+        loop_counts: vec![0; lanes.len()],
+        pending_transitions: vec![None; lanes.len()],
+        timed_transitions: Vec::new(),
+        cuts: cuts.clone(),
+        variables: scripting::VariableStore::new(),
+        bpm,
+        sample_rate,
+        seed: project.seed,
+        update_tx,
+        playhead_sent: std::time::Instant::now(),
+        ahead: 0,
+        prefetcher: None,
+        overflow_wait: std::time::Duration::ZERO,
+        track_configs: track_configs.current.clone(),
+        arrangement: project
+            .arrangement
+            .clone()
+            .map(|arrangement| ArrangementState {
+                section: arrangement.initial_section.clone(),
+                arrangement,
+                end_sample: start_offset,
+                loop_count: 0,
+                pending: None,
+            }),
+    };
+    start_arrangement(&mut timing_state, &lua_runtime, command_tx, start_offset);
+
+    for lane in 0..timing_state.graphs.len() {
+        let initial_node = timing_state.current_nodes[lane].clone();
+        run_node_hooks(
+            &timing_state,
+            &lua_runtime,
+            lane,
+            &initial_node,
+            timing::Hook::OnEnter,
+            start_offset,
+        );
+        run_node_hooks(
+            &timing_state,
+            &lua_runtime,
+            lane,
+            &initial_node,
+            timing::Hook::OnStart,
+            start_offset,
+        );
+    }
+    apply_script_actions(&mut timing_state, &lua_runtime, command_tx);
+
+    for (lane, (graph, current_node)) in timing_state
+        .graphs
+        .iter()
+        .zip(timing_state.current_nodes.iter())
+        .enumerate()
+    {
+        if let Some(node) = graph.get_node(current_node) {
+            timing_state.schedule(lane, node, start_offset, &mut producer, &lua_runtime);
+            let duration = node
+                .sequence
+                .duration_samples(timing_state.bpm, sample_rate);
+            timing_state
+                .sequence_end_samples
+                .push(start_offset.saturating_add(duration));
+        } else {
+            timing_state.sequence_end_samples.push(u64::MAX);
+        }
+    }
+
+    let render_source = track_configs.sent.clone();
+    let audio_state = AudioState {
+        tracks: plugins.into_iter().map(TrackRender::new).collect(),
+        render_pool: None,
+        fading_tracks: Vec::with_capacity(MAX_FADING),
+        pending_event: None,
+        events: Vec::with_capacity(BLOCK_EVENTS),
+        consumer,
+        cuts,
+        ahead: 0,
+        reload_rx,
+        render_configs: Arc::new(render_source.iter().map(audio::morph_scratch).collect()),
+        render_source,
+        morph_knobs: morph_knobs.clone(),
+        morph_smoothers: morph_knobs
+            .iter()
+            .map(|_| audio::MorphSmoother::new(0.0))
+            .collect(),
+        sample_rate,
+        num_channels: 2,
+        track_outputs: false,
+        metronome,
+        patch: None,
+        patch_rx: crossbeam::channel::never(),
+        patch_buffer: vec![0.0; MAX_BLOCK],
+        patch_changes: Vec::with_capacity(BLOCK_EVENTS),
+        // Replaced with the engine's queue when a renderer starts.
+        retire_tx: crossbeam::channel::bounded(0).0,
+        // Offline renders are guarded too; only the live output can bypass it.
+        output_guard: audio::OutputGuard::new(Arc::default()),
+        fade_out,
+        pause,
+        plugin_rx,
+        audition_rx,
+        sample_bank: samples.load_full(),
+        samples,
+        meters,
+        bus_volumes: Arc::new(ArcSwap::from_pointee(
+            project.buses.iter().map(|b| b.volume).collect(),
+        )),
+        bus_buffers: vec![[vec![0.0; MAX_BLOCK], vec![0.0; MAX_BLOCK]]; bus_effects.len()],
+        bus_effects,
+    };
+
+    Ok(Playback {
+        audio_state,
+        track_configs,
+        timing_state,
+        producer,
+        lua_runtime,
+        plugin_tx,
+        audition_tx,
+    })
+}
+
+/// The sample rate renderers are built for: the one the audio settings ask
+/// for, or else the one the output last opened at, or else the project's.
+fn playback_rate(state: &EngineState) -> u32 {
+    state
+        .audio_settings
+        .sample_rate
+        .or(state.output_rate)
+        .or(state.project.as_ref().map(|p| p.sample_rate))
+        .unwrap_or(44_100)
+}
+
+/// Starts the renderer and the sequencer from the top, synced to the
+/// timeline and after the count-in. When the output comes up at another rate
+/// than the renderer was built for, as on a fallback device, it's started
+/// again at that one.
+fn start_playback(
+    state: &mut EngineState,
+    command_tx: &Sender<EngineCommand>,
+    update_tx: &Sender<EngineUpdate>,
+) -> Result<(), AurioError> {
+    for attempt in 0..2 {
+        #[cfg(feature = "link")]
+        follow_link(state, update_tx);
+        let rate = playback_rate(state) as f64;
+        let Some(ref project) = state.project else {
+            return Ok(());
+        };
+        let bar_start = if project.tempo_sync {
+            let now = std::time::Instant::now();
+            // Joined to Link, the grid is the session's as of just now.
+            #[cfg(not(feature = "link"))]
+            state.timeline.set_tempo(project.bpm as f64, now);
+            // Events are rendered early by the output latency, so the first
+            // bar that can still be heard on the grid is after it.
+            let latency = std::time::Duration::from_secs_f64(state.latency.seconds());
+            let bar = state
+                .timeline
+                .next_bar(now + latency, sync::DEFAULT_QUANTUM);
+            (bar - now).as_secs_f64() * rate
+        } else {
+            // The first sample events rendered early can land on.
+            state.latency.samples(rate as f32) as f64
+        } as u64;
+        // Whole bars of clicks, so events still start on the grid.
+        let count_in = project.count_in_bars.min(Project::MAX_COUNT_IN_BARS) as f64
+            * sync::DEFAULT_QUANTUM
+            * 60.0
+            / project.bpm as f64
+            * rate;
+        let start_offset = bar_start + count_in.round() as u64;
+        state.metronome.set_tempo(project.bpm, rate as f32);
+        state.metronome.set_origin(bar_start);
+        state.metronome.set_count_in_end(start_offset);
+        state.fade_out.reset();
+        state.pause.reset();
+        start_renderer(state, command_tx, update_tx, start_offset, false)?;
+        if attempt > 0 || state.render_rate == state.output_rate {
+            break;
+        }
+        stop_audio(state);
+    }
+    Ok(())
+}
+
+/// Opens the renderer with the sequencer left out when nothing is playing,
+/// so notes and patches can be heard.
+fn start_auditioning(
+    state: &mut EngineState,
+    command_tx: &Sender<EngineCommand>,
+    update_tx: &Sender<EngineUpdate>,
+) {
+    if state.audio_state.is_some() || state.project.is_none() {
+        return;
+    }
+    state.fade_out.reset();
+    state.pause.reset();
+    let mut result = start_renderer(state, command_tx, update_tx, 0, true);
+    if result.is_ok() && state.render_rate != state.output_rate {
+        // The output came up at another rate, as on a fallback device.
+        stop_audio(state);
+        result = start_renderer(state, command_tx, update_tx, 0, true);
+    }
+    match result {
+        Ok(()) => state.auditioning = true,
+        Err(e) => {
+            let _ = update_tx.send(EngineUpdate::Error {
+                message: format!("Failed to start audio: {}", e),
+            });
+        }
+    }
+}
+
+/// Swaps `graph` in as the patch editor's patch, easing in from the last.
+/// It's prepared here, so its first block on the audio thread doesn't
+/// allocate.
+fn set_patch(
+    state: &mut EngineState,
+    graph: Option<dsp::AudioGraph>,
+    command_tx: &Sender<EngineCommand>,
+    update_tx: &Sender<EngineUpdate>,
+) {
+    if graph.is_some() {
+        start_auditioning(state, command_tx, update_tx);
+    }
+    let ctx = dsp::ProcessContext {
+        sample_rate: playback_rate(state) as f32,
+    };
+    state.patch_nodes = graph
+        .as_ref()
+        .map(|graph| graph.nodes.iter().map(|node| node.id).collect())
+        .unwrap_or_default();
+    let graph = graph.map(|mut graph| {
+        graph.prepare(&ctx);
+        Box::new(graph)
+    });
+    send_patch(state, PatchChange::Swap(graph));
+}
+
+/// Hands `change` to the renderer, or makes it here while none is running.
+fn send_patch(state: &mut EngineState, change: PatchChange) {
+    if state.audio_stream.is_some() {
+        if state.patch_tx.try_send(change).is_err() {
+            tracing::warn!("Patch queue full; change dropped");
+        }
+        return;
+    }
+    park_patch(state);
+    // Freed here rather than on the audio thread.
+    drop(change.apply(&mut state.patch));
+}
+
+/// Takes the patch back from the renderer once its stream has stopped, with
+/// the changes it didn't get to made.
+fn park_patch(state: &mut EngineState) {
+    if let Some(audio_state) = &state.audio_state
+        && let Some(patch) = audio_state.lock().patch.take()
+    {
+        state.patch = Some(patch);
+    }
+    for change in state.patch_rx.try_iter() {
+        drop(change.apply(&mut state.patch));
+    }
+}
+
+/// Opens the renderer for the loaded project and connects it to the output,
+/// keeping its handles in `state`. With `audition_only` nothing is sequenced
+/// and only notes sent with `NoteOn` play.
+fn start_renderer(
+    state: &mut EngineState,
+    command_tx: &Sender<EngineCommand>,
+    update_tx: &Sender<EngineUpdate>,
+    start_offset: u64,
+    audition_only: bool,
+) -> Result<(), AurioError> {
+    let rate = playback_rate(state);
+    let Some(ref project) = state.project else {
+        return Ok(());
+    };
+    // Everything is rendered at the output's rate, whatever the project's.
+    let at_rate;
+    let project = if project.sample_rate == rate {
+        project
+    } else {
+        at_rate = Project {
+            sample_rate: rate,
+            ..project.clone()
+        };
+        &at_rate
+    };
+    // Auditioning gets a metronome of its own, which never clicks.
+    let metronome = if audition_only {
+        Arc::new(audio::Metronome::new(project.bpm, rate as f32))
+    } else {
+        state.metronome.clone()
+    };
+    let (
+        audio_state,
+        configs,
+        counter,
+        knobs,
+        script_tx,
+        graph_tx,
+        plugin_tx,
+        audition_tx,
+        bus_volumes,
+        meters,
+        samples,
+    ) = setup_audio(
+        project,
+        state.project_path.as_deref(),
+        command_tx.clone(),
+        update_tx.clone(),
+        metronome,
+        state.fade_out.clone(),
+        state.pause.clone(),
+        state.latency.samples(rate as f32),
+        start_offset,
+        audition_only,
+    )?;
+    // The old stream stops before the patch moves to the new renderer.
+    state.audio_stream = None;
+    park_patch(state);
+    {
+        let mut audio_state = audio_state.lock();
+        audio_state.patch = state.patch.take();
+        audio_state.patch_rx = state.patch_rx.clone();
+        audio_state.retire_tx = state.retire_tx.clone();
+        audio_state.output_guard = audio::OutputGuard::new(state.output_guard_bypass.clone());
+    }
+    state.audio_state = Some(audio_state);
+    state.render_rate = Some(rate);
+    state.track_configs = Some(configs);
+    state.sample_counter = Some(counter);
+    state.morph_knobs = Some(knobs);
+    state.script_tx = Some(script_tx);
+    state.graph_tx = Some(graph_tx);
+    state.plugin_tx = Some(plugin_tx);
+    state.audition_tx = Some(audition_tx);
+    state.bus_volumes = Some(bus_volumes);
+    state.meters = Some(meters);
+    state.samples = Some(samples);
+
+    if let Err(e) = connect_audio(state, command_tx, update_tx) {
+        stop_audio(state);
+        return Err(e);
+    }
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn setup_audio(
+    project: &Project,
+    project_path: Option<&std::path::Path>,
+    command_tx: Sender<EngineCommand>,
+    update_tx: Sender<EngineUpdate>,
+    metronome: Arc<audio::Metronome>,
+    fade_out: Arc<audio::FadeOut>,
+    pause: Arc<audio::Pause>,
+    ahead: u64,
+    start_offset: u64,
+    audition_only: bool,
+) -> Result<AudioHandles, AurioError> {
+    let playback = prepare_playback(
+        project,
+        project_path,
+        &command_tx,
+        update_tx,
+        metronome,
+        fade_out,
+        pause,
+        start_offset,
+    )?;
+    let script_tx = playback.lua_runtime.action_sender();
+    let (graph_tx, graph_rx) = crossbeam::channel::unbounded();
+    let sample_counter = Arc::new(AtomicU64::new(0));
+
+    let mut audio_state = playback.audio_state;
+    audio_state.ahead = ahead;
+    let track_configs = playback.track_configs;
+    let morph_knobs = audio_state.morph_knobs.clone();
+    let meters = audio_state.meters.clone();
+    let samples = audio_state.samples.clone();
+    let bus_volumes = audio_state.bus_volumes.clone();
+
+    let counter_timing = sample_counter.clone();
+    let (timing_state, producer, lua_runtime) = (
+        playback.timing_state,
+        playback.producer,
+        playback.lua_runtime,
+    );
+
+    if audition_only {
+        // Drop the first sequences, already queued, and leave the timing
+        // thread out so nothing more is.
+        while audio_state.consumer.try_pop().is_some() {}
+    } else {
+        let mut timing_state = timing_state;
+        timing_state.prefetcher = Some(timing::PatternPrefetcher::spawn());
+        timing_state.overflow_wait = EVENT_BUFFER_WAIT;
+        timing_state.ahead = ahead;
+        for lane in 0..timing_state.graphs.len() {
+            timing_state.prefetch_next(lane);
+        }
+        spawn_timing_thread(
+            timing_state,
+            producer,
+            counter_timing,
+            lua_runtime,
+            command_tx,
+            graph_rx,
+        );
+    }
+
+    Ok((
+        Arc::new(Mutex::new(audio_state)),
+        track_configs,
+        sample_counter,
+        morph_knobs,
+        script_tx,
+        graph_tx,
+        playback.plugin_tx,
+        playback.audition_tx,
+        bus_volumes,
+        meters,
+        samples,
+    ))
+}
+
+/// Frames rendered per block when bouncing offline.
+const RENDER_BLOCK: usize = 512;
+
+/// Renders the first `num_frames` frames of a project without an audio device,
+/// as fast as the machine allows, for bouncing to a file. Returns interleaved
+/// stereo. Load and script errors are printed and rendering carries on, as it
+/// would during playback.
+pub fn render_offline(
+    project: &Project,
+    project_path: Option<&std::path::Path>,
+    num_frames: usize,
+) -> Result<Vec<f32>, AurioError> {
+    let (command_tx, command_rx) = crossbeam::channel::unbounded();
+    let (update_tx, update_rx) = crossbeam::channel::unbounded();
+    let metronome = Arc::new(audio::Metronome::new(
+        project.bpm,
+        project.sample_rate as f32,
+    ));
+    let Playback {
+        mut audio_state,
+        mut track_configs,
+        mut timing_state,
+        mut producer,
+        lua_runtime,
+        plugin_tx: _,
+        audition_tx: _,
+    } = prepare_playback(
+        project,
+        project_path,
+        &command_tx,
+        update_tx,
+        metronome.clone(),
+        Arc::new(audio::FadeOut::default()),
+        Arc::new(audio::Pause::default()),
+        0,
+    )?;
+    // Nothing has to keep up with a clock, so every core can take tracks.
+    audio_state.render_pool =
+        audio::RenderPool::new(std::thread::available_parallelism().map_or(1, |n| n.get()));
+    let sample_counter = Arc::new(AtomicU64::new(0));
+
+    let mut output = vec![0.0; num_frames * 2];
+    for block in output.chunks_mut(RENDER_BLOCK * 2) {
+        // Schedule every sequence starting inside the block before rendering it;
+        // nothing is ever late when there's no clock to keep up with.
+        let block_end = sample_counter.load(Ordering::Relaxed) + (block.len() / 2) as u64;
+        apply_script_actions(&mut timing_state, &lua_runtime, &command_tx);
+        advance_tracks(
+            &mut timing_state,
+            &mut producer,
+            &lua_runtime,
+            &command_tx,
+            block_end,
+        );
+
+        // What scripts and sequences ask of the engine, applied as the engine
+        // thread would when playing live.
+        for command in command_rx.try_iter() {
+            match command {
+                EngineCommand::SetTrackParam {
+                    track_id,
+                    param,
+                    value,
+                } => apply_track_param(
+                    &mut track_configs,
+                    &audio_state.morph_knobs,
+                    track_id,
+                    param,
+                    param.clamp(value),
+                ),
+                // The timing side already keeps to the new tempo.
+                EngineCommand::SetBpm { bpm } => {
+                    metronome.set_tempo(bpm, project.sample_rate as f32)
+                }
+                // The rest only come from someone at the controls.
+                command => tracing::debug!("Offline render ignores {}", command.name()),
+            }
+        }
+        for update in update_rx.try_iter() {
+            if let EngineUpdate::Error { message } = update {
+                tracing::error!("{}", message);
+            }
+        }
+
+        // Checked like the live callback, so renders catch allocations too.
+        audit::no_alloc(|| audio_callback(block, &mut audio_state, &sample_counter));
+    }
+    Ok(output)
+}
+
+/// Decodes the project's sample library, resolving paths against the project
+/// directory. A sample that fails to load is reported and plays silent.
+fn load_samples(
+    project: &Project,
+    project_path: Option<&std::path::Path>,
+    update_tx: &Sender<EngineUpdate>,
+) -> audio::SampleBank {
+    let mut samples = audio::SampleBank::new();
+    for sample in &project.sample_library {
+        let path = match project_path {
+            Some(dir) => sample.resolve(dir),
+            None => PathBuf::from(&sample.path),
+        };
+        match audio::SampleBuffer::load(&path, sample.region.clone()) {
+            Ok(buffer) => {
+                samples.insert(sample.id.clone(), buffer);
+            }
+            Err(message) => {
+                let _ = update_tx.send(EngineUpdate::Error { message });
+            }
+        }
+    }
+    samples
+}
+
+/// Decodes the sample library again and hands it to the running renderer;
+/// voices playing a replaced sample continue from the same position.
+fn reload_samples(state: &EngineState, update_tx: &Sender<EngineUpdate>) {
+    if let (Some(samples), Some(project)) = (&state.samples, &state.project) {
+        samples.store(Arc::new(load_samples(
+            project,
+            state.project_path.as_deref(),
+            update_tx,
+        )));
+    }
+}
+
+/// Loads the effects of every bus. One that fails is reported and left out.
+fn load_bus_effects(
+    project: &Project,
+    update_tx: &Sender<EngineUpdate>,
+) -> Vec<Vec<plugin::PluginInstance>> {
+    let sample_rate = project.sample_rate as f64;
+    project
+        .buses
+        .iter()
+        .map(|bus| {
+            bus.effects
+                .iter()
+                .filter_map(|plugin_ref| {
+                    match plugin::PluginInstance::load(plugin_ref, sample_rate, MAX_BLOCK) {
+                        Ok((instance, _)) => Some(instance),
+                        Err(e) => {
+                            let _ = update_tx.send(EngineUpdate::Error {
+                                message: format!("Bus {}: {}", bus.name, e),
+                            });
+                            None
+                        }
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Instantiates every track's plugins. A plugin that fails to load is reported
+/// and left out: the track plays silent or without that effect.
+fn load_plugins(project: &Project, update_tx: &Sender<EngineUpdate>) -> Vec<plugin::TrackPlugins> {
+    let sample_rate = project.sample_rate as f64;
+    let load =
+        |track_id: usize, slot, plugin_ref: &plugin::PluginRef| match plugin::PluginInstance::load(
+            plugin_ref,
+            sample_rate,
+            MAX_BLOCK,
+        ) {
+            Ok((instance, params)) => {
+                let _ = update_tx.send(EngineUpdate::PluginParams {
+                    track_id,
+                    slot,
+                    params,
+                });
+                Some(instance)
+            }
+            Err(e) => {
+                let _ = update_tx.send(EngineUpdate::Error {
+                    message: format!("Track {}: {}", track_id, e),
+                });
+                None
+            }
+        };
+
+    project
+        .tracks
+        .iter()
+        .enumerate()
+        .map(|(track_id, track)| plugin::TrackPlugins {
+            instrument: match &track.instrument {
+                audio::Instrument::Plugin(plugin_ref) => {
+                    load(track_id, plugin::PluginSlot::Instrument, plugin_ref)
+                }
+                _ => None,
+            },
+            effects: track
+                .effects
+                .iter()
+                .enumerate()
+                .filter_map(|(i, plugin_ref)| {
+                    load(track_id, plugin::PluginSlot::Effect(i), plugin_ref)
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+/// How often the engine looks for the configured device while it's missing.
+const RESCAN_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Opens the output stream described by `settings` around an existing renderer,
+/// so the device can change while the timeline keeps going. Returns the stream,
+/// the name of the device it opened and the sample rate it runs at.
+#[cfg(feature = "audio-host")]
+fn build_stream(
+    settings: &audio::AudioSettings,
+    track_names: &[String],
+    audio_state: Arc<Mutex<AudioState>>,
+    sample_counter: Arc<AtomicU64>,
+    latency: Arc<audio::OutputLatency>,
+    command_tx: Sender<EngineCommand>,
+) -> Result<(audio::Stream, String, u32), AurioError> {
+    let (device, stream_config) = settings.open_output(track_names.len())?;
+    let name = device
+        .description()
+        .map_err(AurioError::audio_device)?
+        .name()
+        .to_string();
+
+    let num_channels = stream_config.channels as usize;
+    tracing::info!(
+        "Audio output ({:?}, {}): {} channels, {} Hz, buffer {:?}",
+        settings.backend,
+        name,
+        num_channels,
+        stream_config.sample_rate,
+        stream_config.buffer_size
+    );
+    {
+        let mut state = audio_state.lock();
+        state.num_channels = num_channels;
+        state.track_outputs = settings.track_outputs;
+        state.render_pool = audio::RenderPool::new(settings.render_threads);
+    }
+
+    let stream = device
+        .build_output_stream(
+            &stream_config,
+            move |data: &mut [f32], info: &cpal::OutputCallbackInfo| {
+                let timestamp = info.timestamp();
+                if let Some(delay) = timestamp.playback.duration_since(&timestamp.callback) {
+                    latency.report(delay.as_secs_f64());
+                }
+                // Only contended while the stream is being rebuilt.
+                match audio_state.try_lock() {
+                    Some(mut state) => {
+                        audit::no_alloc(|| audio_callback(data, &mut state, &sample_counter))
+                    }
+                    None => data.fill(0.0),
+                }
+            },
+            move |err| {
+                tracing::error!("Audio error: {}", err);
+                if matches!(
+                    err,
+                    cpal::StreamError::DeviceNotAvailable | cpal::StreamError::StreamInvalidated
+                ) {
+                    let _ = command_tx.send(EngineCommand::AudioStreamLost);
+                }
+            },
+            None,
+        )
+        .map_err(AurioError::audio_device)?;
+
+    stream.play().map_err(AurioError::audio_device)?;
+    #[cfg(feature = "jack")]
+    if settings.backend == audio::AudioBackend::Jack {
+        let names = settings.port_names(track_names);
+        let names = &names[..names.len().min(num_channels)];
+        if let Err(e) = audio::name_jack_ports(&name, names) {
+            tracing::warn!("Couldn't name the JACK ports: {}", e);
+        }
+    }
+    Ok((stream, name, stream_config.sample_rate))
+}
+
+#[cfg(not(feature = "audio-host"))]
+fn build_stream(
+    _settings: &audio::AudioSettings,
+    _track_names: &[String],
+    _audio_state: Arc<Mutex<AudioState>>,
+    _sample_counter: Arc<AtomicU64>,
+    _latency: Arc<audio::OutputLatency>,
+    _command_tx: Sender<EngineCommand>,
+) -> Result<(audio::Stream, String, u32), AurioError> {
+    Err(audio::no_audio_host())
+}
+
+/// (Re)opens the stream of a running renderer on the configured device, or on
+/// the default device when that one is missing. Until the configured device is
+/// back the engine keeps rescanning for it. Fails only when no device opens.
+fn connect_audio(
+    state: &mut EngineState,
+    command_tx: &Sender<EngineCommand>,
+    update_tx: &Sender<EngineUpdate>,
+) -> Result<(), AurioError> {
+    let (Some(project), Some(audio_state), Some(counter)) =
+        (&state.project, &state.audio_state, &state.sample_counter)
+    else {
+        return Ok(());
+    };
+    let track_names: Vec<String> = project.tracks.iter().map(|t| t.name.clone()).collect();
+    let build = |settings: &audio::AudioSettings| {
+        build_stream(
+            settings,
+            &track_names,
+            audio_state.clone(),
+            counter.clone(),
+            state.latency.clone(),
+            command_tx.clone(),
+        )
+    };
+
+    // Close the old device before opening the new one; some hosts (JACK,
+    // exclusive-mode drivers) refuse a second client on the same device.
+    state.audio_stream = None;
+    let mut on_fallback = false;
+    let result = match build(&state.audio_settings) {
+        Err(e) if state.audio_settings.device_name.is_some() => {
+            let _ = update_tx.send(EngineUpdate::Error {
+                message: format!("Audio device unavailable, using the default: {}", e),
+            });
+            on_fallback = true;
+            build(&audio::AudioSettings {
+                device_name: None,
+                ..state.audio_settings.clone()
+            })
+        }
+        result => result,
+    };
+
+    let (device, result) = match result {
+        Ok((stream, name, rate)) => {
+            state.audio_stream = Some(stream);
+            state.output_rate = Some(rate);
+            state.reconnect_at = on_fallback.then(|| std::time::Instant::now() + RESCAN_INTERVAL);
+            (Some(name), Ok(()))
+        }
+        Err(e) => {
+            state.reconnect_at = Some(std::time::Instant::now() + RESCAN_INTERVAL);
+            (None, Err(e))
+        }
+    };
+    if device != state.audio_device {
+        state.audio_device = device.clone();
+        let _ = update_tx.send(EngineUpdate::AudioDeviceChanged { device });
+    }
+    result
+}
+
+/// Reconnects the stream of a running renderer after the device or its
+/// settings changed. A renderer built for another rate than the stream now
+/// runs at is started again at that rate, from the top.
+fn reconnect_audio(
+    state: &mut EngineState,
+    command_tx: &Sender<EngineCommand>,
+    update_tx: &Sender<EngineUpdate>,
+) -> Result<(), AurioError> {
+    connect_audio(state, command_tx, update_tx)?;
+    if state.render_rate.is_none() || state.render_rate == state.output_rate {
+        return Ok(());
+    }
+    tracing::info!("Output rate changed, restarting the renderer");
+    let (playing, auditioning) = (state.playing, state.auditioning);
+    stop_audio(state);
+    finish_recording(state, update_tx);
+    if playing {
+        let _ = update_tx.send(EngineUpdate::PlaybackState { playing: false });
+        let _ = command_tx.send(EngineCommand::Play);
+    } else if auditioning {
+        start_auditioning(state, command_tx, update_tx);
+    }
+    Ok(())
+}
+
+/// Reconnects once the configured device shows up again. A stream running on
+/// the fallback device is only replaced when the configured one is listed.
+fn rescan_audio(
+    state: &mut EngineState,
+    command_tx: &Sender<EngineCommand>,
+    update_tx: &Sender<EngineUpdate>,
+) {
+    if state.audio_stream.is_some()
+        && let Some(name) = &state.audio_settings.device_name
+    {
+        let listed = audio::output_devices(state.audio_settings.backend)
+            .is_ok_and(|devices| devices.iter().any(|d| &d.name == name));
+        if !listed {
+            state.reconnect_at = Some(std::time::Instant::now() + RESCAN_INTERVAL);
+            return;
+        }
+    }
+    let _ = reconnect_audio(state, command_tx, update_tx);
+}
+
+/// Fades the output out, waiting a few buffers at most.
+fn fade_out_audio(state: &EngineState) {
+    if state.audio_stream.is_none() {
+        return;
+    }
+    state.fade_out.start();
+    // The callback may be late or gone with its device; don't hold up the
+    // caller for more than a few buffers.
+    let deadline = std::time::Instant::now() + SHUTDOWN_TIMEOUT;
+    while !state.fade_out.is_silent() && std::time::Instant::now() < deadline {
+        std::thread::sleep(std::time::Duration::from_millis(1));
+    }
+}
+
+/// Tears down playback: the stream, the renderer and the timing handles.
+fn stop_audio(state: &mut EngineState) {
+    state.audio_stream = None;
+    park_patch(state);
+    // The callback can outlive the stream and drop the renderer last, so
+    // the plugins are taken out to be deactivated and destroyed here rather
+    // than on the audio thread.
+    if let Some(audio_state) = state.audio_state.take() {
+        let mut audio_state = audio_state.lock();
+        drop(std::mem::take(&mut audio_state.tracks));
+        drop(std::mem::take(&mut audio_state.bus_effects));
+    }
+    state.audio_device = None;
+    state.reconnect_at = None;
+    state.render_rate = None;
+    state.track_configs = None;
+    state.sample_counter = None;
+    state.morph_knobs = None;
+    state.script_tx = None;
+    state.graph_tx = None;
+    state.plugin_tx = None;
+    state.audition_tx = None;
+    state.bus_volumes = None;
+    state.auditioning = false;
+    state.meters = None;
+    state.samples = None;
+    state.playing = false;
+}
+
+/// Starts [`timing_thread`], which reports a panic to the engine as
+/// [`EngineCommand::TimingCrashed`].
+fn spawn_timing_thread(
+    state: TimingState,
+    producer: HeapProd<events::ScheduledEvent>,
+    sample_counter: Arc<AtomicU64>,
+    lua_runtime: scripting::LuaRuntime,
+    command_tx: Sender<EngineCommand>,
+    graph_rx: Receiver<Vec<LaneGraph>>,
+) {
+    std::thread::spawn(move || {
+        let crash_tx = command_tx.clone();
+        let run = || {
+            timing_thread(
+                state,
+                producer,
+                sample_counter,
+                lua_runtime,
+                command_tx,
+                graph_rx,
+            )
+        };
+        if let Err(payload) = std::panic::catch_unwind(std::panic::AssertUnwindSafe(run)) {
+            let _ = crash_tx.send(EngineCommand::TimingCrashed {
+                message: panic_message(payload.as_ref()),
+            });
+        }
+    });
+}
+
+fn timing_thread(
+    mut state: TimingState,
+    mut producer: HeapProd<events::ScheduledEvent>,
+    sample_counter: Arc<AtomicU64>,
+    lua_runtime: scripting::LuaRuntime,
+    command_tx: Sender<EngineCommand>,
+    graph_rx: Receiver<Vec<LaneGraph>>,
+) {
+    loop {
+        loop {
+            match graph_rx.try_recv() {
+                Ok(lanes) => {
+                    let current_sample = sample_counter.load(Ordering::Relaxed) + state.ahead;
+                    reload_lanes(
+                        &mut state,
+                        lanes,
+                        &mut producer,
+                        &lua_runtime,
+                        &command_tx,
+                        current_sample,
+                    );
+                }
+                Err(crossbeam::channel::TryRecvError::Empty) => break,
+                // Playback was torn down.
+                Err(crossbeam::channel::TryRecvError::Disconnected) => return,
+            }
+        }
+        apply_script_actions(&mut state, &lua_runtime, &command_tx);
+        let current_sample = sample_counter.load(Ordering::Relaxed) + state.ahead;
+        advance_tracks(
+            &mut state,
+            &mut producer,
+            &lua_runtime,
+            &command_tx,
+            current_sample,
+        );
+        state.send_playhead(current_sample);
+    }
+}
+
+/// Takes reloaded graphs into the lanes running them, found by key: those
+/// keep their place in their graphs and switch over at their next sequence
+/// boundary. Lanes new to the project start on their initial node at
+/// `current_sample`, and those no longer in it stop.
+fn reload_lanes(
+    state: &mut TimingState,
+    lanes: Vec<LaneGraph>,
+    producer: &mut HeapProd<events::ScheduledEvent>,
+    lua_runtime: &scripting::LuaRuntime,
+    command_tx: &Sender<EngineCommand>,
+    current_sample: u64,
+) {
+    use timing::Hook;
+
+    let previous: Vec<Option<usize>> = lanes
+        .iter()
+        .map(|lane| state.lane_keys.iter().position(|&key| key == lane.key))
+        .collect();
+    let mut current_nodes = std::mem::take(&mut state.current_nodes);
+    let mut pending_transitions = std::mem::take(&mut state.pending_transitions);
+    state.current_nodes = lanes
+        .iter()
+        .zip(&previous)
+        .map(|(lane, old)| match old {
+            Some(old) => std::mem::take(&mut current_nodes[*old]),
+            None => lane.initial_node.clone(),
+        })
+        .collect();
+    state.pending_transitions = previous
+        .iter()
+        .map(|old| old.and_then(|old| pending_transitions[old].take()))
+        .collect();
+    state.sequence_end_samples = previous
+        .iter()
+        .map(|old| old.map_or(u64::MAX, |old| state.sequence_end_samples[old]))
+        .collect();
+    state.loop_counts = previous
+        .iter()
+        .map(|old| old.map_or(0, |old| state.loop_counts[old]))
+        .collect();
+    state.timed_transitions = std::mem::take(&mut state.timed_transitions)
+        .into_iter()
+        .filter_map(|(old, timing)| {
+            let lane = previous.iter().position(|&lane| lane == Some(old))?;
+            Some((lane, timing))
+        })
+        .collect();
+    state.lane_keys = lanes.iter().map(|lane| lane.key).collect();
+    state.lane_tracks = lanes.iter().map(|lane| lane.track_id).collect();
+    state.graphs = lanes.into_iter().map(|lane| lane.graph).collect();
+
+    let added: Vec<usize> = (0..previous.len())
+        .filter(|&lane| previous[lane].is_none())
+        .collect();
+    for &lane in &added {
+        let initial_node = state.current_nodes[lane].clone();
+        for hook in [Hook::OnEnter, Hook::OnStart] {
+            run_node_hooks(
+                state,
+                lua_runtime,
+                lane,
+                &initial_node,
+                hook,
+                current_sample,
+            );
+        }
+    }
+    apply_script_actions(state, lua_runtime, command_tx);
+    for &lane in &added {
+        if let Some(node) = state.graphs[lane].get_node(&state.current_nodes[lane]) {
+            state.schedule(lane, node, current_sample, producer, lua_runtime);
+            let duration = node.sequence.duration_samples(state.bpm, state.sample_rate);
+            state.sequence_end_samples[lane] = current_sample.saturating_add(duration);
+        }
+        state.prefetch_next(lane);
+    }
+}
+
+/// Moves every track and layer whose sequence has ended by `current_sample` on
+/// to its next node, running the hooks on the way, and schedules that node's
+/// sequence.
+fn advance_tracks(
+    state: &mut TimingState,
+    producer: &mut HeapProd<events::ScheduledEvent>,
+    lua_runtime: &scripting::LuaRuntime,
+    command_tx: &Sender<EngineCommand>,
+    current_sample: u64,
+) {
+    use timing::Hook;
+
+    advance_arrangement(state, lua_runtime, command_tx, current_sample);
+    cut_sequences(state, producer, current_sample);
+    for lane in 0..state.graphs.len() {
+        let track_id = state.lane_tracks[lane];
+        let end_sample = state.sequence_end_samples[lane];
+        if current_sample >= end_sample {
+            let current_node = state.current_nodes[lane].clone();
+            let _transition =
+                tracing::info_span!("transition", track = track_id, lane, from = %current_node)
+                    .entered();
+            run_node_hooks(
+                state,
+                lua_runtime,
+                lane,
+                &current_node,
+                Hook::OnEnd,
+                end_sample,
+            );
+            apply_script_actions(state, lua_runtime, command_tx);
+
+            let (next_node, inlet_hook) = choose_transition(state, lua_runtime, lane, end_sample);
+
+            tracing::info!("moving to {}", next_node);
+
+            // Chain from the scheduled end rather than the observed sample so
+            // late wakeups of this thread never accumulate as drift. Layers
+            // leave the track's notes to its own graph.
+            if !state.is_layer(lane) {
+                let event = match state.choke_mode(track_id) {
+                    audio::ChokeMode::Release => events::Event::StopAllNotes { track_id },
+                    audio::ChokeMode::Kill => events::Event::KillAllNotes { track_id },
+                };
+                let stop = events::ScheduledEvent {
+                    sample_timestamp: end_sample,
+                    event,
+                };
+                if timing::push_events(producer, [stop], state.overflow_wait) > 0 {
+                    state.report_dropped_events(track_id, &current_node, 1);
+                }
+            }
+
+            let looped = next_node == current_node;
+            if !looped {
+                run_node_hooks(
+                    state,
+                    lua_runtime,
+                    lane,
+                    &current_node,
+                    Hook::OnLeave,
+                    end_sample,
+                );
+            }
+
+            if looped {
+                state.loop_counts[lane] += 1;
+            } else {
+                state.loop_counts[lane] = 0;
+            }
+            state.current_nodes[lane] = next_node.clone();
+
+            if looped {
+                run_node_hooks(
+                    state,
+                    lua_runtime,
+                    lane,
+                    &next_node,
+                    Hook::OnLoop,
+                    end_sample,
+                );
+            } else {
+                if let Some(code) = inlet_hook
+                    && let Some(node) = state.graphs[lane].get_node(&next_node)
+                    && let Err(e) = lua_runtime
+                        .execute_hook(&code, &state.pattern_context(lane, node, end_sample))
+                {
+                    let mut error = scripting::ScriptError::from_lua(&e);
+                    if error.chunk.as_deref() == Some("hook") {
+                        error.chunk = Some(format!("inlet hook from {}", current_node));
+                    }
+                    state.report_script_error(track_id, &next_node, error);
+                }
+                run_node_hooks(
+                    state,
+                    lua_runtime,
+                    lane,
+                    &next_node,
+                    Hook::OnEnter,
+                    end_sample,
+                );
+            }
+            run_node_hooks(
+                state,
+                lua_runtime,
+                lane,
+                &next_node,
+                Hook::OnStart,
+                end_sample,
+            );
+            apply_script_actions(state, lua_runtime, command_tx);
+
+            let graph = &state.graphs[lane];
+            if let Some(node) = graph.get_node(&next_node) {
+                state.schedule(lane, node, end_sample, producer, lua_runtime);
+
+                let duration = node.sequence.duration_samples(state.bpm, state.sample_rate);
+                state.sequence_end_samples[lane] = end_sample.saturating_add(duration);
+            }
+            state.prefetch_next(lane);
+        }
+    }
+}
+
+/// Ends the sequences of lanes asked to move on early at the beat, bar or
+/// sample their transition's timing picks. The renderer drops the rest of
+/// what the track queued, so every graph of the track ends there too.
+fn cut_sequences(
+    state: &mut TimingState,
+    producer: &mut HeapProd<events::ScheduledEvent>,
+    current_sample: u64,
+) {
+    for (lane, timing) in std::mem::take(&mut state.timed_transitions) {
+        let Some(node) = state.graphs[lane].get_node(&state.current_nodes[lane]) else {
+            continue;
+        };
+        let end_sample = state.sequence_end_samples[lane];
+        let duration = node.sequence.duration_samples(state.bpm, state.sample_rate);
+        let cut = timing.boundary(
+            &node.sequence,
+            end_sample.saturating_sub(duration),
+            current_sample,
+            state.bpm,
+            state.sample_rate,
+        );
+        if cut >= end_sample {
+            continue;
+        }
+
+        let track_id = state.lane_tracks[lane];
+        state.cuts.cut(track_id, cut);
+        let transition = events::ScheduledEvent {
+            sample_timestamp: cut,
+            event: events::Event::NodeTransition {
+                track_id,
+                new_node_id: state.pending_transitions[lane].clone().unwrap_or_default(),
+            },
+        };
+        if timing::push_events(producer, [transition], state.overflow_wait) > 0 {
+            state.report_dropped_events(track_id, &state.current_nodes[lane], 1);
+        }
+        for (&owner, end_sample) in state
+            .lane_tracks
+            .iter()
+            .zip(&mut state.sequence_end_samples)
+        {
+            if owner == track_id {
+                *end_sample = (*end_sample).min(cut);
+            }
+        }
+    }
+}
+
+/// Starts the arrangement, if there is one, on its initial section. Tracks
+/// that section's cues send somewhere start there instead of on their own
+/// initial node.
+fn start_arrangement(
+    state: &mut TimingState,
+    lua_runtime: &scripting::LuaRuntime,
+    command_tx: &Sender<EngineCommand>,
+    start_sample: u64,
+) {
+    let Some(song) = &state.arrangement else {
+        return;
+    };
+    let section = song.section.clone();
+    start_section(state, lua_runtime, &section, start_sample, false);
+    apply_script_actions(state, lua_runtime, command_tx);
+    for ((current, pending), graph) in state
+        .current_nodes
+        .iter_mut()
+        .zip(&mut state.pending_transitions)
+        .zip(&state.graphs)
+    {
+        if let Some(node_id) = pending.take_if(|node_id| graph.get_node(node_id).is_some()) {
+            *current = node_id;
+        }
+    }
+}
+
+/// Moves the arrangement on once its section ends, the way
+/// [`advance_tracks`] moves a track, and carries out the next section's cues.
+/// Runs before the tracks, so cues reach tracks ending on the same sample.
+fn advance_arrangement(
+    state: &mut TimingState,
+    lua_runtime: &scripting::LuaRuntime,
+    command_tx: &Sender<EngineCommand>,
+    current_sample: u64,
+) {
+    use timing::Hook;
+
+    let Some(song) = &state.arrangement else {
+        return;
+    };
+    if current_sample < song.end_sample {
+        return;
+    }
+    let end_sample = song.end_sample;
+    let current = song.section.clone();
+    let _section = tracing::info_span!("section", from = %current).entered();
+    run_section_hooks(state, lua_runtime, &current, Hook::OnEnd, end_sample);
+    apply_script_actions(state, lua_runtime, command_tx);
+
+    let (next, inlet_hook) = choose_section(state, lua_runtime, end_sample);
+    let looped = next == current;
+    if !looped {
+        run_section_hooks(state, lua_runtime, &current, Hook::OnLeave, end_sample);
+    }
+    if let Some(song) = &mut state.arrangement {
+        song.loop_count = if looped { song.loop_count + 1 } else { 0 };
+        song.section = next.clone();
+    }
+    if let Some(code) = inlet_hook
+        && let Some(song) = &state.arrangement
+        && let Some(node) = song.arrangement.graph.get_node(&next)
+        && let Err(e) = lua_runtime.execute_hook(&code, &state.section_context(node, end_sample))
+    {
+        tracing::warn!(
+            "Arrangement section {}: inlet hook from {}: {}",
+            next,
+            current,
+            scripting::ScriptError::from_lua(&e)
+        );
+    }
+    start_section(state, lua_runtime, &next, end_sample, looped);
+    apply_script_actions(state, lua_runtime, command_tx);
+}
+
+/// Runs a section's hooks from its start, queues its cues unless it's
+/// looping, and works out when it ends.
+fn start_section(
+    state: &mut TimingState,
+    lua_runtime: &scripting::LuaRuntime,
+    section: &str,
+    start_sample: u64,
+    looped: bool,
+) {
+    use timing::Hook;
+
+    let Some(song) = &mut state.arrangement else {
+        return;
+    };
+    let duration = song.arrangement.graph.get_node(section).map_or(0, |node| {
+        node.sequence.duration_samples(state.bpm, state.sample_rate)
+    });
+    // A section with no length would end over and over; it stays instead.
+    song.end_sample = if duration == 0 {
+        u64::MAX
+    } else {
+        start_sample.saturating_add(duration)
+    };
+
+    if looped {
+        run_section_hooks(state, lua_runtime, section, Hook::OnLoop, start_sample);
+    } else {
+        tracing::info!("Arrangement: playing section {}", section);
+        let _ = state.update_tx.send(EngineUpdate::Section {
+            section: section.to_string(),
+        });
+        let script_tx = lua_runtime.action_sender();
+        for action in song.arrangement.actions(section) {
+            let _ = script_tx.send(action);
+        }
+        run_section_hooks(state, lua_runtime, section, Hook::OnEnter, start_sample);
+    }
+    run_section_hooks(state, lua_runtime, section, Hook::OnStart, start_sample);
+}
+
+/// Picks the arrangement's next section: one asked for wins, then the first
+/// edge whose condition holds, else the section plays again.
+fn choose_section(
+    state: &mut TimingState,
+    lua_runtime: &scripting::LuaRuntime,
+    end_sample: u64,
+) -> (String, Option<String>) {
+    let Some(song) = &mut state.arrangement else {
+        return (String::new(), None);
+    };
+    if let Some(requested) = song.pending.take() {
+        if song.arrangement.graph.get_node(&requested).is_some() {
+            return (requested, None);
+        }
+        tracing::warn!("Arrangement: cannot go to unknown section {}", requested);
+    }
+
+    let Some(song) = &state.arrangement else {
+        return (String::new(), None);
+    };
+    let graph = &song.arrangement.graph;
+    let current = &song.section;
+    let Some(node) = graph.get_node(current) else {
+        let first = graph.nodes.first().map(|n| n.id.clone());
+        return (first.unwrap_or_else(|| current.clone()), None);
+    };
+    let context = state.section_context(node, end_sample);
+    if context.remaining().is_some_and(|remaining| remaining > 0) {
+        return (current.clone(), None);
+    }
+    let edge = choose_edge(
+        graph.get_outgoing_edges(current),
+        &context,
+        |edge| lua_runtime.evaluate_condition(&edge.condition, &context),
+        |edge, e| {
+            tracing::warn!(
+                "Arrangement section {}: condition to {}: {}",
+                current,
+                edge.to,
+                scripting::ScriptError::from_lua(&e)
+            )
+        },
+    );
+    match edge {
+        Some(edge) => (edge.to.clone(), edge.inlet_hook.clone()),
+        None => (current.clone(), None),
+    }
+}
+
+fn run_section_hooks(
+    state: &TimingState,
+    lua_runtime: &scripting::LuaRuntime,
+    section: &str,
+    hook: timing::Hook,
+    start_sample: u64,
+) {
+    let Some(node) = state
+        .arrangement
+        .as_ref()
+        .and_then(|song| song.arrangement.graph.get_node(section))
+    else {
+        return;
+    };
+    for (_, code) in node.hooks.iter().filter(|(kind, _)| *kind == hook) {
+        let context = state.section_context(node, start_sample);
+        if let Err(e) = lua_runtime.execute_hook(code, &context) {
+            tracing::warn!(
+                "Arrangement section {}: {:?} hook: {}",
+                section,
+                hook,
+                scripting::ScriptError::from_lua(&e)
+            );
+        }
+    }
+}
+
+/// Picks the node a track or layer plays next: a transition requested by a script wins,
+/// then the first outgoing edge whose condition holds. Also returns that edge's
+/// inlet hook. With no match the lane stays on its current node.
+fn choose_transition(
+    state: &mut TimingState,
+    lua_runtime: &scripting::LuaRuntime,
+    lane: usize,
+    end_sample: u64,
+) -> (String, Option<String>) {
+    let graph = &state.graphs[lane];
+    if let Some(requested) = state.pending_transitions[lane].take() {
+        if graph.get_node(&requested).is_some() {
+            return (requested, None);
+        }
+        tracing::warn!(
+            "Track {}: cannot transition to unknown node {}",
+            state.lane_tracks[lane],
+            requested
+        );
+    }
+
+    let current_node = &state.current_nodes[lane];
+    let Some(node) = graph.get_node(current_node) else {
+        // The node was removed from the graph while it played.
+        let first = graph.nodes.first().map(|n| n.id.clone());
+        return (first.unwrap_or_else(|| current_node.clone()), None);
+    };
+    let context = state.pattern_context(lane, node, end_sample);
+    if context.remaining().is_some_and(|remaining| remaining > 0) {
+        return (current_node.clone(), None);
+    }
+    let edge = choose_edge(
+        graph.get_outgoing_edges(current_node),
+        &context,
+        |edge| lua_runtime.evaluate_condition(&edge.condition, &context),
+        |edge, e| {
+            let mut error = scripting::ScriptError::from_lua(&e);
+            if error.chunk.as_deref() == Some("condition") {
+                error.chunk = Some(format!("condition to {}", edge.to));
+            }
+            state.report_script_error(state.lane_tracks[lane], &edge.from, error);
+        },
+    );
+    match edge {
+        Some(edge) => (edge.to.clone(), edge.inlet_hook.clone()),
+        None => (current_node.clone(), None),
+    }
+}
+
+/// The edge to take out of a node: a weighted one picked at random among
+/// those whose condition passes, else the first unweighted one that passes.
+/// Without weighted edges, conditions after the first passing one aren't
+/// run.
+fn choose_edge<'a>(
+    edges: Vec<&'a timing::Edge>,
+    context: &scripting::PatternContext,
+    mut passes: impl FnMut(&timing::Edge) -> Result<bool, mlua::Error>,
+    mut report: impl FnMut(&timing::Edge, mlua::Error),
+) -> Option<&'a timing::Edge> {
+    let weighted = edges.iter().any(|edge| edge.weight.is_some());
+    let mut first = None;
+    let mut candidates = Vec::new();
+    for edge in edges {
+        match passes(edge) {
+            Ok(true) if edge.weight.is_some() => candidates.push(edge),
+            Ok(true) if !weighted => return Some(edge),
+            Ok(true) => {
+                first.get_or_insert(edge);
+            }
+            Ok(false) => {}
+            Err(e) => report(edge, e),
+        }
+    }
+    timing::pick_weighted(&candidates, context.roll()).or(first)
+}
+
+fn run_node_hooks(
+    state: &TimingState,
+    lua_runtime: &scripting::LuaRuntime,
+    lane: usize,
+    node_id: &str,
+    hook: timing::Hook,
+    start_sample: u64,
+) {
+    let Some(node) = state.graphs[lane].get_node(node_id) else {
+        return;
+    };
+    for (_, code) in node.hooks.iter().filter(|(kind, _)| *kind == hook) {
+        let context = state.pattern_context(lane, node, start_sample);
+        if let Err(e) = lua_runtime.execute_hook(code, &context) {
+            let mut error = scripting::ScriptError::from_lua(&e);
+            if error.chunk.as_deref() == Some("hook") {
+                error.chunk = Some(format!("{:?} hook", hook));
+            }
+            state.report_script_error(state.lane_tracks[lane], &node.id, error);
+        }
+    }
+}
+
+/// Applies what scripts queued. Variables, tempo and transitions belong to the
+/// timing thread; track parameters are forwarded to the engine thread.
+fn apply_script_actions(
+    state: &mut TimingState,
+    lua_runtime: &scripting::LuaRuntime,
+    command_tx: &Sender<EngineCommand>,
+) {
+    use scripting::{ScriptAction, VariableScope};
+
+    for action in lua_runtime.drain_actions() {
+        match action {
+            ScriptAction::SetVariable { scope, name, value } => match scope {
+                VariableScope::Node { track_id, node_id } => state
+                    .variables
+                    .set_node_var(track_id, &node_id, &name, value),
+                VariableScope::Track(track_id) => {
+                    state.variables.set_track_var(track_id, &name, value)
+                }
+                VariableScope::Global => state.variables.set_global(&name, value),
+            },
+            ScriptAction::SetBpm(bpm) => {
+                // The engine echoes tempo changes back to us; only report new ones.
+                if bpm != state.bpm {
+                    state.bpm = bpm;
+                    let _ = command_tx.send(EngineCommand::SetBpm { bpm });
+                }
+            }
+            ScriptAction::TriggerTransition {
+                track_id,
+                node_id,
+                timing,
+            } => {
+                // Goes to whichever of the track's graphs has the node.
+                let lane = (0..state.graphs.len())
+                    .find(|&lane| {
+                        state.lane_tracks[lane] == track_id
+                            && state.graphs[lane].get_node(&node_id).is_some()
+                    })
+                    .unwrap_or(track_id);
+                if let Some(pending) = state.pending_transitions.get_mut(lane) {
+                    *pending = Some(node_id);
+                    if timing != timing::TransitionTiming::FinishSequence {
+                        state.timed_transitions.push((lane, timing));
+                    }
+                }
+            }
+            ScriptAction::SetParam {
+                track_id,
+                param,
+                value,
+            } => {
+                let _ = command_tx.send(EngineCommand::SetTrackParam {
+                    track_id,
+                    param,
+                    value,
+                });
+            }
+            ScriptAction::GoToSection(section) => {
+                if let Some(song) = &mut state.arrangement {
+                    song.pending = Some(section);
+                }
+            }
+        }
+    }
+}
+
+/// Renders the patch editor's patch into `state.patch_buffer`, or silence
+/// when there's none, after making the changes the engine sent. Automation
+/// sets its nodes' parameters at their frames, heard from the patch's next
+/// block of [`dsp::BLOCK_SIZE`] frames.
+fn render_patch(state: &mut AudioState, num_frames: usize) {
+    while let Ok(change) = state.patch_rx.try_recv() {
+        if let Some(old) = change.apply(&mut state.patch) {
+            retire(&state.retire_tx, Retired::Patch(old));
+        }
+    }
+    let buffer = &mut state.patch_buffer[..num_frames];
+    buffer.fill(0.0);
+    let Some(graph) = &mut state.patch else {
+        state.patch_changes.clear();
+        return;
+    };
+    let ctx = dsp::ProcessContext {
+        sample_rate: state.sample_rate,
+    };
+    let mut changes = state.patch_changes.drain(..).peekable();
+    let mut start = 0;
+    while start < num_frames {
+        while let Some(change) = changes.next_if(|change| change.frame as usize <= start) {
+            // Only trying a name tells whether the node has it, and saying it
+            // doesn't allocates.
+            audit::permit_alloc(|| {
+                let _ = graph.set_param(change.node, change.param.as_str(), change.value);
+            });
+        }
+        let end = changes
+            .peek()
+            .map_or(num_frames, |change| change.frame as usize)
+            .min(num_frames);
+        graph.process(&mut buffer[start..end], &ctx);
+        start = end;
+    }
+}
+
+fn audio_callback(data: &mut [f32], state: &mut AudioState, sample_counter: &Arc<AtomicU64>) {
+    thread_local! {
+        static REGISTERED: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+    }
+    // arc-swap allocates on a thread's first load, once, for its bookkeeping.
+    if !REGISTERED.get() {
+        audit::permit_alloc(|| drop(state.samples.load()));
+        REGISTERED.set(true);
+    }
+    let block_len = MAX_BLOCK * state.num_channels.max(1);
+    for block in data.chunks_mut(block_len) {
+        render_block(block, state, sample_counter);
+    }
+}
+
+/// Sorts a block's events by time, keeping those on the same sample in the
+/// order they came. In place, as the standard sort may take its scratch space
+/// from the heap; the queue comes nearly in order, so it's close to one pass.
+fn sort_events(events: &mut [events::ScheduledEvent]) {
+    for i in 1..events.len() {
+        let mut j = i;
+        while j > 0 && events[j - 1].sample_timestamp > events[j].sample_timestamp {
+            events.swap(j - 1, j);
+            j -= 1;
+        }
+    }
+}
+
+/// Renders one block of at most [`MAX_BLOCK`] frames.
+fn render_block(data: &mut [f32], state: &mut AudioState, sample_counter: &Arc<AtomicU64>) {
+    // Paused: the clock stands still and events wait, so Play picks up from
+    // the same sample. The fade still runs so stopping finds the output silent.
+    if state.pause.is_halted() {
+        data.fill(0.0);
+        state
+            .fade_out
+            .apply(data, state.num_channels, state.sample_rate);
+        return;
+    }
+
+    let num_frames = data.len() / state.num_channels;
+    // Rendered ahead of the events' timestamps by the output latency, so
+    // they're heard at them.
+    let current_sample = sample_counter.load(Ordering::Relaxed) + state.ahead;
+    let buffer_end = current_sample.saturating_add(num_frames as u64);
+
+    update_render_configs(state, num_frames);
+    let samples = state.samples.load();
+    if !Arc::ptr_eq(&samples, &state.sample_bank) {
+        let old = std::mem::replace(&mut state.sample_bank, Arc::clone(&samples));
+        retire(&state.retire_tx, Retired::Samples(old));
+    }
+    drop(samples);
+    let events = &mut state.events;
+    if let Some(ev) = state.pending_event.take() {
+        if state.cuts.drops(&ev) {
+            retire(&state.retire_tx, Retired::Event(ev));
+        } else if ev.sample_timestamp < buffer_end {
+            events.push(ev);
+        } else {
+            state.pending_event = Some(ev);
         }
     }
 
     while state.pending_event.is_none() {
         match state.consumer.try_pop() {
-            Some(ev) if ev.sample_timestamp < buffer_end => events.push(ev),
+            Some(ev) if state.cuts.drops(&ev) => retire(&state.retire_tx, Retired::Event(ev)),
+            Some(ev) if ev.sample_timestamp < buffer_end && events.len() < BLOCK_EVENTS => {
+                events.push(ev)
+            }
             Some(ev) => {
                 state.pending_event = Some(ev);
                 break;
@@ -391,89 +3716,709 @@ fn audio_callback(data: &mut [f32], state: &mut AudioState, sample_counter: &Arc
         }
     }
 
-    events.sort_by_key(|e| e.sample_timestamp);
-    data.fill(0.0);
+    sort_events(events);
 
-    let mut frame = 0;
-    let mut event_idx = 0;
+    // Everything that reaches across tracks is sorted out here, so each track
+    // can then be rendered on its own.
+    let configs = &state.render_configs;
+    for event in state.audition_rx.try_iter() {
+        queue_event(
+            &mut state.tracks,
+            configs,
+            &mut state.patch_changes,
+            event,
+            0,
+        );
+    }
 
-    while frame < num_frames {
-        while event_idx < events.len() {
-            let event_frame = events[event_idx]
-                .sample_timestamp
-                .saturating_sub(current_sample) as usize;
-            if event_frame > frame {
-                break;
+    for (track_id, slot, event) in state.plugin_rx.try_iter() {
+        if let Some(plugin) = state
+            .tracks
+            .get_mut(track_id)
+            .and_then(|track| track.plugins.get_mut(slot))
+        {
+            plugin.queue(0, event);
+        }
+    }
+
+    for event in state.events.drain(..) {
+        let frame = event.sample_timestamp.saturating_sub(current_sample) as u32;
+        queue_event(
+            &mut state.tracks,
+            configs,
+            &mut state.patch_changes,
+            event.event,
+            frame,
+        );
+    }
+
+    let block = TrackBlock {
+        configs: state.render_configs.clone(),
+        samples: state.sample_bank.clone(),
+        sample_rate: state.sample_rate,
+        num_frames,
+    };
+    match &mut state.render_pool {
+        Some(pool) => pool.run(&mut state.tracks, &block),
+        None => {
+            for (index, track) in state.tracks.iter_mut().enumerate() {
+                track.render(index, &block);
             }
-            process_event(&mut state.playback_states, &configs, &events[event_idx]);
-            event_idx += 1;
         }
+    }
 
-        render_frame(
-            &mut data[frame * state.num_channels..(frame + 1) * state.num_channels],
-            &mut state.playback_states,
-            &configs,
-            state.sample_rate,
+    let (configs, samples) = (&block.configs, &block.samples);
+    let any_solo = configs.iter().any(|c| c.solo);
+    for ((meter, config), track) in state
+        .meters
+        .tracks
+        .iter()
+        .zip(configs.iter())
+        .zip(&state.tracks)
+    {
+        let [left, right] = &track.buffers;
+        let [l_gains, r_gains] = &track.gains;
+        let gate = if audible(config, any_solo) { 1.0 } else { 0.0 };
+        meter.record(
+            (0..num_frames)
+                .flat_map(|i| [left[i] * l_gains[i] * gate, right[i] * r_gains[i] * gate]),
+        );
+    }
+
+    for [left, right] in &mut state.bus_buffers {
+        left[..num_frames].fill(0.0);
+        right[..num_frames].fill(0.0);
+    }
+    for (config, track) in configs.iter().zip(&state.tracks) {
+        if !audible(config, any_solo) {
+            continue;
+        }
+        let [left, right] = &track.buffers;
+        let [l_gains, r_gains] = &track.gains;
+        for (&level, [bus_left, bus_right]) in config.sends.iter().zip(&mut state.bus_buffers) {
+            if level <= 0.0 {
+                continue;
+            }
+            for frame in 0..num_frames {
+                bus_left[frame] += left[frame] * l_gains[frame] * level;
+                bus_right[frame] += right[frame] * r_gains[frame] * level;
+            }
+        }
+    }
+    for (effects, [left, right]) in state.bus_effects.iter_mut().zip(&mut state.bus_buffers) {
+        for effect in effects {
+            effect.process(&mut left[..num_frames], &mut right[..num_frames]);
+        }
+    }
+    let bus_volumes = state.bus_volumes.load();
+    render_patch(state, num_frames);
+    // Tracks a reload took out are freed once they've faded.
+    let mut i = 0;
+    while i < state.fading_tracks.len() {
+        if state.fading_tracks[i].is_silent() {
+            let track = state.fading_tracks.swap_remove(i);
+            retire(&state.retire_tx, Retired::Fading(track));
+        } else {
+            i += 1;
+        }
+    }
+
+    data.fill(0.0);
+    for (frame, output) in data.chunks_exact_mut(state.num_channels).enumerate() {
+        mix_frame(
+            output,
+            frame,
+            &state.tracks,
+            configs,
+            any_solo,
+            state.track_outputs,
         );
-        frame += 1;
+        for track in &mut state.fading_tracks {
+            let [left, right] = track.render_frame(samples, state.sample_rate);
+            let (l_gain, r_gain) = track_gains(&track.config, any_solo);
+            let (left, right) = (left * l_gain, right * r_gain);
+            if output.len() >= 2 {
+                output[0] += left;
+                output[1] += right;
+            } else if let Some(mono) = output.first_mut() {
+                *mono += 0.5 * (left + right);
+            }
+        }
+        for ([left, right], volume) in state.bus_buffers.iter().zip(bus_volumes.iter()) {
+            let (left, right) = (left[frame] * volume, right[frame] * volume);
+            if output.len() >= 2 {
+                output[0] += left;
+                output[1] += right;
+            } else if let Some(mono) = output.first_mut() {
+                *mono += 0.5 * (left + right);
+            }
+        }
+
+        // The click and the patch only go to the master pair, never the
+        // per-track outputs.
+        let click = state.metronome.sample(current_sample + frame as u64);
+        let patch = state.patch_buffer[frame];
+        let master = if state.track_outputs {
+            output.len().min(2)
+        } else {
+            output.len()
+        };
+        for sample in output[..master].iter_mut() {
+            *sample += click + patch;
+        }
     }
 
+    state
+        .pause
+        .apply(data, state.num_channels, state.sample_rate);
+    state
+        .fade_out
+        .apply(data, state.num_channels, state.sample_rate);
+
+    let master = if state.track_outputs {
+        state.num_channels.min(2)
+    } else {
+        state.num_channels
+    };
+    state.meters.master.record(
+        data.chunks_exact(state.num_channels)
+            .flat_map(|frame| frame[..master].iter().copied()),
+    );
+    // After metering, so the meters still show what the mix is doing.
+    state
+        .output_guard
+        .process(data, state.num_channels, state.sample_rate);
+
     sample_counter.fetch_add(num_frames as u64, Ordering::Relaxed);
 }
 
-fn process_event(
-    playback_states: &mut [audio::PlaybackState],
+/// Refreshes the configs rendered this block: switches over to hot-swapped
+/// track configs and applies each track's smoothed morph position.
+fn update_render_configs(state: &mut AudioState, num_frames: usize) {
+    while let Ok(reload) = state.reload_rx.try_recv() {
+        apply_reload(state, reload);
+    }
+    let sources = &state.render_source;
+
+    for (i, source) in sources.iter().enumerate() {
+        if source.morph_target.is_none() {
+            continue;
+        }
+        let target = state.morph_knobs.get(i).map_or(0.0, |k| k.get());
+        let position = state.morph_smoothers[i].next_block(target, num_frames, state.sample_rate);
+        audio::morph_into(
+            &mut Arc::make_mut(&mut state.render_configs)[i],
+            source,
+            position,
+        );
+    }
+}
+
+/// Switches the tracks over to `reload`'s configs. When tracks came, went or
+/// moved, the ones still there take their notes and plugins along, added
+/// ones start silent and removed ones fade out, all with what the reload
+/// brought along.
+fn apply_reload(state: &mut AudioState, mut reload: TrackReload) {
+    let (old, new) = (&state.render_source, &reload.source);
+    if !old.iter().map(|c| c.uid).eq(new.iter().map(|c| c.uid)) {
+        reload.slots.extend(state.tracks.drain(..).map(Some));
+        for config in new.iter() {
+            let kept = old
+                .iter()
+                .position(|c| c.uid == config.uid)
+                .and_then(|i| reload.slots.get_mut(i))
+                .and_then(Option::take);
+            if let Some(track) = kept.or_else(|| reload.added.pop()) {
+                reload.tracks.push(track);
+            }
+        }
+        // What's left was removed, in the same order as the fading tracks.
+        let mut removed = reload.removed.iter_mut();
+        for track in reload.slots.iter_mut().flatten() {
+            if let Some(fading) = removed.next() {
+                std::mem::swap(&mut track.state, &mut fading.state);
+            }
+        }
+        std::mem::swap(&mut state.tracks, &mut reload.tracks);
+
+        while state.fading_tracks.len() < MAX_FADING
+            && let Some(track) = reload.removed.pop()
+        {
+            state.fading_tracks.push(track);
+        }
+
+        if state.morph_smoothers.len() < new.len() && reload.morph_smoothers.capacity() >= new.len()
+        {
+            reload.morph_smoothers.append(&mut state.morph_smoothers);
+            while reload.morph_smoothers.len() < new.len() {
+                let i = reload.morph_smoothers.len();
+                let initial = state.morph_knobs.get(i).map_or(0.0, |k| k.get());
+                reload
+                    .morph_smoothers
+                    .push(audio::MorphSmoother::new(initial));
+            }
+            std::mem::swap(&mut state.morph_smoothers, &mut reload.morph_smoothers);
+        }
+    }
+    // Moving a track's fader takes over from its automation.
+    for (i, (config, track)) in reload.source.iter().zip(&mut state.tracks).enumerate() {
+        let Some(was) = state
+            .render_source
+            .get(i)
+            .filter(|c| c.uid == config.uid)
+            .or_else(|| state.render_source.iter().find(|c| c.uid == config.uid))
+        else {
+            continue;
+        };
+        if was.volume != config.volume {
+            track.automation.volume = None;
+        }
+        if was.pan != config.pan {
+            track.automation.pan = None;
+        }
+    }
+    std::mem::swap(&mut state.render_source, &mut reload.source);
+    std::mem::swap(&mut state.render_configs, &mut reload.render_configs);
+    retire(&state.retire_tx, Retired::Reload(reload));
+}
+
+/// Queues what `event` does to each track it reaches for when they render: it
+/// goes to the plugins' event lists, the tracks' voice events or the
+/// automation the tracks and the patch take up at its frame.
+fn queue_event(
+    tracks: &mut [Box<TrackRender>],
     configs: &[audio::TrackConfig],
-    event: &events::ScheduledEvent,
+    patch_changes: &mut Vec<PatchParam>,
+    event: events::Event,
+    frame: u32,
 ) {
-    match &event.event {
+    match event {
         events::Event::MidiEvent {
             track_id,
             pitch,
             velocity,
             is_note_on,
+            expression,
         } => {
-            if *track_id < playback_states.len() {
-                if *is_note_on {
-                    let num_oscs = configs.get(*track_id).map_or(0, |c| c.num_oscillators());
-                    playback_states[*track_id].note_on(*pitch, *velocity, num_oscs);
+            if let Some(instrument) = tracks
+                .get_mut(track_id)
+                .and_then(|track| track.plugins.instrument.as_mut())
+            {
+                if is_note_on {
+                    instrument.queue(
+                        frame,
+                        plugin::PluginEvent::NoteOn {
+                            key: pitch,
+                            velocity,
+                        },
+                    );
+                    if expression.pan != 0.0 {
+                        instrument.queue(
+                            frame,
+                            plugin::PluginEvent::Expression {
+                                key: pitch,
+                                expression: plugin::NoteExpression::Pan,
+                                value: (expression.pan as f64 + 1.0) / 2.0,
+                            },
+                        );
+                    }
+                    if expression.detune_cents != 0.0 {
+                        instrument.queue(
+                            frame,
+                            plugin::PluginEvent::Expression {
+                                key: pitch,
+                                expression: plugin::NoteExpression::Tuning,
+                                value: expression.detune_cents as f64 / 100.0,
+                            },
+                        );
+                    }
                 } else {
-                    playback_states[*track_id].note_off(*pitch);
+                    instrument.queue(frame, plugin::PluginEvent::NoteOff { key: pitch });
                 }
+            } else if let Some(config) = configs.get(track_id)
+                && track_id < tracks.len()
+            {
+                let event = if is_note_on {
+                    if let Some(group) = config
+                        .sample_zone(pitch, velocity)
+                        .and_then(|i| config.zone(i))
+                        .and_then(|zone| zone.choke_group)
+                    {
+                        for track in tracks.iter_mut() {
+                            track.queue(frame, VoiceEvent::Choke(group));
+                        }
+                    }
+                    VoiceEvent::NoteOn {
+                        pitch,
+                        velocity,
+                        expression,
+                    }
+                } else {
+                    VoiceEvent::NoteOff(pitch)
+                };
+                tracks[track_id].queue(frame, event);
             }
         }
         events::Event::StopAllNotes { track_id } => {
-            if *track_id < playback_states.len() {
-                playback_states[*track_id].stop_all();
+            if let Some(instrument) = tracks
+                .get_mut(track_id)
+                .and_then(|track| track.plugins.instrument.as_mut())
+            {
+                instrument.queue(frame, plugin::PluginEvent::ReleaseAll);
+            }
+            if let Some(track) = tracks.get_mut(track_id) {
+                track.queue(frame, VoiceEvent::ReleaseAll);
+            }
+        }
+        events::Event::KillAllNotes { track_id } => {
+            if let Some(instrument) = tracks
+                .get_mut(track_id)
+                .and_then(|track| track.plugins.instrument.as_mut())
+            {
+                instrument.queue(frame, plugin::PluginEvent::StopAll);
+            }
+            if let Some(track) = tracks.get_mut(track_id) {
+                track.queue(frame, VoiceEvent::KillAll);
+            }
+        }
+        events::Event::ClipStart { track_id, clip } => {
+            if let Some(track) = tracks.get_mut(track_id) {
+                track.queue(frame, VoiceEvent::StartClip(clip));
             }
         }
-        events::Event::NodeTransition { .. } => {}
+        events::Event::NoteModulation {
+            track_id,
+            pitch,
+            modulation,
+        } => {
+            if let Some(instrument) = tracks
+                .get_mut(track_id)
+                .and_then(|track| track.plugins.instrument.as_mut())
+            {
+                let (expression, value) = match modulation {
+                    events::NoteModulation::Bend { semitones } => {
+                        (plugin::NoteExpression::Tuning, semitones as f64)
+                    }
+                    events::NoteModulation::Pressure(pressure) => {
+                        (plugin::NoteExpression::Pressure, pressure as f64)
+                    }
+                    events::NoteModulation::Slide(slide) => {
+                        (plugin::NoteExpression::Brightness, slide as f64)
+                    }
+                };
+                instrument.queue(
+                    frame,
+                    plugin::PluginEvent::Expression {
+                        key: pitch,
+                        expression,
+                        value,
+                    },
+                );
+            } else if let Some(track) = tracks.get_mut(track_id) {
+                track.queue(frame, VoiceEvent::Modulate { pitch, modulation });
+            }
+        }
+        events::Event::ParamChange {
+            track_id,
+            target,
+            value,
+        } => {
+            let (slot, id) = match target {
+                timing::ParamTarget::Volume
+                | timing::ParamTarget::Pan
+                | timing::ParamTarget::Filter(_) => {
+                    if let Some(track) = tracks.get_mut(track_id) {
+                        track.param_changes.push((frame, target, value));
+                    }
+                    return;
+                }
+                timing::ParamTarget::Graph { node, param } => {
+                    patch_changes.push(PatchParam {
+                        frame,
+                        node,
+                        param,
+                        value,
+                    });
+                    return;
+                }
+                timing::ParamTarget::Instrument(id) => (plugin::PluginSlot::Instrument, id),
+                timing::ParamTarget::Effect { index, id } => {
+                    (plugin::PluginSlot::Effect(index), id)
+                }
+            };
+            if let Some(plugin) = tracks
+                .get_mut(track_id)
+                .and_then(|track| track.plugins.get_mut(slot))
+            {
+                plugin.queue(
+                    frame,
+                    plugin::PluginEvent::Param {
+                        id,
+                        value: value as f64,
+                    },
+                );
+            }
+        }
+        // Its node id was allocated by the timing thread.
+        transition @ events::Event::NodeTransition { .. } => {
+            audit::permit_alloc(|| drop(transition))
+        }
+    }
+}
+
+/// What happens to the voices of a track's built-in instrument, queued with
+/// the frame it happens at.
+enum VoiceEvent {
+    NoteOn {
+        pitch: u8,
+        velocity: u8,
+        expression: events::NoteExpression,
+    },
+    NoteOff(u8),
+    /// A note in the choke group started, on this track or another.
+    Choke(u8),
+    ReleaseAll,
+    KillAll,
+    StartClip(events::ClipEvent),
+    Modulate {
+        pitch: u8,
+        modulation: events::NoteModulation,
+    },
+}
+
+impl VoiceEvent {
+    fn apply(self, state: &mut audio::PlaybackState, config: &audio::TrackConfig) {
+        match self {
+            VoiceEvent::NoteOn {
+                pitch,
+                velocity,
+                expression,
+            } => state.note_on(pitch, velocity, expression, config),
+            VoiceEvent::NoteOff(pitch) => state.note_off(pitch, config),
+            VoiceEvent::Choke(group) => state.choke(group, config),
+            VoiceEvent::ReleaseAll => state.release_all(config),
+            VoiceEvent::KillAll => state.kill_all(),
+            VoiceEvent::StartClip(clip) => state.start_clip(audio::ClipVoice::new(clip)),
+            VoiceEvent::Modulate { pitch, modulation } => state.modulate(pitch, modulation),
+        }
+    }
+}
+
+/// A track as the audio callback renders it: its voices, its plugins and
+/// its signal for the current block. Tracks only meet again in the mix, so
+/// each can be rendered on whichever thread is free.
+struct TrackRender {
+    state: audio::PlaybackState,
+    plugins: plugin::TrackPlugins,
+    /// Pre-fader stereo signal for the current block, with room for
+    /// [`MAX_BLOCK`] frames.
+    buffers: [Vec<f32>; 2],
+    /// Left and right gain of the fader on each frame of the current block,
+    /// before mute and solo.
+    gains: [Vec<f32>; 2],
+    /// Voice events for the current block, in order.
+    events: Vec<(u32, VoiceEvent)>,
+    /// Automation of the track's own controls due in the current block, in
+    /// order.
+    param_changes: Vec<(u32, timing::ParamTarget, f32)>,
+    automation: TrackAutomation,
+}
+
+impl TrackRender {
+    fn new(plugins: plugin::TrackPlugins) -> Box<Self> {
+        Box::new(Self {
+            state: audio::PlaybackState::new(),
+            plugins,
+            buffers: [vec![0.0; MAX_BLOCK], vec![0.0; MAX_BLOCK]],
+            gains: [vec![0.0; MAX_BLOCK], vec![0.0; MAX_BLOCK]],
+            events: Vec::with_capacity(BLOCK_EVENTS),
+            param_changes: Vec::with_capacity(BLOCK_EVENTS),
+            automation: TrackAutomation::default(),
+        })
+    }
+
+    /// Queues a voice event for the current block. Past the room made for
+    /// them, it's dropped rather than grown on the audio thread.
+    fn queue(&mut self, frame: u32, event: VoiceEvent) {
+        if self.events.len() < self.events.capacity() {
+            self.events.push((frame, event));
+        }
+    }
+}
+
+/// What automation set on a track's own controls. It's kept with the
+/// track's render rather than its config, so reloads and morphing don't
+/// undo it; moving the fader takes over from it.
+#[derive(Default)]
+struct TrackAutomation {
+    volume: Option<f32>,
+    pan: Option<f32>,
+    /// The built-in lowpass, left and right, once a point has set it.
+    filter: Option<[crate::core::Biquad; 2]>,
+}
+
+impl TrackAutomation {
+    fn set(&mut self, target: timing::ParamTarget, value: f32) {
+        use crate::core::{AudioNode, Biquad, FilterMode};
+        use timing::{FilterParam, ParamTarget};
+
+        let param = match target {
+            ParamTarget::Volume => return self.volume = Some(value),
+            ParamTarget::Pan => return self.pan = Some(value),
+            ParamTarget::Filter(FilterParam::Cutoff) => "cutoff",
+            ParamTarget::Filter(FilterParam::Resonance) => "q",
+            ParamTarget::Graph { .. } | ParamTarget::Instrument(_) | ParamTarget::Effect { .. } => {
+                return;
+            }
+        };
+        let filter = self.filter.get_or_insert_with(|| {
+            let open = Biquad::new(
+                FilterMode::Lowpass,
+                20_000.0,
+                std::f32::consts::FRAC_1_SQRT_2,
+            );
+            [open.clone(), open]
+        });
+        for channel in filter {
+            // Both are the filter's own parameters.
+            let _ = channel.set_param(param, value);
+        }
+    }
+
+    /// Left and right gain of the fader, automated or as `config` has it.
+    fn fader(&self, config: &audio::TrackConfig) -> (f32, f32) {
+        let volume = self.volume.unwrap_or(config.volume);
+        let (l_gain, r_gain) = pan_to_gains(self.pan.unwrap_or(config.pan));
+        (l_gain * volume, r_gain * volume)
+    }
+}
+
+/// What every track of a block is rendered with.
+#[derive(Clone)]
+struct TrackBlock {
+    configs: Arc<Vec<audio::TrackConfig>>,
+    samples: Arc<audio::SampleBank>,
+    sample_rate: f32,
+    num_frames: usize,
+}
+
+impl RenderJob for TrackRender {
+    type Block = TrackBlock;
+
+    /// Renders the built-in instrument in stretches between voice events,
+    /// applying each at its frame, then runs the plugins over the result,
+    /// then the automated filter and fader, changing at their frames too.
+    fn render(&mut self, index: usize, block: &TrackBlock) {
+        let [left, right] = &mut self.buffers;
+        let (left, right) = (
+            &mut left[..block.num_frames],
+            &mut right[..block.num_frames],
+        );
+        let config = block.configs.get(index);
+
+        if let Some(config) = config {
+            let mut events = self.events.drain(..).peekable();
+            let mut start = 0;
+            while start < block.num_frames {
+                while let Some((_, event)) = events.next_if(|(at, _)| *at as usize <= start) {
+                    event.apply(&mut self.state, config);
+                }
+                let end = events
+                    .peek()
+                    .map_or(block.num_frames, |(at, _)| *at as usize)
+                    .min(block.num_frames);
+                self.state.render_block(
+                    config,
+                    &block.samples,
+                    block.sample_rate,
+                    &mut left[start..end],
+                    &mut right[start..end],
+                );
+                start = end;
+            }
+        }
+        self.events.clear();
+
+        if let Some(instrument) = &mut self.plugins.instrument {
+            instrument.process(left, right);
+        }
+        for effect in &mut self.plugins.effects {
+            effect.process(left, right);
+        }
+
+        let [l_gains, r_gains] = &mut self.gains;
+        let mut changes = self.param_changes.drain(..).peekable();
+        let mut start = 0;
+        while start < block.num_frames {
+            while let Some((_, target, value)) = changes.next_if(|(at, ..)| *at as usize <= start) {
+                self.automation.set(target, value);
+            }
+            let end = changes
+                .peek()
+                .map_or(block.num_frames, |(at, ..)| *at as usize)
+                .min(block.num_frames);
+            if let Some([l_filter, r_filter]) = &mut self.automation.filter {
+                for (l, r) in left[start..end].iter_mut().zip(&mut right[start..end]) {
+                    *l = l_filter.next(*l, block.sample_rate);
+                    *r = r_filter.next(*r, block.sample_rate);
+                }
+            }
+            let (l_gain, r_gain) = config.map_or((0.0, 0.0), |c| self.automation.fader(c));
+            l_gains[start..end].fill(l_gain);
+            r_gains[start..end].fill(r_gain);
+            start = end;
+        }
     }
 }
 
-fn render_frame(
+/// Mixes one frame of every track into `output` with its volume and pan.
+fn mix_frame(
     output: &mut [f32],
-    states: &mut [audio::PlaybackState],
+    frame: usize,
+    tracks: &[Box<TrackRender>],
     configs: &[audio::TrackConfig],
-    sample_rate: f32,
+    any_solo: bool,
+    track_outputs: bool,
 ) {
-    for (state, config) in states.iter_mut().zip(configs.iter()) {
-        let sample = state.render_sample(config, sample_rate);
-
-        let (l_gain, r_gain) = pan_to_gains(config.pan);
+    for (i, (config, track)) in configs.iter().zip(tracks).enumerate() {
+        if !audible(config, any_solo) {
+            continue;
+        }
+        let [left, right] = &track.buffers;
+        let [l_gains, r_gains] = &track.gains;
 
-        let left = sample * l_gain * config.volume;
-        let right = sample * r_gain * config.volume;
+        let left = left[frame] * l_gains[frame];
+        let right = right[frame] * r_gains[frame];
 
         if output.len() >= 2 {
             output[0] += left;
             output[1] += right;
         } else if !output.is_empty() {
-            output[0] += sample * config.volume;
+            output[0] += 0.5 * (left + right);
+        }
+
+        if track_outputs && let Some(pair) = output.get_mut(2 + 2 * i..4 + 2 * i) {
+            pair[0] += left;
+            pair[1] += right;
         }
     }
 }
 
+/// Whether a track is heard: it isn't muted, and no other track is soloed.
+fn audible(config: &audio::TrackConfig, any_solo: bool) -> bool {
+    !config.mute && (config.solo || !any_solo)
+}
+
+/// Left and right gain of a track after its fader, silent when it's muted or
+/// another track is soloed.
+fn track_gains(config: &audio::TrackConfig, any_solo: bool) -> (f32, f32) {
+    if !audible(config, any_solo) {
+        return (0.0, 0.0);
+    }
+    let (l_gain, r_gain) = pan_to_gains(config.pan);
+    (l_gain * config.volume, r_gain * config.volume)
+}
+
 fn pan_to_gains(pan: f32) -> (f32, f32) {
     let pan = pan.clamp(-1.0, 1.0);
     let angle = (pan + 1.0) * std::f32::consts::FRAC_PI_4; // 0 to PI/2
@@ -481,3 +4426,415 @@ fn pan_to_gains(pan: f32) -> (f32, f32) {
     let r_gain = angle.sin();
     (l_gain, r_gain)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::templates;
+
+    #[test]
+    fn the_tutorial_renders_without_allocating_in_the_callback() {
+        // Only checked with the alloc-audit feature, which aborts on an
+        // allocation in the callback; the render runs through transitions,
+        // with a track sending to a bus, and a chord of more events than a
+        // sort's scratch space fits on the stack in one block.
+        let mut project = templates::tutorial("Audit");
+        project.buses.push(crate::project::BusData {
+            name: "Room".to_string(),
+            volume: 1.0,
+            effects: Vec::new(),
+        });
+        project.tracks[0].sends = vec![0.5];
+        let mut chord = project.tracks[1].clone();
+        chord.id = project.tracks.len();
+        chord.uid = audio::TrackUid::new();
+        let initial = chord
+            .graph
+            .nodes
+            .iter_mut()
+            .find(|node| node.id == chord.initial_node)
+            .unwrap();
+        initial.sequence = timing::Sequence::Static(timing::StaticPattern {
+            duration_bars: 1,
+            time_signature: (4, 4),
+            notes: (0..120)
+                .map(|pitch| timing::Note::new(pitch, 100, 0.0, 0.5))
+                .collect(),
+            automation: Vec::new(),
+        });
+        project.tracks.push(chord);
+        let output = render_offline(&project, None, 48_000 * 8).unwrap();
+        assert!(output.iter().any(|&sample| sample != 0.0));
+    }
+
+    #[test]
+    fn reloads_bring_tracks_in_and_fade_them_out_without_allocating() {
+        let project = templates::tutorial("Audit");
+        let Playback {
+            mut audio_state,
+            mut track_configs,
+            ..
+        } = prepare_playback(
+            &project,
+            None,
+            &crossbeam::channel::unbounded().0,
+            crossbeam::channel::unbounded().0,
+            Arc::new(audio::Metronome::new(project.bpm, 48_000.0)),
+            Arc::default(),
+            Arc::default(),
+            0,
+        )
+        .unwrap();
+        let counter = Arc::new(AtomicU64::new(0));
+        let mut block = vec![0.0; 2 * RENDER_BLOCK];
+        audit::no_alloc(|| audio_callback(&mut block, &mut audio_state, &counter));
+
+        let mut configs = build_track_configs(&project);
+        let first = configs.remove(0).id;
+        // The second track moves up to the first's place and keeps its render.
+        let moved: *const TrackRender = &*audio_state.tracks[1];
+        for id in [98, 99] {
+            let mut added = configs[0].clone();
+            added.id = id;
+            added.uid = audio::TrackUid::new();
+            configs.push(added);
+        }
+        let ids: Vec<_> = configs.iter().map(|c| c.id).collect();
+        track_configs.store(configs);
+        audit::no_alloc(|| audio_callback(&mut block, &mut audio_state, &counter));
+
+        assert_eq!(audio_state.tracks.len(), ids.len());
+        assert!(audio_state.morph_smoothers.len() >= ids.len());
+        assert!(audio_state.render_source.iter().map(|c| c.id).eq(ids));
+        assert!(std::ptr::eq(&*audio_state.tracks[0], moved));
+        let [fading] = &audio_state.fading_tracks[..] else {
+            panic!("one track was removed");
+        };
+        assert_eq!(fading.config.id, first);
+    }
+
+    #[test]
+    fn reloaded_lanes_follow_their_tracks_and_new_ones_start_at_the_playhead() {
+        let mut project = templates::tutorial("Lanes");
+        let command_tx = crossbeam::channel::unbounded().0;
+        let Playback {
+            mut timing_state,
+            mut producer,
+            lua_runtime,
+            ..
+        } = prepare_playback(
+            &project,
+            None,
+            &command_tx,
+            crossbeam::channel::unbounded().0,
+            Arc::new(audio::Metronome::new(project.bpm, 48_000.0)),
+            Arc::default(),
+            Arc::default(),
+            0,
+        )
+        .unwrap();
+        timing_state.sequence_end_samples[1] = 12_345;
+        timing_state.loop_counts[1] = 3;
+
+        project.tracks.remove(0);
+        let mut added = project.tracks[0].clone();
+        added.uid = audio::TrackUid::new();
+        project.tracks.push(added);
+        for (id, track) in project.tracks.iter_mut().enumerate() {
+            track.id = id;
+        }
+        reload_lanes(
+            &mut timing_state,
+            lane_graphs(&project),
+            &mut producer,
+            &lua_runtime,
+            &command_tx,
+            1_000,
+        );
+
+        let lane = |uid| {
+            timing_state
+                .lane_keys
+                .iter()
+                .position(|&key| key == (uid, None))
+                .unwrap()
+        };
+        let moved = lane(project.tracks[0].uid);
+        assert_eq!(moved, 0);
+        assert_eq!(timing_state.lane_tracks[moved], 0);
+        assert_eq!(timing_state.sequence_end_samples[moved], 12_345);
+        assert_eq!(timing_state.loop_counts[moved], 3);
+        let started = lane(project.tracks[2].uid);
+        assert_eq!(timing_state.lane_tracks[started], 2);
+        assert_eq!(timing_state.loop_counts[started], 0);
+        assert!(timing_state.sequence_end_samples[started] > 1_000);
+        assert_eq!(timing_state.graphs.len(), timing_lanes(&project).count());
+    }
+
+    #[test]
+    fn automation_lands_on_its_frame_and_outlasts_reloads_until_the_fader_moves() {
+        let project = templates::tutorial("Automation");
+        let Playback {
+            mut audio_state,
+            mut track_configs,
+            ..
+        } = prepare_playback(
+            &project,
+            None,
+            &crossbeam::channel::unbounded().0,
+            crossbeam::channel::unbounded().0,
+            Arc::new(audio::Metronome::new(project.bpm, 48_000.0)),
+            Arc::default(),
+            Arc::default(),
+            0,
+        )
+        .unwrap();
+        let render = |state: &mut AudioState| {
+            update_render_configs(state, RENDER_BLOCK);
+            let block = TrackBlock {
+                configs: state.render_configs.clone(),
+                samples: state.sample_bank.clone(),
+                sample_rate: state.sample_rate,
+                num_frames: RENDER_BLOCK,
+            };
+            state.tracks[0].render(0, &block);
+            let [left, _] = &state.tracks[0].gains;
+            (left[99], left[100], left[RENDER_BLOCK - 1])
+        };
+
+        let change = |target, value| events::Event::ParamChange {
+            track_id: 0,
+            target,
+            value,
+        };
+        for event in [
+            change(timing::ParamTarget::Volume, 0.0),
+            change(
+                timing::ParamTarget::Filter(timing::FilterParam::Cutoff),
+                500.0,
+            ),
+        ] {
+            queue_event(
+                &mut audio_state.tracks,
+                &audio_state.render_configs,
+                &mut audio_state.patch_changes,
+                event,
+                100,
+            );
+        }
+        let (before, at, _) = render(&mut audio_state);
+        assert!(before > 0.0);
+        assert_eq!(at, 0.0);
+        assert!(audio_state.tracks[0].automation.filter.is_some());
+
+        // Reloads leave it be, until one moves the track's fader.
+        track_configs.update(|configs| configs[1].mute = true);
+        assert_eq!(render(&mut audio_state).2, 0.0);
+        track_configs.update(|configs| configs[0].volume = 0.5);
+        let (_, _, end) = render(&mut audio_state);
+        assert!(end > 0.0);
+    }
+
+    #[test]
+    fn events_are_rendered_ahead_of_their_timestamps_by_the_latency() {
+        let project = templates::tutorial("Latency");
+        // The first sequences start well after the block.
+        let mut audio_state = prepare_playback(
+            &project,
+            None,
+            &crossbeam::channel::unbounded().0,
+            crossbeam::channel::unbounded().0,
+            Arc::new(audio::Metronome::new(project.bpm, 48_000.0)),
+            Arc::default(),
+            Arc::default(),
+            48_000,
+        )
+        .unwrap()
+        .audio_state;
+        audio_state.ahead = 100;
+        audio_state.pending_event = Some(events::ScheduledEvent {
+            sample_timestamp: 150,
+            event: events::Event::ParamChange {
+                track_id: 0,
+                target: timing::ParamTarget::Volume,
+                value: 0.0,
+            },
+        });
+        let counter = Arc::new(AtomicU64::new(0));
+        let mut block = vec![0.0; 2 * RENDER_BLOCK];
+        audio_callback(&mut block, &mut audio_state, &counter);
+
+        let [left, _] = &audio_state.tracks[0].gains;
+        assert!(left[49] > 0.0);
+        assert_eq!(left[50], 0.0);
+    }
+
+    #[test]
+    fn swapped_out_sample_banks_are_retired_rather_than_freed_in_the_callback() {
+        let project = templates::tutorial("Audit");
+        let mut audio_state = prepare_playback(
+            &project,
+            None,
+            &crossbeam::channel::unbounded().0,
+            crossbeam::channel::unbounded().0,
+            Arc::new(audio::Metronome::new(project.bpm, 48_000.0)),
+            Arc::default(),
+            Arc::default(),
+            0,
+        )
+        .unwrap()
+        .audio_state;
+        let (retire_tx, retired_rx) = crossbeam::channel::bounded(AUDIO_QUEUE);
+        audio_state.retire_tx = retire_tx;
+        let counter = Arc::new(AtomicU64::new(0));
+        let mut block = vec![0.0; 2 * RENDER_BLOCK];
+        audit::no_alloc(|| audio_callback(&mut block, &mut audio_state, &counter));
+
+        let bank = Arc::new(audio::SampleBank::new());
+        audio_state.samples.store(bank.clone());
+        audit::no_alloc(|| audio_callback(&mut block, &mut audio_state, &counter));
+        assert!(Arc::ptr_eq(&audio_state.sample_bank, &bank));
+        assert!(matches!(retired_rx.try_recv(), Ok(Retired::Samples(_))));
+    }
+
+    #[test]
+    fn loading_a_project_stops_playback_so_play_starts_it_afresh() {
+        let dir = std::env::temp_dir().join(format!("aurio-load-{}", std::process::id()));
+        let project = templates::tutorial("Load");
+        project.save(&dir).unwrap();
+        let command_tx = crossbeam::channel::unbounded().0;
+        let (update_tx, update_rx) = crossbeam::channel::unbounded();
+
+        // Paused partway through the previous project.
+        let mut state = EngineState::new();
+        let playback = prepare_playback(
+            &project,
+            None,
+            &command_tx,
+            update_tx.clone(),
+            state.metronome.clone(),
+            state.fade_out.clone(),
+            state.pause.clone(),
+            0,
+        )
+        .unwrap();
+        let old = Arc::new(Mutex::new(playback.audio_state));
+        state.project = Some(project);
+        state.audio_state = Some(old.clone());
+        state.graph_tx = Some(crossbeam::channel::unbounded().0);
+        state.script_tx = Some(playback.lua_runtime.action_sender());
+        state.pause.set(true);
+
+        load_project(&mut state, dir.clone(), &command_tx, &update_tx);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(state.audio_state.is_none());
+        assert!(state.graph_tx.is_none() && state.script_tx.is_none());
+        assert!(
+            update_rx
+                .try_iter()
+                .any(|update| matches!(update, EngineUpdate::PlaybackState { playing: false }))
+        );
+
+        // A new renderer, or none without a device to play on, but never the
+        // old one resumed.
+        play(&mut state, &command_tx, &update_tx);
+        assert!(
+            state
+                .audio_state
+                .as_ref()
+                .is_none_or(|new| !Arc::ptr_eq(new, &old))
+        );
+    }
+
+    #[test]
+    fn timing_panics_are_reported_and_leave_the_engine_running() {
+        let project = templates::tutorial("Crash");
+        let (command_tx, command_rx) = crossbeam::channel::unbounded();
+        let Playback {
+            mut timing_state,
+            producer,
+            lua_runtime,
+            ..
+        } = prepare_playback(
+            &project,
+            None,
+            &command_tx,
+            crossbeam::channel::unbounded().0,
+            Arc::new(audio::Metronome::new(project.bpm, 48_000.0)),
+            Arc::default(),
+            Arc::default(),
+            0,
+        )
+        .unwrap();
+        // Without its loop count, the first node to end panics moving on.
+        timing_state.loop_counts.clear();
+        timing_state.sequence_end_samples.fill(0);
+        let (_graph_tx, graph_rx) = crossbeam::channel::unbounded();
+        spawn_timing_thread(
+            timing_state,
+            producer,
+            Arc::default(),
+            lua_runtime,
+            command_tx,
+            graph_rx,
+        );
+        let crashed = command_rx
+            .iter()
+            .find(|command| matches!(command, EngineCommand::TimingCrashed { .. }))
+            .unwrap();
+
+        let engine = spawn_engine_with(false);
+        engine.command_tx.send(crashed).unwrap();
+        let error = engine
+            .update_rx
+            .iter()
+            .find_map(|update| match update {
+                EngineUpdate::Error { message } => Some(message),
+                _ => None,
+            })
+            .unwrap();
+        assert!(error.starts_with("The sequencer crashed"), "{error}");
+        assert!(engine.shutdown(std::time::Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn engine_panics_are_reported_and_the_engine_starts_over() {
+        let (update_tx, update_rx) = crossbeam::channel::unbounded();
+        let mut runs = 0;
+        supervise_engine(true, &update_tx, || {
+            runs += 1;
+            if runs == 1 {
+                panic!("no track 7");
+            }
+        });
+        assert_eq!(runs, 2);
+        let updates: Vec<_> = update_rx.try_iter().collect();
+        assert!(updates.iter().any(|update| matches!(
+            update,
+            EngineUpdate::Error { message } if message == "The engine crashed: no track 7"
+        )));
+        assert!(matches!(updates.last(), Some(EngineUpdate::Restarted)));
+
+        // Without restarting, or once out of restarts, it stays down.
+        let mut runs = 0;
+        supervise_engine(false, &update_tx, || {
+            runs += 1;
+            panic!("again");
+        });
+        assert_eq!(runs, 1);
+        let mut runs = 0;
+        supervise_engine(true, &update_tx, || {
+            runs += 1;
+            panic!("always");
+        });
+        assert_eq!(runs, MAX_ENGINE_RESTARTS + 1);
+    }
+
+    #[test]
+    fn panics_are_reported_with_their_message() {
+        let payload = std::panic::catch_unwind(|| panic!("no node {}", "verse")).unwrap_err();
+        assert_eq!(panic_message(payload.as_ref()), "no node verse");
+        let payload = std::panic::catch_unwind(|| panic!("out of samples")).unwrap_err();
+        assert_eq!(panic_message(payload.as_ref()), "out of samples");
+    }
+}
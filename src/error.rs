@@ -0,0 +1,55 @@
+//! The error the library's fallible entry points return, split by what
+//! failed so callers can tell a bad patch from a missing device or an
+//! unreadable project.
+
+#[cfg(feature = "std")]
+use crate::scripting::ScriptError;
+use std::path::PathBuf;
+
+#[derive(Debug, thiserror::Error)]
+pub enum AurioError {
+    /// A `.au` patch line that doesn't parse.
+    #[error("line {line}: {message}")]
+    Parse { line: usize, message: String },
+    /// Nodes and wires that don't make a playable graph: a duplicate id, a
+    /// wire to a missing node, or a cycle.
+    #[error("{0}")]
+    Graph(String),
+    /// An audio host or device that couldn't be opened or listed.
+    #[error("{0}")]
+    AudioDevice(String),
+    /// A project file, script or asset that couldn't be read or written.
+    #[error("{}: {source}", path.display())]
+    ProjectIo {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    /// Project content that doesn't (de)serialize, or a sample that doesn't
+    /// decode.
+    #[error("{0}")]
+    ProjectFormat(String),
+    #[cfg(feature = "std")]
+    #[error(transparent)]
+    Script(#[from] ScriptError),
+}
+
+impl AurioError {
+    pub fn audio_device(error: impl std::fmt::Display) -> Self {
+        AurioError::AudioDevice(error.to_string())
+    }
+
+    pub fn project_io(path: impl Into<PathBuf>, source: std::io::Error) -> Self {
+        AurioError::ProjectIo {
+            path: path.into(),
+            source,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<mlua::Error> for AurioError {
+    fn from(error: mlua::Error) -> Self {
+        AurioError::Script(ScriptError::from_lua(&error))
+    }
+}
@@ -1,3 +1,6 @@
+use crate::timing::ParamTarget;
+use std::sync::atomic::{AtomicU64, Ordering};
+
 #[derive(Debug, Clone)]
 pub struct ScheduledEvent {
     pub sample_timestamp: u64,
@@ -11,18 +14,184 @@ pub enum Event {
         pitch: u8,
         velocity: u8,
         is_note_on: bool,
+        /// How the note differs from the track's other notes. Only note-ons
+        /// carry it.
+        expression: NoteExpression,
     },
+    /// Releases every note on the track, letting their tails ring out.
     StopAllNotes {
         track_id: usize,
     },
+    /// Cuts every note on the track off at once.
+    KillAllNotes {
+        track_id: usize,
+    },
+    ClipStart {
+        track_id: usize,
+        clip: ClipEvent,
+    },
+    NoteModulation {
+        track_id: usize,
+        pitch: u8,
+        modulation: NoteModulation,
+    },
+    /// An automation point reached, taken up at its exact frame.
+    ParamChange {
+        track_id: usize,
+        target: ParamTarget,
+        value: f32,
+    },
+    /// Where a track's sequence was cut short for another node's. Queued
+    /// after everything the cut sequence had queued; see [`SequenceCuts`].
     NodeTransition {
         track_id: usize,
         new_node_id: String,
     },
 }
 
-#[derive(Debug, Clone)]
+impl Event {
+    pub fn track_id(&self) -> usize {
+        match self {
+            Event::MidiEvent { track_id, .. }
+            | Event::StopAllNotes { track_id }
+            | Event::KillAllNotes { track_id }
+            | Event::ClipStart { track_id, .. }
+            | Event::NoteModulation { track_id, .. }
+            | Event::ParamChange { track_id, .. }
+            | Event::NodeTransition { track_id, .. } => *track_id,
+        }
+    }
+}
+
+/// Tracks whose queued sequence the timing thread cut short, and where.
+///
+/// Sequences are queued whole when they start, so a transition that doesn't
+/// wait for the end leaves the rest of the old sequence in the ring. The
+/// timing thread marks the cut here, then queues a
+/// [`Event::NodeTransition`] at the cut sample. The renderer drops the
+/// track's events from the cut on until it pops that transition, which is
+/// queued after all of them.
+pub struct SequenceCuts(Vec<AtomicU64>);
+
+impl SequenceCuts {
+    pub fn new(tracks: usize) -> Self {
+        Self((0..tracks).map(|_| AtomicU64::new(u64::MAX)).collect())
+    }
+
+    pub fn cut(&self, track_id: usize, sample: u64) {
+        if let Some(cut) = self.0.get(track_id) {
+            cut.store(sample, Ordering::Release);
+        }
+    }
+
+    /// Whether the renderer should drop `event`, popped from the ring. The
+    /// transition that ends a cut lifts it, unless the track was cut again
+    /// since.
+    pub fn drops(&self, event: &ScheduledEvent) -> bool {
+        let Some(cut) = self.0.get(event.event.track_id()) else {
+            return false;
+        };
+        if let Event::NodeTransition { .. } = event.event {
+            let _ = cut.compare_exchange(
+                event.sample_timestamp,
+                u64::MAX,
+                Ordering::AcqRel,
+                Ordering::Relaxed,
+            );
+            return false;
+        }
+        event.sample_timestamp >= cut.load(Ordering::Acquire)
+    }
+}
+
+/// Per-note changes to how a track plays a note.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct NoteExpression {
+    /// Added to the track's pan, from -1 to 1.
+    pub pan: f32,
+    pub detune_cents: f32,
+}
+
+impl NoteExpression {
+    pub fn is_neutral(&self) -> bool {
+        self.pan == 0.0 && self.detune_cents == 0.0
+    }
+}
+
+/// An audio clip to start, with its timing converted to output frames.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClipEvent {
+    pub sample_id: String,
+    /// Seconds into the audio to start from.
+    pub offset: f64,
+    /// Frames to play for, unless the audio runs out first.
+    pub length: u64,
+    pub fade_in: u64,
+    pub fade_out: u64,
+    pub gain: f32,
+}
+
+/// A live change to one sounding note, from an expressive controller.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NoteModulation {
+    Bend {
+        semitones: f32,
+    },
+    /// How hard the key is pressed, from 0 to 1.
+    Pressure(f32),
+    /// Movement along the key, from 0 to 1 with 0.5 at rest.
+    Slide(f32),
+}
+
+/// A channel message from a MIDI controller, besides control changes.
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum MidiMessage {
-    NoteOn { pitch: u8, velocity: u8 },
-    NoteOff { pitch: u8 },
+    NoteOn {
+        pitch: u8,
+        velocity: u8,
+    },
+    NoteOff {
+        pitch: u8,
+    },
+    /// From -1 (all the way down) to 1.
+    PitchBend {
+        value: f32,
+    },
+    ChannelPressure {
+        value: u8,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(sample_timestamp: u64, event: Event) -> ScheduledEvent {
+        ScheduledEvent {
+            sample_timestamp,
+            event,
+        }
+    }
+
+    #[test]
+    fn a_cut_drops_the_old_sequence_until_the_transition() {
+        let cuts = SequenceCuts::new(2);
+        let stop = |track_id| Event::StopAllNotes { track_id };
+        let transition = Event::NodeTransition {
+            track_id: 0,
+            new_node_id: "fill".to_string(),
+        };
+        cuts.cut(0, 100);
+
+        assert!(!cuts.drops(&at(99, stop(0))));
+        assert!(cuts.drops(&at(150, stop(0))));
+        assert!(!cuts.drops(&at(150, stop(1))));
+        assert!(!cuts.drops(&at(100, transition.clone())));
+        assert!(!cuts.drops(&at(150, stop(0))));
+
+        // A transition from an earlier cut leaves a newer one in place.
+        cuts.cut(0, 200);
+        assert!(!cuts.drops(&at(100, transition)));
+        assert!(cuts.drops(&at(250, stop(0))));
+    }
 }
@@ -0,0 +1,274 @@
+//! C bindings for building and running `.au` graphs, so C and C++ hosts (and
+//! anything else that calls C) can embed the graph engine. The declarations
+//! are in `include/aurio.h`, generated from this file by cbindgen.
+//!
+//! Functions that can fail return [`AURIO_OK`] or [`AURIO_ERROR`]; the
+//! message of the last failure is kept with the graph, see
+//! [`aurio_graph_last_error`].
+
+use crate::dsp::{AudioGraph, ProcessContext, parse_file, patch_graph};
+use std::ffi::{CStr, CString, c_char, c_int};
+use std::fmt::Display;
+
+pub const AURIO_OK: c_int = 0;
+pub const AURIO_ERROR: c_int = -1;
+
+/// A graph and what it needs to render into a host's buffers. Opaque to C.
+pub struct AurioGraph {
+    graph: AudioGraph,
+    /// The graph's mono output, before it's copied to every channel.
+    mono: Vec<f32>,
+    error: Option<CString>,
+}
+
+impl AurioGraph {
+    fn report(&mut self, result: Result<(), impl Display>) -> c_int {
+        match result {
+            Ok(()) => {
+                self.error = None;
+                AURIO_OK
+            }
+            Err(e) => {
+                let message = e.to_string().replace('\0', " ");
+                self.error = CString::new(message).ok();
+                AURIO_ERROR
+            }
+        }
+    }
+
+    fn patch(&mut self, source: &str) -> c_int {
+        let result = patch_graph(&mut self.graph, source);
+        self.report(result)
+    }
+}
+
+/// # Safety
+///
+/// `text` is null or a NUL-terminated string that outlives `'a`.
+unsafe fn text_arg<'a>(text: *const c_char) -> Result<&'a str, &'static str> {
+    if text.is_null() {
+        return Err("null string");
+    }
+    // SAFETY: non-null and NUL-terminated per the caller.
+    unsafe { CStr::from_ptr(text) }
+        .to_str()
+        .map_err(|_| "string is not UTF-8")
+}
+
+/// An empty graph. Free it with [`aurio_graph_free`].
+#[unsafe(no_mangle)]
+pub extern "C" fn aurio_graph_new() -> *mut AurioGraph {
+    let graph = AurioGraph {
+        graph: parse_file("").expect("an empty patch parses"),
+        mono: Vec::new(),
+        error: None,
+    };
+    Box::into_raw(Box::new(graph))
+}
+
+/// # Safety
+///
+/// `graph` is null or came from [`aurio_graph_new`] and wasn't freed yet.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn aurio_graph_free(graph: *mut AurioGraph) {
+    if !graph.is_null() {
+        // SAFETY: allocated by `aurio_graph_new` and not freed, per the caller.
+        drop(unsafe { Box::from_raw(graph) });
+    }
+}
+
+/// Adds node `id`, or replaces it keeping its wires. `node` is its type and
+/// arguments as written in a patch, e.g. `Osc Saw 110`.
+///
+/// # Safety
+///
+/// `graph` came from [`aurio_graph_new`]; `node` is a NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn aurio_graph_add_node(
+    graph: *mut AurioGraph,
+    id: u32,
+    node: *const c_char,
+) -> c_int {
+    // SAFETY: valid and not aliased for the call, per the caller.
+    let Some(graph) = (unsafe { graph.as_mut() }) else {
+        return AURIO_ERROR;
+    };
+    // SAFETY: as above.
+    match unsafe { text_arg(node) } {
+        Ok(node) => graph.patch(&format!("[{}] {}", id, node)),
+        Err(e) => graph.report(Err(e)),
+    }
+}
+
+/// Wires the output of node `from` into node `to`. Fails if either is
+/// missing or the wire would close a loop.
+///
+/// # Safety
+///
+/// `graph` came from [`aurio_graph_new`].
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn aurio_graph_add_wire(graph: *mut AurioGraph, from: u32, to: u32) -> c_int {
+    // SAFETY: valid and not aliased for the call, per the caller.
+    let Some(graph) = (unsafe { graph.as_mut() }) else {
+        return AURIO_ERROR;
+    };
+    graph.patch(&format!("{}->{}", from, to))
+}
+
+/// Applies `.au` source to the graph: nodes with a new id are added, nodes
+/// reusing an id replace that node, and wires are added.
+///
+/// # Safety
+///
+/// `graph` came from [`aurio_graph_new`]; `source` is a NUL-terminated
+/// string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn aurio_graph_patch(graph: *mut AurioGraph, source: *const c_char) -> c_int {
+    // SAFETY: valid and not aliased for the call, per the caller.
+    let Some(graph) = (unsafe { graph.as_mut() }) else {
+        return AURIO_ERROR;
+    };
+    // SAFETY: as above.
+    match unsafe { text_arg(source) } {
+        Ok(source) => graph.patch(source),
+        Err(e) => graph.report(Err(e)),
+    }
+}
+
+/// Sets a parameter of node `node`, e.g. an oscillator's `freq`.
+///
+/// # Safety
+///
+/// `graph` came from [`aurio_graph_new`]; `name` is a NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn aurio_graph_set_param(
+    graph: *mut AurioGraph,
+    node: u32,
+    name: *const c_char,
+    value: f32,
+) -> c_int {
+    // SAFETY: valid and not aliased for the call, per the caller.
+    let Some(graph) = (unsafe { graph.as_mut() }) else {
+        return AURIO_ERROR;
+    };
+    // SAFETY: as above.
+    let result = unsafe { text_arg(name) }
+        .map_err(str::to_string)
+        .and_then(|name| graph.graph.set_param(node, name, value));
+    graph.report(result)
+}
+
+/// Renders `frames` frames into `output`, interleaved with `channels`
+/// channels, every channel carrying the graph's output. Only the first call
+/// with a larger block than before allocates.
+///
+/// # Safety
+///
+/// `graph` came from [`aurio_graph_new`]; `output` holds at least
+/// `frames * channels` floats.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn aurio_graph_process(
+    graph: *mut AurioGraph,
+    output: *mut f32,
+    frames: usize,
+    channels: usize,
+    sample_rate: f32,
+) -> c_int {
+    // SAFETY: valid and not aliased for the call, per the caller.
+    let Some(graph) = (unsafe { graph.as_mut() }) else {
+        return AURIO_ERROR;
+    };
+    if output.is_null() || channels == 0 {
+        return graph.report(Err("no output buffer"));
+    }
+    // SAFETY: `frames * channels` writable floats, per the caller.
+    let output = unsafe { std::slice::from_raw_parts_mut(output, frames * channels) };
+
+    graph.mono.resize(frames, 0.0);
+    // A graph without an `Out` node leaves the block silent.
+    graph.mono.fill(0.0);
+    graph
+        .graph
+        .process(&mut graph.mono, &ProcessContext { sample_rate });
+    for (frame, &sample) in output.chunks_exact_mut(channels).zip(&graph.mono) {
+        frame.fill(sample);
+    }
+    graph.error = None;
+    AURIO_OK
+}
+
+/// The message of the last call on `graph` that failed, or null if the last
+/// one succeeded. It stays valid until the next call on `graph`.
+///
+/// # Safety
+///
+/// `graph` came from [`aurio_graph_new`].
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn aurio_graph_last_error(graph: *const AurioGraph) -> *const c_char {
+    // SAFETY: valid for the call, per the caller.
+    unsafe { graph.as_ref() }
+        .and_then(|graph| graph.error.as_ref())
+        .map_or(std::ptr::null(), |error| error.as_ptr())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn last_error(graph: *const AurioGraph) -> Option<String> {
+        let error = unsafe { aurio_graph_last_error(graph) };
+        (!error.is_null()).then(|| {
+            unsafe { CStr::from_ptr(error) }
+                .to_string_lossy()
+                .into_owned()
+        })
+    }
+
+    #[test]
+    fn graphs_built_through_the_c_api_render_interleaved() {
+        let graph = aurio_graph_new();
+        unsafe {
+            assert_eq!(aurio_graph_add_node(graph, 0, c"Out".as_ptr()), AURIO_OK);
+            assert_eq!(
+                aurio_graph_add_node(graph, 1, c"Gain 0.5".as_ptr()),
+                AURIO_OK
+            );
+            // At 0 Hz a square wave stays at -1.
+            assert_eq!(
+                aurio_graph_add_node(graph, 2, c"Osc Square 0".as_ptr()),
+                AURIO_OK
+            );
+            assert_eq!(aurio_graph_add_wire(graph, 2, 1), AURIO_OK);
+            assert_eq!(aurio_graph_add_wire(graph, 1, 0), AURIO_OK);
+
+            let mut output = [0.0f32; 8];
+            let status = aurio_graph_process(graph, output.as_mut_ptr(), 4, 2, 48_000.0);
+            assert_eq!(status, AURIO_OK);
+            assert_eq!(output, [-0.5; 8]);
+
+            assert_eq!(
+                aurio_graph_set_param(graph, 1, c"gain".as_ptr(), 1.0),
+                AURIO_OK
+            );
+            // Gain changes ramp over 10 ms.
+            let mut output = [0.0f32; 2048];
+            aurio_graph_process(graph, output.as_mut_ptr(), 1024, 2, 48_000.0);
+            assert!(output[0] > -1.0);
+            assert_eq!(output[2046..], [-1.0; 2]);
+            assert_eq!(last_error(graph), None);
+
+            assert_eq!(aurio_graph_add_wire(graph, 0, 2), AURIO_ERROR);
+            assert_eq!(last_error(graph).as_deref(), Some("Cycle detected"));
+            assert_eq!(
+                aurio_graph_add_node(graph, 3, c"Nope".as_ptr()),
+                AURIO_ERROR
+            );
+            assert!(last_error(graph).unwrap().contains("unknown node type"));
+            let status = aurio_graph_process(graph, output.as_mut_ptr(), 1024, 2, 48_000.0);
+            assert_eq!(status, AURIO_OK);
+            assert_eq!(last_error(graph), None);
+
+            aurio_graph_free(graph);
+        }
+    }
+}
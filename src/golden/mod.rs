@@ -0,0 +1,326 @@
+//! Golden-audio regression tests: every DSP node, instrument type and
+//! template track is rendered for a fixed time and compared against a
+//! fingerprint stored next to this file, so a refactor that changes how
+//! something sounds fails here instead of going unnoticed.
+//!
+//! A fingerprint is the level (RMS) and zero-crossing count of each window of
+//! each channel, which survives the last-bit float noise a reordered
+//! calculation brings but not a change in level, pitch, envelope or pan. A
+//! missing reference fails like a changed one; after adding a case or an
+//! intended change in sound, rerun with `AURIO_BLESS=1` to write them and
+//! commit the result.
+
+use crate::Project;
+use crate::audio::{
+    ADSRConfig, Instrument, OscConfig, PlaybackState, SampleBank, SampleBuffer, SampleMode,
+    SampleRegion, SampleZone, TrackConfig, Wave,
+};
+use crate::dsp::resample::Interpolation;
+use crate::dsp::{ProcessContext, parse_file};
+use crate::events::NoteExpression;
+use crate::templates::Template;
+use std::fmt::Write as _;
+use std::path::PathBuf;
+
+const SAMPLE_RATE: f32 = 48_000.0;
+const BLOCK: usize = 512;
+/// One second of audio.
+const FRAMES: usize = 48_000;
+/// Notes are released in the block halfway through, so their release is
+/// covered too.
+const RELEASE_BLOCK: usize = FRAMES / BLOCK / 2;
+const WINDOW: usize = 1_500;
+const RMS_TOLERANCE: f32 = 1e-3;
+const CROSSING_TOLERANCE: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Window {
+    rms: f32,
+    crossings: u32,
+}
+
+/// Windows of each channel, channel by channel.
+type Fingerprint = Vec<Vec<Window>>;
+
+fn fingerprint(channels: &[&[f32]]) -> Fingerprint {
+    channels
+        .iter()
+        .map(|channel| {
+            channel
+                .chunks(WINDOW)
+                .map(|window| Window {
+                    rms: (window.iter().map(|s| s * s).sum::<f32>() / window.len() as f32).sqrt(),
+                    crossings: window
+                        .windows(2)
+                        .filter(|pair| (pair[0] < 0.0) != (pair[1] < 0.0))
+                        .count() as u32,
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// One line per window, with `rms:crossings` for each channel.
+fn to_text(fingerprint: &Fingerprint) -> String {
+    let windows = fingerprint.first().map_or(0, Vec::len);
+    let mut text = String::new();
+    for i in 0..windows {
+        let line: Vec<String> = fingerprint
+            .iter()
+            .map(|channel| format!("{:.6}:{}", channel[i].rms, channel[i].crossings))
+            .collect();
+        writeln!(text, "{}", line.join(" ")).unwrap();
+    }
+    text
+}
+
+fn from_text(text: &str) -> Result<Fingerprint, String> {
+    let mut fingerprint: Fingerprint = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let windows = line
+            .split_whitespace()
+            .map(|field| {
+                let (rms, crossings) = field.split_once(':')?;
+                Some(Window {
+                    rms: rms.parse().ok()?,
+                    crossings: crossings.parse().ok()?,
+                })
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| format!("line {}: invalid window", number + 1))?;
+        fingerprint.resize(windows.len(), Vec::new());
+        for (channel, window) in fingerprint.iter_mut().zip(windows) {
+            channel.push(window);
+        }
+    }
+    Ok(fingerprint)
+}
+
+/// Where the first difference beyond the tolerances is, if there is one.
+fn compare(actual: &Fingerprint, expected: &Fingerprint) -> Result<(), String> {
+    if actual.len() != expected.len()
+        || actual.iter().zip(expected).any(|(a, e)| a.len() != e.len())
+    {
+        return Err("different number of channels or windows".to_string());
+    }
+    for (channel, (actual, expected)) in actual.iter().zip(expected).enumerate() {
+        for (i, (a, e)) in actual.iter().zip(expected).enumerate() {
+            if (a.rms - e.rms).abs() > RMS_TOLERANCE
+                || a.crossings.abs_diff(e.crossings) > CROSSING_TOLERANCE
+            {
+                return Err(format!(
+                    "channel {} at {:.3} s: expected {:?}, rendered {:?}",
+                    channel,
+                    (i * WINDOW) as f32 / SAMPLE_RATE,
+                    e,
+                    a
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn reference_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("src/golden")
+        .join(format!("{name}.txt"))
+}
+
+/// Checks `channels` against the reference called `name`, or writes it when
+/// blessing.
+fn check(name: &str, channels: &[&[f32]]) -> Result<(), String> {
+    let actual = fingerprint(channels);
+    let path = reference_path(name);
+    if std::env::var_os("AURIO_BLESS").is_some() {
+        std::fs::write(&path, to_text(&actual)).map_err(|e| format!("{name}: {e}"))?;
+        eprintln!("wrote golden reference {}", path.display());
+        return Ok(());
+    }
+    if !path.exists() {
+        return Err(format!("{name}: no reference at {}", path.display()));
+    }
+    let text = std::fs::read_to_string(&path).map_err(|e| format!("{name}: {e}"))?;
+    let expected = from_text(&text).map_err(|e| format!("{name}: {e}"))?;
+    compare(&actual, &expected).map_err(|e| format!("{name}: {e}"))
+}
+
+fn assert_all(results: impl IntoIterator<Item = Result<(), String>>) {
+    let failures: Vec<String> = results.into_iter().filter_map(Result::err).collect();
+    assert!(
+        failures.is_empty(),
+        "sound changed or has no reference (rerun with AURIO_BLESS=1 if intended):\n{}",
+        failures.join("\n")
+    );
+}
+
+fn render_patch(patch: &str) -> Vec<f32> {
+    let mut graph = parse_file(patch).unwrap();
+    let ctx = ProcessContext {
+        sample_rate: SAMPLE_RATE,
+    };
+    let mut output = vec![0.0; FRAMES];
+    for block in output.chunks_mut(BLOCK) {
+        graph.process(block, &ctx);
+    }
+    output
+}
+
+/// Plays A3 and the E above it, releasing them at [`RELEASE_BLOCK`].
+fn render_track(config: &TrackConfig, samples: &SampleBank) -> [Vec<f32>; 2] {
+    let mut state = PlaybackState::new();
+    for pitch in [57, 64] {
+        state.note_on(pitch, 100, NoteExpression::default(), config);
+    }
+    let (mut left, mut right) = (vec![0.0; FRAMES], vec![0.0; FRAMES]);
+    let blocks = left.chunks_mut(BLOCK).zip(right.chunks_mut(BLOCK));
+    for (i, (left, right)) in blocks.enumerate() {
+        if i == RELEASE_BLOCK {
+            state.release_all(config);
+        }
+        state.render_block(config, samples, SAMPLE_RATE, left, right);
+    }
+    [left, right]
+}
+
+fn check_track(name: &str, config: &TrackConfig, samples: &SampleBank) -> Result<(), String> {
+    let [left, right] = render_track(config, samples);
+    check(name, &[&left, &right])
+}
+
+fn adsr() -> ADSRConfig {
+    ADSRConfig::new(0.01, 0.1, 0.7, 0.2)
+}
+
+fn osc(wave: Wave, gain: f32, semitone: i8, pan: f32) -> OscConfig {
+    OscConfig {
+        wave,
+        gain,
+        semitone,
+        pan,
+    }
+}
+
+#[test]
+fn dsp_nodes_match_their_references() {
+    assert_all([
+        check(
+            "osc_sine",
+            &[&render_patch("[0] Out\n[1] Osc Sine 440\n1->0")],
+        ),
+        check(
+            "osc_saw",
+            &[&render_patch("[0] Out\n[1] Osc Saw 220\n1->0")],
+        ),
+        check(
+            "osc_square",
+            &[&render_patch("[0] Out\n[1] Osc Square 110\n1->0")],
+        ),
+        check(
+            "gain_mix",
+            &[&render_patch(
+                "[0] Out\n[1] Gain 0.25\n[2] Osc Saw 220\n[3] Osc Sine 331\n2->1, 3->1, 1->0",
+            )],
+        ),
+    ]);
+}
+
+#[test]
+fn instruments_match_their_references() {
+    let samples = SampleBank::new();
+    let multi_osc = |name: &str, oscillators, spread| {
+        let instrument = Instrument::MultiOsc {
+            oscillators,
+            spread,
+        };
+        check_track(name, &TrackConfig::new(0, instrument, adsr()), &samples)
+    };
+
+    // A slow saw on the left and one six times faster on the right, looped
+    // so notes sustain.
+    let frames: Vec<[f32; 2]> = (0..4_800)
+        .map(|i| {
+            let t = i as f32 / 4_800.0;
+            [t * 2.0 - 1.0, (t * 6.0).fract() - 0.5]
+        })
+        .collect();
+    let sample_bank = SampleBank::from([(
+        "saw".to_string(),
+        SampleBuffer::new(
+            frames,
+            SAMPLE_RATE,
+            SampleRegion {
+                loop_start: Some(1_200),
+                crossfade: 240,
+                ..Default::default()
+            },
+        ),
+    )]);
+    let sampler = |mode, reverse| Instrument::Sampler {
+        zones: vec![SampleZone {
+            mode,
+            reverse,
+            ..SampleZone::new("saw", 60)
+        }],
+        interpolation: Interpolation::Sinc,
+    };
+
+    assert_all([
+        multi_osc("multi_osc_sine", vec![osc(Wave::Sine, 0.5, 0, 0.0)], 0.0),
+        multi_osc("multi_osc_saw", vec![osc(Wave::Saw, 0.5, 0, 0.0)], 0.0),
+        multi_osc(
+            "multi_osc_square",
+            vec![osc(Wave::Square, 0.5, 0, 0.0)],
+            0.0,
+        ),
+        multi_osc(
+            "multi_osc_stack",
+            vec![
+                osc(Wave::Saw, 0.3, 0, -0.5),
+                osc(Wave::Square, 0.2, -12, 0.5),
+                osc(Wave::Sine, 0.2, 7, 0.0),
+            ],
+            0.6,
+        ),
+        check_track(
+            "sampler_one_shot",
+            &TrackConfig::new(0, sampler(SampleMode::OneShot, false), adsr()),
+            &sample_bank,
+        ),
+        check_track(
+            "sampler_loop",
+            &TrackConfig::new(0, sampler(SampleMode::Loop, false), adsr()),
+            &sample_bank,
+        ),
+        check_track(
+            "sampler_reverse",
+            &TrackConfig::new(0, sampler(SampleMode::Loop, true), adsr()),
+            &sample_bank,
+        ),
+    ]);
+}
+
+#[test]
+fn template_tracks_match_their_references() {
+    let samples = SampleBank::new();
+    let mut results = Vec::new();
+    for template in Template::ALL {
+        let project = Project::from_template(template, "Golden");
+        for track in &project.tracks {
+            if !matches!(track.instrument, Instrument::MultiOsc { .. }) {
+                continue;
+            }
+            let mut config =
+                TrackConfig::new(track.id, track.instrument.clone(), track.adsr.clone());
+            config.velocity = track.velocity;
+            config.key_tracking = track.key_tracking;
+            let name = format!(
+                "{}_{}",
+                template,
+                track.name.to_lowercase().replace(' ', "_")
+            );
+            results.push(check_track(&name, &config, &samples));
+        }
+    }
+    assert_all(results);
+}
@@ -1,11 +1,94 @@
+//! aurio's sequencer and synth engine, usable without its editor.
+//!
+//! A [`Project`] holds tracks whose [`StateGraph`]s move between nodes that
+//! play [`Sequence`]s. [`spawn_engine`] starts an engine driven by
+//! [`EngineCommand`]s and reporting [`EngineUpdate`]s; [`render_offline`]
+//! renders a project without an audio device.
+//!
+//! Cargo features:
+//! - `ui` (default): the egui editor, [`AurioApp`], and the `aurio` binary.
+//! - `audio-host` (default): playback and recording through the system's
+//!   audio devices. Without it, [`render_offline`] still works and playing
+//!   reports an [`AurioError::AudioDevice`].
+//! - `jack`: the JACK backend.
+//! - `link`: tempo sync with other apps through Ableton Link; needs CMake
+//!   and a C++ compiler to build.
+//! - `wasm`: bindings for running patches in an AudioWorklet, see `web/`.
+//! - `ffi`: C functions to build and process `.au` graphs, declared in
+//!   `include/aurio.h`.
+//! - `python`: the `aurio` Python module, to write projects and render them
+//!   offline from Python.
+//! - `alloc-audit`: aborts on allocations in the audio callback.
+//! - `std` (default): everything but [`core`], which is all that builds with
+//!   `no_std` instead, for embedded targets:
+//!   `default-features = false, features = ["no_std"]`.
+//! - `dsp`: [`core`] and the `.au` patches of [`dsp`], with std but without
+//!   the engine and its dependencies; `std` and `wasm` include it.
+
+#![cfg_attr(feature = "no_std", no_std)]
+
+extern crate alloc;
+
+#[cfg(all(feature = "dsp", feature = "no_std"))]
+compile_error!("the no_std feature needs aurio's default features turned off");
+#[cfg(not(any(feature = "dsp", feature = "no_std")))]
+compile_error!("aurio needs either its std or its no_std feature");
+
+#[cfg(feature = "std")]
 pub mod audio;
+#[cfg(feature = "std")]
+pub mod audit;
+#[cfg(feature = "std")]
+pub mod check;
+pub mod core;
+#[cfg(feature = "dsp")]
+pub mod dsp;
+#[cfg(feature = "std")]
 pub mod engine;
+#[cfg(feature = "dsp")]
+pub mod error;
+#[cfg(feature = "std")]
 pub mod events;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(all(test, feature = "std"))]
+mod golden;
+#[cfg(feature = "std")]
+pub mod live;
+#[cfg(feature = "std")]
+pub mod logging;
+#[cfg(feature = "std")]
+pub mod midi;
+#[cfg(feature = "std")]
+pub mod osc;
+#[cfg(feature = "std")]
+pub mod plugin;
+#[cfg(feature = "std")]
 pub mod project;
+#[cfg(feature = "python")]
+pub mod python;
+#[cfg(feature = "std")]
 pub mod scripting;
+#[cfg(feature = "std")]
+pub mod sync;
+#[cfg(feature = "std")]
+pub mod templates;
+#[cfg(feature = "std")]
 pub mod timing;
+#[cfg(feature = "ui")]
 pub mod ui;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 
-pub use engine::{EngineCommand, EngineHandle, EngineUpdate, spawn_engine};
-pub use project::{Project, SampleRef, TrackData};
+#[cfg(feature = "std")]
+pub use engine::{
+    EngineCommand, EngineHandle, EngineUpdate, render_offline, spawn_engine, spawn_engine_with,
+};
+#[cfg(feature = "dsp")]
+pub use error::AurioError;
+#[cfg(feature = "std")]
+pub use project::{Project, ProjectFormat, SampleRef, TrackData};
+#[cfg(feature = "std")]
+pub use timing::{Arrangement, Edge, Node, Sequence, StateGraph, TransitionTiming};
+#[cfg(feature = "ui")]
 pub use ui::AurioApp;
@@ -0,0 +1,297 @@
+//! Live coding: a line protocol applied to whatever is playing, read from stdin
+//! or a local TCP port. Every line gets `ok` or `error: <reason>` back.
+//!
+//! - `set <node>.<param> <value>`, e.g. `set 2.freq 440`
+//! - `bypass <node> [on|off]` takes a node out of the patch, passing its input
+//!   through, or puts it back
+//! - `probe <node> [seconds]` plays a node's output instead of the patch's,
+//!   for that long if given, and `probe off` plays the patch again
+//! - `swap <file.au>` replaces the whole patch
+//! - `bpm <tempo>`
+//! - any other line is `.au` source: `[5] Osc Saw 110` adds (or replaces) a
+//!   node and `5->2` adds a wire
+//!
+//! Blank lines and `#` comments are ignored.
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LiveCommand {
+    Set {
+        node_id: u32,
+        param: String,
+        value: f32,
+    },
+    Bypass {
+        node_id: u32,
+        bypass: bool,
+    },
+    /// `None` ends the probe.
+    Probe {
+        node_id: Option<u32>,
+        seconds: Option<f32>,
+    },
+    Swap(PathBuf),
+    Bpm(f32),
+    /// `.au` source to patch into the running graph.
+    Patch(String),
+}
+
+impl LiveCommand {
+    /// Parses one line; `None` for lines with nothing to do.
+    pub fn parse(line: &str) -> Option<Result<Self, String>> {
+        let line = crate::dsp::strip_comment(line).trim();
+        if line.is_empty() {
+            return None;
+        }
+        let (word, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let rest = rest.trim();
+        Some(match word {
+            "set" => parse_set(rest),
+            "bypass" => parse_bypass(rest),
+            "probe" => parse_probe(rest),
+            "swap" if !rest.is_empty() => Ok(LiveCommand::Swap(PathBuf::from(rest))),
+            "swap" => Err("usage: swap <file.au>".to_string()),
+            "bpm" => rest
+                .parse()
+                .ok()
+                .filter(|bpm: &f32| bpm.is_finite() && *bpm > 0.0)
+                .map(LiveCommand::Bpm)
+                .ok_or_else(|| format!("invalid tempo '{}'", rest)),
+            _ => Ok(LiveCommand::Patch(line.to_string())),
+        })
+    }
+}
+
+fn parse_set(args: &str) -> Result<LiveCommand, String> {
+    let usage = || "usage: set <node>.<param> <value>".to_string();
+    let (target, value) = args.split_once(char::is_whitespace).ok_or_else(usage)?;
+    let (node_id, param) = target.split_once('.').ok_or_else(usage)?;
+    Ok(LiveCommand::Set {
+        node_id: node_id
+            .parse()
+            .map_err(|_| format!("invalid node id '{}'", node_id))?,
+        param: param.to_string(),
+        value: value
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid value '{}'", value.trim()))?,
+    })
+}
+
+fn parse_bypass(args: &str) -> Result<LiveCommand, String> {
+    let mut parts = args.split_whitespace();
+    let node_id = parts
+        .next()
+        .ok_or_else(|| "usage: bypass <node> [on|off]".to_string())?;
+    let bypass = match parts.next() {
+        None | Some("on") => true,
+        Some("off") => false,
+        Some(other) => return Err(format!("expected on or off, not '{}'", other)),
+    };
+    Ok(LiveCommand::Bypass {
+        node_id: node_id
+            .parse()
+            .map_err(|_| format!("invalid node id '{}'", node_id))?,
+        bypass,
+    })
+}
+
+fn parse_probe(args: &str) -> Result<LiveCommand, String> {
+    let mut parts = args.split_whitespace();
+    let node_id = match parts.next() {
+        Some("off") => None,
+        Some(node_id) => Some(
+            node_id
+                .parse()
+                .map_err(|_| format!("invalid node id '{}'", node_id))?,
+        ),
+        None => return Err("usage: probe <node> [seconds] | probe off".to_string()),
+    };
+    let seconds = parts
+        .next()
+        .map(|seconds| {
+            seconds
+                .parse()
+                .ok()
+                .filter(|seconds: &f32| *seconds > 0.0)
+                .ok_or_else(|| format!("invalid length '{}'", seconds))
+        })
+        .transpose()?;
+    Ok(LiveCommand::Probe { node_id, seconds })
+}
+
+/// Applies a command to whatever is playing.
+pub type LiveHandler = Arc<dyn Fn(LiveCommand) -> Result<(), String> + Send + Sync>;
+
+/// Answers every line of `input` on `output` until the input ends.
+pub fn serve(
+    input: impl BufRead,
+    mut output: impl Write,
+    handler: &LiveHandler,
+) -> std::io::Result<()> {
+    for line in input.lines() {
+        let reply = match LiveCommand::parse(&line?) {
+            None => continue,
+            Some(command) => command.and_then(|command| handler(command)),
+        };
+        match reply {
+            Ok(()) => writeln!(output, "ok")?,
+            Err(e) => writeln!(output, "error: {}", e)?,
+        }
+        output.flush()?;
+    }
+    Ok(())
+}
+
+/// A TCP listener on localhost, serving each connection on its own thread.
+/// Dropping it stops accepting connections.
+pub struct LiveServer {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    pub port: u16,
+}
+
+impl LiveServer {
+    pub fn start(port: u16, handler: LiveHandler) -> std::io::Result<Self> {
+        // Anyone who can connect can change what's playing: local only.
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        listener.set_nonblocking(true)?;
+        let port = listener.local_addr()?.port();
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let thread = std::thread::spawn(move || {
+            while !thread_stop.load(Ordering::Relaxed) {
+                let stream = match listener.accept() {
+                    Ok((stream, _)) => stream,
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                        std::thread::sleep(Duration::from_millis(100));
+                        continue;
+                    }
+                    Err(e) => {
+                        tracing::warn!("Live connection error: {}", e);
+                        continue;
+                    }
+                };
+                let handler = handler.clone();
+                std::thread::spawn(move || {
+                    let result = stream
+                        .set_nonblocking(false)
+                        .and_then(|()| stream.try_clone())
+                        .and_then(|input| serve(BufReader::new(input), &stream, &handler));
+                    if let Err(e) = result {
+                        tracing::warn!("Live connection error: {}", e);
+                    }
+                });
+            }
+        });
+
+        tracing::info!("Live coding on TCP port {}", port);
+        Ok(Self {
+            stop,
+            thread: Some(thread),
+            port,
+        })
+    }
+}
+
+impl Drop for LiveServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parking_lot::Mutex;
+
+    #[test]
+    fn parses_commands_and_fragments() {
+        assert_eq!(
+            LiveCommand::parse("set 2.freq 440"),
+            Some(Ok(LiveCommand::Set {
+                node_id: 2,
+                param: "freq".to_string(),
+                value: 440.0,
+            }))
+        );
+        assert_eq!(
+            LiveCommand::parse("bpm 128 # faster"),
+            Some(Ok(LiveCommand::Bpm(128.0)))
+        );
+        assert_eq!(
+            LiveCommand::parse("5->2"),
+            Some(Ok(LiveCommand::Patch("5->2".to_string())))
+        );
+        assert_eq!(
+            LiveCommand::parse("[5] Seq C#4 E4 # arpeggio"),
+            Some(Ok(LiveCommand::Patch("[5] Seq C#4 E4".to_string())))
+        );
+        assert_eq!(
+            LiveCommand::parse("bypass 3 off"),
+            Some(Ok(LiveCommand::Bypass {
+                node_id: 3,
+                bypass: false,
+            }))
+        );
+        assert!(matches!(
+            LiveCommand::parse("bypass 3"),
+            Some(Ok(LiveCommand::Bypass { bypass: true, .. }))
+        ));
+        assert!(matches!(LiveCommand::parse("bypass 3 maybe"), Some(Err(_))));
+        assert_eq!(
+            LiveCommand::parse("probe 4 1.5"),
+            Some(Ok(LiveCommand::Probe {
+                node_id: Some(4),
+                seconds: Some(1.5),
+            }))
+        );
+        assert_eq!(
+            LiveCommand::parse("probe off"),
+            Some(Ok(LiveCommand::Probe {
+                node_id: None,
+                seconds: None,
+            }))
+        );
+        assert!(matches!(LiveCommand::parse("probe 4 -1"), Some(Err(_))));
+        assert!(matches!(LiveCommand::parse("set 2 440"), Some(Err(_))));
+        assert_eq!(LiveCommand::parse("  # nothing"), None);
+    }
+
+    #[test]
+    fn answers_every_command() {
+        let applied = Arc::new(Mutex::new(Vec::new()));
+        let log = applied.clone();
+        let handler: LiveHandler = Arc::new(move |command| match command {
+            LiveCommand::Bpm(_) => Err("no tempo".to_string()),
+            command => {
+                log.lock().push(command);
+                Ok(())
+            }
+        });
+
+        let mut output = Vec::new();
+        serve(
+            &b"swap a.au\n\nbpm 90\nbpm fast\n"[..],
+            &mut output,
+            &handler,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "ok\nerror: no tempo\nerror: invalid tempo 'fast'\n"
+        );
+        assert_eq!(*applied.lock(), vec![LiveCommand::Swap("a.au".into())]);
+    }
+}
@@ -0,0 +1,260 @@
+//! Log output through `tracing`. [`init`] installs a [`Logger`] that prints
+//! what the engine, timing and watcher threads report to stderr, prefixed
+//! with the spans it happened in (the command being handled, the transition
+//! being made, the sequence being scheduled), and passes each record on to
+//! the UI's log panel.
+
+use crossbeam::channel::{Receiver, Sender};
+use parking_lot::Mutex;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{self, Write as _};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tracing::field::{Field, Visit};
+use tracing::level_filters::LevelFilter;
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Metadata, Subscriber};
+
+/// Environment variable with the most verbose level printed, e.g. `debug`.
+pub const LOG_ENV: &str = "AURIO_LOG";
+
+/// Records waiting for the log panel; newer ones are dropped while it's full.
+const LOG_CAPACITY: usize = 1024;
+
+/// One event, as the log panel shows it.
+#[derive(Debug, Clone)]
+pub struct LogRecord {
+    pub level: Level,
+    /// Spans the event happened in, outermost first, e.g.
+    /// `command{kind=Play} > transition{track=0 from=intro}`.
+    pub spans: String,
+    pub message: String,
+}
+
+impl fmt::Display for LogRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:>5} ", self.level)?;
+        if !self.spans.is_empty() {
+            write!(f, "{}: ", self.spans)?;
+        }
+        f.write_str(&self.message)
+    }
+}
+
+struct SpanData {
+    name: &'static str,
+    fields: String,
+    /// Handles to the span still alive; it's forgotten at zero.
+    refs: usize,
+}
+
+thread_local! {
+    /// Spans entered on this thread, innermost last.
+    static ENTERED: RefCell<Vec<u64>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Prints events at or under a level to stderr and sends them to a
+/// [`Receiver`] for the UI.
+pub struct Logger {
+    max_level: LevelFilter,
+    spans: Mutex<HashMap<u64, SpanData>>,
+    next_id: AtomicU64,
+    records: Sender<LogRecord>,
+}
+
+impl Logger {
+    pub fn new(max_level: LevelFilter) -> (Self, Receiver<LogRecord>) {
+        let (records, record_rx) = crossbeam::channel::bounded(LOG_CAPACITY);
+        let logger = Self {
+            max_level,
+            spans: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(1),
+            records,
+        };
+        (logger, record_rx)
+    }
+
+    /// The spans entered on this thread, as `name{fields} > ...`.
+    fn current_spans(&self) -> String {
+        let spans = self.spans.lock();
+        ENTERED.with(|entered| {
+            let mut text = String::new();
+            for id in entered.borrow().iter() {
+                let Some(span) = spans.get(id) else {
+                    continue;
+                };
+                if !text.is_empty() {
+                    text.push_str(" > ");
+                }
+                text.push_str(span.name);
+                if !span.fields.is_empty() {
+                    let _ = write!(text, "{{{}}}", span.fields);
+                }
+            }
+            text
+        })
+    }
+}
+
+impl Subscriber for Logger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        *metadata.level() <= self.max_level
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        Some(self.max_level)
+    }
+
+    fn new_span(&self, attributes: &Attributes<'_>) -> Id {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut fields = String::new();
+        attributes.record(&mut FieldWriter::new(&mut fields));
+        self.spans.lock().insert(
+            id,
+            SpanData {
+                name: attributes.metadata().name(),
+                fields,
+                refs: 1,
+            },
+        );
+        Id::from_u64(id)
+    }
+
+    fn record(&self, span: &Id, values: &Record<'_>) {
+        if let Some(span) = self.spans.lock().get_mut(&span.into_u64()) {
+            values.record(&mut FieldWriter::new(&mut span.fields));
+        }
+    }
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut message = String::new();
+        event.record(&mut FieldWriter::new(&mut message));
+        let record = LogRecord {
+            level: *event.metadata().level(),
+            spans: self.current_spans(),
+            message,
+        };
+        eprintln!("{}", record);
+        let _ = self.records.try_send(record);
+    }
+
+    fn enter(&self, span: &Id) {
+        ENTERED.with(|entered| entered.borrow_mut().push(span.into_u64()));
+    }
+
+    fn exit(&self, span: &Id) {
+        ENTERED.with(|entered| {
+            let mut entered = entered.borrow_mut();
+            if let Some(i) = entered.iter().rposition(|&id| id == span.into_u64()) {
+                entered.remove(i);
+            }
+        });
+    }
+
+    fn clone_span(&self, span: &Id) -> Id {
+        if let Some(data) = self.spans.lock().get_mut(&span.into_u64()) {
+            data.refs += 1;
+        }
+        span.clone()
+    }
+
+    fn try_close(&self, span: Id) -> bool {
+        let mut spans = self.spans.lock();
+        let Some(data) = spans.get_mut(&span.into_u64()) else {
+            return false;
+        };
+        data.refs -= 1;
+        if data.refs == 0 {
+            spans.remove(&span.into_u64());
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Writes an event's message followed by its fields, or a span's fields,
+/// as `message key=value ...`.
+struct FieldWriter<'a> {
+    text: &'a mut String,
+}
+
+impl<'a> FieldWriter<'a> {
+    fn new(text: &'a mut String) -> Self {
+        Self { text }
+    }
+
+    fn separate(&mut self) {
+        if !self.text.is_empty() {
+            self.text.push(' ');
+        }
+    }
+}
+
+impl Visit for FieldWriter<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.record_debug(field, &format_args!("{}", value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            // The message leads, whatever order the fields came in.
+            let message = format!("{:?}", value);
+            self.text.insert_str(0, &message);
+            if self.text.len() > message.len() {
+                self.text.insert(message.len(), ' ');
+            }
+        } else {
+            self.separate();
+            let _ = write!(self.text, "{}={:?}", field.name(), value);
+        }
+    }
+}
+
+/// The level in [`LOG_ENV`], else `info`.
+pub fn level_from_env() -> LevelFilter {
+    std::env::var(LOG_ENV)
+        .ok()
+        .and_then(|level| level.parse().ok())
+        .unwrap_or(LevelFilter::INFO)
+}
+
+/// Makes a [`Logger`] for `max_level` the global subscriber and returns the
+/// records it sends. Nothing is sent when a subscriber was already set.
+pub fn init(max_level: LevelFilter) -> Receiver<LogRecord> {
+    let (logger, records) = Logger::new(max_level);
+    let _ = tracing::subscriber::set_global_default(logger);
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_carry_their_spans_and_fields() {
+        let (logger, records) = Logger::new(LevelFilter::INFO);
+        tracing::subscriber::with_default(logger, || {
+            let _command = tracing::info_span!("command", kind = "Play").entered();
+            {
+                let _transition = tracing::info_span!("transition", track = 0).entered();
+                tracing::info!(node = "verse", "moving on");
+            }
+            tracing::debug!("too verbose");
+            tracing::warn!("no device");
+        });
+
+        let records: Vec<LogRecord> = records.try_iter().collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].level, Level::INFO);
+        assert_eq!(records[0].spans, "command{kind=Play} > transition{track=0}");
+        assert_eq!(records[0].message, "moving on node=verse");
+        assert_eq!(records[1].spans, "command{kind=Play}");
+        assert_eq!(
+            records[1].to_string(),
+            " WARN command{kind=Play}: no device"
+        );
+    }
+}
@@ -1,7 +1,33 @@
-use aurio::{AurioApp, spawn_engine};
+use aurio::dsp::{NodeRegistry, ProcessContext, parse_with};
+use aurio::live::{LiveCommand, LiveHandler, LiveServer};
+use aurio::logging;
+use aurio::templates::Template;
+use aurio::{
+    AurioApp, EngineCommand, EngineHandle, EngineUpdate, Project, ProjectFormat, check,
+    render_offline, spawn_engine,
+};
+use crossbeam::channel::Sender;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::Path;
+use std::sync::Arc;
+
+const USAGE: &str = "[new <path.aurio> [--template <name>] | play <project> [--watch] [--listen <port>] | check <project|file.au> | render <project|file.au> (--bars <n> | --seconds <s>) [--probe <node>] [--oversample <2|4>] -o <out.wav>]";
+
+/// Longest Ctrl+C waits for the engine to fade out and stop.
+const SHUTDOWN_WAIT: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Sample rate `.au` graphs are rendered at; they don't carry one.
+const GRAPH_SAMPLE_RATE: u32 = 48_000;
 
 fn main() {
+    let log = logging::init(logging::level_from_env());
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() > 1 {
+        std::process::exit(run_command(&args));
+    }
+
     let engine = spawn_engine();
+    shutdown_on_ctrl_c(&engine);
 
     let icon_image = image::open("assets/icon.png")
         .expect("Failed to open icon path")
@@ -27,6 +53,444 @@ fn main() {
     let _ = eframe::run_native(
         "Aurio",
         options,
-        Box::new(|_cc| Ok(Box::new(AurioApp::new(engine)))),
+        Box::new(|_cc| Ok(Box::new(AurioApp::new(engine, log)))),
+    );
+}
+
+/// Lets the engine fade the output out on Ctrl+C rather than dying mid-buffer.
+fn shutdown_on_ctrl_c(engine: &EngineHandle) {
+    let shutdown_tx = engine.command_tx.clone();
+    if let Err(e) = ctrlc::set_handler(move || {
+        let (done, finished) = crossbeam::channel::bounded(1);
+        if shutdown_tx.send(EngineCommand::Shutdown { done }).is_ok() {
+            let _ = finished.recv_timeout(SHUTDOWN_WAIT);
+        }
+        std::process::exit(130);
+    }) {
+        eprintln!("Failed to install the Ctrl+C handler: {}", e);
+    }
+}
+
+fn run_command(args: &[String]) -> i32 {
+    match args[1].as_str() {
+        "new" => match parse_new_args(&args[2..]) {
+            Ok((path, template)) => create_project(path, template),
+            Err(e) => {
+                eprintln!("{}", e);
+                eprintln!(
+                    "Usage: {} new <path.aurio> [--template empty|techno|ambient|tutorial]",
+                    args[0]
+                );
+                1
+            }
+        },
+        "play" => match parse_play_args(&args[2..]) {
+            Ok(play) => run_play(&play),
+            Err(e) => {
+                eprintln!("{}", e);
+                eprintln!(
+                    "Usage: {} play <project> [--watch] [--listen <port>]",
+                    args[0]
+                );
+                1
+            }
+        },
+        "render" => match parse_render_args(&args[2..]) {
+            Ok(render) => run_render(&render),
+            Err(e) => {
+                eprintln!("{}", e);
+                eprintln!(
+                    "Usage: {} render <project|file.au> (--bars <n> | --seconds <s>) [--probe <node>] [--oversample <2|4>] -o <out.wav>",
+                    args[0]
+                );
+                1
+            }
+        },
+        "check" => match args.get(2) {
+            Some(path) => run_check(Path::new(path)),
+            None => {
+                eprintln!("Usage: {} check <project|file.au>", args[0]);
+                1
+            }
+        },
+        other => {
+            eprintln!("Unknown command '{}'", other);
+            eprintln!("Usage: {} {}", args[0], USAGE);
+            1
+        }
+    }
+}
+
+struct PlayArgs<'a> {
+    path: &'a Path,
+    watch: bool,
+    listen: Option<u16>,
+}
+
+fn parse_play_args(args: &[String]) -> Result<PlayArgs<'_>, String> {
+    let mut path = None;
+    let mut watch = false;
+    let mut listen = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--watch" => watch = true,
+            "--listen" => {
+                let port = args.next().ok_or("--listen needs a port")?;
+                listen = Some(
+                    port.parse()
+                        .map_err(|_| format!("Invalid port '{}'", port))?,
+                );
+            }
+            flag if flag.starts_with('-') => return Err(format!("Unknown option '{}'", flag)),
+            arg => path = Some(Path::new(arg)),
+        }
+    }
+
+    Ok(PlayArgs {
+        path: path.ok_or("Missing the project to play")?,
+        watch,
+        listen,
+    })
+}
+
+/// Plays a project without the UI until Ctrl+C. Scripts and samples reload live
+/// as they do in the editor; with `--watch`, so does the project file. With
+/// `--listen`, live-coding commands set the tempo.
+fn run_play(play: &PlayArgs) -> i32 {
+    let path = play.path;
+    let project = match Project::load(path) {
+        Ok(project) => project,
+        Err(e) => {
+            eprintln!("Failed to load {}: {}", path.display(), e);
+            return 1;
+        }
+    };
+
+    let engine = spawn_engine();
+    shutdown_on_ctrl_c(&engine);
+    let _ = engine
+        .command_tx
+        .send(EngineCommand::LoadProject(path.to_path_buf()));
+    let _ = engine.command_tx.send(EngineCommand::Play);
+    // Commands are handled in order: the device list arriving before playback
+    // started means it couldn't.
+    let _ = engine.command_tx.send(EngineCommand::ListAudioDevices);
+
+    let _watcher = if play.watch {
+        match watch_project(path, project.bpm, engine.command_tx.clone()) {
+            Ok(watcher) => Some(watcher),
+            Err(e) => {
+                eprintln!("Failed to watch {}: {}", path.display(), e);
+                return 1;
+            }
+        }
+    } else {
+        None
+    };
+
+    let command_tx = engine.command_tx.clone();
+    let handler: LiveHandler = Arc::new(move |command| match command {
+        LiveCommand::Bpm(bpm) => command_tx
+            .send(EngineCommand::SetBpm { bpm })
+            .map_err(|e| e.to_string()),
+        _ => Err("projects only take bpm; patch commands are for .au files".to_string()),
+    });
+    let _server = match play.listen.map(|port| LiveServer::start(port, handler)) {
+        Some(Err(e)) => {
+            eprintln!("Failed to open the live-coding port: {}", e);
+            return 1;
+        }
+        server => server,
+    };
+
+    let mut playing = false;
+    for update in engine.update_rx.iter() {
+        match update {
+            EngineUpdate::PlaybackState { playing: true } => {
+                playing = true;
+                println!("Playing {} - press Ctrl+C to stop", project.name);
+            }
+            EngineUpdate::AudioDevices { .. } if !playing => return 1,
+            // The engine crashed and came back empty; pick up where it was.
+            EngineUpdate::Restarted => {
+                playing = false;
+                let _ = engine
+                    .command_tx
+                    .send(EngineCommand::LoadProject(path.to_path_buf()));
+                let _ = engine.command_tx.send(EngineCommand::Play);
+                let _ = engine.command_tx.send(EngineCommand::ListAudioDevices);
+            }
+            EngineUpdate::Error { message } => eprintln!("{}", message),
+            _ => {}
+        }
+    }
+    0
+}
+
+/// Sends the project to the engine whenever its project file is saved, the way
+/// the editor sends its edits: tracks pick up the new graphs at their next
+/// sequence boundary. Adding or removing tracks needs a restart.
+fn watch_project(
+    path: &Path,
+    mut bpm: f32,
+    command_tx: Sender<EngineCommand>,
+) -> notify::Result<RecommendedWatcher> {
+    let project_path = path.to_path_buf();
+    let format = ProjectFormat::detect(path).unwrap_or(ProjectFormat::Ron);
+    let file_path = format.file_path(&std::fs::canonicalize(path)?);
+    let mut watcher = RecommendedWatcher::new(
+        move |res: notify::Result<notify::Event>| match res {
+            Ok(event) => {
+                if (event.kind.is_modify() || event.kind.is_create())
+                    && event.paths.contains(&file_path)
+                {
+                    match Project::load(&project_path) {
+                        Ok(project) => {
+                            tracing::info!("project.{} changed, reloading", format.extension());
+                            if project.bpm != bpm {
+                                bpm = project.bpm;
+                                let _ = command_tx.send(EngineCommand::SetBpm { bpm });
+                            }
+                            let _ = command_tx.send(EngineCommand::ReloadProject(project));
+                        }
+                        Err(e) => {
+                            tracing::warn!("Failed to reload project.{}: {}", format.extension(), e)
+                        }
+                    }
+                }
+            }
+            Err(e) => tracing::warn!("Watch error: {}", e),
+        },
+        notify::Config::default(),
+    )?;
+    watcher.watch(path, RecursiveMode::NonRecursive)?;
+    Ok(watcher)
+}
+
+/// Prints the diagnostics for a project or `.au` patch. Exits with 1 when there
+/// are errors; warnings alone still pass.
+fn run_check(path: &Path) -> i32 {
+    let diagnostics = if path.extension().is_some_and(|e| e == "au") {
+        check::check_patch(path)
+    } else {
+        check::check_project(path)
+    };
+    for diagnostic in &diagnostics {
+        println!("{}", diagnostic);
+    }
+
+    let errors = diagnostics
+        .iter()
+        .filter(|d| d.severity == check::Severity::Error)
+        .count();
+    println!(
+        "{}: {} error(s), {} warning(s)",
+        path.display(),
+        errors,
+        diagnostics.len() - errors
     );
+    i32::from(errors > 0)
+}
+
+enum Length {
+    Bars(f64),
+    Seconds(f64),
+}
+
+struct RenderArgs<'a> {
+    input: &'a Path,
+    length: Length,
+    /// A `.au` graph's node to render instead of its output.
+    probe: Option<u32>,
+    /// The least a `.au` graph's oversampled nodes run at.
+    oversample: Option<u32>,
+    output: &'a Path,
+}
+
+fn parse_render_args(args: &[String]) -> Result<RenderArgs<'_>, String> {
+    let mut input = None;
+    let mut length = None;
+    let mut probe = None;
+    let mut oversample = None;
+    let mut output = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--bars" | "--seconds" => {
+                let value = value()?;
+                let amount: f64 = value
+                    .parse()
+                    .ok()
+                    .filter(|v: &f64| *v > 0.0)
+                    .ok_or_else(|| format!("Invalid length '{}'", value))?;
+                length = Some(if arg == "--bars" {
+                    Length::Bars(amount)
+                } else {
+                    Length::Seconds(amount)
+                });
+            }
+            "--probe" => {
+                let value = value()?;
+                probe = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("Invalid node id '{}'", value))?,
+                );
+            }
+            "--oversample" => {
+                let value = value()?;
+                oversample = Some(
+                    value
+                        .parse()
+                        .ok()
+                        .filter(|factor| [2, 4].contains(factor))
+                        .ok_or_else(|| format!("Invalid oversampling '{}' (2 or 4)", value))?,
+                );
+            }
+            "-o" | "--output" => output = Some(Path::new(value()?)),
+            flag if flag.starts_with('-') => return Err(format!("Unknown option '{}'", flag)),
+            path => input = Some(Path::new(path)),
+        }
+    }
+
+    Ok(RenderArgs {
+        input: input.ok_or("Missing the project or .au file to render")?,
+        length: length.ok_or("Missing --bars or --seconds")?,
+        probe,
+        oversample,
+        output: output.ok_or("Missing -o <out.wav>")?,
+    })
+}
+
+/// Bounces a project or a `.au` graph to a 32-bit float WAV file.
+fn run_render(render: &RenderArgs) -> i32 {
+    let result = if render.input.extension().is_some_and(|e| e == "au") {
+        render_graph(render)
+    } else {
+        render_project(render)
+    };
+    match result {
+        Ok(seconds) => {
+            println!("Rendered {:.1}s to {}", seconds, render.output.display());
+            0
+        }
+        Err(e) => {
+            eprintln!("Failed to render {}: {}", render.input.display(), e);
+            1
+        }
+    }
+}
+
+/// Renders a project from the start and returns the rendered length in seconds.
+/// Bars are counted in 4/4 at the project tempo.
+fn render_project(render: &RenderArgs) -> Result<f64, Box<dyn std::error::Error>> {
+    if render.probe.is_some() {
+        return Err("only .au graphs can be probed".into());
+    }
+    if render.oversample.is_some() {
+        return Err("only .au graphs can be oversampled".into());
+    }
+    let project = Project::load(render.input)?;
+    let sample_rate = project.sample_rate;
+    let seconds = match render.length {
+        Length::Bars(bars) => bars * 4.0 * 60.0 / project.bpm as f64,
+        Length::Seconds(seconds) => seconds,
+    };
+
+    let samples = render_offline(
+        &project,
+        Some(render.input),
+        (seconds * sample_rate as f64).round() as usize,
+    )?;
+    write_wav(render.output, 2, sample_rate, &samples)?;
+    Ok(seconds)
+}
+
+/// Renders a `.au` graph's mono output and returns the rendered length in seconds.
+fn render_graph(render: &RenderArgs) -> Result<f64, Box<dyn std::error::Error>> {
+    let Length::Seconds(seconds) = render.length else {
+        return Err(".au graphs have no tempo; use --seconds".into());
+    };
+    let mut registry = NodeRegistry::default();
+    if let Some(factor) = render.oversample {
+        registry.set_min_oversampling(factor);
+    }
+    let content = std::fs::read_to_string(render.input)?;
+    let mut graph = parse_with(&content, &registry)?;
+    graph.probe(render.probe, None)?;
+    let ctx = ProcessContext {
+        sample_rate: GRAPH_SAMPLE_RATE as f32,
+    };
+
+    let mut samples = vec![0.0; (seconds * GRAPH_SAMPLE_RATE as f64).round() as usize];
+    for block in samples.chunks_mut(512) {
+        graph.process(block, &ctx);
+    }
+    write_wav(render.output, 1, GRAPH_SAMPLE_RATE, &samples)?;
+    Ok(seconds)
+}
+
+fn write_wav(
+    path: &Path,
+    channels: u16,
+    sample_rate: u32,
+    samples: &[f32],
+) -> Result<(), hound::Error> {
+    let spec = hound::WavSpec {
+        channels,
+        sample_rate,
+        bits_per_sample: 32,
+        sample_format: hound::SampleFormat::Float,
+    };
+    let mut writer = hound::WavWriter::create(path, spec)?;
+    for &sample in samples {
+        writer.write_sample(sample)?;
+    }
+    writer.finalize()
+}
+
+fn parse_new_args(args: &[String]) -> Result<(&Path, Template), String> {
+    let mut path = None;
+    let mut template = Template::Empty;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--tutorial" => template = Template::Tutorial,
+            "--template" => {
+                template = args.next().ok_or("--template needs a name")?.parse()?;
+            }
+            flag if flag.starts_with('-') => return Err(format!("Unknown option '{}'", flag)),
+            arg => path = Some(Path::new(arg)),
+        }
+    }
+
+    Ok((path.ok_or("Missing the project path")?, template))
+}
+
+fn create_project(path: &Path, template: Template) -> i32 {
+    if ProjectFormat::detect(path).is_some() {
+        eprintln!("{} already contains a project", path.display());
+        return 1;
+    }
+
+    let name = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "Untitled".to_string());
+
+    match Project::from_template(template, &name).save(path) {
+        Ok(()) => {
+            println!("Created {} project at {}", template, path.display());
+            0
+        }
+        Err(e) => {
+            eprintln!("Failed to create project: {}", e);
+            1
+        }
+    }
 }
@@ -0,0 +1,576 @@
+//! MIDI input: notes played on a track, with MPE, and MIDI-learn mappings
+//! from controller CCs and notes to engine parameters.
+
+use crate::EngineCommand;
+use crate::events::{MidiMessage, NoteModulation};
+use crate::scripting::TrackParam;
+use crate::timing::TransitionTiming;
+use crossbeam::channel::Sender;
+use serde::{Deserialize, Serialize};
+
+/// Something a CC or note can be bound to.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum MidiTarget {
+    TrackVolume(usize),
+    TrackPan(usize),
+    TrackMorph(usize),
+    /// A global script variable, set to the CC value scaled to 0..1.
+    Variable(String),
+    Metronome,
+    Transport(TransportAction),
+    /// Section of the arrangement to go to once the current one ends.
+    Section(String),
+    /// Node for a track to move to, at the moment `timing` picks.
+    Transition {
+        track_id: usize,
+        node_id: String,
+        timing: TransitionTiming,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum TransportAction {
+    Play,
+    Pause,
+    Stop,
+}
+
+/// What kind of message a mapping listens to.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum MidiSource {
+    #[default]
+    Cc,
+    /// The note numbered `controller`. Its velocity is the value, and
+    /// letting go sends 0.
+    Note,
+}
+
+/// A CC or note bound to a target. Mappings are saved with the project.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MidiMapping {
+    #[serde(default)]
+    pub source: MidiSource,
+    pub channel: u8,
+    pub controller: u8,
+    pub target: MidiTarget,
+}
+
+impl MidiMapping {
+    /// What the mapping listens to, like "CC 7 (ch 1)".
+    pub fn source_label(&self) -> String {
+        let kind = match self.source {
+            MidiSource::Cc => "CC",
+            MidiSource::Note => "Note",
+        };
+        format!("{} {} (ch {})", kind, self.controller, self.channel + 1)
+    }
+}
+
+impl MidiTarget {
+    /// The command a CC value or note velocity (0-127) on this target turns
+    /// into. Switch-like targets treat values of 64 and up as on and ignore
+    /// releases; a note played at all counts as on.
+    pub fn command(&self, source: MidiSource, value: u8) -> Option<EngineCommand> {
+        let amount = value.min(127) as f32 / 127.0;
+        let pressed = match source {
+            MidiSource::Cc => value >= 64,
+            MidiSource::Note => value > 0,
+        };
+
+        let track_param = |track_id: usize, param: TrackParam, value: f32| {
+            Some(EngineCommand::SetTrackParam {
+                track_id,
+                param,
+                value,
+            })
+        };
+
+        match self {
+            MidiTarget::TrackVolume(track_id) => track_param(*track_id, TrackParam::Volume, amount),
+            MidiTarget::TrackPan(track_id) => {
+                track_param(*track_id, TrackParam::Pan, amount * 2.0 - 1.0)
+            }
+            MidiTarget::TrackMorph(track_id) => track_param(*track_id, TrackParam::Morph, amount),
+            MidiTarget::Variable(name) => Some(EngineCommand::SetVariable {
+                name: name.clone(),
+                value: amount as f64,
+            }),
+            MidiTarget::Metronome => Some(EngineCommand::SetMetronome { enabled: pressed }),
+            MidiTarget::Transport(action) if pressed => Some(match action {
+                TransportAction::Play => EngineCommand::Play,
+                TransportAction::Pause => EngineCommand::Pause,
+                TransportAction::Stop => EngineCommand::Stop,
+            }),
+            MidiTarget::Transport(_) => None,
+            MidiTarget::Section(section) if pressed => Some(EngineCommand::GoToSection {
+                section: section.clone(),
+            }),
+            MidiTarget::Section(_) => None,
+            MidiTarget::Transition {
+                track_id,
+                node_id,
+                timing,
+            } if pressed => Some(EngineCommand::TriggerTransition {
+                track_id: *track_id,
+                node_id: node_id.clone(),
+                timing: *timing,
+            }),
+            MidiTarget::Transition { .. } => None,
+        }
+    }
+
+    pub fn label(&self) -> String {
+        match self {
+            MidiTarget::TrackVolume(track_id) => format!("Track {} volume", track_id),
+            MidiTarget::TrackPan(track_id) => format!("Track {} pan", track_id),
+            MidiTarget::TrackMorph(track_id) => format!("Track {} morph", track_id),
+            MidiTarget::Variable(name) => format!("Variable {}", name),
+            MidiTarget::Metronome => "Metronome".to_string(),
+            MidiTarget::Transport(action) => format!("{:?}", action),
+            MidiTarget::Section(section) => format!("Section {}", section),
+            MidiTarget::Transition {
+                track_id,
+                node_id,
+                timing,
+            } => format!("Track {} to {} ({:?})", track_id, node_id, timing),
+        }
+    }
+}
+
+/// Splits a control change message into (channel, controller, value).
+pub fn parse_cc(message: &[u8]) -> Option<(u8, u8, u8)> {
+    match message {
+        [status, controller, value, ..] if status & 0xF0 == 0xB0 => {
+            Some((status & 0x0F, *controller, *value))
+        }
+        _ => None,
+    }
+}
+
+/// Splits any other channel message the engine plays into its channel and
+/// the message. A note-on with velocity 0 is a note-off.
+pub fn parse_message(message: &[u8]) -> Option<(u8, MidiMessage)> {
+    let (status, channel) = (message.first()? & 0xF0, message[0] & 0x0F);
+    let message = match (status, &message[1..]) {
+        (0x90, [pitch, 0, ..]) | (0x80, [pitch, _, ..]) => MidiMessage::NoteOff { pitch: *pitch },
+        (0x90, [pitch, velocity, ..]) => MidiMessage::NoteOn {
+            pitch: *pitch,
+            velocity: *velocity,
+        },
+        (0xE0, [lsb, msb, ..]) => {
+            let value = ((*msb as i32) << 7 | *lsb as i32) - 8192;
+            MidiMessage::PitchBend {
+                value: (value as f32 / 8191.0).max(-1.0),
+            }
+        }
+        (0xD0, [value, ..]) => MidiMessage::ChannelPressure { value: *value },
+        _ => return None,
+    };
+    Some((channel, message))
+}
+
+/// Controller MPE sends slide, the movement along a key, on.
+pub const SLIDE_CC: u8 = 74;
+/// The MPE lower zone's master channel, whose messages change every note.
+pub const MASTER_CHANNEL: u8 = 0;
+/// Semitones a full bend on the master channel moves notes.
+const MASTER_BEND_RANGE: f32 = 2.0;
+
+/// How notes played on MIDI controllers reach a track. Saved with the
+/// project.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MidiInputConfig {
+    /// Track the notes play on. Without one, only CCs are used.
+    pub track: Option<usize>,
+    /// MIDI Polyphonic Expression: the controller plays each note on a
+    /// channel of its own, so bends, pressure and slide on that channel
+    /// change only that note.
+    pub mpe: bool,
+    /// Semitones a full pitch bend moves notes. MPE controllers usually
+    /// expect 48.
+    pub bend_range: f32,
+}
+
+impl Default for MidiInputConfig {
+    fn default() -> Self {
+        Self {
+            track: None,
+            mpe: false,
+            bend_range: 2.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ChannelState {
+    /// From -1 to 1.
+    bend: f32,
+    pressure: f32,
+    slide: f32,
+}
+
+impl Default for ChannelState {
+    fn default() -> Self {
+        Self {
+            bend: 0.0,
+            pressure: 0.0,
+            slide: 0.5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Voice {
+    channel: u8,
+    pitch: u8,
+    /// Where the note was started, so it's stopped there even if the input
+    /// track has changed since.
+    track_id: usize,
+}
+
+/// Turns controller notes and their channels' bends, pressure and slide into
+/// notes and per-note changes on the input track.
+#[derive(Debug, Default)]
+pub struct NoteRouter {
+    voices: Vec<Voice>,
+    channels: [ChannelState; 16],
+}
+
+impl NoteRouter {
+    pub fn handle(
+        &mut self,
+        config: &MidiInputConfig,
+        channel: u8,
+        message: MidiMessage,
+    ) -> Vec<EngineCommand> {
+        let channel = channel & 0x0F;
+        match message {
+            MidiMessage::NoteOn { pitch, velocity } => {
+                let Some(track_id) = config.track else {
+                    return Vec::new();
+                };
+                self.voices
+                    .retain(|v| (v.channel, v.pitch) != (channel, pitch));
+                let voice = Voice {
+                    channel,
+                    pitch,
+                    track_id,
+                };
+                self.voices.push(voice);
+
+                // Expression set on the channel before the note applies from
+                // its start, as MPE controllers expect.
+                let state = self.channels[channel as usize];
+                let mut commands = vec![EngineCommand::NoteOn {
+                    track_id,
+                    pitch,
+                    velocity,
+                }];
+                let bend = self.bend(config, &voice);
+                if bend != 0.0 {
+                    commands.push(modulate(&voice, NoteModulation::Bend { semitones: bend }));
+                }
+                if state.pressure != 0.0 {
+                    commands.push(modulate(&voice, NoteModulation::Pressure(state.pressure)));
+                }
+                if state.slide != 0.5 {
+                    commands.push(modulate(&voice, NoteModulation::Slide(state.slide)));
+                }
+                commands
+            }
+            MidiMessage::NoteOff { pitch } => {
+                let Some(index) = self
+                    .voices
+                    .iter()
+                    .position(|v| (v.channel, v.pitch) == (channel, pitch))
+                else {
+                    return Vec::new();
+                };
+                let voice = self.voices.remove(index);
+                vec![EngineCommand::NoteOff {
+                    track_id: voice.track_id,
+                    pitch,
+                }]
+            }
+            MidiMessage::PitchBend { value } => {
+                self.channels[channel as usize].bend = value;
+                self.affected(config, channel)
+                    .map(|voice| {
+                        let semitones = self.bend(config, voice);
+                        modulate(voice, NoteModulation::Bend { semitones })
+                    })
+                    .collect()
+            }
+            MidiMessage::ChannelPressure { value } => {
+                let pressure = value.min(127) as f32 / 127.0;
+                self.channels[channel as usize].pressure = pressure;
+                self.affected(config, channel)
+                    .map(|voice| modulate(voice, NoteModulation::Pressure(pressure)))
+                    .collect()
+            }
+        }
+    }
+
+    /// Handles a slide CC, which only means slide on MPE's member channels.
+    /// Returns `None` for a CC that isn't slide.
+    pub fn slide(
+        &mut self,
+        config: &MidiInputConfig,
+        channel: u8,
+        controller: u8,
+        value: u8,
+    ) -> Option<Vec<EngineCommand>> {
+        if !config.mpe || controller != SLIDE_CC || channel == MASTER_CHANNEL {
+            return None;
+        }
+        let slide = value.min(127) as f32 / 127.0;
+        self.channels[channel as usize & 0x0F].slide = slide;
+        Some(
+            self.affected(config, channel)
+                .map(|voice| modulate(voice, NoteModulation::Slide(slide)))
+                .collect(),
+        )
+    }
+
+    /// Notes a message on `channel` changes: in MPE, all of them for the
+    /// master channel.
+    fn affected(&self, config: &MidiInputConfig, channel: u8) -> impl Iterator<Item = &Voice> {
+        let all = config.mpe && channel == MASTER_CHANNEL;
+        self.voices
+            .iter()
+            .filter(move |v| all || v.channel == channel)
+    }
+
+    /// Semitones `voice` is bent by, counting an MPE master channel bend.
+    fn bend(&self, config: &MidiInputConfig, voice: &Voice) -> f32 {
+        let own = self.channels[voice.channel as usize].bend * config.bend_range;
+        if config.mpe && voice.channel != MASTER_CHANNEL {
+            own + self.channels[MASTER_CHANNEL as usize].bend * MASTER_BEND_RANGE
+        } else {
+            own
+        }
+    }
+}
+
+fn modulate(voice: &Voice, modulation: NoteModulation) -> EngineCommand {
+    EngineCommand::NoteModulation {
+        track_id: voice.track_id,
+        pitch: voice.pitch,
+        modulation,
+    }
+}
+
+/// Connects to every MIDI input port and forwards their notes and CCs to the
+/// engine.
+///
+/// The connections stop when dropped. Ports that fail to open are skipped.
+pub fn connect_inputs(command_tx: Sender<EngineCommand>) -> Vec<midir::MidiInputConnection<()>> {
+    let ports = match midir::MidiInput::new("aurio") {
+        Ok(midi_in) => midi_in.ports(),
+        Err(e) => {
+            tracing::warn!("MIDI input unavailable: {}", e);
+            return Vec::new();
+        }
+    };
+
+    ports
+        .iter()
+        .filter_map(|port| {
+            // Each connection consumes its MidiInput.
+            let midi_in = midir::MidiInput::new("aurio").ok()?;
+            let name = midi_in.port_name(port).unwrap_or_default();
+            let command_tx = command_tx.clone();
+            match midi_in.connect(
+                port,
+                "aurio-input",
+                move |_, message, _| {
+                    if let Some((channel, controller, value)) = parse_cc(message) {
+                        let _ = command_tx.send(EngineCommand::MidiCc {
+                            channel,
+                            controller,
+                            value,
+                        });
+                    } else if let Some((channel, message)) = parse_message(message) {
+                        let _ = command_tx.send(EngineCommand::MidiNote { channel, message });
+                    }
+                },
+                (),
+            ) {
+                Ok(connection) => {
+                    tracing::info!("MIDI input: {}", name);
+                    Some(connection)
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to connect to MIDI input {}: {}", name, e);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_control_changes_only() {
+        assert_eq!(parse_cc(&[0xB3, 48, 100]), Some((3, 48, 100)));
+        assert_eq!(parse_cc(&[0x90, 60, 100]), None);
+        assert_eq!(parse_cc(&[0xB0, 48]), None);
+    }
+
+    #[test]
+    fn parses_notes_bends_and_pressure() {
+        assert_eq!(
+            parse_message(&[0x92, 60, 100]),
+            Some((
+                2,
+                MidiMessage::NoteOn {
+                    pitch: 60,
+                    velocity: 100
+                }
+            ))
+        );
+        assert_eq!(
+            parse_message(&[0x90, 60, 0]),
+            Some((0, MidiMessage::NoteOff { pitch: 60 }))
+        );
+        assert_eq!(
+            parse_message(&[0xE1, 0x7F, 0x7F]),
+            Some((1, MidiMessage::PitchBend { value: 1.0 }))
+        );
+        assert_eq!(
+            parse_message(&[0xE1, 0, 0x40]),
+            Some((1, MidiMessage::PitchBend { value: 0.0 }))
+        );
+        assert_eq!(parse_message(&[0xB0, 7, 100]), None);
+    }
+
+    #[test]
+    fn mpe_expression_only_changes_its_own_note() {
+        let config = MidiInputConfig {
+            track: Some(1),
+            mpe: true,
+            bend_range: 48.0,
+        };
+        let mut router = NoteRouter::default();
+        let note_on = |pitch| MidiMessage::NoteOn {
+            pitch,
+            velocity: 100,
+        };
+        let modulations = |commands: Vec<EngineCommand>| -> Vec<(u8, NoteModulation)> {
+            commands
+                .into_iter()
+                .filter_map(|command| match command {
+                    EngineCommand::NoteModulation {
+                        track_id: 1,
+                        pitch,
+                        modulation,
+                    } => Some((pitch, modulation)),
+                    _ => None,
+                })
+                .collect()
+        };
+
+        // Pressure set before the note arrives with it.
+        router.handle(&config, 2, MidiMessage::ChannelPressure { value: 127 });
+        let started = router.handle(&config, 2, note_on(60));
+        assert!(matches!(
+            started[0],
+            EngineCommand::NoteOn {
+                track_id: 1,
+                pitch: 60,
+                ..
+            }
+        ));
+        assert_eq!(
+            modulations(started),
+            vec![(60, NoteModulation::Pressure(1.0))]
+        );
+        router.handle(&config, 3, note_on(64));
+
+        let bent = router.handle(&config, 3, MidiMessage::PitchBend { value: 0.5 });
+        assert_eq!(
+            modulations(bent),
+            vec![(64, NoteModulation::Bend { semitones: 24.0 })]
+        );
+        let slid = router.slide(&config, 2, SLIDE_CC, 0).unwrap();
+        assert_eq!(modulations(slid), vec![(60, NoteModulation::Slide(0.0))]);
+        assert!(router.slide(&config, 2, 7, 0).is_none());
+
+        // The master channel bends every note on top of its own bend.
+        let master = router.handle(&config, 0, MidiMessage::PitchBend { value: -1.0 });
+        assert_eq!(
+            modulations(master),
+            vec![
+                (60, NoteModulation::Bend { semitones: -2.0 }),
+                (64, NoteModulation::Bend { semitones: 22.0 }),
+            ]
+        );
+
+        // Notes stop on the track they started on.
+        let config = MidiInputConfig {
+            track: Some(0),
+            ..config
+        };
+        assert!(matches!(
+            router.handle(&config, 2, MidiMessage::NoteOff { pitch: 60 })[..],
+            [EngineCommand::NoteOff {
+                track_id: 1,
+                pitch: 60
+            }]
+        ));
+    }
+
+    #[test]
+    fn scales_cc_values_to_targets() {
+        match MidiTarget::TrackPan(2).command(MidiSource::Cc, 127) {
+            Some(EngineCommand::SetTrackParam {
+                track_id: 2,
+                param: TrackParam::Pan,
+                value,
+            }) => assert_eq!(value, 1.0),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(
+            MidiTarget::Transport(TransportAction::Stop)
+                .command(MidiSource::Cc, 0)
+                .is_none()
+        );
+        assert!(matches!(
+            MidiTarget::Transport(TransportAction::Play).command(MidiSource::Cc, 127),
+            Some(EngineCommand::Play)
+        ));
+        assert!(matches!(
+            MidiTarget::Section("drop".to_string()).command(MidiSource::Cc, 127),
+            Some(EngineCommand::GoToSection { section }) if section == "drop"
+        ));
+    }
+
+    #[test]
+    fn soft_notes_still_press_switches() {
+        let transition = MidiTarget::Transition {
+            track_id: 1,
+            node_id: "fill".to_string(),
+            timing: TransitionTiming::NextBar,
+        };
+        assert!(matches!(
+            transition.command(MidiSource::Note, 20),
+            Some(EngineCommand::TriggerTransition {
+                track_id: 1,
+                timing: TransitionTiming::NextBar,
+                ..
+            })
+        ));
+        assert!(transition.command(MidiSource::Note, 0).is_none());
+        assert!(transition.command(MidiSource::Cc, 20).is_none());
+        match MidiTarget::Variable("energy".to_string()).command(MidiSource::Note, 127) {
+            Some(EngineCommand::SetVariable { name, value }) => {
+                assert_eq!((name.as_str(), value), ("energy", 1.0))
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,326 @@
+//! OSC remote control: a UDP listener turning messages into engine commands.
+//!
+//! Supported addresses:
+//!
+//! - `/aurio/play`, `/aurio/pause`, `/aurio/stop`
+//! - `/aurio/bpm <float>`
+//! - `/aurio/metronome <bool|number>`
+//! - `/aurio/track/<id>/volume|pan|morph <float>`
+//! - `/aurio/var/<name> <number>`
+
+use crate::EngineCommand;
+use crate::scripting::TrackParam;
+use crossbeam::channel::Sender;
+use std::net::UdpSocket;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OscArg {
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    String(String),
+    Bool(bool),
+}
+
+impl OscArg {
+    pub fn as_f32(&self) -> Option<f32> {
+        match self {
+            OscArg::Int(i) => Some(*i as f32),
+            OscArg::Long(i) => Some(*i as f32),
+            OscArg::Float(f) => Some(*f),
+            OscArg::Double(d) => Some(*d as f32),
+            OscArg::Bool(b) => Some(if *b { 1.0 } else { 0.0 }),
+            OscArg::String(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OscMessage {
+    pub address: String,
+    pub args: Vec<OscArg>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OscError {
+    Truncated,
+    InvalidString,
+    MissingTypeTags,
+    UnsupportedType(char),
+}
+
+impl std::fmt::Display for OscError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OscError::Truncated => write!(f, "OSC packet is truncated"),
+            OscError::InvalidString => write!(f, "OSC string is not valid UTF-8"),
+            OscError::MissingTypeTags => write!(f, "OSC message has no type tag string"),
+            OscError::UnsupportedType(tag) => write!(f, "Unsupported OSC argument type '{}'", tag),
+        }
+    }
+}
+
+impl std::error::Error for OscError {}
+
+/// Decodes a packet into its messages, flattening bundles. Bundle time tags are
+/// ignored: everything is applied on arrival.
+pub fn decode(packet: &[u8]) -> Result<Vec<OscMessage>, OscError> {
+    let mut messages = Vec::new();
+    decode_into(packet, &mut messages)?;
+    Ok(messages)
+}
+
+fn decode_into(packet: &[u8], messages: &mut Vec<OscMessage>) -> Result<(), OscError> {
+    let mut reader = Reader { data: packet };
+    let address = reader.string()?;
+
+    if address == "#bundle" {
+        reader.take(8)?; // time tag
+        while !reader.data.is_empty() {
+            let size = reader.i32()?.max(0) as usize;
+            decode_into(reader.take(size)?, messages)?;
+        }
+        return Ok(());
+    }
+
+    // Some senders omit the type tags for argument-less messages.
+    if reader.data.is_empty() {
+        messages.push(OscMessage {
+            address,
+            args: Vec::new(),
+        });
+        return Ok(());
+    }
+    let tags = reader.string()?;
+    let tags = tags.strip_prefix(',').ok_or(OscError::MissingTypeTags)?;
+
+    let mut args = Vec::with_capacity(tags.len());
+    for tag in tags.chars() {
+        args.push(match tag {
+            'i' => OscArg::Int(reader.i32()?),
+            'h' => OscArg::Long(i64::from_be_bytes(reader.array()?)),
+            'f' => OscArg::Float(f32::from_be_bytes(reader.array()?)),
+            'd' => OscArg::Double(f64::from_be_bytes(reader.array()?)),
+            's' | 'S' => OscArg::String(reader.string()?),
+            'T' => OscArg::Bool(true),
+            'F' => OscArg::Bool(false),
+            other => return Err(OscError::UnsupportedType(other)),
+        });
+    }
+    messages.push(OscMessage { address, args });
+    Ok(())
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], OscError> {
+        if len > self.data.len() {
+            return Err(OscError::Truncated);
+        }
+        let (head, tail) = self.data.split_at(len);
+        self.data = tail;
+        Ok(head)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], OscError> {
+        Ok(self.take(N)?.try_into().expect("took N bytes"))
+    }
+
+    fn i32(&mut self) -> Result<i32, OscError> {
+        Ok(i32::from_be_bytes(self.array()?))
+    }
+
+    /// A NUL-terminated string padded to a multiple of four bytes.
+    fn string(&mut self) -> Result<String, OscError> {
+        let len = self
+            .data
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or(OscError::Truncated)?;
+        let padded = (len + 4) & !3;
+        let bytes = self.take(padded.min(self.data.len()))?;
+        String::from_utf8(bytes[..len].to_vec()).map_err(|_| OscError::InvalidString)
+    }
+}
+
+/// Maps a message to the engine command it stands for, if any.
+pub fn command_for(message: &OscMessage) -> Option<EngineCommand> {
+    let path = message.address.strip_prefix("/aurio/")?;
+    let value = message.args.first().and_then(OscArg::as_f32);
+    let segments: Vec<&str> = path.split('/').collect();
+
+    match segments.as_slice() {
+        ["play"] => Some(EngineCommand::Play),
+        ["pause"] => Some(EngineCommand::Pause),
+        ["stop"] => Some(EngineCommand::Stop),
+        ["bpm"] => value
+            .filter(|bpm| bpm.is_finite() && *bpm > 0.0)
+            .map(|bpm| EngineCommand::SetBpm { bpm }),
+        ["metronome"] => Some(EngineCommand::SetMetronome {
+            enabled: value.is_none_or(|v| v != 0.0),
+        }),
+        ["track", id, param] => {
+            let param = TrackParam::from_name(param)?;
+            Some(EngineCommand::SetTrackParam {
+                track_id: id.parse().ok()?,
+                param,
+                value: value?,
+            })
+        }
+        ["var", name] if !name.is_empty() => Some(EngineCommand::SetVariable {
+            name: name.to_string(),
+            value: value? as f64,
+        }),
+        _ => None,
+    }
+}
+
+/// A running OSC listener. Dropping it stops the listening thread.
+pub struct OscServer {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    pub port: u16,
+}
+
+impl OscServer {
+    pub fn start(port: u16, command_tx: Sender<EngineCommand>) -> std::io::Result<Self> {
+        let socket = UdpSocket::bind(("0.0.0.0", port))?;
+        // Wake up regularly to notice when we're asked to stop.
+        socket.set_read_timeout(Some(Duration::from_millis(200)))?;
+        let port = socket.local_addr()?.port();
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let thread = std::thread::spawn(move || {
+            let mut buffer = [0u8; 65536];
+            while !thread_stop.load(Ordering::Relaxed) {
+                let len = match socket.recv_from(&mut buffer) {
+                    Ok((len, _)) => len,
+                    Err(e)
+                        if matches!(
+                            e.kind(),
+                            std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                        ) =>
+                    {
+                        continue;
+                    }
+                    Err(e) => {
+                        tracing::warn!("OSC receive error: {}", e);
+                        continue;
+                    }
+                };
+                match decode(&buffer[..len]) {
+                    Ok(messages) => {
+                        for message in messages {
+                            match command_for(&message) {
+                                Some(command) => {
+                                    let _ = command_tx.send(command);
+                                }
+                                None => tracing::warn!("Unhandled OSC message {}", message.address),
+                            }
+                        }
+                    }
+                    Err(e) => tracing::warn!("Bad OSC packet: {}", e),
+                }
+            }
+        });
+
+        tracing::info!("OSC listening on UDP port {}", port);
+        Ok(Self {
+            stop,
+            thread: Some(thread),
+            port,
+        })
+    }
+}
+
+impl Drop for OscServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pad(bytes: &[u8]) -> Vec<u8> {
+        let mut padded = bytes.to_vec();
+        padded.push(0);
+        while !padded.len().is_multiple_of(4) {
+            padded.push(0);
+        }
+        padded
+    }
+
+    fn message(address: &str, tags: &str, args: &[u8]) -> Vec<u8> {
+        let mut packet = pad(address.as_bytes());
+        packet.extend(pad(tags.as_bytes()));
+        packet.extend_from_slice(args);
+        packet
+    }
+
+    #[test]
+    fn decodes_messages_and_bundles() {
+        let volume = message("/aurio/track/1/volume", ",f", &0.5f32.to_be_bytes());
+        let play = message("/aurio/play", ",", &[]);
+
+        let mut bundle = pad(b"#bundle");
+        bundle.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1]);
+        for part in [&volume, &play] {
+            bundle.extend_from_slice(&(part.len() as i32).to_be_bytes());
+            bundle.extend_from_slice(part);
+        }
+
+        let messages = decode(&bundle).unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].address, "/aurio/track/1/volume");
+        assert_eq!(messages[0].args, vec![OscArg::Float(0.5)]);
+        assert!(messages[1].args.is_empty());
+
+        assert_eq!(
+            decode(&message("/x", ",i", &[0, 0])),
+            Err(OscError::Truncated)
+        );
+    }
+
+    #[test]
+    fn maps_addresses_to_commands() {
+        let command = |address: &str, args: Vec<OscArg>| {
+            command_for(&OscMessage {
+                address: address.to_string(),
+                args,
+            })
+        };
+
+        assert!(matches!(
+            command("/aurio/track/2/pan", vec![OscArg::Float(-1.0)]),
+            Some(EngineCommand::SetTrackParam {
+                track_id: 2,
+                param: TrackParam::Pan,
+                ..
+            })
+        ));
+        assert!(matches!(
+            command("/aurio/var/energy", vec![OscArg::Int(3)]),
+            Some(EngineCommand::SetVariable { ref name, value }) if name == "energy" && value == 3.0
+        ));
+        assert!(matches!(
+            command("/aurio/stop", vec![]),
+            Some(EngineCommand::Stop)
+        ));
+        assert!(command("/aurio/track/x/volume", vec![OscArg::Float(1.0)]).is_none());
+        assert!(command("/other/play", vec![]).is_none());
+    }
+}
@@ -0,0 +1,275 @@
+//! The subset of the CLAP C ABI the host uses, mirrored from `clap/*.h`.
+
+#![allow(non_camel_case_types)]
+
+use std::ffi::{c_char, c_void};
+
+pub type clap_id = u32;
+
+pub const CLAP_NAME_SIZE: usize = 256;
+pub const CLAP_PATH_SIZE: usize = 1024;
+
+pub const CLAP_PLUGIN_FACTORY_ID: &std::ffi::CStr = c"clap.plugin-factory";
+pub const CLAP_EXT_PARAMS: &std::ffi::CStr = c"clap.params";
+pub const CLAP_EXT_AUDIO_PORTS: &std::ffi::CStr = c"clap.audio-ports";
+
+pub const CLAP_CORE_EVENT_SPACE_ID: u16 = 0;
+pub const CLAP_EVENT_NOTE_ON: u16 = 0;
+pub const CLAP_EVENT_NOTE_OFF: u16 = 1;
+pub const CLAP_EVENT_NOTE_CHOKE: u16 = 2;
+pub const CLAP_EVENT_NOTE_EXPRESSION: u16 = 3;
+pub const CLAP_EVENT_PARAM_VALUE: u16 = 5;
+
+pub const CLAP_NOTE_EXPRESSION_PAN: i32 = 1;
+pub const CLAP_NOTE_EXPRESSION_TUNING: i32 = 2;
+pub const CLAP_NOTE_EXPRESSION_BRIGHTNESS: i32 = 5;
+pub const CLAP_NOTE_EXPRESSION_PRESSURE: i32 = 6;
+
+pub const CLAP_PROCESS_ERROR: i32 = 0;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct clap_version {
+    pub major: u32,
+    pub minor: u32,
+    pub revision: u32,
+}
+
+pub const CLAP_VERSION: clap_version = clap_version {
+    major: 1,
+    minor: 2,
+    revision: 2,
+};
+
+#[repr(C)]
+pub struct clap_plugin_entry {
+    pub clap_version: clap_version,
+    pub init: Option<unsafe extern "C" fn(plugin_path: *const c_char) -> bool>,
+    pub deinit: Option<unsafe extern "C" fn()>,
+    pub get_factory: Option<unsafe extern "C" fn(factory_id: *const c_char) -> *const c_void>,
+}
+
+#[repr(C)]
+pub struct clap_plugin_factory {
+    pub get_plugin_count: Option<unsafe extern "C" fn(factory: *const clap_plugin_factory) -> u32>,
+    pub get_plugin_descriptor: Option<
+        unsafe extern "C" fn(
+            factory: *const clap_plugin_factory,
+            index: u32,
+        ) -> *const clap_plugin_descriptor,
+    >,
+    pub create_plugin: Option<
+        unsafe extern "C" fn(
+            factory: *const clap_plugin_factory,
+            host: *const clap_host,
+            plugin_id: *const c_char,
+        ) -> *const clap_plugin,
+    >,
+}
+
+#[repr(C)]
+pub struct clap_plugin_descriptor {
+    pub clap_version: clap_version,
+    pub id: *const c_char,
+    pub name: *const c_char,
+    pub vendor: *const c_char,
+    pub url: *const c_char,
+    pub manual_url: *const c_char,
+    pub support_url: *const c_char,
+    pub version: *const c_char,
+    pub description: *const c_char,
+    /// Null-terminated array of feature strings.
+    pub features: *const *const c_char,
+}
+
+#[repr(C)]
+pub struct clap_host {
+    pub clap_version: clap_version,
+    pub host_data: *mut c_void,
+    pub name: *const c_char,
+    pub vendor: *const c_char,
+    pub url: *const c_char,
+    pub version: *const c_char,
+    pub get_extension:
+        Option<unsafe extern "C" fn(host: *const clap_host, id: *const c_char) -> *const c_void>,
+    pub request_restart: Option<unsafe extern "C" fn(host: *const clap_host)>,
+    pub request_process: Option<unsafe extern "C" fn(host: *const clap_host)>,
+    pub request_callback: Option<unsafe extern "C" fn(host: *const clap_host)>,
+}
+
+#[repr(C)]
+pub struct clap_plugin {
+    pub desc: *const clap_plugin_descriptor,
+    pub plugin_data: *mut c_void,
+    pub init: Option<unsafe extern "C" fn(plugin: *const clap_plugin) -> bool>,
+    pub destroy: Option<unsafe extern "C" fn(plugin: *const clap_plugin)>,
+    pub activate: Option<
+        unsafe extern "C" fn(
+            plugin: *const clap_plugin,
+            sample_rate: f64,
+            min_frames_count: u32,
+            max_frames_count: u32,
+        ) -> bool,
+    >,
+    pub deactivate: Option<unsafe extern "C" fn(plugin: *const clap_plugin)>,
+    pub start_processing: Option<unsafe extern "C" fn(plugin: *const clap_plugin) -> bool>,
+    pub stop_processing: Option<unsafe extern "C" fn(plugin: *const clap_plugin)>,
+    pub reset: Option<unsafe extern "C" fn(plugin: *const clap_plugin)>,
+    pub process: Option<
+        unsafe extern "C" fn(plugin: *const clap_plugin, process: *const clap_process) -> i32,
+    >,
+    pub get_extension: Option<
+        unsafe extern "C" fn(plugin: *const clap_plugin, id: *const c_char) -> *const c_void,
+    >,
+    pub on_main_thread: Option<unsafe extern "C" fn(plugin: *const clap_plugin)>,
+}
+
+#[repr(C)]
+pub struct clap_audio_buffer {
+    pub data32: *mut *mut f32,
+    pub data64: *mut *mut f64,
+    pub channel_count: u32,
+    pub latency: u32,
+    pub constant_mask: u64,
+}
+
+#[repr(C)]
+pub struct clap_process {
+    pub steady_time: i64,
+    pub frames_count: u32,
+    pub transport: *const c_void,
+    pub audio_inputs: *const clap_audio_buffer,
+    pub audio_outputs: *mut clap_audio_buffer,
+    pub audio_inputs_count: u32,
+    pub audio_outputs_count: u32,
+    pub in_events: *const clap_input_events,
+    pub out_events: *const clap_output_events,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct clap_event_header {
+    pub size: u32,
+    pub time: u32,
+    pub space_id: u16,
+    pub type_: u16,
+    pub flags: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct clap_event_note {
+    pub header: clap_event_header,
+    pub note_id: i32,
+    pub port_index: i16,
+    pub channel: i16,
+    pub key: i16,
+    pub velocity: f64,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct clap_event_note_expression {
+    pub header: clap_event_header,
+    pub expression_id: i32,
+    pub note_id: i32,
+    pub port_index: i16,
+    pub channel: i16,
+    pub key: i16,
+    pub value: f64,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct clap_event_param_value {
+    pub header: clap_event_header,
+    pub param_id: clap_id,
+    pub cookie: *mut c_void,
+    pub note_id: i32,
+    pub port_index: i16,
+    pub channel: i16,
+    pub key: i16,
+    pub value: f64,
+}
+
+#[repr(C)]
+pub struct clap_input_events {
+    pub ctx: *mut c_void,
+    pub size: Option<unsafe extern "C" fn(list: *const clap_input_events) -> u32>,
+    pub get: Option<
+        unsafe extern "C" fn(
+            list: *const clap_input_events,
+            index: u32,
+        ) -> *const clap_event_header,
+    >,
+}
+
+#[repr(C)]
+pub struct clap_output_events {
+    pub ctx: *mut c_void,
+    pub try_push: Option<
+        unsafe extern "C" fn(
+            list: *const clap_output_events,
+            event: *const clap_event_header,
+        ) -> bool,
+    >,
+}
+
+#[repr(C)]
+pub struct clap_param_info {
+    pub id: clap_id,
+    pub flags: u32,
+    pub cookie: *mut c_void,
+    pub name: [c_char; CLAP_NAME_SIZE],
+    pub module: [c_char; CLAP_PATH_SIZE],
+    pub min_value: f64,
+    pub max_value: f64,
+    pub default_value: f64,
+}
+
+#[repr(C)]
+pub struct clap_plugin_params {
+    pub count: Option<unsafe extern "C" fn(plugin: *const clap_plugin) -> u32>,
+    pub get_info: Option<
+        unsafe extern "C" fn(
+            plugin: *const clap_plugin,
+            param_index: u32,
+            param_info: *mut clap_param_info,
+        ) -> bool,
+    >,
+    pub get_value: Option<
+        unsafe extern "C" fn(plugin: *const clap_plugin, param_id: clap_id, out: *mut f64) -> bool,
+    >,
+    pub value_to_text: *const c_void,
+    pub text_to_value: *const c_void,
+    pub flush: Option<
+        unsafe extern "C" fn(
+            plugin: *const clap_plugin,
+            in_events: *const clap_input_events,
+            out_events: *const clap_output_events,
+        ),
+    >,
+}
+
+#[repr(C)]
+pub struct clap_audio_port_info {
+    pub id: clap_id,
+    pub name: [c_char; CLAP_NAME_SIZE],
+    pub flags: u32,
+    pub channel_count: u32,
+    pub port_type: *const c_char,
+    pub in_place_pair: clap_id,
+}
+
+#[repr(C)]
+pub struct clap_plugin_audio_ports {
+    pub count: Option<unsafe extern "C" fn(plugin: *const clap_plugin, is_input: bool) -> u32>,
+    pub get: Option<
+        unsafe extern "C" fn(
+            plugin: *const clap_plugin,
+            index: u32,
+            is_input: bool,
+            info: *mut clap_audio_port_info,
+        ) -> bool,
+    >,
+}
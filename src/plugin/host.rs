@@ -0,0 +1,663 @@
+use super::ffi::*;
+use super::{PluginError, PluginInfo, PluginParam, PluginRef, PluginSlot};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::ffi::{CStr, CString, c_char, c_void};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, Weak};
+
+/// Queued events beyond this are dropped rather than grown on the audio thread.
+const MAX_EVENTS: usize = 1024;
+
+/// An event for a plugin, at a frame offset into the next processed block.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PluginEvent {
+    NoteOn {
+        key: u8,
+        velocity: u8,
+    },
+    NoteOff {
+        key: u8,
+    },
+    /// Releases every held note.
+    ReleaseAll,
+    /// Silences every voice at once.
+    StopAll,
+    Param {
+        id: u32,
+        value: f64,
+    },
+    /// Changes one sounding note, in the units CLAP gives `expression`.
+    Expression {
+        key: u8,
+        expression: NoteExpression,
+        value: f64,
+    },
+}
+
+/// Per-note controls a plugin may follow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoteExpression {
+    /// 0 is left, 0.5 centre and 1 right.
+    Pan,
+    /// Semitones away from the key.
+    Tuning,
+    /// 0 to 1.
+    Brightness,
+    /// 0 to 1.
+    Pressure,
+}
+
+/// A loaded `.clap` file. Instances of the same file share it, since a file's
+/// `init` and `deinit` must be balanced.
+struct ClapLibrary {
+    entry: *const clap_plugin_entry,
+    _library: libloading::Library,
+}
+
+// SAFETY: the entry and factory functions are thread-safe per the CLAP spec,
+// and `entry` points into `_library`, which lives as long as this struct.
+unsafe impl Send for ClapLibrary {}
+unsafe impl Sync for ClapLibrary {}
+
+static LIBRARIES: LazyLock<Mutex<HashMap<PathBuf, Weak<ClapLibrary>>>> =
+    LazyLock::new(Default::default);
+
+impl ClapLibrary {
+    fn open(path: &Path) -> Result<Arc<Self>, PluginError> {
+        let mut libraries = LIBRARIES.lock();
+        if let Some(library) = libraries.get(path).and_then(Weak::upgrade) {
+            return Ok(library);
+        }
+
+        // SAFETY: loading runs the library's initializers; plugins are trusted
+        // code the user chose to load.
+        let library = unsafe { libloading::Library::new(binary_path(path)) }
+            .map_err(|e| PluginError::Load(e.to_string()))?;
+        // SAFETY: `clap_entry` is a data symbol holding the entry struct.
+        let entry = unsafe { library.get::<*const clap_plugin_entry>(b"clap_entry\0") }
+            .map(|symbol| *symbol)
+            .map_err(|_| PluginError::NoEntry)?;
+        if entry.is_null() {
+            return Err(PluginError::NoEntry);
+        }
+
+        let path_c = CString::new(path.to_string_lossy().as_bytes())
+            .map_err(|e| PluginError::Load(e.to_string()))?;
+        // SAFETY: `entry` is valid while `library` is loaded.
+        let initialized = unsafe { (*entry).init.is_some_and(|init| init(path_c.as_ptr())) };
+        if !initialized {
+            return Err(PluginError::InitFailed);
+        }
+
+        let library = Arc::new(ClapLibrary {
+            entry,
+            _library: library,
+        });
+        libraries.insert(path.to_path_buf(), Arc::downgrade(&library));
+        Ok(library)
+    }
+
+    fn factory(&self) -> Result<&clap_plugin_factory, PluginError> {
+        // SAFETY: `entry` is valid and initialized; the factory lives as long as
+        // the library.
+        unsafe {
+            let get_factory = (*self.entry).get_factory.ok_or(PluginError::NoFactory)?;
+            let factory =
+                get_factory(CLAP_PLUGIN_FACTORY_ID.as_ptr()) as *const clap_plugin_factory;
+            factory.as_ref().ok_or(PluginError::NoFactory)
+        }
+    }
+}
+
+impl Drop for ClapLibrary {
+    fn drop(&mut self) {
+        // SAFETY: `init` succeeded in `open`, and no instance outlives this.
+        unsafe {
+            if let Some(deinit) = (*self.entry).deinit {
+                deinit();
+            }
+        }
+    }
+}
+
+/// The shared library inside a plugin: the file itself, or the binary of a
+/// macOS bundle.
+fn binary_path(path: &Path) -> PathBuf {
+    match path.file_stem() {
+        Some(stem) if path.is_dir() => path.join("Contents/MacOS").join(stem),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Reads a C string the plugin owns; null reads as empty.
+unsafe fn string(ptr: *const c_char) -> String {
+    if ptr.is_null() {
+        return String::new();
+    }
+    // SAFETY: the caller passes a valid null-terminated string.
+    unsafe { CStr::from_ptr(ptr) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Lists the plugins a `.clap` file provides.
+pub(super) fn describe(path: &Path) -> Result<Vec<PluginInfo>, PluginError> {
+    let library = ClapLibrary::open(path)?;
+    let factory = library.factory()?;
+    let (Some(count), Some(descriptor)) = (factory.get_plugin_count, factory.get_plugin_descriptor)
+    else {
+        return Err(PluginError::NoFactory);
+    };
+
+    let mut plugins = Vec::new();
+    // SAFETY: descriptors and their strings are owned by the loaded library.
+    unsafe {
+        for index in 0..count(factory) {
+            let Some(desc) = descriptor(factory, index).as_ref() else {
+                continue;
+            };
+            let mut instrument = false;
+            let mut feature = desc.features;
+            while !feature.is_null() && !(*feature).is_null() {
+                instrument |= CStr::from_ptr(*feature).to_bytes() == b"instrument";
+                feature = feature.add(1);
+            }
+            plugins.push(PluginInfo {
+                path: path.to_path_buf(),
+                id: string(desc.id),
+                name: string(desc.name),
+                vendor: string(desc.vendor),
+                instrument,
+            });
+        }
+    }
+    Ok(plugins)
+}
+
+/// Input events as the plugin reads them; header first, so a pointer to any
+/// variant is a pointer to its header.
+#[repr(C)]
+#[derive(Clone, Copy)]
+union RawEvent {
+    header: clap_event_header,
+    note: clap_event_note,
+    param: clap_event_param_value,
+    expression: clap_event_note_expression,
+}
+
+impl RawEvent {
+    fn new(time: u32, event: PluginEvent) -> Self {
+        let header = |type_, size| clap_event_header {
+            size: size as u32,
+            time,
+            space_id: CLAP_CORE_EVENT_SPACE_ID,
+            type_,
+            flags: 0,
+        };
+        let note = |type_, key: i16, velocity: f64| RawEvent {
+            note: clap_event_note {
+                header: header(type_, size_of::<clap_event_note>()),
+                note_id: -1,
+                port_index: if key < 0 { -1 } else { 0 },
+                channel: if key < 0 { -1 } else { 0 },
+                key,
+                velocity,
+            },
+        };
+        match event {
+            PluginEvent::NoteOn { key, velocity } => {
+                note(CLAP_EVENT_NOTE_ON, key as i16, velocity as f64 / 127.0)
+            }
+            PluginEvent::NoteOff { key } => note(CLAP_EVENT_NOTE_OFF, key as i16, 0.0),
+            PluginEvent::ReleaseAll => note(CLAP_EVENT_NOTE_OFF, -1, 0.0),
+            PluginEvent::StopAll => note(CLAP_EVENT_NOTE_CHOKE, -1, 0.0),
+            PluginEvent::Expression {
+                key,
+                expression,
+                value,
+            } => RawEvent {
+                expression: clap_event_note_expression {
+                    header: header(
+                        CLAP_EVENT_NOTE_EXPRESSION,
+                        size_of::<clap_event_note_expression>(),
+                    ),
+                    expression_id: match expression {
+                        NoteExpression::Pan => CLAP_NOTE_EXPRESSION_PAN,
+                        NoteExpression::Tuning => CLAP_NOTE_EXPRESSION_TUNING,
+                        NoteExpression::Brightness => CLAP_NOTE_EXPRESSION_BRIGHTNESS,
+                        NoteExpression::Pressure => CLAP_NOTE_EXPRESSION_PRESSURE,
+                    },
+                    note_id: -1,
+                    port_index: 0,
+                    channel: 0,
+                    key: key as i16,
+                    value,
+                },
+            },
+            PluginEvent::Param { id, value } => RawEvent {
+                param: clap_event_param_value {
+                    header: header(CLAP_EVENT_PARAM_VALUE, size_of::<clap_event_param_value>()),
+                    param_id: id,
+                    cookie: std::ptr::null_mut(),
+                    note_id: -1,
+                    port_index: -1,
+                    channel: -1,
+                    key: -1,
+                    value,
+                },
+            },
+        }
+    }
+
+    fn time(&self) -> u32 {
+        // SAFETY: every variant starts with the header.
+        unsafe { self.header.time }
+    }
+
+    fn set_time(&mut self, time: u32) {
+        self.header.time = time;
+    }
+}
+
+unsafe extern "C" fn events_size(list: *const clap_input_events) -> u32 {
+    // SAFETY: `ctx` points at the event vector for the duration of the call.
+    unsafe { (*((*list).ctx as *const Vec<RawEvent>)).len() as u32 }
+}
+
+unsafe extern "C" fn events_get(
+    list: *const clap_input_events,
+    index: u32,
+) -> *const clap_event_header {
+    // SAFETY: as above.
+    let events = unsafe { &*((*list).ctx as *const Vec<RawEvent>) };
+    events
+        .get(index as usize)
+        .map_or(std::ptr::null(), |event| {
+            event as *const RawEvent as *const clap_event_header
+        })
+}
+
+unsafe extern "C" fn discard_event(
+    _list: *const clap_output_events,
+    _event: *const clap_event_header,
+) -> bool {
+    true
+}
+
+unsafe extern "C" fn host_get_extension(
+    _host: *const clap_host,
+    _id: *const c_char,
+) -> *const c_void {
+    std::ptr::null()
+}
+
+unsafe extern "C" fn host_request(_host: *const clap_host) {}
+
+fn input_events(events: &Vec<RawEvent>) -> clap_input_events {
+    clap_input_events {
+        ctx: events as *const Vec<RawEvent> as *mut c_void,
+        size: Some(events_size),
+        get: Some(events_get),
+    }
+}
+
+fn output_events() -> clap_output_events {
+    clap_output_events {
+        ctx: std::ptr::null_mut(),
+        try_push: Some(discard_event),
+    }
+}
+
+/// One activated plugin. Created and dropped off the audio thread, processed
+/// on it.
+pub struct PluginInstance {
+    id: String,
+    plugin: *const clap_plugin,
+    /// The plugin keeps a pointer to this for its whole life.
+    _host: Box<clap_host>,
+    active: bool,
+    processing: bool,
+    input_channels: u32,
+    output_channels: u32,
+    max_frames: usize,
+    steady_time: i64,
+    /// Queued for the next block, in time order.
+    events: Vec<RawEvent>,
+    /// The part of `events` falling into the chunk being processed.
+    chunk_events: Vec<RawEvent>,
+    inputs: [Vec<f32>; 2],
+    outputs: [Vec<f32>; 2],
+    /// Declared last so the library unloads after the plugin is destroyed.
+    _library: Arc<ClapLibrary>,
+}
+
+// SAFETY: CLAP plugins may be moved between threads as long as main-thread and
+// audio-thread calls don't overlap. Loading, parameter setup and teardown
+// happen on the engine thread while the instance isn't being processed.
+unsafe impl Send for PluginInstance {}
+
+impl PluginInstance {
+    /// Creates and activates the plugin `plugin_ref` points to, restoring its
+    /// saved parameter values. Returns it with its full parameter list.
+    pub fn load(
+        plugin_ref: &PluginRef,
+        sample_rate: f64,
+        max_frames: usize,
+    ) -> Result<(Self, Vec<PluginParam>), PluginError> {
+        let library = ClapLibrary::open(&plugin_ref.path)?;
+        let factory = library.factory()?;
+        let create = factory.create_plugin.ok_or(PluginError::NoFactory)?;
+
+        let host = Box::new(clap_host {
+            clap_version: CLAP_VERSION,
+            host_data: std::ptr::null_mut(),
+            name: c"aurio".as_ptr(),
+            vendor: c"aurio".as_ptr(),
+            url: c"".as_ptr(),
+            version: c"0.1.0".as_ptr(),
+            get_extension: Some(host_get_extension),
+            request_restart: Some(host_request),
+            request_process: Some(host_request),
+            request_callback: Some(host_request),
+        });
+        let id_c = CString::new(plugin_ref.id.as_str())
+            .map_err(|_| PluginError::NotFound(plugin_ref.id.clone()))?;
+        // SAFETY: the factory and host outlive the plugin.
+        let plugin = unsafe { create(factory, &*host, id_c.as_ptr()) };
+        if plugin.is_null() {
+            return Err(PluginError::NotFound(plugin_ref.id.clone()));
+        }
+
+        let mut instance = PluginInstance {
+            id: plugin_ref.id.clone(),
+            plugin,
+            _host: host,
+            active: false,
+            processing: false,
+            input_channels: 2,
+            output_channels: 2,
+            max_frames,
+            steady_time: 0,
+            events: Vec::with_capacity(MAX_EVENTS),
+            chunk_events: Vec::with_capacity(MAX_EVENTS),
+            inputs: [vec![0.0; max_frames], vec![0.0; max_frames]],
+            outputs: [vec![0.0; max_frames], vec![0.0; max_frames]],
+            _library: library,
+        };
+
+        // SAFETY: `plugin` is a freshly created instance; these are main-thread
+        // calls made before activation.
+        unsafe {
+            let p = &*plugin;
+            if !p.init.is_some_and(|init| init(plugin)) {
+                return Err(PluginError::CreateFailed(instance.id.clone()));
+            }
+            instance.read_audio_ports();
+            let params = instance.restore_params(&plugin_ref.params);
+
+            let activate = p
+                .activate
+                .ok_or(PluginError::ActivateFailed(instance.id.clone()))?;
+            if !activate(plugin, sample_rate, 1, max_frames as u32) {
+                return Err(PluginError::ActivateFailed(instance.id.clone()));
+            }
+            instance.active = true;
+            Ok((instance, params))
+        }
+    }
+
+    unsafe fn extension<T>(&self, id: &CStr) -> Option<&T> {
+        // SAFETY: the plugin returns a pointer to a `T` for `id`, valid for its
+        // lifetime.
+        unsafe {
+            let get = (*self.plugin).get_extension?;
+            (get(self.plugin, id.as_ptr()) as *const T).as_ref()
+        }
+    }
+
+    /// Takes the channel counts of the main ports, up to stereo.
+    unsafe fn read_audio_ports(&mut self) {
+        // SAFETY: main-thread calls on an initialized plugin.
+        unsafe {
+            let Some(ports) = self.extension::<clap_plugin_audio_ports>(CLAP_EXT_AUDIO_PORTS)
+            else {
+                return;
+            };
+            let (Some(count), Some(get)) = (ports.count, ports.get) else {
+                return;
+            };
+            let channels = |is_input| {
+                let mut info: clap_audio_port_info = std::mem::zeroed();
+                if count(self.plugin, is_input) > 0 && get(self.plugin, 0, is_input, &mut info) {
+                    info.channel_count.min(2)
+                } else {
+                    0
+                }
+            };
+            self.input_channels = channels(true);
+            self.output_channels = channels(false);
+        }
+    }
+
+    /// Lists the plugin's parameters, applying the saved values it still has.
+    unsafe fn restore_params(&mut self, saved: &[PluginParam]) -> Vec<PluginParam> {
+        // SAFETY: main-thread calls on an initialized, inactive plugin.
+        unsafe {
+            let Some(ext) = self.extension::<clap_plugin_params>(CLAP_EXT_PARAMS) else {
+                return Vec::new();
+            };
+            let (Some(count), Some(get_info)) = (ext.count, ext.get_info) else {
+                return Vec::new();
+            };
+
+            let mut params = Vec::new();
+            let mut restore = Vec::new();
+            for index in 0..count(self.plugin) {
+                let mut info: clap_param_info = std::mem::zeroed();
+                if !get_info(self.plugin, index, &mut info) {
+                    continue;
+                }
+                let mut value = info.default_value;
+                if let Some(get_value) = ext.get_value {
+                    get_value(self.plugin, info.id, &mut value);
+                }
+                if let Some(saved) = saved.iter().find(|p| p.id == info.id) {
+                    value = saved.value.clamp(info.min_value, info.max_value);
+                    restore.push(RawEvent::new(0, PluginEvent::Param { id: info.id, value }));
+                }
+                params.push(PluginParam {
+                    id: info.id,
+                    name: string(info.name.as_ptr()),
+                    min: info.min_value,
+                    max: info.max_value,
+                    value,
+                });
+            }
+
+            if let Some(flush) = ext.flush
+                && !restore.is_empty()
+            {
+                flush(self.plugin, &input_events(&restore), &output_events());
+            }
+            params
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Queues `event` at `frame` of the next block. Events must be queued in
+    /// time order.
+    pub fn queue(&mut self, frame: u32, event: PluginEvent) {
+        if self.events.len() < MAX_EVENTS {
+            self.events.push(RawEvent::new(frame, event));
+        }
+    }
+
+    /// Runs one block through the plugin in place: `left`/`right` are its input
+    /// and receive its output. Instruments get silence in.
+    pub fn process(&mut self, left: &mut [f32], right: &mut [f32]) {
+        // SAFETY: audio-thread call on an active plugin.
+        if !self.processing && self.active {
+            self.processing = unsafe {
+                (*self.plugin)
+                    .start_processing
+                    .is_some_and(|start| start(self.plugin))
+            };
+        }
+        let Some(process) = (unsafe { (*self.plugin).process }).filter(|_| self.processing) else {
+            self.events.clear();
+            return;
+        };
+
+        let frames = left.len().min(right.len());
+        let mut start = 0;
+        while start < frames {
+            let len = (frames - start).min(self.max_frames);
+            let last = start + len >= frames;
+            self.chunk_events.clear();
+            for event in &self.events {
+                let time = event.time() as usize;
+                if time >= start && (time < start + len || last) {
+                    let mut event = *event;
+                    event.set_time((time - start).min(len - 1) as u32);
+                    self.chunk_events.push(event);
+                }
+            }
+
+            let (inputs, outputs) = (&mut self.inputs, &mut self.outputs);
+            if self.input_channels == 1 {
+                for (i, sample) in inputs[0][..len].iter_mut().enumerate() {
+                    *sample = 0.5 * (left[start + i] + right[start + i]);
+                }
+            } else {
+                inputs[0][..len].copy_from_slice(&left[start..start + len]);
+                inputs[1][..len].copy_from_slice(&right[start..start + len]);
+            }
+            outputs[0][..len].fill(0.0);
+            outputs[1][..len].fill(0.0);
+
+            let mut input_ptrs = [inputs[0].as_mut_ptr(), inputs[1].as_mut_ptr()];
+            let mut output_ptrs = [outputs[0].as_mut_ptr(), outputs[1].as_mut_ptr()];
+            let input = clap_audio_buffer {
+                data32: input_ptrs.as_mut_ptr(),
+                data64: std::ptr::null_mut(),
+                channel_count: self.input_channels,
+                latency: 0,
+                constant_mask: 0,
+            };
+            let mut output = clap_audio_buffer {
+                data32: output_ptrs.as_mut_ptr(),
+                data64: std::ptr::null_mut(),
+                channel_count: self.output_channels,
+                latency: 0,
+                constant_mask: 0,
+            };
+            let in_events = input_events(&self.chunk_events);
+            let out_events = output_events();
+            let block = clap_process {
+                steady_time: self.steady_time,
+                frames_count: len as u32,
+                transport: std::ptr::null(),
+                audio_inputs: &input,
+                audio_outputs: &mut output,
+                audio_inputs_count: (self.input_channels > 0) as u32,
+                audio_outputs_count: (self.output_channels > 0) as u32,
+                in_events: &in_events,
+                out_events: &out_events,
+            };
+
+            // SAFETY: every buffer holds at least `len` frames and outlives the call.
+            let status = unsafe { process(self.plugin, &block) };
+            if status != CLAP_PROCESS_ERROR {
+                let right_out = if self.output_channels == 1 { 0 } else { 1 };
+                left[start..start + len].copy_from_slice(&outputs[0][..len]);
+                right[start..start + len].copy_from_slice(&outputs[right_out][..len]);
+            }
+
+            self.steady_time += len as i64;
+            start += len;
+        }
+        self.events.clear();
+    }
+}
+
+impl Drop for PluginInstance {
+    fn drop(&mut self) {
+        // SAFETY: the instance is no longer processed; see the `Send` impl.
+        unsafe {
+            let p = &*self.plugin;
+            if self.processing
+                && let Some(stop) = p.stop_processing
+            {
+                stop(self.plugin);
+            }
+            if self.active
+                && let Some(deactivate) = p.deactivate
+            {
+                deactivate(self.plugin);
+            }
+            if let Some(destroy) = p.destroy {
+                destroy(self.plugin);
+            }
+        }
+    }
+}
+
+/// The plugins loaded for one track.
+#[derive(Default)]
+pub struct TrackPlugins {
+    pub instrument: Option<PluginInstance>,
+    pub effects: Vec<PluginInstance>,
+}
+
+impl TrackPlugins {
+    pub fn get_mut(&mut self, slot: PluginSlot) -> Option<&mut PluginInstance> {
+        match slot {
+            PluginSlot::Instrument => self.instrument.as_mut(),
+            PluginSlot::Effect(index) => self.effects.get_mut(index),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instrument.is_none() && self.effects.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn input_event_list_exposes_queued_events() {
+        let events = vec![
+            RawEvent::new(0, PluginEvent::Param { id: 7, value: 0.25 }),
+            RawEvent::new(
+                12,
+                PluginEvent::NoteOn {
+                    key: 60,
+                    velocity: 127,
+                },
+            ),
+        ];
+        let list = input_events(&events);
+
+        unsafe {
+            assert_eq!(events_size(&list), 2);
+            let param = &*(events_get(&list, 0) as *const clap_event_param_value);
+            assert_eq!(param.header.type_, CLAP_EVENT_PARAM_VALUE);
+            assert_eq!(
+                param.header.size as usize,
+                size_of::<clap_event_param_value>()
+            );
+            assert_eq!((param.param_id, param.value), (7, 0.25));
+
+            let note = &*(events_get(&list, 1) as *const clap_event_note);
+            assert_eq!(note.header.type_, CLAP_EVENT_NOTE_ON);
+            assert_eq!((note.header.time, note.key, note.velocity), (12, 60, 1.0));
+            assert!(events_get(&list, 2).is_null());
+        }
+    }
+}
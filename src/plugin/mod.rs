@@ -0,0 +1,145 @@
+//! CLAP plugin hosting: plugins as track instruments and insert effects.
+//!
+//! Plugins are loaded on the engine thread when playback starts and processed
+//! on the audio thread in blocks. Only the main stereo ports are connected and
+//! plugin GUIs aren't supported; parameters are set from aurio's own UI and
+//! saved in the project.
+
+mod ffi;
+mod host;
+
+pub use host::{NoteExpression, PluginEvent, PluginInstance, TrackPlugins};
+
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// A plugin used by a track, with the parameter values to restore.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PluginRef {
+    /// The `.clap` file (or bundle on macOS).
+    pub path: PathBuf,
+    /// Plugin id inside the file, e.g. `com.u-he.diva`.
+    pub id: String,
+    /// Filled in from the plugin when it's loaded.
+    #[serde(default)]
+    pub params: Vec<PluginParam>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PluginParam {
+    pub id: u32,
+    pub name: String,
+    pub min: f64,
+    pub max: f64,
+    pub value: f64,
+}
+
+/// Where a plugin sits on a track.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PluginSlot {
+    Instrument,
+    Effect(usize),
+}
+
+/// A plugin found by [`scan`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct PluginInfo {
+    pub path: PathBuf,
+    pub id: String,
+    pub name: String,
+    pub vendor: String,
+    /// Declares the `instrument` feature; otherwise it's used as an effect.
+    pub instrument: bool,
+}
+
+#[derive(Debug)]
+pub enum PluginError {
+    Load(String),
+    NoEntry,
+    InitFailed,
+    NoFactory,
+    NotFound(String),
+    CreateFailed(String),
+    ActivateFailed(String),
+}
+
+impl std::fmt::Display for PluginError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PluginError::Load(e) => write!(f, "Failed to load plugin: {}", e),
+            PluginError::NoEntry => write!(f, "Not a CLAP plugin: no clap_entry symbol"),
+            PluginError::InitFailed => write!(f, "Plugin library failed to initialize"),
+            PluginError::NoFactory => write!(f, "Plugin library has no plugin factory"),
+            PluginError::NotFound(id) => write!(f, "Plugin {} not found in library", id),
+            PluginError::CreateFailed(id) => write!(f, "Plugin {} failed to initialize", id),
+            PluginError::ActivateFailed(id) => write!(f, "Plugin {} failed to activate", id),
+        }
+    }
+}
+
+impl std::error::Error for PluginError {}
+
+/// Directories searched for plugins: `CLAP_PATH`, then the platform's standard
+/// CLAP locations.
+pub fn search_paths() -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = std::env::var_os("CLAP_PATH")
+        .map(|value| std::env::split_paths(&value).collect())
+        .unwrap_or_default();
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+
+    if cfg!(target_os = "macos") {
+        if let Some(home) = &home {
+            paths.push(home.join("Library/Audio/Plug-Ins/CLAP"));
+        }
+        paths.push("/Library/Audio/Plug-Ins/CLAP".into());
+    } else if cfg!(windows) {
+        for var in ["LOCALAPPDATA", "COMMONPROGRAMFILES"] {
+            if let Some(dir) = std::env::var_os(var) {
+                let dir = PathBuf::from(dir);
+                paths.push(if var == "LOCALAPPDATA" {
+                    dir.join("Programs/Common/CLAP")
+                } else {
+                    dir.join("CLAP")
+                });
+            }
+        }
+    } else {
+        if let Some(home) = &home {
+            paths.push(home.join(".clap"));
+        }
+        paths.push("/usr/lib/clap".into());
+    }
+    paths
+}
+
+/// Lists the plugins in every `.clap` file under [`search_paths`]. Files that
+/// fail to load are skipped.
+pub fn scan() -> Vec<PluginInfo> {
+    let mut files = Vec::new();
+    for dir in search_paths() {
+        find_clap_files(&dir, &mut files);
+    }
+
+    let mut plugins = Vec::new();
+    for file in files {
+        match host::describe(&file) {
+            Ok(found) => plugins.extend(found),
+            Err(e) => tracing::warn!("Skipping {}: {}", file.display(), e),
+        }
+    }
+    plugins
+}
+
+fn find_clap_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.extension().is_some_and(|ext| ext == "clap") {
+            files.push(path);
+        } else if path.is_dir() {
+            find_clap_files(&path, files);
+        }
+    }
+}
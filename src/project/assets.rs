@@ -0,0 +1,140 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::{Project, SampleRef};
+use crate::AurioError;
+
+impl SampleRef {
+    /// The sample file, resolving a relative path against the project
+    /// directory.
+    pub fn resolve(&self, project_path: &Path) -> PathBuf {
+        project_path.join(&self.path)
+    }
+}
+
+impl Project {
+    /// Rewrites absolute sample and script paths that point inside the project
+    /// directory as relative ones, so the project still opens once moved.
+    pub fn make_paths_relative(&mut self, project_path: &Path) {
+        let Ok(root) = fs::canonicalize(project_path) else {
+            return;
+        };
+        for sample in &mut self.sample_library {
+            if let Some(path) = relative_to(&sample.path, &root) {
+                sample.path = path;
+            }
+        }
+        for pattern in self.generated_patterns_mut() {
+            if let Some(path) = pattern.file.as_deref().and_then(|f| relative_to(f, &root)) {
+                pattern.file = Some(path);
+            }
+        }
+    }
+
+    /// Copies every sample and script file that lives outside the project
+    /// directory into `samples/` or `patterns/` and points the project at the
+    /// copies, so the folder can be zipped and opened on another machine.
+    /// Returns how many files were copied.
+    pub fn collect_assets(&mut self, project_path: &Path) -> Result<usize, AurioError> {
+        let root =
+            fs::canonicalize(project_path).map_err(|e| AurioError::project_io(project_path, e))?;
+        let mut copied = 0;
+        for sample in &mut self.sample_library {
+            if let Some(path) = collect(&sample.resolve(project_path), &root, "samples")? {
+                sample.path = path;
+                copied += 1;
+            }
+        }
+        for pattern in self.generated_patterns_mut() {
+            let Some(file) = &pattern.file else {
+                continue;
+            };
+            if let Some(path) = collect(&project_path.join(file), &root, "patterns")? {
+                pattern.file = Some(path);
+                copied += 1;
+            }
+        }
+        Ok(copied)
+    }
+}
+
+/// `path` relative to `root`, with `/` separators, when it's an absolute path
+/// inside it.
+fn relative_to(path: &str, root: &Path) -> Option<String> {
+    let path = Path::new(path);
+    if !path.is_absolute() {
+        return None;
+    }
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let relative = path.strip_prefix(root).ok()?;
+    let components: Vec<_> = relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect();
+    Some(components.join("/"))
+}
+
+/// Copies `source` into `dir` under the project unless it's already inside
+/// the project, returning the relative path of the copy.
+fn collect(source: &Path, root: &Path, dir: &str) -> Result<Option<String>, AurioError> {
+    let fail = |e| AurioError::project_io(source, e);
+
+    let source = fs::canonicalize(source).map_err(fail)?;
+    if source.starts_with(root) {
+        return Ok(None);
+    }
+    fs::create_dir_all(root.join(dir)).map_err(fail)?;
+
+    // Number the copy if a different file already has its name.
+    let stem = source.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = source
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+    let mut name = format!("{}{}", stem, extension);
+    let mut n = 2;
+    while root.join(dir).join(&name).exists() {
+        name = format!("{}-{}{}", stem, n, extension);
+        n += 1;
+    }
+
+    fs::copy(&source, root.join(dir).join(&name)).map_err(fail)?;
+    Ok(Some(format!("{}/{}", dir, name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::templates;
+    use std::fs;
+
+    #[test]
+    fn collects_outside_files_and_keeps_paths_relative() {
+        let base = std::env::temp_dir().join(format!("aurio-assets-{}", std::process::id()));
+        let dir = base.join("Project.aurio");
+        let outside = base.join("elsewhere");
+        fs::create_dir_all(dir.join("samples")).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        fs::write(outside.join("kick.wav"), "outside").unwrap();
+        fs::write(dir.join("samples/kick.wav"), "inside").unwrap();
+        fs::write(dir.join("samples/snare.wav"), "inside").unwrap();
+
+        let mut project = templates::tutorial("Assets");
+        let mut sample = project.new_sample("kick");
+        sample.path = outside.join("kick.wav").to_string_lossy().into_owned();
+        project.sample_library.push(sample);
+        let mut sample = project.new_sample("snare");
+        sample.path = dir.join("samples/snare.wav").to_string_lossy().into_owned();
+        project.sample_library.push(sample);
+
+        project.make_paths_relative(&dir);
+        let relative = project.sample_library[1].path.clone();
+        let copied = project.collect_assets(&dir).unwrap();
+        let copy = fs::read_to_string(project.sample_library[0].resolve(&dir)).unwrap();
+        fs::remove_dir_all(&base).unwrap();
+
+        assert_eq!(relative, "samples/snare.wav");
+        assert_eq!(copied, 1);
+        assert_eq!(project.sample_library[0].path, "samples/kick-2.wav");
+        assert_eq!(copy, "outside");
+    }
+}
@@ -0,0 +1,124 @@
+use std::path::Path;
+
+use super::{Project, SampleRef};
+use crate::dsp::resample::{Interpolation, resample};
+use crate::{AurioError, audio};
+
+impl Project {
+    /// Decodes `source`, converts it to the project sample rate, writes it to
+    /// the project's `samples` directory as a WAV file and adds it to the
+    /// sample library.
+    pub fn import_sample(
+        &mut self,
+        project_path: &Path,
+        source: &Path,
+    ) -> Result<SampleRef, AurioError> {
+        let decoded = audio::decode_file(source).map_err(AurioError::ProjectFormat)?;
+        let frames = resample(
+            &decoded.frames,
+            decoded.sample_rate,
+            self.sample_rate,
+            Interpolation::Sinc,
+        );
+
+        let stem = source.file_stem().unwrap_or_default().to_string_lossy();
+        let sample = self.new_sample(&stem);
+        let samples_dir = project_path.join("samples");
+        std::fs::create_dir_all(&samples_dir)
+            .map_err(|e| AurioError::project_io(&samples_dir, e))?;
+
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: self.sample_rate,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        let path = sample.resolve(project_path);
+        let write = || {
+            let mut writer = hound::WavWriter::create(&path, spec)?;
+            for [left, right] in frames {
+                writer.write_sample(left)?;
+                writer.write_sample(right)?;
+            }
+            writer.finalize()
+        };
+        write().map_err(|e| match e {
+            hound::Error::IoError(e) => AurioError::project_io(&path, e),
+            e => AurioError::ProjectFormat(e.to_string()),
+        })?;
+
+        self.sample_library.push(sample.clone());
+        Ok(sample)
+    }
+
+    /// A library entry for a new WAV file in the samples directory, named
+    /// after `name` made safe for a path and numbered if the library already
+    /// has it. It isn't added to the library.
+    pub fn new_sample(&self, name: &str) -> SampleRef {
+        let stem: String = name
+            .chars()
+            .map(|c| {
+                if c.is_alphanumeric() || c == '-' || c == '_' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        let stem = if stem.is_empty() {
+            "sample".to_string()
+        } else {
+            stem
+        };
+
+        let taken = |id: &str| self.sample_library.iter().any(|s| s.id == id);
+        let mut id = stem.clone();
+        let mut n = 2;
+        while taken(&id) {
+            id = format!("{}-{}", stem, n);
+            n += 1;
+        }
+        SampleRef {
+            path: format!("samples/{}.wav", id),
+            id,
+            region: audio::SampleRegion::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::templates;
+
+    #[test]
+    fn imports_resampled_copy_into_project() {
+        let dir = std::env::temp_dir().join(format!("aurio-import-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("Kick Drum.wav");
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 22_050,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&source, spec).unwrap();
+        for _ in 0..100 {
+            writer.write_sample(1000i16).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let mut project = templates::tutorial("Import");
+        project.sample_rate = 44_100;
+        let first = project.import_sample(&dir, &source).unwrap();
+        let second = project.import_sample(&dir, &source).unwrap();
+        let reader = hound::WavReader::open(dir.join(&first.path)).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(first.id, "Kick_Drum");
+        assert_eq!(first.path, "samples/Kick_Drum.wav");
+        assert_eq!(second.id, "Kick_Drum-2");
+        assert_eq!(project.sample_library.len(), 2);
+        assert_eq!(reader.spec().sample_rate, 44_100);
+        assert_eq!(reader.duration(), 200);
+    }
+}
@@ -0,0 +1,540 @@
+mod assets;
+mod import;
+mod tracks;
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::{
+    AurioError,
+    audio::{
+        ADSRConfig, ChokeMode, Instrument, InstrumentSnapshot, KeyTracking, SampleRegion, TrackUid,
+        VelocityResponse,
+    },
+    midi::{MidiInputConfig, MidiMapping},
+    plugin::{PluginRef, PluginSlot},
+    scripting::TrackParam,
+    timing::{Arrangement, GeneratedPattern, Node, Sequence, StateGraph},
+};
+
+/// Previous versions of `project.ron` kept next to it, as `project.ron.bak1`
+/// (the newest) to `project.ron.bak3`.
+pub const BACKUP_COUNT: usize = 3;
+
+/// Formats the project file can be written in. RON is the native one; JSON is
+/// for tools and scripts that generate projects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProjectFormat {
+    Ron,
+    Json,
+}
+
+impl ProjectFormat {
+    pub const ALL: [ProjectFormat; 2] = [ProjectFormat::Ron, ProjectFormat::Json];
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ProjectFormat::Ron => "ron",
+            ProjectFormat::Json => "json",
+        }
+    }
+
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|format| extension.eq_ignore_ascii_case(format.extension()))
+    }
+
+    /// `project.ron` or `project.json` in the project directory.
+    pub fn file_path(self, project_path: &Path) -> PathBuf {
+        project_path.join(format!("project.{}", self.extension()))
+    }
+
+    /// The format of the project file in `project_path`; RON wins if both
+    /// files are there.
+    pub fn detect(project_path: &Path) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|format| format.file_path(project_path).is_file())
+    }
+
+    fn serialize(self, project: &Project) -> Result<String, AurioError> {
+        match self {
+            ProjectFormat::Ron => {
+                ron::ser::to_string_pretty(project, ron::ser::PrettyConfig::default())
+                    .map_err(|e| AurioError::ProjectFormat(e.to_string()))
+            }
+            ProjectFormat::Json => serde_json::to_string_pretty(project)
+                .map_err(|e| AurioError::ProjectFormat(e.to_string())),
+        }
+    }
+
+    fn deserialize(self, content: &str) -> Result<Project, AurioError> {
+        match self {
+            ProjectFormat::Ron => {
+                ron::from_str(content).map_err(|e| AurioError::ProjectFormat(e.to_string()))
+            }
+            ProjectFormat::Json => {
+                serde_json::from_str(content).map_err(|e| AurioError::ProjectFormat(e.to_string()))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SampleRef {
+    pub id: String,
+    /// Audio file, relative to the project directory.
+    pub path: String,
+    #[serde(default)]
+    pub region: SampleRegion,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrackData {
+    /// The track's index in the project.
+    pub id: usize,
+    /// Follows the track as it moves, so a reload changing the track list
+    /// keeps what each track is playing. Not saved.
+    #[serde(skip)]
+    pub uid: TrackUid,
+    pub name: String,
+    pub instrument: Instrument,
+    pub adsr: ADSRConfig,
+    pub volume: f32,
+    pub pan: f32,
+    pub initial_node: String,
+    pub graph: StateGraph,
+    /// Second parameter set the track's morph knob blends towards.
+    #[serde(default)]
+    pub morph: Option<InstrumentSnapshot>,
+    /// CLAP insert effects, processed in order after the instrument.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub effects: Vec<PluginRef>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub mute: bool,
+    /// While any track is soloed, only soloed tracks are heard.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub solo: bool,
+    /// Post-fader send level to each bus, by bus index. Missing entries are 0.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sends: Vec<f32>,
+    /// What happens to notes still sounding when a node ends.
+    #[serde(default)]
+    pub choke: ChokeMode,
+    #[serde(default)]
+    pub velocity: VelocityResponse,
+    #[serde(default)]
+    pub key_tracking: KeyTracking,
+    /// Graphs run alongside `graph`, sharing the track's variables.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub layers: Vec<Layer>,
+}
+
+/// A state graph a track runs alongside its own, such as a slow one moving
+/// through sections and setting variables the track's patterns read. Its
+/// nodes can play notes too, but only the track's own graph ends them at
+/// node ends. Script transitions go to whichever graph has the node, so node
+/// ids should be unique across a track's graphs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Layer {
+    pub name: String,
+    pub initial_node: String,
+    pub graph: StateGraph,
+}
+
+impl TrackData {
+    /// The track's own graph, then its layers'.
+    pub fn graphs(&self) -> impl Iterator<Item = &StateGraph> {
+        std::iter::once(&self.graph).chain(self.layers.iter().map(|layer| &layer.graph))
+    }
+
+    pub fn graphs_mut(&mut self) -> impl Iterator<Item = &mut StateGraph> {
+        std::iter::once(&mut self.graph).chain(self.layers.iter_mut().map(|layer| &mut layer.graph))
+    }
+
+    /// The node with `id` in any of the track's graphs.
+    pub fn node(&self, id: &str) -> Option<&Node> {
+        self.graphs().find_map(|graph| graph.get_node(id))
+    }
+
+    pub fn node_mut(&mut self, id: &str) -> Option<&mut Node> {
+        self.graphs_mut()
+            .find_map(|graph| graph.nodes.iter_mut().find(|n| n.id == id))
+    }
+
+    /// The track's own graph with its initial node, or those of `layer`.
+    pub fn layer_graph(&self, layer: Option<usize>) -> (&StateGraph, &str) {
+        match layer.and_then(|l| self.layers.get(l)) {
+            Some(layer) => (&layer.graph, &layer.initial_node),
+            None => (&self.graph, &self.initial_node),
+        }
+    }
+
+    pub fn layer_graph_mut(&mut self, layer: Option<usize>) -> (&mut StateGraph, &mut String) {
+        match layer.and_then(|l| self.layers.get_mut(l)) {
+            Some(layer) => (&mut layer.graph, &mut layer.initial_node),
+            None => (&mut self.graph, &mut self.initial_node),
+        }
+    }
+
+    /// The first `node N` id none of the track's graphs has, so that nodes
+    /// can be told apart across layers.
+    pub fn free_node_id(&self) -> String {
+        (1..)
+            .map(|n| format!("node {}", n))
+            .find(|id| self.node(id).is_none())
+            .unwrap_or_default()
+    }
+
+    /// Adds a layer with a single empty node and returns its index.
+    pub fn add_layer(&mut self) -> usize {
+        let name = (1..)
+            .map(|n| format!("layer {}", n))
+            .find(|name| self.layers.iter().all(|l| l.name != *name))
+            .unwrap_or_default();
+        let initial_node = self.free_node_id();
+        let mut graph = StateGraph::new();
+        graph.insert_node(initial_node.clone(), (400.0, 300.0));
+        self.layers.push(Layer {
+            name,
+            initial_node,
+            graph,
+        });
+        self.layers.len() - 1
+    }
+
+    pub fn send(&self, bus: usize) -> f32 {
+        self.sends.get(bus).copied().unwrap_or(0.0)
+    }
+
+    /// Stores a parameter changed while playing. The morph position isn't
+    /// part of the project.
+    pub fn set_param(&mut self, param: TrackParam, value: f32) {
+        match param {
+            TrackParam::Volume => self.volume = value,
+            TrackParam::Pan => self.pan = value,
+            TrackParam::Morph => {}
+            TrackParam::Mute => self.mute = value != 0.0,
+            TrackParam::Solo => self.solo = value != 0.0,
+            TrackParam::Send(bus) => {
+                if self.sends.len() <= bus {
+                    self.sends.resize(bus + 1, 0.0);
+                }
+                self.sends[bus] = value;
+            }
+        }
+    }
+
+    pub fn plugin_mut(&mut self, slot: PluginSlot) -> Option<&mut PluginRef> {
+        match (slot, &mut self.instrument) {
+            (PluginSlot::Instrument, Instrument::Plugin(plugin)) => Some(plugin),
+            (PluginSlot::Instrument, _) => None,
+            (PluginSlot::Effect(index), _) => self.effects.get_mut(index),
+        }
+    }
+}
+
+/// A return channel tracks send to, for effects shared between tracks.
+/// Buses are mixed into the master after the tracks.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BusData {
+    pub name: String,
+    pub volume: f32,
+    /// CLAP effects the bus runs, in order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub effects: Vec<PluginRef>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Project {
+    pub name: String,
+    pub version: String,
+    pub bpm: f32,
+    /// Rate offline renders and imported samples are at. Playback runs at
+    /// the output device's rate instead.
+    pub sample_rate: u32,
+    /// Seeds the RNG exposed to generated patterns, so renders are reproducible.
+    #[serde(default)]
+    pub seed: u64,
+    pub sample_library: Vec<SampleRef>,
+    pub tracks: Vec<TrackData>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub buses: Vec<BusData>,
+    /// Controller CCs bound to engine parameters with MIDI learn.
+    #[serde(default)]
+    pub midi_mappings: Vec<MidiMapping>,
+    #[serde(default)]
+    pub midi_input: MidiInputConfig,
+    /// UDP port to accept OSC remote control on while the project is open.
+    #[serde(default)]
+    pub osc_port: Option<u16>,
+    /// Start playback on the next bar of the tempo session: the Ableton Link
+    /// session on the network with the `link` feature, which the project's
+    /// tempo then follows, or otherwise the engine's own beat grid.
+    #[serde(default)]
+    pub tempo_sync: bool,
+    /// Bars of metronome clicked before playback starts, up to 2.
+    #[serde(default)]
+    pub count_in_bars: u32,
+    /// Song sections moving all the tracks along.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arrangement: Option<Arrangement>,
+}
+
+impl Project {
+    /// Longest count-in Play offers.
+    pub const MAX_COUNT_IN_BARS: u32 = 2;
+
+    /// A new project named `name`, ready to save, from one of the
+    /// [`Template`](crate::templates::Template)s.
+    pub fn from_template(template: crate::templates::Template, name: &str) -> Self {
+        template.build(name)
+    }
+
+    /// Saves in the format the project file already has, RON for a new one.
+    pub fn save(&self, project_path: &Path) -> Result<(), AurioError> {
+        let format = ProjectFormat::detect(project_path).unwrap_or(ProjectFormat::Ron);
+        self.save_as(project_path, format)
+    }
+
+    pub fn save_as(&self, project_path: &Path, format: ProjectFormat) -> Result<(), AurioError> {
+        fs::create_dir_all(project_path).map_err(|e| AurioError::project_io(project_path, e))?;
+
+        let samples_dir = project_path.join("samples");
+        fs::create_dir_all(&samples_dir).map_err(|e| AurioError::project_io(&samples_dir, e))?;
+
+        // Code of file-backed patterns lives in its own file, which stays the
+        // source of truth.
+        let mut project = self.clone();
+        project.make_paths_relative(project_path);
+        for pattern in project.generated_patterns_mut() {
+            if pattern.file.is_some() {
+                pattern.function.clear();
+            }
+        }
+
+        let file_path = format.file_path(project_path);
+        let content = format.serialize(&project)?;
+
+        // Write everything to the side first so a crash mid-save leaves the
+        // previous version intact, then swap it in with a rename.
+        let mut temp_path = file_path.clone().into_os_string();
+        temp_path.push(".tmp");
+        let write = || {
+            let mut file = fs::File::create(&temp_path)?;
+            file.write_all(content.as_bytes())?;
+            file.sync_all()?;
+            drop(file);
+
+            if file_path.exists() {
+                rotate_backups(&file_path)?;
+            }
+            fs::rename(&temp_path, &file_path)
+        };
+        write().map_err(|e| AurioError::project_io(&file_path, e))
+    }
+
+    /// Reads a project from the content of a project file, without touching
+    /// the disk: file-backed patterns keep the code saved with them and no
+    /// sample is read.
+    pub fn parse(content: &str, format: ProjectFormat) -> Result<Self, AurioError> {
+        format.deserialize(content)
+    }
+
+    pub fn load(project_path: &Path) -> Result<Self, AurioError> {
+        let format = ProjectFormat::detect(project_path).ok_or_else(|| {
+            AurioError::ProjectFormat(format!(
+                "{} has no project.ron or project.json",
+                project_path.display()
+            ))
+        })?;
+        let file_path = format.file_path(project_path);
+        let content =
+            fs::read_to_string(&file_path).map_err(|e| AurioError::project_io(&file_path, e))?;
+        let mut project = format.deserialize(&content)?;
+        project.make_paths_relative(project_path);
+        project.load_scripts(project_path)?;
+
+        Ok(project)
+    }
+
+    /// Reads the code of every generated pattern that references a `.lua` file.
+    pub fn load_scripts(&mut self, project_path: &Path) -> Result<(), AurioError> {
+        for pattern in self.generated_patterns_mut() {
+            if let Some(file) = &pattern.file {
+                let path = project_path.join(file);
+                pattern.function =
+                    fs::read_to_string(&path).map_err(|e| AurioError::project_io(&path, e))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Paths of the script files referenced by generated patterns.
+    pub fn script_files(&self, project_path: &Path) -> Vec<PathBuf> {
+        self.tracks
+            .iter()
+            .flat_map(|track| track.graphs())
+            .flat_map(|graph| graph.nodes.iter())
+            .flat_map(|node| node.sequence.parts())
+            .filter_map(|(_, part)| match part {
+                Sequence::Generated(GeneratedPattern {
+                    file: Some(file), ..
+                }) => Some(project_path.join(file)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn generated_patterns_mut(&mut self) -> impl Iterator<Item = &mut GeneratedPattern> {
+        self.tracks
+            .iter_mut()
+            .flat_map(|track| track.graphs_mut())
+            .flat_map(|graph| graph.nodes.iter_mut())
+            .flat_map(|node| node.sequence.parts_mut())
+            .filter_map(|part| match part {
+                Sequence::Generated(pattern) => Some(pattern),
+                _ => None,
+            })
+    }
+}
+
+fn backup_path(file_path: &Path, index: usize) -> PathBuf {
+    let mut path = file_path.as_os_str().to_owned();
+    path.push(format!(".bak{}", index));
+    PathBuf::from(path)
+}
+
+/// Shifts each backup one slot older, dropping the oldest, and copies the
+/// current file into the first slot.
+fn rotate_backups(file_path: &Path) -> std::io::Result<()> {
+    for index in (1..BACKUP_COUNT).rev() {
+        let backup = backup_path(file_path, index);
+        if backup.exists() {
+            fs::rename(&backup, backup_path(file_path, index + 1))?;
+        }
+    }
+    fs::copy(file_path, backup_path(file_path, 1))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::templates;
+
+    #[test]
+    fn file_backed_patterns_load_from_disk() {
+        let dir = std::env::temp_dir().join(format!("aurio-scripts-{}", std::process::id()));
+        let mut project = templates::tutorial("Scripts");
+        for pattern in project.generated_patterns_mut() {
+            pattern.file = Some("patterns/verse.lua".to_string());
+        }
+        project.save(&dir).unwrap();
+
+        let ron_string = fs::read_to_string(dir.join("project.ron")).unwrap();
+        assert!(!ron_string.contains("euclid"));
+
+        fs::create_dir_all(dir.join("patterns")).unwrap();
+        fs::write(dir.join("patterns/verse.lua"), "return {}").unwrap();
+        let loaded = Project::load(&dir).unwrap();
+        let files = loaded.script_files(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(files, vec![dir.join("patterns/verse.lua")]);
+        let code: Vec<_> = loaded
+            .tracks
+            .iter()
+            .flat_map(|t| &t.graph.nodes)
+            .filter_map(|n| match &n.sequence {
+                Sequence::Generated(p) => Some(p.function.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(code, vec!["return {}"]);
+    }
+
+    #[test]
+    fn json_projects_load_and_keep_their_format() {
+        let dir = std::env::temp_dir().join(format!("aurio-json-{}", std::process::id()));
+        let mut project = templates::tutorial("Json");
+        project.save_as(&dir, ProjectFormat::Json).unwrap();
+        project.bpm = 90.0;
+        project.save(&dir).unwrap();
+
+        let format = ProjectFormat::detect(&dir);
+        let loaded = Project::load(&dir).unwrap();
+        let has_ron = dir.join("project.ron").exists();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(format, Some(ProjectFormat::Json));
+        assert!(!has_ron);
+        assert_eq!(loaded.bpm, 90.0);
+        assert_eq!(loaded.tracks.len(), project.tracks.len());
+    }
+
+    #[test]
+    fn saves_keep_rotating_backups() {
+        let dir = std::env::temp_dir().join(format!("aurio-backups-{}", std::process::id()));
+        let mut project = templates::tutorial("Backups");
+        for bpm in 0..=BACKUP_COUNT + 1 {
+            project.bpm = 100.0 + bpm as f32;
+            project.save(&dir).unwrap();
+        }
+
+        let ron_path = dir.join("project.ron");
+        let saved_bpm = |path: &Path| -> f32 {
+            ron::from_str::<Project>(&fs::read_to_string(path).unwrap())
+                .unwrap()
+                .bpm
+        };
+        let bpms: Vec<_> = (1..=BACKUP_COUNT)
+            .map(|index| saved_bpm(&backup_path(&ron_path, index)))
+            .collect();
+        let latest = saved_bpm(&ron_path);
+        let leftovers = [
+            backup_path(&ron_path, BACKUP_COUNT + 1).exists(),
+            dir.join("project.ron.tmp").exists(),
+        ];
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(latest, 104.0);
+        assert_eq!(bpms, vec![103.0, 102.0, 101.0]);
+        assert_eq!(leftovers, [false, false]);
+    }
+
+    #[test]
+    fn layers_get_node_ids_of_their_own_and_round_trip() {
+        let mut project = templates::tutorial("Layers");
+        let track = &mut project.tracks[0];
+        let first = track.add_layer();
+        let second = track.add_layer();
+        let added = track.free_node_id();
+        track.layers[second]
+            .graph
+            .insert_node(added.clone(), (0.0, 0.0));
+
+        let ids: Vec<&str> = track
+            .graphs()
+            .flat_map(|graph| &graph.nodes)
+            .map(|node| node.id.as_str())
+            .collect();
+        assert_eq!(
+            ids.len(),
+            ids.iter().collect::<std::collections::HashSet<_>>().len()
+        );
+        assert_eq!(track.layers[first].name, "layer 1");
+        assert_eq!(track.layers[second].name, "layer 2");
+        assert!(track.node(&added).is_some());
+
+        let loaded: Project =
+            ron::from_str(&ProjectFormat::Ron.serialize(&project).unwrap()).unwrap();
+        let layers = &loaded.tracks[0].layers;
+        assert_eq!(layers.len(), 2);
+        assert_eq!(layers[1].graph.nodes.len(), 2);
+        assert!(loaded.tracks[1].layers.is_empty());
+    }
+}
@@ -0,0 +1,217 @@
+use super::{BusData, Project, TrackData};
+use crate::audio::{
+    ADSRConfig, ChokeMode, Instrument, KeyTracking, OscConfig, TrackUid, VelocityResponse, Wave,
+};
+use crate::midi::MidiTarget;
+use crate::timing::{Cue, Edge, Node, Sequence, StateGraph, StaticPattern, TransitionTiming};
+
+impl TrackData {
+    /// A sine track looping one empty bar, ready to be written into.
+    pub fn new(id: usize, name: impl Into<String>) -> Self {
+        Self {
+            id,
+            uid: TrackUid::new(),
+            name: name.into(),
+            instrument: Instrument::MultiOsc {
+                oscillators: vec![OscConfig {
+                    wave: Wave::Sine,
+                    gain: 0.3,
+                    semitone: 0,
+                    pan: 0.0,
+                }],
+                spread: 0.0,
+            },
+            adsr: ADSRConfig::new(0.01, 0.1, 0.7, 0.2),
+            volume: 0.8,
+            pan: 0.0,
+            initial_node: "main".to_string(),
+            graph: StateGraph {
+                nodes: vec![Node {
+                    id: "main".to_string(),
+                    sequence: Sequence::Static(StaticPattern {
+                        duration_bars: 1,
+                        time_signature: (4, 4),
+                        notes: Vec::new(),
+                        automation: Vec::new(),
+                    }),
+                    hooks: Vec::new(),
+                    repeat: None,
+                    position: None,
+                }],
+                edges: vec![Edge {
+                    from: "main".to_string(),
+                    to: "main".to_string(),
+                    condition: "true".to_string(),
+                    timing: TransitionTiming::FinishSequence,
+                    inlet_hook: None,
+                    weight: None,
+                }],
+            },
+            morph: None,
+            effects: Vec::new(),
+            mute: false,
+            solo: false,
+            sends: Vec::new(),
+            choke: ChokeMode::default(),
+            velocity: VelocityResponse::default(),
+            key_tracking: KeyTracking::default(),
+            layers: Vec::new(),
+        }
+    }
+}
+
+impl Project {
+    /// Appends a new track and returns its index.
+    pub fn add_track(&mut self) -> usize {
+        let index = self.tracks.len();
+        self.tracks
+            .push(TrackData::new(index, format!("Track {}", index + 1)));
+        index
+    }
+
+    /// Inserts a copy of a track right after it and returns the copy's index.
+    pub fn duplicate_track(&mut self, index: usize) -> usize {
+        let mut copy = self.tracks[index].clone();
+        copy.name = format!("{} copy", copy.name);
+        copy.uid = TrackUid::new();
+        self.tracks.insert(index + 1, copy);
+        self.reindex_tracks(|i| Some(if i > index { i + 1 } else { i }));
+        index + 1
+    }
+
+    pub fn remove_track(&mut self, index: usize) {
+        self.tracks.remove(index);
+        self.reindex_tracks(|i| match i.cmp(&index) {
+            std::cmp::Ordering::Less => Some(i),
+            std::cmp::Ordering::Equal => None,
+            std::cmp::Ordering::Greater => Some(i - 1),
+        });
+    }
+
+    /// Moves a track to another position, shifting the ones in between.
+    pub fn move_track(&mut self, from: usize, to: usize) {
+        let track = self.tracks.remove(from);
+        self.tracks.insert(to, track);
+        self.reindex_tracks(|i| {
+            Some(if i == from {
+                to
+            } else if from < to && (from..=to).contains(&i) {
+                i - 1
+            } else if to < from && (to..from).contains(&i) {
+                i + 1
+            } else {
+                i
+            })
+        });
+    }
+
+    /// Appends a bus and returns its index.
+    pub fn add_bus(&mut self) -> usize {
+        let index = self.buses.len();
+        self.buses.push(BusData {
+            name: format!("Bus {}", index + 1),
+            volume: 1.0,
+            effects: Vec::new(),
+        });
+        index
+    }
+
+    /// Removes a bus along with every track's send to it.
+    pub fn remove_bus(&mut self, index: usize) {
+        self.buses.remove(index);
+        for track in &mut self.tracks {
+            if index < track.sends.len() {
+                track.sends.remove(index);
+            }
+        }
+    }
+
+    /// Track ids are indices: after the list changed, renumbers the tracks and
+    /// points MIDI mappings at their track's new index (`None` for a removed
+    /// one) given the old index.
+    fn reindex_tracks(&mut self, new_index: impl Fn(usize) -> Option<usize>) {
+        for (i, track) in self.tracks.iter_mut().enumerate() {
+            track.id = i;
+        }
+        self.midi_input.track = self.midi_input.track.and_then(&new_index);
+        self.midi_mappings
+            .retain_mut(|mapping| match &mut mapping.target {
+                MidiTarget::TrackVolume(track)
+                | MidiTarget::TrackPan(track)
+                | MidiTarget::TrackMorph(track)
+                | MidiTarget::Transition {
+                    track_id: track, ..
+                } => match new_index(*track) {
+                    Some(index) => {
+                        *track = index;
+                        true
+                    }
+                    None => false,
+                },
+                _ => true,
+            });
+        if let Some(arrangement) = &mut self.arrangement {
+            arrangement.cues.retain_mut(|cue| match cue {
+                Cue::Transition { track_id, .. } => match new_index(*track_id) {
+                    Some(index) => {
+                        *track_id = index;
+                        true
+                    }
+                    None => false,
+                },
+                Cue::SetVariable { .. } => true,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::midi::{MidiMapping, MidiSource, MidiTarget};
+    use crate::scripting::TrackParam;
+    use crate::templates;
+
+    #[test]
+    fn track_edits_keep_ids_and_mappings_in_step() {
+        let mut project = templates::tutorial("Tracks");
+        let mapping = |target| MidiMapping {
+            source: MidiSource::Cc,
+            channel: 0,
+            controller: 1,
+            target,
+        };
+        project.midi_mappings = vec![
+            mapping(MidiTarget::TrackVolume(0)),
+            mapping(MidiTarget::TrackPan(2)),
+            mapping(MidiTarget::Metronome),
+        ];
+
+        assert_eq!(project.add_track(), 3);
+        assert_eq!(project.duplicate_track(0), 1);
+        project.remove_track(3);
+        project.move_track(3, 0);
+
+        let names: Vec<_> = project.tracks.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, vec!["Track 4", "Lead", "Lead copy", "Bass"]);
+        assert!(project.tracks.iter().enumerate().all(|(i, t)| t.id == i));
+        let targets: Vec<_> = project.midi_mappings.iter().map(|m| &m.target).collect();
+        assert_eq!(
+            targets,
+            vec![&MidiTarget::TrackVolume(1), &MidiTarget::Metronome]
+        );
+    }
+
+    #[test]
+    fn removing_a_bus_drops_the_sends_to_it() {
+        let mut project = templates::tutorial("Buses");
+        assert_eq!(project.add_bus(), 0);
+        assert_eq!(project.add_bus(), 1);
+        project.tracks[0].set_param(TrackParam::Send(1), 0.5);
+        assert_eq!(project.tracks[0].sends, vec![0.0, 0.5]);
+
+        project.remove_bus(0);
+        assert_eq!(project.buses[0].name, "Bus 2");
+        assert_eq!(project.tracks[0].send(0), 0.5);
+        assert_eq!(project.tracks[1].send(0), 0.0);
+    }
+}
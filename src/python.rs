@@ -0,0 +1,341 @@
+//! Python bindings for writing projects and bouncing them, for composing
+//! algorithmically without the editor. Built as the `aurio` Python module with
+//! `maturin build --features python`.
+//!
+//! ```python
+//! import aurio
+//!
+//! project = aurio.Project("Etude")
+//! lead = project.add_track("Lead")
+//! pattern = aurio.StaticPattern(bars=1)
+//! for beat, pitch in enumerate([57, 60, 64, 67]):
+//!     pattern.add_note(pitch, 100, beat, 1.0)
+//! project.set_pattern(lead, "main", pattern)
+//! project.save("Etude.aurio")
+//! project.render_wav("Etude.wav", bars=8)
+//! ```
+
+use crate::templates::Template;
+use crate::timing::{Note, StaticPattern};
+use crate::{AurioError, Edge, Node, Project, Sequence, TransitionTiming, render_offline};
+use pyo3::exceptions::{PyIOError, PyIndexError, PyValueError};
+use pyo3::prelude::*;
+use std::path::{Path, PathBuf};
+
+impl From<AurioError> for PyErr {
+    fn from(error: AurioError) -> Self {
+        match &error {
+            AurioError::ProjectIo { .. } => PyIOError::new_err(error.to_string()),
+            _ => PyValueError::new_err(error.to_string()),
+        }
+    }
+}
+
+/// A pattern of notes, for a node of a track's graph.
+#[pyclass(name = "StaticPattern")]
+#[derive(Clone)]
+pub struct PyStaticPattern {
+    pattern: StaticPattern,
+}
+
+#[pymethods]
+impl PyStaticPattern {
+    #[new]
+    #[pyo3(signature = (bars = 1, time_signature = (4, 4)))]
+    fn new(bars: u32, time_signature: (u32, u32)) -> PyResult<Self> {
+        if bars == 0 || time_signature.0 == 0 || time_signature.1 == 0 {
+            return Err(PyValueError::new_err(
+                "A pattern needs at least one bar of at least one beat",
+            ));
+        }
+        Ok(Self {
+            pattern: StaticPattern {
+                duration_bars: bars,
+                time_signature,
+                notes: Vec::new(),
+                automation: Vec::new(),
+            },
+        })
+    }
+
+    /// Adds a note, with its start and length in beats from the pattern's
+    /// start.
+    fn add_note(
+        &mut self,
+        pitch: u8,
+        velocity: u8,
+        start_beat: f32,
+        duration_beats: f32,
+    ) -> PyResult<()> {
+        if pitch > 127 || velocity > 127 {
+            return Err(PyValueError::new_err("Pitch and velocity go from 0 to 127"));
+        }
+        if start_beat < 0.0 || duration_beats <= 0.0 {
+            return Err(PyValueError::new_err(
+                "Notes start at or after beat 0 and last more than 0 beats",
+            ));
+        }
+        self.pattern
+            .notes
+            .push(Note::new(pitch, velocity, start_beat, duration_beats));
+        Ok(())
+    }
+
+    #[getter]
+    fn bars(&self) -> u32 {
+        self.pattern.duration_bars
+    }
+
+    #[getter]
+    fn time_signature(&self) -> (u32, u32) {
+        self.pattern.time_signature
+    }
+
+    /// The notes as `(pitch, velocity, start_beat, duration_beats)`.
+    #[getter]
+    fn notes(&self) -> Vec<(u8, u8, f32, f32)> {
+        self.pattern
+            .notes
+            .iter()
+            .map(|n| (n.pitch, n.velocity, n.start_beat, n.duration_beats))
+            .collect()
+    }
+}
+
+/// An aurio project. Remembers where it was loaded from or saved to, so
+/// samples and scripts next to it are found when rendering.
+#[pyclass(name = "Project")]
+pub struct PyProject {
+    project: Project,
+    path: Option<PathBuf>,
+}
+
+#[pymethods]
+impl PyProject {
+    /// A new project from one of the editor's templates.
+    #[new]
+    #[pyo3(signature = (name, template = "empty"))]
+    fn new(name: &str, template: &str) -> PyResult<Self> {
+        let template: Template = template.parse().map_err(PyValueError::new_err)?;
+        Ok(Self {
+            project: Project::from_template(template, name),
+            path: None,
+        })
+    }
+
+    #[staticmethod]
+    fn load(path: PathBuf) -> PyResult<Self> {
+        Ok(Self {
+            project: Project::load(&path)?,
+            path: Some(path),
+        })
+    }
+
+    fn save(&mut self, path: PathBuf) -> PyResult<()> {
+        self.project.save(&path)?;
+        self.path = Some(path);
+        Ok(())
+    }
+
+    #[getter]
+    fn name(&self) -> &str {
+        &self.project.name
+    }
+
+    #[setter]
+    fn set_name(&mut self, name: String) {
+        self.project.name = name;
+    }
+
+    #[getter]
+    fn bpm(&self) -> f32 {
+        self.project.bpm
+    }
+
+    #[setter]
+    fn set_bpm(&mut self, bpm: f32) -> PyResult<()> {
+        if bpm.is_nan() || bpm <= 0.0 {
+            return Err(PyValueError::new_err("The tempo must be above 0 BPM"));
+        }
+        self.project.bpm = bpm;
+        Ok(())
+    }
+
+    #[getter]
+    fn sample_rate(&self) -> u32 {
+        self.project.sample_rate
+    }
+
+    #[getter]
+    fn seed(&self) -> u64 {
+        self.project.seed
+    }
+
+    #[setter]
+    fn set_seed(&mut self, seed: u64) {
+        self.project.seed = seed;
+    }
+
+    /// Track names, in order; tracks are addressed by index.
+    #[getter]
+    fn tracks(&self) -> Vec<String> {
+        self.project.tracks.iter().map(|t| t.name.clone()).collect()
+    }
+
+    /// Adds a sine track looping an empty bar in its `main` node and returns
+    /// its index.
+    #[pyo3(signature = (name = None))]
+    fn add_track(&mut self, name: Option<String>) -> usize {
+        let index = self.project.add_track();
+        if let Some(name) = name {
+            self.project.tracks[index].name = name;
+        }
+        index
+    }
+
+    /// Makes `pattern` what node `node` of track `track` plays, adding the
+    /// node if the track doesn't have one by that name.
+    fn set_pattern(&mut self, track: usize, node: &str, pattern: &PyStaticPattern) -> PyResult<()> {
+        let graph = &mut self.track_mut(track)?.graph;
+        let sequence = Sequence::Static(pattern.pattern.clone());
+        match graph.nodes.iter_mut().find(|n| n.id == node) {
+            Some(existing) => existing.sequence = sequence,
+            None => graph.nodes.push(Node {
+                id: node.to_string(),
+                sequence,
+                hooks: Vec::new(),
+                repeat: None,
+                position: None,
+            }),
+        }
+        Ok(())
+    }
+
+    /// Adds an edge between two nodes of a track, taken when the Lua
+    /// `condition` holds. `timing` is one of `immediate`, `beat`, `bar` or
+    /// `finish`.
+    #[pyo3(signature = (track, from_node, to_node, condition = "true", timing = "finish", weight = None))]
+    fn add_edge(
+        &mut self,
+        track: usize,
+        from_node: &str,
+        to_node: &str,
+        condition: &str,
+        timing: &str,
+        weight: Option<f32>,
+    ) -> PyResult<()> {
+        let timing = match timing {
+            "immediate" => TransitionTiming::Immediate,
+            "beat" => TransitionTiming::NextBeat,
+            "bar" => TransitionTiming::NextBar,
+            "finish" => TransitionTiming::FinishSequence,
+            other => {
+                return Err(PyValueError::new_err(format!(
+                    "Unknown timing '{}' (expected immediate, beat, bar, finish)",
+                    other
+                )));
+            }
+        };
+        let graph = &mut self.track_mut(track)?.graph;
+        for id in [from_node, to_node] {
+            if graph.get_node(id).is_none() {
+                return Err(PyValueError::new_err(format!("No node '{}'", id)));
+            }
+        }
+        graph.edges.push(Edge {
+            from: from_node.to_string(),
+            to: to_node.to_string(),
+            condition: condition.to_string(),
+            timing,
+            inlet_hook: None,
+            weight,
+        });
+        Ok(())
+    }
+
+    /// Sets the node a track starts in.
+    fn set_initial_node(&mut self, track: usize, node: &str) -> PyResult<()> {
+        let track = self.track_mut(track)?;
+        if track.graph.get_node(node).is_none() {
+            return Err(PyValueError::new_err(format!("No node '{}'", node)));
+        }
+        track.initial_node = node.to_string();
+        Ok(())
+    }
+
+    /// Renders the project from the start, for `seconds` or `bars` (counted
+    /// in 4/4 at the project tempo). Returns interleaved stereo samples.
+    #[pyo3(signature = (seconds = None, bars = None))]
+    fn render(
+        &self,
+        py: Python<'_>,
+        seconds: Option<f64>,
+        bars: Option<f64>,
+    ) -> PyResult<Vec<f32>> {
+        let num_frames = self.num_frames(seconds, bars)?;
+        let path = self.path.as_deref();
+        Ok(py.allow_threads(|| render_offline(&self.project, path, num_frames))?)
+    }
+
+    /// Renders like [`render`](Self::render) into a 32-bit float stereo WAV.
+    #[pyo3(signature = (path, seconds = None, bars = None))]
+    fn render_wav(
+        &self,
+        py: Python<'_>,
+        path: PathBuf,
+        seconds: Option<f64>,
+        bars: Option<f64>,
+    ) -> PyResult<()> {
+        let samples = self.render(py, seconds, bars)?;
+        write_wav(&path, self.project.sample_rate, &samples)?;
+        Ok(())
+    }
+}
+
+impl PyProject {
+    fn track_mut(&mut self, track: usize) -> PyResult<&mut crate::TrackData> {
+        let count = self.project.tracks.len();
+        self.project.tracks.get_mut(track).ok_or_else(|| {
+            PyIndexError::new_err(format!("No track {} (the project has {})", track, count))
+        })
+    }
+
+    fn num_frames(&self, seconds: Option<f64>, bars: Option<f64>) -> PyResult<usize> {
+        let seconds = match (seconds, bars) {
+            (Some(seconds), None) => seconds,
+            (None, Some(bars)) => bars * 4.0 * 60.0 / self.project.bpm as f64,
+            _ => return Err(PyValueError::new_err("Give either seconds or bars")),
+        };
+        if seconds.is_nan() || seconds < 0.0 {
+            return Err(PyValueError::new_err("Can't render a negative length"));
+        }
+        Ok((seconds * self.project.sample_rate as f64).round() as usize)
+    }
+}
+
+fn write_wav(path: &Path, sample_rate: u32, samples: &[f32]) -> Result<(), AurioError> {
+    let fail = |e: hound::Error| match e {
+        hound::Error::IoError(e) => AurioError::project_io(path, e),
+        e => AurioError::ProjectFormat(e.to_string()),
+    };
+    let spec = hound::WavSpec {
+        channels: 2,
+        sample_rate,
+        bits_per_sample: 32,
+        sample_format: hound::SampleFormat::Float,
+    };
+    let mut writer = hound::WavWriter::create(path, spec).map_err(fail)?;
+    for &sample in samples {
+        writer.write_sample(sample).map_err(fail)?;
+    }
+    writer.finalize().map_err(fail)
+}
+
+#[pymodule]
+#[pyo3(name = "aurio")]
+fn aurio_module(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyProject>()?;
+    m.add_class::<PyStaticPattern>()?;
+    m.add("TEMPLATES", Template::ALL.map(|t| t.name()).to_vec())?;
+    Ok(())
+}
@@ -0,0 +1,84 @@
+use super::LuaValue;
+use crate::timing::TransitionTiming;
+
+/// Which [`VariableStore`](super::VariableStore) namespace a variable lives in.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VariableScope {
+    Node { track_id: usize, node_id: String },
+    Track(usize),
+    Global,
+}
+
+/// A track parameter scripts may change at runtime.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TrackParam {
+    Volume,
+    Pan,
+    Morph,
+    /// On when non-zero.
+    Mute,
+    Solo,
+    /// Level sent to a bus, by bus index. Named `send1`, `send2` and so on.
+    Send(usize),
+}
+
+impl TrackParam {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "volume" => Some(TrackParam::Volume),
+            "pan" => Some(TrackParam::Pan),
+            "morph" => Some(TrackParam::Morph),
+            "mute" => Some(TrackParam::Mute),
+            "solo" => Some(TrackParam::Solo),
+            _ => match name.strip_prefix("send")?.parse::<usize>() {
+                Ok(number) if number > 0 => Some(TrackParam::Send(number - 1)),
+                _ => None,
+            },
+        }
+    }
+
+    /// Limits `value` to the parameter's range.
+    pub fn clamp(self, value: f32) -> f32 {
+        match self {
+            TrackParam::Volume => value.max(0.0),
+            TrackParam::Pan => value.clamp(-1.0, 1.0),
+            TrackParam::Morph | TrackParam::Send(_) => value.clamp(0.0, 1.0),
+            TrackParam::Mute | TrackParam::Solo => {
+                if value >= 0.5 {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+}
+
+/// A change to engine state requested from Lua.
+///
+/// Scripts never touch the engine directly: the functions exposed to hooks and
+/// conditions only queue these, and the timing thread applies them between
+/// sequences, forwarding anything it doesn't own to the engine thread.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScriptAction {
+    SetVariable {
+        scope: VariableScope,
+        name: String,
+        value: LuaValue,
+    },
+    SetBpm(f32),
+    /// Take this node next instead of following the graph's edges, cutting
+    /// the current sequence short unless `timing` is `FinishSequence`.
+    TriggerTransition {
+        track_id: usize,
+        node_id: String,
+        timing: TransitionTiming,
+    },
+    SetParam {
+        track_id: usize,
+        param: TrackParam,
+        value: f32,
+    },
+    /// Move the arrangement to this section once the current one ends.
+    GoToSection(String),
+}
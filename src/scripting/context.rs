@@ -0,0 +1,124 @@
+use super::{LuaValue, VariableStore};
+use mlua::{Lua, Table};
+
+/// Everything a generated pattern gets to see about the moment it's scheduled.
+///
+/// This is exposed to Lua as the `ctx` table: the pattern chunk receives it as
+/// its first vararg (`local ctx = ...`), and a chunk that returns a function has
+/// that function called with it. It's also set as the global `ctx` so prelude
+/// helpers like `every(n)` can read it.
+pub struct PatternContext<'a> {
+    pub track_id: usize,
+    pub node_id: &'a str,
+    pub start_sample: u64,
+    pub bpm: f32,
+    pub sample_rate: f32,
+    pub time_signature: (u32, u32),
+    /// How many times this node has already played back to back (0 on entry).
+    pub loop_count: u64,
+    /// Times the node plays back to back before it moves on, if it repeats.
+    pub repeat: Option<u32>,
+    /// Project-wide seed, mixed with the track, node and loop count before use.
+    pub seed: u64,
+    /// The generated pattern's own seed (0 for hooks and static patterns).
+    pub pattern_seed: u64,
+    pub variables: &'a VariableStore,
+}
+
+impl PatternContext<'_> {
+    pub fn samples_per_beat(&self) -> f64 {
+        (60.0 / self.bpm as f64) * self.sample_rate as f64
+    }
+
+    /// Times a repeating node plays after this one.
+    pub fn remaining(&self) -> Option<u64> {
+        self.repeat
+            .map(|repeat| (repeat as u64).saturating_sub(self.loop_count + 1))
+    }
+
+    /// Absolute position in quarter-note beats since playback started.
+    pub fn beat(&self) -> f64 {
+        self.start_sample as f64 / self.samples_per_beat()
+    }
+
+    /// Absolute bar index since playback started, using this pattern's time signature.
+    pub fn bar(&self) -> u64 {
+        let (beats_per_bar, beat_unit) = self.time_signature;
+        let quarters_per_bar = beats_per_bar as f64 * (4.0 / beat_unit as f64);
+        (self.beat() / quarters_per_bar).floor() as u64
+    }
+
+    /// Seed for this particular invocation, so every loop iteration differs but a
+    /// re-render of the same project produces identical output.
+    pub fn rng_seed(&self) -> u64 {
+        let mut hash = self.seed ^ 0xcbf2_9ce4_8422_2325;
+        for byte in self.node_id.bytes() {
+            hash = (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3);
+        }
+        // A zero pattern seed leaves the result unchanged, so existing projects
+        // keep rendering the same notes.
+        splitmix64(
+            hash ^ splitmix64(self.track_id as u64)
+                ^ splitmix64(!self.loop_count)
+                ^ self.pattern_seed.wrapping_mul(0x9e37_79b9_7f4a_7c15),
+        )
+    }
+
+    /// Random number for a choice made at `start_sample`, like which of
+    /// several weighted edges to take. The same project always rolls the
+    /// same, but every time round a node and every visit to it differs.
+    pub fn roll(&self) -> u64 {
+        splitmix64(self.rng_seed() ^ splitmix64(self.start_sample))
+    }
+
+    pub fn to_lua_table(&self, lua: &Lua) -> Result<Table, mlua::Error> {
+        let ctx = lua.create_table()?;
+        ctx.set("track_id", self.track_id)?;
+        ctx.set("node_id", self.node_id)?;
+        ctx.set("sample", self.start_sample)?;
+        ctx.set("bar", self.bar())?;
+        ctx.set("beat", self.beat())?;
+        ctx.set("bpm", self.bpm)?;
+        ctx.set("sample_rate", self.sample_rate)?;
+        ctx.set("loop", self.loop_count)?;
+        ctx.set("repeats", self.repeat)?;
+        ctx.set("remaining", self.remaining())?;
+        ctx.set("seed", self.rng_seed() as i64)?;
+
+        let vars = self.variables;
+        ctx.set(
+            "vars",
+            variables_table(lua, vars.node_vars(self.track_id, self.node_id))?,
+        )?;
+        ctx.set(
+            "track",
+            variables_table(lua, vars.track_vars(self.track_id))?,
+        )?;
+        ctx.set("global", variables_table(lua, vars.globals())?)?;
+
+        Ok(ctx)
+    }
+}
+
+fn variables_table<'v>(
+    lua: &Lua,
+    vars: impl Iterator<Item = (&'v str, &'v LuaValue)>,
+) -> Result<Table, mlua::Error> {
+    let table = lua.create_table()?;
+    for (name, value) in vars {
+        match value {
+            LuaValue::Number(n) => table.set(name, *n)?,
+            LuaValue::Boolean(b) => table.set(name, *b)?,
+            LuaValue::String(s) => table.set(name, s.as_str())?,
+            LuaValue::Nil => {}
+        }
+    }
+    Ok(table)
+}
+
+fn splitmix64(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
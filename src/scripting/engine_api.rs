@@ -0,0 +1,145 @@
+//! Engine-control functions available to every script.
+//!
+//! Each one validates its arguments and queues a [`ScriptAction`]; nothing here
+//! mutates engine state. Functions that act on a track default to the one the
+//! script is running for (`ctx.track_id`), and take an optional explicit id.
+
+use super::{LuaValue, ScriptAction, TrackParam, VariableScope};
+use crate::timing::TransitionTiming;
+use crossbeam::channel::Sender;
+use mlua::{Lua, Table, Value};
+
+pub(super) fn register(lua: &Lua, actions: Sender<ScriptAction>) -> Result<(), mlua::Error> {
+    let globals = lua.globals();
+
+    let tx = actions.clone();
+    globals.set(
+        "set_var",
+        lua.create_function(move |lua, (name, value): (String, Value)| {
+            let ctx = context(lua)?;
+            let scope = VariableScope::Node {
+                track_id: ctx.get("track_id")?,
+                node_id: ctx.get("node_id")?,
+            };
+            queue(&tx, set_variable(scope, name, value)?)
+        })?,
+    )?;
+
+    let tx = actions.clone();
+    globals.set(
+        "set_track_var",
+        lua.create_function(move |lua, (name, value): (String, Value)| {
+            let scope = VariableScope::Track(context(lua)?.get("track_id")?);
+            queue(&tx, set_variable(scope, name, value)?)
+        })?,
+    )?;
+
+    let tx = actions.clone();
+    globals.set(
+        "set_global",
+        lua.create_function(move |_, (name, value): (String, Value)| {
+            queue(&tx, set_variable(VariableScope::Global, name, value)?)
+        })?,
+    )?;
+
+    let tx = actions.clone();
+    globals.set(
+        "set_bpm",
+        lua.create_function(move |_, bpm: f32| {
+            if !(bpm.is_finite() && bpm > 0.0) {
+                return Err(mlua::Error::runtime(format!("invalid bpm {}", bpm)));
+            }
+            queue(&tx, ScriptAction::SetBpm(bpm))
+        })?,
+    )?;
+
+    let tx = actions.clone();
+    globals.set(
+        "trigger_transition",
+        lua.create_function(move |lua, (node_id, track_id): (String, Option<usize>)| {
+            let track_id = track(lua, track_id)?;
+            queue(
+                &tx,
+                ScriptAction::TriggerTransition {
+                    track_id,
+                    node_id,
+                    timing: TransitionTiming::FinishSequence,
+                },
+            )
+        })?,
+    )?;
+
+    let tx = actions.clone();
+    globals.set(
+        "go_to_section",
+        lua.create_function(move |_, section: String| {
+            queue(&tx, ScriptAction::GoToSection(section))
+        })?,
+    )?;
+
+    let tx = actions;
+    globals.set(
+        "set_param",
+        lua.create_function(
+            move |lua, (name, value, track_id): (String, f32, Option<usize>)| {
+                let param = TrackParam::from_name(&name).ok_or_else(|| {
+                    mlua::Error::runtime(format!(
+                        "unknown parameter '{}' (expected volume, pan, morph, mute, solo or sendN)",
+                        name
+                    ))
+                })?;
+                let track_id = track(lua, track_id)?;
+                queue(
+                    &tx,
+                    ScriptAction::SetParam {
+                        track_id,
+                        param,
+                        value,
+                    },
+                )
+            },
+        )?,
+    )?;
+
+    Ok(())
+}
+
+fn queue(tx: &Sender<ScriptAction>, action: ScriptAction) -> Result<(), mlua::Error> {
+    tx.send(action)
+        .map_err(|_| mlua::Error::runtime("engine is no longer running"))
+}
+
+fn context(lua: &Lua) -> Result<Table, mlua::Error> {
+    lua.globals()
+        .get::<Option<Table>>("ctx")?
+        .ok_or_else(|| mlua::Error::runtime("only available while a track is playing"))
+}
+
+fn track(lua: &Lua, explicit: Option<usize>) -> Result<usize, mlua::Error> {
+    match explicit {
+        Some(track_id) => Ok(track_id),
+        None => context(lua)?.get("track_id"),
+    }
+}
+
+fn set_variable(
+    scope: VariableScope,
+    name: String,
+    value: Value,
+) -> Result<ScriptAction, mlua::Error> {
+    let value = match value {
+        Value::Nil => LuaValue::Nil,
+        Value::Boolean(b) => LuaValue::Boolean(b),
+        Value::Integer(i) => LuaValue::Number(i as f64),
+        Value::Number(n) => LuaValue::Number(n),
+        Value::String(s) => LuaValue::String(s.to_str()?.to_string()),
+        other => {
+            return Err(mlua::Error::runtime(format!(
+                "cannot store a {} in variable '{}'",
+                other.type_name(),
+                name
+            )));
+        }
+    };
+    Ok(ScriptAction::SetVariable { scope, name, value })
+}
@@ -0,0 +1,153 @@
+use super::ScriptTimeout;
+use std::fmt;
+use std::time::Duration;
+
+/// A Lua error broken down for display: where it was raised and how it got there.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptError {
+    pub message: String,
+    /// Name of the chunk that raised it: a pattern file, node id, `hook`, ...
+    pub chunk: Option<String>,
+    pub line: Option<u32>,
+    pub traceback: Option<String>,
+}
+
+impl ScriptError {
+    pub fn from_lua(error: &mlua::Error) -> Self {
+        if let Some(timeout) = error.downcast_ref::<ScriptTimeout>() {
+            return Self::timeout(timeout.budget);
+        }
+        match error {
+            mlua::Error::RuntimeError(text) | mlua::Error::SyntaxError { message: text, .. } => {
+                let (text, traceback) = match text.split_once("\nstack traceback:\n") {
+                    Some((text, traceback)) => (text, Some(traceback.to_string())),
+                    None => (text.as_str(), None),
+                };
+                let mut script_error = Self::located(text);
+                script_error.traceback = traceback;
+                script_error
+            }
+            mlua::Error::CallbackError { traceback, cause } => {
+                let mut script_error = Self::from_lua(cause);
+                // The innermost Lua frame is where the script made the failing call.
+                if let Some((chunk, line, _)) =
+                    traceback.lines().find_map(|l| split_location(l.trim()))
+                {
+                    script_error.chunk = Some(chunk.to_string());
+                    script_error.line = Some(line);
+                }
+                let traceback = traceback
+                    .strip_prefix("stack traceback:\n")
+                    .unwrap_or(traceback);
+                script_error.traceback = Some(traceback.to_string());
+                script_error
+            }
+            mlua::Error::WithContext { cause, .. } => Self::from_lua(cause),
+            other => Self {
+                message: other.to_string(),
+                chunk: None,
+                line: None,
+                traceback: None,
+            },
+        }
+    }
+
+    /// A script that was aborted after running for `budget`.
+    pub fn timeout(budget: Duration) -> Self {
+        Self {
+            message: ScriptTimeout { budget }.to_string(),
+            chunk: None,
+            line: None,
+            traceback: None,
+        }
+    }
+
+    fn located(text: &str) -> Self {
+        match split_location(text) {
+            Some((chunk, line, message)) => Self {
+                message: message.to_string(),
+                chunk: Some(chunk.to_string()),
+                line: Some(line),
+                traceback: None,
+            },
+            None => Self {
+                message: text.to_string(),
+                chunk: None,
+                line: None,
+                traceback: None,
+            },
+        }
+    }
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.chunk, self.line) {
+            (Some(chunk), Some(line)) => write!(f, "{}:{}: {}", chunk, line, self.message),
+            (Some(chunk), None) => write!(f, "{}: {}", chunk, self.message),
+            _ => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+/// Splits Lua's `[string "name"]:12: message` (or `name:12: message` for chunks
+/// named with `=`/`@`) into its parts.
+fn split_location(text: &str) -> Option<(&str, u32, &str)> {
+    let (chunk, rest) = match text.strip_prefix("[string \"") {
+        Some(quoted) => {
+            let end = quoted.find("\"]:")?;
+            (&quoted[..end], &quoted[end + 3..])
+        }
+        None => {
+            let (chunk, rest) = text.split_once(':')?;
+            (chunk, rest)
+        }
+    };
+    let (line, message) = rest.split_once(':')?;
+    Some((chunk, line.parse().ok()?, message.trim_start()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scripting::LuaRuntime;
+
+    #[test]
+    fn extracts_chunk_line_and_traceback() {
+        let runtime = LuaRuntime::new().unwrap();
+        let error = runtime
+            .lua
+            .load("local x = nil\nreturn x.y")
+            .set_name("=verse")
+            .exec()
+            .unwrap_err();
+
+        let script_error = ScriptError::from_lua(&error);
+        assert_eq!(script_error.chunk.as_deref(), Some("verse"));
+        assert_eq!(script_error.line, Some(2));
+        assert!(
+            script_error
+                .message
+                .starts_with("attempt to index a nil value")
+        );
+        assert!(script_error.traceback.unwrap().contains("in main chunk"));
+    }
+
+    #[test]
+    fn callback_errors_point_at_the_calling_line() {
+        let runtime = LuaRuntime::new().unwrap();
+        let error = runtime
+            .lua
+            .load("\n\nset_param('reverb', 1, 0)")
+            .set_name("hook")
+            .exec()
+            .unwrap_err();
+
+        let script_error = ScriptError::from_lua(&error);
+        assert_eq!(script_error.chunk.as_deref(), Some("hook"));
+        assert_eq!(script_error.line, Some(3));
+        assert!(script_error.message.contains("unknown parameter 'reverb'"));
+    }
+}
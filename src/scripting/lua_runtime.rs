@@ -1,40 +1,655 @@
-use crate::timing::Note;
-use mlua::Lua;
+use super::{PatternContext, ScriptAction, engine_api};
+use crate::timing::{
+    Articulation, AutomationPoint, FilterParam, Note, ParamName, ParamTarget, PatternEvents,
+};
+use crossbeam::channel::{Receiver, Sender};
+use mlua::{HookTriggers, Lua, LuaOptions, StdLib, VmState};
+use parking_lot::Mutex;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const PRELUDE: &str = include_str!("prelude.lua");
+
+/// How long a single script invocation may run before it's aborted. Patterns are
+/// scheduled a sequence ahead, so this only needs to stay well under a bar.
+pub const SCRIPT_TIME_BUDGET: Duration = Duration::from_millis(50);
+
+/// Upper bound on the memory all scripts together may allocate.
+const MEMORY_LIMIT: usize = 64 * 1024 * 1024;
+
+/// The deadline is only checked every this many VM instructions.
+const HOOK_INSTRUCTION_INTERVAL: u32 = 10_000;
+
+/// Returned (wrapped in [`mlua::Error::ExternalError`]) when a script runs past
+/// [`SCRIPT_TIME_BUDGET`].
+#[derive(Debug, Clone, Copy)]
+pub struct ScriptTimeout {
+    pub budget: Duration,
+}
+
+impl std::fmt::Display for ScriptTimeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "script exceeded its {:?} time budget", self.budget)
+    }
+}
+
+impl std::error::Error for ScriptTimeout {}
 
 pub struct LuaRuntime {
     pub lua: Lua,
+    deadline: Arc<Mutex<Option<Instant>>>,
+    actions_tx: Sender<ScriptAction>,
+    actions_rx: Receiver<ScriptAction>,
 }
 
 impl LuaRuntime {
+    /// Creates a sandboxed interpreter: only the table, string, math, utf8 and
+    /// coroutine libraries are loaded (no `os`, `io`, `package` or `debug`), file
+    /// loading is removed, and every call is bounded in time and memory.
     pub fn new() -> Result<Self, mlua::Error> {
-        let lua = Lua::new();
-        Ok(Self { lua })
+        let lua = Lua::new_with(
+            StdLib::TABLE | StdLib::STRING | StdLib::MATH | StdLib::UTF8 | StdLib::COROUTINE,
+            LuaOptions::default(),
+        )?;
+        let globals = lua.globals();
+        for name in ["dofile", "loadfile", "require"] {
+            globals.set(name, mlua::Value::Nil)?;
+        }
+        // Precompiled chunks aren't checked before they run, so `load` only
+        // takes source and nothing can make bytecode to give it.
+        let load: mlua::Function = globals.get("load")?;
+        let load_text = lua.create_function(move |lua, mut args: mlua::MultiValue| {
+            args.truncate(4);
+            if args.len() < 3 {
+                args.resize(3, mlua::Value::Nil);
+            }
+            args[2] = mlua::Value::String(lua.create_string("t")?);
+            // An explicit nil environment would leave the chunk without globals.
+            if args.get(3).is_some_and(mlua::Value::is_nil) {
+                args.truncate(3);
+            }
+            load.call::<mlua::MultiValue>(args)
+        })?;
+        globals.set("load", load_text)?;
+        globals
+            .get::<mlua::Table>("string")?
+            .set("dump", mlua::Value::Nil)?;
+        lua.set_memory_limit(MEMORY_LIMIT)?;
+
+        let deadline = Arc::new(Mutex::new(None::<Instant>));
+        let hook_deadline = Arc::clone(&deadline);
+        lua.set_hook(
+            HookTriggers::new().every_nth_instruction(HOOK_INSTRUCTION_INTERVAL),
+            move |_, _| match *hook_deadline.lock() {
+                Some(deadline) if Instant::now() >= deadline => {
+                    Err(mlua::Error::external(ScriptTimeout {
+                        budget: SCRIPT_TIME_BUDGET,
+                    }))
+                }
+                _ => Ok(VmState::Continue),
+            },
+        )?;
+
+        let (actions_tx, actions_rx) = crossbeam::channel::unbounded();
+        engine_api::register(&lua, actions_tx.clone())?;
+
+        lua.load(PRELUDE).set_name("prelude").exec()?;
+        Ok(Self {
+            lua,
+            deadline,
+            actions_tx,
+            actions_rx,
+        })
+    }
+
+    /// Lets code outside Lua queue actions alongside the ones scripts request.
+    pub fn action_sender(&self) -> Sender<ScriptAction> {
+        self.actions_tx.clone()
+    }
+
+    /// Takes the actions queued since the last call, in request order.
+    pub fn drain_actions(&self) -> impl Iterator<Item = ScriptAction> + '_ {
+        self.actions_rx.try_iter()
     }
 
     pub fn execute(&self, code: &str) -> Result<(), mlua::Error> {
-        self.lua.load(code).exec()
+        self.with_budget(|| self.lua.load(code).exec())
     }
 
-    pub fn execute_pattern(&self, code: &str) -> Result<Vec<Note>, mlua::Error> {
-        let result: mlua::Table = self.lua.load(code).eval()?;
+    /// Runs `f` with the time budget armed, so a runaway script errors out
+    /// instead of blocking the caller.
+    fn with_budget<R>(&self, f: impl FnOnce() -> Result<R, mlua::Error>) -> Result<R, mlua::Error> {
+        *self.deadline.lock() = Some(Instant::now() + SCRIPT_TIME_BUDGET);
+        let result = f();
+        let expired = self
+            .deadline
+            .lock()
+            .take()
+            .is_some_and(|deadline| Instant::now() >= deadline);
 
-        let mut notes = Vec::new();
+        // The hook's error can come back wrapped in callback context; report
+        // timeouts uniformly so callers can tell them apart.
+        match result {
+            Err(_) if expired => Err(mlua::Error::external(ScriptTimeout {
+                budget: SCRIPT_TIME_BUDGET,
+            })),
+            result => result,
+        }
+    }
+
+    /// Runs a pattern chunk and collects the notes it produces.
+    ///
+    /// The chunk receives the context table as `...` and may either return the
+    /// note list directly or return a function taking the context. Entries
+    /// with a `param` are automation points rather than notes.
+    pub fn execute_pattern(
+        &self,
+        code: &str,
+        context: &PatternContext,
+    ) -> Result<PatternEvents, mlua::Error> {
+        self.execute_named_pattern(&format!("={}", context.node_id), code, context)
+    }
+
+    /// Like [`execute_pattern`](Self::execute_pattern), with the chunk name
+    /// errors are reported under (`@path` for code read from a file).
+    pub fn execute_named_pattern(
+        &self,
+        name: &str,
+        code: &str,
+        context: &PatternContext,
+    ) -> Result<PatternEvents, mlua::Error> {
+        self.with_budget(|| self.run_pattern(name, code, context))
+    }
+
+    /// Runs a node or edge hook with `ctx` set for the track it belongs to.
+    pub fn execute_hook(&self, code: &str, context: &PatternContext) -> Result<(), mlua::Error> {
+        self.with_budget(|| {
+            let ctx = self.set_context(context)?;
+            self.lua.load(code).set_name("=hook").call::<()>(ctx)
+        })
+    }
+
+    /// Evaluates an edge condition, a Lua expression such as `counter == 4`.
+    /// An empty condition always holds.
+    pub fn evaluate_condition(
+        &self,
+        condition: &str,
+        context: &PatternContext,
+    ) -> Result<bool, mlua::Error> {
+        if condition.trim().is_empty() {
+            return Ok(true);
+        }
+        self.with_budget(|| {
+            self.set_context(context)?;
+            self.lua
+                .load(format!("return ({})", condition))
+                .set_name("=condition")
+                .eval::<bool>()
+        })
+    }
+
+    /// Seeds `math.random` for this invocation and publishes the context table
+    /// as the global `ctx`.
+    fn set_context(&self, context: &PatternContext) -> Result<mlua::Table, mlua::Error> {
+        let math: mlua::Table = self.lua.globals().get("math")?;
+        let randomseed: mlua::Function = math.get("randomseed")?;
+        randomseed.call::<()>(context.rng_seed() as i64)?;
+
+        let ctx = context.to_lua_table(&self.lua)?;
+        self.lua.globals().set("ctx", &ctx)?;
+        Ok(ctx)
+    }
+
+    fn run_pattern(
+        &self,
+        name: &str,
+        code: &str,
+        context: &PatternContext,
+    ) -> Result<PatternEvents, mlua::Error> {
+        let ctx = self.set_context(context)?;
+        let result = match self
+            .lua
+            .load(code)
+            .set_name(name)
+            .call::<mlua::Value>(ctx.clone())?
+        {
+            mlua::Value::Function(pattern) => pattern.call::<mlua::Table>(ctx)?,
+            value => self.lua.unpack::<mlua::Table>(value)?,
+        };
+
+        let mut events = PatternEvents::default();
         for pair in result.pairs::<usize, mlua::Table>() {
             let (_, note_table) = pair?;
+            if note_table.contains_key("param")? {
+                events.automation.push(automation_point(&note_table)?);
+                continue;
+            }
 
             let pitch: u8 = note_table.get("pitch")?;
             let velocity: u8 = note_table.get("velocity")?;
             let start_beat: f32 = note_table.get("start_beat")?;
             let duration_beats: f32 = note_table.get("duration_beats")?;
 
-            notes.push(Note {
-                pitch,
-                velocity,
-                start_beat,
-                duration_beats,
-            });
+            let mut note = Note::new(pitch, velocity, start_beat, duration_beats);
+            note.pan = note_table.get("pan")?;
+            note.detune_cents = note_table.get("detune_cents")?;
+            note.articulation = match note_table.get::<Option<String>>("articulation")? {
+                Some(name) => Some(Articulation::from_name(&name).ok_or_else(|| {
+                    mlua::Error::runtime(format!("unknown articulation '{}'", name))
+                })?),
+                None => None,
+            };
+            events.notes.push(note);
+        }
+
+        Ok(events)
+    }
+}
+
+/// An automation point from a pattern's result, like
+/// `{ param = "volume", beat = 2, value = 0.5 }`. A numeric `param` is a CLAP
+/// parameter id of the instrument plugin, or of the `effect`th effect. With
+/// a `node`, `param` names a parameter of that node of the patch.
+fn automation_point(table: &mlua::Table) -> Result<AutomationPoint, mlua::Error> {
+    let target = match table.get::<mlua::Value>("param")? {
+        mlua::Value::String(name) if table.contains_key("node")? => ParamTarget::Graph {
+            node: table.get("node")?,
+            param: ParamName::new(&name.to_str()?),
+        },
+        mlua::Value::String(name) => match &*name.to_str()? {
+            "volume" => ParamTarget::Volume,
+            "pan" => ParamTarget::Pan,
+            "cutoff" => ParamTarget::Filter(FilterParam::Cutoff),
+            "resonance" => ParamTarget::Filter(FilterParam::Resonance),
+            other => {
+                return Err(mlua::Error::runtime(format!(
+                    "unknown parameter '{}' (expected volume, pan, cutoff, resonance or a \
+                     plugin parameter id)",
+                    other
+                )));
+            }
+        },
+        mlua::Value::Integer(_) | mlua::Value::Number(_) => {
+            let id: u32 = table.get("param")?;
+            match table.get::<Option<usize>>("effect")? {
+                Some(0) => return Err(mlua::Error::runtime("effects are numbered from 1")),
+                Some(effect) => ParamTarget::Effect {
+                    index: effect - 1,
+                    id,
+                },
+                None => ParamTarget::Instrument(id),
+            }
+        }
+        other => {
+            return Err(mlua::Error::runtime(format!(
+                "invalid parameter of type {}",
+                other.type_name()
+            )));
         }
+    };
+    Ok(AutomationPoint {
+        target,
+        beat: table.get("beat")?,
+        value: table.get("value")?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scripting::{LuaValue, TrackParam, VariableScope, VariableStore};
+    use crate::timing::TransitionTiming;
+
+    fn context(variables: &VariableStore, loop_count: u64) -> PatternContext<'_> {
+        PatternContext {
+            track_id: 1,
+            node_id: "verse",
+            start_sample: 48_000 * 4,
+            bpm: 120.0,
+            sample_rate: 48_000.0,
+            time_signature: (4, 4),
+            loop_count,
+            repeat: None,
+            seed: 42,
+            pattern_seed: 0,
+            variables,
+        }
+    }
+
+    #[test]
+    fn pattern_reads_context_table() {
+        let runtime = LuaRuntime::new().unwrap();
+        let mut variables = VariableStore::new();
+        variables.set_global("energy", LuaValue::Number(3.0));
+        variables.set_track_var(1, "root", LuaValue::Number(48.0));
+
+        let code = r#"
+            local ctx = ...
+            return {
+                { pitch = ctx.track.root + ctx.bar, velocity = 100,
+                  start_beat = ctx.loop, duration_beats = ctx.global.energy },
+            }
+        "#;
+        let notes = runtime
+            .execute_pattern(code, &context(&variables, 2))
+            .unwrap()
+            .notes;
+
+        assert_eq!(notes.len(), 1);
+        assert_eq!(notes[0].pitch, 50);
+        assert_eq!(notes[0].start_beat, 2.0);
+        assert_eq!(notes[0].duration_beats, 3.0);
+    }
+
+    #[test]
+    fn pattern_function_is_called_with_context() {
+        let runtime = LuaRuntime::new().unwrap();
+        let variables = VariableStore::new();
+
+        let code = r#"
+            return function(ctx)
+                return { { pitch = 60, velocity = 100, start_beat = ctx.beat, duration_beats = 1 } }
+            end
+        "#;
+        let notes = runtime
+            .execute_pattern(code, &context(&variables, 0))
+            .unwrap()
+            .notes;
+
+        assert_eq!(notes[0].start_beat, 8.0);
+    }
+
+    #[test]
+    fn notes_carry_their_expression() {
+        let runtime = LuaRuntime::new().unwrap();
+        let variables = VariableStore::new();
+
+        let code = r#"
+            return {
+                { pitch = 60, velocity = 100, start_beat = 0, duration_beats = 1,
+                  pan = -0.5, detune_cents = 12, articulation = "staccato" },
+                { pitch = 62, velocity = 100, start_beat = 1, duration_beats = 1 },
+            }
+        "#;
+        let notes = runtime
+            .execute_pattern(code, &context(&variables, 0))
+            .unwrap()
+            .notes;
+        assert_eq!(notes[0].pan, Some(-0.5));
+        assert_eq!(notes[0].detune_cents, Some(12.0));
+        assert_eq!(notes[0].articulation, Some(Articulation::Staccato));
+        assert_eq!(notes[1], Note::new(62, 100, 1.0, 1.0));
+
+        let code = r#"return { { pitch = 60, velocity = 100, start_beat = 0,
+                                  duration_beats = 1, articulation = "pizzicato" } }"#;
+        let error = runtime
+            .execute_pattern(code, &context(&variables, 0))
+            .unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("unknown articulation 'pizzicato'")
+        );
+    }
+
+    #[test]
+    fn entries_with_a_param_are_automation_points() {
+        let runtime = LuaRuntime::new().unwrap();
+        let variables = VariableStore::new();
+
+        let code = r#"
+            return {
+                { pitch = 60, velocity = 100, start_beat = 0, duration_beats = 4 },
+                { param = "volume", beat = 0, value = 0.5 },
+                { param = 12, beat = 1.5, value = 0.25 },
+                { param = 3, effect = 2, beat = 2, value = 1 },
+                { param = "cutoff", beat = 3, value = 800 },
+                { param = "freq", node = 2, beat = 3.5, value = 220 },
+            }
+        "#;
+        let events = runtime
+            .execute_pattern(code, &context(&variables, 0))
+            .unwrap();
+        assert_eq!(events.notes, vec![Note::new(60, 100, 0.0, 4.0)]);
+        let targets: Vec<(ParamTarget, f32, f32)> = events
+            .automation
+            .iter()
+            .map(|p| (p.target, p.beat, p.value))
+            .collect();
+        assert_eq!(
+            targets,
+            vec![
+                (ParamTarget::Volume, 0.0, 0.5),
+                (ParamTarget::Instrument(12), 1.5, 0.25),
+                (ParamTarget::Effect { index: 1, id: 3 }, 2.0, 1.0),
+                (ParamTarget::Filter(FilterParam::Cutoff), 3.0, 800.0),
+                (
+                    ParamTarget::Graph {
+                        node: 2,
+                        param: ParamName::new("freq"),
+                    },
+                    3.5,
+                    220.0
+                ),
+            ]
+        );
+
+        let code = r#"return { { param = "brightness", beat = 0, value = 1 } }"#;
+        let error = runtime
+            .execute_pattern(code, &context(&variables, 0))
+            .unwrap_err();
+        assert!(error.to_string().contains("unknown parameter 'brightness'"));
+    }
+
+    #[test]
+    fn random_is_reproducible_per_loop() {
+        let runtime = LuaRuntime::new().unwrap();
+        let variables = VariableStore::new();
+        let code = r#"
+            local notes = {}
+            for i = 1, 8 do
+                notes[i] = { pitch = math.random(0, 127), velocity = 100, start_beat = 0, duration_beats = 1 }
+            end
+            return notes
+        "#;
+        let pitches = |loop_count| {
+            runtime
+                .execute_pattern(code, &context(&variables, loop_count))
+                .unwrap()
+                .notes
+                .iter()
+                .map(|n| n.pitch)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(pitches(0), pitches(0));
+        assert_ne!(pitches(0), pitches(1));
+        assert_ne!(pitches(1), pitches(2));
+    }
+
+    #[test]
+    fn pattern_seed_rerolls_the_random_stream() {
+        let runtime = LuaRuntime::new().unwrap();
+        let variables = VariableStore::new();
+        let code = r#"
+            local notes = {}
+            for i = 1, 8 do
+                notes[i] = { pitch = math.random(0, 127), velocity = 100, start_beat = 0, duration_beats = 1 }
+            end
+            return notes
+        "#;
+        let pitches = |pattern_seed| {
+            let mut ctx = context(&variables, 0);
+            ctx.pattern_seed = pattern_seed;
+            runtime
+                .execute_pattern(code, &ctx)
+                .unwrap()
+                .notes
+                .iter()
+                .map(|n| n.pitch)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(pitches(7), pitches(7));
+        assert_ne!(pitches(7), pitches(8));
+    }
+
+    #[test]
+    fn prelude_helpers() {
+        let runtime = LuaRuntime::new().unwrap();
+        let variables = VariableStore::new();
+
+        let code = r#"
+            local notes = {}
+            local pitches = scale("minor", "A3")
+            for step, hit in ipairs(euclid(3, 8)) do
+                if hit then
+                    notes[#notes + 1] = { pitch = pitches[step % #pitches + 1], velocity = 100,
+                                          start_beat = (step - 1) / 2, duration_beats = 0.5 }
+                end
+            end
+            if every(2) then
+                notes[#notes + 1] = { pitch = note("C#4"), velocity = 90,
+                                      start_beat = 0, duration_beats = 1 }
+            end
+            notes[#notes + 1] = { pitch = rand_choice({ note("Eb3") }), velocity = 80,
+                                  start_beat = 0, duration_beats = 1 }
+            return notes
+        "#;
+        let notes = runtime
+            .execute_pattern(code, &context(&variables, 2))
+            .unwrap()
+            .notes;
+
+        assert_eq!(notes.len(), 5);
+        assert_eq!(notes[3].pitch, 61);
+        assert_eq!(notes[4].pitch, 51);
+        assert!(notes[..3].iter().all(|n| n.pitch >= 57 && n.pitch <= 67));
+    }
+
+    #[test]
+    fn runaway_pattern_times_out() {
+        let runtime = LuaRuntime::new().unwrap();
+        let variables = VariableStore::new();
+
+        let started = Instant::now();
+        let error = runtime
+            .execute_pattern("while true do end", &context(&variables, 0))
+            .unwrap_err();
+
+        assert!(error.downcast_ref::<ScriptTimeout>().is_some(), "{error}");
+        assert!(started.elapsed() < SCRIPT_TIME_BUDGET * 10);
+
+        // The runtime stays usable afterwards.
+        let notes = runtime
+            .execute_pattern("return {}", &context(&variables, 0))
+            .unwrap()
+            .notes;
+        assert!(notes.is_empty());
+    }
+
+    #[test]
+    fn environment_is_restricted() {
+        let runtime = LuaRuntime::new().unwrap();
+        runtime
+            .execute(
+                r#"
+                assert(os == nil and io == nil and debug == nil and package == nil)
+                assert(dofile == nil and loadfile == nil and require == nil)
+                assert(string.dump == nil)
+                assert(load("return 1 + 1")() == 2)
+                assert(load("return x", "chunk", "t", { x = 3 })() == 3)
+                local f, err = load("\27Lua")
+                assert(f == nil and err:find("binary"))
+            "#,
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn hooks_queue_engine_actions() {
+        let runtime = LuaRuntime::new().unwrap();
+        let variables = VariableStore::new();
+
+        runtime
+            .execute_hook(
+                r#"
+                set_var("hits", 3)
+                set_global("mood", "dark")
+                set_bpm(140)
+                trigger_transition("chorus")
+                set_param("volume", 0.5, 0)
+                go_to_section("drop")
+            "#,
+                &context(&variables, 0),
+            )
+            .unwrap();
+
+        let actions: Vec<_> = runtime.drain_actions().collect();
+        assert_eq!(
+            actions,
+            vec![
+                ScriptAction::SetVariable {
+                    scope: VariableScope::Node {
+                        track_id: 1,
+                        node_id: "verse".to_string(),
+                    },
+                    name: "hits".to_string(),
+                    value: LuaValue::Number(3.0),
+                },
+                ScriptAction::SetVariable {
+                    scope: VariableScope::Global,
+                    name: "mood".to_string(),
+                    value: LuaValue::String("dark".to_string()),
+                },
+                ScriptAction::SetBpm(140.0),
+                ScriptAction::TriggerTransition {
+                    track_id: 1,
+                    node_id: "chorus".to_string(),
+                    timing: TransitionTiming::FinishSequence,
+                },
+                ScriptAction::SetParam {
+                    track_id: 0,
+                    param: TrackParam::Volume,
+                    value: 0.5,
+                },
+                ScriptAction::GoToSection("drop".to_string()),
+            ]
+        );
+
+        assert!(
+            runtime
+                .execute_hook("set_param('reverb', 1)", &context(&variables, 0))
+                .is_err()
+        );
+        assert!(runtime.drain_actions().next().is_none());
+    }
+
+    #[test]
+    fn conditions_see_hook_state_and_context() {
+        let runtime = LuaRuntime::new().unwrap();
+        let variables = VariableStore::new();
+        let ctx = context(&variables, 3);
+
+        runtime.execute_hook("counter = 4", &ctx).unwrap();
+        assert!(runtime.evaluate_condition("counter == 4", &ctx).unwrap());
+        assert!(runtime.evaluate_condition("ctx.loop == 3", &ctx).unwrap());
+        assert!(!runtime.evaluate_condition("false", &ctx).unwrap());
+        assert!(runtime.evaluate_condition("", &ctx).unwrap());
+        assert!(
+            runtime
+                .evaluate_condition("ctx.remaining == nil", &ctx)
+                .unwrap()
+        );
 
-        Ok(notes)
+        let repeating = PatternContext {
+            repeat: Some(4),
+            ..context(&variables, 1)
+        };
+        assert!(
+            runtime
+                .evaluate_condition("ctx.repeats == 4 and ctx.remaining == 2", &repeating)
+                .unwrap()
+        );
     }
 }
@@ -1,5 +1,12 @@
+mod actions;
+mod context;
+mod engine_api;
+mod error;
 mod lua_runtime;
 mod variables;
 
-pub use lua_runtime::LuaRuntime;
+pub use actions::{ScriptAction, TrackParam, VariableScope};
+pub use context::PatternContext;
+pub use error::ScriptError;
+pub use lua_runtime::{LuaRuntime, SCRIPT_TIME_BUDGET, ScriptTimeout};
 pub use variables::{LuaValue, VariableStore};
@@ -0,0 +1,76 @@
+-- Music helpers available to every pattern script.
+
+local NOTE_OFFSETS = { C = 0, D = 2, E = 4, F = 5, G = 7, A = 9, B = 11 }
+
+-- MIDI pitch for a note name such as "C4", "C#4", "Eb3" or "A-1" (C4 = 60).
+function note(name)
+    if type(name) == "number" then
+        return name
+    end
+    local letter, accidentals, octave = string.match(name, "^([A-Ga-g])([#b]*)(-?%d+)$")
+    if not letter then
+        error("invalid note name '" .. tostring(name) .. "'", 2)
+    end
+    local pitch = NOTE_OFFSETS[string.upper(letter)] + (tonumber(octave) + 1) * 12
+    for accidental in accidentals:gmatch(".") do
+        pitch = pitch + (accidental == "#" and 1 or -1)
+    end
+    return pitch
+end
+
+SCALES = {
+    major = { 0, 2, 4, 5, 7, 9, 11 },
+    minor = { 0, 2, 3, 5, 7, 8, 10 },
+    harmonic_minor = { 0, 2, 3, 5, 7, 8, 11 },
+    melodic_minor = { 0, 2, 3, 5, 7, 9, 11 },
+    dorian = { 0, 2, 3, 5, 7, 9, 10 },
+    phrygian = { 0, 1, 3, 5, 7, 8, 10 },
+    lydian = { 0, 2, 4, 6, 7, 9, 11 },
+    mixolydian = { 0, 2, 4, 5, 7, 9, 10 },
+    locrian = { 0, 1, 3, 5, 6, 8, 10 },
+    major_pentatonic = { 0, 2, 4, 7, 9 },
+    minor_pentatonic = { 0, 3, 5, 7, 10 },
+    blues = { 0, 3, 5, 6, 7, 10 },
+    chromatic = { 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11 },
+}
+
+-- Pitches of a scale starting at `root` (a pitch or note name), spanning `octaves` (default 1).
+function scale(name, root, octaves)
+    local intervals = SCALES[name]
+    if not intervals then
+        error("unknown scale '" .. tostring(name) .. "'", 2)
+    end
+    local base = note(root)
+    local pitches = {}
+    for octave = 0, (octaves or 1) - 1 do
+        for _, interval in ipairs(intervals) do
+            pitches[#pitches + 1] = base + octave * 12 + interval
+        end
+    end
+    return pitches
+end
+
+-- True on every `n`th loop of the current node, shifted by `offset` loops.
+function every(n, offset)
+    return ((ctx.loop or 0) - (offset or 0)) % n == 0
+end
+
+-- Euclidean rhythm: `k` hits spread as evenly as possible over `n` steps,
+-- returned as an array of booleans, optionally rotated by `rotation` steps.
+function euclid(k, n, rotation)
+    local steps = {}
+    local shift = rotation or 0
+    for i = 0, n - 1 do
+        local step = (i + shift) % n
+        steps[i + 1] = (step * k) % n < k
+    end
+    return steps
+end
+
+-- A random element of `list`, using the pattern's seeded RNG.
+function rand_choice(list)
+    if #list == 0 then
+        return nil
+    end
+    return list[math.random(#list)]
+end
@@ -1,6 +1,6 @@
 use std::collections::HashMap;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum LuaValue {
     Number(f64),
     Boolean(bool),
@@ -8,6 +8,7 @@ pub enum LuaValue {
     Nil,
 }
 
+#[derive(Clone)]
 pub struct VariableStore {
     node_vars: HashMap<(usize, String, String), LuaValue>, // (track_id, node_id, var_name)
     track_vars: HashMap<(usize, String), LuaValue>,        // (track_id, var_name)
@@ -53,4 +54,34 @@ impl VariableStore {
     pub fn set_global(&mut self, name: &str, value: LuaValue) {
         self.global_vars.insert(name.to_string(), value);
     }
+
+    pub fn node_vars(
+        &self,
+        track_id: usize,
+        node_id: &str,
+    ) -> impl Iterator<Item = (&str, &LuaValue)> {
+        self.node_vars
+            .iter()
+            .filter(move |((t, n, _), _)| *t == track_id && n == node_id)
+            .map(|((_, _, name), value)| (name.as_str(), value))
+    }
+
+    pub fn track_vars(&self, track_id: usize) -> impl Iterator<Item = (&str, &LuaValue)> {
+        self.track_vars
+            .iter()
+            .filter(move |((t, _), _)| *t == track_id)
+            .map(|((_, name), value)| (name.as_str(), value))
+    }
+
+    pub fn globals(&self) -> impl Iterator<Item = (&str, &LuaValue)> {
+        self.global_vars
+            .iter()
+            .map(|(name, value)| (name.as_str(), value))
+    }
+}
+
+impl Default for VariableStore {
+    fn default() -> Self {
+        Self::new()
+    }
 }
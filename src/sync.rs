@@ -0,0 +1,148 @@
+//! Tempo and bar-phase sync: a tempo plus the beat reached at a reference
+//! time, from which one beat grid runs on across restarts and count-ins, so a
+//! start can be quantized to its next bar.
+//!
+//! With the `link` feature, a [`LinkSession`] keeps the grid on an Ableton
+//! Link session, so tempo and bar phase lock with other apps on the network.
+//! Without it, the grid is the engine's own.
+
+use std::time::{Duration, Instant};
+
+/// Beats per bar used for quantizing.
+pub const DEFAULT_QUANTUM: f64 = 4.0;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Timeline {
+    bpm: f64,
+    beat_origin: f64,
+    time_origin: Instant,
+}
+
+impl Timeline {
+    pub fn new(bpm: f64, now: Instant) -> Self {
+        Self {
+            bpm,
+            beat_origin: 0.0,
+            time_origin: now,
+        }
+    }
+
+    pub fn bpm(&self) -> f64 {
+        self.bpm
+    }
+
+    pub fn beat_at(&self, time: Instant) -> f64 {
+        let elapsed = if time >= self.time_origin {
+            (time - self.time_origin).as_secs_f64()
+        } else {
+            -(self.time_origin - time).as_secs_f64()
+        };
+        self.beat_origin + elapsed * self.bpm / 60.0
+    }
+
+    pub fn time_at_beat(&self, beat: f64) -> Instant {
+        let seconds = (beat - self.beat_origin) * 60.0 / self.bpm;
+        if seconds >= 0.0 {
+            self.time_origin + Duration::from_secs_f64(seconds)
+        } else {
+            self.time_origin - Duration::from_secs_f64(-seconds)
+        }
+    }
+
+    /// Changes tempo at `at` without moving the beat reached at that moment.
+    pub fn set_tempo(&mut self, bpm: f64, at: Instant) {
+        self.beat_origin = self.beat_at(at);
+        self.time_origin = at;
+        self.bpm = bpm;
+    }
+
+    /// Position within the current bar, in beats (0 up to `quantum`).
+    pub fn phase(&self, at: Instant, quantum: f64) -> f64 {
+        self.beat_at(at).rem_euclid(quantum)
+    }
+
+    /// The first bar boundary at or after `at`.
+    pub fn next_bar(&self, at: Instant, quantum: f64) -> Instant {
+        let beat = self.beat_at(at);
+        let phase = beat.rem_euclid(quantum);
+        if phase < 1e-9 {
+            return at;
+        }
+        self.time_at_beat(beat - phase + quantum)
+    }
+}
+
+/// This engine's peer in the Ableton Link session on the local network.
+#[cfg(feature = "link")]
+pub struct LinkSession {
+    link: rusty_link::AblLink,
+    /// Room for capturing the session's tempo and beat grid.
+    state: rusty_link::SessionState,
+}
+
+#[cfg(feature = "link")]
+impl LinkSession {
+    /// Joins the session, starting one at `bpm` if no other app has.
+    pub fn join(bpm: f64) -> Self {
+        let link = rusty_link::AblLink::new(bpm);
+        link.enable(true);
+        Self {
+            link,
+            state: rusty_link::SessionState::new(),
+        }
+    }
+
+    /// Other apps in the session.
+    pub fn peers(&self) -> u64 {
+        self.link.num_peers()
+    }
+
+    /// The session's tempo and beat grid as of `now`, with bars of `quantum`
+    /// beats in phase with the other apps'.
+    pub fn timeline(&mut self, now: Instant, quantum: f64) -> Timeline {
+        self.link.capture_app_session_state(&mut self.state);
+        let micros = self.link.clock_micros();
+        Timeline {
+            bpm: self.state.tempo(),
+            beat_origin: self.state.beat_at_time(micros, quantum),
+            time_origin: now,
+        }
+    }
+
+    /// Changes the session's tempo, for every app in it.
+    pub fn set_tempo(&mut self, bpm: f64) {
+        self.link.capture_app_session_state(&mut self.state);
+        self.state.set_tempo(bpm, self.link.clock_micros());
+        self.link.commit_app_session_state(&self.state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tempo_changes_keep_the_beat_continuous() {
+        let start = Instant::now();
+        let mut timeline = Timeline::new(120.0, start);
+        let one_second = start + Duration::from_secs(1);
+        assert!((timeline.beat_at(one_second) - 2.0).abs() < 1e-9);
+
+        timeline.set_tempo(60.0, one_second);
+        assert!((timeline.beat_at(one_second) - 2.0).abs() < 1e-9);
+        assert!((timeline.beat_at(start + Duration::from_secs(2)) - 3.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn quantizes_to_the_next_bar() {
+        let start = Instant::now();
+        let timeline = Timeline::new(120.0, start);
+
+        // Beat 3 of the first bar: the next bar starts at beat 4, half a second on.
+        let at = start + Duration::from_millis(1500);
+        let next = timeline.next_bar(at, DEFAULT_QUANTUM);
+        assert!(((next - at).as_secs_f64() - 0.5).abs() < 1e-6);
+        assert!((timeline.phase(at, DEFAULT_QUANTUM) - 3.0).abs() < 1e-9);
+        assert_eq!(timeline.next_bar(start, DEFAULT_QUANTUM), start);
+    }
+}
@@ -0,0 +1,705 @@
+use crate::audio::{
+    ADSRConfig, ChokeMode, Instrument, InstrumentSnapshot, KeyTracking, OscConfig, TrackUid,
+    VelocityResponse, Wave,
+};
+use crate::midi::MidiInputConfig;
+use crate::timing::{
+    Edge, GeneratedPattern, Hook, Node, Note, Sequence, StateGraph, StaticPattern, TransitionTiming,
+};
+use crate::{Project, TrackData};
+use std::fmt;
+use std::str::FromStr;
+
+/// Starting points for `aurio new --template`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Template {
+    /// Only a click track.
+    Empty,
+    /// Four-on-the-floor drums and a generated acid bassline at 128 BPM.
+    Techno,
+    /// Slow chord changes under a sparse generated melody at 70 BPM.
+    Ambient,
+    /// The commented walkthrough built by [`tutorial`].
+    Tutorial,
+}
+
+impl Template {
+    pub const ALL: [Template; 4] = [
+        Template::Empty,
+        Template::Techno,
+        Template::Ambient,
+        Template::Tutorial,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Template::Empty => "empty",
+            Template::Techno => "techno",
+            Template::Ambient => "ambient",
+            Template::Tutorial => "tutorial",
+        }
+    }
+
+    pub fn build(self, name: &str) -> Project {
+        match self {
+            Template::Empty => project(name, 120.0, vec![click_track(0)]),
+            Template::Techno => techno(name),
+            Template::Ambient => ambient(name),
+            Template::Tutorial => tutorial(name),
+        }
+    }
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Template {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Template::ALL
+            .into_iter()
+            .find(|template| template.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<_> = Template::ALL.iter().map(|t| t.name()).collect();
+                format!("Unknown template '{}' (expected {})", s, names.join(", "))
+            })
+    }
+}
+
+const ARPEGGIO: &str = r#"-- Generated verse: an A minor arpeggio that evolves every loop.
+-- `ctx` holds the bar, beat, loop count and variables for this run,
+-- and math.random is seeded so renders are reproducible.
+local ctx = ...
+local pitches = scale("minor", "A4")
+local notes = {}
+for step, hit in ipairs(euclid(5, 8, ctx.loop)) do
+    if hit then
+        notes[#notes + 1] = {
+            pitch = rand_choice(pitches),
+            velocity = 70 + math.random(0, 40),
+            start_beat = (step - 1) * 0.5,
+            duration_beats = 0.5,
+        }
+    end
+end
+-- Every other loop, end the phrase on the root an octave up.
+if every(2) then
+    notes[#notes + 1] = { pitch = note("A5"), velocity = 110, start_beat = 3.5, duration_beats = 0.5 }
+end
+return notes
+"#;
+
+const ACID: &str = r#"-- Sixteenth-note acid line: the rhythm shifts every loop and
+-- accents land on random steps of the scale.
+local ctx = ...
+local pitches = scale("phrygian", "E2")
+local notes = {}
+for step, hit in ipairs(euclid(7, 16, ctx.loop % 4)) do
+    if hit then
+        notes[#notes + 1] = {
+            pitch = step == 1 and pitches[1] or rand_choice(pitches),
+            velocity = math.random() < 0.3 and 120 or 80,
+            start_beat = (step - 1) * 0.25,
+            duration_beats = 0.2,
+        }
+    end
+end
+return notes
+"#;
+
+const DRIFT: &str = r#"-- A few notes drifting over the chords, fewer on odd loops.
+local ctx = ...
+local pitches = scale("major_pentatonic", "C5")
+local notes = {}
+local count = every(2) and 4 or 2
+for i = 1, count do
+    notes[#notes + 1] = {
+        pitch = rand_choice(pitches),
+        velocity = 50 + math.random(0, 30),
+        start_beat = (i - 1) * (8 / count) + math.random(0, 1),
+        duration_beats = 2,
+    }
+end
+return notes
+"#;
+
+/// Builds the project created by `aurio new --tutorial`.
+///
+/// It's meant to be read: three tracks, a state machine with conditional
+/// transitions, a Lua-generated pattern and a morph target, each commented
+/// where the format allows it.
+pub fn tutorial(name: &str) -> Project {
+    project(name, 110.0, vec![lead_track(), bass_track(), pad_track()])
+}
+
+fn project(name: &str, bpm: f32, tracks: Vec<TrackData>) -> Project {
+    Project {
+        name: name.to_string(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        bpm,
+        sample_rate: 44100,
+        seed: 2026,
+        sample_library: Vec::new(),
+        tracks,
+        buses: Vec::new(),
+        midi_mappings: Vec::new(),
+        midi_input: MidiInputConfig::default(),
+        osc_port: None,
+        tempo_sync: false,
+        count_in_bars: 0,
+        arrangement: None,
+    }
+}
+
+fn techno(name: &str) -> Project {
+    let kick = (0..4).map(|beat| note(36, beat as f32, 0.25)).collect();
+    let hats = (0..4)
+        .map(|beat| note(96, beat as f32 + 0.5, 0.1))
+        .collect();
+
+    let groove = Node {
+        id: "groove".to_string(),
+        sequence: generated(1, ACID),
+        hooks: vec![
+            (Hook::OnEnter, "acid_bars = 0".to_string()),
+            (Hook::OnLoop, "acid_bars = acid_bars + 1".to_string()),
+        ],
+        repeat: None,
+        position: None,
+    };
+    // A bar of held root between phrases.
+    let breakdown = Node {
+        id: "break".to_string(),
+        sequence: static_pattern(1, vec![note(40, 0.0, 4.0)]),
+        hooks: Vec::new(),
+        repeat: None,
+        position: None,
+    };
+
+    project(
+        name,
+        128.0,
+        vec![
+            click_track(0),
+            TrackData {
+                volume: 0.9,
+                ..simple_track(
+                    1,
+                    "Kick",
+                    vec![osc(Wave::Sine, 0.8, 0)],
+                    ADSRConfig::new(0.001, 0.15, 0.0, 0.05),
+                    static_pattern(1, kick),
+                )
+            },
+            TrackData {
+                volume: 0.4,
+                pan: 0.2,
+                ..simple_track(
+                    2,
+                    "Hats",
+                    vec![osc(Wave::Square, 0.1, 0), osc(Wave::Square, 0.1, 7)],
+                    ADSRConfig::new(0.001, 0.04, 0.0, 0.02),
+                    static_pattern(1, hats),
+                )
+            },
+            TrackData {
+                id: 3,
+                uid: TrackUid::new(),
+                name: "Acid".to_string(),
+                instrument: Instrument::MultiOsc {
+                    oscillators: vec![osc(Wave::Saw, 0.3, 0), osc(Wave::Square, 0.1, 12)],
+                    spread: 0.0,
+                },
+                adsr: ADSRConfig::new(0.002, 0.1, 0.4, 0.05),
+                volume: 0.6,
+                pan: -0.1,
+                initial_node: "groove".to_string(),
+                graph: StateGraph {
+                    nodes: vec![groove, breakdown],
+                    edges: vec![
+                        // Eight bars of groove, then the break.
+                        edge(
+                            "groove",
+                            "break",
+                            "acid_bars == 7",
+                            TransitionTiming::FinishSequence,
+                        ),
+                        edge("groove", "groove", "true", TransitionTiming::FinishSequence),
+                        edge("break", "groove", "true", TransitionTiming::FinishSequence),
+                    ],
+                },
+                morph: None,
+                effects: Vec::new(),
+                mute: false,
+                solo: false,
+                sends: Vec::new(),
+                choke: ChokeMode::default(),
+                velocity: VelocityResponse::default(),
+                key_tracking: KeyTracking::default(),
+                layers: Vec::new(),
+            },
+        ],
+    )
+}
+
+fn ambient(name: &str) -> Project {
+    let chords = [
+        ("c-major", [60, 64, 67]),
+        ("a-minor", [57, 60, 64]),
+        ("f-major", [53, 57, 60]),
+    ];
+    let nodes = chords
+        .iter()
+        .map(|(id, pitches)| Node {
+            id: id.to_string(),
+            sequence: static_pattern(2, pitches.iter().map(|&p| note(p, 0.0, 8.0)).collect()),
+            hooks: Vec::new(),
+            repeat: None,
+            position: None,
+        })
+        .collect();
+    // Each chord moves on to the next, round and round.
+    let edges = chords
+        .iter()
+        .zip(chords.iter().cycle().skip(1))
+        .map(|((from, _), (to, _))| edge(from, to, "true", TransitionTiming::FinishSequence))
+        .collect();
+
+    project(
+        name,
+        70.0,
+        vec![
+            TrackData {
+                volume: 0.15,
+                ..click_track(0)
+            },
+            TrackData {
+                id: 1,
+                uid: TrackUid::new(),
+                name: "Pad".to_string(),
+                instrument: Instrument::MultiOsc {
+                    oscillators: vec![osc(Wave::Sine, 0.2, 0), osc(Wave::Sine, 0.1, 12)],
+                    spread: 0.6,
+                },
+                adsr: ADSRConfig::new(1.5, 1.0, 0.8, 3.0),
+                volume: 0.5,
+                pan: 0.0,
+                initial_node: "c-major".to_string(),
+                graph: StateGraph { nodes, edges },
+                morph: None,
+                effects: Vec::new(),
+                mute: false,
+                solo: false,
+                sends: Vec::new(),
+                choke: ChokeMode::default(),
+                velocity: VelocityResponse::default(),
+                key_tracking: KeyTracking::default(),
+                layers: Vec::new(),
+            },
+            TrackData {
+                pan: 0.3,
+                ..simple_track(
+                    2,
+                    "Drift",
+                    vec![osc(Wave::Sine, 0.2, 0), osc(Wave::Square, 0.02, 12)],
+                    ADSRConfig::new(0.3, 0.5, 0.6, 2.0),
+                    generated(2, DRIFT),
+                )
+            },
+        ],
+    )
+}
+
+/// A high blip on every beat, accented on the one.
+fn click_track(id: usize) -> TrackData {
+    let clicks = (0..4)
+        .map(|beat| {
+            let (pitch, velocity) = if beat == 0 { (96, 120) } else { (84, 80) };
+            Note::new(pitch, velocity, beat as f32, 0.05)
+        })
+        .collect();
+
+    TrackData {
+        volume: 0.3,
+        ..simple_track(
+            id,
+            "Click",
+            vec![osc(Wave::Sine, 0.5, 0)],
+            ADSRConfig::new(0.001, 0.03, 0.0, 0.01),
+            static_pattern(1, clicks),
+        )
+    }
+}
+
+/// A track looping a single `main` node.
+fn simple_track(
+    id: usize,
+    name: &str,
+    oscillators: Vec<OscConfig>,
+    adsr: ADSRConfig,
+    sequence: Sequence,
+) -> TrackData {
+    TrackData {
+        id,
+        uid: TrackUid::new(),
+        name: name.to_string(),
+        instrument: Instrument::MultiOsc {
+            oscillators,
+            spread: 0.0,
+        },
+        adsr,
+        volume: 0.8,
+        pan: 0.0,
+        initial_node: "main".to_string(),
+        graph: StateGraph {
+            nodes: vec![Node {
+                id: "main".to_string(),
+                sequence,
+                hooks: Vec::new(),
+                repeat: None,
+                position: None,
+            }],
+            edges: vec![edge(
+                "main",
+                "main",
+                "true",
+                TransitionTiming::FinishSequence,
+            )],
+        },
+        morph: None,
+        effects: Vec::new(),
+        mute: false,
+        solo: false,
+        sends: Vec::new(),
+        choke: ChokeMode::default(),
+        velocity: VelocityResponse::default(),
+        key_tracking: KeyTracking::default(),
+        layers: Vec::new(),
+    }
+}
+
+fn static_pattern(duration_bars: u32, notes: Vec<Note>) -> Sequence {
+    Sequence::Static(StaticPattern {
+        duration_bars,
+        time_signature: (4, 4),
+        notes,
+        automation: Vec::new(),
+    })
+}
+
+fn generated(duration_bars: u32, function: &str) -> Sequence {
+    Sequence::Generated(GeneratedPattern {
+        duration_bars,
+        time_signature: (4, 4),
+        function: function.to_string(),
+        file: None,
+        seed: 0,
+    })
+}
+
+fn lead_track() -> TrackData {
+    let intro = Node {
+        id: "intro".to_string(),
+        sequence: Sequence::Static(StaticPattern {
+            duration_bars: 1,
+            time_signature: (4, 4),
+            notes: [69, 72, 76, 72]
+                .iter()
+                .enumerate()
+                .map(|(i, &pitch)| note(pitch, i as f32, 1.0))
+                .collect(),
+            automation: Vec::new(),
+        }),
+        hooks: vec![(
+            Hook::OnEnter,
+            "-- Hooks run when the node is entered.\ncounter = 0".to_string(),
+        )],
+        repeat: None,
+        position: None,
+    };
+    let verse = Node {
+        id: "verse".to_string(),
+        sequence: Sequence::Generated(GeneratedPattern {
+            duration_bars: 1,
+            time_signature: (4, 4),
+            function: ARPEGGIO.to_string(),
+            file: None,
+            seed: 0,
+        }),
+        hooks: vec![(
+            Hook::OnLoop,
+            "-- Count how many times the verse played.\ncounter = counter + 1".to_string(),
+        )],
+        repeat: None,
+        position: None,
+    };
+
+    TrackData {
+        id: 0,
+        uid: TrackUid::new(),
+        name: "Lead".to_string(),
+        instrument: Instrument::MultiOsc {
+            oscillators: vec![osc(Wave::Square, 0.15, 0), osc(Wave::Sine, 0.2, 12)],
+            spread: 0.0,
+        },
+        adsr: ADSRConfig::new(0.005, 0.1, 0.6, 0.15),
+        volume: 0.8,
+        pan: -0.2,
+        initial_node: "intro".to_string(),
+        graph: StateGraph {
+            nodes: vec![intro, verse],
+            edges: vec![
+                edge("intro", "verse", "true", TransitionTiming::FinishSequence),
+                // Stay on the verse four times, then restart from the intro.
+                edge(
+                    "verse",
+                    "intro",
+                    "counter == 4",
+                    TransitionTiming::FinishSequence,
+                ),
+                edge("verse", "verse", "true", TransitionTiming::FinishSequence),
+            ],
+        },
+        morph: None,
+        effects: Vec::new(),
+        mute: false,
+        solo: false,
+        sends: Vec::new(),
+        choke: ChokeMode::default(),
+        velocity: VelocityResponse::default(),
+        key_tracking: KeyTracking::default(),
+        layers: Vec::new(),
+    }
+}
+
+fn bass_track() -> TrackData {
+    let root = |pitch: u8| {
+        (0..4)
+            .flat_map(move |beat| {
+                [
+                    note(pitch, beat as f32, 0.5),
+                    note(pitch + 12, beat as f32 + 0.5, 0.5),
+                ]
+            })
+            .collect::<Vec<_>>()
+    };
+    let pattern = |notes: Vec<Note>| {
+        Sequence::Static(StaticPattern {
+            duration_bars: 1,
+            time_signature: (4, 4),
+            notes,
+            automation: Vec::new(),
+        })
+    };
+
+    TrackData {
+        id: 1,
+        uid: TrackUid::new(),
+        name: "Bass".to_string(),
+        instrument: Instrument::MultiOsc {
+            oscillators: vec![osc(Wave::Saw, 0.25, 0), osc(Wave::Sine, 0.3, -12)],
+            spread: 0.0,
+        },
+        adsr: ADSRConfig::new(0.005, 0.2, 0.5, 0.05),
+        volume: 0.9,
+        pan: 0.0,
+        initial_node: "a-minor".to_string(),
+        graph: StateGraph {
+            nodes: vec![
+                Node {
+                    id: "a-minor".to_string(),
+                    sequence: pattern(root(45)),
+                    hooks: Vec::new(),
+                    repeat: None,
+                    position: None,
+                },
+                Node {
+                    id: "f-major".to_string(),
+                    sequence: pattern(root(41)),
+                    hooks: Vec::new(),
+                    repeat: None,
+                    position: None,
+                },
+            ],
+            edges: vec![
+                edge("a-minor", "f-major", "true", TransitionTiming::NextBar),
+                edge("f-major", "a-minor", "true", TransitionTiming::NextBar),
+            ],
+        },
+        morph: None,
+        effects: Vec::new(),
+        mute: false,
+        solo: false,
+        sends: Vec::new(),
+        choke: ChokeMode::default(),
+        velocity: VelocityResponse::default(),
+        key_tracking: KeyTracking::default(),
+        layers: Vec::new(),
+    }
+}
+
+fn pad_track() -> TrackData {
+    let chord = [57, 60, 64]
+        .iter()
+        .map(|&pitch| note(pitch, 0.0, 8.0))
+        .collect();
+
+    TrackData {
+        id: 2,
+        uid: TrackUid::new(),
+        name: "Pad".to_string(),
+        instrument: Instrument::MultiOsc {
+            oscillators: vec![osc(Wave::Sine, 0.15, 0), osc(Wave::Sine, 0.1, 7)],
+            spread: 0.5,
+        },
+        adsr: ADSRConfig::new(0.8, 0.5, 0.8, 1.5),
+        volume: 0.5,
+        pan: 0.3,
+        initial_node: "chord".to_string(),
+        graph: StateGraph {
+            nodes: vec![Node {
+                id: "chord".to_string(),
+                sequence: Sequence::Static(StaticPattern {
+                    duration_bars: 2,
+                    time_signature: (4, 4),
+                    notes: chord,
+                    automation: Vec::new(),
+                }),
+                hooks: Vec::new(),
+                repeat: None,
+                position: None,
+            }],
+            edges: vec![edge(
+                "chord",
+                "chord",
+                "true",
+                TransitionTiming::FinishSequence,
+            )],
+        },
+        // Turn the Morph knob to fade from the soft sines into a buzzier pad.
+        morph: Some(InstrumentSnapshot {
+            instrument: Instrument::MultiOsc {
+                oscillators: vec![osc(Wave::Saw, 0.1, 0), osc(Wave::Square, 0.05, 12)],
+                spread: 0.5,
+            },
+            adsr: ADSRConfig::new(0.2, 0.3, 0.7, 0.8),
+            volume: 0.4,
+            pan: -0.3,
+        }),
+        effects: Vec::new(),
+        mute: false,
+        solo: false,
+        sends: Vec::new(),
+        choke: ChokeMode::default(),
+        velocity: VelocityResponse::default(),
+        key_tracking: KeyTracking::default(),
+        layers: Vec::new(),
+    }
+}
+
+fn note(pitch: u8, start_beat: f32, duration_beats: f32) -> Note {
+    Note::new(pitch, 100, start_beat, duration_beats)
+}
+
+fn osc(wave: Wave, gain: f32, semitone: i8) -> OscConfig {
+    OscConfig {
+        wave,
+        gain,
+        semitone,
+        pan: 0.0,
+    }
+}
+
+fn edge(from: &str, to: &str, condition: &str, timing: TransitionTiming) -> Edge {
+    Edge {
+        from: from.to_string(),
+        to: to.to_string(),
+        condition: condition.to_string(),
+        timing,
+        inlet_hook: None,
+        weight: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scripting::{LuaRuntime, PatternContext, VariableStore};
+    use crate::timing::schedule_sequence_events;
+    use ringbuf::{HeapRb, traits::Consumer, traits::Split};
+    use std::time::Duration;
+
+    #[test]
+    fn every_template_checks_clean() {
+        for template in Template::ALL {
+            let dir = std::env::temp_dir().join(format!(
+                "aurio-template-{}-{}.aurio",
+                template,
+                std::process::id()
+            ));
+            Project::from_template(template, "Template")
+                .save(&dir)
+                .unwrap();
+            let diagnostics = crate::check::check_project(&dir);
+            std::fs::remove_dir_all(&dir).unwrap();
+
+            assert_eq!(diagnostics, vec![], "{} template", template);
+            assert_eq!(template.name().parse(), Ok(template));
+        }
+    }
+
+    #[test]
+    fn tutorial_round_trips_and_schedules() {
+        let dir = std::env::temp_dir().join(format!("aurio-tutorial-{}", std::process::id()));
+        let project = tutorial("Tutorial");
+        project.save(&dir).unwrap();
+        let loaded = Project::load(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(loaded.tracks.len(), 3);
+
+        let runtime = LuaRuntime::new().unwrap();
+        let variables = VariableStore::new();
+        for (track_id, track) in loaded.tracks.iter().enumerate() {
+            assert!(track.graph.get_node(&track.initial_node).is_some());
+            for edge in &track.graph.edges {
+                assert!(track.graph.get_node(&edge.from).is_some());
+                assert!(track.graph.get_node(&edge.to).is_some());
+            }
+
+            for node in &track.graph.nodes {
+                let context = PatternContext {
+                    track_id,
+                    node_id: &node.id,
+                    start_sample: 0,
+                    bpm: loaded.bpm,
+                    sample_rate: loaded.sample_rate as f32,
+                    time_signature: node.sequence.time_signature(),
+                    loop_count: 0,
+                    repeat: node.repeat,
+                    seed: loaded.seed,
+                    pattern_seed: 0,
+                    variables: &variables,
+                };
+                let (mut producer, mut consumer) = HeapRb::new(256).split();
+                schedule_sequence_events(
+                    &node.sequence,
+                    &context,
+                    &mut producer,
+                    Some(&runtime),
+                    Duration::ZERO,
+                )
+                .unwrap();
+                assert!(
+                    consumer.try_pop().is_some(),
+                    "{} produced no events",
+                    node.id
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use super::{StateGraph, TransitionTiming};
+use crate::scripting::{LuaValue, ScriptAction, VariableScope};
+use serde::{Deserialize, Serialize};
+
+/// The song's sections, above the tracks: a state graph whose nodes are
+/// sections, each as long as its sequence, moving on along its edges the way
+/// a track does. When a section starts its cues are carried out, so the whole
+/// arrangement can be changed from one place.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Arrangement {
+    pub initial_section: String,
+    pub graph: StateGraph,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cues: Vec<Cue>,
+}
+
+/// Something done to the tracks when a section starts.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Cue {
+    /// Sends a track to one of its nodes at its next sequence boundary.
+    Transition {
+        section: String,
+        track_id: usize,
+        node_id: String,
+    },
+    /// Sets a global script variable.
+    SetVariable {
+        section: String,
+        name: String,
+        value: f64,
+    },
+}
+
+impl Cue {
+    pub fn section(&self) -> &str {
+        match self {
+            Cue::Transition { section, .. } | Cue::SetVariable { section, .. } => section,
+        }
+    }
+}
+
+impl Arrangement {
+    /// What the cues of `section` ask of the timing thread, in order.
+    pub fn actions(&self, section: &str) -> Vec<ScriptAction> {
+        self.cues
+            .iter()
+            .filter(|cue| cue.section() == section)
+            .map(|cue| match cue {
+                Cue::Transition {
+                    track_id, node_id, ..
+                } => ScriptAction::TriggerTransition {
+                    track_id: *track_id,
+                    node_id: node_id.clone(),
+                    timing: TransitionTiming::FinishSequence,
+                },
+                Cue::SetVariable { name, value, .. } => ScriptAction::SetVariable {
+                    scope: VariableScope::Global,
+                    name: name.clone(),
+                    value: LuaValue::Number(*value),
+                },
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_section_carries_out_its_own_cues() {
+        let arrangement = Arrangement {
+            initial_section: "intro".to_string(),
+            graph: StateGraph::new(),
+            cues: vec![
+                Cue::SetVariable {
+                    section: "drop".to_string(),
+                    name: "energy".to_string(),
+                    value: 1.0,
+                },
+                Cue::Transition {
+                    section: "intro".to_string(),
+                    track_id: 0,
+                    node_id: "quiet".to_string(),
+                },
+                Cue::Transition {
+                    section: "drop".to_string(),
+                    track_id: 1,
+                    node_id: "full".to_string(),
+                },
+            ],
+        };
+        assert_eq!(
+            arrangement.actions("drop"),
+            vec![
+                ScriptAction::SetVariable {
+                    scope: VariableScope::Global,
+                    name: "energy".to_string(),
+                    value: LuaValue::Number(1.0),
+                },
+                ScriptAction::TriggerTransition {
+                    track_id: 1,
+                    node_id: "full".to_string(),
+                    timing: TransitionTiming::FinishSequence,
+                },
+            ]
+        );
+        assert!(arrangement.actions("outro").is_empty());
+    }
+}
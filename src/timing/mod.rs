@@ -1,7 +1,16 @@
+mod arrangement;
+mod prefetch;
 mod scheduler;
 mod sequence;
 mod state_machine;
 
-pub use scheduler::{schedule_sequence_events, EventProducer, SchedulerError};
-pub use sequence::{GeneratedPattern, Note, Sequence, StaticPattern};
-pub use state_machine::{Edge, Hook, Node, StateGraph, TransitionTiming};
+pub use arrangement::{Arrangement, Cue};
+pub use prefetch::{PatternPrefetcher, Prefetched};
+pub use scheduler::{
+    EventProducer, SchedulerError, push_events, schedule_pattern_events, schedule_sequence_events,
+};
+pub use sequence::{
+    Articulation, AudioClip, AutomationPoint, ClipPattern, FilterParam, GeneratedPattern, Note,
+    ParamName, ParamTarget, PatternEvents, Sequence, StaticPattern,
+};
+pub use state_machine::{Edge, Hook, Node, StateGraph, TransitionTiming, pick_weighted};
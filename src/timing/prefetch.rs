@@ -0,0 +1,291 @@
+use super::{GeneratedPattern, PatternEvents, SchedulerError, Sequence};
+use crate::scripting::{
+    LuaRuntime, LuaValue, PatternContext, SCRIPT_TIME_BUDGET, ScriptAction, VariableStore,
+};
+use crossbeam::channel::{Receiver, Sender};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+/// A pattern run ahead of time: its notes and automation and the actions it
+/// asked for, to be carried out when they are used.
+pub struct Prefetched {
+    pub events: Result<PatternEvents, SchedulerError>,
+    pub actions: Vec<ScriptAction>,
+}
+
+struct PrefetchRequest {
+    key: u64,
+    track_id: usize,
+    node_id: String,
+    pattern: GeneratedPattern,
+    start_sample: u64,
+    bpm: f32,
+    sample_rate: f32,
+    loop_count: u64,
+    repeat: Option<u32>,
+    seed: u64,
+    variables: VariableStore,
+}
+
+struct CachedPattern {
+    track_id: usize,
+    start_sample: u64,
+    result: Prefetched,
+}
+
+#[derive(Default)]
+struct Cache {
+    patterns: HashMap<u64, CachedPattern>,
+    /// Keys sent to the worker that haven't come back yet.
+    in_flight: HashSet<u64>,
+}
+
+/// Runs generated patterns on a worker thread before the timing thread needs
+/// them, so a transition only has to look their notes up.
+///
+/// Results are keyed by everything a pattern can see: its code, the node, the
+/// start sample, loop count, seeds and the variables in `ctx`. A pattern that
+/// would run with anything different, say because a hook changed a variable
+/// in between, misses and is run as usual. Patterns run on the worker's own
+/// interpreter, so state has to be kept in variables rather than Lua globals.
+pub struct PatternPrefetcher {
+    request_tx: Sender<PrefetchRequest>,
+    result_rx: Receiver<(u64, CachedPattern)>,
+    cache: RefCell<Cache>,
+}
+
+impl PatternPrefetcher {
+    /// Starts the worker. It stops once the prefetcher is dropped.
+    pub fn spawn() -> Self {
+        let (request_tx, request_rx) = crossbeam::channel::unbounded();
+        let (result_tx, result_rx) = crossbeam::channel::unbounded();
+        std::thread::spawn(move || prefetch_worker(request_rx, result_tx));
+        Self {
+            request_tx,
+            result_rx,
+            cache: RefCell::new(Cache::default()),
+        }
+    }
+
+    /// Has the worker run `pattern` for `context`, unless it already has.
+    pub fn request(&self, pattern: &GeneratedPattern, context: &PatternContext) {
+        let key = pattern_key(pattern, context);
+        let mut cache = self.cache.borrow_mut();
+        if cache.patterns.contains_key(&key) || cache.in_flight.contains(&key) {
+            return;
+        }
+        let request = PrefetchRequest {
+            key,
+            track_id: context.track_id,
+            node_id: context.node_id.to_string(),
+            pattern: pattern.clone(),
+            start_sample: context.start_sample,
+            bpm: context.bpm,
+            sample_rate: context.sample_rate,
+            loop_count: context.loop_count,
+            repeat: context.repeat,
+            seed: context.seed,
+            variables: context.variables.clone(),
+        };
+        if self.request_tx.send(request).is_ok() {
+            cache.in_flight.insert(key);
+        }
+    }
+
+    /// The result of running `pattern` for `context`, if it was requested.
+    /// One still running is waited for, for no longer than the pattern may
+    /// take. Results for the track up to this point are dropped, as they were
+    /// for nodes it didn't go to.
+    pub fn take(&self, pattern: &GeneratedPattern, context: &PatternContext) -> Option<Prefetched> {
+        let key = pattern_key(pattern, context);
+        let mut cache = self.cache.borrow_mut();
+        for (done, cached) in self.result_rx.try_iter() {
+            cache.in_flight.remove(&done);
+            cache.patterns.insert(done, cached);
+        }
+        if cache.in_flight.contains(&key) {
+            let deadline = std::time::Instant::now() + SCRIPT_TIME_BUDGET;
+            while let Ok((done, cached)) = self.result_rx.recv_deadline(deadline) {
+                cache.in_flight.remove(&done);
+                cache.patterns.insert(done, cached);
+                if done == key {
+                    break;
+                }
+            }
+        }
+
+        let taken = cache.patterns.remove(&key);
+        cache.patterns.retain(|_, cached| {
+            cached.track_id != context.track_id || cached.start_sample > context.start_sample
+        });
+        taken.map(|cached| cached.result)
+    }
+}
+
+fn prefetch_worker(requests: Receiver<PrefetchRequest>, results: Sender<(u64, CachedPattern)>) {
+    let lua_runtime = match LuaRuntime::new() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            tracing::warn!("Pattern prefetching is off: {}", e);
+            return;
+        }
+    };
+    for request in requests {
+        let context = PatternContext {
+            track_id: request.track_id,
+            node_id: &request.node_id,
+            start_sample: request.start_sample,
+            bpm: request.bpm,
+            sample_rate: request.sample_rate,
+            time_signature: request.pattern.time_signature,
+            loop_count: request.loop_count,
+            repeat: request.repeat,
+            seed: request.seed,
+            pattern_seed: request.pattern.seed,
+            variables: &request.variables,
+        };
+        let events = Sequence::Generated(request.pattern.clone())
+            .get_events(Some(&lua_runtime), &context)
+            .map_err(SchedulerError::from);
+        let cached = CachedPattern {
+            track_id: request.track_id,
+            start_sample: request.start_sample,
+            result: Prefetched {
+                events,
+                actions: lua_runtime.drain_actions().collect(),
+            },
+        };
+        if results.send((request.key, cached)).is_err() {
+            break;
+        }
+    }
+}
+
+/// Hash of everything that goes into a run of `pattern`.
+fn pattern_key(pattern: &GeneratedPattern, context: &PatternContext) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    pattern.function.hash(&mut hasher);
+    pattern.file.hash(&mut hasher);
+    pattern.duration_bars.hash(&mut hasher);
+    pattern.time_signature.hash(&mut hasher);
+    pattern.seed.hash(&mut hasher);
+
+    context.track_id.hash(&mut hasher);
+    context.node_id.hash(&mut hasher);
+    context.start_sample.hash(&mut hasher);
+    context.bpm.to_bits().hash(&mut hasher);
+    context.sample_rate.to_bits().hash(&mut hasher);
+    context.loop_count.hash(&mut hasher);
+    context.repeat.hash(&mut hasher);
+    context.seed.hash(&mut hasher);
+
+    let variables = context.variables;
+    for scope in [
+        sorted(variables.node_vars(context.track_id, context.node_id)),
+        sorted(variables.track_vars(context.track_id)),
+        sorted(variables.globals()),
+    ] {
+        scope.len().hash(&mut hasher);
+        for (name, value) in scope {
+            name.hash(&mut hasher);
+            match value {
+                LuaValue::Number(n) => n.to_bits().hash(&mut hasher),
+                LuaValue::Boolean(b) => b.hash(&mut hasher),
+                LuaValue::String(s) => s.hash(&mut hasher),
+                LuaValue::Nil => {}
+            }
+            std::mem::discriminant(value).hash(&mut hasher);
+        }
+    }
+    hasher.finish()
+}
+
+fn sorted<'a>(
+    variables: impl Iterator<Item = (&'a str, &'a LuaValue)>,
+) -> Vec<(&'a str, &'a LuaValue)> {
+    let mut variables: Vec<_> = variables.collect();
+    variables.sort_by_key(|(name, _)| *name);
+    variables
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scripting::VariableScope;
+
+    const CODE: &str = r#"
+        local ctx = ...
+        set_var("seen", ctx.loop)
+        return { { pitch = 60 + ctx.global.shift, velocity = math.random(1, 127),
+                   start_beat = ctx.loop, duration_beats = 1 } }
+    "#;
+
+    fn pattern() -> GeneratedPattern {
+        GeneratedPattern {
+            duration_bars: 1,
+            time_signature: (4, 4),
+            function: CODE.to_string(),
+            file: None,
+            seed: 7,
+        }
+    }
+
+    fn context(variables: &VariableStore, loop_count: u64) -> PatternContext<'_> {
+        PatternContext {
+            track_id: 0,
+            node_id: "verse",
+            start_sample: 96_000,
+            bpm: 120.0,
+            sample_rate: 48_000.0,
+            time_signature: (4, 4),
+            loop_count,
+            repeat: None,
+            seed: 1,
+            pattern_seed: 7,
+            variables,
+        }
+    }
+
+    #[test]
+    fn prefetched_patterns_match_running_them_in_place() {
+        let mut variables = VariableStore::new();
+        variables.set_global("shift", LuaValue::Number(2.0));
+        let prefetcher = PatternPrefetcher::spawn();
+        prefetcher.request(&pattern(), &context(&variables, 3));
+
+        let lua_runtime = LuaRuntime::new().unwrap();
+        let expected = lua_runtime
+            .execute_pattern(CODE, &context(&variables, 3))
+            .unwrap();
+        let prefetched = prefetcher
+            .take(&pattern(), &context(&variables, 3))
+            .unwrap();
+        assert_eq!(prefetched.events.unwrap(), expected);
+        assert_eq!(
+            prefetched.actions,
+            lua_runtime.drain_actions().collect::<Vec<_>>()
+        );
+        assert!(matches!(
+            &prefetched.actions[..],
+            [ScriptAction::SetVariable {
+                scope: VariableScope::Node { .. },
+                ..
+            }]
+        ));
+
+        // Taken results are gone, and a variable changed since misses.
+        assert!(
+            prefetcher
+                .take(&pattern(), &context(&variables, 3))
+                .is_none()
+        );
+        prefetcher.request(&pattern(), &context(&variables, 4));
+        variables.set_global("shift", LuaValue::Number(5.0));
+        assert!(
+            prefetcher
+                .take(&pattern(), &context(&variables, 4))
+                .is_none()
+        );
+    }
+}
@@ -1,47 +1,72 @@
-use super::Sequence;
-use crate::events::{Event, ScheduledEvent};
+use super::{PatternEvents, Sequence};
+use crate::events::{ClipEvent, Event, NoteExpression, ScheduledEvent};
+use crate::scripting::{PatternContext, ScriptTimeout};
 use ringbuf::traits::Producer;
+use std::time::{Duration, Instant};
 
 pub type EventProducer = ringbuf::HeapProd<ScheduledEvent>;
 
+/// Queues the events of a sequence starting at `context.start_sample`. When
+/// the ring is full, waits up to `wait` for the renderer to make room before
+/// dropping what doesn't fit.
 pub fn schedule_sequence_events(
     sequence: &Sequence,
-    track_id: usize,
-    start_sample: u64,
-    bpm: f32,
-    sample_rate: f32,
+    context: &PatternContext,
     producer: &mut EventProducer,
     lua_runtime: Option<&crate::scripting::LuaRuntime>,
+    wait: Duration,
 ) -> Result<(), SchedulerError> {
-    let notes = match sequence {
-        Sequence::Static(pattern) => pattern.notes.clone(),
-        Sequence::Generated(_pattern) => sequence.get_notes(lua_runtime),
-    };
+    let pattern_events = sequence.get_events(lua_runtime, context)?;
+    schedule_pattern_events(sequence, pattern_events, context, producer, wait)
+}
 
-    let samples_per_beat = (60.0 / bpm) * sample_rate;
-    let sequence_duration = sequence.duration_samples(bpm, sample_rate) as u64;
+/// Like [`schedule_sequence_events`], with the notes and automation of the
+/// sequence already worked out, e.g. by a
+/// [`PatternPrefetcher`](super::PatternPrefetcher).
+pub fn schedule_pattern_events(
+    sequence: &Sequence,
+    pattern_events: PatternEvents,
+    context: &PatternContext,
+    producer: &mut EventProducer,
+    wait: Duration,
+) -> Result<(), SchedulerError> {
+    let track_id = context.track_id;
+    let start_sample = context.start_sample;
+    let bpm = context.bpm;
+    let sample_rate = context.sample_rate;
 
-    let mut events: Vec<ScheduledEvent> = Vec::with_capacity(notes.len() * 2);
+    let samples_per_beat = context.samples_per_beat();
+    let sequence_end = start_sample.saturating_add(sequence.duration_samples(bpm, sample_rate));
+
+    let PatternEvents { notes, automation } = pattern_events;
+    let mut events: Vec<ScheduledEvent> = Vec::with_capacity(notes.len() * 2 + automation.len());
 
     for note in notes {
-        let note_on_sample = start_sample + (note.start_beat * samples_per_beat) as u64;
+        let note_on_sample = beat_to_sample(start_sample, note.start_beat, samples_per_beat);
 
-        if note_on_sample < start_sample + sequence_duration {
+        if note_on_sample < sequence_end {
             events.push(ScheduledEvent {
                 sample_timestamp: note_on_sample,
                 event: Event::MidiEvent {
                     track_id,
                     pitch: note.pitch,
-                    velocity: note.velocity,
+                    velocity: note.played_velocity(),
                     is_note_on: true,
+                    expression: NoteExpression {
+                        pan: note.pan.unwrap_or(0.0),
+                        detune_cents: note.detune_cents.unwrap_or(0.0),
+                    },
                 },
             });
         }
 
-        let note_off_sample =
-            start_sample + ((note.start_beat + note.duration_beats) * samples_per_beat) as u64;
+        let note_off_sample = beat_to_sample(
+            start_sample,
+            note.start_beat + note.gate_beats(),
+            samples_per_beat,
+        );
 
-        if note_off_sample <= start_sample + sequence_duration {
+        if note_off_sample <= sequence_end {
             events.push(ScheduledEvent {
                 sample_timestamp: note_off_sample,
                 event: Event::MidiEvent {
@@ -49,32 +74,387 @@ pub fn schedule_sequence_events(
                     pitch: note.pitch,
                     velocity: note.velocity,
                     is_note_on: false,
+                    expression: NoteExpression::default(),
+                },
+            });
+        }
+    }
+
+    for point in automation {
+        let sample = beat_to_sample(start_sample, point.beat, samples_per_beat);
+        if sample < sequence_end {
+            events.push(ScheduledEvent {
+                sample_timestamp: sample,
+                event: Event::ParamChange {
+                    track_id,
+                    target: point.target,
+                    value: point.target.clamp(point.value),
+                },
+            });
+        }
+    }
+
+    let frames = |seconds: f32| (seconds.max(0.0) as f64 * sample_rate as f64) as u64;
+    for (offset, part) in sequence.parts() {
+        let Sequence::Clips(pattern) = part else {
+            continue;
+        };
+        // Clips are cut at the end of their part of a chain.
+        let part_start = start_sample.saturating_add((offset * samples_per_beat) as u64);
+        let part_end = start_sample
+            .saturating_add(((offset + part.duration_quarters()) * samples_per_beat) as u64)
+            .min(sequence_end);
+        for clip in &pattern.clips {
+            let start = beat_to_sample(part_start, clip.start_beat, samples_per_beat);
+            if start >= part_end {
+                continue;
+            }
+            let end = clip.duration_beats.map_or(part_end, |duration| {
+                beat_to_sample(part_start, clip.start_beat + duration, samples_per_beat)
+                    .min(part_end)
+            });
+            events.push(ScheduledEvent {
+                sample_timestamp: start,
+                event: Event::ClipStart {
+                    track_id,
+                    clip: ClipEvent {
+                        sample_id: clip.sample_id.clone(),
+                        offset: clip.offset.max(0.0) as f64,
+                        length: end.saturating_sub(start),
+                        fade_in: frames(clip.fade_in),
+                        fade_out: frames(clip.fade_out),
+                        gain: clip.gain,
+                    },
                 },
             });
         }
     }
 
     events.sort_by_key(|e| e.sample_timestamp);
-    for event in events {
-        if producer.try_push(event).is_err() {
-            return Err(SchedulerError::BufferFull);
+    match push_events(producer, events, wait) {
+        0 => Ok(()),
+        dropped => Err(SchedulerError::BufferFull { dropped }),
+    }
+}
+
+/// Pushes events in order, waiting up to `wait` in all for room in a full
+/// ring. Returns how many were dropped. Only for the non-realtime side.
+pub fn push_events(
+    producer: &mut EventProducer,
+    events: impl IntoIterator<Item = ScheduledEvent>,
+    wait: Duration,
+) -> usize {
+    let deadline = Instant::now() + wait;
+    let mut dropped = 0;
+    for mut event in events {
+        loop {
+            match producer.try_push(event) {
+                Ok(()) => break,
+                Err(rejected) if Instant::now() < deadline => {
+                    event = rejected;
+                    std::thread::sleep(Duration::from_micros(500));
+                }
+                Err(_) => {
+                    dropped += 1;
+                    break;
+                }
+            }
         }
     }
+    dropped
+}
 
-    Ok(())
+/// Converts a beat offset within a sequence to an absolute sample position.
+///
+/// The offset is computed in `f64` so precision doesn't depend on how long the
+/// engine has been running, and the addition saturates instead of wrapping.
+fn beat_to_sample(start_sample: u64, beat: f32, samples_per_beat: f64) -> u64 {
+    let offset = (beat as f64 * samples_per_beat).max(0.0) as u64;
+    start_sample.saturating_add(offset)
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub enum SchedulerError {
-    BufferFull,
+    /// The event ring stayed full and this many events didn't make it in.
+    BufferFull { dropped: usize },
+    /// A generated pattern raised an error.
+    Script(mlua::Error),
+    /// A generated pattern ran past its time budget and was aborted.
+    ScriptTimeout,
+}
+
+impl From<mlua::Error> for SchedulerError {
+    fn from(e: mlua::Error) -> Self {
+        match e.downcast_ref::<ScriptTimeout>() {
+            Some(_) => SchedulerError::ScriptTimeout,
+            None => SchedulerError::Script(e),
+        }
+    }
 }
 
 impl std::fmt::Display for SchedulerError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            SchedulerError::BufferFull => write!(f, "Event buffer is full"),
+            SchedulerError::BufferFull { dropped } => {
+                write!(f, "Event buffer is full, dropped {} events", dropped)
+            }
+            SchedulerError::Script(e) => write!(f, "Lua error: {}", e),
+            SchedulerError::ScriptTimeout => write!(f, "Lua pattern timed out"),
         }
     }
 }
 
 impl std::error::Error for SchedulerError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scripting::VariableStore;
+    use crate::timing::{
+        Articulation, AudioClip, AutomationPoint, ClipPattern, Note, ParamTarget, StaticPattern,
+    };
+    use ringbuf::{HeapRb, traits::Consumer, traits::Split};
+
+    fn context(variables: &VariableStore, start_sample: u64) -> PatternContext<'_> {
+        PatternContext {
+            track_id: 0,
+            node_id: "a",
+            start_sample,
+            bpm: 120.0,
+            sample_rate: 48_000.0,
+            time_signature: (4, 4),
+            loop_count: 0,
+            repeat: None,
+            seed: 0,
+            pattern_seed: 0,
+            variables,
+        }
+    }
+
+    fn one_bar_pattern() -> Sequence {
+        Sequence::Static(StaticPattern {
+            duration_bars: 1,
+            time_signature: (4, 4),
+            notes: vec![Note::new(60, 100, 3.0, 1.0)],
+            automation: Vec::new(),
+        })
+    }
+
+    #[test]
+    fn schedules_beyond_u32_range() {
+        // Roughly four weeks of continuous playback at 48kHz.
+        let start_sample = 48_000u64 * 60 * 60 * 24 * 28;
+        assert!(start_sample > u32::MAX as u64);
+
+        let (mut producer, mut consumer) = HeapRb::<ScheduledEvent>::new(16).split();
+        let variables = VariableStore::new();
+        schedule_sequence_events(
+            &one_bar_pattern(),
+            &context(&variables, start_sample),
+            &mut producer,
+            None,
+            Duration::ZERO,
+        )
+        .unwrap();
+
+        let on = consumer.try_pop().unwrap();
+        let off = consumer.try_pop().unwrap();
+        assert_eq!(on.sample_timestamp, start_sample + 72_000);
+        assert_eq!(off.sample_timestamp, start_sample + 96_000);
+    }
+
+    #[test]
+    fn articulation_and_expression_reach_the_events() {
+        let mut note = Note::new(60, 100, 0.0, 2.0);
+        note.articulation = Some(Articulation::Staccato);
+        note.pan = Some(0.25);
+        let mut accented = Note::new(64, 120, 2.0, 1.0);
+        accented.articulation = Some(Articulation::Accent);
+        let sequence = Sequence::Static(StaticPattern {
+            duration_bars: 1,
+            time_signature: (4, 4),
+            notes: vec![note, accented],
+            automation: Vec::new(),
+        });
+
+        let (mut producer, mut consumer) = HeapRb::<ScheduledEvent>::new(16).split();
+        let variables = VariableStore::new();
+        schedule_sequence_events(
+            &sequence,
+            &context(&variables, 0),
+            &mut producer,
+            None,
+            Duration::ZERO,
+        )
+        .unwrap();
+
+        let events: Vec<_> = std::iter::from_fn(|| consumer.try_pop())
+            .map(|e| match e.event {
+                Event::MidiEvent {
+                    pitch,
+                    velocity,
+                    is_note_on,
+                    expression,
+                    ..
+                } => (
+                    e.sample_timestamp,
+                    pitch,
+                    velocity,
+                    is_note_on,
+                    expression.pan,
+                ),
+                other => panic!("unexpected {:?}", other),
+            })
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                (0, 60, 100, true, 0.25),
+                (24_000, 60, 100, false, 0.0),
+                (48_000, 64, 127, true, 0.0),
+                (72_000, 64, 120, false, 0.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn saturates_instead_of_wrapping() {
+        let start_sample = u64::MAX - 10;
+
+        let (mut producer, mut consumer) = HeapRb::<ScheduledEvent>::new(16).split();
+        let variables = VariableStore::new();
+        schedule_sequence_events(
+            &one_bar_pattern(),
+            &context(&variables, start_sample),
+            &mut producer,
+            None,
+            Duration::ZERO,
+        )
+        .unwrap();
+
+        while let Some(event) = consumer.try_pop() {
+            assert!(event.sample_timestamp >= start_sample);
+        }
+    }
+
+    #[test]
+    fn a_full_ring_reports_the_dropped_events() {
+        let sequence = Sequence::Static(StaticPattern {
+            duration_bars: 4,
+            time_signature: (4, 4),
+            notes: (0..10).map(|i| Note::new(60, 100, i as f32, 1.0)).collect(),
+            automation: Vec::new(),
+        });
+
+        let (mut producer, _consumer) = HeapRb::<ScheduledEvent>::new(16).split();
+        let variables = VariableStore::new();
+        let result = schedule_sequence_events(
+            &sequence,
+            &context(&variables, 0),
+            &mut producer,
+            None,
+            Duration::from_millis(2),
+        );
+        assert!(matches!(
+            result,
+            Err(SchedulerError::BufferFull { dropped: 4 })
+        ));
+    }
+
+    #[test]
+    fn duration_is_exact_for_long_sequences() {
+        let sequence = Sequence::Static(StaticPattern {
+            duration_bars: 100_000,
+            time_signature: (4, 4),
+            notes: vec![],
+            automation: Vec::new(),
+        });
+
+        assert_eq!(
+            sequence.duration_samples(120.0, 48_000.0),
+            100_000 * 4 * 24_000
+        );
+    }
+
+    #[test]
+    fn clips_are_cut_at_the_sequence_end() {
+        let clip = |start_beat, duration_beats| AudioClip {
+            sample_id: "loop".to_string(),
+            start_beat,
+            duration_beats,
+            offset: 0.5,
+            fade_in: 0.01,
+            fade_out: 0.0,
+            gain: 1.0,
+        };
+        let sequence = Sequence::Clips(ClipPattern {
+            duration_bars: 1,
+            time_signature: (4, 4),
+            clips: vec![clip(1.0, Some(1.0)), clip(2.0, None), clip(4.0, None)],
+        });
+
+        let (mut producer, mut consumer) = HeapRb::<ScheduledEvent>::new(16).split();
+        let variables = VariableStore::new();
+        schedule_sequence_events(
+            &sequence,
+            &context(&variables, 0),
+            &mut producer,
+            None,
+            Duration::ZERO,
+        )
+        .unwrap();
+
+        let starts: Vec<_> = std::iter::from_fn(|| consumer.try_pop())
+            .map(|event| match event.event {
+                Event::ClipStart { clip, .. } => (event.sample_timestamp, clip.length),
+                other => panic!("unexpected {:?}", other),
+            })
+            .collect();
+        assert_eq!(starts, vec![(24_000, 24_000), (48_000, 48_000)]);
+    }
+
+    #[test]
+    fn automation_points_become_param_changes() {
+        let point = |target, beat, value| AutomationPoint {
+            target,
+            beat,
+            value,
+        };
+        let sequence = Sequence::Static(StaticPattern {
+            duration_bars: 1,
+            time_signature: (4, 4),
+            notes: vec![],
+            automation: vec![
+                point(ParamTarget::Instrument(5), 0.5, 0.75),
+                point(ParamTarget::Pan, 1.0, 3.0),
+                point(ParamTarget::Volume, 4.0, 0.0),
+            ],
+        });
+
+        let (mut producer, mut consumer) = HeapRb::<ScheduledEvent>::new(16).split();
+        let variables = VariableStore::new();
+        schedule_sequence_events(
+            &sequence,
+            &context(&variables, 0),
+            &mut producer,
+            None,
+            Duration::ZERO,
+        )
+        .unwrap();
+
+        // The pan is clamped, and the point at the very end never plays.
+        let changes: Vec<_> = std::iter::from_fn(|| consumer.try_pop())
+            .map(|event| match event.event {
+                Event::ParamChange { target, value, .. } => (event.sample_timestamp, target, value),
+                other => panic!("unexpected {:?}", other),
+            })
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                (12_000, ParamTarget::Instrument(5), 0.75),
+                (24_000, ParamTarget::Pan, 1.0),
+            ]
+        );
+    }
+}
@@ -1,9 +1,16 @@
+use crate::scripting::PatternContext;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Sequence {
     Static(StaticPattern),
     Generated(GeneratedPattern),
+    /// Audio clips, played by tracks with an
+    /// [`Instrument::Audio`](crate::audio::Instrument::Audio).
+    Clips(ClipPattern),
+    /// Sequences played one after the other. The node ends once the last
+    /// has.
+    Chain(Vec<Sequence>),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -11,54 +18,521 @@ pub struct StaticPattern {
     pub duration_bars: u32,
     pub time_signature: (u32, u32),
     pub notes: Vec<Note>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub automation: Vec<AutomationPoint>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl StaticPattern {
+    pub fn beats_per_bar(&self) -> f32 {
+        self.time_signature.0 as f32
+    }
+
+    /// Inserts a copy of `count` bars starting at `first_bar` right after
+    /// them, pushing later notes back and lengthening the pattern to fit.
+    pub fn duplicate_bars(&mut self, first_bar: u32, count: u32) {
+        let start = first_bar as f32 * self.beats_per_bar();
+        let end = (first_bar + count) as f32 * self.beats_per_bar();
+        let shift = end - start;
+
+        let copies: Vec<Note> = self
+            .notes
+            .iter()
+            .filter(|n| n.start_beat >= start && n.start_beat < end)
+            .map(|n| Note {
+                start_beat: n.start_beat + shift,
+                ..n.clone()
+            })
+            .collect();
+        for note in &mut self.notes {
+            if note.start_beat >= end {
+                note.start_beat += shift;
+            }
+        }
+        self.notes.extend(copies);
+
+        let copies: Vec<AutomationPoint> = self
+            .automation
+            .iter()
+            .filter(|p| p.beat >= start && p.beat < end)
+            .map(|p| AutomationPoint {
+                beat: p.beat + shift,
+                ..*p
+            })
+            .collect();
+        for point in &mut self.automation {
+            if point.beat >= end {
+                point.beat += shift;
+            }
+        }
+        self.automation.extend(copies);
+        self.duration_bars += count;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Note {
     pub pitch: u8,
     pub velocity: u8,
     pub start_beat: f32,
     pub duration_beats: f32,
+    /// Moves the note from the track's pan, -1 (left) to 1 (right).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pan: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detune_cents: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub articulation: Option<Articulation>,
+}
+
+impl Note {
+    /// A note played as written, with no expression.
+    pub fn new(pitch: u8, velocity: u8, start_beat: f32, duration_beats: f32) -> Self {
+        Self {
+            pitch,
+            velocity,
+            start_beat,
+            duration_beats,
+            pan: None,
+            detune_cents: None,
+            articulation: None,
+        }
+    }
+
+    /// Beats the note is held for, after its articulation.
+    pub fn gate_beats(&self) -> f32 {
+        self.duration_beats * self.articulation.map_or(1.0, Articulation::gate)
+    }
+
+    /// Velocity the note is played at, after its articulation.
+    pub fn played_velocity(&self) -> u8 {
+        match self.articulation {
+            Some(Articulation::Accent) => (self.velocity as f32 * ACCENT).min(127.0) as u8,
+            _ => self.velocity,
+        }
+    }
+}
+
+/// A parameter set to `value` at `beat`, e.g. one step of a filter sweep.
+/// The value holds until the next point for the same target.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct AutomationPoint {
+    pub target: ParamTarget,
+    pub beat: f32,
+    pub value: f32,
+}
+
+/// What an automation point moves on the track it plays on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ParamTarget {
+    Volume,
+    Pan,
+    /// The track's built-in lowpass filter, left out of its signal until a
+    /// point first sets it.
+    Filter(FilterParam),
+    /// A parameter of a node of the patch editor's patch, by the node's id.
+    Graph {
+        node: u32,
+        param: ParamName,
+    },
+    /// A parameter of the track's instrument plugin, by its CLAP id.
+    Instrument(u32),
+    /// A parameter of one of the track's effect plugins, by its CLAP id.
+    Effect {
+        index: usize,
+        id: u32,
+    },
+}
+
+/// What a [`ParamTarget::Filter`] point sets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FilterParam {
+    /// In Hz.
+    Cutoff,
+    /// The filter's Q.
+    Resonance,
+}
+
+impl ParamTarget {
+    /// Limits `value` to what the target accepts. Plugins and patches get
+    /// their own range, so only the track's controls are clamped.
+    pub fn clamp(self, value: f32) -> f32 {
+        match self {
+            ParamTarget::Volume => value.max(0.0),
+            ParamTarget::Pan => value.clamp(-1.0, 1.0),
+            ParamTarget::Filter(FilterParam::Cutoff) => value.clamp(20.0, 20_000.0),
+            ParamTarget::Filter(FilterParam::Resonance) => value.clamp(0.1, 20.0),
+            ParamTarget::Graph { .. } | ParamTarget::Instrument(_) | ParamTarget::Effect { .. } => {
+                value
+            }
+        }
+    }
+
+    pub fn label(self) -> String {
+        match self {
+            ParamTarget::Volume => "Volume".to_string(),
+            ParamTarget::Pan => "Pan".to_string(),
+            ParamTarget::Filter(FilterParam::Cutoff) => "Filter cutoff".to_string(),
+            ParamTarget::Filter(FilterParam::Resonance) => "Filter resonance".to_string(),
+            ParamTarget::Graph { node, param } => format!("Patch [{}].{}", node, param.as_str()),
+            ParamTarget::Instrument(id) => format!("Instrument #{}", id),
+            ParamTarget::Effect { index, id } => format!("Effect {} #{}", index + 1, id),
+        }
+    }
+}
+
+/// The name of a patch node's parameter. Names are interned and never
+/// freed, so targets stay `Copy` and the audio thread sets them without
+/// allocating; a patch only has so many.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ParamName(&'static str);
+
+impl ParamName {
+    pub fn new(name: &str) -> Self {
+        static NAMES: parking_lot::Mutex<Vec<&'static str>> = parking_lot::Mutex::new(Vec::new());
+        let mut names = NAMES.lock();
+        if let Some(&interned) = names.iter().find(|&&interned| interned == name) {
+            return Self(interned);
+        }
+        let interned: &'static str = Box::leak(name.into());
+        names.push(interned);
+        Self(interned)
+    }
+
+    pub fn as_str(self) -> &'static str {
+        self.0
+    }
+}
+
+impl Serialize for ParamName {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for ParamName {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(|name| Self::new(&name))
+    }
+}
+
+/// What a sequence plays on one run.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PatternEvents {
+    pub notes: Vec<Note>,
+    pub automation: Vec<AutomationPoint>,
+}
+
+/// How much louder an accented note is played.
+const ACCENT: f32 = 1.25;
+
+/// How a note is played beyond its pitch and velocity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Articulation {
+    /// Held for half its length.
+    Staccato,
+    /// Held for a quarter of its length.
+    Staccatissimo,
+    /// Played a quarter louder.
+    Accent,
+}
+
+impl Articulation {
+    pub const ALL: [Articulation; 3] = [
+        Articulation::Staccato,
+        Articulation::Staccatissimo,
+        Articulation::Accent,
+    ];
+
+    /// Share of the note's length it's held for.
+    fn gate(self) -> f32 {
+        match self {
+            Articulation::Staccato => 0.5,
+            Articulation::Staccatissimo => 0.25,
+            Articulation::Accent => 1.0,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Articulation::Staccato => "staccato",
+            Articulation::Staccatissimo => "staccatissimo",
+            Articulation::Accent => "accent",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|a| a.name() == name)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GeneratedPattern {
     pub duration_bars: u32,
     pub time_signature: (u32, u32),
+    /// Lua code of the pattern. For file-backed patterns this is filled in from
+    /// `file` when the project is loaded and isn't saved back to the RON.
+    #[serde(default)]
     pub function: String,
+    /// Path of a `.lua` file holding the code, relative to the project directory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    /// Mixed into the project seed for this pattern's RNG; reroll it to get a
+    /// different but still reproducible variation.
+    #[serde(default)]
+    pub seed: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClipPattern {
+    pub duration_bars: u32,
+    pub time_signature: (u32, u32),
+    pub clips: Vec<AudioClip>,
+}
+
+/// Part of a library sample placed on a sequence.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AudioClip {
+    pub sample_id: String,
+    pub start_beat: f32,
+    /// `None` plays to the end of the audio. Clips are always cut at the end
+    /// of the sequence.
+    #[serde(default)]
+    pub duration_beats: Option<f32>,
+    /// Seconds into the audio the clip starts from.
+    #[serde(default)]
+    pub offset: f32,
+    /// Fade lengths in seconds.
+    #[serde(default)]
+    pub fade_in: f32,
+    #[serde(default)]
+    pub fade_out: f32,
+    #[serde(default = "unity_gain")]
+    pub gain: f32,
+}
+
+fn unity_gain() -> f32 {
+    1.0
 }
 
 impl Sequence {
-    pub fn duration_samples(&self, bpm: f32, sample_rate: f32) -> usize {
+    pub fn duration_samples(&self, bpm: f32, sample_rate: f32) -> u64 {
+        let samples_per_quarter = (60.0 / bpm as f64) * sample_rate as f64;
+        (self.duration_quarters() * samples_per_quarter) as u64
+    }
+
+    /// Length in quarter notes, the unit notes are placed in.
+    pub fn duration_quarters(&self) -> f64 {
         let (bars, time_sig) = match self {
             Sequence::Static(p) => (p.duration_bars, p.time_signature),
             Sequence::Generated(p) => (p.duration_bars, p.time_signature),
+            Sequence::Clips(p) => (p.duration_bars, p.time_signature),
+            Sequence::Chain(parts) => return parts.iter().map(Sequence::duration_quarters).sum(),
         };
 
-        let beats_per_bar = time_sig.0 as f32;
-        let beat_unit = time_sig.1 as f32;
+        let beats_per_bar = time_sig.0 as f64;
+        let beat_unit = time_sig.1 as f64;
+        (beats_per_bar * bars as f64) * (4.0 / beat_unit)
+    }
+
+    /// The patterns the sequence plays, each with the quarter note it starts
+    /// on: chains give their parts, anything else itself at 0.
+    pub fn parts(&self) -> Vec<(f64, &Sequence)> {
+        let Sequence::Chain(chain) = self else {
+            return vec![(0.0, self)];
+        };
+        let mut parts = Vec::new();
+        let mut start = 0.0;
+        for sequence in chain {
+            parts.extend(
+                sequence
+                    .parts()
+                    .into_iter()
+                    .map(|(offset, part)| (start + offset, part)),
+            );
+            start += sequence.duration_quarters();
+        }
+        parts
+    }
 
-        let total_quarter_notes = (beats_per_bar * bars as f32) * (4.0 / beat_unit);
-        let samples_per_quarter = (60.0 / bpm) * sample_rate;
+    /// The patterns the sequence plays, for editing in place.
+    pub fn parts_mut(&mut self) -> Vec<&mut Sequence> {
+        match self {
+            Sequence::Chain(chain) => chain.iter_mut().flat_map(Sequence::parts_mut).collect(),
+            _ => vec![self],
+        }
+    }
 
-        (total_quarter_notes * samples_per_quarter) as usize
+    /// Seed a generated pattern mixes into its RNG; 0 for anything else.
+    pub fn pattern_seed(&self) -> u64 {
+        match self {
+            Sequence::Generated(pattern) => pattern.seed,
+            _ => 0,
+        }
     }
 
-    pub fn get_notes(&self, lua_runtime: Option<&crate::scripting::LuaRuntime>) -> Vec<Note> {
+    pub fn get_events(
+        &self,
+        lua_runtime: Option<&crate::scripting::LuaRuntime>,
+        context: &PatternContext,
+    ) -> Result<PatternEvents, mlua::Error> {
         match self {
-            Sequence::Static(pattern) => pattern.notes.clone(),
-            Sequence::Generated(pattern) => {
-                if let Some(runtime) = lua_runtime {
-                    runtime
-                        .execute_pattern(&pattern.function)
-                        .unwrap_or_else(|e| {
-                            eprintln!("Lua error: {}", e);
-                            Vec::new()
-                        })
-                } else {
-                    Vec::new()
+            Sequence::Static(pattern) => Ok(PatternEvents {
+                notes: pattern.notes.clone(),
+                automation: pattern.automation.clone(),
+            }),
+            Sequence::Generated(pattern) => match lua_runtime {
+                Some(runtime) => match &pattern.file {
+                    Some(file) => runtime.execute_named_pattern(
+                        &format!("@{}", file),
+                        &pattern.function,
+                        context,
+                    ),
+                    None => runtime.execute_pattern(&pattern.function, context),
+                },
+                None => Ok(PatternEvents::default()),
+            },
+            Sequence::Clips(_) => Ok(PatternEvents::default()),
+            Sequence::Chain(_) => {
+                // Each part sees the context of its own start, and what it
+                // plays past its end is cut.
+                let mut events = PatternEvents::default();
+                for (offset, part) in self.parts() {
+                    let length = part.duration_quarters() as f32;
+                    let start_sample = (offset * context.samples_per_beat()) as u64;
+                    let context = PatternContext {
+                        start_sample: context.start_sample.saturating_add(start_sample),
+                        time_signature: part.time_signature(),
+                        pattern_seed: part.pattern_seed(),
+                        ..*context
+                    };
+                    let part_events = part.get_events(lua_runtime, &context)?;
+                    events.notes.extend(
+                        part_events
+                            .notes
+                            .into_iter()
+                            .filter(|note| note.start_beat < length)
+                            .map(|note| Note {
+                                start_beat: note.start_beat + offset as f32,
+                                ..note
+                            }),
+                    );
+                    events.automation.extend(
+                        part_events
+                            .automation
+                            .into_iter()
+                            .filter(|point| point.beat < length)
+                            .map(|point| AutomationPoint {
+                                beat: point.beat + offset as f32,
+                                ..point
+                            }),
+                    );
                 }
+                Ok(events)
             }
         }
     }
+
+    pub fn duration_bars(&self) -> u32 {
+        match self {
+            Sequence::Static(p) => p.duration_bars,
+            Sequence::Generated(p) => p.duration_bars,
+            Sequence::Clips(p) => p.duration_bars,
+            Sequence::Chain(parts) => parts.iter().map(Sequence::duration_bars).sum(),
+        }
+    }
+
+    /// A chain's is its first part's.
+    pub fn time_signature(&self) -> (u32, u32) {
+        match self {
+            Sequence::Static(p) => p.time_signature,
+            Sequence::Generated(p) => p.time_signature,
+            Sequence::Clips(p) => p.time_signature,
+            Sequence::Chain(parts) => parts.first().map_or((4, 4), Sequence::time_signature),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(pitch: u8, start_beat: f32) -> Note {
+        Note::new(pitch, 100, start_beat, 1.0)
+    }
+
+    #[test]
+    fn duplicated_bars_push_the_rest_back() {
+        let mut pattern = StaticPattern {
+            duration_bars: 2,
+            time_signature: (4, 4),
+            notes: vec![note(60, 0.0), note(62, 2.5), note(64, 4.0)],
+            automation: vec![AutomationPoint {
+                target: ParamTarget::Instrument(3),
+                beat: 1.0,
+                value: 0.5,
+            }],
+        };
+
+        pattern.duplicate_bars(0, 1);
+
+        assert_eq!(pattern.duration_bars, 3);
+        let mut starts: Vec<(u8, f32)> = pattern
+            .notes
+            .iter()
+            .map(|n| (n.pitch, n.start_beat))
+            .collect();
+        starts.sort_by(|a, b| a.1.total_cmp(&b.1));
+        assert_eq!(
+            starts,
+            vec![(60, 0.0), (62, 2.5), (60, 4.0), (62, 6.5), (64, 8.0)]
+        );
+        let beats: Vec<f32> = pattern.automation.iter().map(|p| p.beat).collect();
+        assert_eq!(beats, vec![1.0, 5.0]);
+    }
+
+    #[test]
+    fn chained_parts_play_back_to_back() {
+        let part = |time_signature, notes| {
+            Sequence::Static(StaticPattern {
+                duration_bars: 1,
+                time_signature,
+                notes,
+                automation: Vec::new(),
+            })
+        };
+        let chain = Sequence::Chain(vec![
+            part((4, 4), vec![note(60, 0.0)]),
+            Sequence::Chain(vec![part((3, 4), vec![note(62, 1.0), note(64, 3.0)])]),
+            part((6, 8), vec![note(65, 2.5)]),
+        ]);
+        assert_eq!(chain.duration_quarters(), 10.0);
+        assert_eq!(chain.duration_samples(60.0, 100.0), 1000);
+        assert_eq!(chain.duration_bars(), 3);
+        assert_eq!(chain.time_signature(), (4, 4));
+
+        let variables = crate::scripting::VariableStore::new();
+        let context = PatternContext {
+            track_id: 0,
+            node_id: "medley",
+            start_sample: 0,
+            bpm: 60.0,
+            sample_rate: 100.0,
+            time_signature: (4, 4),
+            loop_count: 0,
+            repeat: None,
+            seed: 0,
+            pattern_seed: 0,
+            variables: &variables,
+        };
+        // The note past the end of its 3/4 bar is cut.
+        let starts: Vec<(u8, f32)> = chain
+            .get_events(None, &context)
+            .unwrap()
+            .notes
+            .iter()
+            .map(|n| (n.pitch, n.start_beat))
+            .collect();
+        assert_eq!(starts, vec![(60, 0.0), (62, 5.0), (65, 9.5)]);
+    }
 }
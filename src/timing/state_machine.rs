@@ -1,7 +1,7 @@
-use super::Sequence;
+use super::{Sequence, StaticPattern};
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum Hook {
     OnEnter,
     OnLeave,
@@ -10,7 +10,7 @@ pub enum Hook {
     OnLoop,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum TransitionTiming {
     Immediate,
     NextBeat,
@@ -18,11 +18,45 @@ pub enum TransitionTiming {
     FinishSequence,
 }
 
+impl TransitionTiming {
+    /// Sample a transition asked for at `now` takes effect, in `sequence`
+    /// started at `start_sample`: `now` itself, the sequence's next beat or
+    /// bar, or its end. Never later than the end.
+    pub fn boundary(
+        self,
+        sequence: &Sequence,
+        start_sample: u64,
+        now: u64,
+        bpm: f32,
+        sample_rate: f32,
+    ) -> u64 {
+        let end_sample = start_sample.saturating_add(sequence.duration_samples(bpm, sample_rate));
+        let (beats_per_bar, beat_unit) = sequence.time_signature();
+        let samples_per_beat = 60.0 / bpm as f64 * sample_rate as f64 * 4.0 / beat_unit as f64;
+        let step = match self {
+            TransitionTiming::Immediate => return now.min(end_sample),
+            TransitionTiming::NextBeat => samples_per_beat,
+            TransitionTiming::NextBar => samples_per_beat * beats_per_bar as f64,
+            TransitionTiming::FinishSequence => return end_sample,
+        };
+        let elapsed = now.saturating_sub(start_sample) as f64;
+        let offset = ((elapsed / step).ceil() * step) as u64;
+        start_sample.saturating_add(offset).min(end_sample)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Node {
     pub id: String,
     pub sequence: Sequence,
     pub hooks: Vec<(Hook, String)>,
+    /// Times the node plays back to back before its edges are followed.
+    /// Transitions asked for by scripts or controllers don't wait for it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repeat: Option<u32>,
+    /// Where the graph editor draws the node; laid out in a row when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<(f32, f32)>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -32,6 +66,30 @@ pub struct Edge {
     pub condition: String,
     pub timing: TransitionTiming,
     pub inlet_hook: Option<String>,
+    /// How likely the edge is to be taken over the other weighted edges that
+    /// pass at the same time. Edges without one are only taken when no
+    /// weighted edge passes, the first of them first.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub weight: Option<f32>,
+}
+
+/// Picks one of `edges` at random in proportion to their weights, drawn from
+/// `roll`. Edges without a positive weight are never picked.
+pub fn pick_weighted<'a>(edges: &[&'a Edge], roll: u64) -> Option<&'a Edge> {
+    let weight = |edge: &Edge| edge.weight.unwrap_or(0.0).max(0.0) as f64;
+    let total: f64 = edges.iter().map(|edge| weight(edge)).sum();
+    if total <= 0.0 {
+        return None;
+    }
+    // The top 53 bits make an evenly spread fraction.
+    let mut point = (roll >> 11) as f64 / (1u64 << 53) as f64 * total;
+    for edge in edges {
+        if weight(edge) > 0.0 && point < weight(edge) {
+            return Some(edge);
+        }
+        point -= weight(edge);
+    }
+    edges.iter().rev().find(|edge| weight(edge) > 0.0).copied()
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -55,4 +113,108 @@ impl StateGraph {
     pub fn get_outgoing_edges(&self, node_id: &str) -> Vec<&Edge> {
         self.edges.iter().filter(|e| e.from == node_id).collect()
     }
+
+    /// Adds a node with an empty one-bar pattern under the first free
+    /// `node N` id, and returns the id.
+    pub fn add_node(&mut self, position: (f32, f32)) -> String {
+        let id = (1..)
+            .map(|n| format!("node {}", n))
+            .find(|id| self.get_node(id).is_none())
+            .unwrap_or_default();
+        self.insert_node(id.clone(), position);
+        id
+    }
+
+    /// Adds a node with an empty one-bar pattern under `id`.
+    pub fn insert_node(&mut self, id: String, position: (f32, f32)) {
+        self.nodes.push(Node {
+            id,
+            sequence: Sequence::Static(StaticPattern {
+                duration_bars: 1,
+                time_signature: (4, 4),
+                notes: Vec::new(),
+                automation: Vec::new(),
+            }),
+            hooks: Vec::new(),
+            repeat: None,
+            position: Some(position),
+        });
+    }
+
+    /// Removes a node along with every edge into or out of it.
+    pub fn remove_node(&mut self, id: &str) {
+        self.nodes.retain(|n| n.id != id);
+        self.edges.retain(|e| e.from != id && e.to != id);
+    }
+}
+
+impl Default for StateGraph {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nodes_get_fresh_ids_and_take_their_edges_along() {
+        let mut graph = StateGraph::new();
+        let a = graph.add_node((0.0, 0.0));
+        let b = graph.add_node((100.0, 0.0));
+        for (from, to) in [(&a, &b), (&b, &a), (&b, &b)] {
+            graph.edges.push(Edge {
+                from: from.clone(),
+                to: to.clone(),
+                condition: "true".to_string(),
+                timing: TransitionTiming::FinishSequence,
+                inlet_hook: None,
+                weight: None,
+            });
+        }
+
+        graph.remove_node(&b);
+        assert_eq!(graph.add_node((0.0, 0.0)), "node 2");
+        assert_eq!((a.as_str(), graph.edges.len()), ("node 1", 0));
+    }
+
+    #[test]
+    fn timed_transitions_land_on_the_next_beat_or_bar() {
+        // Two bars of 3/4 at 60 bpm and 1000 Hz: a beat is 1000 samples.
+        let sequence = Sequence::Static(StaticPattern {
+            duration_bars: 2,
+            time_signature: (3, 4),
+            notes: Vec::new(),
+            automation: Vec::new(),
+        });
+        let boundary =
+            |timing: TransitionTiming, now| timing.boundary(&sequence, 500, now, 60.0, 1000.0);
+        assert_eq!(boundary(TransitionTiming::Immediate, 1234), 1234);
+        assert_eq!(boundary(TransitionTiming::NextBeat, 1234), 1500);
+        assert_eq!(boundary(TransitionTiming::NextBeat, 1500), 1500);
+        assert_eq!(boundary(TransitionTiming::NextBar, 1234), 3500);
+        assert_eq!(boundary(TransitionTiming::NextBar, 4000), 6500);
+        assert_eq!(boundary(TransitionTiming::FinishSequence, 600), 6500);
+    }
+
+    #[test]
+    fn weighted_edges_are_picked_in_proportion() {
+        let edge = |to: &str, weight| Edge {
+            from: "a".to_string(),
+            to: to.to_string(),
+            condition: "true".to_string(),
+            timing: TransitionTiming::FinishSequence,
+            inlet_hook: None,
+            weight,
+        };
+        let (b, c, d) = (edge("b", Some(1.0)), edge("c", Some(3.0)), edge("d", None));
+        let edges = [&b, &c, &d];
+        let picked = |roll: u64| pick_weighted(&edges, roll).map(|e| e.to.as_str());
+        assert_eq!(picked(0), Some("b"));
+        assert_eq!(picked(u64::MAX / 4 - 1), Some("b"));
+        assert_eq!(picked(u64::MAX / 4 + (1 << 12)), Some("c"));
+        assert_eq!(picked(u64::MAX), Some("c"));
+        assert!(pick_weighted(&[&d], 0).is_none());
+    }
 }
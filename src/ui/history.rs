@@ -0,0 +1,117 @@
+use crate::Project;
+
+/// Most undo steps kept; older ones are dropped.
+const MAX_STEPS: usize = 100;
+
+/// Undo and redo stacks of whole-project snapshots. Projects are small enough
+/// that copying one per edit is cheaper than describing every kind of edit.
+#[derive(Default)]
+pub struct History {
+    undo: Vec<Project>,
+    redo: Vec<Project>,
+    /// The project as of the last recorded edit.
+    current: Option<Project>,
+    /// The pointer hasn't been released since the last edit, so further
+    /// changes (dragging a note, a slider) belong to the same step.
+    gesture_open: bool,
+}
+
+impl History {
+    /// Starts over from a freshly loaded project.
+    pub fn reset(&mut self, project: &Project) {
+        *self = Self {
+            current: Some(project.clone()),
+            ..Self::default()
+        };
+    }
+
+    /// Records the project after an edit.
+    pub fn record(&mut self, project: &Project) {
+        let previous = self.current.replace(project.clone());
+        if !self.gesture_open
+            && let Some(previous) = previous
+        {
+            if self.undo.len() == MAX_STEPS {
+                self.undo.remove(0);
+            }
+            self.undo.push(previous);
+        }
+        self.redo.clear();
+        self.gesture_open = true;
+    }
+
+    /// Called whenever the pointer is up: the next edit starts a new step.
+    pub fn end_gesture(&mut self) {
+        self.gesture_open = false;
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    /// Puts `project` back to before the last edit. Returns whether there was
+    /// one.
+    pub fn undo(&mut self, project: &mut Project) -> bool {
+        Self::step(&mut self.undo, &mut self.redo, &mut self.current, project)
+    }
+
+    /// Applies the last undone edit again. Returns whether there was one.
+    pub fn redo(&mut self, project: &mut Project) -> bool {
+        Self::step(&mut self.redo, &mut self.undo, &mut self.current, project)
+    }
+
+    fn step(
+        from: &mut Vec<Project>,
+        to: &mut Vec<Project>,
+        current: &mut Option<Project>,
+        project: &mut Project,
+    ) -> bool {
+        let Some(snapshot) = from.pop() else {
+            return false;
+        };
+        if let Some(current) = current.replace(snapshot.clone()) {
+            to.push(current);
+        }
+        *project = snapshot;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::templates;
+
+    #[test]
+    fn drags_undo_as_one_step() {
+        let mut project = templates::tutorial("History");
+        let mut history = History::default();
+        history.reset(&project);
+
+        // A drag: three changes before the pointer is released.
+        for bpm in [111.0, 112.0, 113.0] {
+            project.bpm = bpm;
+            history.record(&project);
+        }
+        history.end_gesture();
+        project.bpm = 120.0;
+        history.record(&project);
+
+        assert!(history.undo(&mut project));
+        assert_eq!(project.bpm, 113.0);
+        assert!(history.undo(&mut project));
+        assert_eq!(project.bpm, 110.0);
+        assert!(!history.undo(&mut project));
+
+        assert!(history.redo(&mut project));
+        assert_eq!(project.bpm, 113.0);
+        history.end_gesture();
+        project.bpm = 90.0;
+        history.record(&project);
+        assert!(!history.can_redo());
+    }
+}
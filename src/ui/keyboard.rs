@@ -0,0 +1,226 @@
+use crate::EngineCommand;
+use eframe::egui;
+
+/// Computer keys playing the notes of an octave and a bit, from C up, laid
+/// out like a piano: the home row holds the white keys, the row above the
+/// black ones.
+const KEY_MAP: [(egui::Key, u8); 17] = [
+    (egui::Key::A, 0),
+    (egui::Key::W, 1),
+    (egui::Key::S, 2),
+    (egui::Key::E, 3),
+    (egui::Key::D, 4),
+    (egui::Key::F, 5),
+    (egui::Key::T, 6),
+    (egui::Key::G, 7),
+    (egui::Key::Y, 8),
+    (egui::Key::H, 9),
+    (egui::Key::U, 10),
+    (egui::Key::J, 11),
+    (egui::Key::K, 12),
+    (egui::Key::O, 13),
+    (egui::Key::L, 14),
+    (egui::Key::P, 15),
+    (egui::Key::Semicolon, 16),
+];
+
+const OCTAVES_SHOWN: u8 = 3;
+const VELOCITY: u8 = 100;
+
+/// On-screen piano for auditioning a track. Notes are played with the mouse
+/// or the computer keyboard (Z and X shift the octave) and go straight to the
+/// engine, not into any pattern.
+pub struct VirtualKeyboard {
+    pub visible: bool,
+    /// Pitch of the lowest C shown, which is also the note on the A key.
+    base_pitch: u8,
+    /// Notes held by computer keys, with the track each was sent to.
+    keys_down: Vec<(egui::Key, usize, u8)>,
+    mouse_note: Option<(usize, u8)>,
+}
+
+impl Default for VirtualKeyboard {
+    fn default() -> Self {
+        Self {
+            visible: false,
+            base_pitch: 48,
+            keys_down: Vec::new(),
+            mouse_note: None,
+        }
+    }
+}
+
+impl VirtualKeyboard {
+    /// Plays notes for the computer keys pressed this frame, unless a text
+    /// field is taking the typing.
+    pub fn handle_keys(
+        &mut self,
+        ctx: &egui::Context,
+        track_id: Option<usize>,
+    ) -> Vec<EngineCommand> {
+        let mut commands = Vec::new();
+        let typing = ctx
+            .memory(|m| m.focused())
+            .is_some_and(|id| egui::text_edit::TextEditState::load(ctx, id).is_some());
+        let events = ctx.input(|i| i.events.clone());
+
+        for event in events {
+            let egui::Event::Key {
+                key,
+                pressed,
+                repeat: false,
+                modifiers,
+                ..
+            } = event
+            else {
+                continue;
+            };
+            if !pressed {
+                if let Some(pos) = self.keys_down.iter().position(|(k, ..)| *k == key) {
+                    let (_, track_id, pitch) = self.keys_down.remove(pos);
+                    commands.push(EngineCommand::NoteOff { track_id, pitch });
+                }
+                continue;
+            }
+            if !self.visible || typing || modifiers.command || modifiers.alt {
+                continue;
+            }
+            match key {
+                egui::Key::Z => self.base_pitch = self.base_pitch.saturating_sub(12),
+                egui::Key::X => self.base_pitch = (self.base_pitch + 12).min(108),
+                _ => {
+                    if let Some(track_id) = track_id
+                        && let Some(&(_, offset)) = KEY_MAP.iter().find(|(k, _)| *k == key)
+                    {
+                        let pitch = (self.base_pitch + offset).min(127);
+                        self.keys_down.push((key, track_id, pitch));
+                        commands.push(EngineCommand::NoteOn {
+                            track_id,
+                            pitch,
+                            velocity: VELOCITY,
+                        });
+                    }
+                }
+            }
+        }
+        commands
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui, track_id: usize) -> Vec<EngineCommand> {
+        let mut commands = Vec::new();
+
+        ui.horizontal(|ui| {
+            if ui.small_button("◀").clicked() {
+                self.base_pitch = self.base_pitch.saturating_sub(12);
+            }
+            ui.label(format!("C{}", self.base_pitch as i32 / 12 - 1));
+            if ui.small_button("▶").clicked() {
+                self.base_pitch = (self.base_pitch + 12).min(108);
+            }
+            ui.weak("Keys A to ; play notes, Z and X change octave");
+        });
+
+        let white_keys = OCTAVES_SHOWN as usize * 7;
+        let size = egui::vec2(ui.available_width(), 80.0);
+        let (rect, response) = ui.allocate_exact_size(size, egui::Sense::drag());
+        let white_width = rect.width() / white_keys as f32;
+
+        let mut keys: Vec<(u8, egui::Rect, bool)> = Vec::new();
+        for pitch in self.base_pitch..(self.base_pitch + OCTAVES_SHOWN * 12).min(128) {
+            let offset = pitch - self.base_pitch;
+            let white_index = (offset / 12) as usize * 7 + WHITE_INDEX[(offset % 12) as usize];
+            let x = rect.left() + white_index as f32 * white_width;
+            if is_black(pitch) {
+                let key = egui::Rect::from_min_size(
+                    egui::pos2(x - white_width * 0.3, rect.top()),
+                    egui::vec2(white_width * 0.6, rect.height() * 0.6),
+                );
+                keys.push((pitch, key, true));
+            } else {
+                let key = egui::Rect::from_min_size(
+                    egui::pos2(x, rect.top()),
+                    egui::vec2(white_width, rect.height()),
+                );
+                keys.push((pitch, key, false));
+            }
+        }
+
+        // Black keys sit on top, so they win the hit test.
+        let hovered = response.interact_pointer_pos().and_then(|pos| {
+            keys.iter()
+                .filter(|(_, _, black)| *black)
+                .chain(keys.iter().filter(|(_, _, black)| !*black))
+                .find(|(_, key, _)| key.contains(pos))
+                .map(|(pitch, ..)| *pitch)
+        });
+        let pressed = if response.is_pointer_button_down_on() {
+            hovered
+        } else {
+            None
+        };
+        if pressed != self.mouse_note.map(|(_, pitch)| pitch) {
+            if let Some((track_id, pitch)) = self.mouse_note.take() {
+                commands.push(EngineCommand::NoteOff { track_id, pitch });
+            }
+            if let Some(pitch) = pressed {
+                self.mouse_note = Some((track_id, pitch));
+                commands.push(EngineCommand::NoteOn {
+                    track_id,
+                    pitch,
+                    velocity: VELOCITY,
+                });
+            }
+        }
+
+        let painter = ui.painter_at(rect);
+        let sounding = |pitch: u8| {
+            self.mouse_note.is_some_and(|(_, p)| p == pitch)
+                || self.keys_down.iter().any(|&(_, _, p)| p == pitch)
+        };
+        for black in [false, true] {
+            for &(pitch, key, _) in keys.iter().filter(|(_, _, b)| *b == black) {
+                let fill = match (sounding(pitch), black) {
+                    (true, _) => egui::Color32::from_rgb(60, 180, 100),
+                    (false, true) => egui::Color32::from_rgb(20, 20, 20),
+                    (false, false) => egui::Color32::from_rgb(220, 220, 220),
+                };
+                painter.rect_filled(key, 2.0, fill);
+                painter.rect_stroke(
+                    key,
+                    2.0,
+                    egui::Stroke::new(1.0, egui::Color32::from_rgb(100, 100, 100)),
+                    egui::StrokeKind::Inside,
+                );
+                if pitch % 12 == 0 {
+                    painter.text(
+                        key.center_bottom() - egui::vec2(0.0, 4.0),
+                        egui::Align2::CENTER_BOTTOM,
+                        format!("C{}", pitch as i32 / 12 - 1),
+                        egui::FontId::proportional(10.0),
+                        egui::Color32::BLACK,
+                    );
+                }
+            }
+        }
+
+        commands
+    }
+
+    /// Stops every note still held, e.g. when the keyboard is hidden.
+    pub fn release_all(&mut self) -> Vec<EngineCommand> {
+        self.keys_down
+            .drain(..)
+            .map(|(_, track_id, pitch)| (track_id, pitch))
+            .chain(self.mouse_note.take())
+            .map(|(track_id, pitch)| EngineCommand::NoteOff { track_id, pitch })
+            .collect()
+    }
+}
+
+/// Index among an octave's white keys of the white key each semitone is on,
+/// or right of, for black keys.
+const WHITE_INDEX: [usize; 12] = [0, 1, 1, 2, 2, 3, 4, 4, 5, 5, 6, 6];
+
+fn is_black(pitch: u8) -> bool {
+    matches!(pitch % 12, 1 | 3 | 6 | 8 | 10)
+}
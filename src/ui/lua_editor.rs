@@ -0,0 +1,249 @@
+use crate::project::Project;
+use crate::scripting::{LuaRuntime, PatternContext, ScriptError, VariableStore};
+use crate::timing::{Node, StaticPattern};
+use eframe::egui;
+use std::ops::Range;
+
+const KEYWORDS: &[&str] = &[
+    "and", "break", "do", "else", "elseif", "end", "false", "for", "function", "goto", "if", "in",
+    "local", "nil", "not", "or", "repeat", "return", "then", "true", "until", "while",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Token {
+    Keyword,
+    String,
+    Number,
+    Comment,
+}
+
+impl Token {
+    fn color(self, dark_mode: bool) -> egui::Color32 {
+        match (self, dark_mode) {
+            (Token::Keyword, true) => egui::Color32::from_rgb(200, 140, 255),
+            (Token::Keyword, false) => egui::Color32::from_rgb(140, 40, 200),
+            (Token::String, true) => egui::Color32::from_rgb(150, 210, 120),
+            (Token::String, false) => egui::Color32::from_rgb(40, 130, 30),
+            (Token::Number, true) => egui::Color32::from_rgb(240, 170, 90),
+            (Token::Number, false) => egui::Color32::from_rgb(180, 90, 0),
+            (Token::Comment, _) => egui::Color32::GRAY,
+        }
+    }
+}
+
+/// Lays Lua code out with its keywords, strings, numbers and comments
+/// coloured, for use as a [`egui::TextEdit`] layouter.
+pub fn highlight(ui: &egui::Ui, code: &str, wrap_width: f32) -> egui::text::LayoutJob {
+    let font_id = egui::TextStyle::Monospace.resolve(ui.style());
+    let plain = egui::TextFormat::simple(font_id.clone(), ui.visuals().text_color());
+    let dark_mode = ui.visuals().dark_mode;
+
+    let mut job = egui::text::LayoutJob::default();
+    job.wrap.max_width = wrap_width;
+    let mut end = 0;
+    for (range, token) in tokens(code) {
+        job.append(&code[end..range.start], 0.0, plain.clone());
+        job.append(
+            &code[range.clone()],
+            0.0,
+            egui::TextFormat::simple(font_id.clone(), token.color(dark_mode)),
+        );
+        end = range.end;
+    }
+    job.append(&code[end..], 0.0, plain);
+    job
+}
+
+/// The highlighted spans of `code`, in order. Everything between them is
+/// plain text. Spans start and end on ASCII characters, so they can be used
+/// to slice the code.
+fn tokens(code: &str) -> Vec<(Range<usize>, Token)> {
+    let bytes = code.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let start = i;
+        let token = match bytes[i] {
+            b'-' if bytes.get(i + 1) == Some(&b'-') => {
+                i += 2;
+                match long_bracket_level(bytes, i) {
+                    Some(level) => i = long_bracket_end(bytes, i, level),
+                    None => {
+                        while i < bytes.len() && bytes[i] != b'\n' {
+                            i += 1;
+                        }
+                    }
+                }
+                Token::Comment
+            }
+            quote @ (b'"' | b'\'') => {
+                i += 1;
+                while i < bytes.len() && bytes[i] != quote && bytes[i] != b'\n' {
+                    i += if bytes[i] == b'\\' { 2 } else { 1 };
+                }
+                i = (i + 1).min(bytes.len());
+                Token::String
+            }
+            b'[' if long_bracket_level(bytes, i).is_some() => {
+                let level = long_bracket_level(bytes, i).unwrap_or_default();
+                i = long_bracket_end(bytes, i, level);
+                Token::String
+            }
+            b'0'..=b'9' => {
+                i = number_end(bytes, i);
+                Token::Number
+            }
+            b'.' if bytes.get(i + 1).is_some_and(u8::is_ascii_digit) => {
+                i = number_end(bytes, i);
+                Token::Number
+            }
+            b if b.is_ascii_alphabetic() || b == b'_' => {
+                while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                    i += 1;
+                }
+                if !KEYWORDS.contains(&&code[start..i]) {
+                    continue;
+                }
+                Token::Keyword
+            }
+            _ => {
+                i += 1;
+                continue;
+            }
+        };
+        tokens.push((start..i, token));
+    }
+    tokens
+}
+
+/// Level (number of `=`) of a long bracket opening at `i`, like `[[` or `[==[`.
+fn long_bracket_level(bytes: &[u8], i: usize) -> Option<usize> {
+    if bytes.get(i) != Some(&b'[') {
+        return None;
+    }
+    let level = bytes[i + 1..].iter().take_while(|&&b| b == b'=').count();
+    (bytes.get(i + 1 + level) == Some(&b'[')).then_some(level)
+}
+
+/// End of the long bracket opened at `i`, or of the code if it's unclosed.
+fn long_bracket_end(bytes: &[u8], i: usize, level: usize) -> usize {
+    let mut close = vec![b']'];
+    close.extend(std::iter::repeat_n(b'=', level));
+    close.push(b']');
+    let body = i + level + 2;
+    bytes[body..]
+        .windows(close.len())
+        .position(|w| w == close)
+        .map_or(bytes.len(), |pos| body + pos + close.len())
+}
+
+fn number_end(bytes: &[u8], mut i: usize) -> usize {
+    while i < bytes.len() {
+        match bytes[i] {
+            b'e' | b'E' | b'p' | b'P' if matches!(bytes.get(i + 1), Some(b'+' | b'-')) => i += 2,
+            b if b.is_ascii_alphanumeric() || b == b'.' => i += 1,
+            _ => break,
+        }
+    }
+    i
+}
+
+/// The notes and automation a node plays on its `loop_count`th time round. A generated
+/// pattern's code runs on a runtime of its own, so whatever it asks of the
+/// engine is dropped.
+pub fn evaluate(
+    project: &Project,
+    track_id: usize,
+    node: &Node,
+    loop_count: u64,
+) -> Result<StaticPattern, ScriptError> {
+    let lua_runtime = LuaRuntime::new().map_err(|e| ScriptError::from_lua(&e))?;
+    let variables = VariableStore::new();
+    let context = PatternContext {
+        track_id,
+        node_id: &node.id,
+        start_sample: 0,
+        bpm: project.bpm,
+        sample_rate: project.sample_rate as f32,
+        time_signature: node.sequence.time_signature(),
+        loop_count,
+        repeat: node.repeat,
+        seed: project.seed,
+        pattern_seed: node.sequence.pattern_seed(),
+        variables: &variables,
+    };
+    let events = node
+        .sequence
+        .get_events(Some(&lua_runtime), &context)
+        .map_err(|e| ScriptError::from_lua(&e))?;
+    Ok(StaticPattern {
+        duration_bars: node.sequence.duration_bars(),
+        time_signature: node.sequence.time_signature(),
+        notes: events.notes,
+        automation: events.automation,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::templates;
+    use crate::timing::Sequence;
+
+    fn spans(code: &str) -> Vec<(&str, Token)> {
+        tokens(code)
+            .into_iter()
+            .map(|(range, token)| (&code[range], token))
+            .collect()
+    }
+
+    #[test]
+    fn lua_tokens_are_found() {
+        assert_eq!(
+            spans("local n = 0x1F -- count\nreturn [==[a]]b]==] .. 'it''s' .. 1.5e-3"),
+            vec![
+                ("local", Token::Keyword),
+                ("0x1F", Token::Number),
+                ("-- count", Token::Comment),
+                ("return", Token::Keyword),
+                ("[==[a]]b]==]", Token::String),
+                ("'it'", Token::String),
+                ("'s'", Token::String),
+                ("1.5e-3", Token::Number),
+            ]
+        );
+        assert_eq!(
+            spans("--[[ a\nlong one ]] x = \"unclosed"),
+            vec![
+                ("--[[ a\nlong one ]]", Token::Comment),
+                ("\"unclosed", Token::String),
+            ]
+        );
+    }
+
+    #[test]
+    fn evaluating_a_pattern_previews_its_notes() {
+        let project = templates::tutorial("Preview");
+        let (track_id, node) = project
+            .tracks
+            .iter()
+            .find_map(|t| {
+                t.graph
+                    .nodes
+                    .iter()
+                    .find(|n| matches!(n.sequence, Sequence::Generated(_)))
+                    .map(|n| (t.id, n.clone()))
+            })
+            .unwrap();
+        let preview = evaluate(&project, track_id, &node, 0).unwrap();
+        assert!(!preview.notes.is_empty());
+
+        let mut broken = node.clone();
+        if let Sequence::Generated(pattern) = &mut broken.sequence {
+            pattern.function = "return {".to_string();
+        }
+        let error = evaluate(&project, track_id, &broken, 0).unwrap_err();
+        assert_eq!(error.line, Some(1));
+    }
+}
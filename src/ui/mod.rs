@@ -1,54 +1,512 @@
+mod history;
+mod keyboard;
+mod lua_editor;
+mod patch_editor;
 mod piano_roll;
+mod settings;
 
-use crate::timing::{Sequence, StaticPattern};
-use crate::{EngineCommand, EngineHandle, EngineUpdate, Project, TrackData};
+use crate::audio::{
+    ADSRConfig, AUDIO_EXTENSIONS, AudioBackend, AudioSettings, ChokeMode, Instrument, Level,
+    OscConfig, OutputDevice, VelocityCurve, Wave,
+};
+use crate::dsp::resample::Interpolation;
+use crate::logging::LogRecord;
+use crate::midi::{MidiTarget, TransportAction};
+use crate::plugin::{self, PluginInfo, PluginRef, PluginSlot};
+use crate::scripting::{ScriptError, TrackParam};
+use crate::templates::Template;
+use crate::timing::{Edge, Node, Sequence, StaticPattern, TransitionTiming};
+use crate::{EngineCommand, EngineHandle, EngineUpdate, Project, ProjectFormat, TrackData};
+use crossbeam::channel::{Receiver, TryRecvError};
 use eframe::egui;
+use history::History;
+use keyboard::VirtualKeyboard;
+use patch_editor::PatchEditor;
 use piano_roll::{PianoRoll, PianoRollState};
-use std::collections::HashMap;
+use settings::{AppSettings, Theme};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::{BuildHasher, RandomState};
 use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Width of a channel strip in the mixer.
+const MIXER_STRIP_WIDTH: f32 = 90.0;
+
+/// Scales offered in the View menu. Ctrl +/- still zooms in between.
+const UI_SCALES: [f32; 8] = [0.75, 0.9, 1.0, 1.1, 1.25, 1.5, 1.75, 2.0];
+
+/// Records the log panel keeps; older ones scroll away.
+const LOG_LINES: usize = 1000;
+
+/// Longest quitting waits for the engine to fade out and stop.
+const SHUTDOWN_WAIT: Duration = Duration::from_secs(1);
+
+/// How long unsaved changes wait before they're saved automatically.
+const AUTOSAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Sample rates offered for new projects.
+const PROJECT_SAMPLE_RATES: [u32; 4] = [44_100, 48_000, 88_200, 96_000];
+
+/// Settings of the project being created in the New Project dialog.
+struct NewProjectDraft {
+    name: String,
+    bpm: f32,
+    sample_rate: u32,
+    template: Template,
+}
+
+impl Default for NewProjectDraft {
+    fn default() -> Self {
+        Self {
+            name: "Untitled".to_string(),
+            bpm: 120.0,
+            sample_rate: 44_100,
+            template: Template::Empty,
+        }
+    }
+}
+
+/// Notes a Lua pattern gave when last evaluated from its panel.
+struct PatternPreview {
+    node: (usize, String),
+    /// Loop the pattern was run for, since code can vary from one to the next.
+    loop_count: u64,
+    notes: Result<StaticPattern, ScriptError>,
+    piano_roll: PianoRollState,
+}
+
+/// A change to the track list made from the side panel.
+enum TrackAction {
+    Add,
+    Duplicate(usize),
+    Remove(usize),
+    Move { from: usize, to: usize },
+}
+
+/// Radius of the port a transition is dragged out of, on a node's right edge.
+const PORT_RADIUS: f32 = 6.0;
+
+/// What a drag that started on the graph canvas is doing.
+#[derive(Debug, Clone, Copy)]
+enum GraphDrag {
+    Node(usize),
+    /// Drawing a transition out of a node.
+    Connect(usize),
+}
+
+/// A change made on the graph canvas, by node and edge index.
+enum GraphEdit {
+    AddNode(egui::Pos2),
+    MoveNode(usize, egui::Pos2),
+    RemoveNode(usize),
+    SetInitial(usize),
+    SetRepeat(usize, Option<u32>),
+    AddEdge(usize, usize),
+    UpdateEdge(usize, Edge),
+    RemoveEdge(usize),
+}
+
+/// Item of the state graph that has keyboard focus.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum GraphItem {
+    Node(usize),
+    Edge(usize),
+}
 
 pub struct AurioApp {
     engine: EngineHandle,
     current_project: Option<Project>,
     project_path: Option<PathBuf>,
+    /// The project as it was when the engine restarted after a crash, sent
+    /// back to it once it has loaded the project from disk again.
+    restart_edits: Option<Project>,
     error_message: Option<String>,
     selected_track: Option<usize>,
     selected_node: Option<(usize, String)>,
     playing: bool,
     current_nodes: HashMap<usize, String>,
+    /// Section of the arrangement playing.
+    current_section: Option<String>,
+    /// Beats into its current node's sequence each track is, by track index.
+    playhead: Vec<f32>,
     project_modified: bool,
+    /// When the oldest unsaved change was made, for autosave.
+    unsaved_since: Option<Instant>,
+    history: History,
     piano_roll_states: HashMap<(usize, String), PianoRollState>,
+    pattern_preview: Option<PatternPreview>,
+    /// The Lua code in the pattern panel changed since the engine last got it.
+    pattern_code_edited: bool,
+    keyboard: VirtualKeyboard,
+    morph_positions: HashMap<usize, f32>,
+    graph_focus: Option<GraphItem>,
+    /// Layer shown in the graph editor instead of a track's own graph, as
+    /// (track, layer).
+    graph_layer: Option<(usize, usize)>,
+    graph_drag: Option<GraphDrag>,
+    /// Node the graph's context menu was opened on.
+    graph_menu_node: Option<usize>,
+    /// Transition whose popover is open.
+    editing_edge: Option<usize>,
+    /// Latest script error per (track, node), shown next to the node.
+    script_errors: HashMap<(usize, String), ScriptError>,
+    metronome: bool,
+    midi_learning: Option<MidiTarget>,
+    /// Variable name typed in the MIDI menu for the next learn.
+    midi_variable: String,
+    audio_settings: AudioSettings,
+    /// Settings being edited in the audio dialog, while it's open.
+    audio_dialog: Option<AudioSettings>,
+    new_project_dialog: Option<NewProjectDraft>,
+    audio_devices: Vec<OutputDevice>,
+    /// Device the engine plays through; `None` while it waits for a reconnect.
+    audio_device: Option<String>,
+    /// Seconds the device last reported between rendering and hearing.
+    output_latency: Option<f64>,
+    /// Plugins found by the last scan.
+    plugin_catalog: Vec<PluginInfo>,
+    /// A scan running on a worker thread, until it reports what it found.
+    plugin_scan: Option<Receiver<Vec<PluginInfo>>>,
+    /// Track the input is being recorded for.
+    recording: Option<usize>,
+    /// Track Record records into, instead of the selected one.
+    armed_track: Option<usize>,
+    show_mixer: bool,
+    log_rx: Receiver<LogRecord>,
+    /// Latest records from the engine, timing and watcher threads.
+    log: VecDeque<LogRecord>,
+    show_log: bool,
+    patch_editor: PatchEditor,
+    master_level: Level,
+    track_levels: Vec<Level>,
+    /// Events dropped because the event ring was full, until dismissed, and
+    /// the track and node it last happened on.
+    dropped_events: usize,
+    last_dropped: Option<(usize, String)>,
+    /// Meters that went over full scale, kept lit until clicked. `None` is
+    /// the master.
+    clipped: HashSet<Option<usize>>,
+    settings: AppSettings,
+    /// Closing was held back to ask what to do with unsaved changes.
+    quit_dialog: bool,
+    /// Whether the theme and scale from the settings were given to egui yet.
+    settings_applied: bool,
 }
 
 impl AurioApp {
-    pub fn new(engine: EngineHandle) -> Self {
-        Self {
+    /// Loads the user's settings and reopens the project they last had open.
+    /// `log_rx` feeds the log panel, e.g. from [`crate::logging::init`].
+    pub fn new(engine: EngineHandle, log_rx: Receiver<LogRecord>) -> Self {
+        let settings = AppSettings::load();
+        let last_project = settings
+            .last_project
+            .clone()
+            .filter(|path| ProjectFormat::detect(path).is_some());
+        let mut app = Self {
             engine,
             current_project: None,
             project_path: None,
+            restart_edits: None,
             error_message: None,
             selected_track: None,
             selected_node: None,
             playing: false,
             current_nodes: HashMap::new(),
+            current_section: None,
+            playhead: Vec::new(),
             project_modified: false,
+            unsaved_since: None,
+            history: History::default(),
             piano_roll_states: HashMap::new(),
+            pattern_preview: None,
+            pattern_code_edited: false,
+            keyboard: VirtualKeyboard::default(),
+            morph_positions: HashMap::new(),
+            graph_focus: None,
+            graph_layer: None,
+            graph_drag: None,
+            graph_menu_node: None,
+            editing_edge: None,
+            script_errors: HashMap::new(),
+            metronome: false,
+            midi_learning: None,
+            midi_variable: String::new(),
+            audio_settings: AudioSettings::default(),
+            audio_dialog: None,
+            new_project_dialog: None,
+            audio_devices: Vec::new(),
+            audio_device: None,
+            output_latency: None,
+            plugin_catalog: Vec::new(),
+            plugin_scan: None,
+            recording: None,
+            armed_track: None,
+            show_mixer: false,
+            log_rx,
+            log: VecDeque::new(),
+            show_log: false,
+            patch_editor: PatchEditor::default(),
+            master_level: Level::default(),
+            track_levels: Vec::new(),
+            dropped_events: 0,
+            last_dropped: None,
+            clipped: HashSet::new(),
+            settings,
+            quit_dialog: false,
+            settings_applied: false,
+        };
+        if let Some(path) = last_project {
+            app.open_project(path);
+        }
+        app
+    }
+
+    fn open_project(&mut self, path: PathBuf) {
+        self.project_path = Some(path.clone());
+        self.restart_edits = None;
+        let _ = self
+            .engine
+            .command_tx
+            .send(EngineCommand::LoadProject(path));
+    }
+
+    /// Holds the window open while the project has unsaved changes, and
+    /// otherwise stops the engine before it closes.
+    fn handle_close_request(&mut self, ctx: &egui::Context) {
+        if !ctx.input(|i| i.viewport().close_requested()) {
+            return;
+        }
+        if self.project_modified {
+            ctx.send_viewport_cmd(egui::ViewportCommand::CancelClose);
+            self.quit_dialog = true;
+        } else if !self.engine.shutdown(SHUTDOWN_WAIT) {
+            tracing::warn!("The engine didn't stop in time");
+        }
+    }
+
+    fn quit_dialog(&mut self, ctx: &egui::Context) {
+        if !self.quit_dialog {
+            return;
+        }
+        let mut choice = None;
+        let modal = egui::Modal::new(egui::Id::new("quit_dialog")).show(ctx, |ui| {
+            ui.heading("Unsaved changes");
+            let name = self
+                .current_project
+                .as_ref()
+                .map_or("The project", |p| p.name.as_str());
+            ui.label(format!("{} has changes that aren't saved.", name));
+            ui.horizontal(|ui| {
+                if ui.button("💾 Save and Quit").clicked() {
+                    choice = Some(true);
+                }
+                if ui.button("Discard").clicked() {
+                    choice = Some(false);
+                }
+                if ui.button("Cancel").clicked() {
+                    self.quit_dialog = false;
+                }
+            });
+        });
+        if modal.should_close() {
+            self.quit_dialog = false;
+        }
+
+        if let Some(save) = choice {
+            self.quit_dialog = false;
+            if save {
+                // Without a folder to save to, ask for one first; cancelling
+                // keeps the window open.
+                if self.project_path.is_some() || self.choose_project_path() {
+                    self.save_project();
+                }
+            } else {
+                self.project_modified = false;
+            }
+            // A failed save keeps the window open, with its error showing.
+            if !self.project_modified {
+                ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+            }
+        }
+    }
+
+    fn save_settings(&mut self) {
+        if let Err(e) = self.settings.save() {
+            self.error_message = Some(format!("Failed to save settings: {}", e));
+        }
+    }
+
+    /// Gives egui the theme and scale from the settings, once at startup,
+    /// and keeps the scale if it's changed with egui's zoom shortcuts.
+    fn apply_settings(&mut self, ctx: &egui::Context) {
+        if !self.settings_applied {
+            ctx.set_theme(self.settings.theme.egui());
+            ctx.set_zoom_factor(self.settings.ui_scale);
+            self.settings_applied = true;
+        } else if ctx.zoom_factor() != self.settings.ui_scale {
+            self.settings.ui_scale = ctx.zoom_factor();
+            self.save_settings();
         }
     }
 
     fn process_engine_updates(&mut self) {
         while let Ok(update) = self.engine.update_rx.try_recv() {
             match update {
+                // Reloaded after a restart: what's on screen, unsaved edits
+                // included, is what should play.
+                EngineUpdate::ProjectLoaded { .. } if self.restart_edits.is_some() => {
+                    if let Some(project) = self.restart_edits.take() {
+                        let _ = self
+                            .engine
+                            .command_tx
+                            .send(EngineCommand::ReloadProject(project));
+                    }
+                }
                 EngineUpdate::ProjectLoaded { project } => {
+                    if let Some(path) = &self.project_path {
+                        self.settings.opened(path);
+                        self.save_settings();
+                    }
+                    self.history.reset(&project);
                     self.current_project = Some(project);
                     self.error_message = None;
+                    self.script_errors.clear();
                     self.selected_track = Some(0);
                 }
                 EngineUpdate::CurrentNodes { track_nodes } => {
                     self.current_nodes = track_nodes.into_iter().collect();
+                    self.current_section = None;
+                    self.playhead.clear();
                 }
+                EngineUpdate::Section { section } => {
+                    self.current_section = Some(section);
+                }
+                // Ignored once stopped, as the timing thread may still be
+                // winding down.
+                EngineUpdate::Playhead { positions } if self.playing => {
+                    self.current_nodes.clear();
+                    self.playhead.clear();
+                    for (track_id, (node_id, beat)) in positions.into_iter().enumerate() {
+                        self.current_nodes.insert(track_id, node_id);
+                        self.playhead.push(beat);
+                    }
+                }
+                EngineUpdate::Playhead { .. } => {}
                 EngineUpdate::PlaybackState { playing } => {
                     self.playing = playing;
+                    if !playing {
+                        self.master_level = Level::default();
+                        self.track_levels.clear();
+                    }
+                }
+                EngineUpdate::BpmChanged { bpm } => {
+                    if let Some(project) = &mut self.current_project {
+                        project.bpm = bpm;
+                    }
+                }
+                EngineUpdate::TrackParamChanged {
+                    track_id,
+                    param,
+                    value,
+                } => match param {
+                    TrackParam::Morph => {
+                        self.morph_positions.insert(track_id, value);
+                    }
+                    _ => {
+                        if let Some(track) = self
+                            .current_project
+                            .as_mut()
+                            .and_then(|p| p.tracks.get_mut(track_id))
+                        {
+                            track.set_param(param, value);
+                        }
+                    }
+                },
+                EngineUpdate::MetronomeState { enabled } => {
+                    self.metronome = enabled;
+                }
+                EngineUpdate::MidiLearning { target } => {
+                    self.midi_learning = target;
+                }
+                EngineUpdate::MidiMappingsChanged { mappings } => {
+                    if let Some(project) = &mut self.current_project {
+                        project.midi_mappings = mappings;
+                        self.project_modified = true;
+                    }
+                }
+                EngineUpdate::ScriptError {
+                    track_id,
+                    node_id,
+                    error,
+                } => {
+                    self.script_errors.insert((track_id, node_id), error);
+                }
+                EngineUpdate::AudioDevices { devices } => {
+                    self.audio_devices = devices;
+                }
+                EngineUpdate::AudioDeviceChanged { device } => {
+                    self.audio_device = device;
+                }
+                EngineUpdate::OutputLatency { seconds } => {
+                    self.output_latency = Some(seconds);
+                }
+                EngineUpdate::PluginParams {
+                    track_id,
+                    slot,
+                    params,
+                } => {
+                    if let Some(plugin) = self
+                        .current_project
+                        .as_mut()
+                        .and_then(|p| p.tracks.get_mut(track_id))
+                        .and_then(|track| track.plugin_mut(slot))
+                        && plugin.params != params
+                    {
+                        plugin.params = params;
+                        self.project_modified = true;
+                    }
+                }
+                EngineUpdate::SampleAdded { sample } => {
+                    if let Some(project) = &mut self.current_project {
+                        project.sample_library.push(sample);
+                        self.project_modified = true;
+                    }
+                }
+                EngineUpdate::Meters { master, tracks } => {
+                    if master.clipped() {
+                        self.clipped.insert(None);
+                    }
+                    for (i, level) in tracks.iter().enumerate() {
+                        if level.clipped() {
+                            self.clipped.insert(Some(i));
+                        }
+                    }
+                    self.master_level = master;
+                    self.track_levels = tracks;
+                }
+                EngineUpdate::Recording { track_id } => {
+                    self.recording = track_id;
+                }
+                EngineUpdate::EventsDropped {
+                    track_id,
+                    node_id,
+                    count,
+                } => {
+                    self.dropped_events += count;
+                    self.last_dropped = Some((track_id, node_id));
+                }
+                EngineUpdate::Restarted => {
+                    self.playing = false;
+                    self.current_nodes.clear();
+                    self.playhead.clear();
+                    if let (Some(path), Some(project)) = (&self.project_path, &self.current_project)
+                    {
+                        self.restart_edits = Some(project.clone());
+                        let _ = self
+                            .engine
+                            .command_tx
+                            .send(EngineCommand::LoadProject(path.clone()));
+                    }
                 }
                 EngineUpdate::Error { message } => {
                     self.error_message = Some(message);
@@ -57,70 +515,1416 @@ impl AurioApp {
         }
     }
 
+    /// Ctrl+Z / Ctrl+Shift+Z (Cmd on macOS), or the Edit menu.
+    fn undo_shortcuts(&mut self, ctx: &egui::Context) {
+        use egui::{Key, Modifiers};
+
+        if !ctx.input(|i| i.pointer.any_down()) {
+            self.history.end_gesture();
+        }
+        let (redo, undo) = ctx.input_mut(|i| {
+            (
+                i.consume_key(Modifiers::COMMAND | Modifiers::SHIFT, Key::Z),
+                i.consume_key(Modifiers::COMMAND, Key::Z),
+            )
+        });
+        if redo {
+            self.step_history(true);
+        } else if undo {
+            self.step_history(false);
+        }
+    }
+
+    fn step_history(&mut self, redo: bool) {
+        let Some(project) = &mut self.current_project else {
+            return;
+        };
+        // Tracks and layers both change what the engine runs per track.
+        let track_names = |project: &Project| -> Vec<(String, usize)> {
+            project
+                .tracks
+                .iter()
+                .map(|t| (t.name.clone(), t.layers.len()))
+                .collect()
+        };
+        let tracks_before = track_names(project);
+        let bpm_before = project.bpm;
+        let changed = if redo {
+            self.history.redo(project)
+        } else {
+            self.history.undo(project)
+        };
+        // Reloads don't change the tempo that's playing, so it goes on its own.
+        if project.bpm != bpm_before {
+            let _ = self
+                .engine
+                .command_tx
+                .send(EngineCommand::SetBpm { bpm: project.bpm });
+        }
+        if changed && track_names(project) != tracks_before {
+            let last = project.tracks.len().checked_sub(1);
+            let selected = self.selected_track.zip(last).map(|(i, last)| i.min(last));
+            self.tracks_restructured(selected);
+        } else if changed {
+            self.project_modified = true;
+            let _ = self
+                .engine
+                .command_tx
+                .send(EngineCommand::ReloadProject(project.clone()));
+        }
+    }
+
+    fn save_project(&mut self) {
+        // A failed save waits for the next interval before autosave retries.
+        self.unsaved_since = None;
+        if let (Some(project), Some(path)) = (&self.current_project, &self.project_path) {
+            match project.save(path) {
+                Ok(_) => {
+                    self.project_modified = false;
+                    tracing::info!("Project saved successfully");
+                }
+                Err(e) => {
+                    self.error_message = Some(format!("Failed to save project: {}", e));
+                }
+            }
+        }
+    }
+
+    /// Asks where to save a project that has no folder yet, as Save As
+    /// would. Returns whether it got one.
+    fn choose_project_path(&mut self) -> bool {
+        let Some(project) = &self.current_project else {
+            return false;
+        };
+        let Some(folder) = rfd::FileDialog::new()
+            .set_title("Save the project in")
+            .pick_folder()
+        else {
+            return false;
+        };
+        let path = folder.join(format!("{}.aurio", project.name));
+        if ProjectFormat::detect(&path).is_some() {
+            self.error_message = Some(format!("{} already contains a project", path.display()));
+            return false;
+        }
+        self.settings.opened(&path);
+        self.save_settings();
+        self.project_path = Some(path);
+        true
+    }
+
+    fn collect_assets(&mut self) {
+        let (Some(project), Some(path)) = (&mut self.current_project, &self.project_path) else {
+            return;
+        };
+        match project.collect_assets(path) {
+            Ok(0) => tracing::info!("All assets are already in the project"),
+            Ok(copied) => {
+                tracing::info!("Copied {} files into the project", copied);
+                self.history.record(project);
+                self.project_modified = true;
+                let _ = self
+                    .engine
+                    .command_tx
+                    .send(EngineCommand::ReloadProject(project.clone()));
+            }
+            Err(e) => {
+                self.error_message = Some(format!("Failed to collect assets: {}", e));
+            }
+        }
+    }
+
+    /// Saves once changes have been left unsaved for [`AUTOSAVE_INTERVAL`].
+    fn autosave(&mut self, ctx: &egui::Context) {
+        if !self.project_modified {
+            self.unsaved_since = None;
+            return;
+        }
+        let since = *self.unsaved_since.get_or_insert_with(Instant::now);
+        match AUTOSAVE_INTERVAL.checked_sub(since.elapsed()) {
+            Some(remaining) if !remaining.is_zero() => ctx.request_repaint_after(remaining),
+            _ => self.save_project(),
+        }
+    }
+
+    fn send_all(&self, commands: Vec<EngineCommand>) {
+        for command in commands {
+            let _ = self.engine.command_tx.send(command);
+        }
+    }
+
     fn menu_bar(&mut self, ui: &mut egui::Ui) {
         egui::MenuBar::new().ui(ui, |ui| {
             ui.menu_button("File", |ui| {
                 if ui.button("New Project...").clicked() {
-                    // TODO
+                    self.new_project_dialog = Some(NewProjectDraft::default());
                     ui.close();
                 }
 
-                if ui.button("Open Project...").clicked() {
-                    if let Some(path) = rfd::FileDialog::new()
+                if ui.button("Open Project...").clicked()
+                    && let Some(path) = rfd::FileDialog::new()
                         .set_title("Open Aurio Project")
                         .pick_folder()
+                {
+                    self.open_project(path);
+                    ui.close();
+                }
+
+                ui.add_enabled_ui(!self.settings.recent_projects.is_empty(), |ui| {
+                    ui.menu_button("Open Recent", |ui| {
+                        let mut opened = None;
+                        for path in &self.settings.recent_projects {
+                            let name = path.file_name().unwrap_or(path.as_os_str());
+                            if ui
+                                .button(name.to_string_lossy())
+                                .on_hover_text(path.display().to_string())
+                                .clicked()
+                            {
+                                opened = Some(path.clone());
+                            }
+                        }
+                        ui.separator();
+                        if ui.button("Clear Recent").clicked() {
+                            self.settings.recent_projects.clear();
+                            self.save_settings();
+                            ui.close();
+                        }
+                        if let Some(path) = opened {
+                            self.open_project(path);
+                            ui.close();
+                        }
+                    });
+                });
+
+                let save_button = if self.project_modified {
+                    ui.button("💾 Save Project *")
+                } else {
+                    ui.button("💾 Save Project")
+                };
+
+                if save_button.clicked()
+                    && (self.project_path.is_some() || self.choose_project_path())
+                {
+                    self.save_project();
+                    ui.close();
+                }
+
+                ui.separator();
+
+                if ui.button("Quit").clicked() {
+                    ui.ctx().send_viewport_cmd(egui::ViewportCommand::Close);
+                    ui.close();
+                }
+            });
+            ui.menu_button("Edit", |ui| {
+                if ui
+                    .add_enabled(self.history.can_undo(), egui::Button::new("Undo"))
+                    .clicked()
+                {
+                    self.step_history(false);
+                    ui.close();
+                }
+                if ui
+                    .add_enabled(self.history.can_redo(), egui::Button::new("Redo"))
+                    .clicked()
+                {
+                    self.step_history(true);
+                    ui.close();
+                }
+            });
+            let mut collect_assets = false;
+            if let Some(project) = &self.current_project {
+                ui.menu_button("Project", |ui| {
+                    let title = if self.project_modified {
+                        format!("{} *", project.name)
+                    } else {
+                        project.name.clone()
+                    };
+                    let _ = ui.button(title);
+                    ui.separator();
+                    if ui.button("Import Sample…").clicked() {
+                        if let Some(source) = rfd::FileDialog::new()
+                            .set_title("Import Sample")
+                            .add_filter("Audio", AUDIO_EXTENSIONS)
+                            .pick_file()
+                        {
+                            let _ = self
+                                .engine
+                                .command_tx
+                                .send(EngineCommand::ImportSample(source));
+                        }
+                        ui.close();
+                    }
+                    if ui
+                        .button("Collect Assets")
+                        .on_hover_text(
+                            "Copy samples and scripts from outside the project folder into it",
+                        )
+                        .clicked()
                     {
-                        self.project_path = Some(path.clone());
-                        let _ = self
-                            .engine
-                            .command_tx
-                            .send(EngineCommand::LoadProject(path));
+                        collect_assets = true;
                         ui.close();
                     }
+                });
+                ui.menu_button("MIDI", |ui| self.midi_menu(ui));
+            }
+            if collect_assets {
+                self.collect_assets();
+            }
+            ui.menu_button("View", |ui| self.view_menu(ui));
+            ui.menu_button("Audio", |ui| self.audio_menu(ui));
+        });
+    }
+
+    fn view_menu(&mut self, ui: &mut egui::Ui) {
+        let mut theme = self.settings.theme;
+        ui.horizontal(|ui| {
+            ui.label("Theme");
+            ui.selectable_value(&mut theme, Theme::Dark, "🌙 Dark");
+            ui.selectable_value(&mut theme, Theme::Light, "☀ Light");
+        });
+        if theme != self.settings.theme {
+            self.settings.theme = theme;
+            ui.ctx().set_theme(theme.egui());
+            self.save_settings();
+        }
+
+        let mut scale = self.settings.ui_scale;
+        egui::ComboBox::from_label("UI scale")
+            .selected_text(format!("{:.0}%", scale * 100.0))
+            .show_ui(ui, |ui| {
+                for option in UI_SCALES {
+                    ui.selectable_value(&mut scale, option, format!("{:.0}%", option * 100.0));
                 }
+            });
+        if scale != self.settings.ui_scale {
+            ui.ctx().set_zoom_factor(scale);
+            self.settings.ui_scale = scale;
+            self.save_settings();
+        }
+    }
+
+    fn audio_menu(&mut self, ui: &mut egui::Ui) {
+        let mut settings = self.audio_settings.clone();
+        for backend in AudioBackend::available() {
+            ui.radio_value(&mut settings.backend, backend, format!("{:?}", backend));
+        }
+        ui.checkbox(&mut settings.track_outputs, "Separate track outputs")
+            .on_hover_text("Master on outputs 1-2, then one stereo pair per track");
+        ui.horizontal(|ui| {
+            ui.label("Latency offset");
+            ui.add(
+                egui::DragValue::new(&mut settings.latency_offset_ms)
+                    .range(-500.0..=500.0)
+                    .suffix(" ms"),
+            )
+            .on_hover_text(
+                "Added to the latency the device reports, so the playhead and synced \
+                 starts line up with what's heard",
+            );
+            if let Some(seconds) = self.output_latency {
+                ui.weak(format!("device: {:.1} ms", seconds * 1000.0));
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("Render threads");
+            let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+            let mut threads = settings.render_threads.max(1);
+            ui.add(egui::DragValue::new(&mut threads).range(1..=cores))
+                .on_hover_text("Tracks are shared out between this many threads");
+            if threads != settings.render_threads.max(1) {
+                settings.render_threads = threads;
+            }
+        });
+        ui.checkbox(&mut settings.bypass_output_guard, "Bypass output safety")
+            .on_hover_text(
+                "Turns off the DC blocker and the limiter keeping the output under 0 dBFS. \
+                 Only for measuring, or when something after aurio protects the speakers",
+            );
+        ui.separator();
+        ui.checkbox(&mut self.show_mixer, "🎚 Mixer");
+        ui.checkbox(&mut self.show_log, "📜 Log");
+        ui.checkbox(&mut self.patch_editor.visible, "🔌 Patch Editor")
+            .on_hover_text("Build a .au patch and play it over the project");
+        if ui
+            .checkbox(&mut self.keyboard.visible, "🎹 Virtual Keyboard")
+            .on_hover_text("Audition the selected track")
+            .changed()
+            && !self.keyboard.visible
+        {
+            let notes = self.keyboard.release_all();
+            self.send_all(notes);
+        }
+
+        if settings != self.audio_settings {
+            let _ = self
+                .engine
+                .command_tx
+                .send(EngineCommand::SetAudioSettings(settings.clone()));
+            if settings.backend != self.audio_settings.backend {
+                let _ = self.engine.command_tx.send(EngineCommand::ListAudioDevices);
+            }
+            self.audio_settings = settings;
+        }
+
+        ui.separator();
+        if ui.button("Device Settings…").clicked() {
+            let _ = self.engine.command_tx.send(EngineCommand::ListAudioDevices);
+            self.audio_dialog = Some(self.audio_settings.clone());
+            ui.close();
+        }
+    }
+
+    /// Name and position of the selected track, and copying or removing it.
+    fn track_controls(&mut self, ui: &mut egui::Ui, track_id: usize) {
+        let Some(project) = &mut self.current_project else {
+            return;
+        };
+        let track_count = project.tracks.len();
+        let Some(track) = project.tracks.get_mut(track_id) else {
+            return;
+        };
+
+        let renamed = ui
+            .horizontal(|ui| {
+                ui.label("Name");
+                ui.text_edit_singleline(&mut track.name).changed()
+            })
+            .inner;
+        let mut action = None;
+        ui.horizontal(|ui| {
+            let up = ui.add_enabled(track_id > 0, egui::Button::new("⬆"));
+            if up.on_hover_text("Move up").clicked() {
+                action = Some(TrackAction::Move {
+                    from: track_id,
+                    to: track_id - 1,
+                });
+            }
+            let down = ui.add_enabled(track_id + 1 < track_count, egui::Button::new("⬇"));
+            if down.on_hover_text("Move down").clicked() {
+                action = Some(TrackAction::Move {
+                    from: track_id,
+                    to: track_id + 1,
+                });
+            }
+            if ui.button("Duplicate").clicked() {
+                action = Some(TrackAction::Duplicate(track_id));
+            }
+            if ui.button("Remove").clicked() {
+                action = Some(TrackAction::Remove(track_id));
+            }
+        });
+
+        if renamed {
+            self.history.record(project);
+            self.project_modified = true;
+            let _ = self
+                .engine
+                .command_tx
+                .send(EngineCommand::ReloadProject(project.clone()));
+        }
+        if let Some(action) = action {
+            self.edit_tracks(action);
+        }
+    }
+
+    /// Applies a change to the track list and restarts the engine on it.
+    fn edit_tracks(&mut self, action: TrackAction) {
+        let Some(project) = &mut self.current_project else {
+            return;
+        };
+        let selected = match action {
+            TrackAction::Add => Some(project.add_track()),
+            TrackAction::Duplicate(index) => Some(project.duplicate_track(index)),
+            TrackAction::Remove(index) => {
+                project.remove_track(index);
+                let last = project.tracks.len().checked_sub(1);
+                last.map(|last| index.min(last))
+            }
+            TrackAction::Move { from, to } => {
+                project.move_track(from, to);
+                Some(to)
+            }
+        };
+        self.history.record(project);
+        self.tracks_restructured(selected);
+    }
+
+    /// Drops what the UI keeps per track index, which no longer lines up, and
+    /// sends the new track list to the engine.
+    fn tracks_restructured(&mut self, selected: Option<usize>) {
+        let Some(project) = &self.current_project else {
+            return;
+        };
+        self.project_modified = true;
+        self.selected_track = selected;
+        self.armed_track = None;
+        self.selected_node = None;
+        self.graph_focus = None;
+        self.current_nodes.clear();
+        self.playhead.clear();
+        self.piano_roll_states.clear();
+        self.morph_positions.clear();
+        self.script_errors.clear();
+        self.track_levels.clear();
+        self.clipped.clear();
+        let _ = self
+            .engine
+            .command_tx
+            .send(EngineCommand::RestructureProject(project.clone()));
+    }
+
+    /// Keeps the latest [`LOG_LINES`] records, whether the panel is open or
+    /// not.
+    fn receive_log(&mut self) {
+        for record in self.log_rx.try_iter() {
+            if self.log.len() == LOG_LINES {
+                self.log.pop_front();
+            }
+            self.log.push_back(record);
+        }
+    }
+
+    fn log_panel(&mut self, ctx: &egui::Context) {
+        egui::TopBottomPanel::bottom("log")
+            .resizable(true)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.heading("Log");
+                    if ui.button("Clear").clicked() {
+                        self.log.clear();
+                    }
+                });
+                egui::ScrollArea::vertical()
+                    .stick_to_bottom(true)
+                    .auto_shrink(false)
+                    .show(ui, |ui| {
+                        for record in &self.log {
+                            let text = egui::RichText::new(record.to_string()).monospace();
+                            match record.level {
+                                tracing::Level::ERROR => {
+                                    ui.label(text.color(egui::Color32::RED));
+                                }
+                                tracing::Level::WARN => {
+                                    ui.label(text.color(egui::Color32::YELLOW));
+                                }
+                                _ => {
+                                    ui.label(text);
+                                }
+                            }
+                        }
+                    });
+            });
+    }
+
+    /// One strip per track with its fader, pan, mute, solo, record arm, bus
+    /// sends and meter, then one per bus. Changes go to the engine as they're
+    /// made, without reloading the project.
+    fn mixer_panel(&mut self, ctx: &egui::Context) {
+        let Some(project) = &mut self.current_project else {
+            return;
+        };
+        let mut params = Vec::new();
+        let mut bus_volumes = Vec::new();
+        let mut buses_changed = false;
+        let mut renamed = false;
+
+        egui::TopBottomPanel::bottom("mixer")
+            .resizable(true)
+            .show(ctx, |ui| {
+                egui::ScrollArea::horizontal().show(ui, |ui| {
+                    ui.horizontal_top(|ui| {
+                        for (i, track) in project.tracks.iter_mut().enumerate() {
+                            ui.vertical(|ui| {
+                                ui.set_width(MIXER_STRIP_WIDTH);
+                                let selected = self.selected_track == Some(i);
+                                if ui.selectable_label(selected, &track.name).clicked() {
+                                    self.selected_track = Some(i);
+                                }
+                                let level = self.track_levels.get(i).copied().unwrap_or_default();
+                                level_meter(ui, level, Some(i), &mut self.clipped);
+                                if ui
+                                    .add(
+                                        egui::Slider::new(&mut track.volume, 0.0..=2.0)
+                                            .vertical()
+                                            .fixed_decimals(2),
+                                    )
+                                    .changed()
+                                {
+                                    params.push((i, TrackParam::Volume, track.volume));
+                                }
+                                if ui
+                                    .add(
+                                        egui::DragValue::new(&mut track.pan)
+                                            .range(-1.0..=1.0)
+                                            .speed(0.01)
+                                            .prefix("pan "),
+                                    )
+                                    .changed()
+                                {
+                                    params.push((i, TrackParam::Pan, track.pan));
+                                }
+                                ui.horizontal(|ui| {
+                                    if ui
+                                        .selectable_label(track.mute, "M")
+                                        .on_hover_text("Mute")
+                                        .clicked()
+                                    {
+                                        params.push((
+                                            i,
+                                            TrackParam::Mute,
+                                            !track.mute as u8 as f32,
+                                        ));
+                                    }
+                                    if ui
+                                        .selectable_label(track.solo, "S")
+                                        .on_hover_text("Solo")
+                                        .clicked()
+                                    {
+                                        params.push((
+                                            i,
+                                            TrackParam::Solo,
+                                            !track.solo as u8 as f32,
+                                        ));
+                                    }
+                                    let armed = self.armed_track == Some(i);
+                                    if ui
+                                        .selectable_label(armed, "R")
+                                        .on_hover_text("Arm for recording")
+                                        .clicked()
+                                    {
+                                        self.armed_track = if armed { None } else { Some(i) };
+                                    }
+                                });
+                                for (bus_index, bus) in project.buses.iter().enumerate() {
+                                    let mut level = track.send(bus_index);
+                                    if ui
+                                        .add(
+                                            egui::DragValue::new(&mut level)
+                                                .range(0.0..=1.0)
+                                                .speed(0.01)
+                                                .prefix(format!("{} ", bus.name)),
+                                        )
+                                        .changed()
+                                    {
+                                        params.push((i, TrackParam::Send(bus_index), level));
+                                    }
+                                }
+                            });
+                            ui.separator();
+                        }
+
+                        let mut remove = None;
+                        for (i, bus) in project.buses.iter_mut().enumerate() {
+                            ui.vertical(|ui| {
+                                ui.set_width(MIXER_STRIP_WIDTH);
+                                renamed |= ui.text_edit_singleline(&mut bus.name).changed();
+                                if ui
+                                    .add(
+                                        egui::Slider::new(&mut bus.volume, 0.0..=2.0)
+                                            .vertical()
+                                            .fixed_decimals(2),
+                                    )
+                                    .changed()
+                                {
+                                    bus_volumes.push((i, bus.volume));
+                                }
+                                if ui.small_button("✕ Remove").clicked() {
+                                    remove = Some(i);
+                                }
+                            });
+                            ui.separator();
+                        }
+                        if let Some(i) = remove {
+                            project.remove_bus(i);
+                            buses_changed = true;
+                        }
+                        if ui
+                            .button("➕ Bus")
+                            .on_hover_text("Add a return bus tracks can send to")
+                            .clicked()
+                        {
+                            project.add_bus();
+                            buses_changed = true;
+                        }
+                    });
+                });
+            });
+
+        for &(track_id, param, value) in &params {
+            if let Some(track) = project.tracks.get_mut(track_id) {
+                track.set_param(param, value);
+            }
+            let _ = self.engine.command_tx.send(EngineCommand::SetTrackParam {
+                track_id,
+                param,
+                value,
+            });
+        }
+        for &(bus, volume) in &bus_volumes {
+            let _ = self
+                .engine
+                .command_tx
+                .send(EngineCommand::SetBusVolume { bus, volume });
+        }
+        if buses_changed {
+            let _ = self
+                .engine
+                .command_tx
+                .send(EngineCommand::RestructureProject(project.clone()));
+        }
+        if !params.is_empty() || !bus_volumes.is_empty() || buses_changed || renamed {
+            self.history.record(project);
+            self.project_modified = true;
+        }
+    }
+
+    /// Volume, pan, oscillators, envelope and note response of the selected
+    /// track, applied to the running track as they're edited.
+    fn instrument_panel(&mut self, ui: &mut egui::Ui, track_id: usize) {
+        let Some(project) = &mut self.current_project else {
+            return;
+        };
+        let Some(track) = project.tracks.get_mut(track_id) else {
+            return;
+        };
+        let mut changed_param = None;
+        let mut instrument_changed = false;
+        let mut response_changed = false;
+
+        egui::CollapsingHeader::new("Instrument")
+            .id_salt(("instrument", track_id))
+            .default_open(true)
+            .show(ui, |ui| {
+                if ui
+                    .add(egui::Slider::new(&mut track.volume, 0.0..=2.0).text("Volume"))
+                    .changed()
+                {
+                    changed_param = Some((TrackParam::Volume, track.volume));
+                }
+                if ui
+                    .add(egui::Slider::new(&mut track.pan, -1.0..=1.0).text("Pan"))
+                    .changed()
+                {
+                    changed_param = Some((TrackParam::Pan, track.pan));
+                }
+
+                ui.separator();
+                match &mut track.instrument {
+                    Instrument::MultiOsc {
+                        oscillators,
+                        spread,
+                    } => {
+                        let mut remove = None;
+                        for (i, osc) in oscillators.iter_mut().enumerate() {
+                            ui.horizontal(|ui| {
+                                egui::ComboBox::from_id_salt(("wave", track_id, i))
+                                    .width(60.0)
+                                    .selected_text(format!("{:?}", osc.wave))
+                                    .show_ui(ui, |ui| {
+                                        for wave in [Wave::Sine, Wave::Square, Wave::Saw] {
+                                            instrument_changed |= ui
+                                                .selectable_value(
+                                                    &mut osc.wave,
+                                                    wave,
+                                                    format!("{:?}", wave),
+                                                )
+                                                .changed();
+                                        }
+                                    });
+                                instrument_changed |= ui
+                                    .add(
+                                        egui::DragValue::new(&mut osc.gain)
+                                            .range(0.0..=1.0)
+                                            .speed(0.01)
+                                            .prefix("gain "),
+                                    )
+                                    .changed();
+                                instrument_changed |= ui
+                                    .add(
+                                        egui::DragValue::new(&mut osc.semitone)
+                                            .range(-48..=48)
+                                            .suffix(" st"),
+                                    )
+                                    .changed();
+                                if ui.small_button("✕").clicked() {
+                                    remove = Some(i);
+                                }
+                            });
+                        }
+                        if let Some(i) = remove {
+                            oscillators.remove(i);
+                            instrument_changed = true;
+                        }
+                        if ui.button("➕ Oscillator").clicked() {
+                            oscillators.push(OscConfig {
+                                wave: Wave::Sine,
+                                gain: 0.3,
+                                semitone: 0,
+                                pan: 0.0,
+                            });
+                            instrument_changed = true;
+                        }
+                        instrument_changed |= ui
+                            .add(egui::Slider::new(spread, 0.0..=1.0).text("Spread"))
+                            .changed();
+                    }
+                    Instrument::Sampler {
+                        zones,
+                        interpolation,
+                    } => {
+                        ui.label(format!("Sampler, {} zones", zones.len()));
+                        ui.horizontal(|ui| {
+                            ui.label("Interpolation");
+                            for (mode, label) in [
+                                (Interpolation::Sinc, "Sinc"),
+                                (Interpolation::Linear, "Linear"),
+                            ] {
+                                instrument_changed |=
+                                    ui.selectable_value(interpolation, mode, label).changed();
+                            }
+                        });
+                    }
+                    Instrument::Audio => {
+                        ui.label("Plays audio clips");
+                    }
+                    Instrument::Plugin(plugin) => {
+                        ui.label(format!("Plugin: {}", plugin.id));
+                    }
+                }
+
+                ui.separator();
+                let adsr = &mut track.adsr;
+                for (value, max, label) in [
+                    (&mut adsr.attack, 5.0, "Attack"),
+                    (&mut adsr.decay, 5.0, "Decay"),
+                    (&mut adsr.sustain, 1.0, "Sustain"),
+                    (&mut adsr.release, 10.0, "Release"),
+                ] {
+                    let slider = egui::Slider::new(value, 0.0..=max).text(label);
+                    let slider = if label == "Sustain" {
+                        slider
+                    } else {
+                        slider.logarithmic(true).suffix(" s")
+                    };
+                    instrument_changed |= ui.add(slider).changed();
+                }
+                for (value, label) in [
+                    (&mut adsr.attack_curve, "Attack curve"),
+                    (&mut adsr.decay_curve, "Decay curve"),
+                    (&mut adsr.release_curve, "Release curve"),
+                ] {
+                    instrument_changed |= ui
+                        .add(egui::Slider::new(value, -1.0..=1.0).text(label))
+                        .changed();
+                }
+                adsr_preview(ui, adsr);
+
+                ui.horizontal(|ui| {
+                    ui.label("At node end");
+                    for (mode, label) in [
+                        (ChokeMode::Release, "Release notes"),
+                        (ChokeMode::Kill, "Cut notes"),
+                    ] {
+                        response_changed |=
+                            ui.selectable_value(&mut track.choke, mode, label).changed();
+                    }
+                });
+
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.label("Velocity");
+                    for (curve, label) in [
+                        (VelocityCurve::Soft, "Soft"),
+                        (VelocityCurve::Linear, "Linear"),
+                        (VelocityCurve::Hard, "Hard"),
+                    ] {
+                        response_changed |= ui
+                            .selectable_value(&mut track.velocity.curve, curve, label)
+                            .changed();
+                    }
+                });
+                let velocity = &mut track.velocity;
+                response_changed |= ui
+                    .add(egui::Slider::new(&mut velocity.min, 0.0..=1.0).text("Softest"))
+                    .changed();
+                response_changed |= ui
+                    .add(egui::Slider::new(&mut velocity.max, 0.0..=1.0).text("Loudest"))
+                    .changed();
+
+                let tracking = &mut track.key_tracking;
+                ui.horizontal(|ui| {
+                    ui.label("Key tracking around");
+                    response_changed |= ui
+                        .add(egui::DragValue::new(&mut tracking.center).range(0..=127))
+                        .changed();
+                });
+                response_changed |= ui
+                    .add(
+                        egui::Slider::new(&mut tracking.amp_db, -12.0..=12.0)
+                            .text("Level")
+                            .suffix(" dB/oct"),
+                    )
+                    .changed();
+                response_changed |= ui
+                    .add(
+                        egui::Slider::new(&mut tracking.envelope, -1.0..=1.0)
+                            .text("Envelope speed")
+                            .suffix("/oct"),
+                    )
+                    .changed();
+            });
+
+        if let Some((param, value)) = changed_param {
+            let _ = self.engine.command_tx.send(EngineCommand::SetTrackParam {
+                track_id,
+                param,
+                value,
+            });
+        }
+        if instrument_changed {
+            let _ = self.engine.command_tx.send(EngineCommand::SetInstrument {
+                track_id,
+                instrument: track.instrument.clone(),
+                adsr: track.adsr.clone(),
+            });
+        }
+        if response_changed {
+            let _ = self
+                .engine
+                .command_tx
+                .send(EngineCommand::ReloadProject(project.clone()));
+        }
+        if changed_param.is_some() || instrument_changed || response_changed {
+            self.history.record(project);
+            self.project_modified = true;
+        }
+    }
+
+    /// The selected track's CLAP instrument and effects with their parameters.
+    fn plugin_panel(&mut self, ui: &mut egui::Ui, track_id: usize) {
+        if let Some(scan) = &self.plugin_scan {
+            match scan.try_recv() {
+                Ok(found) => {
+                    self.plugin_catalog = found;
+                    self.plugin_scan = None;
+                }
+                Err(TryRecvError::Disconnected) => self.plugin_scan = None,
+                Err(TryRecvError::Empty) => {}
+            }
+        }
+        let Some(track) = self
+            .current_project
+            .as_mut()
+            .and_then(|p| p.tracks.get_mut(track_id))
+        else {
+            return;
+        };
+        let mut changed_param = None;
+        let mut structure_changed = false;
+
+        egui::CollapsingHeader::new("Plugins")
+            .id_salt(("plugins", track_id))
+            .show(ui, |ui| {
+                if let Some(plugin) = track.plugin_mut(PluginSlot::Instrument) {
+                    ui.label(format!("Instrument: {}", plugin.id));
+                    changed_param = plugin_params(ui, plugin, PluginSlot::Instrument);
+                }
+
+                let mut remove = None;
+                for (i, effect) in track.effects.iter_mut().enumerate() {
+                    ui.horizontal(|ui| {
+                        ui.label(format!("Effect {}: {}", i + 1, effect.id));
+                        if ui.small_button("✕").clicked() {
+                            remove = Some(i);
+                        }
+                    });
+                    let slot = PluginSlot::Effect(i);
+                    changed_param = changed_param.or(plugin_params(ui, effect, slot));
+                }
+                if let Some(i) = remove {
+                    track.effects.remove(i);
+                    structure_changed = true;
+                }
+
+                ui.separator();
+                if self.plugin_scan.is_some() {
+                    ui.horizontal(|ui| {
+                        ui.spinner();
+                        ui.label("Scanning for plugins…");
+                    });
+                } else if ui.button("🔍 Scan for plugins").clicked() {
+                    // Loading every plugin library can take seconds.
+                    let (found_tx, found_rx) = crossbeam::channel::bounded(1);
+                    let ctx = ui.ctx().clone();
+                    std::thread::spawn(move || {
+                        let _ = found_tx.send(plugin::scan());
+                        ctx.request_repaint();
+                    });
+                    self.plugin_scan = Some(found_rx);
+                }
+                for info in &self.plugin_catalog {
+                    ui.horizontal(|ui| {
+                        ui.label(&info.name).on_hover_text(format!(
+                            "{} by {}\n{}",
+                            info.id,
+                            info.vendor,
+                            info.path.display()
+                        ));
+                        let plugin_ref = || PluginRef {
+                            path: info.path.clone(),
+                            id: info.id.clone(),
+                            params: Vec::new(),
+                        };
+                        if info.instrument {
+                            if ui.small_button("Use as instrument").clicked() {
+                                track.instrument = Instrument::Plugin(plugin_ref());
+                                structure_changed = true;
+                            }
+                        } else if ui.small_button("Add effect").clicked() {
+                            track.effects.push(plugin_ref());
+                            structure_changed = true;
+                        }
+                    });
+                }
+                ui.weak("Added plugins load the next time playback starts.");
+            });
+
+        if let Some((slot, param_id, value)) = changed_param {
+            let _ = self.engine.command_tx.send(EngineCommand::SetPluginParam {
+                track_id,
+                slot,
+                param_id,
+                value,
+            });
+            self.project_modified = true;
+        }
+        if (changed_param.is_some() || structure_changed)
+            && let Some(project) = &self.current_project
+        {
+            self.history.record(project);
+        }
+        if structure_changed && let Some(project) = &self.current_project {
+            let _ = self
+                .engine
+                .command_tx
+                .send(EngineCommand::ReloadProject(project.clone()));
+            self.project_modified = true;
+        }
+    }
+
+    /// Output device, sample rate and buffer size, applied to the running stream.
+    fn audio_settings_dialog(&mut self, ctx: &egui::Context) {
+        let Some(draft) = &mut self.audio_dialog else {
+            return;
+        };
+        let mut open = true;
+        let mut apply = false;
+
+        egui::Window::new("Audio Settings")
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                let default_label = |value: &Option<String>| match value {
+                    Some(name) => name.clone(),
+                    None => "System default".to_string(),
+                };
+                egui::ComboBox::from_label("Output device")
+                    .selected_text(default_label(&draft.device_name))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut draft.device_name, None, "System default");
+                        for device in &self.audio_devices {
+                            ui.selectable_value(
+                                &mut draft.device_name,
+                                Some(device.name.clone()),
+                                &device.name,
+                            );
+                        }
+                    });
+
+                let device = match &draft.device_name {
+                    Some(name) => self.audio_devices.iter().find(|d| &d.name == name),
+                    None => self.audio_devices.iter().find(|d| d.is_default),
+                };
+
+                let rate_label = |rate: Option<u32>| match rate {
+                    Some(rate) => format!("{} Hz", rate),
+                    None => "Device default".to_string(),
+                };
+                egui::ComboBox::from_label("Sample rate")
+                    .selected_text(rate_label(draft.sample_rate))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut draft.sample_rate, None, rate_label(None));
+                        for &rate in device.map_or(&[][..], |d| &d.sample_rates[..]) {
+                            ui.selectable_value(
+                                &mut draft.sample_rate,
+                                Some(rate),
+                                rate_label(Some(rate)),
+                            );
+                        }
+                    });
+
+                let (min, max) = device.and_then(|d| d.buffer_sizes).unwrap_or((16, 8192));
+                ui.horizontal(|ui| {
+                    let mut fixed = draft.buffer_size.is_some();
+                    if ui.checkbox(&mut fixed, "Fixed buffer size").changed() {
+                        draft.buffer_size = fixed.then_some(512u32.clamp(min, max));
+                    }
+                    if let Some(frames) = &mut draft.buffer_size {
+                        ui.add(
+                            egui::DragValue::new(frames)
+                                .range(min..=max)
+                                .suffix(" frames"),
+                        );
+                    }
+                });
+
+                ui.separator();
+                ui.horizontal(|ui| {
+                    if ui.button("Refresh").clicked() {
+                        let _ = self.engine.command_tx.send(EngineCommand::ListAudioDevices);
+                    }
+                    apply = ui.button("Apply").clicked();
+                });
+            });
+
+        if apply {
+            let _ = self.engine.command_tx.send(EngineCommand::SetAudioConfig {
+                device_name: draft.device_name.clone(),
+                sample_rate: draft.sample_rate,
+                buffer_size: draft.buffer_size,
+            });
+            self.audio_settings.device_name = draft.device_name.clone();
+            self.audio_settings.sample_rate = draft.sample_rate;
+            self.audio_settings.buffer_size = draft.buffer_size;
+        }
+        if !open {
+            self.audio_dialog = None;
+        }
+    }
+
+    /// Name, tempo, sample rate and template of a new project, created in a
+    /// folder picked once they're set.
+    fn new_project_dialog(&mut self, ctx: &egui::Context) {
+        let Some(draft) = &mut self.new_project_dialog else {
+            return;
+        };
+        let mut open = true;
+        let mut create = false;
+
+        egui::Window::new("New Project")
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Name");
+                    ui.text_edit_singleline(&mut draft.name);
+                });
+                ui.horizontal(|ui| {
+                    ui.label("BPM");
+                    ui.add(egui::DragValue::new(&mut draft.bpm).range(20.0..=300.0));
+                });
+                egui::ComboBox::from_label("Sample rate")
+                    .selected_text(format!("{} Hz", draft.sample_rate))
+                    .show_ui(ui, |ui| {
+                        for rate in PROJECT_SAMPLE_RATES {
+                            ui.selectable_value(
+                                &mut draft.sample_rate,
+                                rate,
+                                format!("{} Hz", rate),
+                            );
+                        }
+                    });
+                egui::ComboBox::from_label("Template")
+                    .selected_text(draft.template.name())
+                    .show_ui(ui, |ui| {
+                        for template in Template::ALL {
+                            ui.selectable_value(&mut draft.template, template, template.name());
+                        }
+                    });
+
+                ui.separator();
+                create = ui
+                    .add_enabled(!draft.name.trim().is_empty(), egui::Button::new("Create…"))
+                    .clicked();
+            });
+
+        if create
+            && let Some(folder) = rfd::FileDialog::new()
+                .set_title("Create the project in")
+                .pick_folder()
+        {
+            let name = draft.name.trim();
+            let path = folder.join(format!("{}.aurio", name));
+            let mut project = Project::from_template(draft.template, name);
+            project.bpm = draft.bpm;
+            project.sample_rate = draft.sample_rate;
+
+            let result = if ProjectFormat::detect(&path).is_some() {
+                Err(format!("{} already contains a project", path.display()))
+            } else {
+                project.save(&path).map_err(|e| e.to_string())
+            };
+            match result {
+                Ok(()) => {
+                    self.open_project(path);
+                    open = false;
+                }
+                Err(e) => {
+                    self.error_message = Some(format!("Failed to create project: {}", e));
+                }
+            }
+        }
+        if !open {
+            self.new_project_dialog = None;
+        }
+    }
+
+    /// The track controller notes play on, and MIDI learn: pick a target,
+    /// then move a control on the controller.
+    fn midi_menu(&mut self, ui: &mut egui::Ui) {
+        self.midi_input_settings(ui);
+        let Some(project) = &self.current_project else {
+            return;
+        };
+        let mut learn = None;
+
+        if let Some(target) = &self.midi_learning {
+            ui.label(format!(
+                "Move a control or play a note to map {}…",
+                target.label()
+            ));
+            if ui.button("Cancel").clicked() {
+                let _ = self.engine.command_tx.send(EngineCommand::CancelMidiLearn);
+            }
+            ui.separator();
+        }
+
+        ui.menu_button("Learn", |ui| {
+            for track in &project.tracks {
+                ui.menu_button(&track.name, |ui| {
+                    if ui.button("Volume").clicked() {
+                        learn = Some(MidiTarget::TrackVolume(track.id));
+                    }
+                    if ui.button("Pan").clicked() {
+                        learn = Some(MidiTarget::TrackPan(track.id));
+                    }
+                    if track.morph.is_some() && ui.button("Morph").clicked() {
+                        learn = Some(MidiTarget::TrackMorph(track.id));
+                    }
+                    ui.menu_button("Go to", |ui| {
+                        for node in track.graphs().flat_map(|graph| &graph.nodes) {
+                            ui.menu_button(&node.id, |ui| {
+                                for timing in [
+                                    TransitionTiming::Immediate,
+                                    TransitionTiming::NextBeat,
+                                    TransitionTiming::NextBar,
+                                    TransitionTiming::FinishSequence,
+                                ] {
+                                    if ui.button(format!("{:?}", timing)).clicked() {
+                                        learn = Some(MidiTarget::Transition {
+                                            track_id: track.id,
+                                            node_id: node.id.clone(),
+                                            timing,
+                                        });
+                                    }
+                                }
+                            });
+                        }
+                    });
+                });
+            }
+            ui.separator();
+            for action in [
+                TransportAction::Play,
+                TransportAction::Pause,
+                TransportAction::Stop,
+            ] {
+                if ui.button(format!("{:?}", action)).clicked() {
+                    learn = Some(MidiTarget::Transport(action));
+                }
+            }
+            if ui.button("Metronome").clicked() {
+                learn = Some(MidiTarget::Metronome);
+            }
+            if let Some(arrangement) = &project.arrangement {
+                ui.menu_button("Section", |ui| {
+                    for node in &arrangement.graph.nodes {
+                        if ui.button(&node.id).clicked() {
+                            learn = Some(MidiTarget::Section(node.id.clone()));
+                        }
+                    }
+                });
+            }
+            ui.separator();
+            ui.horizontal(|ui| {
+                ui.add(
+                    egui::TextEdit::singleline(&mut self.midi_variable)
+                        .hint_text("variable")
+                        .desired_width(100.0),
+                );
+                let name = self.midi_variable.trim();
+                if ui
+                    .add_enabled(!name.is_empty(), egui::Button::new("Learn"))
+                    .clicked()
+                {
+                    learn = Some(MidiTarget::Variable(name.to_string()));
+                }
+            });
+        });
+
+        if !project.midi_mappings.is_empty() {
+            ui.separator();
+        }
+        let mut clear = None;
+        for mapping in &project.midi_mappings {
+            ui.horizontal(|ui| {
+                ui.label(format!(
+                    "{} → {}",
+                    mapping.source_label(),
+                    mapping.target.label()
+                ));
+                if ui
+                    .small_button("✕")
+                    .on_hover_text("Remove mapping")
+                    .clicked()
+                {
+                    clear = Some(mapping.target.clone());
+                }
+            });
+        }
+
+        if let Some(target) = learn {
+            let _ = self
+                .engine
+                .command_tx
+                .send(EngineCommand::MidiLearn { target });
+            ui.close();
+        }
+        if let Some(target) = clear {
+            let _ = self
+                .engine
+                .command_tx
+                .send(EngineCommand::ClearMidiMapping { target });
+        }
+    }
+
+    fn midi_input_settings(&mut self, ui: &mut egui::Ui) {
+        let Some(project) = &mut self.current_project else {
+            return;
+        };
+        let input = &mut project.midi_input;
+        let mut changed = false;
+
+        let track_name = |id: Option<usize>| {
+            id.and_then(|id| project.tracks.get(id))
+                .map_or("Nothing".to_string(), |t| t.name.clone())
+        };
+        ui.menu_button(format!("Notes play: {}", track_name(input.track)), |ui| {
+            changed |= ui
+                .selectable_value(&mut input.track, None, "Nothing")
+                .changed();
+            for track in &project.tracks {
+                changed |= ui
+                    .selectable_value(&mut input.track, Some(track.id), &track.name)
+                    .changed();
+            }
+        });
+        if ui
+            .checkbox(&mut input.mpe, "MPE")
+            .on_hover_text("For controllers that play each note on its own channel")
+            .changed()
+        {
+            input.bend_range = if input.mpe { 48.0 } else { 2.0 };
+            changed = true;
+        }
+        ui.horizontal(|ui| {
+            ui.label("Bend range");
+            changed |= ui
+                .add(
+                    egui::DragValue::new(&mut input.bend_range)
+                        .range(0.0..=96.0)
+                        .suffix(" st"),
+                )
+                .changed();
+        });
+        ui.separator();
 
-                let save_button = if self.project_modified {
-                    ui.button("💾 Save Project *")
-                } else {
-                    ui.button("💾 Save Project")
-                };
+        if changed {
+            let _ = self
+                .engine
+                .command_tx
+                .send(EngineCommand::ReloadProject(project.clone()));
+            self.history.record(project);
+            self.project_modified = true;
+        }
+    }
 
-                if save_button.clicked() {
-                    if let (Some(project), Some(path)) = (&self.current_project, &self.project_path)
-                    {
-                        match project.save(path) {
-                            Ok(_) => {
-                                self.project_modified = false;
-                                println!("Project saved successfully");
-                            }
-                            Err(e) => {
-                                self.error_message = Some(format!("Failed to save project: {}", e));
-                            }
-                        }
-                    }
-                    ui.close();
-                }
+    /// How Play starts: on the next bar of the shared tempo session, and after
+    /// a count-in of metronome bars.
+    fn start_options(&mut self, ui: &mut egui::Ui) {
+        let Some(project) = &mut self.current_project else {
+            return;
+        };
+        let mut changed = false;
+        ui.menu_button("Start", |ui| {
+            changed |= ui
+                .checkbox(&mut project.tempo_sync, "On next bar")
+                .on_hover_text(
+                    if cfg!(feature = "link") {
+                        "Join the Ableton Link session and start on its next bar, at its tempo"
+                    } else {
+                        "Start on the next bar of the engine's beat grid, in time with what it played before"
+                    },
+                )
+                .changed();
+            ui.label("Count-in");
+            for bars in 0..=Project::MAX_COUNT_IN_BARS {
+                let label = match bars {
+                    0 => "Off".to_string(),
+                    1 => "1 bar".to_string(),
+                    n => format!("{} bars", n),
+                };
+                changed |= ui
+                    .radio_value(&mut project.count_in_bars, bars, label)
+                    .changed();
+            }
+        });
 
-                ui.separator();
+        if changed {
+            let _ = self
+                .engine
+                .command_tx
+                .send(EngineCommand::ReloadProject(project.clone()));
+            self.history.record(project);
+            self.project_modified = true;
+        }
+    }
 
-                if ui.button("Quit").clicked() {
-                    std::process::exit(0);
+    /// The arrangement's sections, the one playing highlighted. Clicking one
+    /// goes there once the current section ends.
+    fn section_buttons(&mut self, ui: &mut egui::Ui) {
+        let Some(arrangement) = self
+            .current_project
+            .as_ref()
+            .and_then(|project| project.arrangement.as_ref())
+        else {
+            return;
+        };
+        ui.horizontal_wrapped(|ui| {
+            ui.label("Sections");
+            for node in &arrangement.graph.nodes {
+                let playing = self.current_section.as_ref() == Some(&node.id);
+                if ui.selectable_label(playing, &node.id).clicked() {
+                    let _ = self.engine.command_tx.send(EngineCommand::GoToSection {
+                        section: node.id.clone(),
+                    });
                 }
-            });
-            if let Some(project) = &self.current_project {
-                ui.menu_button("Project", |ui| {
-                    let title = if self.project_modified {
-                        format!("{} *", project.name)
-                    } else {
-                        project.name.clone()
-                    };
-                    let _ = ui.button(title);
-                });
             }
         });
     }
 
-    fn transport_controls(&self, ui: &mut egui::Ui) {
+    fn transport_controls(&mut self, ui: &mut egui::Ui) {
         ui.horizontal(|ui| {
             if self.playing {
                 if ui.button("⏸ Pause").clicked() {
@@ -135,10 +1939,82 @@ impl AurioApp {
             if ui.button("⏹ Stop").clicked() {
                 let _ = self.engine.command_tx.send(EngineCommand::Stop);
             }
+
+            if self.recording.is_some() {
+                let stop = egui::Button::new("⏺ Stop Recording").fill(egui::Color32::DARK_RED);
+                if ui.add(stop).clicked() {
+                    let _ = self.engine.command_tx.send(EngineCommand::StopRecording);
+                }
+            } else if let Some(track_id) = self.armed_track.or(self.selected_track)
+                && ui
+                    .button("⏺ Record")
+                    .on_hover_text("Record the audio input into a new sample")
+                    .clicked()
+            {
+                let _ = self
+                    .engine
+                    .command_tx
+                    .send(EngineCommand::RecordInput { track_id });
+            }
+
+            let mut metronome = self.metronome;
+            if ui.checkbox(&mut metronome, "Metronome").changed() {
+                let _ = self
+                    .engine
+                    .command_tx
+                    .send(EngineCommand::SetMetronome { enabled: metronome });
+            }
+
+            self.start_options(ui);
         });
+
+        if self.dropped_events > 0 {
+            let location = match (&self.last_dropped, &self.current_project) {
+                (Some((track_id, node_id)), Some(project)) => project
+                    .tracks
+                    .get(*track_id)
+                    .map(|track| format!(", last on {} / {}", track.name, node_id)),
+                _ => None,
+            };
+            let warning = ui
+                .add(
+                    egui::Label::new(
+                        egui::RichText::new(format!(
+                            "⚠ {} events dropped{}",
+                            self.dropped_events,
+                            location.unwrap_or_default()
+                        ))
+                        .color(egui::Color32::YELLOW),
+                    )
+                    .sense(egui::Sense::click()),
+                )
+                .on_hover_text("Too many notes at once for the event buffer. Click to dismiss");
+            if warning.clicked() {
+                self.dropped_events = 0;
+                self.last_dropped = None;
+            }
+        }
+
+        if self.playing {
+            match &self.audio_device {
+                Some(device) => {
+                    ui.weak(format!("🔈 {}", device));
+                }
+                None => {
+                    ui.colored_label(
+                        egui::Color32::YELLOW,
+                        "⚠ Audio device lost, waiting for it to reconnect…",
+                    );
+                }
+            }
+        }
     }
 
-    fn draw_graph(&mut self, ui: &mut egui::Ui, track: &TrackData) {
+    /// Draws a track's state graph and turns pointer input on it into edits:
+    /// double-click adds a node, dragging a node moves it, dragging from a
+    /// node's port to another node connects them, clicking a transition's
+    /// label edits it and right-clicking a node offers the rest.
+    fn draw_graph(&mut self, ui: &mut egui::Ui, track: &TrackData) -> Vec<GraphEdit> {
         let (response, painter) = ui.allocate_painter(
             egui::Vec2::new(ui.available_width(), ui.available_height()),
             egui::Sense::click_and_drag(),
@@ -148,43 +2024,89 @@ impl AurioApp {
             egui::Rect::from_min_size(egui::Pos2::ZERO, egui::Vec2::new(800.0, 600.0)),
             response.rect,
         );
+        let from_screen = to_screen.inverse();
+        let nodes = &track.graph.nodes;
+        let node_size = egui::Vec2::new(100.0, 60.0);
+        let node_rect = |i: usize| {
+            egui::Rect::from_center_size(
+                to_screen.transform_pos(node_position(i, &nodes[i])),
+                node_size,
+            )
+        };
+        let port = |i: usize| node_rect(i).right_center();
+        let node_at =
+            |pos: egui::Pos2| (0..nodes.len()).rev().find(|&i| node_rect(i).contains(pos));
+        let port_at = |pos: egui::Pos2| {
+            (0..nodes.len())
+                .rev()
+                .find(|&i| port(i).distance(pos) <= PORT_RADIUS + 2.0)
+        };
+        let mut edits = Vec::new();
+
+        if response.clicked() {
+            response.request_focus();
+        }
+        let has_focus = response.has_focus();
+        if has_focus {
+            self.handle_graph_keyboard(ui, &response, track);
+        }
+        let focused = if has_focus { self.graph_focus } else { None };
+        let mut description = graph_item_description(track, self.graph_focus);
+        if let Some(GraphItem::Node(idx)) = self.graph_focus
+            && let Some(node) = track.graph.nodes.get(idx)
+            && let Some(error) = self.script_errors.get(&(track.id, node.id.clone()))
+        {
+            description.push_str(&format!(", script error: {}", error));
+        }
+        response
+            .widget_info(|| egui::WidgetInfo::labeled(egui::WidgetType::Other, true, &description));
+        let focus_stroke = egui::Stroke::new(3.0, egui::Color32::YELLOW);
 
         let current_node = self.current_nodes.get(&track.id);
 
-        for edge in &track.graph.edges {
+        let mut edge_labels = Vec::new();
+        for (edge_idx, edge) in track.graph.edges.iter().enumerate() {
             if let (Some(from_idx), Some(to_idx)) = (
                 track.graph.nodes.iter().position(|n| n.id == edge.from),
                 track.graph.nodes.iter().position(|n| n.id == edge.to),
             ) {
-                let from_pos = node_position(from_idx);
-                let to_pos = node_position(to_idx);
-
-                let from_screen = to_screen.transform_pos(from_pos);
-                let to_screen_pos = to_screen.transform_pos(to_pos);
-
-                painter.arrow(
-                    from_screen,
-                    to_screen_pos - from_screen,
-                    egui::Stroke::new(2.0, egui::Color32::GRAY),
-                );
+                let stroke = if focused == Some(GraphItem::Edge(edge_idx))
+                    || self.editing_edge == Some(edge_idx)
+                {
+                    focus_stroke
+                } else {
+                    egui::Stroke::new(2.0, egui::Color32::GRAY)
+                };
 
-                let mid = (from_screen + to_screen_pos.to_vec2()) / 2.0;
-                painter.text(
-                    mid,
+                let label_pos = if from_idx == to_idx {
+                    // A loop back into the same node, drawn above it.
+                    let top = node_rect(from_idx).center_top();
+                    painter.circle_stroke(top - egui::vec2(0.0, 12.0), 12.0, stroke);
+                    top - egui::vec2(0.0, 32.0)
+                } else {
+                    let from = node_rect(from_idx).center();
+                    let to = node_rect(to_idx).center();
+                    painter.arrow(from, to - from, stroke);
+                    (from + to.to_vec2()) / 2.0
+                };
+                let text = match edge.weight {
+                    Some(weight) => format!("{} ({})", edge.condition, weight),
+                    None => edge.condition.clone(),
+                };
+                let label = painter.text(
+                    label_pos,
                     egui::Align2::CENTER_CENTER,
-                    &edge.condition,
+                    text,
                     egui::FontId::proportional(10.0),
                     egui::Color32::LIGHT_GRAY,
                 );
+                edge_labels.push((edge_idx, label.expand(4.0)));
             }
         }
 
         for (i, node) in track.graph.nodes.iter().enumerate() {
-            let pos = node_position(i);
-            let screen_pos = to_screen.transform_pos(pos);
-
-            let node_size = egui::Vec2::new(100.0, 60.0);
-            let rect = egui::Rect::from_center_size(screen_pos, node_size);
+            let rect = node_rect(i);
+            let screen_pos = rect.center();
 
             let fill_color = if current_node == Some(&node.id) {
                 egui::Color32::from_rgb(60, 180, 100)
@@ -194,13 +2116,30 @@ impl AurioApp {
                 egui::Color32::from_rgb(40, 40, 40)
             };
 
+            let error = self.script_errors.get(&(track.id, node.id.clone()));
+            let stroke = if focused == Some(GraphItem::Node(i)) {
+                focus_stroke
+            } else if error.is_some() {
+                egui::Stroke::new(2.0, egui::Color32::RED)
+            } else {
+                egui::Stroke::new(2.0, egui::Color32::WHITE)
+            };
             painter.rect_filled(rect, 5.0, fill_color);
-            painter.rect_stroke(
-                rect,
-                5.0,
-                egui::Stroke::new(2.0, egui::Color32::WHITE),
-                egui::StrokeKind::Inside,
-            );
+            if current_node == Some(&node.id)
+                && let Some(&beat) = self.playhead.get(track.id)
+            {
+                // How far through its sequence the node is, along its bottom edge.
+                let beats =
+                    node.sequence.duration_bars() as f32 * node.sequence.time_signature().0 as f32;
+                let progress = (beat / beats).clamp(0.0, 1.0);
+                let bar = egui::Rect::from_min_max(
+                    rect.left_bottom() - egui::vec2(0.0, 4.0),
+                    rect.left_bottom() + egui::vec2(rect.width() * progress, 0.0),
+                );
+                painter.rect_filled(bar, 2.0, egui::Color32::WHITE);
+            }
+            painter.rect_stroke(rect, 5.0, stroke, egui::StrokeKind::Inside);
+            painter.circle_filled(port(i), PORT_RADIUS, egui::Color32::LIGHT_BLUE);
 
             painter.text(
                 screen_pos,
@@ -213,36 +2152,793 @@ impl AurioApp {
             let seq_type = match &node.sequence {
                 crate::timing::Sequence::Static(_) => "Static",
                 crate::timing::Sequence::Generated(_) => "Lua",
+                crate::timing::Sequence::Clips(_) => "Clips",
+                crate::timing::Sequence::Chain(_) => "Chain",
+            };
+            let seq_label = match node.repeat {
+                Some(repeat) => format!("{} ×{}", seq_type, repeat),
+                None => seq_type.to_string(),
             };
             painter.text(
                 screen_pos + egui::Vec2::new(0.0, 15.0),
                 egui::Align2::CENTER_CENTER,
-                seq_type,
+                seq_label,
                 egui::FontId::proportional(10.0),
                 egui::Color32::LIGHT_GRAY,
             );
 
-            if response.clicked() {
-                if let Some(click_pos) = response.interact_pointer_pos() {
-                    if rect.contains(click_pos) {
-                        self.selected_node = Some((track.id, node.id.clone()));
+            if let Some(error) = error {
+                painter.text(
+                    rect.center_bottom() + egui::Vec2::new(0.0, 6.0),
+                    egui::Align2::CENTER_TOP,
+                    format!("⚠ {}", error),
+                    egui::FontId::proportional(10.0),
+                    egui::Color32::RED,
+                );
+            }
+        }
+
+        let pointer = response.interact_pointer_pos();
+        if response.drag_started()
+            && let Some(pos) = pointer
+        {
+            self.graph_drag = port_at(pos)
+                .map(GraphDrag::Connect)
+                .or(node_at(pos).map(GraphDrag::Node));
+        }
+        match self.graph_drag {
+            Some(GraphDrag::Node(i)) if i < nodes.len() && response.dragged() => {
+                let delta = response.drag_delta() / to_screen.scale();
+                edits.push(GraphEdit::MoveNode(i, node_position(i, &nodes[i]) + delta));
+            }
+            Some(GraphDrag::Connect(from)) if from < nodes.len() => {
+                if let Some(pos) = ui.ctx().pointer_latest_pos() {
+                    painter.line_segment([port(from), pos], focus_stroke);
+                    if response.drag_stopped()
+                        && let Some(to) = node_at(pos)
+                    {
+                        edits.push(GraphEdit::AddEdge(from, to));
+                    }
+                }
+            }
+            _ => {}
+        }
+        if response.drag_stopped() {
+            self.graph_drag = None;
+        }
+
+        if response.double_clicked()
+            && let Some(pos) = pointer
+            && node_at(pos).is_none()
+        {
+            edits.push(GraphEdit::AddNode(from_screen.transform_pos(pos)));
+        } else if response.clicked()
+            && let Some(pos) = pointer
+        {
+            let edge = edge_labels.iter().find(|(_, rect)| rect.contains(pos));
+            if let Some(i) = node_at(pos) {
+                self.selected_node = Some((track.id, nodes[i].id.clone()));
+                self.graph_focus = Some(GraphItem::Node(i));
+            } else if let Some(&(i, _)) = edge {
+                self.editing_edge = Some(i);
+                self.graph_focus = Some(GraphItem::Edge(i));
+            } else {
+                self.editing_edge = None;
+            }
+        }
+
+        if response.secondary_clicked() {
+            self.graph_menu_node = pointer.and_then(node_at);
+        }
+        response.context_menu(|ui| {
+            let Some(i) = self.graph_menu_node.filter(|&i| i < nodes.len()) else {
+                ui.weak("Double-click to add a node");
+                return;
+            };
+            let initial = nodes[i].id == track.initial_node;
+            if ui
+                .add_enabled(!initial, egui::Button::new("Set as initial node"))
+                .clicked()
+            {
+                edits.push(GraphEdit::SetInitial(i));
+                ui.close();
+            }
+            ui.horizontal(|ui| {
+                let mut repeat = nodes[i].repeat;
+                let mut repeats = repeat.is_some();
+                if ui
+                    .checkbox(&mut repeats, "Repeat")
+                    .on_hover_text("Plays this many times before following its transitions")
+                    .changed()
+                {
+                    repeat = repeats.then_some(2);
+                }
+                if let Some(times) = &mut repeat {
+                    ui.add(egui::DragValue::new(times).range(1..=64).suffix("×"));
+                }
+                if repeat != nodes[i].repeat {
+                    edits.push(GraphEdit::SetRepeat(i, repeat));
+                }
+            });
+            let remove = ui
+                .add_enabled(nodes.len() > 1, egui::Button::new("Remove node"))
+                .on_disabled_hover_text("A graph keeps at least one node to start from");
+            let remove = if initial {
+                remove.on_hover_text("The next node becomes the initial one")
+            } else {
+                remove
+            };
+            if remove.clicked() {
+                edits.push(GraphEdit::RemoveNode(i));
+                ui.close();
+            }
+        });
+
+        if let Some(i) = self.editing_edge
+            && let Some(&(_, label)) = edge_labels.iter().find(|(e, _)| *e == i)
+        {
+            let mut edge = track.graph.edges[i].clone();
+            let mut close = false;
+            egui::Area::new(egui::Id::new(("edge_popover", track.id)))
+                .fixed_pos(label.center_bottom())
+                .order(egui::Order::Foreground)
+                .show(ui.ctx(), |ui| {
+                    egui::Frame::popup(ui.style()).show(ui, |ui| {
+                        ui.label(format!("{} → {}", edge.from, edge.to));
+                        ui.horizontal(|ui| {
+                            ui.label("When");
+                            if ui.text_edit_singleline(&mut edge.condition).changed() {
+                                edits.push(GraphEdit::UpdateEdge(i, edge.clone()));
+                            }
+                        });
+                        ui.horizontal(|ui| {
+                            let mut weighted = edge.weight.is_some();
+                            if ui
+                                .checkbox(&mut weighted, "Weight")
+                                .on_hover_text(
+                                    "Picked at random among the weighted edges that pass, \
+                                     more often the heavier it is",
+                                )
+                                .changed()
+                            {
+                                edge.weight = weighted.then_some(1.0);
+                                edits.push(GraphEdit::UpdateEdge(i, edge.clone()));
+                            }
+                            if let Some(weight) = &mut edge.weight
+                                && ui
+                                    .add(
+                                        egui::DragValue::new(weight).speed(0.05).range(0.0..=100.0),
+                                    )
+                                    .changed()
+                            {
+                                edits.push(GraphEdit::UpdateEdge(i, edge.clone()));
+                            }
+                        });
+                        egui::ComboBox::from_label("Timing")
+                            .selected_text(format!("{:?}", edge.timing))
+                            .show_ui(ui, |ui| {
+                                for timing in [
+                                    TransitionTiming::Immediate,
+                                    TransitionTiming::NextBeat,
+                                    TransitionTiming::NextBar,
+                                    TransitionTiming::FinishSequence,
+                                ] {
+                                    if ui
+                                        .selectable_value(
+                                            &mut edge.timing,
+                                            timing,
+                                            format!("{:?}", timing),
+                                        )
+                                        .changed()
+                                    {
+                                        edits.push(GraphEdit::UpdateEdge(i, edge.clone()));
+                                    }
+                                }
+                            });
+                        ui.horizontal(|ui| {
+                            if ui.button("Remove").clicked() {
+                                edits.push(GraphEdit::RemoveEdge(i));
+                                close = true;
+                            }
+                            close |= ui.button("Close").clicked();
+                        });
+                    });
+                });
+            if close {
+                self.editing_edge = None;
+            }
+        }
+
+        edits
+    }
+
+    /// The layer of `track_id` the graph editor shows, if not its own graph.
+    fn shown_layer(&self, track_id: usize) -> Option<usize> {
+        self.graph_layer
+            .filter(|&(track, _)| track == track_id)
+            .map(|(_, layer)| layer)
+    }
+
+    /// Tabs picking which of a track's graphs the editor shows, with buttons
+    /// adding and removing layers. The engine runs a set number of graphs per
+    /// track, so a layer added or removed restarts playback.
+    fn layer_tabs(&mut self, ui: &mut egui::Ui, track_id: usize) {
+        let before = self.shown_layer(track_id);
+        let Some(project) = &mut self.current_project else {
+            return;
+        };
+        let Some(track) = project.tracks.get_mut(track_id) else {
+            return;
+        };
+        let mut shown = before.filter(|&layer| layer < track.layers.len());
+        let mut changed = false;
+        ui.horizontal(|ui| {
+            if ui.selectable_label(shown.is_none(), "Track").clicked() {
+                shown = None;
+            }
+            for (i, layer) in track.layers.iter().enumerate() {
+                if ui.selectable_label(shown == Some(i), &layer.name).clicked() {
+                    shown = Some(i);
+                }
+            }
+            if ui
+                .button("➕ Layer")
+                .on_hover_text("Run another graph alongside the track's, sharing its variables")
+                .clicked()
+            {
+                shown = Some(track.add_layer());
+                changed = true;
+            }
+            if let Some(i) = shown
+                && ui.button("🗑 Remove Layer").clicked()
+            {
+                track.layers.remove(i);
+                shown = None;
+                changed = true;
+            }
+        });
+
+        if shown != before {
+            self.graph_focus = None;
+            self.graph_menu_node = None;
+            self.editing_edge = None;
+        }
+        self.graph_layer = shown.map(|layer| (track_id, layer));
+        if changed {
+            self.history.record(project);
+            self.project_modified = true;
+            let _ = self
+                .engine
+                .command_tx
+                .send(EngineCommand::RestructureProject(project.clone()));
+        }
+    }
+
+    /// Applies edits made on the graph of a track, or of the layer shown.
+    /// Moving nodes only changes the layout; anything else goes to the engine
+    /// too.
+    fn apply_graph_edits(&mut self, track_id: usize, edits: Vec<GraphEdit>) {
+        let layer = self.shown_layer(track_id);
+        let Some(project) = &mut self.current_project else {
+            return;
+        };
+        let Some(track) = project.tracks.get_mut(track_id) else {
+            return;
+        };
+        if edits.is_empty() {
+            return;
+        }
+        let mut layout_only = true;
+        for edit in edits {
+            if !matches!(edit, GraphEdit::MoveNode(..)) {
+                layout_only = false;
+            }
+            let node_id = track.free_node_id();
+            let (graph, initial_node) = track.layer_graph_mut(layer);
+            match edit {
+                GraphEdit::AddNode(pos) => {
+                    graph.insert_node(node_id, (pos.x, pos.y));
+                }
+                GraphEdit::MoveNode(i, pos) => {
+                    if let Some(node) = graph.nodes.get_mut(i) {
+                        node.position = Some((pos.x, pos.y));
+                    }
+                }
+                GraphEdit::RemoveNode(i) => {
+                    if graph.nodes.len() < 2 {
+                        continue;
+                    }
+                    let id = graph.nodes[i].id.clone();
+                    graph.remove_node(&id);
+                    if *initial_node == id {
+                        *initial_node = graph.nodes[i.min(graph.nodes.len() - 1)].id.clone();
+                    }
+                    if self.selected_node.as_ref().is_some_and(|(_, n)| *n == id) {
+                        self.selected_node = None;
+                    }
+                    self.graph_focus = None;
+                    self.editing_edge = None;
+                }
+                GraphEdit::SetInitial(i) => {
+                    *initial_node = graph.nodes[i].id.clone();
+                }
+                GraphEdit::SetRepeat(i, repeat) => {
+                    if let Some(node) = graph.nodes.get_mut(i) {
+                        node.repeat = repeat;
+                    }
+                }
+                GraphEdit::AddEdge(from, to) => {
+                    graph.edges.push(Edge {
+                        from: graph.nodes[from].id.clone(),
+                        to: graph.nodes[to].id.clone(),
+                        condition: "true".to_string(),
+                        timing: TransitionTiming::FinishSequence,
+                        inlet_hook: None,
+                        weight: None,
+                    });
+                }
+                GraphEdit::UpdateEdge(i, edge) => {
+                    if let Some(slot) = graph.edges.get_mut(i) {
+                        *slot = edge;
+                    }
+                }
+                GraphEdit::RemoveEdge(i) => {
+                    graph.edges.remove(i);
+                    self.graph_focus = None;
+                    self.editing_edge = None;
+                }
+            }
+        }
+
+        self.history.record(project);
+        self.project_modified = true;
+        if !layout_only {
+            let _ = self
+                .engine
+                .command_tx
+                .send(EngineCommand::ReloadProject(project.clone()));
+        }
+    }
+
+    /// Panel for a selected Lua pattern: its code, seed and a preview of the
+    /// notes it gives. Edited code goes to the engine once the editor loses
+    /// focus; code kept in a file is edited there and only shown here.
+    fn generated_pattern_panel(&mut self, ctx: &egui::Context) {
+        let Some((track_id, node_id)) = self.selected_node.clone() else {
+            return;
+        };
+        let Some(project) = &mut self.current_project else {
+            return;
+        };
+        let Some(pattern) = project
+            .tracks
+            .iter_mut()
+            .find(|t| t.id == track_id)
+            .and_then(|t| t.node_mut(&node_id))
+            .and_then(|n| match &mut n.sequence {
+                Sequence::Generated(pattern) => Some(pattern),
+                Sequence::Static(_) | Sequence::Clips(_) | Sequence::Chain(_) => None,
+            })
+        else {
+            return;
+        };
+        let selected = (track_id, node_id.clone());
+        if self
+            .pattern_preview
+            .as_ref()
+            .is_some_and(|preview| preview.node != selected)
+        {
+            self.pattern_preview = None;
+        }
+
+        let mut reroll = false;
+        let mut close = false;
+        let mut code_changed = false;
+        let mut editor_left = false;
+        let mut evaluate = None;
+        egui::TopBottomPanel::bottom("generated_pattern")
+            .resizable(true)
+            .default_height(300.0)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.heading(format!("Lua Pattern: {}", node_id));
+                    if ui.button("✕ Close").clicked() {
+                        close = true;
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label(match &pattern.file {
+                        Some(file) => format!("Code: {} (reloaded when the file changes)", file),
+                        None => "Code: inline".to_string(),
+                    });
+                    ui.separator();
+                    ui.label(format!("Seed: {}", pattern.seed));
+                    reroll = ui
+                        .button("🎲 Reroll")
+                        .on_hover_text("Pick a new seed for this pattern's random numbers")
+                        .clicked();
+                });
+
+                ui.columns(2, |columns| {
+                    egui::ScrollArea::vertical()
+                        .id_salt("lua_code")
+                        .show(&mut columns[0], |ui| {
+                            let mut layouter =
+                                |ui: &egui::Ui, code: &dyn egui::TextBuffer, wrap_width: f32| {
+                                    let job = lua_editor::highlight(ui, code.as_str(), wrap_width);
+                                    ui.fonts_mut(|fonts| fonts.layout_job(job))
+                                };
+                            let response = ui.add(
+                                egui::TextEdit::multiline(&mut pattern.function)
+                                    .code_editor()
+                                    .interactive(pattern.file.is_none())
+                                    .desired_width(f32::INFINITY)
+                                    .desired_rows(12)
+                                    .layouter(&mut layouter),
+                            );
+                            code_changed = response.changed();
+                            editor_left = response.lost_focus();
+                        });
+
+                    let ui = &mut columns[1];
+                    let preview = self.pattern_preview.as_mut();
+                    let mut loop_count = preview.as_ref().map_or(0, |p| p.loop_count);
+                    ui.horizontal(|ui| {
+                        if ui
+                            .button("▶ Evaluate")
+                            .on_hover_text("Run the code and preview the notes it gives")
+                            .clicked()
+                        {
+                            evaluate = Some(loop_count);
+                        }
+                        if ui
+                            .add(egui::DragValue::new(&mut loop_count).prefix("Loop "))
+                            .on_hover_text("Which time round the node is playing")
+                            .changed()
+                        {
+                            evaluate = Some(loop_count);
+                        }
+                    });
+                    match preview {
+                        Some(PatternPreview {
+                            notes: Ok(notes),
+                            piano_roll,
+                            ..
+                        }) => {
+                            ui.label(format!("{} notes", notes.notes.len()));
+                            PianoRoll::new(notes, piano_roll).read_only().show(ui);
+                        }
+                        Some(PatternPreview {
+                            notes: Err(error), ..
+                        }) => {
+                            ui.colored_label(egui::Color32::RED, format!("⚠ {}", error));
+                            if let Some(traceback) = &error.traceback {
+                                egui::CollapsingHeader::new("Stack traceback")
+                                    .id_salt("preview_traceback")
+                                    .show(ui, |ui| {
+                                        ui.monospace(traceback);
+                                    });
+                            }
+                        }
+                        None => {
+                            ui.weak("Evaluate the code to see its notes");
+                        }
                     }
+                });
+            });
+
+        if reroll {
+            pattern.seed = RandomState::new().hash_one(SystemTime::now());
+            self.history.record(project);
+            self.project_modified = true;
+            evaluate = evaluate.or(self.pattern_preview.as_ref().map(|p| p.loop_count));
+        }
+        if code_changed {
+            self.pattern_code_edited = true;
+            self.history.record(project);
+            self.project_modified = true;
+        }
+        if reroll || ((editor_left || evaluate.is_some()) && self.pattern_code_edited) {
+            self.pattern_code_edited = false;
+            let _ = self
+                .engine
+                .command_tx
+                .send(EngineCommand::ReloadProject(project.clone()));
+        }
+        if let Some(loop_count) = evaluate
+            && let Some(node) = project
+                .tracks
+                .iter()
+                .find(|t| t.id == track_id)
+                .and_then(|t| t.node(&node_id))
+        {
+            let notes = lua_editor::evaluate(project, track_id, node, loop_count);
+            let mut piano_roll = self
+                .pattern_preview
+                .take()
+                .map(|p| p.piano_roll)
+                .unwrap_or_default();
+            if let Ok(notes) = &notes {
+                piano_roll.fit_to_pattern(notes, egui::vec2(400.0, 200.0));
+            }
+            self.pattern_preview = Some(PatternPreview {
+                node: selected,
+                loop_count,
+                notes,
+                piano_roll,
+            });
+        }
+        if close {
+            self.selected_node = None;
+        }
+    }
+
+    /// Errors reported for a track's scripts, with their stack traces folded away.
+    fn script_error_list(&mut self, ui: &mut egui::Ui, track_id: usize) {
+        let mut errors: Vec<_> = self
+            .script_errors
+            .iter()
+            .filter(|((t, _), _)| *t == track_id)
+            .collect();
+        if errors.is_empty() {
+            return;
+        }
+        errors.sort_by(|a, b| a.0.cmp(b.0));
+
+        let mut dismissed = None;
+        for ((_, node_id), error) in errors {
+            ui.horizontal(|ui| {
+                ui.colored_label(egui::Color32::RED, format!("⚠ {}: {}", node_id, error));
+                if ui.small_button("✕").on_hover_text("Dismiss").clicked() {
+                    dismissed = Some((track_id, node_id.clone()));
+                }
+            });
+            if let Some(traceback) = &error.traceback {
+                egui::CollapsingHeader::new("Stack traceback")
+                    .id_salt(("traceback", track_id, node_id))
+                    .show(ui, |ui| {
+                        ui.monospace(traceback);
+                    });
+            }
+        }
+        if let Some(key) = dismissed {
+            self.script_errors.remove(&key);
+        }
+    }
+
+    /// Tab/Shift+Tab (or the arrow keys) cycle through nodes then edges, Enter
+    /// opens the focused node and Escape hands focus back to the rest of the UI.
+    fn handle_graph_keyboard(
+        &mut self,
+        ui: &egui::Ui,
+        response: &egui::Response,
+        track: &TrackData,
+    ) {
+        use egui::{Key, Modifiers};
+
+        ui.memory_mut(|mem| {
+            mem.set_focus_lock_filter(
+                response.id,
+                egui::EventFilter {
+                    tab: true,
+                    horizontal_arrows: true,
+                    vertical_arrows: true,
+                    escape: false,
+                },
+            )
+        });
+
+        let items: Vec<GraphItem> = (0..track.graph.nodes.len())
+            .map(GraphItem::Node)
+            .chain((0..track.graph.edges.len()).map(GraphItem::Edge))
+            .collect();
+        if items.is_empty() {
+            return;
+        }
+        let position = self
+            .graph_focus
+            .and_then(|item| items.iter().position(|i| *i == item));
+
+        let (forward, backward, enter, escape) = ui.input_mut(|i| {
+            (
+                i.consume_key(Modifiers::NONE, Key::Tab)
+                    || i.consume_key(Modifiers::NONE, Key::ArrowRight)
+                    || i.consume_key(Modifiers::NONE, Key::ArrowDown),
+                i.consume_key(Modifiers::SHIFT, Key::Tab)
+                    || i.consume_key(Modifiers::NONE, Key::ArrowLeft)
+                    || i.consume_key(Modifiers::NONE, Key::ArrowUp),
+                i.consume_key(Modifiers::NONE, Key::Enter),
+                i.consume_key(Modifiers::NONE, Key::Escape),
+            )
+        });
+
+        if forward {
+            let next = position.map_or(0, |p| (p + 1) % items.len());
+            self.graph_focus = Some(items[next]);
+        } else if backward {
+            let previous =
+                position.map_or(items.len() - 1, |p| (p + items.len() - 1) % items.len());
+            self.graph_focus = Some(items[previous]);
+        } else if position.is_none() {
+            self.graph_focus = Some(items[0]);
+        }
+
+        if enter && let Some(GraphItem::Node(idx)) = self.graph_focus {
+            self.selected_node = Some((track.id, track.graph.nodes[idx].id.clone()));
+        }
+        if escape {
+            response.surrender_focus();
+        }
+    }
+}
+
+/// Sliders for a plugin's parameters; returns the one the user moved.
+fn plugin_params(
+    ui: &mut egui::Ui,
+    plugin: &mut PluginRef,
+    slot: PluginSlot,
+) -> Option<(PluginSlot, u32, f64)> {
+    if plugin.params.is_empty() {
+        ui.weak("Parameters appear once the plugin has loaded.");
+        return None;
+    }
+    let mut changed = None;
+    egui::ScrollArea::vertical()
+        .id_salt((slot, plugin.id.as_str()))
+        .max_height(200.0)
+        .show(ui, |ui| {
+            for param in &mut plugin.params {
+                let slider =
+                    egui::Slider::new(&mut param.value, param.min..=param.max).text(&param.name);
+                if ui.add(slider).changed() {
+                    changed = Some((slot, param.id, param.value));
                 }
             }
+        });
+    changed
+}
+
+/// The envelope of a note held for a second, drawn to scale.
+fn adsr_preview(ui: &mut egui::Ui, adsr: &ADSRConfig) {
+    const HOLD: f32 = 1.0;
+
+    let (rect, _) = ui.allocate_exact_size(egui::vec2(160.0, 48.0), egui::Sense::hover());
+    let painter = ui.painter();
+    painter.rect_filled(rect, 2.0, egui::Color32::from_gray(30));
+
+    const STEPS: usize = 24;
+
+    let total = adsr.attack + adsr.decay + HOLD + adsr.release;
+    let mut start = 0.0;
+    let mut points = vec![rect.left_bottom()];
+    let mut segment = |duration: f32, level: &dyn Fn(f32) -> f32| {
+        for step in 1..=STEPS {
+            let time = duration * step as f32 / STEPS as f32;
+            points.push(egui::pos2(
+                rect.left() + rect.width() * (start + time) / total,
+                rect.bottom() - rect.height() * level(time),
+            ));
+        }
+        start += duration;
+    };
+    segment(adsr.attack, &|time| adsr.attack_level(time));
+    segment(adsr.decay, &|time| adsr.decay_level(time));
+    segment(HOLD, &|_| adsr.sustain);
+    segment(adsr.release, &|time| adsr.release_level(adsr.sustain, time));
+    painter.add(egui::Shape::line(
+        points,
+        egui::Stroke::new(1.5, egui::Color32::LIGHT_BLUE),
+    ));
+}
+
+/// Horizontal level meter: RMS as a bar, the peak as a tick, and a clip light
+/// that stays on until clicked.
+fn level_meter(
+    ui: &mut egui::Ui,
+    level: Level,
+    id: Option<usize>,
+    clipped: &mut HashSet<Option<usize>>,
+) {
+    // -60 dBFS at the left edge, 0 dBFS at the right.
+    let position = |amplitude: f32| {
+        let db = 20.0 * amplitude.max(1e-6).log10();
+        ((db + 60.0) / 60.0).clamp(0.0, 1.0)
+    };
+
+    let (rect, _) = ui.allocate_exact_size(egui::vec2(80.0, 8.0), egui::Sense::hover());
+    let painter = ui.painter();
+    painter.rect_filled(rect, 1.0, egui::Color32::from_gray(40));
+    let rms = position(level.rms);
+    let color = if rms > 0.9 {
+        egui::Color32::YELLOW
+    } else {
+        egui::Color32::GREEN
+    };
+    let mut bar = rect;
+    bar.set_width(rect.width() * rms);
+    painter.rect_filled(bar, 1.0, color);
+    let peak_x = rect.left() + rect.width() * position(level.peak);
+    painter.vline(
+        peak_x,
+        rect.y_range(),
+        egui::Stroke::new(1.0, egui::Color32::WHITE),
+    );
+
+    let (light, response) = ui.allocate_exact_size(egui::vec2(8.0, 8.0), egui::Sense::click());
+    let lit = clipped.contains(&id);
+    let color = if lit {
+        egui::Color32::RED
+    } else {
+        egui::Color32::from_gray(60)
+    };
+    ui.painter().circle_filled(light.center(), 4.0, color);
+    if lit && response.on_hover_text("Clipped, click to reset").clicked() {
+        clipped.remove(&id);
+    }
+}
+
+/// `track` with the graph and initial node of `layer` in place of its own,
+/// for the graph editor to draw.
+fn layer_view(track: &TrackData, layer: Option<usize>) -> TrackData {
+    let (graph, initial_node) = track.layer_graph(layer);
+    TrackData {
+        graph: graph.clone(),
+        initial_node: initial_node.to_string(),
+        ..track.clone()
+    }
+}
+
+/// Screen-reader text for the graph canvas and its focused item.
+fn graph_item_description(track: &TrackData, item: Option<GraphItem>) -> String {
+    match item {
+        Some(GraphItem::Node(idx)) if idx < track.graph.nodes.len() => {
+            let node = &track.graph.nodes[idx];
+            let kind = match &node.sequence {
+                Sequence::Static(_) => "static pattern",
+                Sequence::Generated(_) => "Lua pattern",
+                Sequence::Clips(_) => "audio clips",
+                Sequence::Chain(_) => "chain of sequences",
+            };
+            let outgoing = track.graph.get_outgoing_edges(&node.id).len();
+            format!(
+                "Node {}, {}, {} outgoing transitions{}",
+                node.id,
+                kind,
+                outgoing,
+                if node.id == track.initial_node {
+                    ", initial node"
+                } else {
+                    ""
+                }
+            )
+        }
+        Some(GraphItem::Edge(idx)) if idx < track.graph.edges.len() => {
+            let edge = &track.graph.edges[idx];
+            format!(
+                "Transition from {} to {} when {}, timing {:?}",
+                edge.from, edge.to, edge.condition, edge.timing
+            )
         }
+        _ => format!("State graph for track {}", track.name),
     }
 }
 
-fn node_position(index: usize) -> egui::Pos2 {
-    let spacing = 200.0;
-    let x = 100.0 + (index as f32 * spacing);
-    let y = 300.0;
-    egui::Pos2::new(x, y)
+/// Where a node sits on the 800×600 graph canvas: its saved position, or a
+/// slot in a row for nodes that were never moved.
+fn node_position(index: usize, node: &Node) -> egui::Pos2 {
+    match node.position {
+        Some((x, y)) => egui::Pos2::new(x, y),
+        None => egui::Pos2::new(100.0 + index as f32 * 200.0, 300.0),
+    }
 }
 
 impl eframe::App for AurioApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.apply_settings(ctx);
+        self.handle_close_request(ctx);
         self.process_engine_updates();
+        self.undo_shortcuts(ctx);
+        self.autosave(ctx);
 
         egui::TopBottomPanel::top("menu_bar").show(ctx, |ui| {
             self.menu_bar(ui);
@@ -254,6 +2950,30 @@ impl eframe::App for AurioApp {
             });
         }
 
+        if self.show_mixer {
+            self.mixer_panel(ctx);
+        }
+        self.receive_log();
+        if self.show_log {
+            self.log_panel(ctx);
+        }
+        let patch_commands = self.patch_editor.show(ctx);
+        self.send_all(patch_commands);
+
+        let notes = self.keyboard.handle_keys(ctx, self.selected_track);
+        self.send_all(notes);
+        if self.keyboard.visible {
+            egui::TopBottomPanel::bottom("keyboard").show(ctx, |ui| match self.selected_track {
+                Some(track_id) => {
+                    let notes = self.keyboard.show(ui, track_id);
+                    self.send_all(notes);
+                }
+                None => {
+                    ui.weak("Select a track to play it from here");
+                }
+            });
+        }
+
         let mut close_piano_roll = false;
         let mut modified_pattern: Option<(usize, String, StaticPattern)> = None;
 
@@ -261,7 +2981,7 @@ impl eframe::App for AurioApp {
             if let Some((track_id, node_id)) = &self.selected_node {
                 if let Some(ref project) = self.current_project {
                     if let Some(track) = project.tracks.iter().find(|t| t.id == *track_id) {
-                        if let Some(node) = track.graph.get_node(node_id) {
+                        if let Some(node) = track.node(node_id) {
                             if let Sequence::Static(pattern) = &node.sequence {
                                 Some((*track_id, node_id.clone(), pattern.clone(), node.id.clone()))
                             } else {
@@ -284,8 +3004,8 @@ impl eframe::App for AurioApp {
             let state_key = (track_id, node_id.clone());
             let state = self
                 .piano_roll_states
-                .entry(state_key)
-                .or_insert_with(PianoRollState::default);
+                .entry(state_key.clone())
+                .or_insert_with(|| self.settings.piano_roll.state());
             if state.vertical_zoom == 20.0 && state.horizontal_zoom == 50.0 {
                 state.fit_to_pattern(&pattern, egui::Vec2::new(800.0, 300.0));
             }
@@ -299,29 +3019,52 @@ impl eframe::App for AurioApp {
                         }
                     });
 
-                    let response = PianoRoll::new(&mut pattern, state).show(ui);
+                    let playhead = self
+                        .playhead
+                        .get(track_id)
+                        .filter(|_| self.current_nodes.get(&track_id) == Some(&node_id));
+                    let response = PianoRoll::new(&mut pattern, state)
+                        .playhead(playhead.copied())
+                        .show(ui);
 
                     if response.modified {
                         self.project_modified = true;
                         modified_pattern = Some((track_id, node_id, pattern));
                     }
                 });
-        }
 
-        if let Some((track_id, node_id, new_pattern)) = modified_pattern {
-            if let Some(ref mut project) = self.current_project {
-                if let Some(track) = project.tracks.iter_mut().find(|t| t.id == track_id) {
-                    if let Some(node) = track.graph.nodes.iter_mut().find(|n| n.id == node_id) {
-                        node.sequence = Sequence::Static(new_pattern);
-                        let _ = self
-                            .engine
-                            .command_tx
-                            .send(EngineCommand::ReloadProject(project.clone()));
-                    }
-                }
+            // The last snap and follow picked are where new piano rolls start.
+            if let Some(state) = self.piano_roll_states.get(&state_key)
+                && (state.snap, state.follow)
+                    != (
+                        self.settings.piano_roll.snap,
+                        self.settings.piano_roll.follow,
+                    )
+            {
+                self.settings.piano_roll.snap = state.snap;
+                self.settings.piano_roll.follow = state.follow;
+                self.save_settings();
             }
         }
 
+        if let Some((track_id, node_id, new_pattern)) = modified_pattern
+            && let Some(ref mut project) = self.current_project
+            && let Some(track) = project.tracks.iter_mut().find(|t| t.id == track_id)
+            && let Some(node) = track.node_mut(&node_id)
+        {
+            node.sequence = Sequence::Static(new_pattern);
+            self.history.record(project);
+            let _ = self
+                .engine
+                .command_tx
+                .send(EngineCommand::ReloadProject(project.clone()));
+        }
+
+        self.generated_pattern_panel(ctx);
+        self.audio_settings_dialog(ctx);
+        self.new_project_dialog(ctx);
+        self.quit_dialog(ctx);
+
         if close_piano_roll {
             self.selected_node = None;
         }
@@ -333,33 +3076,67 @@ impl eframe::App for AurioApp {
                     ui.heading("Tracks");
 
                     self.transport_controls(ui);
+                    ui.horizontal(|ui| {
+                        ui.label("Master");
+                        level_meter(ui, self.master_level, None, &mut self.clipped);
+                    });
+                    self.section_buttons(ui);
 
                     ui.separator();
 
                     if let Some(ref project) = self.current_project {
                         for (i, track) in project.tracks.iter().enumerate() {
                             let is_selected = self.selected_track == Some(i);
-                            if ui.selectable_label(is_selected, &track.name).clicked() {
-                                self.selected_track = Some(i);
+                            ui.horizontal(|ui| {
+                                if ui.selectable_label(is_selected, &track.name).clicked() {
+                                    self.selected_track = Some(i);
+                                }
+                                let level = self.track_levels.get(i).copied().unwrap_or_default();
+                                level_meter(ui, level, Some(i), &mut self.clipped);
+                            });
+
+                            if is_selected && track.morph.is_some() {
+                                let position = self.morph_positions.entry(i).or_insert(0.0);
+                                let slider = egui::Slider::new(position, 0.0..=1.0).text("Morph");
+                                if ui.add(slider).changed() {
+                                    let _ = self.engine.command_tx.send(EngineCommand::SetMorph {
+                                        track_id: i,
+                                        value: *position,
+                                    });
+                                }
                             }
                         }
                     }
+                    if ui.button("➕ Add Track").clicked() {
+                        self.edit_tracks(TrackAction::Add);
+                    }
+
+                    if let Some(track_id) = self.selected_track {
+                        ui.separator();
+                        self.track_controls(ui, track_id);
+                        self.instrument_panel(ui, track_id);
+                        self.plugin_panel(ui, track_id);
+                    }
                 });
 
             egui::CentralPanel::default().show(ctx, |ui| {
                 if let Some(track_idx) = self.selected_track {
-                    if let Some(ref project) = self.current_project {
-                        if let Some(track) = project.tracks.get(track_idx) {
-                            ui.heading(format!("Graph: {}", track.name));
-                            ui.label(format!("BPM: {}", project.bpm));
-                            if let Some(current) = self.current_nodes.get(&track.id) {
-                                ui.label(format!("▶ Currently playing: {}", current));
-                            }
-                            ui.separator();
-
-                            let track_clone = track.clone();
-                            self.draw_graph(ui, &track_clone);
+                    if let Some(ref project) = self.current_project
+                        && let Some(track) = project.tracks.get(track_idx)
+                    {
+                        ui.heading(format!("Graph: {}", track.name));
+                        ui.label(format!("BPM: {}", project.bpm));
+                        if let Some(current) = self.current_nodes.get(&track.id) {
+                            ui.label(format!("▶ Currently playing: {}", current));
                         }
+                        ui.separator();
+
+                        let track_clone = track.clone();
+                        self.layer_tabs(ui, track_clone.id);
+                        self.script_error_list(ui, track_clone.id);
+                        let view = layer_view(&track_clone, self.shown_layer(track_clone.id));
+                        let edits = self.draw_graph(ui, &view);
+                        self.apply_graph_edits(track_clone.id, edits);
                     }
                 } else {
                     ui.vertical_centered(|ui| {
@@ -0,0 +1,662 @@
+use crate::dsp::{self, AudioGraph};
+use crate::{AurioError, EngineCommand};
+use eframe::egui;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// Node types offered for adding, with the arguments a new one starts with.
+const PALETTE: [(&str, &str); 20] = [
+    ("Osc", "Sine 220"),
+    ("Pluck", "220"),
+    ("Gain", "0.5"),
+    ("Out", ""),
+    ("Const", "0"),
+    ("Add", ""),
+    ("Mul", ""),
+    ("Scale", "1 0"),
+    ("Uni", ""),
+    ("Bi", ""),
+    ("Slew", "0.05"),
+    ("Quant", "minor A"),
+    ("Follow", "0.01 0.1"),
+    ("Gate", "0.5"),
+    ("Clock", "120 4"),
+    ("Seq", "A3 C4 E4"),
+    ("Random", "Step 4"),
+    ("SampleHold", ""),
+    ("In", "gate"),
+    ("MidiIn", "gate"),
+];
+
+/// The patch a new editor starts with.
+const DEFAULT_PATCH: &str = "[0] Osc Sine 220\n[1] Gain 0.2\n[2] Out\n\n0->1\n1->2\n";
+
+const NODE_WIDTH: f32 = 120.0;
+/// Height of a node's title and arguments, above its parameter inputs.
+const NODE_HEADER: f32 = 36.0;
+const PARAM_ROW: f32 = 16.0;
+const PORT_RADIUS: f32 = 5.0;
+
+/// What a drag that started on the patch canvas is doing, by node id.
+#[derive(Debug, Clone, Copy)]
+enum PatchDrag {
+    Node(u32),
+    /// Drawing a wire out of a node.
+    Connect(u32),
+}
+
+/// A change made in the patch editor, by node id and wire index.
+#[derive(Debug, Clone, PartialEq)]
+enum PatchEdit {
+    AddNode {
+        id: u32,
+        kind: String,
+        args: String,
+    },
+    SetArgs(u32, Vec<String>),
+    SetBypass(u32, bool),
+    SetOversample(u32, u32),
+    RemoveNode(u32),
+    AddWire {
+        from: u32,
+        to: u32,
+        param: Option<String>,
+    },
+    RemoveWire(usize),
+}
+
+/// `source` with `edit` made to it, written back out by
+/// [`AudioGraph::to_source`], and the graph it parses into.
+fn edited(source: &str, edit: &PatchEdit) -> Result<(String, AudioGraph), AurioError> {
+    let mut graph = dsp::parse_file(source)?;
+    match edit {
+        PatchEdit::AddNode { id, kind, args } => {
+            dsp::patch_graph(&mut graph, &format!("[{id}] {kind} {args}"))?;
+        }
+        PatchEdit::SetArgs(id, args) => {
+            if let Some(node) = graph.nodes.iter_mut().find(|node| node.id == *id) {
+                node.args = args.clone();
+            }
+        }
+        PatchEdit::SetBypass(id, bypass) => {
+            graph.set_bypass(*id, *bypass).map_err(AurioError::Graph)?;
+        }
+        PatchEdit::SetOversample(id, factor) => {
+            if let Some(node) = graph.nodes.iter_mut().find(|node| node.id == *id) {
+                node.oversample = *factor;
+            }
+        }
+        PatchEdit::RemoveNode(id) => {
+            graph.nodes.retain(|node| node.id != *id);
+            graph
+                .wires
+                .retain(|wire| wire.from_node_id != *id && wire.to_node_id != *id);
+        }
+        PatchEdit::AddWire { from, to, param } => {
+            let port = param
+                .as_ref()
+                .map_or(String::new(), |param| format!(".{param}"));
+            dsp::patch_graph(&mut graph, &format!("{from}->{to}{port}"))?;
+        }
+        PatchEdit::RemoveWire(index) => {
+            if *index < graph.wires.len() {
+                graph.wires.remove(*index);
+            }
+        }
+    }
+    // Parsed again so edits made straight to the nodes are checked too.
+    let source = graph.to_source();
+    let graph = dsp::parse_file(&source)?;
+    Ok((source, graph))
+}
+
+/// Where a node without a position of its own is first drawn: `index` in a
+/// grid filled row by row.
+fn default_position(index: usize) -> egui::Pos2 {
+    egui::pos2(
+        20.0 + 160.0 * (index % 4) as f32,
+        20.0 + 110.0 * (index / 4) as f32,
+    )
+}
+
+/// Node-graph view of a `.au` patch: nodes are dragged around, wired up by
+/// dragging from an output to an input, and their arguments edited in the
+/// side panel. Every edit rewrites the patch's source, and while it's playing
+/// the engine swaps the new patch in. Positions are only kept while the
+/// editor is open, since `.au` files don't store them.
+pub struct PatchEditor {
+    pub visible: bool,
+    path: Option<PathBuf>,
+    /// The patch as written, or as last rewritten by an edit.
+    source: String,
+    graph: AudioGraph,
+    /// Where nodes were dragged to, by id, relative to the canvas.
+    positions: HashMap<u32, egui::Pos2>,
+    drag: Option<PatchDrag>,
+    selected: Option<u32>,
+    /// An argument being typed or dragged: node id, position and value. It
+    /// only reaches the patch once the edit is finished.
+    editing: Option<(u32, usize, String)>,
+    error: Option<String>,
+    playing: bool,
+    /// The node played instead of the output while it's playing.
+    probe: Option<u32>,
+    show_source: bool,
+}
+
+impl Default for PatchEditor {
+    fn default() -> Self {
+        Self {
+            visible: false,
+            path: None,
+            source: DEFAULT_PATCH.to_string(),
+            graph: dsp::parse_file(DEFAULT_PATCH).expect("the default patch parses"),
+            positions: HashMap::new(),
+            drag: None,
+            selected: None,
+            editing: None,
+            error: None,
+            playing: false,
+            probe: None,
+            show_source: false,
+        }
+    }
+}
+
+impl PatchEditor {
+    /// Draws the editor's window, if it's open. Returns the commands that
+    /// play, swap or stop the patch.
+    pub fn show(&mut self, ctx: &egui::Context) -> Vec<EngineCommand> {
+        let mut commands = Vec::new();
+        let mut visible = self.visible;
+        egui::Window::new("Patch Editor")
+            .open(&mut visible)
+            .default_size([760.0, 480.0])
+            .show(ctx, |ui| {
+                self.toolbar(ui, &mut commands);
+                if let Some(error) = &self.error {
+                    ui.colored_label(egui::Color32::RED, error);
+                }
+                ui.separator();
+
+                let mut edit = None;
+                egui::SidePanel::right("patch_inspector")
+                    .resizable(false)
+                    .default_width(200.0)
+                    .show_inside(ui, |ui| edit = self.inspector(ui, &mut commands));
+                egui::CentralPanel::default().show_inside(ui, |ui| {
+                    if self.show_source {
+                        egui::ScrollArea::vertical().show(ui, |ui| {
+                            ui.add(
+                                egui::TextEdit::multiline(&mut self.source.as_str())
+                                    .code_editor()
+                                    .desired_width(f32::INFINITY),
+                            );
+                        });
+                    } else if let Some(canvas_edit) = self.canvas(ui) {
+                        edit = Some(canvas_edit);
+                    }
+                });
+                if let Some(edit) = edit {
+                    self.apply(&edit, &mut commands);
+                }
+            });
+        self.visible = visible;
+        commands
+    }
+
+    fn toolbar(&mut self, ui: &mut egui::Ui, commands: &mut Vec<EngineCommand>) {
+        ui.horizontal(|ui| {
+            let label = if self.playing { "⏹ Stop" } else { "▶ Play" };
+            if ui
+                .button(label)
+                .on_hover_text("Play the patch over the open project")
+                .clicked()
+            {
+                self.playing = !self.playing;
+                let source = self.playing.then(|| self.source.clone());
+                commands.push(EngineCommand::SetPatch(source));
+                if self.playing && self.probe.is_some() {
+                    commands.push(EngineCommand::ProbePatch(self.probe));
+                }
+            }
+            if ui.button("Open…").clicked()
+                && let Some(path) = rfd::FileDialog::new()
+                    .set_title("Open Patch")
+                    .add_filter("Patch", &["au"])
+                    .pick_file()
+            {
+                self.open(path, commands);
+            }
+            if ui.button("Save").clicked() {
+                self.save();
+            }
+
+            ui.menu_button("➕ Add node", |ui| {
+                for (kind, args) in PALETTE {
+                    if ui.button(kind).clicked() {
+                        let id = self.graph.nodes.iter().map(|n| n.id + 1).max();
+                        let edit = PatchEdit::AddNode {
+                            id: id.unwrap_or(0),
+                            kind: kind.to_string(),
+                            args: args.to_string(),
+                        };
+                        self.apply(&edit, commands);
+                        ui.close();
+                    }
+                }
+            });
+            ui.checkbox(&mut self.show_source, "Source");
+            if let Some(path) = &self.path {
+                ui.weak(path.display().to_string());
+            }
+        });
+    }
+
+    fn open(&mut self, path: PathBuf, commands: &mut Vec<EngineCommand>) {
+        let loaded = std::fs::read_to_string(&path)
+            .map_err(|e| AurioError::project_io(&path, e))
+            .and_then(|source| dsp::parse_file(&source).map(|graph| (source, graph)));
+        match loaded {
+            Ok((source, graph)) => {
+                self.path = Some(path);
+                self.positions.clear();
+                self.selected = None;
+                self.editing = None;
+                self.error = None;
+                self.set(source, graph, commands);
+                if self.probe.take().is_some() {
+                    commands.push(EngineCommand::ProbePatch(None));
+                }
+            }
+            Err(e) => self.error = Some(e.to_string()),
+        }
+    }
+
+    /// Writes the patch to its file, asking where first if it hasn't got one.
+    fn save(&mut self) {
+        if self.path.is_none() {
+            self.path = rfd::FileDialog::new()
+                .set_title("Save Patch")
+                .add_filter("Patch", &["au"])
+                .set_file_name("patch.au")
+                .save_file();
+        }
+        if let Some(path) = &self.path {
+            self.error = std::fs::write(path, &self.source)
+                .err()
+                .map(|e| AurioError::project_io(path, e).to_string());
+        }
+    }
+
+    /// Makes `edit`, or shows why it can't be made and leaves the patch be.
+    fn apply(&mut self, edit: &PatchEdit, commands: &mut Vec<EngineCommand>) {
+        match edited(&self.source, edit) {
+            Ok((source, graph)) => {
+                self.error = None;
+                if let PatchEdit::RemoveNode(id) = edit {
+                    self.positions.remove(id);
+                    self.selected = self.selected.filter(|selected| selected != id);
+                    // The engine ends a probe of a node that's gone by itself.
+                    self.probe = self.probe.filter(|probe| probe != id);
+                }
+                self.set(source, graph, commands);
+            }
+            Err(e) => self.error = Some(e.to_string()),
+        }
+    }
+
+    fn set(&mut self, source: String, graph: AudioGraph, commands: &mut Vec<EngineCommand>) {
+        if self.playing {
+            commands.push(EngineCommand::SetPatch(Some(source.clone())));
+        }
+        self.source = source;
+        self.graph = graph;
+    }
+
+    /// The selected node's arguments, numbers as drag values and anything
+    /// else as text, its parameter inputs, and whether it's bypassed,
+    /// oversampled or probed.
+    fn inspector(
+        &mut self,
+        ui: &mut egui::Ui,
+        commands: &mut Vec<EngineCommand>,
+    ) -> Option<PatchEdit> {
+        let Some(node) = self
+            .selected
+            .and_then(|id| self.graph.nodes.iter().find(|node| node.id == id))
+        else {
+            ui.weak("Click a node to edit it. Drag from an output to an input to wire them up");
+            return None;
+        };
+        ui.heading(format!("[{}] {}", node.id, node.kind));
+
+        let mut finished = false;
+        for (index, arg) in node.args.iter().enumerate() {
+            let value = match &self.editing {
+                Some((id, i, value)) if (*id, *i) == (node.id, index) => value.clone(),
+                _ => arg.clone(),
+            };
+            ui.horizontal(|ui| {
+                ui.label(format!("Argument {}", index + 1));
+                let response = if let Ok(mut number) = value.parse::<f32>() {
+                    let speed = number.abs().max(1.0) * 0.01;
+                    let response = ui.add(egui::DragValue::new(&mut number).speed(speed));
+                    if response.changed() {
+                        self.editing = Some((node.id, index, number.to_string()));
+                    }
+                    // Typed values are done as soon as they're entered.
+                    finished |= response.changed() && !response.dragged();
+                    finished |= response.drag_stopped();
+                    response
+                } else {
+                    let mut text = value;
+                    let response =
+                        ui.add(egui::TextEdit::singleline(&mut text).desired_width(90.0));
+                    if response.changed() {
+                        self.editing = Some((node.id, index, text));
+                    }
+                    response
+                };
+                finished |= response.lost_focus();
+            });
+        }
+        if node.body.is_some() {
+            ui.weak("The voice patch inside the braces is kept as it is");
+        }
+
+        let params = node.inner.param_inputs();
+        if !params.is_empty() {
+            ui.separator();
+            ui.label(format!("Parameter inputs: {}", params.join(", ")));
+        }
+        ui.separator();
+        let mut bypass = node.bypass;
+        let bypassed = ui
+            .checkbox(&mut bypass, "Bypass")
+            .on_hover_text("Pass the node's input straight through, or mute it if it has none")
+            .changed();
+        let mut oversample = node.oversample;
+        ui.horizontal(|ui| {
+            ui.label("Oversample");
+            egui::ComboBox::from_id_salt("oversample")
+                .selected_text(format!("{oversample}x"))
+                .show_ui(ui, |ui| {
+                    for factor in [1, 2, 4] {
+                        ui.selectable_value(&mut oversample, factor, format!("{factor}x"));
+                    }
+                });
+        })
+        .response
+        .on_hover_text(
+            "Run the node faster than the sample rate, for less aliasing from FM or distortion",
+        );
+        let mut probed = self.probe == Some(node.id);
+        if ui
+            .checkbox(&mut probed, "🎧 Probe")
+            .on_hover_text("Play this node's output instead of the patch's, clipped to ±1")
+            .changed()
+        {
+            self.probe = probed.then_some(node.id);
+            if self.playing {
+                commands.push(EngineCommand::ProbePatch(self.probe));
+            }
+        }
+        let remove = ui.button("🗑 Remove node").clicked();
+
+        let id = node.id;
+        let mut args = node.args.clone();
+        if remove {
+            return Some(PatchEdit::RemoveNode(id));
+        }
+        if bypassed {
+            return Some(PatchEdit::SetBypass(id, bypass));
+        }
+        if oversample != node.oversample {
+            return Some(PatchEdit::SetOversample(id, oversample));
+        }
+        match self.editing.take() {
+            Some((edited_id, index, value)) if finished && edited_id == id => {
+                args[index] = value;
+                Some(PatchEdit::SetArgs(id, args))
+            }
+            editing => {
+                self.editing = editing;
+                None
+            }
+        }
+    }
+
+    /// Draws the patch and turns pointer input on it into edits: dragging a
+    /// node moves it, dragging from its output to another node's input or
+    /// parameter wires them, clicking a wire's midpoint removes it, clicking
+    /// a node selects it and right-clicking one removes it.
+    fn canvas(&mut self, ui: &mut egui::Ui) -> Option<PatchEdit> {
+        let (response, painter) =
+            ui.allocate_painter(ui.available_size(), egui::Sense::click_and_drag());
+        let origin = response.rect.min.to_vec2();
+        let nodes = &self.graph.nodes;
+        let params: Vec<&[&str]> = nodes.iter().map(|node| node.inner.param_inputs()).collect();
+        let rects: Vec<egui::Rect> = nodes
+            .iter()
+            .enumerate()
+            .map(|(i, node)| {
+                let position = self
+                    .positions
+                    .get(&node.id)
+                    .copied()
+                    .unwrap_or_else(|| default_position(i));
+                let height = NODE_HEADER + PARAM_ROW * params[i].len() as f32;
+                egui::Rect::from_min_size(position + origin, egui::vec2(NODE_WIDTH, height))
+            })
+            .collect();
+        let index_of = |id: u32| nodes.iter().position(|node| node.id == id);
+        let input_port = |i: usize| rects[i].left_top() + egui::vec2(0.0, NODE_HEADER / 2.0);
+        let output_port = |i: usize| rects[i].right_top() + egui::vec2(0.0, NODE_HEADER / 2.0);
+        let param_port = |i: usize, param: usize| {
+            rects[i].left_top() + egui::vec2(0.0, NODE_HEADER + PARAM_ROW * (param as f32 + 0.5))
+        };
+        let node_at = |pos: egui::Pos2| (0..nodes.len()).rev().find(|&i| rects[i].contains(pos));
+        let near = |port: egui::Pos2, pos: egui::Pos2| port.distance(pos) <= PORT_RADIUS + 3.0;
+
+        let wire_stroke = egui::Stroke::new(2.0, egui::Color32::GRAY);
+        let mut midpoints = Vec::new();
+        for (index, wire) in self.graph.wires.iter().enumerate() {
+            let (Some(from), Some(to)) = (index_of(wire.from_node_id), index_of(wire.to_node_id))
+            else {
+                continue;
+            };
+            let start = output_port(from);
+            let end = match &wire.to_param {
+                Some(param) => match params[to].iter().position(|p| p == param) {
+                    Some(param) => param_port(to, param),
+                    None => input_port(to),
+                },
+                None => input_port(to),
+            };
+            let bend = egui::vec2(((end.x - start.x).abs() / 2.0).max(40.0), 0.0);
+            painter.add(egui::epaint::CubicBezierShape::from_points_stroke(
+                [start, start + bend, end - bend, end],
+                false,
+                egui::Color32::TRANSPARENT,
+                wire_stroke,
+            ));
+            let middle = start + (end - start) / 2.0;
+            painter.circle_filled(middle, 3.0, egui::Color32::GRAY);
+            if wire.weight != 1.0 {
+                painter.text(
+                    middle + egui::vec2(0.0, -6.0),
+                    egui::Align2::CENTER_BOTTOM,
+                    format!("× {}", wire.weight),
+                    egui::FontId::proportional(10.0),
+                    egui::Color32::LIGHT_GRAY,
+                );
+            }
+            midpoints.push((index, middle));
+        }
+
+        for (i, node) in nodes.iter().enumerate() {
+            let rect = rects[i];
+            let stroke = if self.probe == Some(node.id) {
+                egui::Stroke::new(2.0, egui::Color32::LIGHT_GREEN)
+            } else if self.selected == Some(node.id) {
+                egui::Stroke::new(2.0, egui::Color32::YELLOW)
+            } else {
+                egui::Stroke::new(1.0, egui::Color32::WHITE)
+            };
+            let (fill, title) = if node.bypass {
+                (
+                    egui::Color32::from_gray(25),
+                    format!("[{}]! {}", node.id, node.kind),
+                )
+            } else {
+                (
+                    egui::Color32::from_gray(40),
+                    format!("[{}] {}", node.id, node.kind),
+                )
+            };
+            painter.rect_filled(rect, 5.0, fill);
+            painter.rect_stroke(rect, 5.0, stroke, egui::StrokeKind::Inside);
+            painter.text(
+                rect.center_top() + egui::vec2(0.0, 4.0),
+                egui::Align2::CENTER_TOP,
+                title,
+                egui::FontId::proportional(13.0),
+                egui::Color32::WHITE,
+            );
+            painter.text(
+                rect.center_top() + egui::vec2(0.0, 20.0),
+                egui::Align2::CENTER_TOP,
+                node.args.join(" "),
+                egui::FontId::monospace(10.0),
+                egui::Color32::LIGHT_GRAY,
+            );
+            if !node.inner.is_output() {
+                painter.circle_filled(output_port(i), PORT_RADIUS, egui::Color32::LIGHT_BLUE);
+            }
+            painter.circle_filled(input_port(i), PORT_RADIUS, egui::Color32::LIGHT_GREEN);
+            for (param, name) in params[i].iter().enumerate() {
+                let port = param_port(i, param);
+                painter.circle_filled(port, PORT_RADIUS - 1.0, egui::Color32::GOLD);
+                painter.text(
+                    port + egui::vec2(PORT_RADIUS + 3.0, 0.0),
+                    egui::Align2::LEFT_CENTER,
+                    name,
+                    egui::FontId::proportional(10.0),
+                    egui::Color32::LIGHT_GRAY,
+                );
+            }
+        }
+
+        let pointer = response.interact_pointer_pos();
+        let mut edit = None;
+        if response.drag_started()
+            && let Some(pos) = pointer
+        {
+            let from = (0..nodes.len())
+                .rev()
+                .find(|&i| !nodes[i].inner.is_output() && near(output_port(i), pos));
+            self.drag = from
+                .map(|i| PatchDrag::Connect(nodes[i].id))
+                .or(node_at(pos).map(|i| PatchDrag::Node(nodes[i].id)));
+        }
+        match self.drag {
+            Some(PatchDrag::Node(id)) if response.dragged() => {
+                if let Some(i) = index_of(id) {
+                    let position = rects[i].min - origin + response.drag_delta();
+                    self.positions.insert(id, position);
+                }
+            }
+            Some(PatchDrag::Connect(from)) => {
+                if let (Some(i), Some(pos)) = (index_of(from), ui.ctx().pointer_latest_pos()) {
+                    painter.line_segment(
+                        [output_port(i), pos],
+                        egui::Stroke::new(2.0, egui::Color32::YELLOW),
+                    );
+                    if response.drag_stopped()
+                        && let Some(to) = node_at(pos)
+                    {
+                        let param = (0..params[to].len())
+                            .find(|&param| near(param_port(to, param), pos))
+                            .map(|param| params[to][param].to_string());
+                        edit = Some(PatchEdit::AddWire {
+                            from,
+                            to: nodes[to].id,
+                            param,
+                        });
+                    }
+                }
+            }
+            _ => {}
+        }
+        if response.drag_stopped() {
+            self.drag = None;
+        }
+
+        if response.clicked()
+            && let Some(pos) = pointer
+        {
+            if let Some(&(index, _)) = midpoints.iter().find(|(_, middle)| near(*middle, pos)) {
+                edit = Some(PatchEdit::RemoveWire(index));
+            } else {
+                self.selected = node_at(pos).map(|i| nodes[i].id);
+                self.editing = None;
+            }
+        }
+        if response.secondary_clicked()
+            && let Some(i) = pointer.and_then(node_at)
+        {
+            edit = Some(PatchEdit::RemoveNode(nodes[i].id));
+        }
+        edit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edits_rewrite_the_patch() {
+        let add = PatchEdit::AddNode {
+            id: 3,
+            kind: "Osc".to_string(),
+            args: "Sine 5".to_string(),
+        };
+        let (source, _) = edited(DEFAULT_PATCH, &add).unwrap();
+        let wire = PatchEdit::AddWire {
+            from: 3,
+            to: 0,
+            param: Some("freq".to_string()),
+        };
+        let (source, graph) = edited(&source, &wire).unwrap();
+        assert_eq!(graph.nodes.len(), 4);
+        assert!(source.contains("[3] Osc Sine 5\n"));
+        assert!(source.contains("3->0.freq\n"));
+
+        let args = PatchEdit::SetArgs(1, vec!["-6dB".to_string()]);
+        let (source, _) = edited(&source, &args).unwrap();
+        assert!(source.contains("[1] Gain -6dB\n"));
+
+        let (source, graph) = edited(&source, &PatchEdit::SetBypass(0, true)).unwrap();
+        assert!(source.contains("[0]! Osc Sine 220\n"));
+        assert!(graph.nodes.iter().any(|node| node.bypass));
+        let (source, _) = edited(&source, &PatchEdit::SetOversample(0, 4)).unwrap();
+        assert!(source.contains("[0]!x4 Osc Sine 220\n"));
+
+        // Removing a node takes its wires with it.
+        let (source, graph) = edited(&source, &PatchEdit::RemoveNode(3)).unwrap();
+        assert_eq!(graph.wires.len(), 2);
+        assert!(!source.contains("3->"));
+
+        // Edits that don't make a patch are refused.
+        let bad = PatchEdit::SetArgs(0, vec!["Wobble".to_string()]);
+        assert!(edited(&source, &bad).is_err());
+        let cycle = PatchEdit::AddWire {
+            from: 1,
+            to: 0,
+            param: None,
+        };
+        assert!(edited(&source, &cycle).is_err());
+    }
+}
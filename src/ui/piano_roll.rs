@@ -1,4 +1,6 @@
-use crate::timing::{Note, StaticPattern};
+use crate::timing::{
+    Articulation, AutomationPoint, FilterParam, Note, ParamName, ParamTarget, StaticPattern,
+};
 use eframe::egui;
 
 #[derive(Clone)]
@@ -7,6 +9,16 @@ pub struct PianoRollState {
     pub horizontal_zoom: f32,
     pub pan_x: f32,
     pub pan_y: f32,
+    /// Keyboard cursor used for note entry without the mouse.
+    pub cursor_pitch: u8,
+    pub cursor_beat: f32,
+    /// Indices of the selected notes.
+    pub selection: Vec<usize>,
+    /// Grid that inserted, moved and resized notes line up with.
+    pub snap: Snap,
+    /// Scroll along with the playhead while the pattern plays.
+    pub follow: bool,
+    drag: Option<NoteDrag>,
 }
 
 impl Default for PianoRollState {
@@ -16,6 +28,12 @@ impl Default for PianoRollState {
             horizontal_zoom: 50.0,
             pan_x: 0.0,
             pan_y: 0.0,
+            cursor_pitch: 60,
+            cursor_beat: 0.0,
+            selection: Vec::new(),
+            snap: Snap::default(),
+            follow: true,
+            drag: None,
         }
     }
 }
@@ -43,30 +61,101 @@ impl PianoRollState {
         let center_pitch = (min_pitch + max_pitch) as f32 / 2.0;
         self.pan_y = center_pitch - (available_size.y / self.vertical_zoom / 2.0);
         self.pan_x = 0.0;
+
+        if let Some(first) = pattern
+            .notes
+            .iter()
+            .min_by(|a, b| a.start_beat.total_cmp(&b.start_beat))
+        {
+            self.cursor_pitch = first.pitch;
+            self.cursor_beat = first.start_beat;
+        }
     }
 }
 
 pub struct PianoRoll<'a> {
     pattern: &'a mut StaticPattern,
     state: &'a mut PianoRollState,
+    playhead: Option<f32>,
+    read_only: bool,
 }
 
 impl<'a> PianoRoll<'a> {
     pub fn new(pattern: &'a mut StaticPattern, state: &'a mut PianoRollState) -> Self {
-        Self { pattern, state }
+        Self {
+            pattern,
+            state,
+            playhead: None,
+            read_only: false,
+        }
+    }
+
+    /// Shows the notes without letting them be edited, e.g. for a preview.
+    pub fn read_only(mut self) -> Self {
+        self.read_only = true;
+        self
+    }
+
+    /// Beat the pattern is playing at, if it is playing.
+    pub fn playhead(mut self, beat: Option<f32>) -> Self {
+        self.playhead = beat;
+        self
     }
 
     pub fn show(mut self, ui: &mut egui::Ui) -> PianoRollResponse {
         let mut response = PianoRollResponse { modified: false };
 
+        if !self.read_only {
+            self.toolbar(ui, &mut response);
+        }
+
         let available_size = ui.available_size();
-        let (rect_response, painter) =
-            ui.allocate_painter(available_size, egui::Sense::click_and_drag());
+        let sense = if self.read_only {
+            egui::Sense::hover()
+        } else {
+            egui::Sense::click_and_drag()
+        };
+        let (rect_response, painter) = ui.allocate_painter(available_size, sense);
 
         let rect = rect_response.rect;
         let piano_key_width = 60.0;
 
         self.handle_input(ui, rect.size());
+        if self.state.follow
+            && self.state.drag.is_none()
+            && let Some(beat) = self.playhead
+        {
+            // Turn the page once the playhead runs off either side.
+            let visible_beats = (rect.width() - piano_key_width) / self.state.horizontal_zoom;
+            if beat < self.state.pan_x || beat > self.state.pan_x + visible_beats {
+                self.state.pan_x = beat.floor().max(0.0);
+            }
+        }
+
+        if rect_response.clicked() {
+            rect_response.request_focus();
+        }
+        let has_focus = rect_response.has_focus();
+        if has_focus {
+            ui.memory_mut(|mem| {
+                mem.set_focus_lock_filter(
+                    rect_response.id,
+                    egui::EventFilter {
+                        horizontal_arrows: true,
+                        vertical_arrows: true,
+                        ..Default::default()
+                    },
+                )
+            });
+            if self.handle_keyboard(ui, rect, piano_key_width) {
+                response.modified = true;
+            }
+        }
+
+        let cursor_description = self.cursor_description();
+        rect_response.widget_info(|| {
+            egui::WidgetInfo::labeled(egui::WidgetType::Other, true, &cursor_description)
+        });
 
         let visible_semitones = rect.height() / self.state.vertical_zoom;
         let min_visible_pitch = self.state.pan_y.floor() as u8;
@@ -103,6 +192,7 @@ impl<'a> PianoRoll<'a> {
         );
 
         let modification = self.draw_notes(
+            ui,
             &painter,
             &rect_response,
             rect,
@@ -114,9 +204,484 @@ impl<'a> PianoRoll<'a> {
             response.modified = true;
         }
 
+        if has_focus {
+            self.draw_cursor(&painter, rect, piano_key_width);
+        }
+
+        if let Some(beat) = self.playhead {
+            let x = self.beat_to_screen_x(beat, rect, piano_key_width);
+            if x >= rect.left() + piano_key_width && x <= rect.right() {
+                painter.line_segment(
+                    [
+                        egui::Pos2::new(x, rect.top()),
+                        egui::Pos2::new(x, rect.bottom()),
+                    ],
+                    egui::Stroke::new(2.0, egui::Color32::from_rgb(60, 220, 120)),
+                );
+            }
+        }
+
         response
     }
 
+    fn toolbar(&mut self, ui: &mut egui::Ui, response: &mut PianoRollResponse) {
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_label("Snap")
+                .selected_text(self.state.snap.to_string())
+                .show_ui(ui, |ui| {
+                    for snap in Snap::ALL {
+                        ui.selectable_value(&mut self.state.snap, snap, snap.to_string());
+                    }
+                });
+            ui.checkbox(&mut self.state.follow, "Follow")
+                .on_hover_text("Scroll along with the playhead");
+            ui.separator();
+            let cursor_bar = (self.state.cursor_beat / self.pattern.beats_per_bar()) as u32;
+            if ui
+                .button("Duplicate bar")
+                .on_hover_text("Insert a copy of the bar under the cursor after it")
+                .clicked()
+            {
+                self.pattern.duplicate_bars(
+                    cursor_bar.min(self.pattern.duration_bars.saturating_sub(1)),
+                    1,
+                );
+                self.state.selection.clear();
+                response.modified = true;
+            }
+            if ui
+                .button("Duplicate pattern")
+                .on_hover_text("Double the pattern's length by repeating it")
+                .clicked()
+            {
+                self.pattern.duplicate_bars(0, self.pattern.duration_bars);
+                self.state.selection.clear();
+                response.modified = true;
+            }
+        });
+        if self.note_expression(ui) {
+            response.modified = true;
+        }
+        if self.automation(ui) {
+            response.modified = true;
+        }
+    }
+
+    /// The pattern's automation points, one row each, with new ones added at
+    /// the cursor's beat. Returns whether any changed.
+    fn automation(&mut self, ui: &mut egui::Ui) -> bool {
+        let mut changed = false;
+        let total_beats = self.total_beats();
+        let title = format!("Automation ({})", self.pattern.automation.len());
+        ui.collapsing(title, |ui| {
+            let mut removed = None;
+            for (i, point) in self.pattern.automation.iter_mut().enumerate() {
+                ui.horizontal(|ui| {
+                    egui::ComboBox::from_id_salt(("automation", i))
+                        .selected_text(point.target.label())
+                        .show_ui(ui, |ui| {
+                            let id = match point.target {
+                                ParamTarget::Instrument(id) | ParamTarget::Effect { id, .. } => id,
+                                _ => 0,
+                            };
+                            for target in [
+                                ParamTarget::Volume,
+                                ParamTarget::Pan,
+                                ParamTarget::Filter(FilterParam::Cutoff),
+                                ParamTarget::Filter(FilterParam::Resonance),
+                                ParamTarget::Graph {
+                                    node: 0,
+                                    param: ParamName::new("freq"),
+                                },
+                                ParamTarget::Instrument(id),
+                                ParamTarget::Effect { index: 0, id },
+                            ] {
+                                let selected = match (point.target, target) {
+                                    (ParamTarget::Filter(a), ParamTarget::Filter(b)) => a == b,
+                                    (a, b) => {
+                                        std::mem::discriminant(&a) == std::mem::discriminant(&b)
+                                    }
+                                };
+                                if ui.selectable_label(selected, target.label()).clicked()
+                                    && !selected
+                                {
+                                    point.target = target;
+                                    changed = true;
+                                }
+                            }
+                        });
+                    match &mut point.target {
+                        ParamTarget::Instrument(id) => {
+                            ui.label("Id");
+                            changed |= ui.add(egui::DragValue::new(id)).changed();
+                        }
+                        ParamTarget::Effect { index, id } => {
+                            let mut number = *index + 1;
+                            ui.label("Effect");
+                            if ui
+                                .add(egui::DragValue::new(&mut number).range(1..=16))
+                                .changed()
+                            {
+                                *index = number - 1;
+                                changed = true;
+                            }
+                            ui.label("Id");
+                            changed |= ui.add(egui::DragValue::new(id)).changed();
+                        }
+                        ParamTarget::Graph { node, param } => {
+                            ui.label("Node");
+                            changed |= ui.add(egui::DragValue::new(node)).changed();
+                            let mut name = param.as_str().to_string();
+                            if ui
+                                .add(egui::TextEdit::singleline(&mut name).desired_width(60.0))
+                                .changed()
+                            {
+                                *param = ParamName::new(&name);
+                                changed = true;
+                            }
+                        }
+                        ParamTarget::Volume | ParamTarget::Pan | ParamTarget::Filter(_) => {}
+                    }
+                    ui.label("Beat");
+                    changed |= ui
+                        .add(
+                            egui::DragValue::new(&mut point.beat)
+                                .range(0.0..=total_beats)
+                                .speed(0.05),
+                        )
+                        .changed();
+                    ui.label("Value");
+                    changed |= ui
+                        .add(egui::DragValue::new(&mut point.value).speed(0.01))
+                        .changed();
+                    if ui.small_button("🗑").clicked() {
+                        removed = Some(i);
+                    }
+                });
+            }
+            if let Some(i) = removed {
+                self.pattern.automation.remove(i);
+                changed = true;
+            }
+            if ui
+                .button("Add point")
+                .on_hover_text("Set the track's volume at the cursor's beat")
+                .clicked()
+            {
+                self.pattern.automation.push(AutomationPoint {
+                    target: ParamTarget::Volume,
+                    beat: self.state.cursor_beat,
+                    value: 1.0,
+                });
+                changed = true;
+            }
+        });
+        changed
+    }
+
+    /// Pan, detune and articulation of the selected notes, shown as the
+    /// first one's and set on all of them.
+    fn note_expression(&mut self, ui: &mut egui::Ui) -> bool {
+        let Some(first) = self
+            .state
+            .selection
+            .first()
+            .and_then(|&i| self.pattern.notes.get(i))
+        else {
+            return false;
+        };
+        let mut pan = first.pan.unwrap_or(0.0);
+        let mut detune = first.detune_cents.unwrap_or(0.0);
+        let mut articulation = first.articulation;
+
+        let (mut pan_changed, mut detune_changed, mut articulation_changed) = (false, false, false);
+        ui.horizontal(|ui| {
+            ui.label(format!("{} selected:", self.state.selection.len()));
+            ui.label("Pan");
+            pan_changed = ui
+                .add(egui::DragValue::new(&mut pan).range(-1.0..=1.0).speed(0.01))
+                .changed();
+            ui.label("Detune");
+            detune_changed = ui
+                .add(
+                    egui::DragValue::new(&mut detune)
+                        .range(-100.0..=100.0)
+                        .suffix(" ct"),
+                )
+                .changed();
+            egui::ComboBox::from_label("Articulation")
+                .selected_text(articulation.map_or("none", Articulation::name))
+                .show_ui(ui, |ui| {
+                    articulation_changed |= ui
+                        .selectable_value(&mut articulation, None, "none")
+                        .changed();
+                    for a in Articulation::ALL {
+                        articulation_changed |= ui
+                            .selectable_value(&mut articulation, Some(a), a.name())
+                            .changed();
+                    }
+                });
+        });
+
+        for &i in &self.state.selection {
+            if let Some(note) = self.pattern.notes.get_mut(i) {
+                if pan_changed {
+                    note.pan = (pan != 0.0).then_some(pan);
+                }
+                if detune_changed {
+                    note.detune_cents = (detune != 0.0).then_some(detune);
+                }
+                if articulation_changed {
+                    note.articulation = articulation;
+                }
+            }
+        }
+        pan_changed || detune_changed || articulation_changed
+    }
+
+    /// Arrow keys move the cursor (Shift+Up/Down by octave), Enter places a note,
+    /// Shift+Left/Right shrinks or extends the note under the cursor and
+    /// Delete/Backspace removes the selected notes, or the one under the
+    /// cursor. Escape clears the selection. Ctrl+C/Ctrl+X copy or cut the
+    /// selection, Ctrl+V pastes it at the cursor and Ctrl+Shift+V at the same
+    /// place in the cursor's bar. Returns whether the pattern changed.
+    fn handle_keyboard(&mut self, ui: &egui::Ui, rect: egui::Rect, piano_key_width: f32) -> bool {
+        use egui::{Key, Modifiers};
+
+        let total_beats = self.total_beats();
+        let mut modified = false;
+
+        let (copy, cut, paste, keep_bar_position) = ui.input(|i| {
+            let mut clipboard = (false, false, None, i.modifiers.shift);
+            for event in &i.events {
+                match event {
+                    egui::Event::Copy => clipboard.0 = true,
+                    egui::Event::Cut => clipboard.1 = true,
+                    egui::Event::Paste(text) => clipboard.2 = Some(text.clone()),
+                    _ => {}
+                }
+            }
+            clipboard
+        });
+        if (copy || cut) && !self.state.selection.is_empty() {
+            ui.ctx().copy_text(self.copy_selection());
+            if cut {
+                self.delete_selection();
+                modified = true;
+            }
+        }
+        if let Some(text) = paste
+            && self.paste(&text, keep_bar_position)
+        {
+            modified = true;
+        }
+
+        ui.input_mut(|i| {
+            if i.consume_key(Modifiers::SHIFT, Key::ArrowRight) {
+                if let Some(note) = self.note_at_cursor_mut() {
+                    let max_duration = total_beats - note.start_beat;
+                    note.duration_beats = (note.duration_beats + 1.0).min(max_duration);
+                    modified = true;
+                }
+            } else if i.consume_key(Modifiers::SHIFT, Key::ArrowLeft) {
+                if let Some(note) = self.note_at_cursor_mut() {
+                    note.duration_beats = (note.duration_beats - 1.0).max(1.0);
+                    modified = true;
+                }
+            } else if i.consume_key(Modifiers::SHIFT, Key::ArrowUp) {
+                self.state.cursor_pitch = self.state.cursor_pitch.saturating_add(12).min(127);
+            } else if i.consume_key(Modifiers::SHIFT, Key::ArrowDown) {
+                self.state.cursor_pitch = self.state.cursor_pitch.saturating_sub(12);
+            } else if i.consume_key(Modifiers::NONE, Key::ArrowRight) {
+                self.state.cursor_beat = (self.state.cursor_beat + 1.0).min(total_beats - 1.0);
+            } else if i.consume_key(Modifiers::NONE, Key::ArrowLeft) {
+                self.state.cursor_beat = (self.state.cursor_beat - 1.0).max(0.0);
+            } else if i.consume_key(Modifiers::NONE, Key::ArrowUp) {
+                self.state.cursor_pitch = self.state.cursor_pitch.saturating_add(1).min(127);
+            } else if i.consume_key(Modifiers::NONE, Key::ArrowDown) {
+                self.state.cursor_pitch = self.state.cursor_pitch.saturating_sub(1);
+            } else if i.consume_key(Modifiers::NONE, Key::Enter) {
+                if self.note_at_cursor_mut().is_none()
+                    && self.insert_note(self.state.cursor_pitch, self.state.cursor_beat, 1.0)
+                {
+                    modified = true;
+                }
+            } else if i.consume_key(Modifiers::NONE, Key::Escape) {
+                self.state.selection.clear();
+            } else if (i.consume_key(Modifiers::NONE, Key::Delete)
+                || i.consume_key(Modifiers::NONE, Key::Backspace))
+                && !self.state.selection.is_empty()
+            {
+                self.delete_selection();
+                modified = true;
+            } else if i.consume_key(Modifiers::NONE, Key::Delete)
+                || i.consume_key(Modifiers::NONE, Key::Backspace)
+            {
+                let (pitch, beat) = (self.state.cursor_pitch, self.state.cursor_beat);
+                let before = self.pattern.notes.len();
+                self.pattern
+                    .notes
+                    .retain(|n| !(n.pitch == pitch && (n.start_beat - beat).abs() < 0.1));
+                modified = self.pattern.notes.len() != before;
+            }
+        });
+
+        self.scroll_to_cursor(rect, piano_key_width);
+        modified
+    }
+
+    fn delete_selection(&mut self) {
+        let selection = std::mem::take(&mut self.state.selection);
+        let mut idx = 0;
+        self.pattern.notes.retain(|_| {
+            idx += 1;
+            !selection.contains(&(idx - 1))
+        });
+    }
+
+    /// The selected notes as clipboard text, timed from the start of the
+    /// bar the earliest of them is in.
+    fn copy_selection(&self) -> String {
+        let notes: Vec<Note> = self
+            .state
+            .selection
+            .iter()
+            .filter_map(|&i| self.pattern.notes.get(i).cloned())
+            .collect();
+        let beats_per_bar = self.pattern.beats_per_bar();
+        let earliest = notes.iter().map(|n| n.start_beat).fold(f32::MAX, f32::min);
+        let bar_start = (earliest / beats_per_bar).floor() * beats_per_bar;
+        let notes = notes
+            .into_iter()
+            .map(|n| Note {
+                start_beat: n.start_beat - bar_start,
+                ..n
+            })
+            .collect();
+        serde_json::to_string(&ClipboardNotes { aurio_notes: notes }).unwrap_or_default()
+    }
+
+    /// Adds notes copied from a piano roll, starting at the cursor or, with
+    /// `keep_bar_position`, where they were within their bar in the cursor's
+    /// bar. Notes that would start past the end of the pattern are dropped
+    /// and the pasted ones become the selection. Returns whether any were
+    /// added; text that isn't copied notes is ignored.
+    fn paste(&mut self, text: &str, keep_bar_position: bool) -> bool {
+        let Ok(clipboard) = serde_json::from_str::<ClipboardNotes>(text) else {
+            return false;
+        };
+        let total_beats = self.total_beats();
+        let beats_per_bar = self.pattern.beats_per_bar();
+        let earliest = clipboard
+            .aurio_notes
+            .iter()
+            .map(|n| n.start_beat)
+            .fold(f32::MAX, f32::min);
+        let offset = if keep_bar_position {
+            (self.state.cursor_beat / beats_per_bar).floor() * beats_per_bar
+        } else {
+            self.state.snap.floor(self.state.cursor_beat) - earliest
+        };
+
+        self.state.selection.clear();
+        for mut note in clipboard.aurio_notes {
+            note.start_beat += offset;
+            if note.start_beat < 0.0 || note.start_beat >= total_beats {
+                continue;
+            }
+            note.duration_beats = note.duration_beats.min(total_beats - note.start_beat);
+            self.state.selection.push(self.pattern.notes.len());
+            self.pattern.notes.push(note);
+        }
+        !self.state.selection.is_empty()
+    }
+
+    /// Adds a note cut short at the end of the pattern, as pasting does.
+    /// Returns whether it was added: nothing starts outside the pattern or
+    /// above the top of the MIDI range.
+    fn insert_note(&mut self, pitch: u8, beat: f32, duration: f32) -> bool {
+        let total_beats = self.total_beats();
+        if pitch > 127 || !(0.0..total_beats).contains(&beat) {
+            return false;
+        }
+        let duration = duration.min(total_beats - beat);
+        self.pattern
+            .notes
+            .push(Note::new(pitch, 100, beat, duration));
+        true
+    }
+
+    fn note_at_cursor_mut(&mut self) -> Option<&mut Note> {
+        let (pitch, beat) = (self.state.cursor_pitch, self.state.cursor_beat);
+        self.pattern
+            .notes
+            .iter_mut()
+            .find(|n| n.pitch == pitch && (n.start_beat - beat).abs() < 0.1)
+    }
+
+    fn scroll_to_cursor(&mut self, rect: egui::Rect, piano_key_width: f32) {
+        let visible_beats = (rect.width() - piano_key_width) / self.state.horizontal_zoom;
+        if self.state.cursor_beat < self.state.pan_x {
+            self.state.pan_x = self.state.cursor_beat;
+        } else if self.state.cursor_beat + 1.0 > self.state.pan_x + visible_beats {
+            self.state.pan_x = self.state.cursor_beat + 1.0 - visible_beats;
+        }
+
+        let visible_semitones = rect.height() / self.state.vertical_zoom;
+        let cursor = self.state.cursor_pitch as f32;
+        if cursor < self.state.pan_y + 1.0 {
+            self.state.pan_y = (cursor - 1.0).max(0.0);
+        } else if cursor > self.state.pan_y + visible_semitones - 1.0 {
+            self.state.pan_y = cursor - visible_semitones + 1.0;
+        }
+    }
+
+    fn draw_cursor(&self, painter: &egui::Painter, rect: egui::Rect, piano_key_width: f32) {
+        let x = self.beat_to_screen_x(self.state.cursor_beat, rect, piano_key_width);
+        let y = self.pitch_to_screen_y(self.state.cursor_pitch, rect);
+        let cursor_rect = egui::Rect::from_min_size(
+            egui::Pos2::new(x, y),
+            egui::Vec2::new(self.state.horizontal_zoom, self.state.vertical_zoom),
+        );
+        painter.rect_stroke(
+            cursor_rect,
+            2.0,
+            egui::Stroke::new(2.0, egui::Color32::YELLOW),
+            egui::StrokeKind::Outside,
+        );
+    }
+
+    fn cursor_description(&self) -> String {
+        let pitch = self.state.cursor_pitch;
+        let beat = self.state.cursor_beat;
+        let beats_per_bar = self.pattern.time_signature.0 as f32;
+        let bar = (beat / beats_per_bar).floor() as u32 + 1;
+        let beat_in_bar = beat % beats_per_bar + 1.0;
+
+        let mut description = format!(
+            "Piano roll, cursor at {} bar {} beat {}",
+            pitch_name(pitch),
+            bar,
+            beat_in_bar
+        );
+        if let Some(note) = self
+            .pattern
+            .notes
+            .iter()
+            .find(|n| n.pitch == pitch && (n.start_beat - beat).abs() < 0.1)
+        {
+            description.push_str(&format!(
+                ", note of {} beats, velocity {}",
+                note.duration_beats, note.velocity
+            ));
+        }
+        description
+    }
+
+    fn total_beats(&self) -> f32 {
+        self.pattern.beats_per_bar() * self.pattern.duration_bars as f32
+    }
+
     fn handle_input(&mut self, ui: &egui::Ui, available_size: egui::Vec2) {
         let modifiers = ui.input(|i| i.modifiers);
 
@@ -199,6 +764,7 @@ impl<'a> PianoRoll<'a> {
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn draw_grid(
         &self,
         painter: &egui::Painter,
@@ -215,6 +781,29 @@ impl<'a> PianoRoll<'a> {
         let start_beat = min_beat.floor() as i32;
         let end_beat = max_beat.ceil() as i32;
 
+        // Faint lines for snap steps finer than a beat, while they're far
+        // enough apart to tell from each other.
+        if let Some(step) = self.state.snap.beats()
+            && step < 1.0
+            && step * self.state.horizontal_zoom >= 6.0
+        {
+            let first = (start_beat.max(0) as f32 / step).ceil() as i32;
+            let last = (end_beat as f32 / step).floor() as i32;
+            for i in first..=last {
+                let x = self.beat_to_screen_x(i as f32 * step, rect, piano_key_width);
+                if x < rect.left() + piano_key_width || x > rect.right() {
+                    continue;
+                }
+                painter.line_segment(
+                    [
+                        egui::Pos2::new(x, rect.top()),
+                        egui::Pos2::new(x, rect.bottom()),
+                    ],
+                    egui::Stroke::new(1.0, egui::Color32::from_rgb(50, 50, 50)),
+                );
+            }
+        }
+
         for beat in start_beat..=end_beat {
             let x = self.beat_to_screen_x(beat as f32, rect, piano_key_width);
 
@@ -267,8 +856,10 @@ impl<'a> PianoRoll<'a> {
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn draw_notes(
         &mut self,
+        ui: &egui::Ui,
         painter: &egui::Painter,
         response: &egui::Response,
         rect: egui::Rect,
@@ -277,24 +868,127 @@ impl<'a> PianoRoll<'a> {
         max_pitch: u8,
     ) -> Option<NoteModification> {
         let mut modification = None;
-        let mut note_to_delete: Option<usize> = None;
+        let modifiers = ui.input(|i| i.modifiers);
+        let grid_left = rect.left() + piano_key_width;
+
+        if response.drag_started()
+            && let Some(origin) = ui.input(|i| i.pointer.press_origin())
+            && origin.x > grid_left
+        {
+            let hit = self.note_at(origin, rect, piano_key_width);
+            let kind = match hit {
+                Some(_) if modifiers.alt => DragKind::Velocity,
+                Some(idx)
+                    if origin.x
+                        >= self
+                            .note_rect(&self.pattern.notes[idx], rect, piano_key_width)
+                            .right()
+                            - RESIZE_HANDLE_WIDTH =>
+                {
+                    DragKind::Resize
+                }
+                Some(_) => DragKind::Move,
+                None => DragKind::Marquee,
+            };
+            match hit {
+                Some(idx) if !self.state.selection.contains(&idx) => {
+                    self.state.selection = vec![idx];
+                }
+                None if !modifiers.shift => self.state.selection.clear(),
+                _ => {}
+            }
+            let originals = self
+                .state
+                .selection
+                .iter()
+                .filter_map(|&i| self.pattern.notes.get(i).map(|n| (i, n.clone())))
+                .collect();
+            self.state.drag = Some(NoteDrag {
+                kind,
+                origin,
+                originals,
+            });
+        }
+
+        if let Some(drag) = self.state.drag.take() {
+            if let Some(pos) = response.interact_pointer_pos() {
+                if self.apply_drag(&drag, pos, rect, piano_key_width) {
+                    modification = Some(NoteModification::Edited);
+                }
+                if matches!(drag.kind, DragKind::Marquee) {
+                    let marquee = egui::Rect::from_two_pos(drag.origin, pos);
+                    painter.rect(
+                        marquee,
+                        0.0,
+                        egui::Color32::from_rgba_unmultiplied(255, 200, 0, 20),
+                        egui::Stroke::new(1.0, egui::Color32::from_rgb(255, 200, 0)),
+                        egui::StrokeKind::Inside,
+                    );
+                }
+            }
+            if response.dragged() {
+                self.state.drag = Some(drag);
+            }
+        }
+
+        if response.secondary_clicked()
+            && let Some(click_pos) = response.interact_pointer_pos()
+            && let Some(idx) = self.note_at(click_pos, rect, piano_key_width)
+        {
+            self.pattern.notes.remove(idx);
+            self.state.selection.clear();
+            modification = Some(NoteModification::Deleted);
+        }
+
+        if response.clicked()
+            && let Some(click_pos) = response.interact_pointer_pos()
+            && click_pos.x > grid_left
+        {
+            let pitch = self.screen_y_to_pitch(click_pos.y, rect);
+            let beat = self.screen_x_to_beat(click_pos.x, rect, piano_key_width);
+            self.state.cursor_pitch = pitch;
+            self.state.cursor_beat = beat.floor().max(0.0);
+
+            if let Some(idx) = self.note_at(click_pos, rect, piano_key_width) {
+                if !modifiers.shift {
+                    self.state.selection.clear();
+                }
+                if let Some(pos) = self.state.selection.iter().position(|&i| i == idx) {
+                    self.state.selection.remove(pos);
+                } else {
+                    self.state.selection.push(idx);
+                }
+            } else if pitch >= min_pitch && pitch <= max_pitch && beat >= 0.0 {
+                self.state.selection.clear();
+                let snapped_beat = self.state.snap.floor(beat);
+
+                let note_exists = self
+                    .pattern
+                    .notes
+                    .iter()
+                    .any(|n| n.pitch == pitch && (n.start_beat - snapped_beat).abs() < 0.1);
 
+                let duration = self.state.snap.beats().unwrap_or(1.0);
+                if !note_exists && self.insert_note(pitch, snapped_beat, duration) {
+                    modification = Some(NoteModification::Added);
+                }
+            }
+        }
+
+        let show_velocity = matches!(
+            self.state.drag,
+            Some(NoteDrag {
+                kind: DragKind::Velocity,
+                ..
+            })
+        );
         for (idx, note) in self.pattern.notes.iter().enumerate() {
             if note.pitch < min_pitch || note.pitch > max_pitch {
                 continue;
             }
 
-            let note_x = self.beat_to_screen_x(note.start_beat, rect, piano_key_width);
-            let note_width = note.duration_beats * self.state.horizontal_zoom;
-            let note_y = self.pitch_to_screen_y(note.pitch, rect);
-
-            let note_rect = egui::Rect::from_min_size(
-                egui::Pos2::new(note_x, note_y),
-                egui::Vec2::new(note_width, self.state.vertical_zoom),
-            );
-
-            if note_rect.right() < rect.left() + piano_key_width || note_rect.left() > rect.right()
-            {
+            let note_rect = self.note_rect(note, rect, piano_key_width);
+            if note_rect.right() < grid_left || note_rect.left() > rect.right() {
                 continue;
             }
 
@@ -305,62 +999,126 @@ impl<'a> PianoRoll<'a> {
                 (200.0 + 55.0 * velocity_factor) as u8,
             );
 
+            let selected = self.state.selection.contains(&idx);
+            let stroke = if selected {
+                egui::Stroke::new(2.0, egui::Color32::from_rgb(255, 200, 0))
+            } else {
+                egui::Stroke::new(1.0, egui::Color32::WHITE)
+            };
             painter.rect_filled(note_rect, 2.0, note_color);
-            painter.rect_stroke(
-                note_rect,
-                2.0,
-                egui::Stroke::new(1.0, egui::Color32::WHITE),
-                egui::StrokeKind::Inside,
-            );
+            painter.rect_stroke(note_rect, 2.0, stroke, egui::StrokeKind::Inside);
 
-            if response.secondary_clicked() {
-                if let Some(click_pos) = response.interact_pointer_pos() {
-                    if note_rect.contains(click_pos) {
-                        note_to_delete = Some(idx);
-                    }
-                }
+            if show_velocity && selected {
+                painter.text(
+                    note_rect.left_center() + egui::Vec2::new(3.0, 0.0),
+                    egui::Align2::LEFT_CENTER,
+                    note.velocity.to_string(),
+                    egui::FontId::proportional(10.0),
+                    egui::Color32::BLACK,
+                );
             }
         }
 
-        if let Some(idx) = note_to_delete {
-            self.pattern.notes.remove(idx);
-            modification = Some(NoteModification::Deleted);
+        modification
+    }
+
+    /// Updates the dragged notes for a pointer at `pos`. Moves keep the whole
+    /// selection inside the pattern, resizes snap the end of each note and
+    /// velocity follows the vertical distance. Returns whether a note changed.
+    fn apply_drag(
+        &mut self,
+        drag: &NoteDrag,
+        pos: egui::Pos2,
+        rect: egui::Rect,
+        piano_key_width: f32,
+    ) -> bool {
+        let total_beats = self.total_beats();
+        let beat_delta = (pos.x - drag.origin.x) / self.state.horizontal_zoom;
+        let pitch_delta = ((drag.origin.y - pos.y) / self.state.vertical_zoom).round() as i32;
+
+        if matches!(drag.kind, DragKind::Marquee) {
+            let marquee = egui::Rect::from_two_pos(drag.origin, pos);
+            let mut selection: Vec<usize> = drag.originals.iter().map(|(i, _)| *i).collect();
+            for (idx, note) in self.pattern.notes.iter().enumerate() {
+                if !selection.contains(&idx)
+                    && marquee.intersects(self.note_rect(note, rect, piano_key_width))
+                {
+                    selection.push(idx);
+                }
+            }
+            self.state.selection = selection;
+            return false;
         }
 
-        if response.clicked() {
-            if let Some(click_pos) = response.interact_pointer_pos() {
-                if click_pos.x > rect.left() + piano_key_width {
-                    let pitch = self.screen_y_to_pitch(click_pos.y, rect);
-                    let beat = self.screen_x_to_beat(click_pos.x, rect, piano_key_width);
-
-                    if pitch >= min_pitch && pitch <= max_pitch && beat >= 0.0 {
-                        let snapped_beat = beat.round();
-
-                        let time_signature = self.pattern.time_signature;
-                        let beats_per_bar = time_signature.0 as f32;
-                        let total_beats = beats_per_bar * self.pattern.duration_bars as f32;
-
-                        if snapped_beat < total_beats {
-                            let note_exists = self.pattern.notes.iter().any(|n| {
-                                n.pitch == pitch && (n.start_beat - snapped_beat).abs() < 0.1
-                            });
-
-                            if !note_exists {
-                                self.pattern.notes.push(Note {
-                                    pitch,
-                                    velocity: 100,
-                                    start_beat: snapped_beat,
-                                    duration_beats: 1.0,
-                                });
-                                modification = Some(NoteModification::Added);
-                            }
-                        }
-                    }
+        let originals = drag.originals.iter().map(|(_, n)| n);
+        let earliest = originals
+            .clone()
+            .map(|n| n.start_beat)
+            .fold(f32::MAX, f32::min);
+        let latest = originals
+            .clone()
+            .map(|n| n.start_beat + n.duration_beats)
+            .fold(0.0, f32::max);
+        let lowest = originals.clone().map(|n| n.pitch as i32).min().unwrap_or(0);
+        let highest = originals.map(|n| n.pitch as i32).max().unwrap_or(127);
+        let snap = self.state.snap;
+        // Not `clamp`: notes already running past a shortened pattern leave
+        // no room either side, and keeping them from before its start wins.
+        let beat_delta = snap
+            .round(beat_delta)
+            .min(total_beats - latest)
+            .max(-earliest);
+        let pitch_delta = pitch_delta.clamp(-lowest, 127 - highest);
+
+        let mut changed = false;
+        for (idx, original) in &drag.originals {
+            let mut note = original.clone();
+            match drag.kind {
+                DragKind::Move => {
+                    note.start_beat += beat_delta;
+                    note.pitch = (note.pitch as i32 + pitch_delta) as u8;
+                }
+                DragKind::Resize => {
+                    let end = snap.round(note.start_beat + note.duration_beats + beat_delta);
+                    note.duration_beats = (end - note.start_beat)
+                        .max(snap.beats().unwrap_or(MIN_NOTE_BEATS))
+                        .min(total_beats - note.start_beat);
                 }
+                DragKind::Velocity => {
+                    let velocity = note.velocity as f32 + (drag.origin.y - pos.y) * 0.5;
+                    note.velocity = velocity.round().clamp(1.0, 127.0) as u8;
+                }
+                DragKind::Marquee => unreachable!(),
+            }
+            if let Some(current) = self.pattern.notes.get_mut(*idx)
+                && *current != note
+            {
+                *current = note;
+                changed = true;
             }
         }
+        changed
+    }
 
-        modification
+    /// Index of the topmost note under a screen position.
+    fn note_at(&self, pos: egui::Pos2, rect: egui::Rect, piano_key_width: f32) -> Option<usize> {
+        self.pattern
+            .notes
+            .iter()
+            .rposition(|note| self.note_rect(note, rect, piano_key_width).contains(pos))
+    }
+
+    fn note_rect(&self, note: &Note, rect: egui::Rect, piano_key_width: f32) -> egui::Rect {
+        egui::Rect::from_min_size(
+            egui::Pos2::new(
+                self.beat_to_screen_x(note.start_beat, rect, piano_key_width),
+                self.pitch_to_screen_y(note.pitch, rect),
+            ),
+            egui::Vec2::new(
+                note.duration_beats * self.state.horizontal_zoom,
+                self.state.vertical_zoom,
+            ),
+        )
     }
 
     fn pitch_to_screen_y(&self, pitch: u8, rect: egui::Rect) -> f32 {
@@ -385,6 +1143,15 @@ impl<'a> PianoRoll<'a> {
     }
 }
 
+const NOTE_NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+
+fn pitch_name(pitch: u8) -> String {
+    let octave = (pitch / 12) as i32 - 1;
+    format!("{}{}", NOTE_NAMES[(pitch % 12) as usize], octave)
+}
+
 pub struct PianoRollResponse {
     pub modified: bool,
 }
@@ -392,4 +1159,165 @@ pub struct PianoRollResponse {
 enum NoteModification {
     Added,
     Deleted,
+    Edited,
+}
+
+/// What the piano roll puts on the clipboard; the field name keeps other
+/// JSON from being pasted as notes.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct ClipboardNotes {
+    aurio_notes: Vec<Note>,
+}
+
+/// Shortest note a resize can leave with snapping off.
+const MIN_NOTE_BEATS: f32 = 0.125;
+
+/// Grid note positions snap to, as a note value where a beat is a quarter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Snap {
+    Off,
+    Straight(u32),
+    Triplet(u32),
+}
+
+impl Snap {
+    pub const ALL: [Snap; 10] = [
+        Snap::Straight(1),
+        Snap::Straight(2),
+        Snap::Straight(4),
+        Snap::Straight(8),
+        Snap::Straight(16),
+        Snap::Straight(32),
+        Snap::Triplet(4),
+        Snap::Triplet(8),
+        Snap::Triplet(16),
+        Snap::Off,
+    ];
+
+    /// Length of one grid step in beats, or `None` when snapping is off.
+    pub fn beats(self) -> Option<f32> {
+        match self {
+            Snap::Off => None,
+            Snap::Straight(division) => Some(4.0 / division as f32),
+            Snap::Triplet(division) => Some(4.0 / division as f32 * 2.0 / 3.0),
+        }
+    }
+
+    /// Nearest grid position to `beat`.
+    pub fn round(self, beat: f32) -> f32 {
+        match self.beats() {
+            Some(step) => (beat / step).round() * step,
+            None => beat,
+        }
+    }
+
+    /// Start of the grid cell `beat` falls in.
+    pub fn floor(self, beat: f32) -> f32 {
+        match self.beats() {
+            // Nudged so a beat that is a hair under a line still lands on it.
+            Some(step) => (beat / step + 1e-4).floor() * step,
+            None => beat,
+        }
+    }
+}
+
+impl Default for Snap {
+    fn default() -> Self {
+        Snap::Straight(4)
+    }
+}
+
+impl std::fmt::Display for Snap {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Snap::Off => write!(f, "Off"),
+            Snap::Straight(division) => write!(f, "1/{}", division),
+            Snap::Triplet(division) => write!(f, "1/{}T", division),
+        }
+    }
+}
+
+/// How close to a note's right edge a drag resizes it instead of moving it.
+const RESIZE_HANDLE_WIDTH: f32 = 6.0;
+
+/// A mouse gesture in progress: dragging a note moves the selection, its
+/// right edge resizes it, Alt-dragging changes velocity and dragging empty
+/// space draws a marquee (Shift adds to the selection).
+#[derive(Clone)]
+struct NoteDrag {
+    kind: DragKind,
+    origin: egui::Pos2,
+    /// The notes being edited, as they were when the drag started.
+    originals: Vec<(usize, Note)>,
+}
+
+#[derive(Clone, Copy)]
+enum DragKind {
+    Move,
+    Resize,
+    Velocity,
+    Marquee,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snap_steps_follow_note_values() {
+        assert_eq!(Snap::Straight(1).beats(), Some(4.0));
+        assert_eq!(Snap::Straight(16).beats(), Some(0.25));
+        assert_eq!(Snap::Off.beats(), None);
+
+        let triplet = Snap::Triplet(8);
+        assert!((triplet.beats().unwrap() - 1.0 / 3.0).abs() < 1e-6);
+        assert!((triplet.floor(1.0) - 1.0).abs() < 1e-6);
+        assert!((triplet.round(0.6) - 2.0 / 3.0).abs() < 1e-6);
+
+        assert_eq!(Snap::Straight(4).floor(2.7), 2.0);
+        assert_eq!(Snap::Straight(8).round(1.3), 1.5);
+        assert_eq!(Snap::Off.round(1.3), 1.3);
+    }
+
+    #[test]
+    fn dragging_notes_longer_than_the_pattern_keeps_them_in_place() {
+        let note = Note::new(60, 100, 0.0, 8.0);
+        let mut pattern = StaticPattern {
+            duration_bars: 1,
+            time_signature: (4, 4),
+            notes: vec![note.clone()],
+            automation: Vec::new(),
+        };
+        let mut state = PianoRollState::default();
+        let origin = egui::pos2(100.0, 100.0);
+        let drag = NoteDrag {
+            kind: DragKind::Move,
+            origin,
+            originals: vec![(0, note)],
+        };
+        let rect = egui::Rect::from_min_size(egui::Pos2::ZERO, egui::vec2(800.0, 600.0));
+        let mut roll = PianoRoll::new(&mut pattern, &mut state);
+        for x in [-200.0, 300.0] {
+            roll.apply_drag(&drag, origin + egui::vec2(x, 0.0), rect, 40.0);
+            assert_eq!(roll.pattern.notes[0].start_beat, 0.0);
+        }
+    }
+
+    #[test]
+    fn inserted_notes_stay_inside_the_pattern() {
+        let mut pattern = StaticPattern {
+            duration_bars: 1,
+            time_signature: (4, 4),
+            notes: Vec::new(),
+            automation: Vec::new(),
+        };
+        let mut state = PianoRollState::default();
+        let mut roll = PianoRoll::new(&mut pattern, &mut state);
+        assert!(roll.insert_note(60, 3.5, 1.0));
+        assert_eq!(roll.pattern.notes[0].duration_beats, 0.5);
+        assert!(!roll.insert_note(60, 4.0, 1.0));
+        assert!(!roll.insert_note(60, -0.5, 1.0));
+        assert!(!roll.insert_note(128, 0.0, 1.0));
+        assert_eq!(roll.pattern.notes.len(), 1);
+    }
 }
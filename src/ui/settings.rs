@@ -0,0 +1,174 @@
+use super::piano_roll::{PianoRollState, Snap};
+use eframe::egui;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Projects listed under File > Open Recent.
+const RECENT_PROJECTS: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum Theme {
+    #[default]
+    Dark,
+    Light,
+}
+
+impl Theme {
+    pub fn egui(self) -> egui::Theme {
+        match self {
+            Theme::Dark => egui::Theme::Dark,
+            Theme::Light => egui::Theme::Light,
+        }
+    }
+}
+
+/// What a piano roll starts out with the first time a pattern is opened.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PianoRollDefaults {
+    pub snap: Snap,
+    pub follow: bool,
+}
+
+impl Default for PianoRollDefaults {
+    fn default() -> Self {
+        let state = PianoRollState::default();
+        Self {
+            snap: state.snap,
+            follow: state.follow,
+        }
+    }
+}
+
+impl PianoRollDefaults {
+    pub fn state(&self) -> PianoRollState {
+        let mut state = PianoRollState::default();
+        state.snap = self.snap;
+        state.follow = self.follow;
+        state
+    }
+}
+
+/// Preferences kept per user rather than per project, in `settings.ron`
+/// under the platform's config directory.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AppSettings {
+    pub theme: Theme,
+    pub ui_scale: f32,
+    /// Project reopened at startup.
+    pub last_project: Option<PathBuf>,
+    /// Most recently opened first.
+    pub recent_projects: Vec<PathBuf>,
+    pub piano_roll: PianoRollDefaults,
+}
+
+impl Default for AppSettings {
+    fn default() -> Self {
+        Self {
+            theme: Theme::default(),
+            ui_scale: 1.0,
+            last_project: None,
+            recent_projects: Vec::new(),
+            piano_roll: PianoRollDefaults::default(),
+        }
+    }
+}
+
+impl AppSettings {
+    /// The saved settings, or the defaults if there are none or they can't
+    /// be read.
+    pub fn load() -> Self {
+        let Some(path) = settings_path() else {
+            return Self::default();
+        };
+        match Self::load_from(&path) {
+            Ok(settings) => settings,
+            Err(e) => {
+                tracing::warn!("Ignoring settings in {}: {}", path.display(), e);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let path = settings_path().ok_or("No config directory to save settings in")?;
+        self.save_to(&path)
+    }
+
+    fn load_from(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        Ok(ron::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    fn save_to(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+        fs::write(path, text)?;
+        Ok(())
+    }
+
+    /// Makes `path` the last opened project, at the top of the recent list.
+    pub fn opened(&mut self, path: &Path) {
+        self.recent_projects.retain(|p| p != path);
+        self.recent_projects.insert(0, path.to_path_buf());
+        self.recent_projects.truncate(RECENT_PROJECTS);
+        self.last_project = Some(path.to_path_buf());
+    }
+}
+
+fn settings_path() -> Option<PathBuf> {
+    let config_dir = if cfg!(windows) {
+        std::env::var_os("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        std::env::var_os("HOME").map(|home| PathBuf::from(home).join("Library/Application Support"))
+    } else {
+        std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+    };
+    config_dir.map(|dir| dir.join("aurio").join("settings.ron"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_round_trip_and_keep_recent_projects_unique() {
+        let path = std::env::temp_dir()
+            .join(format!("aurio-settings-{}", std::process::id()))
+            .join("settings.ron");
+        assert_eq!(
+            AppSettings::load_from(&path).unwrap(),
+            AppSettings::default()
+        );
+
+        let mut settings = AppSettings {
+            theme: Theme::Light,
+            ui_scale: 1.25,
+            ..AppSettings::default()
+        };
+        settings.piano_roll.snap = Snap::Triplet(8);
+        for i in 0..12 {
+            settings.opened(Path::new(&format!("song{}.aurio", i)));
+        }
+        settings.opened(Path::new("song5.aurio"));
+        settings.save_to(&path).unwrap();
+        let loaded = AppSettings::load_from(&path).unwrap();
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+        assert_eq!(loaded, settings);
+        assert_eq!(loaded.recent_projects.len(), RECENT_PROJECTS);
+        assert_eq!(
+            loaded.recent_projects[..2],
+            [PathBuf::from("song5.aurio"), PathBuf::from("song11.aurio")]
+        );
+        assert_eq!(loaded.last_project, Some(PathBuf::from("song5.aurio")));
+    }
+}
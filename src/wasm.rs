@@ -0,0 +1,48 @@
+//! Bindings for the browser, built with the `wasm` feature for
+//! `wasm32-unknown-unknown`. Patches render here while the AudioWorklet glue
+//! in `web/` calls `process` once per render quantum. Only `core` and `dsp`
+//! are compiled in, so none of the engine's native dependencies are needed.
+
+use crate::dsp::{AudioGraph, ProcessContext, parse_file, patch_graph};
+use wasm_bindgen::prelude::*;
+
+fn js_error(error: impl std::fmt::Display) -> JsError {
+    JsError::new(&error.to_string())
+}
+
+/// A `.au` patch playing into a mono output.
+#[wasm_bindgen]
+pub struct Patch {
+    graph: AudioGraph,
+    ctx: ProcessContext,
+}
+
+#[wasm_bindgen]
+impl Patch {
+    #[wasm_bindgen(constructor)]
+    pub fn new(source: &str, sample_rate: f32) -> Result<Patch, JsError> {
+        Ok(Self {
+            graph: parse_file(source).map_err(js_error)?,
+            ctx: ProcessContext { sample_rate },
+        })
+    }
+
+    /// Adds or replaces nodes and wires, as a line typed at the live prompt.
+    pub fn patch(&mut self, source: &str) -> Result<(), JsError> {
+        patch_graph(&mut self.graph, source).map_err(js_error)
+    }
+
+    #[wasm_bindgen(js_name = setParam)]
+    pub fn set_param(&mut self, node: u32, name: &str, value: f32) -> Result<(), JsError> {
+        self.graph.set_param(node, name, value).map_err(js_error)
+    }
+
+    pub fn process(&mut self, output: &mut [f32]) {
+        self.graph.process(output, &self.ctx);
+    }
+
+    /// The patch with the changes made to it since, as `.au` source.
+    pub fn source(&self) -> String {
+        self.graph.to_source()
+    }
+}
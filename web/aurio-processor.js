@@ -0,0 +1,44 @@
+// The AudioWorklet side: runs an aurio Patch compiled to
+// WebAssembly for every render quantum. The page compiles the module and
+// passes it in `processorOptions`, since a worklet can't fetch it itself.
+
+import "./text-codec.js";
+import { initSync, Patch } from "./pkg/aurio.js";
+
+class AurioProcessor extends AudioWorkletProcessor {
+  constructor(options) {
+    super();
+    const { module, patch } = options.processorOptions;
+    initSync({ module });
+    try {
+      this.patch = new Patch(patch, sampleRate);
+    } catch (error) {
+      this.port.postMessage({ error: String(error) });
+    }
+    this.port.onmessage = (event) => this.receive(event.data);
+  }
+
+  receive(message) {
+    try {
+      if (message.patch !== undefined) {
+        this.patch?.patch(message.patch);
+      } else if (message.param !== undefined) {
+        const { node, name, value } = message.param;
+        this.patch?.setParam(node, name, value);
+      }
+    } catch (error) {
+      this.port.postMessage({ error: String(error) });
+    }
+  }
+
+  process(_inputs, outputs) {
+    const [left, right] = outputs[0];
+    if (this.patch) {
+      this.patch.process(left);
+      right.set(left);
+    }
+    return true;
+  }
+}
+
+registerProcessor("aurio", AurioProcessor);
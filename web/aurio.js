@@ -0,0 +1,46 @@
+// Page-side glue: loads the WebAssembly build in an AudioWorklet and wraps
+// the node in a few methods. The parser also runs on the page, so a patch
+// can be checked before it's played.
+
+import init, { Patch } from "./pkg/aurio.js";
+
+let compiled;
+
+async function load(context) {
+  if (!compiled) {
+    const url = new URL("./pkg/aurio_bg.wasm", import.meta.url);
+    compiled = (async () => {
+      const module = await WebAssembly.compileStreaming(fetch(url));
+      await init({ module_or_path: module });
+      await context.audioWorklet.addModule(new URL("./aurio-processor.js", import.meta.url));
+      return module;
+    })();
+  }
+  return compiled;
+}
+
+function connect(context, module, options, onError) {
+  const node = new AudioWorkletNode(context, "aurio", {
+    numberOfInputs: 0,
+    outputChannelCount: [2],
+    processorOptions: { module, ...options },
+  });
+  node.port.onmessage = (event) => event.data.error && onError(event.data.error);
+  node.connect(context.destination);
+  return node;
+}
+
+// Parses `source` on the page, throwing its error if it doesn't, then plays
+// it. `onError` gets the errors of later patches.
+export async function playPatch(context, source, onError = console.error) {
+  const module = await load(context);
+  new Patch(source, context.sampleRate).free();
+  const node = connect(context, module, { patch: source }, onError);
+  return {
+    node,
+    patch: (fragment) => node.port.postMessage({ patch: fragment }),
+    setParam: (nodeId, name, value) =>
+      node.port.postMessage({ param: { node: nodeId, name, value } }),
+    stop: () => node.disconnect(),
+  };
+}
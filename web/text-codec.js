@@ -0,0 +1,56 @@
+// AudioWorkletGlobalScope has no TextDecoder or TextEncoder, which the
+// wasm-bindgen glue needs for strings. Imported before it, this fills them in
+// for UTF-8.
+
+if (typeof globalThis.TextDecoder === "undefined") {
+  globalThis.TextDecoder = class {
+    decode(bytes) {
+      if (!bytes) return "";
+      let text = "";
+      for (let i = 0; i < bytes.length; ) {
+        let code = bytes[i++];
+        if (code >= 0xf0) {
+          code = ((code & 0x07) << 18) | ((bytes[i++] & 0x3f) << 12) | ((bytes[i++] & 0x3f) << 6) | (bytes[i++] & 0x3f);
+        } else if (code >= 0xe0) {
+          code = ((code & 0x0f) << 12) | ((bytes[i++] & 0x3f) << 6) | (bytes[i++] & 0x3f);
+        } else if (code >= 0xc0) {
+          code = ((code & 0x1f) << 6) | (bytes[i++] & 0x3f);
+        }
+        text += String.fromCodePoint(code);
+      }
+      return text;
+    }
+  };
+}
+
+if (typeof globalThis.TextEncoder === "undefined") {
+  globalThis.TextEncoder = class {
+    encode(text = "") {
+      const bytes = [];
+      for (const char of text) {
+        const code = char.codePointAt(0);
+        if (code < 0x80) {
+          bytes.push(code);
+        } else if (code < 0x800) {
+          bytes.push(0xc0 | (code >> 6), 0x80 | (code & 0x3f));
+        } else if (code < 0x10000) {
+          bytes.push(0xe0 | (code >> 12), 0x80 | ((code >> 6) & 0x3f), 0x80 | (code & 0x3f));
+        } else {
+          bytes.push(
+            0xf0 | (code >> 18),
+            0x80 | ((code >> 12) & 0x3f),
+            0x80 | ((code >> 6) & 0x3f),
+            0x80 | (code & 0x3f),
+          );
+        }
+      }
+      return new Uint8Array(bytes);
+    }
+
+    encodeInto(text, view) {
+      const bytes = this.encode(text);
+      view.set(bytes.subarray(0, view.length));
+      return { read: text.length, written: Math.min(bytes.length, view.length) };
+    }
+  };
+}